@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: UNLICENSED
+
+pragma solidity ^0.8.0;
+
+import { Ownable } from "@openzeppelin/contracts/access/Ownable.sol";
+
+/// @notice Records the commitment of every block decided by HotShot, in order.
+/// @dev Commitments are appended in batches by the poster, which is trusted to post the
+/// commitments of decided blocks. The owner can rotate the poster.
+contract BlockCommitments is Ownable {
+    // === Events ===
+    //
+    /// @notice a batch of block commitments was appended
+    event NewBlocks(uint256 firstBlockNumber, uint256 numBlocks);
+
+    /// @notice the address allowed to post commitments changed
+    event PosterChanged(address poster);
+
+    // === Errors ===
+    //
+    /// @notice the batch does not start at the next block height
+    error IncorrectBlockNumber(uint256 blockNumber, uint256 expectedBlockNumber);
+    /// @notice the batch is empty
+    error NoBlocks();
+    /// @notice the sender is not the poster
+    error UnauthorizedPoster();
+
+    // === Storage ===
+    //
+    /// @notice the number of blocks whose commitments have been recorded
+    uint256 public blockHeight;
+
+    /// @notice the commitment of each recorded block, by height
+    mapping(uint256 blockNumber => bytes32 commitment) public commitments;
+
+    /// @notice the address allowed to post commitments
+    address public poster;
+
+    constructor(address owner, address initialPoster) Ownable(owner) {
+        poster = initialPoster;
+        emit PosterChanged(initialPoster);
+    }
+
+    /// @notice Append the commitments of the blocks starting at `firstBlockNumber`.
+    /// @dev `firstBlockNumber` must equal the current `blockHeight`, so that a batch which is
+    /// posted twice (for example, when a transaction is replaced) cannot be recorded twice.
+    function newBlocks(uint256 firstBlockNumber, bytes32[] calldata blockCommitments) external {
+        if (msg.sender != poster) {
+            revert UnauthorizedPoster();
+        }
+        if (blockCommitments.length == 0) {
+            revert NoBlocks();
+        }
+        if (firstBlockNumber != blockHeight) {
+            revert IncorrectBlockNumber(firstBlockNumber, blockHeight);
+        }
+
+        for (uint256 i = 0; i < blockCommitments.length; i++) {
+            commitments[firstBlockNumber + i] = blockCommitments[i];
+        }
+        blockHeight = firstBlockNumber + blockCommitments.length;
+        emit NewBlocks(firstBlockNumber, blockCommitments.length);
+    }
+
+    /// @notice Change the address allowed to post commitments.
+    function setPoster(address newPoster) external onlyOwner {
+        poster = newPoster;
+        emit PosterChanged(newPoster);
+    }
+}
@@ -0,0 +1,88 @@
+// SPDX-License-Identifier: Unlicensed
+
+/* solhint-disable contract-name-camelcase, func-name-mixedcase, one-contract-per-file */
+
+pragma solidity ^0.8.0;
+
+// Libraries
+import { Test } from "forge-std/Test.sol";
+import { Ownable } from "@openzeppelin/contracts/access/Ownable.sol";
+
+// Target contract
+import { BlockCommitments } from "../src/BlockCommitments.sol";
+
+/// @title BlockCommitments Test
+contract BlockCommitmentsTest is Test {
+    BlockCommitments public commitments;
+    address public owner = makeAddr("owner");
+    address public poster = makeAddr("poster");
+
+    function setUp() public {
+        commitments = new BlockCommitments(owner, poster);
+    }
+
+    function batch(uint256 first, uint256 len) internal pure returns (bytes32[] memory comms) {
+        comms = new bytes32[](len);
+        for (uint256 i = 0; i < len; i++) {
+            comms[i] = keccak256(abi.encode(first + i));
+        }
+    }
+
+    function test_newBlocks() public {
+        vm.expectEmit(false, false, false, true);
+        emit BlockCommitments.NewBlocks(0, 3);
+        vm.prank(poster);
+        commitments.newBlocks(0, batch(0, 3));
+
+        vm.prank(poster);
+        commitments.newBlocks(3, batch(3, 2));
+
+        assertEq(commitments.blockHeight(), 5);
+        for (uint256 i = 0; i < 5; i++) {
+            assertEq(commitments.commitments(i), keccak256(abi.encode(i)));
+        }
+    }
+
+    function test_RevertWhen_WrongBlockNumber() public {
+        vm.startPrank(poster);
+        commitments.newBlocks(0, batch(0, 2));
+
+        // Posting the same batch again is rejected.
+        vm.expectRevert(abi.encodeWithSelector(BlockCommitments.IncorrectBlockNumber.selector, 0, 2));
+        commitments.newBlocks(0, batch(0, 2));
+
+        // So is skipping blocks.
+        vm.expectRevert(abi.encodeWithSelector(BlockCommitments.IncorrectBlockNumber.selector, 3, 2));
+        commitments.newBlocks(3, batch(3, 1));
+        vm.stopPrank();
+    }
+
+    function test_RevertWhen_EmptyBatch() public {
+        vm.prank(poster);
+        vm.expectRevert(BlockCommitments.NoBlocks.selector);
+        commitments.newBlocks(0, new bytes32[](0));
+    }
+
+    function testFuzz_RevertWhen_NotPoster(address sender) public {
+        vm.assume(sender != poster);
+        vm.prank(sender);
+        vm.expectRevert(BlockCommitments.UnauthorizedPoster.selector);
+        commitments.newBlocks(0, batch(0, 1));
+    }
+
+    function test_setPoster() public {
+        address newPoster = makeAddr("newPoster");
+
+        vm.prank(poster);
+        vm.expectRevert(abi.encodeWithSelector(Ownable.OwnableUnauthorizedAccount.selector, poster));
+        commitments.setPoster(newPoster);
+
+        vm.prank(owner);
+        commitments.setPoster(newPoster);
+        assertEq(commitments.poster(), newPoster);
+
+        vm.prank(newPoster);
+        commitments.newBlocks(0, batch(0, 1));
+        assertEq(commitments.blockHeight(), 1);
+    }
+}
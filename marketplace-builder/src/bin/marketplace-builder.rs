@@ -141,7 +141,7 @@ async fn run<V: Versions>(
     opt: NonPermissionedBuilderOptions,
 ) -> anyhow::Result<()> {
     let l1_params = L1Params {
-        url: opt.l1_provider_url,
+        urls: vec![opt.l1_provider_url],
         options: Default::default(),
     };
 
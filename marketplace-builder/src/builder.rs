@@ -68,7 +68,7 @@ pub async fn build_instance_state<V: Versions>(
     l1_params: L1Params,
     state_peers: Vec<Url>,
 ) -> anyhow::Result<NodeState> {
-    let l1_client = l1_params.options.connect(l1_params.url).await?;
+    let l1_client = l1_params.options.connect_failover(l1_params.urls).await?;
 
     let instance_state = NodeState::new(
         u64::MAX, // dummy node ID, only used for debugging
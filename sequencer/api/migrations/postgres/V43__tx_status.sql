@@ -0,0 +1,4 @@
+CREATE TABLE tx_status (
+    hash VARCHAR PRIMARY KEY,
+    data BYTEA NOT NULL
+);
@@ -0,0 +1,4 @@
+CREATE TABLE chain_config_history (
+    height BIGINT PRIMARY KEY,
+    commitment VARCHAR NOT NULL REFERENCES chain_config (commitment)
+);
@@ -0,0 +1,6 @@
+-- The height below which payloads and VID data have been pruned by the payload pruner. This table
+-- only ever has a single row, with id 0.
+CREATE TABLE payload_pruned_height (
+    id INT PRIMARY KEY,
+    height BIGINT NOT NULL
+);
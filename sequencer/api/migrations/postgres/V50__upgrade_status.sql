@@ -0,0 +1,4 @@
+CREATE TABLE upgrade_status (
+    version TEXT PRIMARY KEY,
+    data BYTEA NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Index blocks by time, so the block decided at a given time can be found without scanning headers.
+-- Heights break ties between blocks with the same timestamp.
+CREATE INDEX header_timestamp_height_idx ON header (timestamp, height);
@@ -0,0 +1,17 @@
+CREATE TABLE fee_deposit (
+    l1_block BIGINT NOT NULL,
+    log_index BIGINT NOT NULL,
+    account BYTEA NOT NULL,
+    data BYTEA NOT NULL,
+    PRIMARY KEY (l1_block, log_index)
+);
+
+CREATE INDEX fee_deposit_account_idx ON fee_deposit (account, l1_block, log_index);
+
+CREATE TABLE fee_deposit_scan (
+    -- The ID is always set to 0. Setting it explicitly allows us to enforce with every insert or
+    -- update that there is only a single entry in this table: the last L1 block scanned.
+    id INT PRIMARY KEY,
+
+    l1_block BIGINT NOT NULL
+);
@@ -0,0 +1,8 @@
+CREATE TABLE shutdown_checkpoint (
+    -- The ID is always set to 0. Setting it explicitly allows us to enforce with every insert or
+    -- update that there is only a single entry in this table: the checkpoint of the last graceful
+    -- shutdown.
+    id INT PRIMARY KEY,
+
+    data BYTEA NOT NULL
+);
@@ -0,0 +1,9 @@
+CREATE TABLE anchor_state (
+    -- The ID is always set to 0. Setting it explicitly allows us to enforce with every insert or
+    -- update that there is only a single entry in this table: the state after the latest anchor
+    -- leaf.
+    id INT PRIMARY KEY,
+
+    view BIGINT NOT NULL,
+    data BYTEA NOT NULL
+);
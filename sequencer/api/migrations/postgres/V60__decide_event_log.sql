@@ -0,0 +1,4 @@
+CREATE TABLE decide_event_log (
+    height BIGINT PRIMARY KEY,
+    data BYTEA NOT NULL
+);
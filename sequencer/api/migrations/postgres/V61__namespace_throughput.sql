@@ -0,0 +1,6 @@
+-- Number of transactions in each namespace of each block. Blocks recorded before this column was
+-- added report no transactions.
+ALTER TABLE explorer_namespace_stats ADD COLUMN num_transactions BIGINT NOT NULL DEFAULT 0;
+
+-- Look up the history of a single namespace.
+CREATE INDEX explorer_namespace_stats_namespace_idx ON explorer_namespace_stats (namespace, height);
@@ -0,0 +1,4 @@
+CREATE TABLE builder_fees (
+    account BYTEA PRIMARY KEY,
+    data BYTEA NOT NULL
+);
@@ -0,0 +1,110 @@
+-- SQLite schema for sequencer storage.
+--
+-- The Postgres schema evolved over many migrations (see `../postgres`). SQLite support was added
+-- later, so this migration creates the equivalent of the final Postgres schema in one step. Future
+-- schema changes must be added to both directories.
+
+CREATE TABLE network_config (
+    id     INTEGER PRIMARY KEY AUTOINCREMENT,
+    config JSONB
+);
+
+CREATE TABLE anchor_leaf (
+    view BIGINT PRIMARY KEY,
+    leaf BLOB,
+    qc   BLOB
+);
+
+CREATE TABLE highest_voted_view (
+    -- The ID is always set to 0. Setting it explicitly allows us to enforce with every insert or
+    -- update that there is only a single entry in this table: the latest known view.
+    id INT PRIMARY KEY,
+
+    view BIGINT
+);
+
+CREATE TABLE da_proposal (
+    view BIGINT PRIMARY KEY,
+    data BLOB
+);
+
+CREATE TABLE vid_share (
+    view BIGINT PRIMARY KEY,
+    data BLOB
+);
+
+CREATE TABLE undecided_state (
+    -- The ID is always set to 0. Setting it explicitly allows us to enforce with every insert or
+    -- update that there is only a single entry in this table: the latest known state.
+    id INT PRIMARY KEY,
+
+    leaves BLOB NOT NULL,
+    state  BLOB NOT NULL
+);
+
+CREATE TABLE quorum_proposals (
+    view      BIGINT PRIMARY KEY,
+    leaf_hash VARCHAR,
+    data      BLOB
+);
+CREATE UNIQUE INDEX quorum_proposals_leaf_hash_idx ON quorum_proposals (leaf_hash);
+
+CREATE TABLE chain_config (
+    commitment VARCHAR PRIMARY KEY,
+    data       BLOB NOT NULL
+);
+
+CREATE TABLE upgrade_certificate (
+    id   BOOLEAN PRIMARY KEY DEFAULT true,
+    data BLOB
+);
+
+CREATE TABLE event_stream (
+    id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+    last_processed_view BIGINT
+);
+
+CREATE TABLE tx_status (
+    hash VARCHAR PRIMARY KEY,
+    data BLOB NOT NULL
+);
+
+-- Merklized state.
+CREATE TABLE IF NOT EXISTS hash (
+    id    INTEGER PRIMARY KEY AUTOINCREMENT,
+    value JSONB NOT NULL UNIQUE
+);
+
+CREATE TABLE fee_merkle_tree (
+    path            JSONB NOT NULL,
+    created         BIGINT NOT NULL,
+    hash_id         INT NOT NULL REFERENCES hash (id),
+    children        JSONB,
+    children_bitvec BLOB,
+    idx             JSONB,
+    entry           JSONB,
+    PRIMARY KEY (path, created)
+);
+
+CREATE TABLE block_merkle_tree (
+    path            JSONB NOT NULL,
+    created         BIGINT NOT NULL,
+    hash_id         INT NOT NULL REFERENCES hash (id),
+    children        JSONB,
+    children_bitvec BLOB,
+    idx             JSONB,
+    entry           JSONB,
+    PRIMARY KEY (path, created)
+);
+
+-- SQLite cannot add stored generated columns to an existing table, so the header Merkle roots are
+-- virtual columns. They are still indexable.
+ALTER TABLE header
+    ADD COLUMN block_merkle_tree_root TEXT
+    GENERATED ALWAYS AS (coalesce(data->'fields'->>'block_merkle_tree_root', data->>'block_merkle_tree_root')) VIRTUAL;
+ALTER TABLE header
+    ADD COLUMN fee_merkle_tree_root TEXT
+    GENERATED ALWAYS AS (coalesce(data->'fields'->>'fee_merkle_tree_root', data->>'fee_merkle_tree_root')) VIRTUAL;
+
+CREATE INDEX header_block_merkle_tree_root_idx ON header (block_merkle_tree_root);
+CREATE INDEX header_fee_merkle_tree_root_idx ON header (fee_merkle_tree_root);
@@ -0,0 +1,24 @@
+-- Statistics for each block, maintained incrementally for the explorer summary.
+CREATE TABLE explorer_block_stats (
+    height BIGINT PRIMARY KEY,
+    timestamp BIGINT NOT NULL,
+    num_transactions BIGINT NOT NULL,
+    size BIGINT NOT NULL
+);
+CREATE INDEX explorer_block_stats_timestamp_idx ON explorer_block_stats (timestamp);
+
+-- Payload bytes in each namespace of each block.
+CREATE TABLE explorer_namespace_stats (
+    height BIGINT NOT NULL,
+    namespace BIGINT NOT NULL,
+    bytes BIGINT NOT NULL,
+    PRIMARY KEY (height, namespace)
+);
+
+-- Running totals over all blocks below `height`. This table only ever has a single row, with id 0.
+CREATE TABLE explorer_stats_totals (
+    id INT PRIMARY KEY,
+    height BIGINT NOT NULL,
+    num_transactions BIGINT NOT NULL,
+    bytes BIGINT NOT NULL
+);
@@ -0,0 +1,4 @@
+CREATE TABLE mempool (
+    hash VARCHAR PRIMARY KEY,
+    data BLOB NOT NULL
+);
@@ -0,0 +1,4 @@
+CREATE TABLE epoch_stake_table (
+    epoch BIGINT PRIMARY KEY,
+    data BLOB NOT NULL
+);
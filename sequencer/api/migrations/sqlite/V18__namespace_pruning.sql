@@ -0,0 +1,8 @@
+-- Progress of the payload pruner's per-namespace retention policies. Blocks below `resolved` have
+-- been pruned or kept for good; blocks in `[resolved, scanned)` have been checked at least once.
+-- This table only ever has a single row, with id 0.
+CREATE TABLE namespace_pruning_progress (
+    id INT PRIMARY KEY,
+    resolved BIGINT NOT NULL,
+    scanned BIGINT NOT NULL
+);
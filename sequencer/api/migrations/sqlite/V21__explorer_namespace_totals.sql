@@ -0,0 +1,13 @@
+-- Running totals for each namespace, maintained incrementally alongside `explorer_stats_totals`.
+CREATE TABLE explorer_namespace_totals (
+    namespace BIGINT PRIMARY KEY,
+    num_blocks BIGINT NOT NULL,
+    bytes BIGINT NOT NULL,
+    last_height BIGINT NOT NULL
+);
+
+-- Populate the totals from the statistics recorded so far.
+INSERT INTO explorer_namespace_totals (namespace, num_blocks, bytes, last_height)
+    SELECT namespace, count(*), sum(bytes), max(height)
+      FROM explorer_namespace_stats
+     GROUP BY namespace;
@@ -0,0 +1,7 @@
+CREATE TABLE libp2p_peers (
+    -- The ID is always set to 0. Setting it explicitly allows us to enforce with every insert or
+    -- update that there is only a single entry in this table: the latest known list of peers.
+    id INT PRIMARY KEY,
+
+    data BLOB NOT NULL
+);
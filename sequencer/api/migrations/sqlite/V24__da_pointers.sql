@@ -0,0 +1,4 @@
+CREATE TABLE da_pointer (
+    height BIGINT PRIMARY KEY,
+    data BLOB NOT NULL
+);
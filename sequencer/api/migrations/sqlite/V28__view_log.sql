@@ -0,0 +1,4 @@
+CREATE TABLE view_log (
+    view BIGINT PRIMARY KEY,
+    data BLOB NOT NULL
+);
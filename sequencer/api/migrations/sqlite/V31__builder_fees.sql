@@ -0,0 +1,4 @@
+CREATE TABLE builder_fees (
+    account BLOB PRIMARY KEY,
+    data BLOB NOT NULL
+);
@@ -1,5 +1,28 @@
+use std::{env, fs, path::Path};
+
+use anyhow::Context;
+use serde_json::{json, Map, Value};
 use vergen::EmitBuilder;
 
+/// Modules of the public HTTP API defined in this crate, with the route definitions they are built
+/// from. For modules which extend a query service module, only the routes added here are listed.
+const PUBLIC_MODULES: &[(&str, &str)] = &[
+    ("availability", "api/availability.toml"),
+    ("node", "api/node.toml"),
+    ("status", "api/status.toml"),
+    ("block-state", "api/merklized_state.toml"),
+    ("fee-state", "api/merklized_state.toml"),
+    ("submit", "api/submit.toml"),
+    ("catchup", "api/catchup.toml"),
+    ("state-signature", "api/state_signature.toml"),
+    ("events", "api/events.toml"),
+    ("config", "api/config.toml"),
+    ("fee", "api/fee.toml"),
+    ("marketplace", "api/marketplace.toml"),
+    ("namespaces", "api/namespaces.toml"),
+    ("explorer-stats", "api/explorer_stats.toml"),
+];
+
 pub fn main() -> anyhow::Result<()> {
     // Set an environment variable with git information
     EmitBuilder::builder()
@@ -7,5 +30,122 @@ pub fn main() -> anyhow::Result<()> {
         .git_describe(true, true, None)
         .git_commit_timestamp()
         .emit()?;
+
+    // Generate gRPC bindings, using a vendored `protoc` so that building does not depend on a
+    // system installation.
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+        tonic_build::compile_protos("api/proto/espresso.proto")?;
+    }
+
+    // Generate the OpenAPI specification served at `/api-spec.json`.
+    let spec = api_spec()?;
+    let out = Path::new(&env::var("OUT_DIR")?).join("api-spec.json");
+    fs::write(out, serde_json::to_string_pretty(&spec)?)?;
+
     Ok(())
 }
+
+/// Build an OpenAPI 3.0 document describing the routes of [`PUBLIC_MODULES`].
+fn api_spec() -> anyhow::Result<Value> {
+    let mut paths = Map::new();
+    for (module, file) in PUBLIC_MODULES {
+        println!("cargo:rerun-if-changed={file}");
+        let api: toml::Value = toml::from_str(&fs::read_to_string(file)?)
+            .with_context(|| format!("malformed route definitions in {file}"))?;
+        let Some(routes) = api.get("route").and_then(|routes| routes.as_table()) else {
+            continue;
+        };
+
+        for (name, route) in routes {
+            let method = route
+                .get("METHOD")
+                .and_then(|method| method.as_str())
+                .unwrap_or("GET")
+                .to_lowercase();
+            // Socket routes have no OpenAPI equivalent.
+            if method == "socket" {
+                continue;
+            }
+            let doc = route
+                .get("DOC")
+                .and_then(|doc| doc.as_str())
+                .unwrap_or_default()
+                .trim();
+            let patterns = route
+                .get("PATH")
+                .and_then(|path| path.as_array())
+                .with_context(|| format!("route {module}/{name} has no PATH"))?;
+
+            for (i, pattern) in patterns.iter().enumerate() {
+                let pattern = pattern
+                    .as_str()
+                    .with_context(|| format!("route {module}/{name} has a malformed PATH"))?;
+                let mut path = format!("/{module}");
+                let mut parameters = vec![];
+                for segment in pattern.split('/').filter(|segment| !segment.is_empty()) {
+                    let Some(param) = segment.strip_prefix(':') else {
+                        path = format!("{path}/{segment}");
+                        continue;
+                    };
+                    path = format!("{path}/{{{param}}}");
+                    let ty = route
+                        .get(segment)
+                        .and_then(|ty| ty.as_str())
+                        .unwrap_or("Literal");
+                    parameters.push(json!({
+                        "name": param,
+                        "in": "path",
+                        "required": true,
+                        "schema": param_schema(ty),
+                    }));
+                }
+
+                // Operation IDs must be unique, so alternative paths for a route are numbered.
+                let mut operation_id = format!("{module}.{name}");
+                if i > 0 {
+                    operation_id = format!("{operation_id}.{i}");
+                }
+                let operation = json!({
+                    "operationId": operation_id,
+                    "tags": [module],
+                    "summary": doc.lines().next().unwrap_or_default(),
+                    "description": doc,
+                    "parameters": parameters,
+                    "responses": {
+                        "200": { "description": "Success" },
+                        "default": { "description": "Error" },
+                    },
+                });
+                paths
+                    .entry(path)
+                    .or_insert_with(|| json!({}))
+                    .as_object_mut()
+                    .context("path item is an object")?
+                    .insert(method.clone(), operation);
+            }
+        }
+    }
+
+    Ok(json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Espresso Sequencer API",
+            "version": env::var("CARGO_PKG_VERSION")?,
+        },
+        "servers": [{ "url": "/v0" }],
+        "paths": paths,
+    }))
+}
+
+/// The schema of a route parameter of tide-disco type `ty`.
+fn param_schema(ty: &str) -> Value {
+    match ty {
+        "Integer" => json!({ "type": "integer", "minimum": 0 }),
+        "Boolean" => json!({ "type": "boolean" }),
+        "Hexadecimal" => json!({ "type": "string", "pattern": "^(0x)?[0-9a-fA-F]*$" }),
+        "TaggedBase64" => json!({ "type": "string", "format": "tagged-base64" }),
+        _ => json!({ "type": "string" }),
+    }
+}
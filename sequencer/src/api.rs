@@ -1,20 +1,29 @@
 use std::pin::Pin;
 
-use anyhow::{bail, Context};
+use anyhow::{anyhow, bail, ensure, Context};
 use async_lock::RwLock;
 use async_once_cell::Lazy;
 use async_trait::async_trait;
 use committable::{Commitment, Committable};
-use data_source::{CatchupDataSource, StakeTableDataSource, SubmitDataSource};
+use data_source::{
+    BlockAtTime, BlockTimeDataSource, CatchupDataSource, DaMirrorDataSource, DecideEventDataSource,
+    FeeAccountDataSource, FeeEstimateDataSource, MempoolDataSource, NetworkHealthDataSource,
+    SequencerDataSource, SolverDataSource, StakeTableDataSource, SubmitDataSource,
+    TaskStatusDataSource, TxStatusDataSource, VersionDataSource, ViewLogDataSource,
+};
 use derivative::Derivative;
 use espresso_types::{
-    retain_accounts, v0::traits::SequencerPersistence, v0_3::ChainConfig, AccountQueryData,
-    BlockMerkleTree, FeeAccount, FeeAccountProof, FeeMerkleTree, MockSequencerVersions, NodeState,
-    PubKey, Transaction, ValidatedState,
+    retain_accounts,
+    v0::traits::SequencerPersistence,
+    v0_3::{BidTx, ChainConfig, SolverAuctionResults},
+    AccountQueryData, BlockMerkleTree, DaPointer, DecideEvent, EpochStakeTable, FeeAccount,
+    FeeAccountProof, FeeInfo, FeeMerkleTree, Header, MarketplaceVersion, MockSequencerVersions,
+    NamespaceId, NodeState, PubKey, SolverAuctionResultsProvider, Transaction, TxStatus,
+    ValidatedState, ViewRecord,
 };
 use futures::{
     future::{BoxFuture, Future, FutureExt},
-    stream::BoxStream,
+    stream::{BoxStream, StreamExt},
 };
 use hotshot_events_service::events_source::{
     EventFilterSet, EventsSource, EventsStreamer, StartupInfo,
@@ -22,35 +31,90 @@ use hotshot_events_service::events_source::{
 use hotshot_query_service::data_source::ExtensibleDataSource;
 use hotshot_state_prover::service::light_client_genesis_from_stake_table;
 use hotshot_types::{
-    data::ViewNumber,
-    event::Event,
+    data::{EpochNumber, ViewNumber},
+    event::{Event, EventType, LeafInfo},
     light_client::StateSignatureRequestBody,
     network::NetworkConfig,
     traits::{
+        auction_results_provider::AuctionResultsProvider,
+        block_contents::BlockPayload,
+        election::Membership,
         network::ConnectedNetwork,
-        node_implementation::{NodeType, Versions},
+        node_implementation::{ConsensusTime, Versions},
         ValidatedState as _,
     },
-    utils::{View, ViewInner},
+    utils::{epoch_from_block_number, View, ViewInner},
 };
-use hotshot_types::{stake_table::StakeTableEntry, traits::election::Membership};
 use jf_merkle_tree::MerkleTreeScheme;
-use std::sync::Arc;
-
-use self::data_source::{
-    HotShotConfigDataSource, NodeStateDataSource, PublicNetworkConfig, StateSignatureDataSource,
+use std::{iter, sync::Arc, time::Duration};
+use vbs::version::{StaticVersionType, Version};
+
+use self::{
+    data_source::{
+        ChainConfigActivation, ChainConfigHistoryDataSource, HotShotConfigDataSource,
+        LeaderSchedule, LeaderScheduleDataSource, NodeStateDataSource, PublicNetworkConfig,
+        StateSignatureDataSource, UpgradeDataSource,
+    },
+    endpoints::{
+        DecidedTransaction, FeeAccountQueryData, FeeDepositsQueryData, FeeEstimate, VersionInfo,
+    },
+    peers::QueryPeers,
 };
 use crate::{
-    catchup::CatchupStorage, context::Consensus, network, state_signature::StateSigner, SeqTypes,
-    SequencerApiVersion, SequencerContext,
+    builder_fees::{BuilderEarnings, BuilderFeeLedger},
+    builder_registry::{BuilderInfo, BuilderRegistry},
+    catchup::CatchupStorage,
+    context::{epoch_stake_table, Consensus, TaskMonitor},
+    mempool::{Mempool, PendingTransaction},
+    network::{
+        self,
+        health::{NetworkHealth, NetworkStatus},
+    },
+    shutdown::Flag,
+    state_signature::{aggregator::StateSignatureBundleQueryData, StateSigner},
+    supervisor::TaskStatus,
+    tx_trace::{TraceParent, TxTracer},
+    upgrade::{UpgradeInfo, UpgradeManager},
+    SeqTypes, SequencerApiVersion, SequencerContext,
 };
 
+pub mod archive;
+mod auth;
+pub mod backfill;
+pub mod cache;
+mod catchup_cache;
+mod catchup_limits;
+#[cfg(feature = "client")]
+pub mod client;
+mod compression;
 pub mod data_source;
+pub mod db_pool;
 pub mod endpoints;
+pub mod error;
+pub mod fanout;
+mod fee_deposits;
 pub mod fs;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+mod limits;
+mod metrics;
+pub mod namespaces;
+pub mod openapi;
 pub mod options;
+pub mod otlp;
+mod pagination;
+pub mod peers;
+pub mod pruner;
+pub mod rate_limit;
+pub mod routing;
 pub mod sql;
+pub mod stats;
+pub mod stream;
+pub mod telemetry;
+mod tls;
+pub mod tx_validation;
 mod update;
+pub mod vid_repair;
 
 pub use options::Options;
 
@@ -68,6 +132,31 @@ struct ConsensusState<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: V
 
     #[derivative(Debug = "ignore")]
     handle: Arc<RwLock<Consensus<N, P, V>>>,
+
+    #[derivative(Debug = "ignore")]
+    persistence: Arc<P>,
+
+    #[derivative(Debug = "ignore")]
+    mempool: Arc<Mempool<P>>,
+
+    #[derivative(Debug = "ignore")]
+    upgrades: Arc<UpgradeManager<P>>,
+
+    #[derivative(Debug = "ignore")]
+    builder_fees: Arc<BuilderFeeLedger<P>>,
+
+    tx_tracer: Arc<TxTracer>,
+
+    builder_registry: BuilderRegistry,
+
+    solver: Arc<SolverAuctionResultsProvider>,
+
+    network_health: Option<NetworkHealth>,
+
+    /// Raised when the node is shutting down and no longer accepts transactions.
+    draining: Flag,
+
+    tasks: TaskMonitor,
 }
 
 impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions>
@@ -80,6 +169,16 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions>
             node_state: ctx.node_state(),
             network_config: ctx.network_config(),
             handle: ctx.consensus(),
+            persistence: ctx.persistence(),
+            mempool: ctx.mempool(),
+            upgrades: ctx.upgrades(),
+            builder_fees: ctx.builder_fees(),
+            tx_tracer: ctx.tx_tracer(),
+            builder_registry: ctx.builder_registry(),
+            solver: ctx.solver(),
+            network_health: ctx.network_health(),
+            draining: ctx.shutdown_signals().draining,
+            tasks: ctx.task_monitor(),
         }
     }
 }
@@ -95,6 +194,22 @@ struct ApiState<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Version
     consensus: BoxLazy<ConsensusState<N, P, V>>,
 }
 
+/// State of the admin API server, which manages this node's query peers and mempool.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""))]
+struct AdminState<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> {
+    peers: QueryPeers,
+    node: ApiState<N, P, V>,
+}
+
+impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> AsRef<QueryPeers>
+    for AdminState<N, P, V>
+{
+    fn as_ref(&self) -> &QueryPeers {
+        &self.peers
+    }
+}
+
 impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> ApiState<N, P, V> {
     fn new(init: impl Future<Output = ConsensusState<N, P, V>> + Send + 'static) -> Self {
         Self {
@@ -114,6 +229,65 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> ApiState
         Arc::clone(&self.consensus.as_ref().get().await.get_ref().handle)
     }
 
+    async fn persistence(&self) -> &P {
+        &self.consensus.as_ref().get().await.get_ref().persistence
+    }
+
+    async fn mempool(&self) -> &Mempool<P> {
+        &self.consensus.as_ref().get().await.get_ref().mempool
+    }
+
+    async fn upgrades(&self) -> &UpgradeManager<P> {
+        &self.consensus.as_ref().get().await.get_ref().upgrades
+    }
+
+    async fn builder_fees(&self) -> &BuilderFeeLedger<P> {
+        &self.consensus.as_ref().get().await.get_ref().builder_fees
+    }
+
+    async fn tx_tracer(&self) -> &TxTracer {
+        &self.consensus.as_ref().get().await.get_ref().tx_tracer
+    }
+
+    async fn builder_registry(&self) -> &BuilderRegistry {
+        &self
+            .consensus
+            .as_ref()
+            .get()
+            .await
+            .get_ref()
+            .builder_registry
+    }
+
+    async fn solver(&self) -> &SolverAuctionResultsProvider {
+        &self.consensus.as_ref().get().await.get_ref().solver
+    }
+
+    async fn ensure_not_draining(&self) -> anyhow::Result<()> {
+        ensure!(
+            !self
+                .consensus
+                .as_ref()
+                .get()
+                .await
+                .get_ref()
+                .draining
+                .is_raised(),
+            "node is shutting down"
+        );
+        Ok(())
+    }
+
+    async fn network_health(&self) -> Option<&NetworkHealth> {
+        self.consensus
+            .as_ref()
+            .get()
+            .await
+            .get_ref()
+            .network_health
+            .as_ref()
+    }
+
     async fn network_config(&self) -> NetworkConfig<PubKey> {
         self.consensus
             .as_ref()
@@ -123,10 +297,41 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> ApiState
             .network_config
             .clone()
     }
+
+    /// This node's position among the storage nodes, which determines the VID share it stores.
+    async fn vid_share_index(&self) -> Option<usize> {
+        let consensus = self.consensus().await;
+        let consensus = consensus.read().await;
+        let view = consensus.cur_view().await;
+        let epoch = consensus.cur_epoch().await;
+        let key = consensus.public_key();
+        consensus
+            .memberships
+            .quorum_membership
+            .committee_members(view, epoch)
+            .iter()
+            .position(|node| *node == key)
+    }
 }
 
 type StorageState<N, P, D, V> = ExtensibleDataSource<D, ApiState<N, P, V>>;
 
+/// The serialization version served alongside [`SequencerApiVersion`] during an upgrade window.
+///
+/// This is the protocol version being upgraded to, if an upgrade is configured and its version
+/// differs from the one the API is normally bound to.
+fn upgrade_api_version<V: Versions>() -> Option<Version> {
+    let upgrade = V::Upgrade::VERSION;
+    (upgrade > V::Base::VERSION && upgrade != SequencerApiVersion::VERSION).then_some(upgrade)
+}
+
+/// Versions of binary serialization the API can respond with, preferred first.
+fn api_versions<V: Versions>() -> Vec<Version> {
+    iter::once(SequencerApiVersion::VERSION)
+        .chain(upgrade_api_version::<V>())
+        .collect()
+}
+
 #[async_trait]
 impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> EventsSource<SeqTypes>
     for ApiState<N, P, V>
@@ -160,28 +365,147 @@ impl<N: ConnectedNetwork<PubKey>, D: Send + Sync, V: Versions, P: SequencerPersi
     async fn submit(&self, tx: Transaction) -> anyhow::Result<()> {
         self.as_ref().submit(tx).await
     }
+
+    async fn submit_batch(&self, txs: Vec<Transaction>) -> Vec<anyhow::Result<()>> {
+        self.as_ref().submit_batch(txs).await
+    }
+
+    async fn submit_and_wait(
+        &self,
+        tx: Transaction,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<DecidedTransaction>> {
+        // Decided leaves are added to query storage before the decide event is streamed, so once
+        // the transaction is decided, the block containing it can be queried from this node.
+        self.as_ref().submit_and_wait(tx, timeout).await
+    }
+
+    async fn active_chain_config(&self) -> ChainConfig {
+        self.as_ref().active_chain_config().await
+    }
+
+    async fn trace_transaction(
+        &self,
+        hash: Commitment<Transaction>,
+        ns: NamespaceId,
+        parent: Option<TraceParent>,
+    ) {
+        self.as_ref().trace_transaction(hash, ns, parent).await
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, D: Sync, V: Versions, P: SequencerPersistence> VersionDataSource
+    for StorageState<N, P, D, V>
+{
+    async fn version_info(&self) -> VersionInfo {
+        self.as_ref().version_info().await
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, D: Sync, V: Versions, P: SequencerPersistence>
+    NetworkHealthDataSource for StorageState<N, P, D, V>
+{
+    async fn network_status(&self) -> Option<NetworkStatus> {
+        self.as_ref().network_status().await
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> NetworkHealthDataSource
+    for ApiState<N, P, V>
+{
+    async fn network_status(&self) -> Option<NetworkStatus> {
+        Some(self.network_health().await?.status().await)
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, D: Sync, V: Versions, P: SequencerPersistence>
+    TaskStatusDataSource for StorageState<N, P, D, V>
+{
+    async fn task_status(&self) -> Vec<TaskStatus> {
+        self.as_ref().task_status().await
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> TaskStatusDataSource
+    for ApiState<N, P, V>
+{
+    async fn task_status(&self) -> Vec<TaskStatus> {
+        self.consensus.as_ref().get().await.get_ref().tasks.status()
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> VersionDataSource
+    for ApiState<N, P, V>
+{
+    async fn version_info(&self) -> VersionInfo {
+        let upgrade_version =
+            (V::Upgrade::VERSION > V::Base::VERSION).then_some(V::Upgrade::VERSION);
+        let upgrade = match upgrade_version {
+            Some(version) => self.node_state().await.upgrades.get(&version).cloned(),
+            None => None,
+        };
+        let current_version = self
+            .consensus()
+            .await
+            .read()
+            .await
+            .decided_leaf()
+            .await
+            .block_header()
+            .version();
+
+        VersionInfo {
+            api_versions: api_versions::<V>(),
+            base_version: V::Base::VERSION,
+            upgrade_version,
+            current_version,
+            upgrade,
+        }
+    }
 }
 
 impl<N: ConnectedNetwork<PubKey>, D: Sync, V: Versions, P: SequencerPersistence>
-    StakeTableDataSource<SeqTypes> for StorageState<N, P, D, V>
+    StakeTableDataSource for StorageState<N, P, D, V>
 {
     /// Get the stake table for a given epoch or the current epoch if not provided
-    async fn get_stake_table(
-        &self,
-        epoch: Option<<SeqTypes as NodeType>::Epoch>,
-    ) -> Vec<StakeTableEntry<<SeqTypes as NodeType>::SignatureKey>> {
+    async fn get_stake_table(&self, epoch: Option<EpochNumber>) -> EpochStakeTable {
         self.as_ref().get_stake_table(epoch).await
     }
+
+    async fn get_stake_table_for_height(&self, height: u64) -> EpochStakeTable {
+        self.as_ref().get_stake_table_for_height(height).await
+    }
+}
+
+impl<N, P, D, V> DaMirrorDataSource for StorageState<N, P, D, V>
+where
+    N: ConnectedNetwork<PubKey>,
+    V: Versions,
+    P: SequencerPersistence,
+    D: Sync,
+{
+    async fn get_da_pointer(&self, height: u64) -> anyhow::Result<Option<DaPointer>> {
+        self.as_ref().get_da_pointer(height).await
+    }
+}
+
+impl<N, P, D, V> BlockTimeDataSource for StorageState<N, P, D, V>
+where
+    N: ConnectedNetwork<PubKey>,
+    V: Versions,
+    P: SequencerPersistence,
+    D: SequencerDataSource + Send + Sync,
+{
+    async fn get_block_by_time(&self, timestamp: u64) -> anyhow::Result<Option<BlockAtTime>> {
+        self.inner().get_block_by_time(timestamp).await
+    }
 }
 
-impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence>
-    StakeTableDataSource<SeqTypes> for ApiState<N, P, V>
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> StakeTableDataSource
+    for ApiState<N, P, V>
 {
     /// Get the stake table for a given epoch or the current epoch if not provided
-    async fn get_stake_table(
-        &self,
-        epoch: Option<<SeqTypes as NodeType>::Epoch>,
-    ) -> Vec<StakeTableEntry<<SeqTypes as NodeType>::SignatureKey>> {
+    async fn get_stake_table(&self, epoch: Option<EpochNumber>) -> EpochStakeTable {
         // Get the epoch from the argument or the current epoch if not provided
         let epoch = if let Some(epoch) = epoch {
             epoch
@@ -189,124 +513,484 @@ impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence>
             self.consensus().await.read().await.cur_epoch().await
         };
 
-        self.consensus()
-            .await
-            .read()
-            .await
-            .memberships
-            .quorum_membership
-            .stake_table(epoch)
+        // Prefer the stake table recorded when the epoch began.
+        match self.persistence().await.load_stake_table(epoch).await {
+            Ok(Some(stake_table)) => return stake_table,
+            Ok(None) => {}
+            Err(err) => tracing::warn!(?epoch, "failed to load stake table: {err:#}"),
+        }
+
+        let peers = self.network_config().await.config.known_nodes_with_stake;
+        epoch_stake_table(&*self.consensus().await.read().await, &peers, epoch)
+    }
+
+    async fn get_stake_table_for_height(&self, height: u64) -> EpochStakeTable {
+        let epoch_height = self.network_config().await.config.epoch_height;
+        let epoch = EpochNumber::new(epoch_from_block_number(height, epoch_height));
+        self.get_stake_table(Some(epoch)).await
     }
 }
 
-impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> SubmitDataSource<N, P>
+impl<N: ConnectedNetwork<PubKey>, D: Sync, V: Versions, P: SequencerPersistence>
+    LeaderScheduleDataSource for StorageState<N, P, D, V>
+{
+    async fn get_leaders(
+        &self,
+        from_view: Option<u64>,
+        count: u64,
+    ) -> anyhow::Result<LeaderSchedule> {
+        self.as_ref().get_leaders(from_view, count).await
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> LeaderScheduleDataSource
     for ApiState<N, P, V>
 {
-    async fn submit(&self, tx: Transaction) -> anyhow::Result<()> {
-        let handle = self.consensus().await;
+    async fn get_leaders(
+        &self,
+        from_view: Option<u64>,
+        count: u64,
+    ) -> anyhow::Result<LeaderSchedule> {
+        let consensus = self.consensus().await;
+        let consensus = consensus.read().await;
+        let current_view = consensus.cur_view().await;
+        let epoch = consensus.cur_epoch().await;
+        let from_view = from_view.map_or(current_view, ViewNumber::new);
+
+        // The leader of each view is determined by the stake table alone. We only know the stake
+        // table of the current epoch, so views in later epochs are predicted as if it stays the
+        // same.
+        let membership = &consensus.memberships.quorum_membership;
+        let leaders = (0..count)
+            .map(|i| {
+                let view = from_view + i;
+                let leader = membership
+                    .leader(view, epoch)
+                    .map_err(|err| anyhow::anyhow!("no leader for view {view:?}: {err}"))?;
+                Ok(ViewLeader {
+                    view: view.u64(),
+                    leader,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(LeaderSchedule {
+            current_view: current_view.u64(),
+            epoch: epoch.u64(),
+            leaders,
+        })
+    }
+}
 
-        let consensus_read_lock = handle.read().await;
+impl<N: ConnectedNetwork<PubKey>, D: Sync, V: Versions, P: SequencerPersistence> ViewLogDataSource
+    for StorageState<N, P, D, V>
+{
+    async fn get_view_records(&self, from: u64, until: u64) -> anyhow::Result<Vec<ViewRecord>> {
+        self.as_ref().get_view_records(from, until).await
+    }
+}
 
-        // Fetch full chain config from the validated state, if present.
-        // This is necessary because we support chain config upgrades,
-        // so the updated chain config is found in the validated state.
-        let cf = consensus_read_lock
-            .decided_state()
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> ViewLogDataSource
+    for ApiState<N, P, V>
+{
+    async fn get_view_records(&self, from: u64, until: u64) -> anyhow::Result<Vec<ViewRecord>> {
+        self.persistence()
             .await
-            .chain_config
-            .resolve();
+            .load_view_records(from, until)
+            .await
+    }
+}
 
-        // Use the chain config from the validated state if available,
-        // otherwise, use the node state's chain config
-        // The node state's chain config is the node's base version chain config
-        let cf = match cf {
-            Some(cf) => cf,
-            None => self.node_state().await.chain_config,
-        };
+impl<N: ConnectedNetwork<PubKey>, D: Sync, V: Versions, P: SequencerPersistence> SolverDataSource
+    for StorageState<N, P, D, V>
+{
+    async fn marketplace_view(&self) -> Option<ViewNumber> {
+        self.as_ref().marketplace_view().await
+    }
 
-        let max_block_size: u64 = cf.max_block_size.into();
-        let txn_size = tx.payload().len() as u64;
+    async fn get_auction_results(&self, view: ViewNumber) -> anyhow::Result<SolverAuctionResults> {
+        self.as_ref().get_auction_results(view).await
+    }
+
+    async fn submit_bid(&self, bid: &BidTx) -> anyhow::Result<()> {
+        self.as_ref().submit_bid(bid).await
+    }
+}
 
-        // reject transaction bigger than block size
-        if txn_size > max_block_size {
-            bail!("transaction size ({txn_size}) is greater than max_block_size ({max_block_size})")
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> SolverDataSource
+    for ApiState<N, P, V>
+{
+    async fn marketplace_view(&self) -> Option<ViewNumber> {
+        let consensus = self.consensus().await;
+        let consensus = consensus.read().await;
+        let leaf = consensus.decided_leaf().await;
+        if leaf.block_header().version() < MarketplaceVersion::version() {
+            return None;
         }
+        Some(consensus.cur_view().await)
+    }
 
-        consensus_read_lock.submit_transaction(tx).await?;
-        Ok(())
+    async fn get_auction_results(&self, view: ViewNumber) -> anyhow::Result<SolverAuctionResults> {
+        let results =
+            AuctionResultsProvider::<SeqTypes>::fetch_auction_result(self.solver().await, view)
+                .await?;
+        // Consensus does not check auction results, so don't pass on inconsistent results from
+        // the solver.
+        results
+            .validate()
+            .context("solver returned invalid auction results")?;
+        Ok(results)
+    }
+
+    async fn submit_bid(&self, bid: &BidTx) -> anyhow::Result<()> {
+        self.solver().await.submit_bid(bid).await
     }
 }
 
-impl<N, P, D, V> NodeStateDataSource for StorageState<N, P, D, V>
-where
-    N: ConnectedNetwork<PubKey>,
-    V: Versions,
-    P: SequencerPersistence,
-    D: Sync,
+impl<N: ConnectedNetwork<PubKey>, D: Sync, V: Versions, P: SequencerPersistence>
+    DecideEventDataSource for StorageState<N, P, D, V>
 {
-    async fn node_state(&self) -> &NodeState {
-        self.as_ref().node_state().await
+    async fn get_decide_events(&self, from: u64, limit: usize) -> anyhow::Result<Vec<DecideEvent>> {
+        self.as_ref().get_decide_events(from, limit).await
     }
 }
 
-impl<
-        N: ConnectedNetwork<PubKey>,
-        V: Versions,
-        P: SequencerPersistence,
-        D: CatchupStorage + Send + Sync,
-    > CatchupDataSource for StorageState<N, P, D, V>
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> DecideEventDataSource
+    for ApiState<N, P, V>
 {
-    #[tracing::instrument(skip(self, instance))]
-    async fn get_accounts(
-        &self,
-        instance: &NodeState,
-        height: u64,
-        view: ViewNumber,
-        accounts: &[FeeAccount],
-    ) -> anyhow::Result<FeeMerkleTree> {
-        // Check if we have the desired state in memory.
-        match self
-            .as_ref()
-            .get_accounts(instance, height, view, accounts)
+    async fn get_decide_events(&self, from: u64, limit: usize) -> anyhow::Result<Vec<DecideEvent>> {
+        self.persistence()
             .await
-        {
-            Ok(accounts) => return Ok(accounts),
-            Err(err) => {
-                tracing::info!("accounts not in memory, trying storage: {err:#}");
-            }
-        }
-
-        // Try storage.
-        let (tree, leaf) = self
-            .inner()
-            .get_accounts(instance, height, view, accounts)
+            .load_decide_events(from, limit)
             .await
-            .context("accounts not in memory, and could not fetch from storage")?;
-        // If we successfully fetched accounts from storage, try to add them back into the in-memory
-        // state.
-        let handle = self.as_ref().consensus().await;
-        let handle = handle.read().await;
-        let consensus = handle.consensus();
-        let mut consensus = consensus.write().await;
-        let (state, delta, leaf_commit) = match consensus.validated_state_map().get(&view) {
-            Some(View {
-                view_inner: ViewInner::Leaf { state, delta, leaf },
-            }) => {
-                let mut state = (**state).clone();
+    }
+}
 
-                // Add the fetched accounts to the state.
-                for account in accounts {
-                    if let Some((proof, _)) = FeeAccountProof::prove(&tree, (*account).into()) {
-                        if let Err(err) = proof.remember(&mut state.fee_merkle_tree) {
-                            tracing::warn!(
-                                ?view,
-                                %account,
-                                "cannot update fetched account state: {err:#}"
-                            );
-                        }
-                    } else {
-                        tracing::warn!(?view, %account, "cannot update fetched account state because account is not in the merkle tree");
-                    };
+impl<N: ConnectedNetwork<PubKey>, D: Sync, V: Versions, P: SequencerPersistence> TxStatusDataSource
+    for StorageState<N, P, D, V>
+{
+    async fn get_tx_status(
+        &self,
+        hash: Commitment<Transaction>,
+    ) -> anyhow::Result<Option<TxStatus>> {
+        self.as_ref().get_tx_status(hash).await
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> TxStatusDataSource
+    for ApiState<N, P, V>
+{
+    async fn get_tx_status(
+        &self,
+        hash: Commitment<Transaction>,
+    ) -> anyhow::Result<Option<TxStatus>> {
+        self.persistence().await.load_tx_status(hash).await
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, D: Sync, V: Versions, P: SequencerPersistence> MempoolDataSource
+    for StorageState<N, P, D, V>
+{
+    async fn pending_transactions(&self) -> Vec<PendingTransaction> {
+        self.as_ref().pending_transactions().await
+    }
+
+    async fn evict_pending_transaction(
+        &self,
+        hash: Commitment<Transaction>,
+    ) -> anyhow::Result<bool> {
+        self.as_ref().evict_pending_transaction(hash).await
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> MempoolDataSource
+    for ApiState<N, P, V>
+{
+    async fn pending_transactions(&self) -> Vec<PendingTransaction> {
+        self.mempool().await.pending()
+    }
+
+    async fn evict_pending_transaction(
+        &self,
+        hash: Commitment<Transaction>,
+    ) -> anyhow::Result<bool> {
+        self.mempool().await.evict(hash).await
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> MempoolDataSource
+    for AdminState<N, P, V>
+{
+    async fn pending_transactions(&self) -> Vec<PendingTransaction> {
+        self.node.pending_transactions().await
+    }
+
+    async fn evict_pending_transaction(
+        &self,
+        hash: Commitment<Transaction>,
+    ) -> anyhow::Result<bool> {
+        self.node.evict_pending_transaction(hash).await
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> SubmitDataSource<N, P>
+    for ApiState<N, P, V>
+{
+    async fn submit(&self, tx: Transaction) -> anyhow::Result<()> {
+        let hash = tx.commit();
+        let res = self.try_submit(tx.clone()).await;
+
+        // Keep the transaction in the mempool until it is sequenced, so it survives restarts and
+        // reaches other nodes' builders.
+        if res.is_ok() {
+            if let Err(err) = self.mempool().await.add(tx).await {
+                tracing::warn!(%hash, "failed to add transaction to mempool: {err:#}");
+            }
+        }
+
+        self.record_tx_status(hash, &res).await;
+        res
+    }
+
+    async fn submit_batch(&self, txs: Vec<Transaction>) -> Vec<anyhow::Result<()>> {
+        if let Err(err) = self.ensure_not_draining().await {
+            return txs.iter().map(|_| Err(anyhow!("{err:#}"))).collect();
+        }
+
+        // Hold the consensus handle once for the whole batch, rather than once per transaction.
+        let mut results = Vec::with_capacity(txs.len());
+        let mut accepted = vec![];
+        {
+            let consensus = self.consensus().await.read().await;
+            for tx in &txs {
+                let res = consensus
+                    .submit_transaction(tx.clone())
+                    .await
+                    .map_err(anyhow::Error::from);
+                if res.is_ok() {
+                    accepted.push(tx.clone());
+                }
+                results.push(res);
+            }
+        }
+
+        // Accepted transactions are added to the mempool, and gossiped to peers, as one batch.
+        if !accepted.is_empty() {
+            if let Err(err) = self.mempool().await.add_batch(accepted).await {
+                tracing::warn!("failed to add transaction batch to mempool: {err:#}");
+            }
+        }
+
+        for (tx, res) in txs.iter().zip(&results) {
+            self.record_tx_status(tx.commit(), res).await;
+        }
+        results
+    }
+
+    async fn submit_and_wait(
+        &self,
+        tx: Transaction,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<DecidedTransaction>> {
+        let hash = tx.commit();
+
+        // Subscribe before submitting, so we cannot miss the decide event for the transaction.
+        let mut events = self.get_event_stream(None).await;
+
+        // If the transaction has already been sequenced, there is nothing to wait for, and
+        // resubmitting it would reset its status.
+        if let Some(TxStatus::Sequenced { block, index }) = self.get_tx_status(hash).await? {
+            return Ok(Some(DecidedTransaction { hash, block, index }));
+        }
+        self.submit(tx).await?;
+
+        let decided = async move {
+            while let Some(event) = events.next().await {
+                let EventType::Decide { leaf_chain, .. } = &event.event else {
+                    continue;
+                };
+                for LeafInfo { leaf, .. } in leaf_chain.iter() {
+                    let Some(payload) = leaf.block_payload() else {
+                        continue;
+                    };
+                    if let Some(index) = payload
+                        .transactions(payload.ns_table())
+                        .position(|tx| tx.commit() == hash)
+                    {
+                        return Ok(DecidedTransaction {
+                            hash,
+                            block: leaf.block_header().height(),
+                            index: index as u64,
+                        });
+                    }
+                }
+            }
+            bail!("event stream ended before transaction {hash} was decided");
+        };
+        match tokio::time::timeout(timeout, decided).await {
+            Ok(res) => res.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    async fn active_chain_config(&self) -> ChainConfig {
+        // Fetch full chain config from the validated state, if present.
+        // This is necessary because we support chain config upgrades,
+        // so the updated chain config is found in the validated state.
+        let cf = self
+            .consensus()
+            .await
+            .read()
+            .await
+            .decided_state()
+            .await
+            .chain_config
+            .resolve();
+
+        // Use the chain config from the validated state if available,
+        // otherwise, use the node state's chain config
+        // The node state's chain config is the node's base version chain config
+        match cf {
+            Some(cf) => cf,
+            None => self.node_state().await.chain_config,
+        }
+    }
+
+    async fn trace_transaction(
+        &self,
+        hash: Commitment<Transaction>,
+        ns: NamespaceId,
+        parent: Option<TraceParent>,
+    ) {
+        self.tx_tracer().await.start(hash, ns, parent);
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> ApiState<N, P, V> {
+    async fn try_submit(&self, tx: Transaction) -> anyhow::Result<()> {
+        self.ensure_not_draining().await?;
+        self.consensus()
+            .await
+            .read()
+            .await
+            .submit_transaction(tx)
+            .await?;
+        Ok(())
+    }
+
+    /// Record the outcome of a submission so clients can later query the status of the
+    /// transaction.
+    ///
+    /// Failing to record the status does not affect the submission itself.
+    async fn record_tx_status(&self, hash: Commitment<Transaction>, res: &anyhow::Result<()>) {
+        let status = match res {
+            Ok(()) => TxStatus::Pending,
+            Err(err) => TxStatus::Rejected {
+                reason: format!("{err:#}"),
+            },
+        };
+        if let Err(err) = self.persistence().await.store_tx_status(hash, status).await {
+            tracing::warn!(%hash, "failed to store transaction status: {err:#}");
+        }
+
+        // Submission is the first stage of the transaction's trace, if it is being traced.
+        self.tx_tracer().await.forwarded(hash, res.is_ok());
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, D: Sync, V: Versions, P: SequencerPersistence>
+    FeeEstimateDataSource for StorageState<N, P, D, V>
+{
+    async fn estimate_fee(&self, size: u64) -> anyhow::Result<FeeEstimate> {
+        self.as_ref().estimate_fee(size).await
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> FeeEstimateDataSource
+    for ApiState<N, P, V>
+{
+    async fn estimate_fee(&self, size: u64) -> anyhow::Result<FeeEstimate> {
+        let chain_config = self.active_chain_config().await;
+        let leaf = self.consensus().await.read().await.decided_leaf().await;
+
+        // The decided leaf may not have its payload attached, in which case we have no information
+        // about recent block fullness and estimate as if blocks are empty.
+        let block_size = leaf.block_payload().map_or(0, |payload| {
+            payload.ns_byte_lens().map(|(_, len)| len as u64).sum()
+        });
+        Ok(FeeEstimate::new(&chain_config, block_size, size))
+    }
+}
+
+impl<N, P, D, V> NodeStateDataSource for StorageState<N, P, D, V>
+where
+    N: ConnectedNetwork<PubKey>,
+    V: Versions,
+    P: SequencerPersistence,
+    D: Sync,
+{
+    async fn node_state(&self) -> &NodeState {
+        self.as_ref().node_state().await
+    }
+}
+
+impl<
+        N: ConnectedNetwork<PubKey>,
+        V: Versions,
+        P: SequencerPersistence,
+        D: CatchupStorage + Send + Sync,
+    > CatchupDataSource for StorageState<N, P, D, V>
+{
+    #[tracing::instrument(skip(self, instance))]
+    async fn get_accounts(
+        &self,
+        instance: &NodeState,
+        height: u64,
+        view: ViewNumber,
+        accounts: &[FeeAccount],
+    ) -> anyhow::Result<FeeMerkleTree> {
+        // Check if we have the desired state in memory.
+        match self
+            .as_ref()
+            .get_accounts(instance, height, view, accounts)
+            .await
+        {
+            Ok(accounts) => return Ok(accounts),
+            Err(err) => {
+                tracing::info!("accounts not in memory, trying storage: {err:#}");
+            }
+        }
+
+        // Try storage.
+        let (tree, leaf) = self
+            .inner()
+            .get_accounts(instance, height, view, accounts)
+            .await
+            .context("accounts not in memory, and could not fetch from storage")?;
+        // If we successfully fetched accounts from storage, try to add them back into the in-memory
+        // state.
+        let handle = self.as_ref().consensus().await;
+        let handle = handle.read().await;
+        let consensus = handle.consensus();
+        let mut consensus = consensus.write().await;
+        let (state, delta, leaf_commit) = match consensus.validated_state_map().get(&view) {
+            Some(View {
+                view_inner: ViewInner::Leaf { state, delta, leaf },
+            }) => {
+                let mut state = (**state).clone();
+
+                // Add the fetched accounts to the state.
+                for account in accounts {
+                    if let Some((proof, _)) = FeeAccountProof::prove(&tree, (*account).into()) {
+                        if let Err(err) = proof.remember(&mut state.fee_merkle_tree) {
+                            tracing::warn!(
+                                ?view,
+                                %account,
+                                "cannot update fetched account state: {err:#}"
+                            );
+                        }
+                    } else {
+                        tracing::warn!(?view, %account, "cannot update fetched account state because account is not in the merkle tree");
+                    };
                 }
 
                 (Arc::new(state), delta.clone(), *leaf)
@@ -378,6 +1062,119 @@ impl<
     }
 }
 
+impl<
+        N: ConnectedNetwork<PubKey>,
+        V: Versions,
+        P: SequencerPersistence,
+        D: CatchupStorage + Send + Sync,
+    > FeeAccountDataSource for StorageState<N, P, D, V>
+{
+    async fn get_fee_deposits(
+        &self,
+        account: FeeAccount,
+        offset: u64,
+        limit: u64,
+    ) -> anyhow::Result<FeeDepositsQueryData> {
+        let persistence = self.as_ref().persistence().await;
+        let l1_block = persistence.load_fee_deposits_l1_block().await?;
+        let deposits = persistence
+            .load_fee_deposits(account, offset, limit)
+            .await?;
+        let next = (deposits.len() as u64 == limit).then_some(offset + limit);
+        Ok(FeeDepositsQueryData {
+            l1_block,
+            deposits,
+            next,
+        })
+    }
+
+    async fn get_builder_earnings(&self, account: FeeAccount) -> anyhow::Result<BuilderEarnings> {
+        self.as_ref().builder_fees().await.earnings(account).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_fee_account(
+        &self,
+        account: FeeAccount,
+        height: Option<u64>,
+    ) -> anyhow::Result<FeeAccountQueryData> {
+        let instance = self.node_state().await;
+
+        if let Some(height) = height {
+            // Historical queries are always served from storage.
+            let (tree, leaf) = self
+                .inner()
+                .get_historical_accounts(height, &[account])
+                .await?;
+            let (proof, balance) = FeeAccountProof::prove(&tree, account.into()).context(
+                format!("account {account} not available for height {height}"),
+            )?;
+            let pending_deposits = pending_deposits(instance, leaf.block_header(), account).await;
+            return Ok(FeeAccountQueryData {
+                height,
+                balance,
+                proof,
+                pending_deposits,
+            });
+        }
+
+        let leaf = self
+            .as_ref()
+            .consensus()
+            .await
+            .read()
+            .await
+            .decided_leaf()
+            .await;
+        let header = leaf.block_header();
+        let height = header.height();
+
+        // Look up the account in the decided state, falling back to storage if it is not in
+        // memory.
+        let AccountQueryData { balance, proof } = self
+            .get_account(instance, height, leaf.view_number(), account)
+            .await?;
+        let pending_deposits = pending_deposits(instance, header, account).await;
+
+        Ok(FeeAccountQueryData {
+            height,
+            balance,
+            proof,
+            pending_deposits,
+        })
+    }
+}
+
+/// Get deposits to `account` which are finalized on the L1 but not yet reflected in the fee state
+/// as of `header`.
+async fn pending_deposits(
+    instance: &NodeState,
+    header: &Header,
+    account: FeeAccount,
+) -> Vec<FeeInfo> {
+    let chain_config = header
+        .chain_config()
+        .resolve()
+        .unwrap_or(instance.chain_config);
+    let Some(fee_contract) = chain_config.fee_contract else {
+        return vec![];
+    };
+    let Some(finalized) = instance.l1_client.snapshot().await.finalized else {
+        return vec![];
+    };
+    instance
+        .l1_client
+        .get_finalized_deposits(
+            fee_contract,
+            header.l1_finalized().map(|block| block.number()),
+            finalized.number(),
+        )
+        .await
+        .into_iter()
+        .filter(|deposit| deposit.account() == account)
+        .collect()
+}
+
 // #[async_trait]
 // impl<
 //         N: ConnectedNetwork<PubKey>,
@@ -397,6 +1194,17 @@ impl<
 //     }
 // }
 
+impl<N, V, P> DaMirrorDataSource for ApiState<N, P, V>
+where
+    N: ConnectedNetwork<PubKey>,
+    V: Versions,
+    P: SequencerPersistence,
+{
+    async fn get_da_pointer(&self, height: u64) -> anyhow::Result<Option<DaPointer>> {
+        self.persistence().await.load_da_pointer(height).await
+    }
+}
+
 impl<N, V, P> NodeStateDataSource for ApiState<N, P, V>
 where
     N: ConnectedNetwork<PubKey>,
@@ -469,12 +1277,52 @@ impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> CatchupD
     }
 }
 
+impl<
+        N: ConnectedNetwork<PubKey>,
+        V: Versions,
+        P: SequencerPersistence,
+        D: CatchupStorage + Send + Sync,
+    > ChainConfigHistoryDataSource for StorageState<N, P, D, V>
+{
+    async fn get_chain_config_history(&self) -> anyhow::Result<Vec<ChainConfigActivation>> {
+        self.inner().get_chain_config_history().await
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> ChainConfigHistoryDataSource
+    for ApiState<N, P, V>
+{
+    async fn get_chain_config_history(&self) -> anyhow::Result<Vec<ChainConfigActivation>> {
+        bail!("chain config history requires the query module with SQL storage");
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, D: Sync, V: Versions, P: SequencerPersistence> UpgradeDataSource
+    for StorageState<N, P, D, V>
+{
+    async fn get_upgrades(&self) -> Vec<UpgradeInfo> {
+        self.as_ref().get_upgrades().await
+    }
+}
+
+impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> UpgradeDataSource
+    for ApiState<N, P, V>
+{
+    async fn get_upgrades(&self) -> Vec<UpgradeInfo> {
+        self.upgrades().await.upgrades()
+    }
+}
+
 impl<N: ConnectedNetwork<PubKey>, D: Sync, V: Versions, P: SequencerPersistence>
     HotShotConfigDataSource for StorageState<N, P, D, V>
 {
     async fn get_config(&self) -> PublicNetworkConfig {
         self.as_ref().network_config().await.into()
     }
+
+    async fn get_builders(&self) -> Vec<BuilderInfo> {
+        self.as_ref().get_builders().await
+    }
 }
 
 impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> HotShotConfigDataSource
@@ -483,6 +1331,10 @@ impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> HotShotC
     async fn get_config(&self) -> PublicNetworkConfig {
         self.network_config().await.into()
     }
+
+    async fn get_builders(&self) -> Vec<BuilderInfo> {
+        self.builder_registry().await.builders().await
+    }
 }
 
 #[async_trait]
@@ -492,6 +1344,10 @@ impl<N: ConnectedNetwork<PubKey>, D: Sync, V: Versions, P: SequencerPersistence>
     async fn get_state_signature(&self, height: u64) -> Option<StateSignatureRequestBody> {
         self.as_ref().get_state_signature(height).await
     }
+
+    async fn get_aggregate(&self, height: u64) -> Option<StateSignatureBundleQueryData> {
+        self.as_ref().get_aggregate(height).await
+    }
 }
 
 #[async_trait]
@@ -501,9 +1357,16 @@ impl<N: ConnectedNetwork<PubKey>, V: Versions, P: SequencerPersistence> StateSig
     async fn get_state_signature(&self, height: u64) -> Option<StateSignatureRequestBody> {
         self.state_signer().await.get_state_signature(height).await
     }
-}
 
-#[cfg(any(test, feature = "testing"))]
+    async fn get_aggregate(&self, height: u64) -> Option<StateSignatureBundleQueryData> {
+        self.state_signer()
+            .await
+            .get_state_signature_bundle(height)
+            .await
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
 pub mod test_helpers {
     use std::time::Duration;
 
@@ -523,6 +1386,7 @@ pub mod test_helpers {
     use hotshot_contract_adapter::light_client::{ParsedLightClientState, ParsedStakeTableState};
     use hotshot_types::{
         event::LeafInfo,
+        message::UpgradeLock,
         traits::{metrics::NoMetrics, node_implementation::ConsensusTime},
     };
     use itertools::izip;
@@ -828,6 +1692,18 @@ pub mod test_helpers {
         assert!(success_rate.is_finite(), "{success_rate}");
         // We know at least some views have been successful, since we finalized a block.
         assert!(success_rate > 0.0, "{success_rate}");
+
+        // The node reports its identity, including the status module we enabled.
+        let identity = client
+            .get::<telemetry::NodeIdentity>("status/node-identity")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(identity.version, env!("CARGO_PKG_VERSION"));
+        assert!(
+            identity.modules.contains(&"status".to_string()),
+            "{identity:?}"
+        );
     }
 
     /// Test the submit API with custom options.
@@ -988,6 +1864,32 @@ pub mod test_helpers {
             0.into()
         );
 
+        // Undecided fee state: bulk query for multiple accounts.
+        let accounts = vec![
+            FeeAccount::from(Address::default()),
+            FeeAccount::from(Address::repeat_byte(1)),
+        ];
+        let tree = client
+            .post::<FeeMerkleTree>(&format!("catchup/{height}/{}/accounts", view.u64()))
+            .body_binary(&accounts)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        let fee_root = network
+            .server
+            .state(view)
+            .await
+            .unwrap()
+            .fee_merkle_tree
+            .commitment();
+        assert_eq!(tree.commitment(), fee_root);
+        for account in accounts {
+            let (proof, balance) = FeeAccountProof::prove(&tree, account.into()).unwrap();
+            assert_eq!(balance, 0.into());
+            assert_eq!(proof.verify(&fee_root).unwrap(), 0.into());
+        }
+
         // Undecided block state.
         let res = client
             .get::<BlocksFrontier>(&format!("catchup/{height}/{}/blocks", view.u64()))
@@ -1092,12 +1994,13 @@ mod api_tests {
         TestNetwork, TestNetworkConfigBuilder,
     };
     use tide_disco::error::ServerError;
+    use url::Url;
     use vbs::version::StaticVersion;
 
     use super::{update::ApiEventConsumer, *};
     use crate::{
         persistence::no_storage::NoStorage,
-        testing::{wait_for_decide_on_handle, TestConfigBuilder},
+        testing::{wait_for_decide_on_handle, TestConfig, TestConfigBuilder},
     };
 
     #[tokio::test(flavor = "multi_thread")]
@@ -1214,6 +2117,23 @@ mod api_tests {
         }
         assert!(found_txn);
         assert!(found_empty_block);
+
+        // The namespace stream should yield the same data as the individual queries.
+        let mut stream = client
+            .socket(&format!("availability/stream/namespace/{ns_id}/0"))
+            .subscribe::<NamespaceProofQueryData>()
+            .await
+            .unwrap();
+        for block_num in 0..=block_height {
+            let expected: NamespaceProofQueryData = client
+                .get(&format!("availability/block/{block_num}/namespace/{ns_id}"))
+                .send()
+                .await
+                .unwrap();
+            let streamed = stream.next().await.unwrap().unwrap();
+            assert_eq!(streamed.transactions, expected.transactions);
+            assert_eq!(streamed.proof.is_some(), expected.proof.is_some());
+        }
     }
 
     #[tokio::test(flavor = "multi_thread")]
@@ -1222,6 +2142,160 @@ mod api_tests {
         catchup_test_helper(|opt| D::options(&storage, opt)).await
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    pub(crate) async fn test_fee_account<D: TestableSequencerDataSource>() {
+        setup_test();
+
+        let port = pick_unused_port().expect("No ports free");
+        let storage = D::create_storage().await;
+        let anvil = Anvil::new().spawn();
+        let l1 = anvil.endpoint().parse().unwrap();
+        let network_config = TestConfigBuilder::default().l1_url(l1).build();
+        let config = TestNetworkConfigBuilder::default()
+            .api_config(D::options(&storage, Options::with_port(port)).fee(Default::default()))
+            .network_config(network_config)
+            .build();
+        let _network = TestNetwork::new(config, MockSequencerVersions::new()).await;
+
+        let client: Client<ServerError, StaticVersion<0, 1>> =
+            Client::new(format!("http://localhost:{port}").parse().unwrap());
+        client.connect(None).await;
+
+        // Wait for a few blocks to be decided.
+        client
+            .socket("availability/stream/blocks/3")
+            .subscribe::<BlockQueryData<SeqTypes>>()
+            .await
+            .unwrap()
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+
+        // The builder account is funded at genesis, so it should have a balance with a valid
+        // proof against the fee state of the reported block.
+        let account = TestConfig::<5>::builder_key().fee_account();
+        let res: FeeAccountQueryData = client
+            .get(&format!("fee/account/{account}"))
+            .send()
+            .await
+            .unwrap();
+        assert!(res.height >= 3, "{res:?}");
+        assert!(res.balance > 0.into(), "{res:?}");
+        assert!(res.pending_deposits.is_empty(), "{res:?}");
+
+        let header: Header = client
+            .get(&format!("availability/header/{}", res.height))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            res.proof.verify(&header.fee_merkle_tree_root()).unwrap(),
+            res.balance
+        );
+
+        // An account with no entry in the fee state has a zero balance and a non-membership proof.
+        let account = FeeAccount::default();
+        let res: FeeAccountQueryData = client
+            .get(&format!("fee/account/{account}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.balance, 0.into());
+        let header: Header = client
+            .get(&format!("availability/header/{}", res.height))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            res.proof.verify(&header.fee_merkle_tree_root()).unwrap(),
+            0.into()
+        );
+
+        // Malformed addresses are rejected.
+        client
+            .get::<FeeAccountQueryData>("fee/account/not-an-address")
+            .send()
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub(crate) async fn test_range_pagination<D: TestableSequencerDataSource>() {
+        setup_test();
+
+        let port = pick_unused_port().expect("No ports free");
+        let storage = D::create_storage().await;
+        let anvil = Anvil::new().spawn();
+        let l1 = anvil.endpoint().parse().unwrap();
+        let network_config = TestConfigBuilder::default().l1_url(l1).build();
+        let http = options::Http {
+            max_range_size: Some(2),
+            ..options::Http::with_port(port)
+        };
+        let config = TestNetworkConfigBuilder::default()
+            .api_config(D::options(&storage, http.into()))
+            .network_config(network_config)
+            .build();
+        let _network = TestNetwork::new(config, MockSequencerVersions::new()).await;
+
+        let url: Url = format!("http://localhost:{port}").parse().unwrap();
+        let client: Client<ServerError, StaticVersion<0, 1>> = Client::new(url.clone());
+        client.connect(None).await;
+
+        // Wait for a few blocks to be decided.
+        client
+            .socket("availability/stream/blocks/4")
+            .subscribe::<BlockQueryData<SeqTypes>>()
+            .await
+            .unwrap()
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Follow `next` links through a range larger than a page.
+        let mut path = "/availability/header/0/5".to_string();
+        let mut headers = vec![];
+        let mut pages = 0;
+        loop {
+            let res = reqwest::get(url.join(&path).unwrap()).await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::OK);
+            let next = res
+                .headers()
+                .get("Link")
+                .map(|link| link.to_str().unwrap().to_string());
+            let page: Vec<Header> = res.json().await.unwrap();
+            assert!(page.len() <= 2, "{page:?}");
+            headers.extend(page);
+            pages += 1;
+
+            let Some(link) = next else {
+                break;
+            };
+            path = link
+                .strip_prefix('<')
+                .and_then(|link| link.strip_suffix(">; rel=\"next\""))
+                .unwrap()
+                .to_string();
+        }
+        assert_eq!(pages, 3);
+        assert_eq!(
+            headers
+                .iter()
+                .map(|header| header.height())
+                .collect::<Vec<_>>(),
+            (0..5).collect::<Vec<_>>()
+        );
+
+        // Ranges within the limit are served in one response, without a link.
+        let res = reqwest::get(url.join("/availability/header/0/2").unwrap())
+            .await
+            .unwrap();
+        assert!(res.headers().get("Link").is_none());
+        assert_eq!(res.json::<Vec<Header>>().await.unwrap().len(), 2);
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     pub async fn test_non_consecutive_decide_with_failing_event_consumer<D>()
     where
@@ -1250,39 +2324,7 @@ mod api_tests {
             ));
 
         // Create two non-consecutive leaf chains.
-        let mut chain1 = vec![];
-
-        let mut quorum_proposal = QuorumProposal::<SeqTypes> {
-            block_header: Leaf::genesis(&Default::default(), &NodeState::mock())
-                .await
-                .block_header()
-                .clone(),
-            view_number: ViewNumber::genesis(),
-            justify_qc: QuorumCertificate::genesis::<MockSequencerVersions>(
-                &ValidatedState::default(),
-                &NodeState::mock(),
-            )
-            .await,
-            upgrade_certificate: None,
-            proposal_certificate: None,
-        };
-        let mut qc = QuorumCertificate::genesis::<MockSequencerVersions>(
-            &ValidatedState::default(),
-            &NodeState::mock(),
-        )
-        .await;
-
-        let mut justify_qc = qc.clone();
-        for i in 0..5 {
-            *quorum_proposal.block_header.height_mut() = i;
-            quorum_proposal.view_number = ViewNumber::new(i);
-            quorum_proposal.justify_qc = justify_qc;
-            let leaf = Leaf::from_quorum_proposal(&quorum_proposal);
-            qc.view_number = leaf.view_number();
-            qc.data.leaf_commit = Committable::commit(&leaf);
-            justify_qc = qc.clone();
-            chain1.push((leaf.clone(), qc.clone()));
-        }
+        let mut chain1 = mock_chain(5, 0).await;
         // Split into two chains.
         let mut chain2 = chain1.split_off(2);
         // Make non-consecutive (i.e. we skip a leaf).
@@ -1329,6 +2371,100 @@ mod api_tests {
         }
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    pub(crate) async fn test_reconcile_query_storage<D>()
+    where
+        D: TestableSequencerDataSource + Debug + 'static,
+    {
+        setup_test();
+
+        let storage = D::create_storage().await;
+        let data_source: Arc<StorageState<network::Memory, NoStorage, _, MockSequencerVersions>> =
+            Arc::new(StorageState::new(
+                D::create(D::persistence_options(&storage), Default::default(), false)
+                    .await
+                    .unwrap(),
+                ApiState::new(future::pending()),
+            ));
+        let consumer = ApiEventConsumer::from(data_source.clone());
+        let stored_leaf = |height: usize| {
+            let data_source = data_source.clone();
+            async move {
+                data_source
+                    .get_leaf(height)
+                    .await
+                    .try_resolve()
+                    .ok()
+                    .map(|leaf| leaf.leaf().clone())
+            }
+        };
+        let chain = mock_chain(3, 0).await;
+
+        tracing::info!("query storage behind consensus storage");
+        let (anchor, qc) = &chain[2];
+        consumer.reconcile(anchor, qc).await.unwrap();
+        assert_eq!(stored_leaf(2).await.as_ref(), Some(anchor));
+
+        tracing::info!("query storage ahead of consensus storage");
+        let (anchor, qc) = &chain[1];
+        consumer.reconcile(anchor, qc).await.unwrap();
+        assert_eq!(stored_leaf(1).await.as_ref(), Some(anchor));
+        assert_eq!(stored_leaf(2).await.as_ref(), Some(&chain[2].0));
+
+        tracing::info!("query storage on a different chain");
+        let fork = mock_chain(2, 10).await;
+        let (anchor, qc) = &fork[1];
+        match consumer.reconcile(anchor, qc).await {
+            Ok(()) => {
+                assert_eq!(stored_leaf(1).await.as_ref(), Some(anchor));
+                assert_eq!(stored_leaf(2).await, None);
+            }
+            Err(err) => {
+                // Not all backends can truncate storage, but those which can't must not ingest the
+                // conflicting leaf.
+                tracing::info!("failed to reconcile: {err:#}");
+                assert_eq!(stored_leaf(1).await.as_ref(), Some(&chain[1].0));
+            }
+        }
+    }
+
+    /// A chain of `len` mock leaves starting from height 0, in consecutive views from `first_view`.
+    async fn mock_chain(len: u64, first_view: u64) -> Vec<(Leaf, QuorumCertificate<SeqTypes>)> {
+        let mut quorum_proposal = QuorumProposal::<SeqTypes> {
+            block_header: Leaf::genesis(&Default::default(), &NodeState::mock())
+                .await
+                .block_header()
+                .clone(),
+            view_number: ViewNumber::genesis(),
+            justify_qc: QuorumCertificate::genesis::<MockSequencerVersions>(
+                &ValidatedState::default(),
+                &NodeState::mock(),
+            )
+            .await,
+            upgrade_certificate: None,
+            proposal_certificate: None,
+        };
+        let mut qc = QuorumCertificate::genesis::<MockSequencerVersions>(
+            &ValidatedState::default(),
+            &NodeState::mock(),
+        )
+        .await;
+
+        let mut chain = vec![];
+        let mut justify_qc = qc.clone();
+        for i in 0..len {
+            *quorum_proposal.block_header.height_mut() = i;
+            quorum_proposal.view_number = ViewNumber::new(first_view + i);
+            quorum_proposal.justify_qc = justify_qc;
+            let leaf = Leaf::from_quorum_proposal(&quorum_proposal);
+            qc.view_number = leaf.view_number();
+            qc.data.leaf_commit = Committable::commit(&leaf);
+            justify_qc = qc.clone();
+            chain.push((leaf.clone(), qc.clone()));
+        }
+        chain
+    }
+
     fn leaf_info(leaf: Leaf) -> LeafInfo<SeqTypes> {
         LeafInfo {
             leaf,
@@ -1346,10 +2482,13 @@ mod test {
     use tokio::time::sleep;
 
     use espresso_types::{
+        leaf_proof::{verify_leaf_certificate, LeafCertificateError},
         traits::NullEventConsumer,
+        transaction_proof::verify_transaction_proof,
         v0_1::{UpgradeMode, ViewBasedUpgrade},
-        BackoffParams, FeeAccount, FeeAmount, Header, MockSequencerVersions, SequencerVersions,
-        TimeBasedUpgrade, Timestamp, Upgrade, UpgradeType, ValidatedState,
+        BackoffParams, BlockMerkleTree, FeeAccount, FeeAmount, Header, MockSequencerVersions,
+        NamespaceId, SequencerVersions, TimeBasedUpgrade, Timestamp, Upgrade, UpgradeStatus,
+        UpgradeType, ValidatedState,
     };
     use ethers::utils::Anvil;
     use futures::{
@@ -1366,7 +2505,10 @@ mod test {
         traits::{metrics::NoMetrics, node_implementation::ConsensusTime},
         ValidatorConfig,
     };
-    use jf_merkle_tree::prelude::{MerkleProof, Sha3Node};
+    use jf_merkle_tree::{
+        prelude::{MerkleProof, Sha3Node},
+        MerkleCommitment, MerkleTreeScheme,
+    };
     use portpicker::pick_unused_port;
     use sequencer_utils::{ser::FromStringOrInteger, test_utils::setup_test};
     use surf_disco::Client;
@@ -1374,77 +2516,590 @@ mod test {
         catchup_test_helper, spawn_dishonest_peer_catchup_api, state_signature_test_helper,
         status_test_helper, submit_test_helper, TestNetwork, TestNetworkConfigBuilder,
     };
-    use tide_disco::{app::AppHealth, error::ServerError, healthcheck::HealthStatus};
+    use tide_disco::{app::AppHealth, error::ServerError, healthcheck::HealthStatus, StatusCode};
     use time::OffsetDateTime;
     use vbs::version::{StaticVersion, StaticVersionType, Version};
 
-    use self::{
-        data_source::{testing::TestableSequencerDataSource, PublicHotShotConfig},
-        options::HotshotEvents,
-        sql::DataSource as SqlDataSource,
-    };
-    use super::*;
-    use crate::{
-        catchup::{NullStateCatchup, StatePeers},
-        persistence::no_storage,
-        testing::{TestConfig, TestConfigBuilder},
-    };
+    use self::{
+        data_source::{testing::TestableSequencerDataSource, PublicHotShotConfig},
+        options::HotshotEvents,
+        sql::DataSource as SqlDataSource,
+    };
+    use super::*;
+    use crate::{
+        catchup::{NullStateCatchup, StatePeers},
+        persistence::no_storage,
+        testing::{TestConfig, TestConfigBuilder},
+    };
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_healthcheck() {
+        setup_test();
+
+        let port = pick_unused_port().expect("No ports free");
+        let url = format!("http://localhost:{port}").parse().unwrap();
+        let client: Client<ServerError, StaticVersion<0, 1>> = Client::new(url);
+        let options = Options::with_port(port);
+        let anvil = Anvil::new().spawn();
+        let l1 = anvil.endpoint().parse().unwrap();
+        let network_config = TestConfigBuilder::default().l1_url(l1).build();
+        let config = TestNetworkConfigBuilder::<5, _, NullStateCatchup>::default()
+            .api_config(options)
+            .network_config(network_config)
+            .build();
+        let _network = TestNetwork::new(config, MockSequencerVersions::new()).await;
+
+        client.connect(None).await;
+        let health = client.get::<AppHealth>("healthcheck").send().await.unwrap();
+        assert_eq!(health.status, HealthStatus::Available);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn status_test_without_query_module() {
+        status_test_helper(|opt| opt).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn submit_test_without_query_module() {
+        submit_test_helper(|opt| opt).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn state_signature_test_without_query_module() {
+        state_signature_test_helper(|opt| opt).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn catchup_test_without_query_module() {
+        catchup_test_helper(|opt| opt).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn slow_test_merklized_state_api() {
+        setup_test();
+
+        let port = pick_unused_port().expect("No ports free");
+
+        let storage = SqlDataSource::create_storage().await;
+        let options = SqlDataSource::options(
+            &storage,
+            Options::with_port(port)
+                .state(Default::default())
+                .status(Default::default()),
+        );
+
+        let anvil = Anvil::new().spawn();
+        let l1 = anvil.endpoint().parse().unwrap();
+        let network_config = TestConfigBuilder::default().l1_url(l1).build();
+        let config = TestNetworkConfigBuilder::default()
+            .api_config(options)
+            .network_config(network_config)
+            .build();
+        let mut network = TestNetwork::new(config, MockSequencerVersions::new()).await;
+        let url = format!("http://localhost:{port}").parse().unwrap();
+        let client: Client<ServerError, SequencerApiVersion> = Client::new(url);
+
+        client.connect(None).await;
+
+        // Wait until some blocks have been decided.
+        tracing::info!("waiting for blocks");
+        let blocks = client
+            .socket("availability/stream/blocks/0")
+            .subscribe::<BlockQueryData<SeqTypes>>()
+            .await
+            .unwrap()
+            .take(4)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        // sleep for few seconds so that state data is upserted
+        tracing::info!("waiting for state to be inserted");
+        sleep(Duration::from_secs(5)).await;
+        network.stop_consensus().await;
+
+        for block in blocks {
+            let i = block.height();
+            tracing::info!(i, "get block state");
+            let path = client
+                .get::<MerkleProof<Commitment<Header>, u64, Sha3Node, 3>>(&format!(
+                    "block-state/{}/{i}",
+                    i + 1
+                ))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(*path.elem().unwrap(), block.hash());
+
+            // The path proves the block against the root committed to by the later header.
+            let header = client
+                .get::<Header>(&format!("availability/header/{}", i + 1))
+                .send()
+                .await
+                .unwrap();
+            BlockMerkleTree::verify(header.block_merkle_tree_root().digest(), i, &path)
+                .unwrap()
+                .unwrap();
+
+            tracing::info!(i, "get fee state");
+            let account = TestConfig::<5>::builder_key().fee_account();
+            let path = client
+                .get::<MerkleProof<FeeAmount, FeeAccount, Sha3Node, 256>>(&format!(
+                    "fee-state/{}/{}",
+                    i + 1,
+                    account
+                ))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(*path.index(), account);
+            assert!(*path.elem().unwrap() > 0.into(), "{:?}", path.elem());
+        }
+
+        // testing fee_balance api
+        let account = TestConfig::<5>::builder_key().fee_account();
+        let amount = client
+            .get::<Option<FeeAmount>>(&format!("fee-state/fee-balance/latest/{}", account))
+            .send()
+            .await
+            .unwrap()
+            .unwrap();
+        let expected = ethers::types::U256::max_value();
+        assert_eq!(expected, amount.0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn slow_test_transaction_proof() {
+        setup_test();
+
+        let port = pick_unused_port().expect("No ports free");
+        let storage = SqlDataSource::create_storage().await;
+        let options = SqlDataSource::options(
+            &storage,
+            Options::with_port(port)
+                .state(Default::default())
+                .submit(Default::default()),
+        );
+
+        let anvil = Anvil::new().spawn();
+        let l1 = anvil.endpoint().parse().unwrap();
+        let network_config = TestConfigBuilder::default().l1_url(l1).build();
+        let config = TestNetworkConfigBuilder::default()
+            .api_config(options)
+            .network_config(network_config)
+            .build();
+        let network = TestNetwork::new(config, MockSequencerVersions::new()).await;
+        let mut events = network.server.event_stream().await;
+        let url = format!("http://localhost:{port}").parse().unwrap();
+        let client: Client<ServerError, SequencerApiVersion> = Client::new(url);
+        client.connect(None).await;
+
+        let txn = Transaction::new(NamespaceId::from(42_u32), vec![1, 2, 3, 4]);
+        let hash = client
+            .post::<Commitment<Transaction>>("submit/submit")
+            .body_json(&txn)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        let height = crate::testing::wait_for_decide_on_handle(&mut events, &txn).await;
+
+        // Wait for a later block, and for its state to be stored, so we can use it as an anchor.
+        client
+            .socket(&format!("availability/stream/blocks/{}", height + 1))
+            .subscribe::<BlockQueryData<SeqTypes>>()
+            .await
+            .unwrap()
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+        sleep(Duration::from_secs(5)).await;
+
+        // Proof relative to the block containing the transaction.
+        let res = client
+            .get::<endpoints::TransactionProofQueryData>(&format!(
+                "availability/transaction/{hash}/proof"
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.transaction, txn);
+        assert_eq!(res.header.height(), height);
+        assert_eq!(res.anchor, res.header);
+        assert!(res.block_proof.is_none());
+        verify_transaction_proof(
+            &res.anchor,
+            &res.header,
+            None,
+            &res.transaction,
+            &res.proof,
+            &res.vid_common,
+        )
+        .unwrap();
+
+        // Proof relative to a later block.
+        let res = client
+            .get::<endpoints::TransactionProofQueryData>(&format!(
+                "availability/transaction/{hash}/proof/{}",
+                height + 1
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.anchor.height(), height + 1);
+        verify_transaction_proof(
+            &res.anchor,
+            &res.header,
+            res.block_proof.as_ref(),
+            &res.transaction,
+            &res.proof,
+            &res.vid_common,
+        )
+        .unwrap();
+
+        // The anchor cannot precede the transaction.
+        let err = client
+            .get::<endpoints::TransactionProofQueryData>(&format!(
+                "availability/transaction/{hash}/proof/{}",
+                height - 1
+            ))
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn slow_test_leaf_certificate() {
+        setup_test();
+
+        let port = pick_unused_port().expect("No ports free");
+        let storage = SqlDataSource::create_storage().await;
+        let options = SqlDataSource::options(&storage, Options::with_port(port));
+
+        let anvil = Anvil::new().spawn();
+        let l1 = anvil.endpoint().parse().unwrap();
+        let network_config = TestConfigBuilder::default().l1_url(l1).build();
+        let config = TestNetworkConfigBuilder::default()
+            .api_config(options)
+            .network_config(network_config)
+            .build();
+        let _network = TestNetwork::new(config, MockSequencerVersions::new()).await;
+        let url = format!("http://localhost:{port}").parse().unwrap();
+        let client: Client<ServerError, SequencerApiVersion> = Client::new(url);
+        client.connect(None).await;
+
+        // Wait for a few blocks to be decided.
+        client
+            .socket("availability/stream/leaves/3")
+            .subscribe::<LeafQueryData<SeqTypes>>()
+            .await
+            .unwrap()
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+
+        let lock = UpgradeLock::<SeqTypes, MockSequencerVersions>::new();
+        for height in 1..=3 {
+            let res = client
+                .get::<endpoints::LeafCertificateQueryData>(&format!(
+                    "availability/leaf/{height}/qc"
+                ))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.leaf.height(), height);
+            assert!(!res.stake_table.nodes.is_empty());
+            verify_leaf_certificate(&res.leaf, &res.qc, &res.stake_table, &lock)
+                .await
+                .unwrap();
+
+            // The certificate does not verify against a different stake table.
+            let mut stake_table = res.stake_table.clone();
+            stake_table.nodes.truncate(1);
+            assert_eq!(
+                verify_leaf_certificate(&res.leaf, &res.qc, &stake_table, &lock).await,
+                Err(LeafCertificateError::InvalidSignatures)
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_block_by_time() {
+        setup_test();
+
+        let port = pick_unused_port().expect("No ports free");
+        let storage = SqlDataSource::create_storage().await;
+        let options = SqlDataSource::options(&storage, Options::with_port(port));
+
+        let anvil = Anvil::new().spawn();
+        let l1 = anvil.endpoint().parse().unwrap();
+        let network_config = TestConfigBuilder::default().l1_url(l1).build();
+        let config = TestNetworkConfigBuilder::default()
+            .api_config(options)
+            .network_config(network_config)
+            .build();
+        let _network = TestNetwork::new(config, MockSequencerVersions::new()).await;
+        let url = format!("http://localhost:{port}").parse().unwrap();
+        let client: Client<ServerError, SequencerApiVersion> = Client::new(url);
+        client.connect(None).await;
+
+        // Wait for a few blocks to be decided.
+        let leaves = client
+            .socket("availability/stream/leaves/0")
+            .subscribe::<LeafQueryData<SeqTypes>>()
+            .await
+            .unwrap()
+            .take(4)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        for leaf in &leaves {
+            let header = leaf.header();
+            let res = client
+                .get::<BlockAtTime>(&format!("node/block-by-time/{}", header.timestamp()))
+                .send()
+                .await
+                .unwrap();
+            // Several blocks may share a timestamp; the latest of them is returned.
+            assert_eq!(res.timestamp, header.timestamp());
+            assert!(res.height >= header.height(), "{res:?} {header:?}");
+        }
+
+        // A time after the genesis block but before the next block resolves to genesis.
+        let genesis = leaves[0].header();
+        if leaves[1].header().timestamp() > genesis.timestamp() + 1 {
+            let res = client
+                .get::<BlockAtTime>(&format!("node/block-by-time/{}", genesis.timestamp() + 1))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.height, 0);
+        }
+
+        // There is no block before genesis.
+        let err = client
+            .get::<BlockAtTime>(&format!("node/block-by-time/{}", genesis.timestamp() - 1))
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_chain_config_history() {
+        setup_test();
+
+        let port = pick_unused_port().expect("No ports free");
+
+        let storage = SqlDataSource::create_storage().await;
+        let options = SqlDataSource::options(
+            &storage,
+            Options::with_port(port)
+                .state(Default::default())
+                .config(Default::default()),
+        );
+
+        let anvil = Anvil::new().spawn();
+        let l1 = anvil.endpoint().parse().unwrap();
+        let network_config = TestConfigBuilder::default().l1_url(l1).build();
+        let config = TestNetworkConfigBuilder::default()
+            .api_config(options)
+            .network_config(network_config)
+            .build();
+        let _network = TestNetwork::new(config, MockSequencerVersions::new()).await;
+        let url = format!("http://localhost:{port}").parse().unwrap();
+        let client: Client<ServerError, SequencerApiVersion> = Client::new(url);
+        client.connect(None).await;
+
+        // Wait until the genesis state has been stored.
+        let genesis: Header = client.get("availability/header/0").send().await.unwrap();
+        let history = loop {
+            let history = client
+                .get::<Vec<ChainConfigActivation>>("config/chain/history")
+                .send()
+                .await
+                .unwrap();
+            if !history.is_empty() {
+                break history;
+            }
+            tracing::info!("waiting for genesis chain config");
+            sleep(Duration::from_secs(1)).await;
+        };
+
+        // Without any upgrades, the only config is the genesis config.
+        assert_eq!(history.len(), 1, "{history:#?}");
+        assert_eq!(history[0].height, 0);
+        assert_eq!(history[0].commitment, genesis.chain_config().commit());
+        assert_eq!(history[0].chain_config.commit(), history[0].commitment);
+    }
 
     #[tokio::test(flavor = "multi_thread")]
-    async fn test_healthcheck() {
+    async fn test_submit_rate_limit() {
         setup_test();
 
         let port = pick_unused_port().expect("No ports free");
-        let url = format!("http://localhost:{port}").parse().unwrap();
-        let client: Client<ServerError, StaticVersion<0, 1>> = Client::new(url);
-        let options = Options::with_port(port);
+        let limited = NamespaceId::from(1_u32);
+        let unlimited = NamespaceId::from(2_u32);
+        let options = Options::with_port(port).submit(options::Submit {
+            rate_limits: vec![rate_limit::NamespaceRateLimit {
+                namespace: limited,
+                rate: 1.0,
+            }],
+            ..Default::default()
+        });
+
         let anvil = Anvil::new().spawn();
         let l1 = anvil.endpoint().parse().unwrap();
         let network_config = TestConfigBuilder::default().l1_url(l1).build();
-        let config = TestNetworkConfigBuilder::<5, _, NullStateCatchup>::default()
+        let config = TestNetworkConfigBuilder::default()
             .api_config(options)
             .network_config(network_config)
             .build();
         let _network = TestNetwork::new(config, MockSequencerVersions::new()).await;
-
+        let url = format!("http://localhost:{port}").parse().unwrap();
+        let client: Client<ServerError, SequencerApiVersion> = Client::new(url);
         client.connect(None).await;
-        let health = client.get::<AppHealth>("healthcheck").send().await.unwrap();
-        assert_eq!(health.status, HealthStatus::Available);
-    }
 
-    #[tokio::test(flavor = "multi_thread")]
-    async fn status_test_without_query_module() {
-        status_test_helper(|opt| opt).await
-    }
+        let submit = |ns, payload| {
+            let client = client.clone();
+            async move {
+                client
+                    .post::<Commitment<Transaction>>("submit/submit")
+                    .body_json(&Transaction::new(ns, payload))
+                    .unwrap()
+                    .send()
+                    .await
+            }
+        };
 
-    #[tokio::test(flavor = "multi_thread")]
-    async fn submit_test_without_query_module() {
-        submit_test_helper(|opt| opt).await
-    }
+        // The first transaction in the limited namespace is accepted, but a burst exceeding the
+        // limit is rejected.
+        submit(limited, vec![1]).await.unwrap();
+        let err = submit(limited, vec![2]).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::TOO_MANY_REQUESTS, "{err:#}");
 
-    #[tokio::test(flavor = "multi_thread")]
-    async fn state_signature_test_without_query_module() {
-        state_signature_test_helper(|opt| opt).await
+        // Other namespaces are unaffected.
+        for i in 0..5 {
+            submit(unlimited, vec![i]).await.unwrap();
+        }
+
+        // Once the bucket refills, the limited namespace can submit again.
+        sleep(Duration::from_secs(1)).await;
+        submit(limited, vec![3]).await.unwrap();
     }
 
     #[tokio::test(flavor = "multi_thread")]
-    async fn catchup_test_without_query_module() {
-        catchup_test_helper(|opt| opt).await
+    async fn test_submit_batch() {
+        setup_test();
+
+        let port = pick_unused_port().expect("No ports free");
+        let limited = NamespaceId::from(1_u32);
+        let unlimited = NamespaceId::from(2_u32);
+        let disallowed = NamespaceId::from(3_u32);
+        let options = Options::with_port(port).submit(options::Submit {
+            rate_limits: vec![
+                rate_limit::NamespaceRateLimit {
+                    namespace: limited,
+                    rate: 1.0,
+                },
+                rate_limit::NamespaceRateLimit {
+                    namespace: disallowed,
+                    rate: 1.0,
+                },
+            ],
+            allowed_namespaces: vec!["1-2".parse().unwrap()],
+            max_batch_count: 3,
+            ..Default::default()
+        });
+
+        let anvil = Anvil::new().spawn();
+        let l1 = anvil.endpoint().parse().unwrap();
+        let network_config = TestConfigBuilder::default().l1_url(l1).build();
+        let config = TestNetworkConfigBuilder::default()
+            .api_config(options)
+            .network_config(network_config)
+            .build();
+        let _network = TestNetwork::new(config, MockSequencerVersions::new()).await;
+        let url = format!("http://localhost:{port}").parse().unwrap();
+        let client: Client<ServerError, SequencerApiVersion> = Client::new(url);
+        client.connect(None).await;
+
+        // The second transaction in the limited namespace exceeds the rate limit, but the rest of
+        // the batch is still accepted.
+        let txs = vec![
+            Transaction::new(limited, vec![1]),
+            Transaction::new(limited, vec![2]),
+            Transaction::new(unlimited, vec![3]),
+        ];
+        let results: Vec<endpoints::BatchSubmitResult> = client
+            .post("submit/batch")
+            .body_json(&txs)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(results.len(), txs.len());
+        for (tx, res) in txs.iter().zip(&results) {
+            assert_eq!(res.hash, tx.commit());
+        }
+        assert!(results[0].is_accepted(), "{results:#?}");
+        assert!(!results[1].is_accepted(), "{results:#?}");
+        assert!(results[2].is_accepted(), "{results:#?}");
+
+        // The status of each accepted transaction is recorded.
+        for tx in [&txs[0], &txs[2]] {
+            let status: TxStatus = client
+                .get(&format!("submit/status/{}", tx.commit()))
+                .send()
+                .await
+                .unwrap();
+            assert!(!matches!(status, TxStatus::Rejected { .. }), "{status:?}");
+        }
+
+        // Transactions are charged to the rate limit before they are validated, so invalid
+        // transactions still use up their namespace's limit.
+        let txs = vec![
+            Transaction::new(disallowed, vec![4]),
+            Transaction::new(disallowed, vec![5]),
+        ];
+        let results: Vec<endpoints::BatchSubmitResult> = client
+            .post("submit/batch")
+            .body_json(&txs)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        let errors = results
+            .iter()
+            .map(|res| res.error.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert!(!errors[0].contains("rate limit"), "{errors:?}");
+        assert!(errors[1].contains("rate limit"), "{errors:?}");
+
+        // Batches larger than the maximum are rejected outright.
+        let txs = vec![Transaction::new(unlimited, vec![6]); 4];
+        let err = client
+            .post::<Vec<endpoints::BatchSubmitResult>>("submit/batch")
+            .body_json(&txs)
+            .unwrap()
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST, "{err:#}");
     }
 
     #[tokio::test(flavor = "multi_thread")]
-    async fn slow_test_merklized_state_api() {
+    async fn test_submit_and_wait() {
         setup_test();
 
         let port = pick_unused_port().expect("No ports free");
-
         let storage = SqlDataSource::create_storage().await;
-        let options = SqlDataSource::options(
-            &storage,
-            Options::with_port(port)
-                .state(Default::default())
-                .status(Default::default()),
-        );
+        let options =
+            SqlDataSource::options(&storage, Options::with_port(port)).submit(Default::default());
 
         let anvil = Anvil::new().spawn();
         let l1 = anvil.endpoint().parse().unwrap();
@@ -1453,67 +3108,242 @@ mod test {
             .api_config(options)
             .network_config(network_config)
             .build();
-        let mut network = TestNetwork::new(config, MockSequencerVersions::new()).await;
+        let _network = TestNetwork::new(config, MockSequencerVersions::new()).await;
         let url = format!("http://localhost:{port}").parse().unwrap();
         let client: Client<ServerError, SequencerApiVersion> = Client::new(url);
+        client.connect(None).await;
+
+        let txn = Transaction::new(NamespaceId::from(1_u32), vec![1, 2, 3]);
+        let res: endpoints::DecidedTransaction = client
+            .post("submit/submit/wait")
+            .body_json(&txn)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.hash, txn.commit());
+
+        // The block containing the transaction can be queried right away.
+        let block: BlockQueryData<SeqTypes> = client
+            .get(&format!("availability/block/{}", res.block))
+            .send()
+            .await
+            .unwrap();
+        let payload = block.payload();
+        assert_eq!(
+            payload
+                .transactions(payload.ns_table())
+                .nth(res.index as usize),
+            Some(txn.clone())
+        );
+        let status: TxStatus = client
+            .get(&format!("submit/status/{}", txn.commit()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            status,
+            TxStatus::Sequenced {
+                block: res.block,
+                index: res.index
+            }
+        );
+
+        // Waiting for a transaction which has already been decided returns immediately.
+        let again: endpoints::DecidedTransaction = client
+            .post("submit/submit/wait/0")
+            .body_json(&txn)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(again, res);
+
+        // A transaction which is not decided in time is reported as a timeout.
+        let txn = Transaction::new(NamespaceId::from(1_u32), vec![4, 5, 6]);
+        let err = client
+            .post::<endpoints::DecidedTransaction>("submit/submit/wait/0")
+            .body_json(&txn)
+            .unwrap()
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_historical_fee_account() {
+        setup_test();
+
+        let port = pick_unused_port().expect("No ports free");
+        let storage = SqlDataSource::create_storage().await;
+        let anvil = Anvil::new().spawn();
+        let l1 = anvil.endpoint().parse().unwrap();
+        let network_config = TestConfigBuilder::default().l1_url(l1).build();
+        let config = TestNetworkConfigBuilder::default()
+            .api_config(
+                SqlDataSource::options(&storage, Options::with_port(port)).fee(Default::default()),
+            )
+            .network_config(network_config)
+            .build();
+        let _network = TestNetwork::new(config, MockSequencerVersions::new()).await;
 
+        let client: Client<ServerError, StaticVersion<0, 1>> =
+            Client::new(format!("http://localhost:{port}").parse().unwrap());
         client.connect(None).await;
 
-        // Wait until some blocks have been decided.
-        tracing::info!("waiting for blocks");
-        let blocks = client
-            .socket("availability/stream/blocks/0")
+        // Wait for a few blocks to be stored.
+        client
+            .socket("availability/stream/blocks/3")
             .subscribe::<BlockQueryData<SeqTypes>>()
             .await
             .unwrap()
-            .take(4)
-            .try_collect::<Vec<_>>()
+            .next()
             .await
+            .unwrap()
             .unwrap();
 
-        // sleep for few seconds so that state data is upserted
-        tracing::info!("waiting for state to be inserted");
-        sleep(Duration::from_secs(5)).await;
-        network.stop_consensus().await;
-
-        for block in blocks {
-            let i = block.height();
-            tracing::info!(i, "get block state");
-            let path = client
-                .get::<MerkleProof<Commitment<Header>, u64, Sha3Node, 3>>(&format!(
-                    "block-state/{}/{i}",
-                    i + 1
-                ))
+        // The state of the account at an old block can be proven against that block's header.
+        let account = TestConfig::<5>::builder_key().fee_account();
+        for height in 1..3 {
+            let res: FeeAccountQueryData = client
+                .get(&format!("fee/account/{account}/{height}"))
                 .send()
                 .await
                 .unwrap();
-            assert_eq!(*path.elem().unwrap(), block.hash());
+            assert_eq!(res.height, height);
+            assert!(res.balance > 0.into(), "{res:?}");
 
-            tracing::info!(i, "get fee state");
-            let account = TestConfig::<5>::builder_key().fee_account();
-            let path = client
-                .get::<MerkleProof<FeeAmount, FeeAccount, Sha3Node, 256>>(&format!(
-                    "fee-state/{}/{}",
-                    i + 1,
-                    account
-                ))
+            let header: Header = client
+                .get(&format!("availability/header/{height}"))
                 .send()
                 .await
                 .unwrap();
-            assert_eq!(*path.index(), account);
-            assert!(*path.elem().unwrap() > 0.into(), "{:?}", path.elem());
+            assert_eq!(
+                res.proof.verify(&header.fee_merkle_tree_root()).unwrap(),
+                res.balance
+            );
         }
 
-        // testing fee_balance api
-        let account = TestConfig::<5>::builder_key().fee_account();
-        let amount = client
-            .get::<Option<FeeAmount>>(&format!("fee-state/fee-balance/latest/{}", account))
+        // Blocks which do not exist yet cannot be queried.
+        client
+            .get::<FeeAccountQueryData>(&format!("fee/account/{account}/{}", u32::MAX))
+            .send()
+            .await
+            .unwrap_err();
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_typed_client() {
+        setup_test();
+
+        let ns_id = NamespaceId::from(42_u32);
+        let txn = Transaction::new(ns_id, vec![1, 2, 3, 4]);
+
+        let port = pick_unused_port().expect("No ports free");
+        let storage = SqlDataSource::create_storage().await;
+        let anvil = Anvil::new().spawn();
+        let l1 = anvil.endpoint().parse().unwrap();
+        let network_config = TestConfigBuilder::default().l1_url(l1).build();
+        let config = TestNetworkConfigBuilder::default()
+            .api_config(
+                SqlDataSource::options(&storage, Options::with_port(port))
+                    .submit(Default::default())
+                    .status(Default::default()),
+            )
+            .network_config(network_config)
+            .build();
+        let network = TestNetwork::new(config, MockSequencerVersions::new()).await;
+        let mut events = network.server.event_stream().await;
+
+        let client =
+            client::SequencerClient::new(format!("http://localhost:{port}").parse().unwrap());
+        client.connect().await;
+
+        assert_eq!(client.submit(&txn).await.unwrap(), txn.commit());
+        let height = crate::testing::wait_for_decide_on_handle(&mut events, &txn).await;
+
+        // Wait for the query service to catch up to the block containing the transaction.
+        while client.block_height().await.unwrap() <= height {
+            sleep(Duration::from_millis(100)).await;
+        }
+
+        let header = client.header(height).await.unwrap();
+        assert_eq!(header.height(), height);
+        let block = client.block(height).await.unwrap();
+        assert_eq!(block.hash(), header.commit());
+        let leaf = client.leaf(height).await.unwrap();
+        assert_eq!(leaf.block_hash(), block.hash());
+
+        let ns = client.namespace(height, ns_id).await.unwrap();
+        assert_eq!(ns.transactions, vec![txn]);
+        assert!(ns.proof.is_some());
+
+        let estimate = client.fee_estimate(100).await.unwrap();
+        assert_eq!(estimate.size, 100);
+        assert_eq!(estimate.min_fee, estimate.base_fee * 100u64);
+        assert!(estimate.fee >= estimate.min_fee);
+
+        // The stake table for the current epoch is recorded and can be looked up by epoch. In the
+        // test network every node is staked and in the DA committee.
+        let stake_table = client.current_stake_table().await.unwrap();
+        assert!(!stake_table.nodes.is_empty());
+        assert!(stake_table.nodes.iter().all(|node| node.da));
+        assert_eq!(
+            client.stake_table(stake_table.epoch).await.unwrap(),
+            stake_table
+        );
+
+        // The mock versions schedule an upgrade from 0.1 to 0.2, so both API versions are served.
+        let info = client.version_info().await.unwrap();
+        let upgrade = <MockSequencerVersions as Versions>::Upgrade::VERSION;
+        assert_eq!(
+            info.base_version,
+            <MockSequencerVersions as Versions>::Base::VERSION
+        );
+        assert_eq!(info.upgrade_version, Some(upgrade));
+        assert_eq!(
+            info.api_versions,
+            vec![SequencerApiVersion::VERSION, upgrade]
+        );
+        let upgraded_estimate: FeeEstimate = client
+            .inner()
+            .get(&format!(
+                "{}/fee-estimate/100",
+                options::versioned_module("submit", upgrade)
+            ))
+            .header("Accept", "application/json")
             .send()
             .await
-            .unwrap()
             .unwrap();
-        let expected = ethers::types::U256::max_value();
-        assert_eq!(expected, amount.0);
+        assert_eq!(upgraded_estimate.size, estimate.size);
+        assert_eq!(upgraded_estimate.min_fee, estimate.min_fee);
+    }
+
+    #[test]
+    fn test_fee_estimate() {
+        let chain_config = ChainConfig {
+            max_block_size: 1000.into(),
+            base_fee: 2.into(),
+            ..Default::default()
+        };
+
+        // Empty blocks: no premium.
+        let estimate = endpoints::FeeEstimate::new(&chain_config, 0, 10);
+        assert_eq!(estimate.min_fee, 20.into());
+        assert_eq!(estimate.block_fullness, 0);
+        assert_eq!(estimate.fee, 20.into());
+
+        // Half full blocks: 50% premium.
+        let estimate = endpoints::FeeEstimate::new(&chain_config, 500, 10);
+        assert_eq!(estimate.block_fullness, 5_000);
+        assert_eq!(estimate.fee, 30.into());
+
+        // Full blocks: the fee doubles.
+        let estimate = endpoints::FeeEstimate::new(&chain_config, 1000, 10);
+        assert_eq!(estimate.block_fullness, 10_000);
+        assert_eq!(estimate.fee, 40.into());
     }
 
     #[tokio::test(flavor = "multi_thread")]
@@ -1710,6 +3540,14 @@ mod test {
                 Options::from(options::Http {
                     port,
                     max_connections: None,
+                    cors_origins: vec![],
+                    max_request_body_bytes: None,
+                    max_range_size: None,
+                    compress_responses: false,
+                    bind_address: [0, 0, 0, 0].into(),
+                    module_listeners: vec![],
+                    tls_cert: None,
+                    tls_key: None,
                 })
                 .catchup(Default::default()),
             )
@@ -1792,6 +3630,14 @@ mod test {
                 Options::from(options::Http {
                     port,
                     max_connections: None,
+                    cors_origins: vec![],
+                    max_request_body_bytes: None,
+                    max_range_size: None,
+                    compress_responses: false,
+                    bind_address: [0, 0, 0, 0].into(),
+                    module_listeners: vec![],
+                    tls_cert: None,
+                    tls_key: None,
                 })
                 .catchup(Default::default()),
             )
@@ -1960,9 +3806,18 @@ mod test {
                 Options::from(options::Http {
                     port,
                     max_connections: None,
+                    cors_origins: vec![],
+                    max_request_body_bytes: None,
+                    max_range_size: None,
+                    compress_responses: false,
+                    bind_address: [0, 0, 0, 0].into(),
+                    module_listeners: vec![],
+                    tls_cert: None,
+                    tls_key: None,
                 })
                 .catchup(Default::default())
-                .status(Default::default()),
+                .status(Default::default())
+                .config(Default::default()),
             )
             .catchups(std::array::from_fn(|_| {
                 StatePeers::<SequencerApiVersion>::from_urls(
@@ -2040,6 +3895,28 @@ mod test {
             sleep(Duration::from_millis(200)).await;
         }
 
+        // The upgrade is reported as activated, along with its schedule.
+        let upgrade_version = <MockSeqVersions as Versions>::Upgrade::VERSION;
+        loop {
+            let upgrades = client
+                .get::<Vec<UpgradeInfo>>("config/upgrades")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(upgrades.len(), 1, "{upgrades:#?}");
+            assert_eq!(upgrades[0].version, upgrade_version);
+            assert_eq!(
+                upgrades[0].upgrade.as_ref().unwrap().upgrade_type.data(),
+                chain_config_upgrade
+            );
+            if let UpgradeStatus::Activated { view, .. } = upgrades[0].status {
+                assert!(view >= new_version_first_view.u64());
+                break;
+            }
+            tracing::info!(status = ?upgrades[0].status, "waiting for upgrade to activate");
+            sleep(Duration::from_millis(200)).await;
+        }
+
         network.server.shut_down().await;
     }
 
@@ -0,0 +1,227 @@
+//! Tiered storage of block payloads and VID data in an object store.
+//!
+//! Payloads and VID data make up the bulk of the storage used by a query node. When a payload
+//! archive is configured, the [`PayloadPruner`](super::pruner::PayloadPruner) uploads payloads and
+//! VID common data to an S3-compatible object store before deleting them from the database, rather
+//! than discarding them. The archive is also the first provider consulted when the query service
+//! is missing data, so queries for archived blocks transparently fetch the data back from the
+//! object store. Fetched data is cached in the database until it is pruned again.
+//!
+//! Objects are keyed by payload commitment, so the same archive can be shared by several nodes.
+//! Leaves and headers are never pruned, and thus never archived.
+
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use clap::Parser;
+use derivative::Derivative;
+use espresso_types::Payload;
+use hotshot_query_service::{
+    fetching::{
+        provider::Provider,
+        request::{PayloadRequest, VidCommonRequest},
+    },
+    VidCommitment, VidCommon,
+};
+use hotshot_types::vid::VidSchemeType;
+use jf_vid::VidScheme;
+use object_store::{
+    aws::AmazonS3Builder, local::LocalFileSystem, path::Path, ObjectStore, PutPayload,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use url::Url;
+
+use crate::SeqTypes;
+
+/// Options for archiving payloads and VID data to an object store.
+#[derive(Parser, Clone, Debug, Default)]
+pub struct PayloadArchiveOptions {
+    /// Archive pruned payloads and VID data to an object store, instead of discarding them.
+    ///
+    /// Supported URLs are `s3://<bucket>/<prefix>` and `file:///<path>`. S3 credentials, region,
+    /// and endpoint (for S3-compatible stores) are read from the standard `AWS_*` environment
+    /// variables. Payloads are only archived when they are pruned, so a payload retention policy
+    /// must also be configured.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_PAYLOAD_ARCHIVE_URL")]
+    pub payload_archive_url: Option<Url>,
+}
+
+impl PayloadArchiveOptions {
+    /// Connect to the configured archive, if there is one.
+    pub fn connect(&self) -> anyhow::Result<Option<PayloadArchive>> {
+        let Some(url) = &self.payload_archive_url else {
+            return Ok(None);
+        };
+        let (store, prefix): (Arc<dyn ObjectStore>, _) = match url.scheme() {
+            "s3" => {
+                let store = AmazonS3Builder::from_env()
+                    .with_url(url.as_str())
+                    .build()
+                    .context(format!("connecting to payload archive {url}"))?;
+                (Arc::new(store), Path::from(url.path()))
+            }
+            "file" => {
+                std::fs::create_dir_all(url.path())
+                    .context(format!("creating payload archive directory {url}"))?;
+                let store = LocalFileSystem::new_with_prefix(url.path())
+                    .context(format!("opening payload archive {url}"))?;
+                // The store is already rooted at the archive path.
+                (Arc::new(store), Path::default())
+            }
+            scheme => bail!("unsupported payload archive scheme {scheme}"),
+        };
+        tracing::info!(%url, "archiving pruned payloads");
+        Ok(Some(PayloadArchive::new(store, prefix)))
+    }
+}
+
+/// An object store holding payloads and VID common data, keyed by payload commitment.
+///
+/// Objects are trusted: data fetched from the archive is checked for consistency with the
+/// requested commitment only where this is cheap, so the archive should only be writable by the
+/// operator.
+#[derive(Clone, Derivative)]
+#[derivative(Debug)]
+pub struct PayloadArchive {
+    #[derivative(Debug = "ignore")]
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+}
+
+impl PayloadArchive {
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: Path) -> Self {
+        Self { store, prefix }
+    }
+
+    /// Archive the payload with commitment `commit`.
+    pub async fn put_payload(
+        &self,
+        commit: VidCommitment,
+        payload: &Payload,
+    ) -> anyhow::Result<()> {
+        self.put(self.path("payload", commit), payload).await
+    }
+
+    /// Archive the VID common data for the payload with commitment `commit`.
+    pub async fn put_vid_common(
+        &self,
+        commit: VidCommitment,
+        common: &VidCommon,
+    ) -> anyhow::Result<()> {
+        self.put(self.path("vid-common", commit), common).await
+    }
+
+    /// Get the payload with commitment `commit`, if it has been archived.
+    pub async fn get_payload(&self, commit: VidCommitment) -> anyhow::Result<Option<Payload>> {
+        self.get(self.path("payload", commit)).await
+    }
+
+    /// Get the VID common data for the payload with commitment `commit`, if it has been archived.
+    pub async fn get_vid_common(&self, commit: VidCommitment) -> anyhow::Result<Option<VidCommon>> {
+        let Some(common) = self.get(self.path("vid-common", commit)).await? else {
+            return Ok(None);
+        };
+        if VidSchemeType::is_consistent(&commit, &common).is_err() {
+            bail!("archived VID common is inconsistent with commitment {commit}");
+        }
+        Ok(Some(common))
+    }
+
+    fn path(&self, kind: &str, commit: VidCommitment) -> Path {
+        self.prefix.child(kind).child(commit.to_string())
+    }
+
+    async fn put<T: Serialize>(&self, path: Path, value: &T) -> anyhow::Result<()> {
+        let bytes = bincode::serialize(value)?;
+        self.store
+            .put(&path, PutPayload::from(bytes))
+            .await
+            .context(format!("uploading {path}"))?;
+        Ok(())
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: Path) -> anyhow::Result<Option<T>> {
+        let res = match self.store.get(&path).await {
+            Ok(res) => res,
+            Err(object_store::Error::NotFound { .. }) => return Ok(None),
+            Err(err) => return Err(err).context(format!("downloading {path}")),
+        };
+        let bytes = res.bytes().await.context(format!("downloading {path}"))?;
+        let value = bincode::deserialize(&bytes).context(format!("deserializing {path}"))?;
+        Ok(Some(value))
+    }
+}
+
+#[async_trait]
+impl Provider<SeqTypes, PayloadRequest> for PayloadArchive {
+    async fn fetch(&self, req: PayloadRequest) -> Option<Payload> {
+        match self.get_payload(req.0).await {
+            Ok(payload) => payload,
+            Err(err) => {
+                tracing::warn!(?req, "failed to fetch payload from archive: {err:#}");
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Provider<SeqTypes, VidCommonRequest> for PayloadArchive {
+    async fn fetch(&self, req: VidCommonRequest) -> Option<VidCommon> {
+        match self.get_vid_common(req.0).await {
+            Ok(common) => common,
+            Err(err) => {
+                tracing::warn!(?req, "failed to fetch VID common from archive: {err:#}");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use espresso_types::{NamespaceId, NodeState, Transaction};
+    use hotshot::traits::BlockPayload;
+    use hotshot_types::{traits::EncodeBytes, vid::vid_scheme};
+    use object_store::memory::InMemory;
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_payload_archive() {
+        let archive = PayloadArchive::new(Arc::new(InMemory::new()), Path::from("prefix"));
+
+        let txs = vec![Transaction::new(NamespaceId::from(1u32), vec![1, 2, 3])];
+        let (payload, _) = Payload::from_transactions(txs, &Default::default(), &NodeState::mock())
+            .await
+            .unwrap();
+        let vid = vid_scheme(10).disperse(payload.encode()).unwrap();
+
+        // Nothing is archived yet.
+        assert_eq!(archive.get_payload(vid.commit).await.unwrap(), None);
+        assert!(archive.fetch(VidCommonRequest(vid.commit)).await.is_none());
+
+        archive.put_payload(vid.commit, &payload).await.unwrap();
+        archive
+            .put_vid_common(vid.commit, &vid.common)
+            .await
+            .unwrap();
+        assert_eq!(
+            archive.fetch(PayloadRequest(vid.commit)).await.unwrap(),
+            payload
+        );
+        assert_eq!(
+            archive.fetch(VidCommonRequest(vid.commit)).await.unwrap(),
+            vid.common
+        );
+
+        // VID common which does not match the commitment is rejected.
+        let other = vid_scheme(10).disperse(vec![0; 100]).unwrap();
+        archive
+            .put_vid_common(other.commit, &vid.common)
+            .await
+            .unwrap();
+        archive.get_vid_common(other.commit).await.unwrap_err();
+    }
+}
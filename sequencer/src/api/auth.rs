@@ -0,0 +1,349 @@
+//! API key authentication for protected API modules.
+//!
+//! Public nodes usually want to serve read APIs to anyone, while restricting who can submit
+//! transactions or reconfigure the node. When the auth module is enabled, requests for any of the
+//! protected modules must carry one of the configured API keys in an `Authorization: Bearer <key>`
+//! header, and are rejected with 401 otherwise. All other modules remain open.
+//!
+//! Any configured key is accepted, so keys can be rotated without downtime by adding the new key,
+//! migrating clients, and then removing the old one. Keys read from a key file are reloaded when
+//! the file changes, so rotating them does not require a restart.
+//!
+//! Like [`ApiMetrics`](super::metrics::ApiMetrics), the [`ApiAuth`] middleware is installed by
+//! wrapping the listener the app is served on, in an [`AuthListener`].
+
+use std::{
+    collections::HashSet,
+    fmt::{self, Display, Formatter},
+    fs, io,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use tide::{
+    listener::{ListenInfo, Listener, ToListener},
+    Middleware, Next, Request, Response, Server, StatusCode,
+};
+
+use super::{
+    options::Auth,
+    routing::{request_module, unversioned},
+};
+
+/// How often to check whether the key file has changed.
+const KEY_FILE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Middleware which rejects unauthenticated requests for protected modules.
+#[derive(Clone, Debug)]
+pub(crate) struct ApiAuth {
+    modules: Arc<HashSet<String>>,
+    keys: Arc<Keys>,
+}
+
+#[derive(Debug)]
+struct Keys {
+    /// Keys given directly in the options, which never change.
+    fixed: Vec<String>,
+    file: Option<Mutex<KeyFile>>,
+}
+
+#[derive(Debug)]
+struct KeyFile {
+    path: PathBuf,
+    keys: Vec<String>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl KeyFile {
+    fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let mut file = Self {
+            path,
+            keys: vec![],
+            modified: None,
+            checked: Instant::now(),
+        };
+        file.load()
+            .with_context(|| format!("reading API key file {}", file.path.display()))?;
+        Ok(file)
+    }
+
+    fn load(&mut self) -> io::Result<()> {
+        let modified = fs::metadata(&self.path)?.modified().ok();
+        self.keys = parse_keys(&fs::read_to_string(&self.path)?);
+        self.modified = modified;
+        Ok(())
+    }
+
+    /// Reload the keys if the file has been modified since we last read it.
+    ///
+    /// If the file cannot be read, the previously loaded keys remain in effect.
+    fn refresh(&mut self) {
+        if self.checked.elapsed() < KEY_FILE_CHECK_INTERVAL {
+            return;
+        }
+        self.checked = Instant::now();
+
+        let modified = match fs::metadata(&self.path) {
+            Ok(meta) => meta.modified().ok(),
+            Err(err) => {
+                tracing::warn!(path = %self.path.display(), "unable to check API key file: {err}");
+                return;
+            }
+        };
+        if modified.is_some() && modified == self.modified {
+            return;
+        }
+        match self.load() {
+            Ok(()) => tracing::info!(
+                path = %self.path.display(),
+                keys = self.keys.len(),
+                "reloaded API keys"
+            ),
+            Err(err) => {
+                tracing::warn!(path = %self.path.display(), "unable to reload API key file: {err}")
+            }
+        }
+    }
+}
+
+/// Parse a key file with one key per line, ignoring blank lines and `#` comments.
+fn parse_keys(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+impl ApiAuth {
+    pub(crate) fn new(opt: &Auth) -> anyhow::Result<Self> {
+        let file = opt.api_keys_file.clone().map(KeyFile::open).transpose()?;
+        if opt.api_keys.is_empty() && file.is_none() {
+            bail!("API authentication is enabled but no API keys are configured");
+        }
+        Ok(Self {
+            modules: Arc::new(opt.protected_modules.iter().cloned().collect()),
+            keys: Arc::new(Keys {
+                fixed: opt.api_keys.clone(),
+                file: file.map(Mutex::new),
+            }),
+        })
+    }
+
+    /// Whether a request for `path` requires an API key.
+    ///
+    /// Versioned copies of a module, such as `submit-v0.3`, are protected along with the module.
+    fn is_protected(&self, path: &str) -> bool {
+        request_module(path).is_some_and(|module| self.modules.contains(unversioned(module)))
+    }
+
+    /// Whether `key` is one of the currently accepted API keys.
+    fn is_authorized(&self, key: &str) -> bool {
+        let mut authorized = self
+            .keys
+            .fixed
+            .iter()
+            .fold(false, |ok, k| ok | constant_time_eq(k, key));
+        if let Some(file) = &self.keys.file {
+            let mut file = file.lock();
+            file.refresh();
+            authorized = file
+                .keys
+                .iter()
+                .fold(authorized, |ok, k| ok | constant_time_eq(k, key));
+        }
+        authorized
+    }
+}
+
+/// Compare two keys without short-circuiting on the first mismatched byte.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+#[async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for ApiAuth {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        if !self.is_protected(req.url().path()) {
+            return Ok(next.run(req).await);
+        }
+
+        let authorized = req
+            .header("Authorization")
+            .and_then(|value| value.last().as_str().strip_prefix("Bearer "))
+            .is_some_and(|key| self.is_authorized(key.trim()));
+        if authorized {
+            return Ok(next.run(req).await);
+        }
+
+        let mut res = Response::new(StatusCode::Unauthorized);
+        res.insert_header("WWW-Authenticate", "Bearer");
+        res.set_body("missing or invalid API key");
+        Ok(res)
+    }
+}
+
+/// A [`Listener`] which installs [`ApiAuth`], if enabled, on the server before delegating to
+/// another listener.
+#[derive(Debug)]
+pub(crate) struct AuthListener<L> {
+    inner: L,
+    auth: Option<ApiAuth>,
+}
+
+impl<L> AuthListener<L> {
+    pub(crate) fn new(inner: L, auth: Option<ApiAuth>) -> Self {
+        Self { inner, auth }
+    }
+}
+
+impl<L: Display> Display for AuthListener<L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+#[async_trait]
+impl<State, L> Listener<State> for AuthListener<L>
+where
+    State: Clone + Send + Sync + 'static,
+    L: Listener<State>,
+{
+    async fn bind(&mut self, mut app: Server<State>) -> io::Result<()> {
+        if let Some(auth) = &self.auth {
+            app.with(auth.clone());
+        }
+        self.inner.bind(app).await
+    }
+
+    async fn accept(&mut self) -> io::Result<()> {
+        self.inner.accept().await
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        self.inner.info()
+    }
+}
+
+impl<State, L> ToListener<State> for AuthListener<L>
+where
+    State: Clone + Send + Sync + 'static,
+    L: Listener<State>,
+{
+    type Listener = Self;
+
+    fn to_listener(self) -> io::Result<Self::Listener> {
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread::sleep;
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    fn options(keys: &[&str], file: Option<PathBuf>) -> Auth {
+        Auth {
+            api_keys: keys.iter().map(|key| key.to_string()).collect(),
+            api_keys_file: file,
+            protected_modules: vec!["submit".into(), "peers".into()],
+        }
+    }
+
+    #[test]
+    fn test_protected_modules() {
+        let auth = ApiAuth::new(&options(&["key"], None)).unwrap();
+        assert!(auth.is_protected("/v0/submit/submit"));
+        assert!(auth.is_protected("/submit/submit"));
+        assert!(auth.is_protected("/v1/peers/add"));
+        assert!(auth.is_protected("/submit-v0.3/submit"));
+        assert!(auth.is_protected("/v0/peers-v0.3/add"));
+        assert!(!auth.is_protected("/v0/submit-foo/submit"));
+        assert!(!auth.is_protected("/v0/availability/block/1"));
+        assert!(!auth.is_protected("/healthcheck"));
+        assert!(!auth.is_protected("/v0"));
+        assert!(!auth.is_protected("/"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_versioned_module_requires_key() {
+        let mut app = tide::new();
+        app.with(ApiAuth::new(&options(&["key"], None)).unwrap());
+        app.at("/submit-v0.3/submit")
+            .post(|_| async { Ok("submitted") });
+
+        let request = |key: Option<&str>| {
+            let mut req = tide::http::Request::new(
+                tide::http::Method::Post,
+                "http://localhost/submit-v0.3/submit",
+            );
+            if let Some(key) = key {
+                req.insert_header("Authorization", format!("Bearer {key}"));
+            }
+            req
+        };
+        let res: tide::http::Response = app.respond(request(None)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Unauthorized);
+        let res: tide::http::Response = app.respond(request(Some("wrong"))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Unauthorized);
+        let res: tide::http::Response = app.respond(request(Some("key"))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+    }
+
+    #[test]
+    fn test_fixed_keys() {
+        let auth = ApiAuth::new(&options(&["old", "new"], None)).unwrap();
+        assert!(auth.is_authorized("old"));
+        assert!(auth.is_authorized("new"));
+        assert!(!auth.is_authorized("ne"));
+        assert!(!auth.is_authorized("other"));
+        assert!(!auth.is_authorized(""));
+    }
+
+    #[test]
+    fn test_no_keys() {
+        ApiAuth::new(&options(&[], None)).unwrap_err();
+        ApiAuth::new(&options(&[], Some("/nonexistent/api-keys".into()))).unwrap_err();
+    }
+
+    #[test]
+    fn test_key_file_rotation() {
+        let file = NamedTempFile::new().unwrap();
+        fs::write(file.path(), "# operator keys\nfirst\n\n  second  \n").unwrap();
+
+        let auth = ApiAuth::new(&options(&["fixed"], Some(file.path().into()))).unwrap();
+        assert!(auth.is_authorized("fixed"));
+        assert!(auth.is_authorized("first"));
+        assert!(auth.is_authorized("second"));
+        assert!(!auth.is_authorized("# operator keys"));
+
+        // Rotate out the first key. Wait long enough that both the modification time and the
+        // check interval have elapsed.
+        sleep(KEY_FILE_CHECK_INTERVAL + Duration::from_millis(100));
+        fs::write(file.path(), "second\nthird\n").unwrap();
+        assert!(auth.is_authorized("third"));
+        assert!(auth.is_authorized("second"));
+        assert!(!auth.is_authorized("first"));
+        assert!(auth.is_authorized("fixed"));
+
+        // If the file disappears, the last loaded keys stay in effect.
+        let path = file.path().to_owned();
+        drop(file);
+        sleep(KEY_FILE_CHECK_INTERVAL + Duration::from_millis(100));
+        assert!(!path.exists());
+        assert!(auth.is_authorized("third"));
+    }
+}
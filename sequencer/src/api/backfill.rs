@@ -0,0 +1,225 @@
+//! Backfilling of historical data for nodes started from a snapshot.
+//!
+//! A node restored from a recent snapshot can serve new blocks right away, but has holes for older
+//! heights. The query service fetches missing objects from its peers when they are requested, but
+//! otherwise the holes may take a long time to fill. When enabled, the [`Backfill`] task walks
+//! every height below the block height at startup and fetches any missing leaf, block, or VID
+//! common data from the configured peers, at a bounded rate so as not to overload them.
+//!
+//! Heights which cannot be fetched are retried on a later pass over the chain, until a pass
+//! completes with nothing missing. Progress is served at `status/backfill`.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use clap::Parser;
+use espresso_types::parse_duration;
+use futures::join;
+use hotshot_query_service::{
+    availability::{AvailabilityDataSource, Fetch},
+    status::StatusDataSource,
+};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use crate::SeqTypes;
+
+/// How long to wait before starting a new pass after some heights could not be fetched.
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Options for backfilling missing historical data.
+#[derive(Parser, Clone, Copy, Debug)]
+pub struct BackfillOptions {
+    /// Fetch missing data for all heights below the current block height from peers.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_BACKFILL")]
+    pub backfill: bool,
+
+    /// Maximum number of heights per second to fetch missing data for while backfilling.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_BACKFILL_RATE", default_value = "10")]
+    pub backfill_rate: u32,
+
+    /// How long to wait for peers to provide the missing data for a single height.
+    ///
+    /// Heights which time out are retried on the next pass.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_BACKFILL_FETCH_TIMEOUT",
+        value_parser = parse_duration,
+        default_value = "30s"
+    )]
+    pub backfill_fetch_timeout: Duration,
+}
+
+impl Default for BackfillOptions {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+/// The progress of the backfill task, as reported by `status/backfill`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackfillStatus {
+    /// Whether a pass has completed with no missing data.
+    pub complete: bool,
+    /// Number of passes over the chain started so far.
+    pub passes: u64,
+    /// The next height to check in the current pass.
+    pub height: u64,
+    /// The block height at which the current pass ends.
+    pub target_height: u64,
+    /// Number of heights in the current pass whose missing data has been fetched.
+    pub fetched: u64,
+    /// Number of heights in the current pass whose missing data could not be fetched.
+    pub failed: u64,
+}
+
+/// A handle to the progress of a running backfill task.
+#[derive(Clone, Debug, Default)]
+pub struct BackfillProgress(Arc<RwLock<BackfillStatus>>);
+
+impl BackfillProgress {
+    pub fn status(&self) -> BackfillStatus {
+        *self.0.read()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut BackfillStatus)) {
+        f(&mut self.0.write());
+    }
+}
+
+/// The result of checking a single object or height.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Outcome {
+    /// The data was already present in local storage.
+    Present,
+    /// The data was missing and has been fetched.
+    Fetched,
+    /// The data was missing and could not be fetched.
+    Failed,
+}
+
+/// Background task which fetches missing historical data.
+#[derive(Debug)]
+pub(crate) struct Backfill<D> {
+    storage: Arc<D>,
+    opt: BackfillOptions,
+    progress: BackfillProgress,
+}
+
+impl<D> Backfill<D>
+where
+    D: AvailabilityDataSource<SeqTypes> + StatusDataSource,
+{
+    pub(crate) fn new(storage: Arc<D>, opt: BackfillOptions, progress: BackfillProgress) -> Self {
+        Self {
+            storage,
+            opt,
+            progress,
+        }
+    }
+
+    /// Run passes over the chain until one completes with no missing data.
+    pub(crate) async fn run(self) {
+        tracing::info!(opt = ?self.opt, "starting backfill");
+        loop {
+            match self.pass().await {
+                Ok(0) => break,
+                Ok(failed) => tracing::warn!(failed, "some heights could not be backfilled"),
+                Err(err) => tracing::warn!("error backfilling: {err:#}"),
+            }
+            sleep(RETRY_DELAY).await;
+        }
+        self.progress.update(|status| status.complete = true);
+        tracing::info!(status = ?self.progress.status(), "backfill complete");
+    }
+
+    /// Check every height below the current block height, returning the number of heights whose
+    /// missing data could not be fetched.
+    async fn pass(&self) -> anyhow::Result<u64> {
+        let target_height = self
+            .storage
+            .block_height()
+            .await
+            .context("loading block height")? as u64;
+        self.progress.update(|status| {
+            *status = BackfillStatus {
+                passes: status.passes + 1,
+                target_height,
+                ..Default::default()
+            };
+        });
+        tracing::info!(target_height, "starting backfill pass");
+
+        let interval = Duration::from_secs(1) / self.opt.backfill_rate.max(1);
+        let mut failed = 0;
+        for height in 0..target_height {
+            let outcome = self.backfill(height).await;
+            if outcome == Outcome::Failed {
+                tracing::debug!(height, "failed to backfill height");
+                failed += 1;
+            }
+            self.progress.update(|status| {
+                status.height = height + 1;
+                match outcome {
+                    Outcome::Present => {}
+                    Outcome::Fetched => status.fetched += 1,
+                    Outcome::Failed => status.failed += 1,
+                }
+            });
+            if outcome != Outcome::Present {
+                // Only fetches from peers count against the rate limit; local lookups are cheap.
+                sleep(interval).await;
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Fetch any missing data for the block at `height`.
+    async fn backfill(&self, height: u64) -> Outcome {
+        let height = height as usize;
+        let timeout = self.opt.backfill_fetch_timeout;
+        let (leaf, block, common) = join!(
+            self.storage.get_leaf(height),
+            self.storage.get_block(height),
+            self.storage.get_vid_common(height),
+        );
+        let (leaf, block, common) = join!(
+            resolve(leaf, timeout),
+            resolve(block, timeout),
+            resolve(common, timeout),
+        );
+        leaf.max(block).max(common)
+    }
+}
+
+async fn resolve<T>(fetch: Fetch<T>, timeout: Duration) -> Outcome {
+    match fetch.try_resolve() {
+        Ok(_) => Outcome::Present,
+        Err(fetch) => match fetch.with_timeout(timeout).await {
+            Some(_) => Outcome::Fetched,
+            None => Outcome::Failed,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_outcome_order() {
+        // The outcome for a height is the worst outcome for any of its objects.
+        assert_eq!(Outcome::Present.max(Outcome::Fetched), Outcome::Fetched);
+        assert_eq!(Outcome::Fetched.max(Outcome::Failed), Outcome::Failed);
+        assert_eq!(Outcome::Present.max(Outcome::Present), Outcome::Present);
+    }
+
+    #[test]
+    fn test_backfill_disabled_by_default() {
+        let opt = BackfillOptions::default();
+        assert!(!opt.backfill);
+        assert_eq!(opt.backfill_rate, 10);
+        assert_eq!(opt.backfill_fetch_timeout, Duration::from_secs(30));
+    }
+}
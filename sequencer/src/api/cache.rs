@@ -0,0 +1,150 @@
+//! In-memory caching of recently queried blocks.
+//!
+//! Rollups poll the namespace endpoints for the same few recent blocks over and over, and each of
+//! these requests needs the full block and its VID common data. Since decided blocks never change,
+//! the [`QueryCache`] keeps the most recently used ones in memory, so that repeated queries for the
+//! same heights are served without hitting storage.
+//!
+//! The cache is used by the sequencer-specific availability endpoints (namespace and transaction
+//! proofs) and by the gRPC API. Routes served directly by the query service, such as
+//! `availability/block/:height`, are not cached. Cache effectiveness is reported via the `hits` and `misses`
+//! counters in the `query_cache` metrics group.
+
+use std::{num::NonZeroUsize, sync::Arc, time::Duration};
+
+use clap::Parser;
+use derivative::Derivative;
+use hotshot_query_service::availability::{
+    AvailabilityDataSource, BlockQueryData, VidCommonQueryData,
+};
+use hotshot_types::traits::metrics::{Counter, Metrics};
+use lru::LruCache;
+use parking_lot::Mutex;
+
+use crate::SeqTypes;
+
+/// Options for the in-memory query cache.
+#[derive(Parser, Clone, Copy, Debug)]
+pub struct QueryCacheOptions {
+    /// Number of recently queried blocks to keep in memory.
+    ///
+    /// Each cached block includes its full payload and VID common data. Set to 0 to disable the
+    /// cache.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_QUERY_CACHE_SIZE",
+        default_value = "1000"
+    )]
+    pub query_cache_size: usize,
+}
+
+impl Default for QueryCacheOptions {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+/// An LRU cache of decided blocks and VID common data, keyed by height.
+#[derive(Clone, Derivative)]
+#[derivative(Debug)]
+pub struct QueryCache {
+    #[derivative(Debug = "ignore")]
+    entries: Option<Arc<Mutex<Entries>>>,
+    #[derivative(Debug = "ignore")]
+    metrics: Arc<CacheMetrics>,
+}
+
+struct Entries {
+    blocks: LruCache<usize, BlockQueryData<SeqTypes>>,
+    vid_common: LruCache<usize, VidCommonQueryData<SeqTypes>>,
+}
+
+struct CacheMetrics {
+    hits: Box<dyn Counter>,
+    misses: Box<dyn Counter>,
+}
+
+impl QueryCache {
+    pub fn new(opt: QueryCacheOptions, metrics: &dyn Metrics) -> Self {
+        let metrics = metrics.subgroup("query_cache".into());
+        let entries = NonZeroUsize::new(opt.query_cache_size).map(|size| {
+            Arc::new(Mutex::new(Entries {
+                blocks: LruCache::new(size),
+                vid_common: LruCache::new(size),
+            }))
+        });
+        Self {
+            entries,
+            metrics: Arc::new(CacheMetrics {
+                hits: metrics.create_counter("hits".into(), None),
+                misses: metrics.create_counter("misses".into(), None),
+            }),
+        }
+    }
+
+    /// Get the block at `height`, from the cache if possible or else from `ds`.
+    ///
+    /// Returns [`None`] if the block is not available from `ds` within `timeout`.
+    pub async fn get_block<D>(
+        &self,
+        ds: &D,
+        height: usize,
+        timeout: Duration,
+    ) -> Option<BlockQueryData<SeqTypes>>
+    where
+        D: AvailabilityDataSource<SeqTypes> + Sync,
+    {
+        if let Some(block) = self.lookup(|entries| entries.blocks.get(&height).cloned()) {
+            return Some(block);
+        }
+        let block = ds.get_block(height).await.with_timeout(timeout).await?;
+        self.insert(|entries| {
+            entries.blocks.put(height, block.clone());
+        });
+        Some(block)
+    }
+
+    /// Get the VID common data for the block at `height`, from the cache if possible or else from
+    /// `ds`.
+    ///
+    /// Returns [`None`] if the data is not available from `ds` within `timeout`.
+    pub async fn get_vid_common<D>(
+        &self,
+        ds: &D,
+        height: usize,
+        timeout: Duration,
+    ) -> Option<VidCommonQueryData<SeqTypes>>
+    where
+        D: AvailabilityDataSource<SeqTypes> + Sync,
+    {
+        if let Some(common) = self.lookup(|entries| entries.vid_common.get(&height).cloned()) {
+            return Some(common);
+        }
+        let common = ds
+            .get_vid_common(height)
+            .await
+            .with_timeout(timeout)
+            .await?;
+        self.insert(|entries| {
+            entries.vid_common.put(height, common.clone());
+        });
+        Some(common)
+    }
+
+    fn lookup<T>(&self, f: impl FnOnce(&mut Entries) -> Option<T>) -> Option<T> {
+        let entries = self.entries.as_ref()?;
+        let res = f(&mut entries.lock());
+        if res.is_some() {
+            self.metrics.hits.add(1);
+        } else {
+            self.metrics.misses.add(1);
+        }
+        res
+    }
+
+    fn insert(&self, f: impl FnOnce(&mut Entries)) {
+        if let Some(entries) = &self.entries {
+            f(&mut entries.lock());
+        }
+    }
+}
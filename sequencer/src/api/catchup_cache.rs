@@ -0,0 +1,170 @@
+//! Caching of account proofs served to peers catching up.
+//!
+//! When many peers restart at once, they mostly ask for the same few accounts, like the fee
+//! recipient and active builders, at the same height and view. Each of these requests proves the
+//! account against the fee state at that view, which means reading a path of the fee Merkle tree.
+//! The [`AccountProofCache`] keeps recently served proofs for `catchup/account` in memory for a
+//! short time, so that a burst of identical requests is served without touching the state tree.
+//!
+//! The state at a given height and view never changes, so cached proofs are always valid. The TTL
+//! only bounds how long they are kept around once the burst of requests is over. Cache
+//! effectiveness is reported via the `account_cache_hits` and `account_cache_misses` counters in
+//! the `catchup` metrics group.
+
+use std::{
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
+
+use derivative::Derivative;
+use espresso_types::{AccountQueryData, FeeAccount};
+use hotshot_types::{
+    data::ViewNumber,
+    traits::metrics::{Counter, Metrics},
+};
+use lru::LruCache;
+use parking_lot::Mutex;
+
+use super::options::Catchup;
+
+type Key = (u64, ViewNumber, FeeAccount);
+
+/// An LRU cache of account proofs, keyed by height, view and account, whose entries expire.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub(crate) struct AccountProofCache {
+    ttl: Duration,
+    #[derivative(Debug = "ignore")]
+    entries: Option<Mutex<LruCache<Key, (Instant, AccountQueryData)>>>,
+    #[derivative(Debug = "ignore")]
+    hits: Box<dyn Counter>,
+    #[derivative(Debug = "ignore")]
+    misses: Box<dyn Counter>,
+}
+
+impl AccountProofCache {
+    pub(crate) fn new(opt: &Catchup, metrics: &dyn Metrics) -> Self {
+        let metrics = metrics.subgroup("catchup".into());
+        let entries = NonZeroUsize::new(opt.account_cache_size)
+            .filter(|_| !opt.account_cache_ttl.is_zero())
+            .map(|size| Mutex::new(LruCache::new(size)));
+        Self {
+            ttl: opt.account_cache_ttl,
+            entries,
+            hits: metrics.create_counter("account_cache_hits".into(), None),
+            misses: metrics.create_counter("account_cache_misses".into(), None),
+        }
+    }
+
+    /// The cached proof for `account` at `height` and `view`, if there is one which has not
+    /// expired.
+    pub(crate) fn get(
+        &self,
+        height: u64,
+        view: ViewNumber,
+        account: FeeAccount,
+    ) -> Option<AccountQueryData> {
+        let entries = self.entries.as_ref()?;
+        let key = (height, view, account);
+        let mut entries = entries.lock();
+        let res = entries
+            .get(&key)
+            .filter(|(inserted, _)| inserted.elapsed() < self.ttl)
+            .map(|(_, data)| data.clone());
+        if res.is_some() {
+            self.hits.add(1);
+        } else {
+            // Drop the entry if it has expired.
+            entries.pop(&key);
+            self.misses.add(1);
+        }
+        res
+    }
+
+    /// Cache the proof for `account` at `height` and `view`.
+    pub(crate) fn insert(
+        &self,
+        height: u64,
+        view: ViewNumber,
+        account: FeeAccount,
+        data: AccountQueryData,
+    ) {
+        if let Some(entries) = &self.entries {
+            entries
+                .lock()
+                .put((height, view, account), (Instant::now(), data));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use espresso_types::{FeeAccountProof, ValidatedState};
+    use hotshot_types::traits::{metrics::NoMetrics, node_implementation::ConsensusTime};
+
+    use super::*;
+
+    fn proof(account: FeeAccount) -> AccountQueryData {
+        let mut state = ValidatedState::default();
+        state.prefund_account(account, 1.into());
+        FeeAccountProof::prove(&state.fee_merkle_tree, account.into())
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn test_account_proof_cache() {
+        let account = FeeAccount::default();
+        let data = proof(account);
+        let view = ViewNumber::new(1);
+
+        let cache = AccountProofCache::new(
+            &Catchup {
+                account_cache_size: 1,
+                account_cache_ttl: Duration::from_secs(3600),
+                ..Default::default()
+            },
+            &NoMetrics,
+        );
+        assert!(cache.get(1, view, account).is_none());
+        cache.insert(1, view, account, data.clone());
+        assert_eq!(cache.get(1, view, account).unwrap().balance, data.balance);
+
+        // Entries are keyed by height and view as well as account.
+        assert!(cache.get(2, view, account).is_none());
+        assert!(cache.get(1, ViewNumber::new(2), account).is_none());
+
+        // Least recently used entries are evicted.
+        cache.insert(2, view, account, data.clone());
+        assert!(cache.get(1, view, account).is_none());
+        assert!(cache.get(2, view, account).is_some());
+    }
+
+    #[test]
+    fn test_account_proof_cache_expiry() {
+        let account = FeeAccount::default();
+        let view = ViewNumber::new(1);
+
+        let cache = AccountProofCache::new(
+            &Catchup {
+                account_cache_ttl: Duration::from_millis(10),
+                ..Default::default()
+            },
+            &NoMetrics,
+        );
+        cache.insert(1, view, account, proof(account));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(cache.get(1, view, account).is_none());
+
+        // A TTL of 0 disables the cache.
+        let cache = AccountProofCache::new(
+            &Catchup {
+                account_cache_ttl: Duration::ZERO,
+                ..Default::default()
+            },
+            &NoMetrics,
+        );
+        cache.insert(1, view, account, proof(account));
+        assert!(cache.get(1, view, account).is_none());
+    }
+}
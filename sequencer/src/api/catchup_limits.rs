@@ -0,0 +1,288 @@
+//! Admission control for the catchup API.
+//!
+//! When many peers restart at once, they all ask us for state at the same time, and serving them
+//! can take enough CPU and database time to slow down our own participation in consensus. The
+//! [`CatchupLimiter`] bounds the work done for catchup with a separate [`WorkerPool`] for each kind
+//! of request: account proofs, which are the most expensive, the blocks frontier, and chain configs.
+//! This also keeps a flood of one kind of request from delaying the others.
+//!
+//! Each pool serves a fixed number of requests at a time. Further requests wait in a bounded queue,
+//! and are rejected with status 429 once the queue is full, so that peers move on to another node
+//! instead of waiting on an overloaded one. Queued requests for recent state are served first: a
+//! peer catching up to the head of the chain needs that state to rejoin consensus, while requests
+//! for older state can wait. Each pool can also be limited to a sustained rate of requests.
+//!
+//! Queue depths and rejections are reported in the `catchup` group of metrics.
+
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+    time::Instant,
+};
+
+use hotshot_types::traits::metrics::{Counter, Gauge, Metrics};
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+
+use super::{error::ApiError, options::Catchup, rate_limit::TokenBucket};
+
+/// Worker pools for each kind of catchup request.
+#[derive(Debug)]
+pub(crate) struct CatchupLimiter {
+    pub(crate) accounts: Arc<WorkerPool>,
+    pub(crate) frontier: Arc<WorkerPool>,
+    pub(crate) chain_config: Arc<WorkerPool>,
+}
+
+impl CatchupLimiter {
+    pub(crate) fn new(opt: &Catchup, metrics: &dyn Metrics) -> Self {
+        let metrics = metrics.subgroup("catchup".into());
+        let pool = |name: &str, workers: usize| {
+            Arc::new(WorkerPool::new(
+                name,
+                workers,
+                opt.max_queue,
+                opt.rate_limit,
+                &*metrics,
+            ))
+        };
+        Self {
+            accounts: pool("accounts", opt.account_workers),
+            frontier: pool("frontier", opt.frontier_workers),
+            chain_config: pool("chain_config", opt.chain_config_workers),
+        }
+    }
+}
+
+/// A bounded number of workers serving one kind of catchup request, with a priority queue.
+pub(crate) struct WorkerPool {
+    name: String,
+    workers: usize,
+    max_queue: usize,
+    rate: Option<TokenBucket>,
+    state: Mutex<PoolState>,
+    active: Box<dyn Gauge>,
+    queue_depth: Box<dyn Gauge>,
+    rejected: Box<dyn Counter>,
+}
+
+impl Debug for WorkerPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkerPool")
+            .field("name", &self.name)
+            .field("workers", &self.workers)
+            .field("max_queue", &self.max_queue)
+            .finish()
+    }
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    active: usize,
+    queue: BinaryHeap<Waiter>,
+    /// Number of requests queued so far, used to serve requests of equal priority in order.
+    queued: u64,
+}
+
+/// A request waiting for a worker.
+#[derive(Debug)]
+struct Waiter {
+    priority: u64,
+    seq: u64,
+    wake: oneshot::Sender<Permit>,
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Highest priority first, then first come, first served.
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+impl WorkerPool {
+    fn new(
+        name: &str,
+        workers: usize,
+        max_queue: usize,
+        rate: Option<f64>,
+        metrics: &dyn Metrics,
+    ) -> Self {
+        let rate = rate.map(|rate| {
+            TokenBucket::new(
+                rate,
+                Instant::now(),
+                metrics.create_counter(format!("{name}_rate_limited"), None),
+            )
+        });
+        Self {
+            name: name.into(),
+            workers: workers.max(1),
+            max_queue,
+            rate,
+            state: Default::default(),
+            active: metrics.create_gauge(format!("{name}_active"), None),
+            queue_depth: metrics.create_gauge(format!("{name}_queue_depth"), None),
+            rejected: metrics.create_counter(format!("{name}_queue_full"), None),
+        }
+    }
+
+    /// Wait for a worker to serve a request.
+    ///
+    /// Requests with a higher `priority` are served first. The returned [`Permit`] holds the worker
+    /// until it is dropped.
+    pub(crate) async fn acquire(self: &Arc<Self>, priority: u64) -> Result<Permit, ApiError> {
+        if let Some(rate) = &self.rate {
+            if !rate.take(Instant::now()) {
+                return Err(ApiError::RateLimited(format!(
+                    "too many {} catchup requests, try another peer",
+                    self.name
+                )));
+            }
+        }
+
+        let wake = {
+            let mut state = self.state.lock();
+            if state.active < self.workers {
+                state.active += 1;
+                self.active.set(state.active);
+                return Ok(Permit(self.clone()));
+            }
+            if state.queue.len() >= self.max_queue {
+                self.rejected.add(1);
+                return Err(ApiError::RateLimited(format!(
+                    "{} catchup queue is full, try another peer",
+                    self.name
+                )));
+            }
+            let (send, wake) = oneshot::channel();
+            let seq = state.queued;
+            state.queued += 1;
+            state.queue.push(Waiter {
+                priority,
+                seq,
+                wake: send,
+            });
+            self.queue_depth.set(state.queue.len());
+            wake
+        };
+
+        // The worker is handed over to us by a finishing request. The pool holds the sender as
+        // long as we are queued, so the channel cannot be closed before then.
+        wake.await
+            .map_err(|_| ApiError::Internal("catchup worker pool closed".into()))
+    }
+
+    /// Hand a finished request's worker to the next queued request, or free it.
+    fn release(self: &Arc<Self>) {
+        let next = {
+            let mut state = self.state.lock();
+            let next = state.queue.pop();
+            self.queue_depth.set(state.queue.len());
+            if next.is_none() {
+                state.active -= 1;
+                self.active.set(state.active);
+            }
+            next
+        };
+        if let Some(waiter) = next {
+            // If the waiting request was cancelled, the permit is returned and dropped, which hands
+            // the worker to the next request in turn.
+            waiter.wake.send(Permit(self.clone())).ok();
+        }
+    }
+}
+
+/// A worker serving a single catchup request.
+#[derive(Debug)]
+pub(crate) struct Permit(Arc<WorkerPool>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use hotshot_types::traits::metrics::NoMetrics;
+    use tokio::time::timeout;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_worker_pool_priority() {
+        let pool = Arc::new(WorkerPool::new("test", 1, 2, None, &NoMetrics));
+
+        // Occupy the only worker.
+        let permit = pool.acquire(0).await.unwrap();
+
+        // Queue a low and a high priority request; the queue is then full.
+        let low = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.acquire(1).await.map(|_| ()) }
+        });
+        let high = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.acquire(10).await }
+        });
+        while pool.state.lock().queue.len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(
+            pool.acquire(5).await,
+            Err(ApiError::RateLimited(_))
+        ));
+
+        // The high priority request is served first.
+        drop(permit);
+        let permit = timeout(Duration::from_secs(1), high)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(!low.is_finished());
+        drop(permit);
+        timeout(Duration::from_secs(1), low)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(pool.state.lock().active, 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter() {
+        let pool = Arc::new(WorkerPool::new("test", 1, 2, None, &NoMetrics));
+        let permit = pool.acquire(0).await.unwrap();
+
+        // A request which gives up waiting does not hold on to the worker.
+        timeout(Duration::from_millis(10), pool.acquire(0))
+            .await
+            .unwrap_err();
+        drop(permit);
+        let _permit = timeout(Duration::from_secs(1), pool.acquire(0))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
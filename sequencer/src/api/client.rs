@@ -0,0 +1,248 @@
+//! A typed client for the sequencer API.
+//!
+//! The general purpose [`surf_disco::Client`] requires every caller to know the path and the
+//! response type of each endpoint. [`SequencerClient`] wraps it with one method per endpoint,
+//! using the same types (and therefore the same serialization) as the server, so that Rust
+//! applications built on the sequencer do not have to keep their own copies of these types in
+//! sync with the node.
+
+use anyhow::Context;
+use committable::Commitment;
+use espresso_types::{
+    v0_3::ChainConfig, EpochStakeTable, FeeAccount, FeeMerkleTree, Header, NamespaceId,
+    Transaction, TxStatus,
+};
+use hotshot_query_service::availability::{BlockQueryData, LeafQueryData};
+use hotshot_types::{
+    data::{EpochNumber, ViewNumber},
+    light_client::StateSignatureRequestBody,
+    traits::node_implementation::ConsensusTime as _,
+};
+use serde::de::DeserializeOwned;
+use surf_disco::Client;
+use tide_disco::error::ServerError;
+use url::Url;
+
+use super::{
+    endpoints::{
+        BatchSubmitResult, DecidedTransaction, FeeEstimate, LeafCertificateQueryData,
+        NamespaceProofQueryData, VersionInfo,
+    },
+    BlocksFrontier,
+};
+use crate::{
+    state_signature::aggregator::StateSignatureBundleQueryData, AccountQueryData, SeqTypes,
+    SequencerApiVersion,
+};
+
+/// A client for the sequencer API.
+#[derive(Clone, Debug)]
+pub struct SequencerClient {
+    inner: Client<ServerError, SequencerApiVersion>,
+}
+
+impl SequencerClient {
+    /// Connect to the sequencer API served at `url`.
+    ///
+    /// This does not wait for the server to be ready; use [`connect`](Self::connect) for that.
+    pub fn new(url: Url) -> Self {
+        Self {
+            inner: Client::new(url),
+        }
+    }
+
+    /// Wait until the server is ready to handle requests.
+    pub async fn connect(&self) {
+        self.inner.connect(None).await;
+    }
+
+    /// The underlying untyped client, for endpoints not covered by this interface.
+    pub fn inner(&self) -> &Client<ServerError, SequencerApiVersion> {
+        &self.inner
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> anyhow::Result<T> {
+        self.inner
+            .get(path)
+            .send()
+            .await
+            .with_context(|| format!("GET {path}"))
+    }
+
+    /// Submit a transaction, returning its hash.
+    pub async fn submit(&self, tx: &Transaction) -> anyhow::Result<Commitment<Transaction>> {
+        self.inner
+            .post("submit/submit")
+            .body_binary(tx)
+            .context("encoding transaction")?
+            .send()
+            .await
+            .context("submitting transaction")
+    }
+
+    /// Submit a transaction and wait until it is included in a decided block.
+    ///
+    /// The server waits for at most `timeout_secs` seconds, or its own limit if that is shorter.
+    pub async fn submit_and_wait(
+        &self,
+        tx: &Transaction,
+        timeout_secs: Option<u64>,
+    ) -> anyhow::Result<DecidedTransaction> {
+        let path = match timeout_secs {
+            Some(secs) => format!("submit/submit/wait/{secs}"),
+            None => "submit/submit/wait".into(),
+        };
+        self.inner
+            .post(&path)
+            .body_binary(tx)
+            .context("encoding transaction")?
+            .send()
+            .await
+            .context("submitting transaction")
+    }
+
+    /// Submit several transactions in one request.
+    ///
+    /// Returns the result for each transaction, in the same order as `txs`.
+    pub async fn submit_batch(
+        &self,
+        txs: &[Transaction],
+    ) -> anyhow::Result<Vec<BatchSubmitResult>> {
+        self.inner
+            .post("submit/batch")
+            .body_binary(&txs)
+            .context("encoding transactions")?
+            .send()
+            .await
+            .context("submitting transactions")
+    }
+
+    /// The status of a previously submitted transaction.
+    pub async fn tx_status(&self, hash: Commitment<Transaction>) -> anyhow::Result<TxStatus> {
+        self.get(&format!("submit/status/{hash}")).await
+    }
+
+    /// Estimate the fee required to sequence a transaction of `size` bytes.
+    pub async fn fee_estimate(&self, size: u64) -> anyhow::Result<FeeEstimate> {
+        self.get(&format!("submit/fee-estimate/{size}")).await
+    }
+
+    /// The API and protocol versions spoken by the server.
+    pub async fn version_info(&self) -> anyhow::Result<VersionInfo> {
+        self.get("status/version").await
+    }
+
+    /// The number of blocks in the chain, according to the server.
+    pub async fn block_height(&self) -> anyhow::Result<u64> {
+        self.get("node/block-height").await
+    }
+
+    /// The stake table in effect during `epoch`.
+    pub async fn stake_table(&self, epoch: EpochNumber) -> anyhow::Result<EpochStakeTable> {
+        self.get(&format!("node/stake-table/{}", epoch.u64())).await
+    }
+
+    /// The stake table for the current epoch.
+    pub async fn current_stake_table(&self) -> anyhow::Result<EpochStakeTable> {
+        self.get("node/stake-table/current").await
+    }
+
+    /// The header of the block at `height`.
+    pub async fn header(&self, height: u64) -> anyhow::Result<Header> {
+        self.get(&format!("availability/header/{height}")).await
+    }
+
+    /// The leaf at `height`.
+    pub async fn leaf(&self, height: u64) -> anyhow::Result<LeafQueryData<SeqTypes>> {
+        self.get(&format!("availability/leaf/{height}")).await
+    }
+
+    /// The leaf at `height`, with the quorum certificate and stake table needed to verify it.
+    pub async fn leaf_certificate(&self, height: u64) -> anyhow::Result<LeafCertificateQueryData> {
+        self.get(&format!("availability/leaf/{height}/qc")).await
+    }
+
+    /// The block at `height`.
+    pub async fn block(&self, height: u64) -> anyhow::Result<BlockQueryData<SeqTypes>> {
+        self.get(&format!("availability/block/{height}")).await
+    }
+
+    /// The transactions in namespace `ns` of the block at `height`, with a proof of their
+    /// inclusion.
+    pub async fn namespace(
+        &self,
+        height: u64,
+        ns: NamespaceId,
+    ) -> anyhow::Result<NamespaceProofQueryData> {
+        self.get(&format!("availability/block/{height}/namespace/{ns}"))
+            .await
+    }
+
+    /// The fee state of `account` after the block at `height`, decided in `view`.
+    pub async fn catchup_account(
+        &self,
+        height: u64,
+        view: ViewNumber,
+        account: FeeAccount,
+    ) -> anyhow::Result<AccountQueryData> {
+        self.get(&format!(
+            "catchup/{height}/{}/account/{account}",
+            view.u64()
+        ))
+        .await
+    }
+
+    /// A fee state snapshot containing `accounts`, after the block at `height`, decided in `view`.
+    pub async fn catchup_accounts(
+        &self,
+        height: u64,
+        view: ViewNumber,
+        accounts: &[FeeAccount],
+    ) -> anyhow::Result<FeeMerkleTree> {
+        let path = format!("catchup/{height}/{}/accounts", view.u64());
+        self.inner
+            .post(&path)
+            .body_binary(&accounts.to_vec())
+            .context("encoding accounts")?
+            .send()
+            .await
+            .with_context(|| format!("POST {path}"))
+    }
+
+    /// The frontier of the block Merkle tree after the block at `height`, decided in `view`.
+    pub async fn catchup_blocks(
+        &self,
+        height: u64,
+        view: ViewNumber,
+    ) -> anyhow::Result<BlocksFrontier> {
+        self.get(&format!("catchup/{height}/{}/blocks", view.u64()))
+            .await
+    }
+
+    /// The chain config with commitment `commit`.
+    pub async fn catchup_chain_config(
+        &self,
+        commit: Commitment<ChainConfig>,
+    ) -> anyhow::Result<ChainConfig> {
+        self.get(&format!("catchup/chain-config/{commit}")).await
+    }
+
+    /// The server's signature on the light client state after the block at `height`.
+    pub async fn state_signature(&self, height: u64) -> anyhow::Result<StateSignatureRequestBody> {
+        self.get(&format!("state-signature/block/{height}")).await
+    }
+
+    /// An aggregated bundle of signatures on the light client state after the block at `height`.
+    pub async fn state_signature_bundle(
+        &self,
+        height: u64,
+    ) -> anyhow::Result<StateSignatureBundleQueryData> {
+        self.get(&format!("state-signature/bundle/{height}")).await
+    }
+}
+
+impl From<Client<ServerError, SequencerApiVersion>> for SequencerClient {
+    fn from(inner: Client<ServerError, SequencerApiVersion>) -> Self {
+        Self { inner }
+    }
+}
@@ -0,0 +1,148 @@
+//! Compression of HTTP API responses.
+//!
+//! Availability responses are dominated by block payloads, which for rollup data typically
+//! compress several times over. When enabled, the [`ResponseCompression`] middleware compresses
+//! response bodies with zstd for clients which advertise support for it in their `Accept-Encoding`
+//! header. Other clients, and small or streaming responses, are served unchanged.
+//!
+//! Like [`ApiLimits`](super::limits::ApiLimits), [`ResponseCompression`] is installed by wrapping the
+//! listener the app is served on, in a [`CompressionListener`].
+
+use std::{
+    fmt::{self, Display, Formatter},
+    io,
+};
+
+use async_trait::async_trait;
+use tide::{
+    listener::{ListenInfo, Listener, ToListener},
+    Body, Middleware, Next, Request, Server,
+};
+
+/// Responses smaller than this are not worth compressing.
+const MIN_COMPRESSED_BYTES: usize = 1024;
+
+/// zstd compression level for responses, favoring speed over size.
+const LEVEL: i32 = 3;
+
+/// Middleware which compresses responses for clients that accept zstd.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ResponseCompression;
+
+/// Whether an `Accept-Encoding` header value allows a zstd-encoded response.
+fn accepts_zstd(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|coding| {
+        let mut params = coding.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        if !name.eq_ignore_ascii_case("zstd") && name != "*" {
+            return false;
+        }
+        // A quality of 0 means the coding is explicitly not acceptable.
+        !params.any(|param| {
+            param
+                .trim()
+                .strip_prefix("q=")
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        })
+    })
+}
+
+#[async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for ResponseCompression {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let accepts = req
+            .header("Accept-Encoding")
+            .is_some_and(|values| values.iter().any(|value| accepts_zstd(value.as_str())));
+        let mut res = next.run(req).await;
+        res.append_header("Vary", "Accept-Encoding");
+        if !accepts || res.header("Content-Encoding").is_some() {
+            return Ok(res);
+        }
+        // Streaming responses have no known length, and are left alone.
+        if !res.len().is_some_and(|len| len >= MIN_COMPRESSED_BYTES) {
+            return Ok(res);
+        }
+
+        let mime = res.content_type();
+        let bytes = res.take_body().into_bytes().await?;
+        let mut body = Body::from_bytes(zstd::encode_all(bytes.as_slice(), LEVEL)?);
+        if let Some(mime) = mime {
+            body.set_mime(mime);
+        }
+        res.set_body(body);
+        res.insert_header("Content-Encoding", "zstd");
+        Ok(res)
+    }
+}
+
+/// A [`Listener`] which installs [`ResponseCompression`], if enabled, on the server before
+/// delegating to another listener.
+#[derive(Debug)]
+pub(crate) struct CompressionListener<L> {
+    inner: L,
+    compression: Option<ResponseCompression>,
+}
+
+impl<L> CompressionListener<L> {
+    pub(crate) fn new(inner: L, compression: Option<ResponseCompression>) -> Self {
+        Self { inner, compression }
+    }
+}
+
+impl<L: Display> Display for CompressionListener<L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+#[async_trait]
+impl<State, L> Listener<State> for CompressionListener<L>
+where
+    State: Clone + Send + Sync + 'static,
+    L: Listener<State>,
+{
+    async fn bind(&mut self, mut app: Server<State>) -> io::Result<()> {
+        if let Some(compression) = self.compression {
+            app.with(compression);
+        }
+        self.inner.bind(app).await
+    }
+
+    async fn accept(&mut self) -> io::Result<()> {
+        self.inner.accept().await
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        self.inner.info()
+    }
+}
+
+impl<State, L> ToListener<State> for CompressionListener<L>
+where
+    State: Clone + Send + Sync + 'static,
+    L: Listener<State>,
+{
+    type Listener = Self;
+
+    fn to_listener(self) -> io::Result<Self::Listener> {
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_accepts_zstd() {
+        assert!(accepts_zstd("zstd"));
+        assert!(accepts_zstd("gzip, deflate, br, zstd"));
+        assert!(accepts_zstd("gzip;q=1.0, ZSTD;q=0.5"));
+        assert!(accepts_zstd("*"));
+        assert!(!accepts_zstd(""));
+        assert!(!accepts_zstd("gzip, br"));
+        assert!(!accepts_zstd("zstd;q=0"));
+        assert!(!accepts_zstd("gzip, zstd; q=0.0"));
+    }
+}
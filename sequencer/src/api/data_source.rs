@@ -1,49 +1,68 @@
 use std::{num::NonZeroUsize, time::Duration};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use async_trait::async_trait;
 use committable::Commitment;
 use espresso_types::{
     v0::traits::{PersistenceOptions, SequencerPersistence},
-    v0_3::ChainConfig,
-    FeeAccount, FeeAccountProof, FeeMerkleTree, NodeState, PubKey, Transaction,
+    v0_3::{BidTx, ChainConfig, SolverAuctionResults},
+    DaPointer, DecideEvent, EpochStakeTable, FeeAccount, FeeAccountProof, FeeMerkleTree,
+    NamespaceId, NodeState, PubKey, Transaction, TxStatus, ValidatedState, ViewRecord,
 };
-use futures::future::Future;
+use futures::{future::Future, stream::BoxStream};
 use hotshot_query_service::{
     availability::AvailabilityDataSource,
     data_source::{UpdateDataSource, VersionedDataSource},
-    fetching::provider::{AnyProvider, QueryServiceProvider},
+    fetching::provider::AnyProvider,
     node::NodeDataSource,
     status::StatusDataSource,
 };
+use hotshot_types::network::{
+    BuilderType, CombinedNetworkConfig, Libp2pConfig, RandomBuilderConfig,
+};
 use hotshot_types::{
-    data::ViewNumber,
+    data::{EpochNumber, ViewNumber},
     light_client::StateSignatureRequestBody,
     network::NetworkConfig,
-    stake_table::StakeTableEntry,
-    traits::{network::ConnectedNetwork, node_implementation::Versions},
+    traits::network::ConnectedNetwork,
     HotShotConfig, PeerConfig, ValidatorConfig,
 };
-use hotshot_types::{
-    network::{BuilderType, CombinedNetworkConfig, Libp2pConfig, RandomBuilderConfig},
-    traits::node_implementation::NodeType,
-};
 use serde::{Deserialize, Serialize};
 use tide_disco::Url;
 use vec1::Vec1;
 
 use super::{
+    archive::PayloadArchive,
+    endpoints::{
+        namespace_proofs, DecidedTransaction, FeeAccountQueryData, FeeDepositsQueryData,
+        FeeEstimate, NamespaceProofQueryData, VersionInfo,
+    },
     fs,
     options::{Options, Query},
+    peers::QueryPeers,
     sql, AccountQueryData, BlocksFrontier,
 };
 use crate::{
+    builder_fees::BuilderEarnings,
+    builder_registry::BuilderInfo,
+    mempool::PendingTransaction,
+    network::health::NetworkStatus,
     persistence::{self},
-    SeqTypes, SequencerApiVersion,
+    state_signature::aggregator::StateSignatureBundleQueryData,
+    supervisor::TaskStatus,
+    tx_trace::TraceParent,
+    upgrade::UpgradeInfo,
+    SeqTypes,
 };
 
 pub trait DataSourceOptions: PersistenceOptions {
-    type DataSource: SequencerDataSource<Options = Self>;
+    type DataSource: SequencerDataSource;
+
+    /// Options for the query service storage associated with this persistence.
+    ///
+    /// For most backends the query service shares its storage with consensus persistence, and
+    /// this is just a copy of `self`.
+    fn query_options(&self) -> <Self::DataSource as SequencerDataSource>::Options;
 
     fn enable_query_module(&self, opt: Options, query: Query) -> Options;
 }
@@ -51,6 +70,10 @@ pub trait DataSourceOptions: PersistenceOptions {
 impl DataSourceOptions for persistence::sql::Options {
     type DataSource = sql::DataSource;
 
+    fn query_options(&self) -> Self {
+        self.clone()
+    }
+
     fn enable_query_module(&self, opt: Options, query: Query) -> Options {
         opt.query_sql(query, self.clone())
     }
@@ -59,11 +82,46 @@ impl DataSourceOptions for persistence::sql::Options {
 impl DataSourceOptions for persistence::fs::Options {
     type DataSource = fs::DataSource;
 
+    fn query_options(&self) -> Self {
+        self.clone()
+    }
+
     fn enable_query_module(&self, opt: Options, query: Query) -> Options {
         opt.query_fs(query, self.clone())
     }
 }
 
+impl<Old, New> DataSourceOptions for persistence::migrating::Options<Old, New>
+where
+    Old: DataSourceOptions,
+    New: PersistenceOptions,
+{
+    // The query service keeps using the old backend's storage for the duration of the migration.
+    type DataSource = Old::DataSource;
+
+    fn query_options(&self) -> <Self::DataSource as SequencerDataSource>::Options {
+        self.old().query_options()
+    }
+
+    fn enable_query_module(&self, opt: Options, query: Query) -> Options {
+        self.old().enable_query_module(opt, query)
+    }
+}
+
+impl DataSourceOptions for persistence::rocksdb::Options {
+    // The query service does not have a RocksDB backend, so we colocate a file system query
+    // database with the consensus storage.
+    type DataSource = fs::DataSource;
+
+    fn query_options(&self) -> persistence::fs::Options {
+        persistence::fs::Options::new(self.query_path())
+    }
+
+    fn enable_query_module(&self, opt: Options, query: Query) -> Options {
+        opt.query_fs(query, self.query_options())
+    }
+}
+
 /// A data source with sequencer-specific functionality.
 ///
 /// This trait extends the generic [`AvailabilityDataSource`] with some additional data needed to
@@ -81,47 +139,350 @@ pub trait SequencerDataSource:
 
     /// Instantiate a data source from command line options.
     async fn create(opt: Self::Options, provider: Provider, reset: bool) -> anyhow::Result<Self>;
+
+    /// Subscribe to the transactions and proof for a single namespace of each block.
+    ///
+    /// The stream yields one item per block, starting from block `from`, including blocks which do
+    /// not contain the namespace (in which case the item is empty). If a proof cannot be made for
+    /// some block, the stream yields an error and ends.
+    async fn subscribe_namespace(
+        &self,
+        from: usize,
+        ns_id: NamespaceId,
+    ) -> BoxStream<'static, anyhow::Result<NamespaceProofQueryData>>
+    where
+        Self: Sync,
+    {
+        let blocks = self.subscribe_blocks(from).await;
+        let vid = self.subscribe_vid_common(from).await;
+        namespace_proofs(blocks, vid, ns_id)
+    }
+
+    /// Load the full merklized state as of block `height`, for inclusion in a snapshot.
+    ///
+    /// Returns `None` if this data source does not store merklized state.
+    async fn load_state_snapshot(&self, _height: u64) -> anyhow::Result<Option<ValidatedState>> {
+        Ok(None)
+    }
+
+    /// Seed this data source with the merklized state as of block `height` from a snapshot.
+    ///
+    /// Data sources which do not store merklized state ignore the state. The leaf at `height` must
+    /// already have been added to the data source.
+    async fn import_state_snapshot(
+        &self,
+        height: u64,
+        _state: &ValidatedState,
+    ) -> anyhow::Result<()> {
+        tracing::info!(
+            height,
+            "data source does not store merklized state; not importing state"
+        );
+        Ok(())
+    }
+
+    /// The height below which block payloads have been pruned from this data source.
+    ///
+    /// Returns `None` if this data source never prunes payloads.
+    async fn pruned_height(&self) -> anyhow::Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Prove that the block at `height` is in the block Merkle tree of the block at `anchor`.
+    ///
+    /// `anchor` must be greater than `height`. Returns `None` if this data source does not store
+    /// merklized state.
+    async fn get_block_proof(
+        &self,
+        _height: u64,
+        _anchor: u64,
+    ) -> anyhow::Result<Option<BlocksFrontier>> {
+        Ok(None)
+    }
+
+    /// The latest block decided at or before `timestamp`, if any.
+    async fn get_block_by_time(&self, _timestamp: u64) -> anyhow::Result<Option<BlockAtTime>> {
+        bail!("block lookup by time requires the query module with SQL storage");
+    }
+
+    /// Delete all data for blocks at `height` and above.
+    ///
+    /// This is used to repair query storage which has diverged from consensus storage, so that the
+    /// chain can be ingested again from consensus.
+    async fn truncate(&self, _height: u64) -> anyhow::Result<()> {
+        bail!("truncating query storage requires SQL storage");
+    }
 }
 
 /// Provider for fetching missing data for the query service.
 pub type Provider = AnyProvider<SeqTypes>;
 
-/// Create a provider for fetching missing data from a list of peer query services.
-pub fn provider<V: Versions>(
-    peers: impl IntoIterator<Item = Url>,
-    bind_version: SequencerApiVersion,
-) -> Provider {
+/// Create a provider for fetching missing data from a set of peer query services.
+///
+/// The provider shares `peers`, so changes made to the set of peers after the provider is created
+/// take effect for subsequent fetches. If a payload `archive` is given, missing payloads and VID
+/// common data are fetched from the archive before trying peers.
+pub fn provider(peers: QueryPeers, archive: Option<PayloadArchive>) -> Provider {
     let mut provider = Provider::default();
-    for peer in peers {
-        tracing::info!("will fetch missing data from {peer}");
-        provider = provider.with_provider(QueryServiceProvider::new(peer, bind_version));
+    if let Some(archive) = archive {
+        provider = provider
+            .with_block_provider(archive.clone())
+            .with_vid_common_provider(archive);
     }
-    provider
+    provider.with_provider(peers)
 }
 
 pub(crate) trait SubmitDataSource<N: ConnectedNetwork<PubKey>, P: SequencerPersistence> {
     fn submit(&self, tx: Transaction) -> impl Send + Future<Output = anyhow::Result<()>>;
+
+    /// Submit several transactions at once.
+    ///
+    /// Each transaction is accepted or rejected independently. The result for each transaction is
+    /// returned in the same order as `txs`.
+    fn submit_batch(
+        &self,
+        txs: Vec<Transaction>,
+    ) -> impl Send + Future<Output = Vec<anyhow::Result<()>>>;
+
+    /// Submit a transaction and wait until it is included in a decided block.
+    ///
+    /// Returns the position of the transaction in the chain, or `None` if it was not decided
+    /// within `timeout`.
+    fn submit_and_wait(
+        &self,
+        tx: Transaction,
+        timeout: Duration,
+    ) -> impl Send + Future<Output = anyhow::Result<Option<DecidedTransaction>>>;
+
+    /// The chain config in effect as of the latest decided block.
+    ///
+    /// Submitted transactions are validated against this config.
+    fn active_chain_config(&self) -> impl Send + Future<Output = ChainConfig>;
+
+    /// Start tracing a transaction which is about to be submitted.
+    ///
+    /// `parent` is the trace context the transaction was submitted with, if any.
+    fn trace_transaction(
+        &self,
+        hash: Commitment<Transaction>,
+        ns: NamespaceId,
+        parent: Option<TraceParent>,
+    ) -> impl Send + Future<Output = ()>;
+}
+
+pub(crate) trait TxStatusDataSource {
+    /// Get the status of a transaction submitted through this node.
+    ///
+    /// Returns `None` if this node has no record of the transaction.
+    fn get_tx_status(
+        &self,
+        hash: Commitment<Transaction>,
+    ) -> impl Send + Future<Output = anyhow::Result<Option<TxStatus>>>;
+}
+
+pub(crate) trait MempoolDataSource {
+    /// The transactions waiting in this node's mempool, highest priority first.
+    fn pending_transactions(&self) -> impl Send + Future<Output = Vec<PendingTransaction>>;
+
+    /// Drop a stuck transaction from this node's mempool.
+    ///
+    /// Returns `false` if the transaction was not in the mempool.
+    fn evict_pending_transaction(
+        &self,
+        hash: Commitment<Transaction>,
+    ) -> impl Send + Future<Output = anyhow::Result<bool>>;
+}
+
+pub(crate) trait FeeEstimateDataSource {
+    /// Estimate the fee for a transaction of `size` bytes, as of the latest decided block.
+    fn estimate_fee(&self, size: u64) -> impl Send + Future<Output = anyhow::Result<FeeEstimate>>;
+}
+
+pub(crate) trait FeeAccountDataSource {
+    /// Get the state of `account` as of the block at `height`, or the latest decided block.
+    ///
+    /// The result includes the balance of `account` with a proof relative to the fee state of the
+    /// requested block, as well as any deposits to `account` which have been finalized on the L1
+    /// but are not yet reflected in that fee state.
+    ///
+    /// Historical queries are only supported by storage which retains old fee state snapshots.
+    fn get_fee_account(
+        &self,
+        account: FeeAccount,
+        height: Option<u64>,
+    ) -> impl Send + Future<Output = anyhow::Result<FeeAccountQueryData>>;
+
+    /// Get up to `limit` indexed L1 deposits to `account`, skipping the first `offset`.
+    fn get_fee_deposits(
+        &self,
+        account: FeeAccount,
+        offset: u64,
+        limit: u64,
+    ) -> impl Send + Future<Output = anyhow::Result<FeeDepositsQueryData>>;
+
+    /// Get the fees charged to the builder fee account `account` by decided and pending blocks.
+    fn get_builder_earnings(
+        &self,
+        account: FeeAccount,
+    ) -> impl Send + Future<Output = anyhow::Result<BuilderEarnings>>;
 }
 
 pub(crate) trait HotShotConfigDataSource {
     fn get_config(&self) -> impl Send + Future<Output = PublicNetworkConfig>;
+
+    /// Get the builders known to this node, and their health.
+    fn get_builders(&self) -> impl Send + Future<Output = Vec<BuilderInfo>>;
+}
+
+pub(crate) trait UpgradeDataSource {
+    /// Get every scheduled or observed protocol upgrade, with its status.
+    fn get_upgrades(&self) -> impl Send + Future<Output = Vec<UpgradeInfo>>;
+}
+
+pub(crate) trait ChainConfigHistoryDataSource {
+    /// Get every chain config that has been active, ordered by activation height.
+    fn get_chain_config_history(
+        &self,
+    ) -> impl Send + Future<Output = anyhow::Result<Vec<ChainConfigActivation>>>;
+}
+
+/// A chain config, along with the height of the first block in which it was active.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ChainConfigActivation {
+    pub height: u64,
+    pub commitment: Commitment<ChainConfig>,
+    pub chain_config: ChainConfig,
 }
 
 #[async_trait]
 pub(crate) trait StateSignatureDataSource<N: ConnectedNetwork<PubKey>> {
     async fn get_state_signature(&self, height: u64) -> Option<StateSignatureRequestBody>;
+    /// The signatures of a quorum of nodes on the light client state at `height`, if this node has
+    /// aggregated them.
+    async fn get_aggregate(&self, height: u64) -> Option<StateSignatureBundleQueryData>;
+}
+
+pub(crate) trait BlockTimeDataSource {
+    /// The latest block decided at or before `timestamp`, if any.
+    fn get_block_by_time(
+        &self,
+        timestamp: u64,
+    ) -> impl Send + Future<Output = anyhow::Result<Option<BlockAtTime>>>;
+}
+
+/// The block which was the tip of the chain at a given time.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BlockAtTime {
+    pub height: u64,
+    /// The timestamp of the block, which is at or before the requested time.
+    pub timestamp: u64,
+}
+
+pub(crate) trait LeaderScheduleDataSource {
+    /// The leaders of `count` consecutive views, starting from `from_view` or the current view.
+    fn get_leaders(
+        &self,
+        from_view: Option<u64>,
+        count: u64,
+    ) -> impl Send + Future<Output = anyhow::Result<LeaderSchedule>>;
+}
+
+/// The predicted leaders of a range of views.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct LeaderSchedule {
+    /// The view this node was in when the schedule was computed.
+    pub current_view: u64,
+    /// The epoch whose stake table the schedule was computed from.
+    ///
+    /// Leaders of views in later epochs are predicted from this stake table, and may change if the
+    /// stake table changes when the next epoch begins.
+    pub epoch: u64,
+    pub leaders: Vec<ViewLeader>,
+}
+
+/// The leader of a view.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ViewLeader {
+    pub view: u64,
+    pub leader: PubKey,
+}
+
+pub(crate) trait ViewLogDataSource {
+    /// The records of the views in `from..until` observed by this node.
+    fn get_view_records(
+        &self,
+        from: u64,
+        until: u64,
+    ) -> impl Send + Future<Output = anyhow::Result<Vec<ViewRecord>>>;
+}
+
+pub(crate) trait DecideEventDataSource {
+    /// At most `limit` logged decide events, starting from the block at height `from`.
+    fn get_decide_events(
+        &self,
+        from: u64,
+        limit: usize,
+    ) -> impl Send + Future<Output = anyhow::Result<Vec<DecideEvent>>>;
+}
+
+pub(crate) trait SolverDataSource {
+    /// The current view, or [`None`] if the marketplace version is not active yet.
+    fn marketplace_view(&self) -> impl Send + Future<Output = Option<ViewNumber>>;
+
+    /// Get the results of the namespace sequencing auction for `view` from the solver.
+    fn get_auction_results(
+        &self,
+        view: ViewNumber,
+    ) -> impl Send + Future<Output = anyhow::Result<SolverAuctionResults>>;
+
+    /// Forward a bid for the sequencing rights of some namespaces to the solver.
+    fn submit_bid(&self, bid: &BidTx) -> impl Send + Future<Output = anyhow::Result<()>>;
 }
 
 pub(crate) trait NodeStateDataSource {
     fn node_state(&self) -> impl Send + Future<Output = &NodeState>;
 }
 
-pub(crate) trait StakeTableDataSource<T: NodeType> {
+pub(crate) trait VersionDataSource {
+    /// The API and protocol versions this node speaks.
+    fn version_info(&self) -> impl Send + Future<Output = VersionInfo>;
+}
+
+pub(crate) trait DaMirrorDataSource {
+    /// Where the payload of block `height` was posted on an external DA layer, if it was mirrored.
+    fn get_da_pointer(
+        &self,
+        height: u64,
+    ) -> impl Send + Future<Output = anyhow::Result<Option<DaPointer>>>;
+}
+
+pub(crate) trait NetworkHealthDataSource {
+    /// The health of the transports this node sends messages over.
+    ///
+    /// Returns `None` if this node does not use the production CDN and libp2p network.
+    fn network_status(&self) -> impl Send + Future<Output = Option<NetworkStatus>>;
+}
+
+pub(crate) trait TaskStatusDataSource {
+    /// The health of this node's long-running background tasks.
+    fn task_status(&self) -> impl Send + Future<Output = Vec<TaskStatus>>;
+}
+
+pub(crate) trait StakeTableDataSource {
     /// Get the stake table for a given epoch or the current epoch if not provided
+    ///
+    /// Stake tables are served from the history recorded in persistence when available, and
+    /// otherwise computed from the consensus memberships.
     fn get_stake_table(
         &self,
-        epoch: Option<<T as NodeType>::Epoch>,
-    ) -> impl Send + Future<Output = Vec<StakeTableEntry<T::SignatureKey>>>;
+        epoch: Option<EpochNumber>,
+    ) -> impl Send + Future<Output = EpochStakeTable>;
+
+    /// Get the stake table in effect for the block at `height`.
+    fn get_stake_table_for_height(
+        &self,
+        height: u64,
+    ) -> impl Send + Future<Output = EpochStakeTable>;
 }
 
 pub(crate) trait CatchupDataSource: Sync {
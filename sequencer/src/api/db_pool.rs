@@ -0,0 +1,129 @@
+//! Monitoring of the database connection pool used by the SQL query service.
+//!
+//! Every API request served from the database holds a pooled connection for the duration of its
+//! transaction. Under heavy load the pool can be exhausted, at which point requests queue for a
+//! connection until they time out. [`PoolMonitor`] periodically samples the state of the pool and
+//! reports it in the `db_pool` group of the metrics served on the `status/metrics` endpoint, and
+//! logs a warning when the pool is saturated.
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use hotshot_query_service::data_source::ExtensibleDataSource;
+use hotshot_types::traits::metrics::{Counter, Gauge, Histogram, Metrics};
+use tokio::time::sleep;
+
+/// How often to sample the connection pool.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A snapshot of the state of a connection pool.
+#[derive(Clone, Copy, Debug)]
+pub struct PoolSample {
+    /// How long it took to acquire a connection from the pool.
+    pub wait: Duration,
+    /// Connections to the database currently open, if the database reports it.
+    ///
+    /// This does not include the connection used to take the sample.
+    pub open: Option<usize>,
+    /// Open connections which are currently running a statement or transaction, if the database
+    /// reports it.
+    ///
+    /// This does not include the connection used to take the sample.
+    pub in_use: Option<usize>,
+}
+
+/// Storage backed by a pool of database connections.
+#[async_trait]
+pub trait ConnectionPool: Send + Sync {
+    /// Acquire a connection from the pool and report the state of the pool.
+    async fn sample_pool(&self) -> anyhow::Result<PoolSample>;
+}
+
+#[async_trait]
+impl<T, S> ConnectionPool for ExtensibleDataSource<T, S>
+where
+    T: ConnectionPool,
+    S: Send + Sync,
+{
+    async fn sample_pool(&self) -> anyhow::Result<PoolSample> {
+        self.inner().sample_pool().await
+    }
+}
+
+#[derive(Debug)]
+struct PoolMetrics {
+    max_connections: Box<dyn Gauge>,
+    open_connections: Box<dyn Gauge>,
+    in_use_connections: Box<dyn Gauge>,
+    acquire_wait: Box<dyn Histogram>,
+    acquire_failures: Box<dyn Counter>,
+}
+
+impl PoolMetrics {
+    fn new(metrics: &dyn Metrics) -> Self {
+        let metrics = metrics.subgroup("db_pool".into());
+        Self {
+            max_connections: metrics.create_gauge("max_connections".into(), None),
+            open_connections: metrics.create_gauge("open_connections".into(), None),
+            in_use_connections: metrics.create_gauge("in_use_connections".into(), None),
+            acquire_wait: metrics.create_histogram("acquire_wait".into(), Some("s".into())),
+            acquire_failures: metrics.create_counter("acquire_failures".into(), None),
+        }
+    }
+}
+
+/// Periodically samples a connection pool and reports its utilization.
+pub(super) struct PoolMonitor<D> {
+    db: Arc<D>,
+    max_connections: u32,
+    metrics: PoolMetrics,
+}
+
+impl<D: ConnectionPool> PoolMonitor<D> {
+    pub(super) fn new(db: Arc<D>, max_connections: u32, metrics: &dyn Metrics) -> Self {
+        let metrics = PoolMetrics::new(metrics);
+        metrics.max_connections.set(max_connections as usize);
+        Self {
+            db,
+            max_connections,
+            metrics,
+        }
+    }
+
+    /// Sample the pool forever.
+    pub(super) async fn run(self) {
+        loop {
+            self.sample().await;
+            sleep(SAMPLE_INTERVAL).await;
+        }
+    }
+
+    async fn sample(&self) {
+        let sample = match self.db.sample_pool().await {
+            Ok(sample) => sample,
+            Err(err) => {
+                self.metrics.acquire_failures.add(1);
+                tracing::warn!("failed to acquire database connection: {err:#}");
+                return;
+            }
+        };
+        self.metrics
+            .acquire_wait
+            .add_point(sample.wait.as_secs_f64());
+        if let Some(open) = sample.open {
+            self.metrics.open_connections.set(open);
+        }
+        if let Some(in_use) = sample.in_use {
+            self.metrics.in_use_connections.set(in_use);
+            // Count the connection used for the sample itself.
+            if in_use + 1 >= self.max_connections as usize {
+                tracing::warn!(
+                    in_use,
+                    max_connections = self.max_connections,
+                    wait = ?sample.wait,
+                    "database connection pool is saturated"
+                );
+            }
+        }
+    }
+}
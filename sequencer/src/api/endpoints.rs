@@ -3,43 +3,210 @@
 use std::{
     collections::{BTreeSet, HashMap},
     env,
+    sync::Arc,
+    time::Duration,
 };
 
-use anyhow::Result;
-use committable::Committable;
-use espresso_types::{FeeAccount, FeeMerkleTree, NamespaceId, NsProof, PubKey, Transaction};
-use futures::{try_join, FutureExt};
+use anyhow::{anyhow, Result};
+use committable::{Commitment, Committable};
+use espresso_types::{
+    parse_duration,
+    transaction_proof::BlockProof,
+    v0_3::{BidTx, ChainConfig},
+    EpochStakeTable, FeeAccount, FeeAccountProof, FeeAmount, FeeDeposit, FeeInfo, FeeMerkleTree,
+    Header, Leaf, NamespaceId, NsProof, Payload, PubKey, Transaction, TxProof, Upgrade,
+};
+use ethers::types::U256;
+use futures::{
+    future,
+    stream::{BoxStream, Stream},
+    try_join, FutureExt, StreamExt, TryFutureExt,
+};
 use hotshot_query_service::{
-    availability::{self, AvailabilityDataSource, CustomSnafu, FetchBlockSnafu},
+    availability::{
+        self, AvailabilityDataSource, BlockQueryData, CustomSnafu, FetchBlockSnafu, FetchLeafSnafu,
+        VidCommonQueryData,
+    },
     explorer::{self, ExplorerDataSource},
     merklized_state::{
         self, MerklizedState, MerklizedStateDataSource, MerklizedStateHeightPersistence,
     },
-    node, ApiState, Error,
+    node,
+    status::{self, StatusDataSource},
+    ApiState,
 };
 use hotshot_query_service::{merklized_state::Snapshot, node::NodeDataSource};
 use hotshot_types::{
     data::{EpochNumber, ViewNumber},
+    simple_certificate::QuorumCertificate,
     traits::{
         network::ConnectedNetwork,
         node_implementation::{ConsensusTime, Versions},
     },
+    vid::VidCommon,
 };
 use jf_merkle_tree::MerkleTreeScheme;
 use serde::{de::Error as _, Deserialize, Serialize};
 use snafu::OptionExt;
 use tagged_base64::TaggedBase64;
-use tide_disco::{method::ReadState, Api, Error as _, StatusCode};
-use vbs::version::{StaticVersion, StaticVersionType};
+use tide_disco::{method::ReadState, Api, Error as _, RequestParams, StatusCode, Url};
+use vbs::version::{StaticVersion, StaticVersionType, Version};
 
 use super::{
+    backfill::BackfillProgress,
+    cache::QueryCache,
+    catchup_cache::AccountProofCache,
+    catchup_limits::CatchupLimiter,
     data_source::{
-        CatchupDataSource, HotShotConfigDataSource, NodeStateDataSource, SequencerDataSource,
-        StakeTableDataSource, StateSignatureDataSource, SubmitDataSource,
+        BlockTimeDataSource, CatchupDataSource, ChainConfigHistoryDataSource, DaMirrorDataSource,
+        DecideEventDataSource, FeeAccountDataSource, FeeEstimateDataSource,
+        HotShotConfigDataSource, MempoolDataSource, NetworkHealthDataSource, NodeStateDataSource,
+        SequencerDataSource, SolverDataSource, StakeTableDataSource, StateSignatureDataSource,
+        SubmitDataSource, TaskStatusDataSource, TxStatusDataSource, UpgradeDataSource,
+        VersionDataSource, ViewLogDataSource,
     },
+    error::ApiError,
+    namespaces::NamespaceRegistry,
+    peers::QueryPeers,
+    rate_limit::SubmitRateLimiter,
+    stats::ExplorerStatsStorage,
+    telemetry::Telemetry,
+    tx_validation::TxValidator,
     StorageState,
 };
-use crate::{SeqTypes, SequencerApiVersion, SequencerPersistence};
+use crate::{tx_trace::TraceParent, SeqTypes, SequencerApiVersion, SequencerPersistence};
+
+/// The most views whose leaders can be requested from `node/leaders` at once.
+const MAX_LEADERS: u64 = 1000;
+
+/// The maximum number of view records which can be requested at once.
+const MAX_VIEW_RECORDS: u64 = 1000;
+
+/// The largest page of fee deposits served by `fee/deposits`.
+const MAX_FEE_DEPOSITS_PAGE: u64 = 100;
+
+/// The most decide events served by `events/replay` at once.
+const MAX_DECIDE_EVENTS: u64 = 100;
+
+/// The window over which `namespaces/namespace/:namespace/stats` reports throughput by default.
+const DEFAULT_THROUGHPUT_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The state of a fee account as of the latest decided block.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FeeAccountQueryData {
+    /// The height of the block whose fee state `balance` and `proof` are relative to.
+    pub height: u64,
+    /// The account balance, or 0 if the account has no entry in the fee state.
+    pub balance: U256,
+    /// Merkle (non-)membership proof for the account in the fee state at `height`.
+    pub proof: FeeAccountProof,
+    /// Deposits to the account which have been finalized on the L1 but are not yet included in the
+    /// fee state at `height`.
+    pub pending_deposits: Vec<FeeInfo>,
+}
+
+/// The builder holding the sequencing rights for a namespace in some view.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NamespaceReservation {
+    pub namespace: NamespaceId,
+    pub url: Url,
+}
+
+/// A page of the L1 deposits made to a fee account.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FeeDepositsQueryData {
+    /// The last L1 block indexed for deposits.
+    ///
+    /// Deposits made after this block are not yet included. This is `None` if indexing has not
+    /// started.
+    pub l1_block: Option<u64>,
+    /// Deposits to the account, in the order they were made on the L1.
+    pub deposits: Vec<FeeDeposit>,
+    /// The offset of the next page, if there may be more deposits.
+    pub next: Option<u64>,
+}
+
+/// An estimate of the fee required to sequence a transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeEstimate {
+    /// The size of the transaction payload, in bytes.
+    pub size: u64,
+    /// The minimum fee per byte, from the active chain config.
+    pub base_fee: FeeAmount,
+    /// The minimum fee a builder must pay to include the transaction, `base_fee * size`.
+    pub min_fee: FeeAmount,
+    /// The portion of the maximum block size used by the latest decided block, in basis points.
+    pub block_fullness: u64,
+    /// The expected fee.
+    ///
+    /// This is `min_fee` plus a premium proportional to `block_fullness`, up to double the minimum
+    /// fee when blocks are full, reflecting competition for block space.
+    pub fee: FeeAmount,
+}
+
+impl FeeEstimate {
+    /// Estimate the fee for a transaction of `size` bytes, given the active chain config and the
+    /// size of the latest decided block.
+    pub fn new(chain_config: &ChainConfig, block_size: u64, size: u64) -> Self {
+        let max_block_size = u64::from(chain_config.max_block_size).max(1);
+        let block_fullness = (block_size.min(max_block_size) * 10_000) / max_block_size;
+        let min_fee = chain_config.base_fee * size;
+        let premium = FeeAmount(min_fee.0 * block_fullness / 10_000u64);
+        Self {
+            size,
+            base_fee: chain_config.base_fee,
+            min_fee,
+            block_fullness,
+            fee: min_fee + premium,
+        }
+    }
+}
+
+/// The outcome of submitting one transaction in a batch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchSubmitResult {
+    /// The hash of the transaction.
+    pub hash: Commitment<Transaction>,
+    /// The reason the transaction was rejected, or `None` if it was accepted for sequencing.
+    pub error: Option<String>,
+}
+
+impl BatchSubmitResult {
+    /// Whether the transaction was accepted for sequencing.
+    pub fn is_accepted(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// A submitted transaction which has been included in a decided block.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecidedTransaction {
+    /// The hash of the transaction.
+    pub hash: Commitment<Transaction>,
+    /// Height of the block containing the transaction.
+    pub block: u64,
+    /// Position of the transaction within the block.
+    pub index: u64,
+}
+
+/// The API and protocol versions spoken by a node, so that clients can negotiate which to use.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionInfo {
+    /// Versions of binary serialization the API can respond with, preferred first.
+    ///
+    /// Modules are served with the first version at their usual paths (e.g. `submit`). During an
+    /// upgrade window, modules which depend on the serialization version are also served with the
+    /// upgraded version, at paths suffixed with that version (e.g. `submit-v0.3`).
+    pub api_versions: Vec<Version>,
+    /// The protocol version this node started with.
+    pub base_version: Version,
+    /// The protocol version this node is prepared to upgrade to, if any.
+    pub upgrade_version: Option<Version>,
+    /// The protocol version of the latest decided block.
+    pub current_version: Version,
+    /// The upgrade to `upgrade_version` scheduled in the genesis file, if any.
+    pub upgrade: Option<Upgrade>,
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NamespaceProofQueryData {
@@ -47,6 +214,90 @@ pub struct NamespaceProofQueryData {
     pub transactions: Vec<Transaction>,
 }
 
+impl NamespaceProofQueryData {
+    /// Extract the transactions in namespace `ns_id` of `payload`, along with a proof.
+    ///
+    /// If the namespace is not present in the block, the result has no proof and no transactions.
+    /// Returns [`None`] if the namespace is present but a proof could not be constructed.
+    pub fn new(payload: &Payload, common: &VidCommon, ns_id: NamespaceId) -> Option<Self> {
+        let Some(ns_index) = payload.ns_table().find_ns_id(&ns_id) else {
+            // ns_id not found in ns_table
+            return Some(Self {
+                proof: None,
+                transactions: Vec::new(),
+            });
+        };
+        let proof = NsProof::new(payload, &ns_index, common)?;
+        Some(Self {
+            transactions: proof.export_all_txs(&ns_id),
+            proof: Some(proof),
+        })
+    }
+}
+
+/// The proof for namespace `ns_id` in each of `blocks`, using the VID common data in `vid` for the
+/// same blocks.
+///
+/// If a proof cannot be constructed for some block, the stream yields an error for that block and
+/// then ends, so that a client never silently misses a block containing the namespace.
+pub(super) fn namespace_proofs(
+    blocks: impl Stream<Item = BlockQueryData<SeqTypes>> + Send + 'static,
+    vid: impl Stream<Item = VidCommonQueryData<SeqTypes>> + Send + 'static,
+    ns_id: NamespaceId,
+) -> BoxStream<'static, Result<NamespaceProofQueryData>> {
+    blocks
+        .zip(vid)
+        .map(move |(block, common)| {
+            NamespaceProofQueryData::new(block.payload(), common.common(), ns_id).ok_or_else(|| {
+                let height = block.height();
+                tracing::warn!(height, %ns_id, "failed to make proof for namespace");
+                anyhow!("failed to make proof for namespace {ns_id} in block {height}")
+            })
+        })
+        .scan(false, |failed, res| {
+            if *failed {
+                return future::ready(None);
+            }
+            *failed = res.is_err();
+            future::ready(Some(res))
+        })
+        .boxed()
+}
+
+/// A transaction along with a proof of its inclusion in the chain as of an anchor block.
+///
+/// This can be checked without trusting the server using
+/// [`verify_transaction_proof`](espresso_types::transaction_proof::verify_transaction_proof).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransactionProofQueryData {
+    pub transaction: Transaction,
+    /// The header of the block containing the transaction.
+    pub header: Header,
+    /// Proof of the transaction against the payload commitment in `header`.
+    pub proof: TxProof,
+    /// VID common data for the block containing the transaction, needed to check `proof`.
+    pub vid_common: VidCommon,
+    /// The header of the block the proof is relative to.
+    pub anchor: Header,
+    /// Proof that `header` is in the block Merkle tree of `anchor`.
+    ///
+    /// This is [`None`] if `anchor` is the block containing the transaction.
+    pub block_proof: Option<BlockProof>,
+}
+
+/// A decided leaf along with the data needed to check that it was decided by consensus.
+///
+/// This can be checked without trusting the server using
+/// [`verify_leaf_certificate`](espresso_types::leaf_proof::verify_leaf_certificate).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LeafCertificateQueryData {
+    pub leaf: Leaf,
+    /// The quorum certificate justifying `leaf`.
+    pub qc: QuorumCertificate<SeqTypes>,
+    /// The stake table of the epoch containing `leaf`, against which `qc` is signed.
+    pub stake_table: EpochStakeTable,
+}
+
 pub(super) fn get_balance<State, Ver>() -> Result<Api<State, merklized_state::Error, Ver>>
 where
     State: 'static + Send + Sync + ReadState,
@@ -90,6 +341,8 @@ type AvailabilityApi<N, P, D, V, ApiVer> = Api<AvailState<N, P, D, V>, availabil
 // Snafu has been replaced by `this_error` everywhere.
 // However, the query service still uses snafu
 pub(super) fn availability<N, P, D, V: Versions>(
+    cache: QueryCache,
+    max_range_size: Option<usize>,
 ) -> Result<AvailabilityApi<N, P, D, V, SequencerApiVersion>>
 where
     N: ConnectedNetwork<PubKey>,
@@ -97,6 +350,11 @@ where
     P: SequencerPersistence,
 {
     let mut options = availability::Options::default();
+    if let Some(max) = max_range_size {
+        // Larger ranges are split into pages before they reach the query service.
+        options.small_object_range_limit = max;
+        options.large_object_range_limit = max;
+    }
     let extension = toml::from_str(include_str!("../../api/availability.toml"))?;
     options.extensions.push(extension);
     let timeout = options.fetch_timeout;
@@ -106,59 +364,231 @@ where
         SequencerApiVersion::instance(),
     )?;
 
+    let namespace_cache = cache.clone();
     api.get("getnamespaceproof", move |req, state| {
+        let cache = namespace_cache.clone();
         async move {
             let height: usize = req.integer_param("height")?;
             let ns_id = NamespaceId::from(req.integer_param::<_, u32>("namespace")?);
-            let (block, common) = try_join!(
-                async move {
-                    state
-                        .get_block(height)
+            let fetch = try_join!(
+                async {
+                    cache
+                        .get_block(state, height, timeout)
                         .await
-                        .with_timeout(timeout)
+                        .context(FetchBlockSnafu {
+                            resource: height.to_string(),
+                        })
+                },
+                async {
+                    cache
+                        .get_vid_common(state, height, timeout)
                         .await
                         .context(FetchBlockSnafu {
                             resource: height.to_string(),
                         })
+                }
+            );
+            let (block, common) = match fetch {
+                Ok(res) => res,
+                Err(err) => return Err(payload_error(state.inner(), height as u64, err).await),
+            };
+
+            NamespaceProofQueryData::new(block.payload(), common.common(), ns_id).context(
+                CustomSnafu {
+                    message: format!("failed to make proof for namespace {ns_id}"),
+                    status: StatusCode::NOT_FOUND,
                 },
-                async move {
-                    state
-                        .get_vid_common(height)
+            )
+        }
+        .boxed()
+    })?
+    .get("getleafcertificate", move |req, state| {
+        async move {
+            let height: u64 = req.integer_param("height")?;
+            let leaf = state
+                .get_leaf(height as usize)
+                .await
+                .with_timeout(timeout)
+                .await
+                .context(FetchLeafSnafu {
+                    resource: height.to_string(),
+                })?;
+            let stake_table = state.inner().get_stake_table_for_height(height).await;
+            Ok(LeafCertificateQueryData {
+                leaf: leaf.leaf().clone(),
+                qc: leaf.qc().clone(),
+                stake_table,
+            })
+        }
+        .boxed()
+    })?
+    .get("gettransactionproof", move |req, state| {
+        let cache = cache.clone();
+        async move {
+            let hash: Commitment<Transaction> = req.blob_param("hash")?;
+            let anchor: Option<u64> = req.opt_integer_param("anchor")?;
+            let height = state
+                .get_transaction(hash)
+                .await
+                .with_timeout(timeout)
+                .await
+                .context(CustomSnafu {
+                    message: format!("transaction {hash} not found"),
+                    status: StatusCode::NOT_FOUND,
+                })?
+                .block_height();
+            let fetch = try_join!(
+                async {
+                    cache
+                        .get_block(state, height as usize, timeout)
                         .await
-                        .with_timeout(timeout)
+                        .context(FetchBlockSnafu {
+                            resource: height.to_string(),
+                        })
+                },
+                async {
+                    cache
+                        .get_vid_common(state, height as usize, timeout)
                         .await
                         .context(FetchBlockSnafu {
                             resource: height.to_string(),
                         })
                 }
-            )?;
-
-            if let Some(ns_index) = block.payload().ns_table().find_ns_id(&ns_id) {
-                let proof = NsProof::new(block.payload(), &ns_index, common.common()).context(
-                    CustomSnafu {
-                        message: format!("failed to make proof for namespace {ns_id}"),
-                        status: StatusCode::NOT_FOUND,
-                    },
-                )?;
-
-                Ok(NamespaceProofQueryData {
-                    transactions: proof.export_all_txs(&ns_id),
-                    proof: Some(proof),
+            );
+            let (block, common) = match fetch {
+                Ok(res) => res,
+                Err(err) => return Err(payload_error(state.inner(), height, err).await),
+            };
+
+            let (transaction, proof) = block
+                .enumerate()
+                .find(|(_, tx)| tx.commit() == hash)
+                .and_then(|(index, _)| TxProof::new(&index, block.payload(), common.common()))
+                .context(CustomSnafu {
+                    message: format!("failed to make proof for transaction {hash}"),
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                })?;
+
+            let (anchor, block_proof) = match anchor {
+                None => (block.header().clone(), None),
+                Some(anchor) if anchor == height => (block.header().clone(), None),
+                Some(anchor) if anchor < height => {
+                    return Err(availability::Error::Custom {
+                        message: format!(
+                            "anchor block {anchor} is older than block {height} containing the \
+                             transaction"
+                        ),
+                        status: StatusCode::BAD_REQUEST,
+                    });
+                }
+                Some(anchor) => {
+                    let leaf = state
+                        .get_leaf(anchor as usize)
+                        .await
+                        .with_timeout(timeout)
+                        .await
+                        .context(FetchLeafSnafu {
+                            resource: anchor.to_string(),
+                        })?;
+                    let block_proof = state
+                        .inner()
+                        .get_block_proof(height, anchor)
+                        .await
+                        .map_err(|err| availability::Error::Custom {
+                            message: format!("{err:#}"),
+                            status: StatusCode::NOT_FOUND,
+                        })?
+                        .context(CustomSnafu {
+                            message: "this node does not store merklized state",
+                            status: StatusCode::NOT_IMPLEMENTED,
+                        })?;
+                    (leaf.header().clone(), Some(block_proof))
+                }
+            };
+
+            Ok(TransactionProofQueryData {
+                transaction,
+                header: block.header().clone(),
+                proof,
+                vid_common: common.common().clone(),
+                anchor,
+                block_proof,
+            })
+        }
+        .boxed()
+    })?
+    .get("getdapointer", move |req, state| {
+        async move {
+            let height: u64 = req.integer_param("height")?;
+            state
+                .inner()
+                .get_da_pointer(height)
+                .await
+                .map_err(|err| availability::Error::Custom {
+                    message: format!("failed to load DA pointer: {err:#}"),
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                })?
+                .context(CustomSnafu {
+                    message: format!(
+                        "block {height} has not been mirrored to an external DA layer"
+                    ),
+                    status: StatusCode::NOT_FOUND,
                 })
-            } else {
-                // ns_id not found in ns_table
-                Ok(NamespaceProofQueryData {
-                    proof: None,
-                    transactions: Vec::new(),
+        }
+        .boxed()
+    })?
+    .stream("streamnamespace", move |req, state| {
+        async move {
+            let ns_id = NamespaceId::from(req.integer_param::<_, u32>("namespace")?);
+            let from = req.opt_integer_param("height")?;
+            state
+                .read(|state| {
+                    async move {
+                        // If no starting height is given, only stream blocks decided from now on.
+                        let from = match from {
+                            Some(from) => from,
+                            None => NodeDataSource::<SeqTypes>::block_height(state)
+                                .await
+                                .map_err(|err| availability::Error::Custom {
+                                    message: format!("failed to get block height: {err}"),
+                                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                                })?,
+                        };
+                        Ok(state.subscribe_namespace(from, ns_id).await.map(|res| {
+                            res.map_err(|err| availability::Error::Custom {
+                                message: format!("{err:#}"),
+                                status: StatusCode::INTERNAL_SERVER_ERROR,
+                            })
+                        }))
+                    }
+                    .boxed()
                 })
-            }
+                .await
         }
+        .try_flatten_stream()
         .boxed()
     })?;
 
     Ok(api)
 }
 
+/// Report a block whose payload could not be fetched as pruned, if it is below the pruned height.
+///
+/// Otherwise, the payload may still be fetched from a peer later, so the original error is kept.
+async fn payload_error<D: SequencerDataSource + Sync>(
+    ds: &D,
+    height: u64,
+    err: availability::Error,
+) -> availability::Error {
+    match ds.pruned_height().await {
+        Ok(Some(pruned)) if height < pruned => availability::Error::Custom {
+            message: format!("payload for block {height} has been pruned"),
+            status: StatusCode::GONE,
+        },
+        _ => err,
+    }
+}
+
 type ExplorerApi<N, P, D, V, ApiVer> = Api<AvailState<N, P, D, V>, explorer::Error, ApiVer>;
 
 pub(super) fn explorer<N, P, D, V: Versions>(
@@ -174,11 +604,169 @@ where
     Ok(api)
 }
 
+pub(super) fn explorer_stats<S, ApiVer: StaticVersionType + 'static>(
+    windows: Vec<Duration>,
+    registry: NamespaceRegistry,
+    _: ApiVer,
+) -> Result<Api<S, ApiError, ApiVer>>
+where
+    S: 'static + Send + Sync + ReadState,
+    S::State: Send + Sync + ExplorerStatsStorage,
+{
+    let toml = toml::from_str::<toml::Value>(include_str!("../../api/explorer_stats.toml"))?;
+    let mut api = Api::<S, ApiError, ApiVer>::new(toml)?;
+
+    api.get("summary", move |_, state| {
+        let windows = windows.clone();
+        let registry = registry.clone();
+        async move {
+            let mut summary = state
+                .explorer_summary(&windows)
+                .await
+                .map_err(|err| ApiError::Internal(format!("{err:#}")))?;
+            for ns in summary
+                .windows
+                .iter_mut()
+                .flat_map(|window| &mut window.namespaces)
+            {
+                ns.name = registry.name(ns.namespace);
+            }
+            Ok(summary)
+        }
+        .boxed()
+    })?;
+
+    Ok(api)
+}
+
+pub(super) fn namespaces<S, ApiVer: StaticVersionType + 'static>(
+    registry: NamespaceRegistry,
+    _: ApiVer,
+) -> Result<Api<S, ApiError, ApiVer>>
+where
+    S: 'static + Send + Sync + ReadState,
+    S::State: Send + Sync + ExplorerStatsStorage,
+{
+    let toml = toml::from_str::<toml::Value>(include_str!("../../api/namespaces.toml"))?;
+    let mut api = Api::<S, ApiError, ApiVer>::new(toml)?;
+
+    let list_registry = registry.clone();
+    let stats_registry = registry.clone();
+    api.get("list", move |_, state| {
+        let registry = list_registry.clone();
+        async move {
+            let stats = state
+                .namespace_stats()
+                .await
+                .map_err(|err| ApiError::Internal(format!("{err:#}")))?;
+            Ok(registry.list(stats))
+        }
+        .boxed()
+    })?
+    .get("namespace", move |req, state| {
+        let registry = registry.clone();
+        async move {
+            let namespace = NamespaceId::from(
+                req.integer_param::<_, u32>("namespace")
+                    .map_err(ApiError::from_request_error)?,
+            );
+            let stats = state
+                .namespace_stats()
+                .await
+                .map_err(|err| ApiError::Internal(format!("{err:#}")))?;
+            registry
+                .list(stats)
+                .into_iter()
+                .find(|info| info.namespace == namespace)
+                .ok_or_else(|| ApiError::NotFound(format!("unknown namespace {namespace}")))
+        }
+        .boxed()
+    })?
+    .get("stats", move |req, state| {
+        let registry = stats_registry.clone();
+        async move {
+            let namespace = NamespaceId::from(
+                req.integer_param::<_, u32>("namespace")
+                    .map_err(ApiError::from_request_error)?,
+            );
+            let window = match req
+                .opt_string_param("window")
+                .map_err(ApiError::from_request_error)?
+            {
+                Some(window) => parse_duration(&window)
+                    .map_err(|err| ApiError::BadRequest(format!("invalid window: {err}")))?,
+                None => DEFAULT_THROUGHPUT_WINDOW,
+            };
+            if window.is_zero() {
+                return Err(ApiError::BadRequest("window must not be empty".into()));
+            }
+            let mut throughput = state
+                .namespace_throughput(namespace, window)
+                .await
+                .map_err(|err| ApiError::Internal(format!("{err:#}")))?;
+            throughput.name = registry.name(namespace);
+            Ok(throughput)
+        }
+        .boxed()
+    })?;
+
+    Ok(api)
+}
+
+pub(super) fn status<S, ApiVer: StaticVersionType + 'static>(
+    backfill: Option<BackfillProgress>,
+    telemetry: Telemetry,
+    bind_version: ApiVer,
+) -> Result<Api<S, status::Error, ApiVer>>
+where
+    S: 'static + Send + Sync + ReadState,
+    S::State: Send
+        + Sync
+        + StatusDataSource
+        + VersionDataSource
+        + NetworkHealthDataSource
+        + TaskStatusDataSource,
+{
+    // Extend the base API
+    let mut options = status::Options::default();
+    let extension = toml::from_str(include_str!("../../api/status.toml"))?;
+    options.extensions.push(extension);
+
+    // Create the base API with our extensions
+    let mut api = status::define_api::<S, ApiVer>(&options, bind_version)?;
+
+    // Tack on the application logic
+    api.get("backfill", move |_, _| {
+        let status = backfill.as_ref().map(BackfillProgress::status);
+        async move { Ok(status) }.boxed()
+    })?
+    .get("version", |_, state| {
+        async move { Ok(state.version_info().await) }.boxed()
+    })?
+    .get("node_identity", move |_, _| {
+        let identity = telemetry.identity();
+        async move { Ok(identity) }.boxed()
+    })?
+    .get("network", |_, state| {
+        async move { Ok(state.network_status().await) }.boxed()
+    })?
+    .get("tasks", |_, state| {
+        async move { Ok(state.task_status().await) }.boxed()
+    })?;
+
+    Ok(api)
+}
+
 pub(super) fn node<S>() -> Result<Api<S, node::Error, StaticVersion<0, 1>>>
 where
     S: 'static + Send + Sync + ReadState,
-    <S as ReadState>::State:
-        Send + Sync + StakeTableDataSource<SeqTypes> + NodeDataSource<SeqTypes>,
+    <S as ReadState>::State: Send
+        + Sync
+        + StakeTableDataSource
+        + BlockTimeDataSource
+        + LeaderScheduleDataSource
+        + ViewLogDataSource
+        + NodeDataSource<SeqTypes>,
 {
     // Extend the base API
     let mut options = node::Options::default();
@@ -213,34 +801,356 @@ where
                 .await)
         }
         .boxed()
+    })?
+    .at("block_by_time", |req, state| {
+        async move {
+            let timestamp: u64 = req.integer_param("timestamp")?;
+            state
+                .read(|state| state.get_block_by_time(timestamp).boxed())
+                .await
+                .map_err(|err| node::Error::Custom {
+                    message: format!("{err:#}"),
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                })?
+                .ok_or_else(|| node::Error::Custom {
+                    message: format!("no block was decided at or before time {timestamp}"),
+                    status: StatusCode::NOT_FOUND,
+                })
+        }
+        .boxed()
+    })?
+    .at("leaders", |req, state| {
+        async move {
+            let from_view = req.opt_integer_param("from_view")?;
+            let count = req.opt_integer_param("count")?.unwrap_or(100);
+            if count > MAX_LEADERS {
+                return Err(node::Error::Custom {
+                    message: format!("cannot request more than {MAX_LEADERS} leaders at once"),
+                    status: StatusCode::BAD_REQUEST,
+                });
+            }
+            state
+                .read(|state| state.get_leaders(from_view, count).boxed())
+                .await
+                .map_err(|err| node::Error::Custom {
+                    message: format!("{err:#}"),
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                })
+        }
+        .boxed()
+    })?
+    .at("view_log", |req, state| {
+        async move {
+            let view: u64 = req.integer_param("view")?;
+            state
+                .read(|state| state.get_view_records(view, view + 1).boxed())
+                .await
+                .map_err(|err| node::Error::Custom {
+                    message: format!("{err:#}"),
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                })?
+                .pop()
+                .ok_or_else(|| node::Error::Custom {
+                    message: format!("no record of view {view}"),
+                    status: StatusCode::NOT_FOUND,
+                })
+        }
+        .boxed()
+    })?
+    .at("view_log_range", |req, state| {
+        async move {
+            let from: u64 = req.integer_param("from")?;
+            let until: u64 = req.integer_param("until")?;
+            if until.saturating_sub(from) > MAX_VIEW_RECORDS {
+                return Err(node::Error::Custom {
+                    message: format!("cannot request more than {MAX_VIEW_RECORDS} views at once"),
+                    status: StatusCode::BAD_REQUEST,
+                });
+            }
+            state
+                .read(|state| state.get_view_records(from, until).boxed())
+                .await
+                .map_err(|err| node::Error::Custom {
+                    message: format!("{err:#}"),
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                })
+        }
+        .boxed()
     })?;
 
     Ok(api)
 }
-pub(super) fn submit<N, P, S, ApiVer: StaticVersionType + 'static>() -> Result<Api<S, Error, ApiVer>>
+
+/// The trace context a transaction was submitted with, from the W3C `traceparent` header.
+///
+/// A malformed header is ignored, as if the request had no trace context.
+fn trace_parent(req: &RequestParams) -> Option<TraceParent> {
+    req.headers()
+        .get("traceparent")?
+        .last()
+        .as_str()
+        .parse()
+        .ok()
+}
+
+/// How long to wait for a transaction to be decided, given the timeout requested by a client.
+///
+/// Clients may ask to wait for less than the server's limit, `max`, but never for more.
+fn clamp_wait_timeout(requested_secs: Option<u64>, max: Duration) -> Duration {
+    requested_secs.map_or(max, |secs| max.min(Duration::from_secs(secs)))
+}
+
+pub(super) fn submit<N, P, S, ApiVer: StaticVersionType + 'static>(
+    limiter: Arc<SubmitRateLimiter>,
+    validator: TxValidator,
+    wait_timeout: Duration,
+    max_batch_count: usize,
+) -> Result<Api<S, ApiError, ApiVer>>
 where
     N: ConnectedNetwork<PubKey>,
     S: 'static + Send + Sync + ReadState,
     P: SequencerPersistence,
-    S::State: Send + Sync + SubmitDataSource<N, P>,
+    S::State: Send
+        + Sync
+        + SubmitDataSource<N, P>
+        + TxStatusDataSource
+        + MempoolDataSource
+        + FeeEstimateDataSource,
 {
     let toml = toml::from_str::<toml::Value>(include_str!("../../api/submit.toml"))?;
-    let mut api = Api::<S, Error, ApiVer>::new(toml)?;
+    let mut api = Api::<S, ApiError, ApiVer>::new(toml)?;
 
-    api.at("submit", |req, state| {
+    let validator = Arc::new(validator);
+    let batch_limiter = limiter.clone();
+    let wait_limiter = limiter.clone();
+    let batch_validator = validator.clone();
+    let wait_validator = validator.clone();
+    api.at("submit", move |req, state| {
+        let limiter = limiter.clone();
+        let validator = validator.clone();
         async move {
             let tx = req
                 .body_auto::<Transaction, ApiVer>(ApiVer::instance())
-                .map_err(Error::from_request_error)?;
+                .map_err(ApiError::from_request_error)?;
+
+            let ns = tx.namespace();
+            if !limiter.check(ns) {
+                return Err(ApiError::RateLimited(format!(
+                    "rate limit exceeded for namespace {ns}"
+                )));
+            }
+
+            let cf = state
+                .read(|state| state.active_chain_config().boxed())
+                .await;
+            validator.validate(&cf, &tx)?;
 
             let hash = tx.commit();
+            let parent = trace_parent(&req);
+            state
+                .read(|state| state.trace_transaction(hash, ns, parent).boxed())
+                .await;
             state
                 .read(|state| state.submit(tx).boxed())
                 .await
-                .map_err(|err| Error::internal(err.to_string()))?;
+                .map_err(|err| ApiError::Internal(err.to_string()))?;
             Ok(hash)
         }
         .boxed()
+    })?
+    .at("submit_and_wait", move |req, state| {
+        let limiter = wait_limiter.clone();
+        let validator = wait_validator.clone();
+        async move {
+            let tx = req
+                .body_auto::<Transaction, ApiVer>(ApiVer::instance())
+                .map_err(ApiError::from_request_error)?;
+            let secs: Option<u64> = req
+                .opt_integer_param("timeout")
+                .map_err(ApiError::from_request_error)?;
+            let timeout = clamp_wait_timeout(secs, wait_timeout);
+
+            let ns = tx.namespace();
+            if !limiter.check(ns) {
+                return Err(ApiError::RateLimited(format!(
+                    "rate limit exceeded for namespace {ns}"
+                )));
+            }
+
+            let cf = state
+                .read(|state| state.active_chain_config().boxed())
+                .await;
+            validator.validate(&cf, &tx)?;
+
+            let hash = tx.commit();
+            let parent = trace_parent(&req);
+            state
+                .read(|state| state.trace_transaction(hash, ns, parent).boxed())
+                .await;
+            state
+                .read(|state| state.submit_and_wait(tx, timeout).boxed())
+                .await
+                .map_err(|err| ApiError::Internal(format!("{err:#}")))?
+                .ok_or_else(|| {
+                    ApiError::Timeout(format!(
+                        "transaction {hash} was not decided within {timeout:?}"
+                    ))
+                })
+        }
+        .boxed()
+    })?
+    .at("batch", move |req, state| {
+        let limiter = batch_limiter.clone();
+        let validator = batch_validator.clone();
+        async move {
+            let txs = req
+                .body_auto::<Vec<Transaction>, ApiVer>(ApiVer::instance())
+                .map_err(ApiError::from_request_error)?;
+            if txs.len() > max_batch_count {
+                return Err(ApiError::BadRequest(format!(
+                    "batch of {} transactions exceeds the maximum of {max_batch_count}",
+                    txs.len()
+                )));
+            }
+            let cf = state
+                .read(|state| state.active_chain_config().boxed())
+                .await;
+
+            // Transactions which are over their namespace's rate limit or invalid are rejected
+            // without being forwarded; the rest are submitted together. As with single submissions,
+            // every transaction is charged to the rate limit before it is validated, so a batch
+            // cannot make the node do more work than the same transactions submitted one by one.
+            let mut results = vec![];
+            let mut forwarded = vec![];
+            for tx in txs {
+                let ns = tx.namespace();
+                let error = if !limiter.check(ns) {
+                    Some(format!("rate limit exceeded for namespace {ns}"))
+                } else if let Err(err) = validator.validate(&cf, &tx) {
+                    Some(err.message().to_string())
+                } else {
+                    forwarded.push((results.len(), tx.clone()));
+                    None
+                };
+                results.push(BatchSubmitResult {
+                    hash: tx.commit(),
+                    error,
+                });
+            }
+
+            // Every transaction in the batch is traced under the trace context of the request.
+            let (indices, txs): (Vec<_>, Vec<_>) = forwarded.into_iter().unzip();
+            let parent = trace_parent(&req);
+            for tx in &txs {
+                let (hash, ns) = (tx.commit(), tx.namespace());
+                state
+                    .read(|state| state.trace_transaction(hash, ns, parent).boxed())
+                    .await;
+            }
+            let outcomes = state.read(|state| state.submit_batch(txs).boxed()).await;
+            for (i, outcome) in indices.into_iter().zip(outcomes) {
+                results[i].error = outcome.err().map(|err| format!("{err:#}"));
+            }
+            Ok(results)
+        }
+        .boxed()
+    })?
+    .get("status", |req, state| {
+        async move {
+            let hash = req
+                .blob_param("hash")
+                .map_err(ApiError::from_request_error)?;
+            state
+                .get_tx_status(hash)
+                .await
+                .map_err(|err| ApiError::Internal(format!("{err:#}")))?
+                .ok_or_else(|| ApiError::NotFound(format!("unknown transaction {hash}")))
+        }
+        .boxed()
+    })?
+    .get("fee_estimate", |req, state| {
+        async move {
+            let size = req
+                .integer_param("size")
+                .map_err(ApiError::from_request_error)?;
+            state
+                .estimate_fee(size)
+                .await
+                .map_err(|err| ApiError::Internal(format!("{err:#}")))
+        }
+        .boxed()
+    })?
+    .get("pending", |_, state| {
+        async move { Ok(state.pending_transactions().await) }.boxed()
+    })?;
+
+    Ok(api)
+}
+
+pub(super) fn submit_admin<S, ApiVer: StaticVersionType + 'static>(
+    _: ApiVer,
+) -> Result<Api<S, ApiError, ApiVer>>
+where
+    S: 'static + Send + Sync + ReadState,
+    S::State: Send + Sync + MempoolDataSource,
+{
+    let toml = toml::from_str::<toml::Value>(include_str!("../../api/submit_admin.toml"))?;
+    let mut api = Api::<S, ApiError, ApiVer>::new(toml)?;
+
+    api.at("evict", |req, state| {
+        async move {
+            let hash = req
+                .blob_param("hash")
+                .map_err(ApiError::from_request_error)?;
+            if state
+                .evict_pending_transaction(hash)
+                .await
+                .map_err(|err| ApiError::Internal(format!("{err:#}")))?
+            {
+                Ok(())
+            } else {
+                Err(ApiError::NotFound(format!(
+                    "transaction {hash} is not pending"
+                )))
+            }
+        }
+        .boxed()
+    })?;
+
+    Ok(api)
+}
+
+pub(super) fn events<S, ApiVer: StaticVersionType + 'static>(
+    _: ApiVer,
+) -> Result<Api<S, ApiError, ApiVer>>
+where
+    S: 'static + Send + Sync + ReadState,
+    S::State: Send + Sync + DecideEventDataSource,
+{
+    let toml = toml::from_str::<toml::Value>(include_str!("../../api/events.toml"))?;
+    let mut api = Api::<S, ApiError, ApiVer>::new(toml)?;
+
+    api.get("replay", |req, state| {
+        async move {
+            let from = req
+                .integer_param("from_height")
+                .map_err(ApiError::from_request_error)?;
+            let limit = req
+                .opt_integer_param("limit")
+                .map_err(ApiError::from_request_error)?
+                .unwrap_or(MAX_DECIDE_EVENTS);
+            if limit == 0 || limit > MAX_DECIDE_EVENTS {
+                return Err(ApiError::BadRequest(format!(
+                    "limit must be between 1 and {MAX_DECIDE_EVENTS}"
+                )));
+            }
+
+            state
+                .get_decide_events(from, limit as usize)
+                .await
+                .map_err(|err| ApiError::Internal(format!("{err:#}")))
+        }
+        .boxed()
     })?;
 
     Ok(api)
@@ -248,27 +1158,36 @@ where
 
 pub(super) fn state_signature<N, S, ApiVer: StaticVersionType + 'static>(
     _: ApiVer,
-) -> Result<Api<S, Error, ApiVer>>
+) -> Result<Api<S, ApiError, ApiVer>>
 where
     N: ConnectedNetwork<PubKey>,
     S: 'static + Send + Sync + ReadState,
     S::State: Send + Sync + StateSignatureDataSource<N>,
 {
     let toml = toml::from_str::<toml::Value>(include_str!("../../api/state_signature.toml"))?;
-    let mut api = Api::<S, Error, ApiVer>::new(toml)?;
+    let mut api = Api::<S, ApiError, ApiVer>::new(toml)?;
 
     api.get("get_state_signature", |req, state| {
         async move {
             let height = req
                 .integer_param("height")
-                .map_err(Error::from_request_error)?;
+                .map_err(ApiError::from_request_error)?;
             state
                 .get_state_signature(height)
                 .await
-                .ok_or(tide_disco::Error::catch_all(
-                    StatusCode::NOT_FOUND,
-                    "Signature not found.".to_owned(),
-                ))
+                .ok_or(ApiError::NotFound("Signature not found.".to_owned()))
+        }
+        .boxed()
+    })?
+    .get("get_state_signature_bundle", |req, state| {
+        async move {
+            let height = req
+                .integer_param("height")
+                .map_err(ApiError::from_request_error)?;
+            state
+                .get_aggregate(height)
+                .await
+                .ok_or(ApiError::NotFound("Signature bundle not found.".to_owned()))
         }
         .boxed()
     })?;
@@ -277,57 +1196,71 @@ where
 }
 
 pub(super) fn catchup<S, ApiVer: StaticVersionType + 'static>(
+    limiter: Arc<CatchupLimiter>,
+    cache: Arc<AccountProofCache>,
     _: ApiVer,
-) -> Result<Api<S, Error, ApiVer>>
+) -> Result<Api<S, ApiError, ApiVer>>
 where
     S: 'static + Send + Sync + ReadState,
     S::State: Send + Sync + NodeStateDataSource + CatchupDataSource,
 {
     let toml = toml::from_str::<toml::Value>(include_str!("../../api/catchup.toml"))?;
-    let mut api = Api::<S, Error, ApiVer>::new(toml)?;
+    let mut api = Api::<S, ApiError, ApiVer>::new(toml)?;
 
-    api.get("account", |req, state| {
+    // Each kind of request waits for a worker from its own pool. Requests for state at greater
+    // heights are served first, since they come from peers catching up to the head of the chain.
+    let account_pool = limiter.accounts.clone();
+    let accounts_pool = limiter.accounts.clone();
+    let frontier_pool = limiter.frontier.clone();
+    let chain_config_pool = limiter.chain_config.clone();
+
+    api.get("account", move |req, state| {
+        let pool = account_pool.clone();
+        let cache = cache.clone();
         async move {
             let height = req
                 .integer_param("height")
-                .map_err(Error::from_request_error)?;
+                .map_err(ApiError::from_request_error)?;
             let view = req
                 .integer_param("view")
-                .map_err(Error::from_request_error)?;
+                .map_err(ApiError::from_request_error)?;
             let account = req
                 .string_param("address")
-                .map_err(Error::from_request_error)?;
+                .map_err(ApiError::from_request_error)?;
             let account = account.parse().map_err(|err| {
-                Error::catch_all(
-                    StatusCode::BAD_REQUEST,
-                    format!("malformed account {account}: {err}"),
-                )
+                ApiError::BadRequest(format!("malformed account {account}: {err}"))
             })?;
+            let view = ViewNumber::new(view);
 
-            state
-                .get_account(
-                    state.node_state().await,
-                    height,
-                    ViewNumber::new(view),
-                    account,
-                )
+            // Cached proofs are served without waiting for a worker.
+            if let Some(data) = cache.get(height, view, account) {
+                return Ok(data);
+            }
+
+            let _permit = pool.acquire(height).await?;
+            let data = state
+                .get_account(state.node_state().await, height, view, account)
                 .await
-                .map_err(|err| Error::catch_all(StatusCode::NOT_FOUND, format!("{err:#}")))
+                .map_err(|err| ApiError::NotFound(format!("{err:#}")))?;
+            cache.insert(height, view, account, data.clone());
+            Ok(data)
         }
         .boxed()
     })?
-    .at("accounts", |req, state| {
+    .at("accounts", move |req, state| {
+        let pool = accounts_pool.clone();
         async move {
             let height = req
                 .integer_param("height")
-                .map_err(Error::from_request_error)?;
+                .map_err(ApiError::from_request_error)?;
             let view = req
                 .integer_param("view")
-                .map_err(Error::from_request_error)?;
+                .map_err(ApiError::from_request_error)?;
             let accounts = req
                 .body_auto::<Vec<FeeAccount>, ApiVer>(ApiVer::instance())
-                .map_err(Error::from_request_error)?;
+                .map_err(ApiError::from_request_error)?;
 
+            let _permit = pool.acquire(height).await?;
             state
                 .read(|state| {
                     async move {
@@ -339,9 +1272,7 @@ where
                                 &accounts,
                             )
                             .await
-                            .map_err(|err| {
-                                Error::catch_all(StatusCode::NOT_FOUND, format!("{err:#}"))
-                            })
+                            .map_err(|err| ApiError::NotFound(format!("{err:#}")))
                     }
                     .boxed()
                 })
@@ -349,32 +1280,36 @@ where
         }
         .boxed()
     })?
-    .get("blocks", |req, state| {
+    .get("blocks", move |req, state| {
+        let pool = frontier_pool.clone();
         async move {
             let height = req
                 .integer_param("height")
-                .map_err(Error::from_request_error)?;
+                .map_err(ApiError::from_request_error)?;
             let view = req
                 .integer_param("view")
-                .map_err(Error::from_request_error)?;
+                .map_err(ApiError::from_request_error)?;
 
+            let _permit = pool.acquire(height).await?;
             state
                 .get_frontier(state.node_state().await, height, ViewNumber::new(view))
                 .await
-                .map_err(|err| Error::catch_all(StatusCode::NOT_FOUND, format!("{err:#}")))
+                .map_err(|err| ApiError::NotFound(format!("{err:#}")))
         }
         .boxed()
     })?
-    .get("chainconfig", |req, state| {
+    .get("chainconfig", move |req, state| {
+        let pool = chain_config_pool.clone();
         async move {
             let commitment = req
                 .blob_param("commitment")
-                .map_err(Error::from_request_error)?;
+                .map_err(ApiError::from_request_error)?;
 
+            let _permit = pool.acquire(0).await?;
             state
                 .get_chain_config(commitment)
                 .await
-                .map_err(|err| Error::catch_all(StatusCode::NOT_FOUND, format!("{err:#}")))
+                .map_err(|err| ApiError::NotFound(format!("{err:#}")))
         }
         .boxed()
     })?;
@@ -407,28 +1342,231 @@ where
     Ok(api)
 }
 
+pub(super) fn fee<S, ApiVer: StaticVersionType + 'static>(
+    _: ApiVer,
+) -> Result<Api<S, ApiError, ApiVer>>
+where
+    S: 'static + Send + Sync + ReadState,
+    S::State: Send + Sync + FeeAccountDataSource,
+{
+    let toml = toml::from_str::<toml::Value>(include_str!("../../api/fee.toml"))?;
+    let mut api = Api::<S, ApiError, ApiVer>::new(toml)?;
+
+    api.get("account", |req, state| {
+        async move {
+            let account = req
+                .string_param("address")
+                .map_err(ApiError::from_request_error)?;
+            let account = account.parse().map_err(|err| {
+                ApiError::BadRequest(format!("malformed account {account}: {err}"))
+            })?;
+
+            let height = req
+                .opt_integer_param("height")
+                .map_err(ApiError::from_request_error)?;
+
+            state
+                .get_fee_account(account, height)
+                .await
+                .map_err(|err| ApiError::NotFound(format!("{err:#}")))
+        }
+        .boxed()
+    })?
+    .get("deposits", |req, state| {
+        async move {
+            let account = req
+                .string_param("address")
+                .map_err(ApiError::from_request_error)?;
+            let account = account.parse().map_err(|err| {
+                ApiError::BadRequest(format!("malformed account {account}: {err}"))
+            })?;
+
+            let offset = req
+                .opt_integer_param("offset")
+                .map_err(ApiError::from_request_error)?
+                .unwrap_or(0);
+            let limit = req
+                .opt_integer_param("limit")
+                .map_err(ApiError::from_request_error)?
+                .unwrap_or(MAX_FEE_DEPOSITS_PAGE);
+            if limit == 0 || limit > MAX_FEE_DEPOSITS_PAGE {
+                return Err(ApiError::BadRequest(format!(
+                    "limit must be between 1 and {MAX_FEE_DEPOSITS_PAGE}"
+                )));
+            }
+
+            state
+                .get_fee_deposits(account, offset, limit)
+                .await
+                .map_err(|err| ApiError::Internal(format!("{err:#}")))
+        }
+        .boxed()
+    })?
+    .get("builder_earnings", |req, state| {
+        async move {
+            let account = req
+                .string_param("address")
+                .map_err(ApiError::from_request_error)?;
+            let account = account.parse().map_err(|err| {
+                ApiError::BadRequest(format!("malformed account {account}: {err}"))
+            })?;
+
+            state
+                .get_builder_earnings(account)
+                .await
+                .map_err(|err| ApiError::Internal(format!("{err:#}")))
+        }
+        .boxed()
+    })?;
+
+    Ok(api)
+}
+
+pub(super) fn peers<S, ApiVer: StaticVersionType + 'static>(
+    _: ApiVer,
+) -> Result<Api<S, ApiError, ApiVer>>
+where
+    S: 'static + Send + Sync + ReadState,
+    S::State: Send + Sync + AsRef<QueryPeers>,
+{
+    let toml = toml::from_str::<toml::Value>(include_str!("../../api/peers.toml"))?;
+    let mut api = Api::<S, ApiError, ApiVer>::new(toml)?;
+
+    api.get("list", |_, state| {
+        async move { Ok(state.as_ref().list().await) }.boxed()
+    })?
+    .at("add", |req, state| {
+        async move {
+            let url = req
+                .body_auto::<Url, ApiVer>(ApiVer::instance())
+                .map_err(ApiError::from_request_error)?;
+            Ok(state.as_ref().add(url).await)
+        }
+        .boxed()
+    })?
+    .at("remove", |req, state| {
+        async move {
+            let url = req
+                .body_auto::<Url, ApiVer>(ApiVer::instance())
+                .map_err(ApiError::from_request_error)?;
+            Ok(state.as_ref().remove(&url).await)
+        }
+        .boxed()
+    })?;
+
+    Ok(api)
+}
+
 pub(super) fn config<S, ApiVer: StaticVersionType + 'static>(
     _: ApiVer,
-) -> Result<Api<S, Error, ApiVer>>
+) -> Result<Api<S, ApiError, ApiVer>>
 where
     S: 'static + Send + Sync + ReadState,
-    S::State: Send + Sync + HotShotConfigDataSource,
+    S::State:
+        Send + Sync + HotShotConfigDataSource + ChainConfigHistoryDataSource + UpgradeDataSource,
 {
     let toml = toml::from_str::<toml::Value>(include_str!("../../api/config.toml"))?;
-    let mut api = Api::<S, Error, ApiVer>::new(toml)?;
+    let mut api = Api::<S, ApiError, ApiVer>::new(toml)?;
 
-    let env_variables = get_public_env_vars()
-        .map_err(|err| Error::catch_all(StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")))?;
+    let env_variables =
+        get_public_env_vars().map_err(|err| ApiError::Internal(format!("{err:#}")))?;
 
     api.get("hotshot", |_, state| {
         async move { Ok(state.get_config().await) }.boxed()
     })?
+    .get("builders", |_, state| {
+        async move { Ok(state.get_builders().await) }.boxed()
+    })?
     .get("env", move |_, _| {
         {
             let env_variables = env_variables.clone();
             async move { Ok(env_variables) }
         }
         .boxed()
+    })?
+    .get("chainhistory", |_, state| {
+        async move {
+            state
+                .get_chain_config_history()
+                .await
+                .map_err(|err| ApiError::NotFound(format!("{err:#}")))
+        }
+        .boxed()
+    })?
+    .get("upgrades", |_, state| {
+        async move { Ok(state.get_upgrades().await) }.boxed()
+    })?;
+
+    Ok(api)
+}
+
+pub(super) fn marketplace<S, ApiVer: StaticVersionType + 'static>(
+    _: ApiVer,
+) -> Result<Api<S, ApiError, ApiVer>>
+where
+    S: 'static + Send + Sync + ReadState,
+    S::State: Send + Sync + SolverDataSource,
+{
+    let toml = toml::from_str::<toml::Value>(include_str!("../../api/marketplace.toml"))?;
+    let mut api = Api::<S, ApiError, ApiVer>::new(toml)?;
+
+    api.at("bid", |req, state| {
+        async move {
+            let bid = req
+                .body_auto::<BidTx, ApiVer>(ApiVer::instance())
+                .map_err(ApiError::from_request_error)?;
+
+            let Some(view) = state.read(|state| state.marketplace_view().boxed()).await else {
+                return Err(ApiError::BadRequest(
+                    "the marketplace is not active yet".into(),
+                ));
+            };
+            if bid.view() <= view {
+                return Err(ApiError::BadRequest(format!(
+                    "bid for view {:?}, which has already started (current view {view:?})",
+                    bid.view()
+                )));
+            }
+            bid.verify()
+                .map_err(|err| ApiError::BadRequest(format!("invalid bid: {err}")))?;
+
+            let hash = bid.commit();
+            state
+                .read(move |state| async move { state.submit_bid(&bid).await }.boxed())
+                .await
+                .map_err(|err| ApiError::Internal(format!("{err:#}")))?;
+            Ok(hash)
+        }
+        .boxed()
+    })?
+    .get("auction_results", |req, state| {
+        async move {
+            let view = req
+                .integer_param("view")
+                .map_err(ApiError::from_request_error)?;
+            state
+                .get_auction_results(ViewNumber::new(view))
+                .await
+                .map_err(|err| ApiError::NotFound(format!("{err:#}")))
+        }
+        .boxed()
+    })?
+    .get("reservations", |req, state| {
+        async move {
+            let view = req
+                .integer_param("view")
+                .map_err(ApiError::from_request_error)?;
+            let results = state
+                .get_auction_results(ViewNumber::new(view))
+                .await
+                .map_err(|err| ApiError::NotFound(format!("{err:#}")))?;
+            Ok(results
+                .reservations()
+                .into_iter()
+                .map(|(namespace, url)| NamespaceReservation { namespace, url })
+                .collect::<Vec<_>>())
+        }
+        .boxed()
     })?;
 
     Ok(api)
@@ -456,3 +1594,71 @@ fn get_public_env_vars() -> Result<Vec<String>> {
 
     Ok(public_env_vars)
 }
+
+#[cfg(test)]
+mod test {
+    use espresso_types::{Leaf, NodeState, ValidatedState};
+    use futures::stream;
+    use hotshot::traits::BlockPayload;
+    use hotshot_types::{traits::block_contents::GENESIS_VID_NUM_STORAGE_NODES, vid::vid_scheme};
+
+    use super::*;
+
+    #[test]
+    fn test_clamp_wait_timeout() {
+        let max = Duration::from_secs(60);
+        assert_eq!(clamp_wait_timeout(None, max), max);
+        assert_eq!(clamp_wait_timeout(Some(0), max), Duration::ZERO);
+        assert_eq!(clamp_wait_timeout(Some(10), max), Duration::from_secs(10));
+        assert_eq!(clamp_wait_timeout(Some(60), max), max);
+        assert_eq!(clamp_wait_timeout(Some(3600), max), max);
+        assert_eq!(clamp_wait_timeout(Some(u64::MAX), max), max);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_namespace_proofs_stop_on_failure() {
+        let instance = NodeState::mock();
+        let header = Leaf::genesis(&ValidatedState::default(), &instance)
+            .await
+            .block_header()
+            .clone();
+        let ns_id = NamespaceId::from(1u32);
+        let disperse = |payload: &Payload| {
+            VidCommonQueryData::new(
+                header.clone(),
+                vid_scheme(GENESIS_VID_NUM_STORAGE_NODES)
+                    .disperse(payload.encode())
+                    .unwrap()
+                    .common,
+            )
+        };
+
+        let empty = Payload::empty().0;
+        let (payload, _) = Payload::from_transactions(
+            [Transaction::new(ns_id, vec![1])],
+            &Default::default(),
+            &instance,
+        )
+        .await
+        .unwrap();
+
+        // The second block contains the namespace, but its VID common data does not match the
+        // payload, so no proof can be made.
+        let blocks = [
+            BlockQueryData::new(header.clone(), empty.clone()),
+            BlockQueryData::new(header.clone(), payload.clone()),
+            BlockQueryData::new(header.clone(), payload.clone()),
+        ];
+        let vid = [disperse(&empty), disperse(&empty), disperse(&payload)];
+        let proofs = namespace_proofs(stream::iter(blocks), stream::iter(vid), ns_id)
+            .collect::<Vec<_>>()
+            .await;
+
+        // The failure is reported, rather than the block being skipped, and the stream ends there.
+        assert_eq!(proofs.len(), 2);
+        let first = proofs[0].as_ref().unwrap();
+        assert!(first.proof.is_none());
+        assert!(first.transactions.is_empty());
+        proofs[1].as_ref().unwrap_err();
+    }
+}
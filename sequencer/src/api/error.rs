@@ -0,0 +1,260 @@
+//! Structured errors for the sequencer API.
+//!
+//! Every error returned by the API carries a machine-readable `code` identifying what went wrong,
+//! alongside the HTTP status and a human-readable message. Clients can use the code to decide
+//! whether a request is worth retrying: data which is [not yet available](ApiError::NotYetAvailable)
+//! may appear later, while data which has been [pruned](ApiError::Pruned) never will.
+//!
+//! On the wire, an error is an object with `status`, `message` and `code` fields. The first two
+//! match the generic error format of `tide_disco`, so clients which are not aware of error codes
+//! can still decode the error.
+
+use std::fmt::{self, Display, Formatter};
+
+use hotshot_query_service::{availability, explorer, merklized_state, node, status};
+use serde::{Deserialize, Serialize};
+use tide_disco::{Error as _, StatusCode};
+
+/// An error returned by the sequencer API.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "ErrorBody", from = "ErrorBody")]
+pub enum ApiError {
+    /// The requested resource does not exist.
+    NotFound(String),
+    /// The requested resource is not available yet, but may be later.
+    NotYetAvailable(String),
+    /// The requested resource has been pruned from this node, and will not be available again.
+    Pruned(String),
+    /// The request was rejected because the client has exceeded a rate limit.
+    RateLimited(String),
+    /// The server failed to process an otherwise valid request.
+    Internal(String),
+    /// The request was malformed.
+    BadRequest(String),
+    /// The server gave up waiting for something the request asked it to wait for.
+    Timeout(String),
+}
+
+impl ApiError {
+    /// The machine-readable code identifying this kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound(_) => "not_found",
+            Self::NotYetAvailable(_) => "not_yet_available",
+            Self::Pruned(_) => "pruned",
+            Self::RateLimited(_) => "rate_limited",
+            Self::Internal(_) => "internal",
+            Self::BadRequest(_) => "bad_request",
+            Self::Timeout(_) => "timeout",
+        }
+    }
+
+    /// The human-readable description of this error.
+    pub fn message(&self) -> &str {
+        match self {
+            Self::NotFound(message)
+            | Self::NotYetAvailable(message)
+            | Self::Pruned(message)
+            | Self::RateLimited(message)
+            | Self::Internal(message)
+            | Self::BadRequest(message)
+            | Self::Timeout(message) => message,
+        }
+    }
+
+    /// The kind of error identified by `code`, if the code is known.
+    fn from_code(code: &str) -> Option<fn(String) -> Self> {
+        Some(match code {
+            "not_found" => Self::NotFound,
+            "not_yet_available" => Self::NotYetAvailable,
+            "pruned" => Self::Pruned,
+            "rate_limited" => Self::RateLimited,
+            "internal" => Self::Internal,
+            "bad_request" => Self::BadRequest,
+            "timeout" => Self::Timeout,
+            _ => return None,
+        })
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl tide_disco::Error for ApiError {
+    fn catch_all(status: StatusCode, message: String) -> Self {
+        match u16::from(status) {
+            404 => Self::NotFound(message),
+            410 => Self::Pruned(message),
+            429 => Self::RateLimited(message),
+            503 => Self::NotYetAvailable(message),
+            504 => Self::Timeout(message),
+            400..=499 => Self::BadRequest(message),
+            _ => Self::Internal(message),
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            // Missing data has always been reported as 404; the code tells clients whether it is
+            // worth waiting for.
+            Self::NotFound(_) | Self::NotYetAvailable(_) => StatusCode::NOT_FOUND,
+            Self::Pruned(_) => StatusCode::GONE,
+            Self::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+}
+
+impl From<availability::Error> for ApiError {
+    fn from(err: availability::Error) -> Self {
+        match err {
+            // The query service fetches missing data in the background, so it may be available if
+            // the request is retried.
+            availability::Error::FetchLeaf { .. }
+            | availability::Error::FetchBlock { .. }
+            | availability::Error::FetchTransaction { .. } => {
+                Self::NotYetAvailable(err.to_string())
+            }
+            err => Self::catch_all(err.status(), err.to_string()),
+        }
+    }
+}
+
+/// Convert errors from query service modules which don't need special handling, by status.
+macro_rules! from_module_error {
+    ($($err:ty),*) => {
+        $(
+            impl From<$err> for ApiError {
+                fn from(err: $err) -> Self {
+                    Self::catch_all(err.status(), err.to_string())
+                }
+            }
+        )*
+    };
+}
+
+from_module_error!(
+    explorer::Error,
+    merklized_state::Error,
+    node::Error,
+    status::Error,
+    hotshot_query_service::Error
+);
+
+/// The wire format of an [`ApiError`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct ErrorBody {
+    status: StatusCode,
+    message: String,
+    code: String,
+}
+
+impl From<ApiError> for ErrorBody {
+    fn from(err: ApiError) -> Self {
+        Self {
+            status: err.status(),
+            code: err.code().into(),
+            message: err.message().into(),
+        }
+    }
+}
+
+impl From<ErrorBody> for ApiError {
+    fn from(body: ErrorBody) -> Self {
+        match Self::from_code(&body.code) {
+            Some(kind) => kind(body.message),
+            // Fall back to the status for codes added by newer servers.
+            None => Self::catch_all(body.status, body.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tide_disco::error::ServerError;
+
+    use super::*;
+
+    #[test]
+    fn test_api_error_serialization() {
+        let errors = [
+            ApiError::NotFound("not found".into()),
+            ApiError::NotYetAvailable("not yet available".into()),
+            ApiError::Pruned("pruned".into()),
+            ApiError::RateLimited("rate limited".into()),
+            ApiError::Internal("internal".into()),
+            ApiError::BadRequest("bad request".into()),
+            ApiError::Timeout("timeout".into()),
+        ];
+        for err in errors {
+            let json = serde_json::to_value(&err).unwrap();
+            assert_eq!(json["code"], err.code());
+            assert_eq!(json["message"], err.message());
+            assert_eq!(
+                serde_json::from_value::<ApiError>(json.clone()).unwrap(),
+                err
+            );
+
+            let bytes = bincode::serialize(&err).unwrap();
+            assert_eq!(bincode::deserialize::<ApiError>(&bytes).unwrap(), err);
+
+            // Clients which don't know about error codes still see the status and message.
+            let generic: ServerError = serde_json::from_value(json).unwrap();
+            assert_eq!(generic.status, err.status());
+            assert_eq!(generic.message, err.message());
+        }
+
+        // Unknown codes fall back to the status.
+        let json = serde_json::json!({
+            "status": 429,
+            "message": "slow down",
+            "code": "some_new_code",
+        });
+        assert_eq!(
+            serde_json::from_value::<ApiError>(json).unwrap(),
+            ApiError::RateLimited("slow down".into())
+        );
+    }
+
+    #[test]
+    fn test_api_error_catch_all() {
+        assert_eq!(
+            ApiError::catch_all(StatusCode::NOT_FOUND, "x".into()),
+            ApiError::NotFound("x".into())
+        );
+        assert_eq!(
+            ApiError::catch_all(StatusCode::GONE, "x".into()),
+            ApiError::Pruned("x".into())
+        );
+        assert_eq!(
+            ApiError::catch_all(StatusCode::TOO_MANY_REQUESTS, "x".into()),
+            ApiError::RateLimited("x".into())
+        );
+        assert_eq!(
+            ApiError::catch_all(StatusCode::UNPROCESSABLE_ENTITY, "x".into()),
+            ApiError::BadRequest("x".into())
+        );
+        assert_eq!(
+            ApiError::catch_all(StatusCode::GATEWAY_TIMEOUT, "x".into()),
+            ApiError::Timeout("x".into())
+        );
+        assert_eq!(
+            ApiError::catch_all(StatusCode::NOT_IMPLEMENTED, "x".into()),
+            ApiError::Internal("x".into())
+        );
+        assert_eq!(
+            ApiError::from(availability::Error::FetchBlock {
+                resource: "1".into()
+            })
+            .code(),
+            "not_yet_available"
+        );
+    }
+}
@@ -0,0 +1,338 @@
+//! Fanout of decided events to additional storage sinks.
+//!
+//! Decided blocks are always written to the node's own query storage, if it has any. Deployments
+//! which also want them written elsewhere, for example streamed to a message queue, can register
+//! additional [`EventSink`]s: any [`UpdateDataSource`] can be a sink.
+//!
+//! The [`FanoutEventConsumer`] passes each event to the primary consumer first, so that consensus
+//! storage keeps decided leaves until they have been processed by the query storage, as before.
+//! Once the primary consumer has succeeded, the event is queued for each sink. Each sink has its
+//! own bounded queue and update task, so a slow or failing sink delays neither consensus nor the
+//! other sinks. Failed updates are retried with exponential backoff, up to a maximum number of
+//! attempts. If a sink falls so far behind that its queue is full, new events for it are dropped;
+//! sinks which fetch missing data, like query service data sources, will fill in the gaps
+//! themselves. Updates, retries, failures and drops are reported via metrics in the `event_sink`
+//! group.
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use derivative::Derivative;
+use espresso_types::{v0::traits::EventConsumer, Leaf, SeqTypes};
+use hotshot::types::Event;
+use hotshot_query_service::data_source::UpdateDataSource;
+use hotshot_types::{
+    simple_certificate::QuorumCertificate,
+    traits::metrics::{Counter, Metrics},
+};
+use tokio::{
+    sync::mpsc::{channel, error::TrySendError, Receiver, Sender},
+    time::sleep,
+};
+
+use crate::context::TaskList;
+
+/// A storage sink which consumes decided events.
+pub type Sink = Arc<dyn UpdateDataSource<SeqTypes> + Send + Sync>;
+
+/// An additional destination for decided events.
+#[derive(Clone, Derivative)]
+#[derivative(Debug)]
+pub struct EventSink {
+    /// Name of the sink, used in logs.
+    pub name: String,
+    #[derivative(Debug = "ignore")]
+    pub sink: Sink,
+    pub config: EventSinkConfig,
+}
+
+impl EventSink {
+    pub fn new(name: impl Into<String>, sink: Sink) -> Self {
+        Self {
+            name: name.into(),
+            sink,
+            config: Default::default(),
+        }
+    }
+
+    pub fn with_config(mut self, config: EventSinkConfig) -> Self {
+        self.config = config;
+        self
+    }
+}
+
+/// Delivery settings for an [`EventSink`].
+#[derive(Clone, Copy, Debug)]
+pub struct EventSinkConfig {
+    /// Maximum number of events waiting to be written to the sink.
+    ///
+    /// Events for a sink whose queue is full are dropped.
+    pub queue_capacity: usize,
+    /// Maximum number of attempts to write each event before giving up.
+    pub max_attempts: usize,
+    /// Delay before retrying a failed write. The delay doubles with each failed attempt.
+    pub retry_delay: Duration,
+}
+
+impl Default for EventSinkConfig {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 1000,
+            max_attempts: 5,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SinkMetrics {
+    /// Number of events written successfully.
+    updated: Box<dyn Counter>,
+    /// Number of failed writes which were retried.
+    retried: Box<dyn Counter>,
+    /// Number of events which could not be written after the maximum number of attempts.
+    failed: Box<dyn Counter>,
+    /// Number of events dropped because a sink's queue was full.
+    dropped: Box<dyn Counter>,
+}
+
+impl SinkMetrics {
+    fn new(metrics: &dyn Metrics) -> Self {
+        let metrics = metrics.subgroup("event_sink".into());
+        Self {
+            updated: metrics.create_counter("updated".into(), None),
+            retried: metrics.create_counter("retried".into(), None),
+            failed: metrics.create_counter("failed".into(), None),
+            dropped: metrics.create_counter("dropped".into(), None),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Target {
+    name: String,
+    queue: Sender<Arc<Event<SeqTypes>>>,
+}
+
+/// Passes events to a primary consumer, and then to each registered [`EventSink`].
+#[derive(Clone, Debug)]
+pub(crate) struct FanoutEventConsumer {
+    primary: Box<dyn EventConsumer>,
+    targets: Arc<Vec<Target>>,
+    metrics: Arc<SinkMetrics>,
+}
+
+impl FanoutEventConsumer {
+    /// Create a consumer, spawning an update task in `tasks` for each sink.
+    pub(crate) fn new(
+        primary: Box<dyn EventConsumer>,
+        sinks: &[EventSink],
+        metrics: &dyn Metrics,
+        tasks: &mut TaskList,
+    ) -> Self {
+        let metrics = Arc::new(SinkMetrics::new(metrics));
+        let targets = sinks
+            .iter()
+            .map(|sink| {
+                let (queue, events) = channel(sink.config.queue_capacity);
+                tasks.spawn(
+                    format!("event sink {}", sink.name),
+                    update(sink.clone(), events, metrics.clone()),
+                );
+                Target {
+                    name: sink.name.clone(),
+                    queue,
+                }
+            })
+            .collect();
+        Self {
+            primary,
+            targets: Arc::new(targets),
+            metrics,
+        }
+    }
+}
+
+#[async_trait]
+impl EventConsumer for FanoutEventConsumer {
+    async fn handle_event(&self, event: &Event<SeqTypes>) -> anyhow::Result<()> {
+        // If the primary consumer fails, consensus storage will send this event again, so only
+        // queue it for the sinks once it has been processed.
+        self.primary.handle_event(event).await?;
+
+        let event = Arc::new(event.clone());
+        for target in self.targets.iter() {
+            match target.queue.try_send(event.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    tracing::warn!(
+                        sink = target.name,
+                        view = ?event.view_number,
+                        "event sink queue full, dropping event"
+                    );
+                    self.metrics.dropped.add(1);
+                }
+                Err(TrySendError::Closed(_)) => {
+                    tracing::error!(sink = target.name, "event sink update task has exited");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn reconcile(
+        &self,
+        anchor: &Leaf,
+        qc: &QuorumCertificate<SeqTypes>,
+    ) -> anyhow::Result<()> {
+        self.primary.reconcile(anchor, qc).await
+    }
+}
+
+/// Write queued events to `sink`, in order.
+async fn update(
+    sink: EventSink,
+    mut events: Receiver<Arc<Event<SeqTypes>>>,
+    metrics: Arc<SinkMetrics>,
+) {
+    let EventSink { name, sink, config } = sink;
+    while let Some(event) = events.recv().await {
+        let mut delay = config.retry_delay;
+        for attempt in 1..=config.max_attempts.max(1) {
+            match sink.update(&event).await {
+                Ok(()) => {
+                    metrics.updated.add(1);
+                    break;
+                }
+                Err(height) if attempt < config.max_attempts => {
+                    tracing::info!(
+                        sink = name,
+                        attempt,
+                        height,
+                        "event sink update failed, will retry after {delay:?}"
+                    );
+                    metrics.retried.add(1);
+                    sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(height) => {
+                    tracing::warn!(
+                        sink = name,
+                        attempt,
+                        height,
+                        "giving up on event sink update"
+                    );
+                    metrics.failed.add(1);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::bail;
+    use espresso_types::v0::traits::NullEventConsumer;
+    use hotshot::types::EventType;
+    use hotshot_types::{
+        data::ViewNumber,
+        traits::{metrics::NoMetrics, node_implementation::ConsensusTime},
+    };
+    use parking_lot::Mutex;
+    use sequencer_utils::test_utils::setup_test;
+    use tokio::time::timeout;
+
+    use super::*;
+
+    /// A sink which records the views of the events it is sent.
+    #[derive(Debug, Default)]
+    struct RecordingSink {
+        views: Mutex<Vec<u64>>,
+    }
+
+    #[async_trait]
+    impl UpdateDataSource<SeqTypes> for RecordingSink {
+        async fn update(&self, event: &Event<SeqTypes>) -> Result<(), u64> {
+            self.views.lock().push(event.view_number.u64());
+            Ok(())
+        }
+    }
+
+    /// A sink which fails every update.
+    #[derive(Debug)]
+    struct FailingSink;
+
+    #[async_trait]
+    impl UpdateDataSource<SeqTypes> for FailingSink {
+        async fn update(&self, _: &Event<SeqTypes>) -> Result<(), u64> {
+            Err(0)
+        }
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    struct FailingConsumer;
+
+    #[async_trait]
+    impl EventConsumer for FailingConsumer {
+        async fn handle_event(&self, _: &Event<SeqTypes>) -> anyhow::Result<()> {
+            bail!("mock error injection");
+        }
+    }
+
+    fn event(view: u64) -> Event<SeqTypes> {
+        Event {
+            view_number: ViewNumber::new(view),
+            event: EventType::ViewTimeout {
+                view_number: ViewNumber::new(view),
+            },
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fanout_isolates_sinks() {
+        setup_test();
+
+        let recording = Arc::new(RecordingSink::default());
+        let sinks = [
+            EventSink::new("failing", Arc::new(FailingSink)).with_config(EventSinkConfig {
+                queue_capacity: 1,
+                max_attempts: 100,
+                retry_delay: Duration::from_secs(60),
+            }),
+            EventSink::new("recording", recording.clone()),
+        ];
+        let mut tasks = TaskList::default();
+        let consumer =
+            FanoutEventConsumer::new(Box::new(NullEventConsumer), &sinks, &NoMetrics, &mut tasks);
+
+        // The failing sink falls behind and drops events, without holding up the other sink.
+        for view in 0..5 {
+            consumer.handle_event(&event(view)).await.unwrap();
+        }
+        timeout(Duration::from_secs(10), async {
+            while recording.views.lock().len() < 5 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(*recording.views.lock(), [0, 1, 2, 3, 4]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fanout_primary_failure() {
+        setup_test();
+
+        let recording = Arc::new(RecordingSink::default());
+        let sinks = [EventSink::new("recording", recording.clone())];
+        let mut tasks = TaskList::default();
+        let consumer =
+            FanoutEventConsumer::new(Box::new(FailingConsumer), &sinks, &NoMetrics, &mut tasks);
+
+        // Events which the primary consumer fails to process are not sent to sinks, since they will
+        // be sent again.
+        consumer.handle_event(&event(0)).await.unwrap_err();
+        tokio::task::yield_now().await;
+        assert!(recording.views.lock().is_empty());
+    }
+}
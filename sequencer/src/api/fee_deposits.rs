@@ -0,0 +1,81 @@
+//! Indexing of deposits into the fee contract.
+//!
+//! The fee state only records the balance of each account, so it cannot tell a user which deposits
+//! make up their balance. When the fee API is enabled, [`index`] scans the L1 for deposit events as
+//! blocks are finalized, and records each deposit in persistence along with the L1 transaction
+//! which made it. Deposits are served by `fee/deposits/:address`, so that users can reconcile
+//! changes in their balance against their L1 transactions.
+
+use std::cmp::min;
+
+use espresso_types::{v0::traits::SequencerPersistence, PubKey};
+use hotshot_types::traits::{network::ConnectedNetwork, node_implementation::Versions};
+use tokio::time::sleep;
+
+use super::{
+    data_source::{NodeStateDataSource, SubmitDataSource},
+    options::Fee,
+    ApiState,
+};
+
+/// Maximum number of L1 blocks to scan before recording progress.
+const MAX_SCAN_RANGE: u64 = 100_000;
+
+/// Index fee deposits as L1 blocks are finalized, forever.
+pub(super) async fn index<N, P, V>(state: ApiState<N, P, V>, opt: Fee)
+where
+    N: ConnectedNetwork<PubKey>,
+    P: SequencerPersistence,
+    V: Versions,
+{
+    loop {
+        match scan(&state, &opt).await {
+            // Keep going without waiting if we are still catching up.
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(err) => tracing::warn!("failed to index fee deposits: {err:#}"),
+        }
+        sleep(opt.fee_deposit_index_interval).await;
+    }
+}
+
+/// Scan the next range of finalized L1 blocks for deposits.
+///
+/// Returns whether there are more finalized blocks left to scan.
+async fn scan<N, P, V>(state: &ApiState<N, P, V>, opt: &Fee) -> anyhow::Result<bool>
+where
+    N: ConnectedNetwork<PubKey>,
+    P: SequencerPersistence,
+    V: Versions,
+{
+    let Some(fee_contract) = state.active_chain_config().await.fee_contract else {
+        return Ok(false);
+    };
+    let node_state = state.node_state().await;
+    let Some(finalized) = node_state.l1_client.snapshot().await.finalized else {
+        return Ok(false);
+    };
+    let persistence = state.persistence().await;
+
+    // Resume after the last block scanned or, the first time, from the configured start block or
+    // the L1 block the chain started at.
+    let from = match persistence.load_fee_deposits_l1_block().await? {
+        Some(scanned) => scanned + 1,
+        None => opt
+            .fee_deposit_index_start
+            .or(node_state.l1_genesis.map(|block| block.number()))
+            .unwrap_or(0),
+    };
+    if from > finalized.number() {
+        return Ok(false);
+    }
+    let to = min(finalized.number(), from + MAX_SCAN_RANGE - 1);
+
+    let deposits = node_state
+        .l1_client
+        .get_finalized_deposit_events(fee_contract, from.checked_sub(1), to)
+        .await;
+    persistence.store_fee_deposits(&deposits, to).await?;
+    tracing::info!(from, to, deposits = deposits.len(), "indexed fee deposits");
+    Ok(to < finalized.number())
+}
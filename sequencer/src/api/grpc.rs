@@ -0,0 +1,360 @@
+//! gRPC mirror of the availability and submit APIs.
+//!
+//! This serves a subset of the `availability` and `submit` HTTP APIs over gRPC, for clients which
+//! cannot speak HTTP. The gRPC server runs on its own port alongside the HTTP server, and shares its
+//! data source. The schema is defined in `api/proto/espresso.proto`.
+
+use std::{fmt::Display, net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use committable::Committable;
+use espresso_types::{Header, NamespaceId, PubKey, Transaction};
+use futures::stream::{BoxStream, StreamExt};
+use hotshot_query_service::availability::{self, AvailabilityDataSource, Fetch};
+use hotshot_types::traits::{network::ConnectedNetwork, node_implementation::Versions};
+use serde::Serialize;
+use tonic::{transport::Server, Request, Response, Status};
+
+use self::proto::{
+    availability_server::{Availability, AvailabilityServer},
+    submit_server::{Submit, SubmitServer},
+};
+use super::{
+    cache::QueryCache,
+    data_source::{SequencerDataSource, SubmitDataSource},
+    endpoints::NamespaceProofQueryData,
+    rate_limit::SubmitRateLimiter,
+    tx_validation::TxValidator,
+    StorageState,
+};
+use crate::SequencerPersistence;
+
+/// Types and services generated from `api/proto/espresso.proto`.
+pub mod proto {
+    tonic::include_proto!("espresso.v0");
+}
+
+/// Serve the gRPC API on `port`.
+pub(crate) async fn serve<N, P, D, V>(
+    port: u16,
+    state: Arc<StorageState<N, P, D, V>>,
+    cache: QueryCache,
+    limiter: SubmitRateLimiter,
+    validator: TxValidator,
+) -> anyhow::Result<()>
+where
+    N: ConnectedNetwork<PubKey>,
+    P: SequencerPersistence,
+    D: SequencerDataSource + Send + Sync + 'static,
+    V: Versions,
+{
+    let service = Arc::new(GrpcService {
+        state,
+        cache,
+        limiter,
+        validator,
+        timeout: availability::Options::default().fetch_timeout,
+    });
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    tracing::info!(%addr, "starting gRPC server");
+    Server::builder()
+        .add_service(AvailabilityServer::from_arc(service.clone()))
+        .add_service(SubmitServer::from_arc(service))
+        .serve(addr)
+        .await
+        .context("gRPC server failed")
+}
+
+struct GrpcService<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, D, V: Versions> {
+    state: Arc<StorageState<N, P, D, V>>,
+    /// Recently queried blocks, shared with the HTTP server.
+    cache: QueryCache,
+    limiter: SubmitRateLimiter,
+    validator: TxValidator,
+    /// How long to wait for missing data to be fetched before giving up on a request.
+    timeout: Duration,
+}
+
+impl<N, P, D, V> GrpcService<N, P, D, V>
+where
+    N: ConnectedNetwork<PubKey>,
+    P: SequencerPersistence,
+    V: Versions,
+{
+    async fn fetch<T>(&self, fetch: Fetch<T>, resource: impl Display) -> Result<T, Status> {
+        fetch
+            .with_timeout(self.timeout)
+            .await
+            .ok_or_else(|| not_available(resource))
+    }
+}
+
+fn not_available(resource: impl Display) -> Status {
+    Status::not_found(format!("{resource} is not available"))
+}
+
+#[tonic::async_trait]
+impl<N, P, D, V> Availability for GrpcService<N, P, D, V>
+where
+    N: ConnectedNetwork<PubKey>,
+    P: SequencerPersistence,
+    D: SequencerDataSource + Send + Sync + 'static,
+    V: Versions,
+{
+    type StreamHeadersStream = BoxStream<'static, Result<proto::Header, Status>>;
+
+    async fn get_header(
+        &self,
+        req: Request<proto::BlockRequest>,
+    ) -> Result<Response<proto::Header>, Status> {
+        let height = req.into_inner().height;
+        let leaf = self
+            .fetch(
+                self.state.get_leaf(height as usize).await,
+                format!("header {height}"),
+            )
+            .await?;
+        Ok(Response::new(header_to_proto(leaf.header())?))
+    }
+
+    async fn get_block(
+        &self,
+        req: Request<proto::BlockRequest>,
+    ) -> Result<Response<proto::Block>, Status> {
+        let height = req.into_inner().height;
+        let block = self
+            .cache
+            .get_block(&*self.state, height as usize, self.timeout)
+            .await
+            .ok_or_else(|| not_available(format!("block {height}")))?;
+        Ok(Response::new(proto::Block {
+            header: Some(header_to_proto(block.header())?),
+            payload: encode(block.payload())?,
+            num_transactions: block.enumerate().count() as u64,
+        }))
+    }
+
+    async fn get_namespace(
+        &self,
+        req: Request<proto::NamespaceRequest>,
+    ) -> Result<Response<proto::Namespace>, Status> {
+        let proto::NamespaceRequest { height, namespace } = req.into_inner();
+        let ns_id = NamespaceId::from(namespace);
+        let block = self
+            .cache
+            .get_block(&*self.state, height as usize, self.timeout)
+            .await
+            .ok_or_else(|| not_available(format!("block {height}")))?;
+        let common = self
+            .cache
+            .get_vid_common(&*self.state, height as usize, self.timeout)
+            .await
+            .ok_or_else(|| not_available(format!("VID common {height}")))?;
+
+        let ns = NamespaceProofQueryData::new(block.payload(), common.common(), ns_id).ok_or_else(
+            || Status::not_found(format!("failed to make proof for namespace {ns_id}")),
+        )?;
+        Ok(Response::new(proto::Namespace {
+            height,
+            namespace,
+            transactions: ns.transactions.iter().map(transaction_to_proto).collect(),
+            proof: encode(&ns.proof)?,
+            vid_common: encode(common.common())?,
+        }))
+    }
+
+    async fn stream_headers(
+        &self,
+        req: Request<proto::BlockRequest>,
+    ) -> Result<Response<Self::StreamHeadersStream>, Status> {
+        let height = req.into_inner().height;
+        let leaves = self.state.subscribe_leaves(height as usize).await;
+        Ok(Response::new(
+            leaves.map(|leaf| header_to_proto(leaf.header())).boxed(),
+        ))
+    }
+}
+
+#[tonic::async_trait]
+impl<N, P, D, V> Submit for GrpcService<N, P, D, V>
+where
+    N: ConnectedNetwork<PubKey>,
+    P: SequencerPersistence,
+    D: SequencerDataSource + Send + Sync + 'static,
+    V: Versions,
+{
+    async fn submit(
+        &self,
+        req: Request<proto::Transaction>,
+    ) -> Result<Response<proto::TransactionHash>, Status> {
+        let proto::Transaction { namespace, payload } = req.into_inner();
+        let ns = NamespaceId::from(namespace);
+        if !self.limiter.check(ns) {
+            return Err(Status::resource_exhausted(format!(
+                "rate limit exceeded for namespace {ns}"
+            )));
+        }
+
+        let tx = Transaction::new(ns, payload);
+        let cf = self.state.active_chain_config().await;
+        self.validator
+            .validate(&cf, &tx)
+            .map_err(|err| Status::invalid_argument(err.message()))?;
+
+        let hash = tx.commit();
+        self.state
+            .submit(tx)
+            .await
+            .map_err(|err| Status::internal(format!("{err:#}")))?;
+        Ok(Response::new(proto::TransactionHash {
+            hash: hash.to_string(),
+        }))
+    }
+}
+
+fn header_to_proto(header: &Header) -> Result<proto::Header, Status> {
+    Ok(proto::Header {
+        height: header.height(),
+        hash: header.commit().to_string(),
+        timestamp: header.timestamp(),
+        data: encode(header)?,
+    })
+}
+
+fn transaction_to_proto(tx: &Transaction) -> proto::Transaction {
+    proto::Transaction {
+        namespace: tx.namespace().into(),
+        payload: tx.payload().to_vec(),
+    }
+}
+
+fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Status> {
+    bincode::serialize(value).map_err(|err| Status::internal(format!("serialization error: {err}")))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use espresso_types::{namespace::verify_namespace_proof, MockSequencerVersions, NsProof};
+    use ethers::utils::Anvil;
+    use hotshot_types::vid::VidCommon;
+    use portpicker::pick_unused_port;
+    use sequencer_utils::test_utils::setup_test;
+    use tokio::time::sleep;
+
+    use super::{
+        proto::{availability_client::AvailabilityClient, submit_client::SubmitClient},
+        *,
+    };
+    use crate::{
+        api::{
+            data_source::testing::TestableSequencerDataSource,
+            options::{Grpc, Options},
+            sql::DataSource as SqlDataSource,
+            test_helpers::{TestNetwork, TestNetworkConfigBuilder},
+        },
+        testing::{wait_for_decide_on_handle, TestConfigBuilder},
+    };
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_grpc() {
+        setup_test();
+
+        let port = pick_unused_port().expect("No ports free");
+        let grpc_port = pick_unused_port().expect("No ports free");
+        let storage = SqlDataSource::create_storage().await;
+        let options = SqlDataSource::options(&storage, Options::with_port(port))
+            .grpc(Grpc { port: grpc_port });
+
+        let anvil = Anvil::new().spawn();
+        let l1 = anvil.endpoint().parse().unwrap();
+        let network_config = TestConfigBuilder::default().l1_url(l1).build();
+        let config = TestNetworkConfigBuilder::default()
+            .api_config(options)
+            .network_config(network_config)
+            .build();
+        let network = TestNetwork::new(config, MockSequencerVersions::new()).await;
+        let mut events = network.server.event_stream().await;
+
+        // Wait for the gRPC server to start.
+        let url = format!("http://localhost:{grpc_port}");
+        let mut submit = loop {
+            match SubmitClient::connect(url.clone()).await {
+                Ok(client) => break client,
+                Err(err) => {
+                    tracing::info!("waiting for gRPC server: {err}");
+                    sleep(Duration::from_millis(100)).await;
+                }
+            }
+        };
+        let mut availability = AvailabilityClient::connect(url).await.unwrap();
+
+        // Submit a transaction.
+        let ns_id = NamespaceId::from(42_u32);
+        let txn = Transaction::new(ns_id, vec![1, 2, 3, 4]);
+        let hash = submit
+            .submit(transaction_to_proto(&txn))
+            .await
+            .unwrap()
+            .into_inner()
+            .hash;
+        assert_eq!(hash, txn.commit().to_string());
+        let height = wait_for_decide_on_handle(&mut events, &txn).await;
+
+        // Stream headers until the query service has caught up to the transaction.
+        let mut headers = availability
+            .stream_headers(proto::BlockRequest { height: 0 })
+            .await
+            .unwrap()
+            .into_inner();
+        for i in 0..=height {
+            let header = headers.message().await.unwrap().unwrap();
+            assert_eq!(header.height, i);
+            let decoded: Header = bincode::deserialize(&header.data).unwrap();
+            assert_eq!(decoded.height(), i);
+            assert_eq!(header.hash, decoded.commit().to_string());
+        }
+
+        // Query the block containing the transaction.
+        let header = availability
+            .get_header(proto::BlockRequest { height })
+            .await
+            .unwrap()
+            .into_inner();
+        let block = availability
+            .get_block(proto::BlockRequest { height })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(block.header, Some(header.clone()));
+        assert!(block.num_transactions >= 1);
+        let header: Header = bincode::deserialize(&header.data).unwrap();
+
+        // Query and verify the namespace.
+        let ns = availability
+            .get_namespace(proto::NamespaceRequest {
+                height,
+                namespace: ns_id.into(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let transactions = ns
+            .transactions
+            .into_iter()
+            .map(|tx| Transaction::new(tx.namespace.into(), tx.payload))
+            .collect::<Vec<_>>();
+        assert!(transactions.contains(&txn));
+        let proof: Option<NsProof> = bincode::deserialize(&ns.proof).unwrap();
+        let common: VidCommon = bincode::deserialize(&ns.vid_common).unwrap();
+        verify_namespace_proof(&header, ns_id, &transactions, proof.as_ref(), &common).unwrap();
+
+        // Missing blocks are reported as not found.
+        let err = availability
+            .get_block(proto::BlockRequest { height: u64::MAX })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+    }
+}
@@ -0,0 +1,204 @@
+//! Cross-origin and request size limits for the HTTP API.
+//!
+//! [tide_disco] answers CORS preflight requests itself and allows any origin. When a list of
+//! allowed origins is configured, the [`ApiLimits`] middleware rejects requests made by browsers on
+//! any other origin, so that only the configured sites (e.g. a rollup's explorer) can call the API
+//! from a browser. Requests without an `Origin` header, which do not come from browsers, are not
+//! affected.
+//!
+//! The middleware also bounds the size of request bodies, so that a single huge submission cannot
+//! exhaust the memory of a small node. Bodies are rejected up front if they declare a length over
+//! the limit, and are otherwise read with a cap, so the limit also applies to chunked requests.
+//!
+//! Like [`ApiAuth`](super::auth::ApiAuth), [`ApiLimits`] is installed by wrapping the listener the
+//! app is served on, in a [`LimitsListener`]. Since it wraps the other listeners, these limits are
+//! checked before authentication and before any work is done to handle the request.
+
+use std::{
+    collections::HashSet,
+    fmt::{self, Display, Formatter},
+    io,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use futures::io::AsyncReadExt;
+use tide::{
+    listener::{ListenInfo, Listener, ToListener},
+    Middleware, Next, Request, Response, Server, StatusCode,
+};
+
+use super::options::Http;
+
+/// Middleware which enforces allowed origins and a maximum request body size.
+#[derive(Clone, Debug)]
+pub(crate) struct ApiLimits {
+    /// Origins allowed to make cross-origin requests, or [`None`] to allow any origin.
+    origins: Option<Arc<HashSet<String>>>,
+    max_body_bytes: Option<u64>,
+}
+
+impl ApiLimits {
+    /// The limits configured in `opt`, or [`None`] if there are none to enforce.
+    pub(crate) fn new(opt: &Http) -> Option<Self> {
+        let origins = opt
+            .cors_origins
+            .iter()
+            .map(|origin| origin.trim().trim_end_matches('/').to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect::<HashSet<_>>();
+        let origins = if origins.is_empty() || origins.contains("*") {
+            None
+        } else {
+            Some(Arc::new(origins))
+        };
+        if origins.is_none() && opt.max_request_body_bytes.is_none() {
+            return None;
+        }
+        Some(Self {
+            origins,
+            max_body_bytes: opt.max_request_body_bytes,
+        })
+    }
+
+    /// Whether a request carrying the given `Origin` header is allowed.
+    fn is_allowed_origin(&self, origin: Option<&str>) -> bool {
+        match (&self.origins, origin) {
+            (Some(origins), Some(origin)) => origins.contains(origin.trim_end_matches('/')),
+            _ => true,
+        }
+    }
+}
+
+fn too_large(limit: u64) -> Response {
+    let mut res = Response::new(StatusCode::PayloadTooLarge);
+    res.set_body(format!("request body exceeds limit of {limit} bytes"));
+    res
+}
+
+#[async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for ApiLimits {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let origin = req.header("Origin").map(|value| value.last().as_str());
+        if !self.is_allowed_origin(origin) {
+            let mut res = Response::new(StatusCode::Forbidden);
+            res.set_body("origin not allowed");
+            return Ok(res);
+        }
+
+        if let Some(limit) = self.max_body_bytes {
+            if req.len().is_some_and(|len| len as u64 > limit) {
+                return Ok(too_large(limit));
+            }
+
+            // The declared length may be missing (for chunked bodies) or wrong, so read at most one
+            // byte more than the limit to find out whether the body actually fits.
+            let mut body = vec![];
+            req.take_body()
+                .take(limit.saturating_add(1))
+                .read_to_end(&mut body)
+                .await?;
+            if body.len() as u64 > limit {
+                return Ok(too_large(limit));
+            }
+            req.set_body(body);
+        }
+
+        Ok(next.run(req).await)
+    }
+}
+
+/// A [`Listener`] which installs [`ApiLimits`], if any are configured, on the server before
+/// delegating to another listener.
+#[derive(Debug)]
+pub(crate) struct LimitsListener<L> {
+    inner: L,
+    limits: Option<ApiLimits>,
+}
+
+impl<L> LimitsListener<L> {
+    pub(crate) fn new(inner: L, limits: Option<ApiLimits>) -> Self {
+        Self { inner, limits }
+    }
+}
+
+impl<L: Display> Display for LimitsListener<L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+#[async_trait]
+impl<State, L> Listener<State> for LimitsListener<L>
+where
+    State: Clone + Send + Sync + 'static,
+    L: Listener<State>,
+{
+    async fn bind(&mut self, mut app: Server<State>) -> io::Result<()> {
+        if let Some(limits) = &self.limits {
+            app.with(limits.clone());
+        }
+        self.inner.bind(app).await
+    }
+
+    async fn accept(&mut self) -> io::Result<()> {
+        self.inner.accept().await
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        self.inner.info()
+    }
+}
+
+impl<State, L> ToListener<State> for LimitsListener<L>
+where
+    State: Clone + Send + Sync + 'static,
+    L: Listener<State>,
+{
+    type Listener = Self;
+
+    fn to_listener(self) -> io::Result<Self::Listener> {
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn options(origins: &[&str], max_request_body_bytes: Option<u64>) -> Http {
+        Http {
+            cors_origins: origins.iter().map(|origin| origin.to_string()).collect(),
+            max_request_body_bytes,
+            ..Http::with_port(0)
+        }
+    }
+
+    #[test]
+    fn test_no_limits() {
+        assert!(ApiLimits::new(&options(&[], None)).is_none());
+        assert!(ApiLimits::new(&options(&["*"], None)).is_none());
+    }
+
+    #[test]
+    fn test_allowed_origins() {
+        let limits = ApiLimits::new(&options(
+            &["https://explorer.example.com/", " https://rollup.example.com"],
+            None,
+        ))
+        .unwrap();
+        assert!(limits.is_allowed_origin(Some("https://explorer.example.com")));
+        assert!(limits.is_allowed_origin(Some("https://rollup.example.com")));
+        assert!(!limits.is_allowed_origin(Some("https://evil.example.com")));
+
+        // Requests not made by browsers have no origin, and are always allowed.
+        assert!(limits.is_allowed_origin(None));
+    }
+
+    #[test]
+    fn test_any_origin_with_body_limit() {
+        let limits = ApiLimits::new(&options(&["*"], Some(1024))).unwrap();
+        assert!(limits.is_allowed_origin(Some("https://anywhere.example.com")));
+        assert_eq!(limits.max_body_bytes, Some(1024));
+    }
+}
@@ -0,0 +1,249 @@
+//! Per-route latency metrics for the HTTP API.
+//!
+//! [tide_disco] does not expose a way to add server middleware, so we instead wrap whatever
+//! listener the app is served on in a [`MetricsListener`], which installs the [`ApiMetrics`]
+//! middleware on the underlying server when it is bound.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    io,
+    sync::Arc,
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use hotshot_types::traits::metrics::{Gauge, Histogram, Metrics};
+use parking_lot::Mutex;
+use tide::{
+    listener::{ListenInfo, Listener, ToListener},
+    Middleware, Next, Request, Server, StatusCode,
+};
+
+/// Latency and concurrency metrics for a single API route.
+#[derive(Debug)]
+struct RouteMetrics {
+    /// Time taken to respond to each request, in seconds.
+    latency: Box<dyn Histogram>,
+    /// Number of requests currently being handled.
+    in_flight: Box<dyn Gauge>,
+}
+
+/// Decrements the in-flight gauge of a route when a request completes or is cancelled.
+struct InFlight<'a>(&'a RouteMetrics);
+
+impl<'a> InFlight<'a> {
+    fn new(route: &'a RouteMetrics) -> Self {
+        route.in_flight.update(1);
+        Self(route)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.update(-1);
+    }
+}
+
+/// Middleware which records latency histograms and in-flight gauges for each API route.
+///
+/// Routes are identified by the module name and the first literal segment of the path, so that a
+/// request for `/v0/availability/block/42` is recorded in `api_availability_block_seconds` and
+/// `api_availability_block_in_flight`. Metrics for a route are only created once that route has
+/// returned a response other than 404, so requests for nonexistent paths cannot create an
+/// unbounded number of metrics.
+#[derive(Clone, Debug)]
+pub(crate) struct ApiMetrics {
+    metrics: Arc<Box<dyn Metrics>>,
+    routes: Arc<Mutex<HashMap<RouteKey, Arc<RouteMetrics>>>>,
+}
+
+impl ApiMetrics {
+    pub(crate) fn new(metrics: &dyn Metrics) -> Self {
+        Self {
+            metrics: Arc::new(metrics.subgroup("api".into())),
+            routes: Default::default(),
+        }
+    }
+
+    fn get(&self, key: &RouteKey) -> Option<Arc<RouteMetrics>> {
+        self.routes.lock().get(key).cloned()
+    }
+
+    fn get_or_create(&self, key: &RouteKey) -> Arc<RouteMetrics> {
+        self.routes
+            .lock()
+            .entry(key.clone())
+            .or_insert_with(|| {
+                let metrics = self.metrics.subgroup(key.module.clone());
+                let prefix = match &key.route {
+                    Some(route) => format!("{route}_"),
+                    None => String::new(),
+                };
+                Arc::new(RouteMetrics {
+                    latency: metrics.create_histogram(format!("{prefix}seconds"), None),
+                    in_flight: metrics.create_gauge(format!("{prefix}in_flight"), None),
+                })
+            })
+            .clone()
+    }
+}
+
+#[async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for ApiMetrics {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let Some(key) = RouteKey::parse(req.url().path()) else {
+            return Ok(next.run(req).await);
+        };
+
+        let start = Instant::now();
+        let route = self.get(&key);
+        let guard = route.as_deref().map(InFlight::new);
+        let res = next.run(req).await;
+        let elapsed = start.elapsed();
+        drop(guard);
+
+        let route = match route {
+            Some(route) => route,
+            None if res.status() != StatusCode::NotFound => self.get_or_create(&key),
+            None => return Ok(res),
+        };
+        route.latency.add_point(elapsed.as_secs_f64());
+        Ok(res)
+    }
+}
+
+/// The module and route a request path belongs to, sanitized for use in metric names.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct RouteKey {
+    module: String,
+    route: Option<String>,
+}
+
+impl RouteKey {
+    fn parse(path: &str) -> Option<Self> {
+        let mut segments = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .peekable();
+
+        // Skip the optional version prefix.
+        if segments.peek().is_some_and(|segment| is_version(segment)) {
+            segments.next();
+        }
+
+        let module = metric_name(segments.next()?)?;
+        let route = segments.next().and_then(metric_name);
+        Some(Self { module, route })
+    }
+}
+
+pub(super) fn is_version(segment: &str) -> bool {
+    segment
+        .strip_prefix('v')
+        .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Convert a literal path segment to a metric name component.
+///
+/// Returns [`None`] if the segment does not look like a literal (e.g. it is a block height or a
+/// commitment), since including parameter values in metric names would create a metric for each
+/// distinct value.
+fn metric_name(segment: &str) -> Option<String> {
+    if !segment.starts_with(|c: char| c.is_ascii_alphabetic())
+        || !segment
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+    {
+        return None;
+    }
+    Some(segment.replace('-', "_"))
+}
+
+/// A [`Listener`] which installs [`ApiMetrics`] on the server before delegating to another
+/// listener.
+#[derive(Debug)]
+pub(crate) struct MetricsListener<L> {
+    inner: L,
+    metrics: ApiMetrics,
+}
+
+impl<L> MetricsListener<L> {
+    pub(crate) fn new(inner: L, metrics: ApiMetrics) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+impl<L: Display> Display for MetricsListener<L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+#[async_trait]
+impl<State, L> Listener<State> for MetricsListener<L>
+where
+    State: Clone + Send + Sync + 'static,
+    L: Listener<State>,
+{
+    async fn bind(&mut self, mut app: Server<State>) -> io::Result<()> {
+        app.with(self.metrics.clone());
+        self.inner.bind(app).await
+    }
+
+    async fn accept(&mut self) -> io::Result<()> {
+        self.inner.accept().await
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        self.inner.info()
+    }
+}
+
+impl<State, L> ToListener<State> for MetricsListener<L>
+where
+    State: Clone + Send + Sync + 'static,
+    L: Listener<State>,
+{
+    type Listener = Self;
+
+    fn to_listener(self) -> io::Result<Self::Listener> {
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(module: &str, route: Option<&str>) -> Option<RouteKey> {
+        Some(RouteKey {
+            module: module.into(),
+            route: route.map(Into::into),
+        })
+    }
+
+    #[test]
+    fn test_route_key() {
+        assert_eq!(
+            RouteKey::parse("/v0/availability/block/42"),
+            key("availability", Some("block"))
+        );
+        assert_eq!(
+            RouteKey::parse("/availability/block/hash/BLOCK~abc"),
+            key("availability", Some("block"))
+        );
+        assert_eq!(
+            RouteKey::parse("/v1/state-signature/block-height"),
+            key("state_signature", Some("block_height"))
+        );
+        assert_eq!(
+            RouteKey::parse("/v0/block-state/42/7"),
+            key("block_state", None)
+        );
+        assert_eq!(RouteKey::parse("/healthcheck"), key("healthcheck", None));
+        assert_eq!(RouteKey::parse("/"), None);
+        assert_eq!(RouteKey::parse("/v0"), None);
+        assert_eq!(RouteKey::parse("/v0/Availability/block"), None);
+    }
+}
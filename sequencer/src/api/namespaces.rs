@@ -0,0 +1,434 @@
+//! Human-readable names for namespaces.
+//!
+//! Rollups are identified on chain only by their numeric namespace IDs, which makes dashboards and
+//! API responses hard to read. A node can optionally load a registry of namespace names, from a
+//! TOML file, from an L1 registry contract, or both:
+//!
+//! ```toml
+//! [[namespace]]
+//! id = 42
+//! name = "my-rollup"
+//! ```
+//!
+//! The registry contract must implement `namespaces() returns (uint32[] ids, string[] names)`.
+//! Both sources are reloaded periodically, and names from the file take precedence over those from
+//! the contract, so an operator can override or add to the on-chain registry locally.
+//!
+//! Registered names label namespaces in the explorer summary and in the `namespaces` API, which
+//! lists every known namespace along with statistics about the data sequenced in it. Each
+//! registered namespace also gets its own metrics in the `namespaces` group, named after the
+//! namespace.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{ensure, Context};
+use clap::Parser;
+use espresso_types::{parse_duration, NamespaceId};
+use ethers::{
+    contract::abigen,
+    providers::{Http, Provider},
+    types::Address,
+};
+use hotshot_types::traits::metrics::{Counter, Metrics};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+use url::Url;
+
+use super::stats::BlockStats;
+
+abigen!(
+    NamespaceRegistryContract,
+    r#"[
+        function namespaces() external view returns (uint32[] ids, string[] names)
+    ]"#
+);
+
+/// Options for the namespace registry.
+#[derive(Parser, Clone, Debug)]
+pub struct NamespaceRegistryOptions {
+    /// TOML file assigning names to namespace IDs.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_NAMESPACE_REGISTRY_FILE")]
+    pub namespace_registry_file: Option<PathBuf>,
+
+    /// Address of an L1 contract to load namespace names from.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_NAMESPACE_REGISTRY_CONTRACT")]
+    pub namespace_registry_contract: Option<Address>,
+
+    /// URL of the L1 JSON-RPC provider used to read the registry contract.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_NAMESPACE_REGISTRY_L1_PROVIDER")]
+    pub namespace_registry_l1_provider: Option<Url>,
+
+    /// How often to reload the registry file and contract.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_NAMESPACE_REGISTRY_REFRESH_INTERVAL",
+        value_parser = parse_duration,
+        default_value = "10m"
+    )]
+    pub namespace_registry_refresh_interval: Duration,
+}
+
+impl Default for NamespaceRegistryOptions {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+/// A namespace, as reported by `namespaces/list`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespaceInfo {
+    pub namespace: NamespaceId,
+    /// The registered name of this namespace, if any.
+    pub name: Option<String>,
+    /// Number of blocks with data in this namespace.
+    pub num_blocks: u64,
+    /// Total payload bytes sequenced in this namespace.
+    pub bytes: u64,
+    /// The latest block with data in this namespace, if any.
+    pub last_height: Option<u64>,
+}
+
+/// Statistics about the data sequenced in a namespace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NamespaceStats {
+    pub namespace: NamespaceId,
+    pub num_blocks: u64,
+    pub bytes: u64,
+    pub last_height: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RegistryFile {
+    #[serde(default)]
+    namespace: Vec<RegistryEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RegistryEntry {
+    id: u32,
+    name: String,
+}
+
+/// Parse a registry file.
+fn parse_registry(contents: &str) -> anyhow::Result<BTreeMap<NamespaceId, String>> {
+    let file: RegistryFile = toml::from_str(contents)?;
+    let mut names = BTreeMap::new();
+    for entry in file.namespace {
+        ensure!(
+            names
+                .insert(NamespaceId::from(entry.id), entry.name)
+                .is_none(),
+            "namespace {} is registered more than once",
+            entry.id
+        );
+    }
+    Ok(names)
+}
+
+fn load_registry_file(path: &Path) -> anyhow::Result<BTreeMap<NamespaceId, String>> {
+    let contents = fs::read_to_string(path)?;
+    parse_registry(&contents)
+}
+
+/// The names of known namespaces.
+#[derive(Clone, Debug, Default)]
+pub struct NamespaceRegistry {
+    names: Arc<RwLock<BTreeMap<NamespaceId, String>>>,
+}
+
+impl NamespaceRegistry {
+    /// The registered name of `namespace`, if any.
+    pub fn name(&self, namespace: NamespaceId) -> Option<String> {
+        self.names.read().get(&namespace).cloned()
+    }
+
+    /// Every registered namespace, with its name.
+    pub fn names(&self) -> BTreeMap<NamespaceId, String> {
+        self.names.read().clone()
+    }
+
+    fn set(&self, names: BTreeMap<NamespaceId, String>) {
+        *self.names.write() = names;
+    }
+
+    /// Combine statistics for namespaces which have data with the registered names.
+    ///
+    /// Registered namespaces without any data are included with empty statistics.
+    pub fn list(&self, stats: impl IntoIterator<Item = NamespaceStats>) -> Vec<NamespaceInfo> {
+        let mut names = self.names();
+        let mut namespaces = stats
+            .into_iter()
+            .map(|stats| {
+                (
+                    stats.namespace,
+                    NamespaceInfo {
+                        namespace: stats.namespace,
+                        name: names.remove(&stats.namespace),
+                        num_blocks: stats.num_blocks,
+                        bytes: stats.bytes,
+                        last_height: Some(stats.last_height),
+                    },
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+        for (namespace, name) in names {
+            namespaces.insert(
+                namespace,
+                NamespaceInfo {
+                    namespace,
+                    name: Some(name),
+                    num_blocks: 0,
+                    bytes: 0,
+                    last_height: None,
+                },
+            );
+        }
+        namespaces.into_values().collect()
+    }
+}
+
+/// Background task which keeps a [`NamespaceRegistry`] up to date.
+#[derive(Debug)]
+pub(crate) struct NamespaceRegistryLoader {
+    registry: NamespaceRegistry,
+    file: Option<PathBuf>,
+    contract: Option<NamespaceRegistryContract<Provider<Http>>>,
+    interval: Duration,
+}
+
+impl NamespaceRegistryLoader {
+    /// Load the registry file, if any, and prepare to keep `registry` up to date.
+    ///
+    /// Returns [`None`] if no registry is configured.
+    pub(crate) fn new(
+        opt: &NamespaceRegistryOptions,
+        registry: NamespaceRegistry,
+    ) -> anyhow::Result<Option<Self>> {
+        let contract = match opt.namespace_registry_contract {
+            Some(address) => {
+                let url = opt.namespace_registry_l1_provider.as_ref().context(
+                    "namespace registry contract requires --namespace-registry-l1-provider",
+                )?;
+                let provider = Provider::try_from(url.to_string())?;
+                Some(NamespaceRegistryContract::new(address, Arc::new(provider)))
+            }
+            None => None,
+        };
+        if opt.namespace_registry_file.is_none() && contract.is_none() {
+            return Ok(None);
+        }
+
+        // Fail fast on an invalid file, rather than running without the names it registers.
+        if let Some(path) = &opt.namespace_registry_file {
+            let names = load_registry_file(path)
+                .with_context(|| format!("loading namespace registry {}", path.display()))?;
+            registry.set(names);
+        }
+        Ok(Some(Self {
+            registry,
+            file: opt.namespace_registry_file.clone(),
+            contract,
+            interval: opt.namespace_registry_refresh_interval,
+        }))
+    }
+
+    /// Reload the registry periodically, forever.
+    pub(crate) async fn run(self) {
+        loop {
+            match self.load().await {
+                Ok(names) => {
+                    tracing::debug!(namespaces = names.len(), "reloaded namespace registry");
+                    self.registry.set(names);
+                }
+                Err(err) => tracing::warn!("unable to reload namespace registry: {err:#}"),
+            }
+            sleep(self.interval).await;
+        }
+    }
+
+    async fn load(&self) -> anyhow::Result<BTreeMap<NamespaceId, String>> {
+        let mut names = BTreeMap::new();
+        if let Some(contract) = &self.contract {
+            let (ids, contract_names) = contract
+                .namespaces()
+                .call()
+                .await
+                .context("reading namespace registry contract")?;
+            ensure!(
+                ids.len() == contract_names.len(),
+                "registry contract returned {} IDs but {} names",
+                ids.len(),
+                contract_names.len()
+            );
+            names.extend(ids.into_iter().map(NamespaceId::from).zip(contract_names));
+        }
+        if let Some(path) = &self.file {
+            names.extend(
+                load_registry_file(path)
+                    .with_context(|| format!("loading namespace registry {}", path.display()))?,
+            );
+        }
+        Ok(names)
+    }
+}
+
+/// Per-namespace metrics for registered namespaces.
+///
+/// Metrics are only created for registered namespaces, so that arbitrary namespaces cannot create
+/// an unbounded number of metrics.
+#[derive(Debug)]
+pub(crate) struct NamespaceMetrics {
+    registry: NamespaceRegistry,
+    metrics: Box<dyn Metrics>,
+    namespaces: Mutex<HashMap<(NamespaceId, String), NamespaceCounters>>,
+}
+
+#[derive(Debug)]
+struct NamespaceCounters {
+    blocks: Box<dyn Counter>,
+    bytes: Box<dyn Counter>,
+}
+
+impl NamespaceMetrics {
+    pub(crate) fn new(registry: NamespaceRegistry, metrics: &dyn Metrics) -> Self {
+        Self {
+            registry,
+            metrics: metrics.subgroup("namespaces".into()),
+            namespaces: Default::default(),
+        }
+    }
+
+    /// Record the data sequenced in each registered namespace of a block.
+    pub(crate) fn record(&self, stats: &BlockStats) {
+        let mut namespaces = self.namespaces.lock();
+        for ns in &stats.namespaces {
+            let Some(name) = self.registry.name(ns.namespace) else {
+                continue;
+            };
+            let counters =
+                namespaces
+                    .entry((ns.namespace, name))
+                    .or_insert_with_key(|(_, name)| {
+                        let metrics = self.metrics.subgroup(metric_label(name));
+                        NamespaceCounters {
+                            blocks: metrics.create_counter("blocks".into(), None),
+                            bytes: metrics.create_counter("bytes".into(), Some("bytes".into())),
+                        }
+                    });
+            counters.blocks.add(1);
+            counters.bytes.add(ns.bytes as usize);
+        }
+    }
+}
+
+/// Turn a namespace name into a valid metric name component.
+fn metric_label(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_registry() {
+        let names = parse_registry(
+            r#"
+            [[namespace]]
+            id = 1
+            name = "one"
+
+            [[namespace]]
+            id = 2
+            name = "two"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            names,
+            [
+                (NamespaceId::from(1u32), "one".to_string()),
+                (NamespaceId::from(2u32), "two".to_string())
+            ]
+            .into()
+        );
+        assert_eq!(parse_registry("").unwrap(), BTreeMap::new());
+
+        parse_registry(
+            r#"
+            [[namespace]]
+            id = 1
+            name = "one"
+
+            [[namespace]]
+            id = 1
+            name = "uno"
+            "#,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_list_namespaces() {
+        let registry = NamespaceRegistry::default();
+        registry.set(
+            [
+                (NamespaceId::from(1u32), "one".to_string()),
+                (NamespaceId::from(3u32), "three".to_string()),
+            ]
+            .into(),
+        );
+        let stats = |id: u32| NamespaceStats {
+            namespace: NamespaceId::from(id),
+            num_blocks: id as u64,
+            bytes: 10 * id as u64,
+            last_height: 100 + id as u64,
+        };
+        assert_eq!(
+            registry.list([stats(2), stats(1)]),
+            [
+                NamespaceInfo {
+                    namespace: NamespaceId::from(1u32),
+                    name: Some("one".into()),
+                    num_blocks: 1,
+                    bytes: 10,
+                    last_height: Some(101),
+                },
+                NamespaceInfo {
+                    namespace: NamespaceId::from(2u32),
+                    name: None,
+                    num_blocks: 2,
+                    bytes: 20,
+                    last_height: Some(102),
+                },
+                NamespaceInfo {
+                    namespace: NamespaceId::from(3u32),
+                    name: Some("three".into()),
+                    num_blocks: 0,
+                    bytes: 0,
+                    last_height: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_metric_label() {
+        assert_eq!(metric_label("My-Rollup 2"), "my_rollup_2");
+    }
+}
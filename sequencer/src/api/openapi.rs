@@ -0,0 +1,124 @@
+//! OpenAPI specification of the public API.
+//!
+//! The build script generates an OpenAPI 3.0 document from the TOML route definitions of the API
+//! modules defined in this crate, so that clients generated from it, for TypeScript, Go or any
+//! other language, stay in sync with the routes the node actually serves. The [`ApiSpec`]
+//! middleware serves the document at `/api-spec.json`.
+//!
+//! Like [`ResponseCompression`](super::compression::ResponseCompression), [`ApiSpec`] is installed
+//! by wrapping the listener the app is served on, in an [`ApiSpecListener`].
+
+use std::{
+    fmt::{self, Display, Formatter},
+    io,
+};
+
+use async_trait::async_trait;
+use tide::{
+    http::{mime, Method},
+    listener::{ListenInfo, Listener, ToListener},
+    Middleware, Next, Request, Response, Server, StatusCode,
+};
+
+/// The OpenAPI document generated by the build script.
+pub const API_SPEC: &str = include_str!(concat!(env!("OUT_DIR"), "/api-spec.json"));
+
+/// The path at which the OpenAPI document is served.
+const API_SPEC_PATH: &str = "/api-spec.json";
+
+/// Middleware which serves the OpenAPI document of the public API.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ApiSpec;
+
+#[async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for ApiSpec {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        if req.method() != Method::Get || req.url().path() != API_SPEC_PATH {
+            return Ok(next.run(req).await);
+        }
+        Ok(Response::builder(StatusCode::Ok)
+            .body(API_SPEC)
+            .content_type(mime::JSON)
+            .build())
+    }
+}
+
+/// A [`Listener`] which installs [`ApiSpec`], if enabled, on the server before delegating to
+/// another listener.
+#[derive(Debug)]
+pub(crate) struct ApiSpecListener<L> {
+    inner: L,
+    spec: Option<ApiSpec>,
+}
+
+impl<L> ApiSpecListener<L> {
+    pub(crate) fn new(inner: L, spec: Option<ApiSpec>) -> Self {
+        Self { inner, spec }
+    }
+}
+
+impl<L: Display> Display for ApiSpecListener<L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+#[async_trait]
+impl<State, L> Listener<State> for ApiSpecListener<L>
+where
+    State: Clone + Send + Sync + 'static,
+    L: Listener<State>,
+{
+    async fn bind(&mut self, mut app: Server<State>) -> io::Result<()> {
+        if let Some(spec) = self.spec {
+            app.with(spec);
+        }
+        self.inner.bind(app).await
+    }
+
+    async fn accept(&mut self) -> io::Result<()> {
+        self.inner.accept().await
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        self.inner.info()
+    }
+}
+
+impl<State, L> ToListener<State> for ApiSpecListener<L>
+where
+    State: Clone + Send + Sync + 'static,
+    L: Listener<State>,
+{
+    type Listener = Self;
+
+    fn to_listener(self) -> io::Result<Self::Listener> {
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn test_api_spec() {
+        let spec: Value = serde_json::from_str(API_SPEC).unwrap();
+        assert_eq!(spec["openapi"], "3.0.3");
+
+        let paths = &spec["paths"];
+        assert!(paths["/submit/submit"]["post"].is_object());
+        assert!(paths["/submit/status/{hash}"]["get"].is_object());
+
+        // Every path of a route is listed, with typed parameters.
+        let replay = &paths["/events/replay/{from_height}/{limit}"]["get"];
+        assert_eq!(replay["tags"][0], "events");
+        let params = replay["parameters"].as_array().unwrap();
+        assert_eq!(params.len(), 2);
+        assert_eq!(params[0]["name"], "from_height");
+        assert_eq!(params[0]["schema"]["type"], "integer");
+        assert!(paths["/events/replay/{from_height}"]["get"].is_object());
+    }
+}
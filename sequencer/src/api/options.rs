@@ -1,47 +1,98 @@
 //! Sequencer-specific API options and initialization.
 
-use anyhow::{bail, Context};
-use clap::Parser;
+use anyhow::{bail, ensure, Context};
+use clap::{Parser, ValueEnum};
+use derivative::Derivative;
 use espresso_types::{
+    parse_duration,
     v0::traits::{EventConsumer, NullEventConsumer, SequencerPersistence},
-    BlockMerkleTree, PubKey,
+    BlockMerkleTree, FeeVersion, PubKey, SequencerVersions, V0_0,
 };
 use futures::{
     channel::oneshot,
-    future::{BoxFuture, Future},
+    future::{self, BoxFuture, Future},
 };
 use hotshot_events_service::events::Error as EventStreamingError;
 use hotshot_query_service::{
     data_source::{ExtensibleDataSource, MetricsDataSource},
-    status::{self, UpdateStatusData},
-    ApiState as AppState, Error,
+    metrics::PrometheusMetrics,
+    status::{HasMetrics, UpdateStatusData},
+    ApiState as AppState,
 };
 use hotshot_types::traits::{
     metrics::{Metrics, NoMetrics},
     network::ConnectedNetwork,
     node_implementation::Versions,
 };
-use std::sync::Arc;
+use std::{
+    iter::once,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use tide::listener::ConcurrentListener;
 use tide_disco::{listener::RateLimitListener, method::ReadState, App, Url};
-use vbs::version::StaticVersionType;
+use tokio::spawn;
+use vbs::version::{StaticVersionType, Version};
 
 use super::{
+    archive::PayloadArchiveOptions,
+    auth::{ApiAuth, AuthListener},
+    backfill::{Backfill, BackfillOptions, BackfillProgress},
+    cache::{QueryCache, QueryCacheOptions},
+    catchup_cache::AccountProofCache,
+    catchup_limits::CatchupLimiter,
+    compression::{CompressionListener, ResponseCompression},
     data_source::{
-        provider, CatchupDataSource, HotShotConfigDataSource, NodeStateDataSource,
-        SequencerDataSource, StateSignatureDataSource, SubmitDataSource,
+        provider, CatchupDataSource, ChainConfigHistoryDataSource, DecideEventDataSource,
+        FeeEstimateDataSource, HotShotConfigDataSource, MempoolDataSource, NodeStateDataSource,
+        SequencerDataSource, SolverDataSource, StateSignatureDataSource, SubmitDataSource,
+        TxStatusDataSource, UpgradeDataSource,
+    },
+    db_pool::PoolMonitor,
+    endpoints,
+    error::ApiError,
+    fanout::{EventSink, FanoutEventConsumer},
+    fee_deposits, fs,
+    limits::{ApiLimits, LimitsListener},
+    metrics::{ApiMetrics, MetricsListener},
+    namespaces::{
+        NamespaceMetrics, NamespaceRegistry, NamespaceRegistryLoader, NamespaceRegistryOptions,
     },
-    endpoints, fs, sql,
+    openapi::{ApiSpec, ApiSpecListener},
+    otlp::{OtlpExporter, OtlpOptions},
+    pagination::{PaginationListener, RangePagination},
+    peers::QueryPeers,
+    pruner::{PayloadPruner, PayloadPruningOptions},
+    rate_limit::{NamespaceRateLimit, SubmitRateLimiter},
+    routing::{ModuleListener, ModuleRouting, RoutingListener},
+    sql,
+    stats::{update_explorer_stats_loop, ExplorerStatsOptions},
+    stream::{StreamFormat, StreamSink},
+    telemetry::{StorageBackend, Telemetry, TelemetryOptions},
+    tls::{TlsConfig, TlsListener},
+    tx_validation::{NamespaceRange, TxValidator},
     update::ApiEventConsumer,
-    ApiState, StorageState,
+    vid_repair::{VidRepair, VidRepairOptions},
+    AdminState, ApiState, StorageState,
 };
 use crate::{
     catchup::CatchupStorage,
     context::{SequencerContext, TaskList},
-    persistence,
+    network, persistence,
     state::update_state_storage_loop,
+    supervisor::RestartPolicy,
     SequencerApiVersion,
 };
 
+/// API state of a read replica, which has no consensus handle.
+type ReplicaState = ApiState<
+    network::Production,
+    persistence::sql::Persistence,
+    SequencerVersions<FeeVersion, V0_0>,
+>;
+
 #[derive(Clone, Debug)]
 pub struct Options {
     pub http: Http,
@@ -53,8 +104,16 @@ pub struct Options {
     pub state: Option<State>,
     pub hotshot_events: Option<HotshotEvents>,
     pub explorer: Option<Explorer>,
+    pub fee: Option<Fee>,
+    pub marketplace: Option<Marketplace>,
+    pub admin: Option<Admin>,
+    pub auth: Option<Auth>,
+    #[cfg(feature = "grpc")]
+    pub grpc: Option<Grpc>,
     pub storage_fs: Option<persistence::fs::Options>,
     pub storage_sql: Option<persistence::sql::Options>,
+    pub stream: Option<Stream>,
+    pub event_sinks: Vec<EventSink>,
 }
 
 impl From<Http> for Options {
@@ -69,8 +128,16 @@ impl From<Http> for Options {
             state: None,
             hotshot_events: None,
             explorer: None,
+            fee: None,
+            marketplace: None,
+            admin: None,
+            auth: None,
+            #[cfg(feature = "grpc")]
+            grpc: None,
             storage_fs: None,
             storage_sql: None,
+            stream: None,
+            event_sinks: vec![],
         }
     }
 }
@@ -119,6 +186,12 @@ impl Options {
         self
     }
 
+    /// Add a marketplace API module.
+    pub fn marketplace(mut self, opt: Marketplace) -> Self {
+        self.marketplace = Some(opt);
+        self
+    }
+
     /// Add a state API module.
     pub fn state(mut self, opt: State) -> Self {
         self.state = Some(opt);
@@ -137,11 +210,92 @@ impl Options {
         self
     }
 
+    /// Add a fee account API module.
+    pub fn fee(mut self, opt: Fee) -> Self {
+        self.fee = Some(opt);
+        self
+    }
+
+    /// Add an admin API server.
+    pub fn admin(mut self, opt: Admin) -> Self {
+        self.admin = Some(opt);
+        self
+    }
+
+    /// Require API keys for protected modules.
+    pub fn auth(mut self, opt: Auth) -> Self {
+        self.auth = Some(opt);
+        self
+    }
+
+    /// Add a gRPC API server.
+    #[cfg(feature = "grpc")]
+    pub fn grpc(mut self, opt: Grpc) -> Self {
+        self.grpc = Some(opt);
+        self
+    }
+
+    /// Publish decided blocks to a NATS server.
+    pub fn stream(mut self, opt: Stream) -> Self {
+        self.stream = Some(opt);
+        self
+    }
+
+    /// Send decided events to an additional storage sink.
+    ///
+    /// Sinks are updated after the query storage, if any, and each has its own queue, so a slow or
+    /// failing sink does not hold up consensus or other sinks.
+    pub fn event_sink(mut self, sink: EventSink) -> Self {
+        self.event_sinks.push(sink);
+        self
+    }
+
     /// Whether these options will run the query API.
     pub fn has_query_module(&self) -> bool {
         self.query.is_some() && (self.storage_fs.is_some() || self.storage_sql.is_some())
     }
 
+    /// The names of the optional modules enabled by these options.
+    fn enabled_modules(&self) -> Vec<String> {
+        let mut modules = vec![];
+        let mut add = |name: &str, enabled: bool| {
+            if enabled {
+                modules.push(name.to_string());
+            }
+        };
+        add("query", self.query.is_some());
+        add("submit", self.submit.is_some());
+        add("status", self.status.is_some());
+        add("catchup", self.catchup.is_some());
+        add("config", self.config.is_some());
+        add("state", self.state.is_some());
+        add("hotshot-events", self.hotshot_events.is_some());
+        add("explorer", self.explorer.is_some());
+        add("fee", self.fee.is_some());
+        add("marketplace", self.marketplace.is_some());
+        add("admin", self.admin.is_some());
+        add("auth", self.auth.is_some());
+        add("stream", self.stream.is_some());
+        #[cfg(feature = "grpc")]
+        add("grpc", self.grpc.is_some());
+        modules
+    }
+
+    /// The storage backend used by the query service, if any.
+    fn storage_backend(&self) -> StorageBackend {
+        if !self.has_query_module() {
+            StorageBackend::None
+        } else if self.storage_sql.is_some() {
+            if cfg!(feature = "embedded-db") {
+                StorageBackend::Sqlite
+            } else {
+                StorageBackend::Postgres
+            }
+        } else {
+            StorageBackend::FileSystem
+        }
+    }
+
     /// Start the server.
     ///
     /// The function `init_context` is used to create a sequencer context from a metrics object and
@@ -170,6 +324,30 @@ impl Options {
         });
         let mut tasks = TaskList::default();
 
+        let telemetry = Telemetry::new(self.enabled_modules(), self.storage_backend());
+        let mut event_sinks = std::mem::take(&mut self.event_sinks);
+        if let Some(stream) = self.stream.take() {
+            event_sinks.push(StreamSink::event_sink(&stream));
+        }
+        if let Some(TelemetryOptions {
+            telemetry_url: Some(url),
+            telemetry_interval,
+        }) = self.status.as_ref().map(|status| &status.telemetry)
+        {
+            tracing::info!(%url, "reporting telemetry");
+            tasks.spawn(
+                "telemetry",
+                telemetry.clone().push(url.clone(), *telemetry_interval),
+            );
+        }
+
+        if let Some(fee) = self.fee {
+            let state = state.clone();
+            tasks.spawn_supervised("fee deposit indexer", RestartPolicy::restart(), move || {
+                fee_deposits::index(state.clone(), fee)
+            });
+        }
+
         // The server state type depends on whether we are running a query or status API or not, so
         // we handle the two cases differently.
         let (metrics, consumer): (Box<dyn Metrics>, Box<dyn EventConsumer>) =
@@ -179,6 +357,7 @@ impl Options {
                         query_opt,
                         opt,
                         state,
+                        telemetry,
                         &mut tasks,
                         SequencerApiVersion::instance(),
                     )
@@ -188,6 +367,7 @@ impl Options {
                         query_opt,
                         opt,
                         state,
+                        telemetry,
                         &mut tasks,
                         SequencerApiVersion::instance(),
                     )
@@ -201,16 +381,17 @@ impl Options {
                 // storage.
                 let ds = MetricsDataSource::default();
                 let metrics = ds.populate_metrics();
-                let mut app = App::<_, Error>::with_state(AppState::from(
+                self.init_and_spawn_otlp_exporter(ds.metrics(), &mut tasks);
+                let mut app = App::<_, ApiError>::with_state(AppState::from(
                     ExtensibleDataSource::new(ds, state.clone()),
                 ));
 
                 // Initialize status API.
                 let status_api =
-                    status::define_api(&Default::default(), SequencerApiVersion::instance())?;
+                    endpoints::status(None, telemetry, SequencerApiVersion::instance())?;
                 app.register_module("status", status_api)?;
 
-                self.init_hotshot_modules(&mut app)?;
+                self.init_hotshot_modules::<N, P, V, _>(&mut app, &*metrics)?;
 
                 if self.hotshot_events.is_some() {
                     self.init_and_spawn_hotshot_event_streaming_module(state, &mut tasks)?;
@@ -218,7 +399,12 @@ impl Options {
 
                 tasks.spawn(
                     "API server",
-                    self.listen(self.http.port, app, SequencerApiVersion::instance()),
+                    self.listen(
+                        self.http.address(),
+                        app,
+                        &*metrics,
+                        SequencerApiVersion::instance(),
+                    ),
                 );
 
                 (metrics, Box::new(NullEventConsumer))
@@ -229,9 +415,9 @@ impl Options {
                 //
                 // If we have no availability API, we cannot load a saved leaf from local storage,
                 // so we better have been provided the leaf ahead of time if we want it at all.
-                let mut app = App::<_, Error>::with_state(AppState::from(state.clone()));
+                let mut app = App::<_, ApiError>::with_state(AppState::from(state.clone()));
 
-                self.init_hotshot_modules(&mut app)?;
+                self.init_hotshot_modules::<N, P, V, _>(&mut app, &NoMetrics)?;
 
                 if self.hotshot_events.is_some() {
                     self.init_and_spawn_hotshot_event_streaming_module(state, &mut tasks)?;
@@ -239,11 +425,26 @@ impl Options {
 
                 tasks.spawn(
                     "API server",
-                    self.listen(self.http.port, app, SequencerApiVersion::instance()),
+                    self.listen(
+                        self.http.address(),
+                        app,
+                        &NoMetrics,
+                        SequencerApiVersion::instance(),
+                    ),
                 );
 
                 (Box::new(NoMetrics), Box::new(NullEventConsumer))
             };
+        let consumer: Box<dyn EventConsumer> = if event_sinks.is_empty() {
+            consumer
+        } else {
+            Box::new(FanoutEventConsumer::new(
+                consumer,
+                &event_sinks,
+                &*metrics,
+                &mut tasks,
+            ))
+        };
 
         let ctx = init_context(metrics, consumer).await?;
         send_ctx
@@ -253,15 +454,80 @@ impl Options {
         Ok(ctx.with_task_list(tasks))
     }
 
+    /// Serve the query API from a read replica of another node's SQL database.
+    ///
+    /// The replica does not participate in consensus: it only serves data which the node
+    /// populating the database has already written, so that the query API can be scaled
+    /// horizontally without running more consensus instances. Only the availability, node and
+    /// explorer APIs are served; endpoints which need a consensus handle never complete. This runs
+    /// until the server exits.
+    pub async fn serve_replica(mut self) -> anyhow::Result<()> {
+        let query_opt = self
+            .query
+            .take()
+            .context("read replica requires the query module")?;
+        let mut mod_opt = self
+            .storage_sql
+            .take()
+            .context("read replica requires SQL storage")?;
+        mod_opt.read_only = true;
+        mod_opt.disable_proactive_fetching = true;
+
+        // A replica never gets a consensus handle, so the network, persistence and version types
+        // of its API state are only placeholders.
+        let state: ReplicaState = ApiState::new(future::pending());
+        let bind_version = SequencerApiVersion::instance();
+
+        // Missing data is never fetched from peers, since it could not be stored anyway.
+        let ds = sql::DataSource::create(
+            mod_opt,
+            provider(QueryPeers::new([], bind_version), None),
+            false,
+        )
+        .await?;
+        let telemetry = Telemetry::new(self.enabled_modules(), self.storage_backend());
+        let (metrics, _, _, mut app) = self
+            .init_app_modules(ds, state, query_opt.cache, None, telemetry, bind_version)
+            .await?;
+
+        // Explorer statistics are kept up to date by the node populating the database.
+        if let Some(explorer) = &self.explorer {
+            let registry = NamespaceRegistry::default();
+            if let Some(loader) =
+                NamespaceRegistryLoader::new(&explorer.registry, registry.clone())?
+            {
+                spawn(loader.run());
+            }
+            app.register_module("explorer", endpoints::explorer()?)?;
+            app.register_module(
+                "explorer-stats",
+                endpoints::explorer_stats(
+                    explorer.stats.explorer_stats_windows.clone(),
+                    registry.clone(),
+                    bind_version,
+                )?,
+            )?;
+            app.register_module("namespaces", endpoints::namespaces(registry, bind_version)?)?;
+        }
+
+        tracing::info!(port = self.http.port, "serving query API from read replica");
+        self.listen(self.http.address(), app, &*metrics, bind_version)
+            .await
+    }
+
     async fn init_app_modules<N, P, D, V: Versions>(
         &self,
         ds: D,
         state: ApiState<N, P, V>,
+        cache_opt: QueryCacheOptions,
+        backfill: Option<BackfillProgress>,
+        telemetry: Telemetry,
         bind_version: SequencerApiVersion,
     ) -> anyhow::Result<(
         Box<dyn Metrics>,
         Arc<StorageState<N, P, D, V>>,
-        App<AppState<StorageState<N, P, D, V>>, Error>,
+        QueryCache,
+        App<AppState<StorageState<N, P, D, V>>, ApiError>,
     )>
     where
         N: ConnectedNetwork<PubKey>,
@@ -269,25 +535,35 @@ impl Options {
         D: SequencerDataSource + CatchupStorage + Send + Sync + 'static,
     {
         let metrics = ds.populate_metrics();
+        let cache = QueryCache::new(cache_opt, &*metrics);
         let ds = Arc::new(ExtensibleDataSource::new(ds, state.clone()));
         let api_state: endpoints::AvailState<N, P, D, V> = ds.clone().into();
-        let mut app = App::<_, Error>::with_state(api_state);
+        let mut app = App::<_, ApiError>::with_state(api_state);
 
         // Initialize status API
         if self.status.is_some() {
-            let status_api = status::define_api::<endpoints::AvailState<N, P, D, _>, _>(
-                &Default::default(),
+            let status_api = endpoints::status::<endpoints::AvailState<N, P, D, _>, _>(
+                backfill,
+                telemetry,
                 bind_version,
             )?;
             app.register_module("status", status_api)?;
         }
 
         // Initialize availability and node APIs (these both use the same data source).
-        app.register_module("availability", endpoints::availability()?)?;
+        app.register_module(
+            "availability",
+            endpoints::availability(cache.clone(), self.http.max_range_size)?,
+        )?;
         app.register_module("node", endpoints::node()?)?;
 
-        self.init_hotshot_modules(&mut app)?;
-        Ok((metrics, ds, app))
+        // Initialize fee account API.
+        if self.fee.is_some() {
+            app.register_module("fee", endpoints::fee(bind_version)?)?;
+        }
+
+        self.init_hotshot_modules::<N, P, V, _>(&mut app, &*metrics)?;
+        Ok((metrics, ds, cache, app))
     }
 
     async fn init_with_query_module_fs<N, P, V: Versions + 'static>(
@@ -295,6 +571,7 @@ impl Options {
         query_opt: Query,
         mod_opt: persistence::fs::Options,
         state: ApiState<N, P, V>,
+        telemetry: Telemetry,
         tasks: &mut TaskList,
         bind_version: SequencerApiVersion,
     ) -> anyhow::Result<(Box<dyn Metrics>, Box<dyn EventConsumer>)>
@@ -302,30 +579,77 @@ impl Options {
         N: ConnectedNetwork<PubKey>,
         P: SequencerPersistence,
     {
+        if query_opt.query_mode == QueryMode::HeadersOnly {
+            bail!("headers-only query mode is not supported with file system storage");
+        }
+        if query_opt.pruning.is_enabled() {
+            bail!("payload pruning is not supported with file system storage");
+        }
+        if query_opt.archive.payload_archive_url.is_some() {
+            bail!("payload archiving is not supported with file system storage");
+        }
+
+        let peers = QueryPeers::new(query_opt.peers, bind_version)
+            .with_concurrency(query_opt.peer_fetch_concurrency);
         let ds = <fs::DataSource as SequencerDataSource>::create(
             mod_opt,
-            provider::<V>(query_opt.peers, bind_version),
+            provider(peers.clone(), None),
             false,
         )
         .await?;
 
-        let (metrics, ds, app) = self
-            .init_app_modules(ds, state.clone(), bind_version)
+        let backfill = query_opt.backfill.backfill.then(BackfillProgress::default);
+
+        // The cache is only used outside of the HTTP app by the gRPC server.
+        #[cfg_attr(not(feature = "grpc"), allow(unused_variables))]
+        let (metrics, ds, cache, app) = self
+            .init_app_modules(
+                ds,
+                state.clone(),
+                query_opt.cache,
+                backfill.clone(),
+                telemetry,
+                bind_version,
+            )
             .await?;
+        self.init_and_spawn_otlp_exporter(ds.metrics(), tasks);
+
+        if let Some(progress) = backfill {
+            tasks.spawn(
+                "backfill",
+                Backfill::new(ds.clone(), query_opt.backfill, progress).run(),
+            );
+        }
+        if query_opt.vid_repair.vid_repair {
+            let repair = VidRepair::new(ds.clone(), peers.clone(), query_opt.vid_repair, &*metrics);
+            let state = state.clone();
+            tasks.spawn("VID repair", async move {
+                repair.run(state.vid_share_index().await).await
+            });
+        }
+
+        self.init_and_spawn_admin_server(state.clone(), peers, tasks)?;
 
         if self.hotshot_events.is_some() {
             self.init_and_spawn_hotshot_event_streaming_module(state, tasks)?;
         }
 
-        tasks.spawn("API server", self.listen(self.http.port, app, bind_version));
+        #[cfg(feature = "grpc")]
+        self.init_and_spawn_grpc_server(ds.clone(), cache, &*metrics, tasks);
+
+        tasks.spawn(
+            "API server",
+            self.listen(self.http.address(), app, &*metrics, bind_version),
+        );
         Ok((metrics, Box::new(ApiEventConsumer::from(ds))))
     }
 
     async fn init_with_query_module_sql<N, P, V: Versions + 'static>(
         self,
-        query_opt: Query,
-        mod_opt: persistence::sql::Options,
+        mut query_opt: Query,
+        mut mod_opt: persistence::sql::Options,
         state: ApiState<N, P, V>,
+        telemetry: Telemetry,
         tasks: &mut TaskList,
         bind_version: SequencerApiVersion,
     ) -> anyhow::Result<(Box<dyn Metrics>, Box<dyn EventConsumer>)>
@@ -333,18 +657,100 @@ impl Options {
         N: ConnectedNetwork<PubKey>,
         P: SequencerPersistence,
     {
+        if query_opt.query_mode == QueryMode::HeadersOnly {
+            query_opt.pruning = query_opt.pruning.headers_only();
+        }
+        // The query service's own pruner (`--prune`) deletes from the same tables with its own
+        // bookkeeping, and `--archive` fetches pruned data back, so neither can run alongside the
+        // payload pruner.
+        if mod_opt.prune && query_opt.pruning.is_enabled() {
+            bail!("a payload retention policy cannot be combined with --prune");
+        }
+        if mod_opt.archive && query_opt.pruning.is_enabled() {
+            bail!("a payload retention policy cannot be combined with --archive");
+        }
+        if query_opt.pruning.is_enabled() {
+            // Pruned payloads would otherwise be fetched right back from peers.
+            mod_opt.disable_proactive_fetching = true;
+        }
+
+        let archive = query_opt.archive.connect()?;
+        if archive.is_some() && !query_opt.pruning.is_enabled() {
+            bail!("payload archiving requires a payload retention policy");
+        }
+        if query_opt.backfill.backfill && query_opt.pruning.is_enabled() {
+            bail!("backfilling is not supported with a payload retention policy");
+        }
+        if query_opt.vid_repair.vid_repair && query_opt.pruning.is_enabled() {
+            bail!("VID repair is not supported with a payload retention policy");
+        }
+
+        let peers = QueryPeers::new(query_opt.peers.clone(), bind_version)
+            .with_concurrency(query_opt.peer_fetch_concurrency);
         let ds = sql::DataSource::create(
             mod_opt.clone(),
-            provider::<V>(query_opt.peers.clone(), bind_version),
+            provider(peers.clone(), archive.clone()),
             false,
         )
         .await?;
-        let (metrics, ds, mut app) = self
-            .init_app_modules(ds, state.clone(), bind_version)
+        let backfill = query_opt.backfill.backfill.then(BackfillProgress::default);
+
+        // The cache is only used outside of the HTTP app by the gRPC server.
+        #[cfg_attr(not(feature = "grpc"), allow(unused_variables))]
+        let (metrics, ds, cache, mut app) = self
+            .init_app_modules(
+                ds,
+                state.clone(),
+                query_opt.cache,
+                backfill.clone(),
+                telemetry,
+                bind_version,
+            )
             .await?;
+        self.init_and_spawn_otlp_exporter(ds.metrics(), tasks);
 
-        if self.explorer.is_some() {
+        if let Some(progress) = backfill {
+            tasks.spawn(
+                "backfill",
+                Backfill::new(ds.clone(), query_opt.backfill, progress).run(),
+            );
+        }
+        if query_opt.vid_repair.vid_repair {
+            let repair = VidRepair::new(ds.clone(), peers.clone(), query_opt.vid_repair, &*metrics);
+            let state = state.clone();
+            tasks.spawn("VID repair", async move {
+                repair.run(state.vid_share_index().await).await
+            });
+        }
+        tasks.spawn(
+            "database pool monitor",
+            PoolMonitor::new(ds.clone(), mod_opt.max_connections, &*metrics).run(),
+        );
+
+        if let Some(explorer) = &self.explorer {
+            let registry = NamespaceRegistry::default();
+            if let Some(loader) =
+                NamespaceRegistryLoader::new(&explorer.registry, registry.clone())?
+            {
+                tasks.spawn("namespace registry", loader.run());
+            }
             app.register_module("explorer", endpoints::explorer()?)?;
+            app.register_module(
+                "explorer-stats",
+                endpoints::explorer_stats(
+                    explorer.stats.explorer_stats_windows.clone(),
+                    registry.clone(),
+                    bind_version,
+                )?,
+            )?;
+            app.register_module(
+                "namespaces",
+                endpoints::namespaces(registry.clone(), bind_version)?,
+            )?;
+            tasks.spawn(
+                "explorer statistics update loop",
+                update_explorer_stats_loop(ds.clone(), NamespaceMetrics::new(registry, &*metrics)),
+            );
         }
 
         if self.state.is_some() {
@@ -367,13 +773,36 @@ impl Options {
             );
         }
 
+        if query_opt.pruning.is_enabled() {
+            let pruner = Arc::new(PayloadPruner::new(
+                ds.clone(),
+                query_opt.pruning,
+                archive,
+                &*metrics,
+            ));
+            tasks.spawn_supervised("payload pruner", RestartPolicy::restart(), move || {
+                let pruner = pruner.clone();
+                async move { pruner.run().await }
+            });
+        }
+
+        self.init_and_spawn_admin_server(state.clone(), peers, tasks)?;
+
         if self.hotshot_events.is_some() {
             self.init_and_spawn_hotshot_event_streaming_module(state, tasks)?;
         }
 
+        #[cfg(feature = "grpc")]
+        self.init_and_spawn_grpc_server(ds.clone(), cache, &*metrics, tasks);
+
         tasks.spawn(
             "API server",
-            self.listen(self.http.port, app, SequencerApiVersion::instance()),
+            self.listen(
+                self.http.address(),
+                app,
+                &*metrics,
+                SequencerApiVersion::instance(),
+            ),
         );
         Ok((metrics, Box::new(ApiEventConsumer::from(ds))))
     }
@@ -383,38 +812,139 @@ impl Options {
     /// This function adds the `submit`, `state`, and `state_signature` API modules to the given
     /// app. These modules only require a HotShot handle as state, and thus they work with any data
     /// source, so initialization is the same no matter what mode the service is running in.
-    fn init_hotshot_modules<N, P, S>(&self, app: &mut App<S, Error>) -> anyhow::Result<()>
+    ///
+    /// During an upgrade window, the modules are registered a second time with the upgraded
+    /// serialization version, under versioned names (see [`versioned_module`]), so that clients
+    /// which have already upgraded can use them alongside clients which have not.
+    fn init_hotshot_modules<N, P, V, S>(
+        &self,
+        app: &mut App<S, ApiError>,
+        metrics: &dyn Metrics,
+    ) -> anyhow::Result<()>
     where
         S: 'static + Send + Sync + ReadState,
         P: SequencerPersistence,
+        V: Versions,
         S::State: Send
             + Sync
             + SubmitDataSource<N, P>
+            + TxStatusDataSource
+            + MempoolDataSource
+            + FeeEstimateDataSource
             + StateSignatureDataSource<N>
             + NodeStateDataSource
             + CatchupDataSource
-            + HotShotConfigDataSource,
+            + HotShotConfigDataSource
+            + ChainConfigHistoryDataSource
+            + UpgradeDataSource
+            + SolverDataSource
+            + DecideEventDataSource,
         N: ConnectedNetwork<PubKey>,
     {
-        let bind_version = SequencerApiVersion::instance();
+        // Share one rate limiter between all versions of the submit API, so that submitting via
+        // both versions does not double a namespace's limit.
+        let limiter = self
+            .submit
+            .as_ref()
+            .map(|submit| Arc::new(SubmitRateLimiter::new(&submit.rate_limits, metrics)));
+
+        // Likewise, both versions of the catchup API share the same worker pools and cache.
+        let catchup_limiter = self
+            .catchup
+            .as_ref()
+            .map(|catchup| Arc::new(CatchupLimiter::new(catchup, metrics)));
+        let account_cache = self
+            .catchup
+            .as_ref()
+            .map(|catchup| Arc::new(AccountProofCache::new(catchup, metrics)));
+
+        self.register_hotshot_modules::<N, P, S, SequencerApiVersion>(
+            app,
+            limiter.clone(),
+            catchup_limiter.clone(),
+            account_cache.clone(),
+            None,
+        )?;
+        if let Some(version) = super::upgrade_api_version::<V>() {
+            tracing::info!(%version, "serving upgraded API version alongside current version");
+            self.register_hotshot_modules::<N, P, S, V::Upgrade>(
+                app,
+                limiter,
+                catchup_limiter,
+                account_cache,
+                Some(version),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Register the modules for interacting with HotShot, bound to serialization version `ApiVer`.
+    ///
+    /// If `version` is given, modules are registered under [`versioned_module`] names.
+    fn register_hotshot_modules<N, P, S, ApiVer>(
+        &self,
+        app: &mut App<S, ApiError>,
+        limiter: Option<Arc<SubmitRateLimiter>>,
+        catchup_limiter: Option<Arc<CatchupLimiter>>,
+        account_cache: Option<Arc<AccountProofCache>>,
+        version: Option<Version>,
+    ) -> anyhow::Result<()>
+    where
+        S: 'static + Send + Sync + ReadState,
+        P: SequencerPersistence,
+        ApiVer: StaticVersionType + 'static,
+        S::State: Send
+            + Sync
+            + SubmitDataSource<N, P>
+            + TxStatusDataSource
+            + MempoolDataSource
+            + FeeEstimateDataSource
+            + StateSignatureDataSource<N>
+            + NodeStateDataSource
+            + CatchupDataSource
+            + HotShotConfigDataSource
+            + ChainConfigHistoryDataSource
+            + UpgradeDataSource
+            + SolverDataSource
+            + DecideEventDataSource,
+        N: ConnectedNetwork<PubKey>,
+    {
+        let bind_version = ApiVer::instance();
+        let name = |module: &str| match version {
+            Some(version) => versioned_module(module, version),
+            None => module.to_string(),
+        };
+
         // Initialize submit API
-        if self.submit.is_some() {
-            let submit_api = endpoints::submit::<_, _, _, SequencerApiVersion>()?;
-            app.register_module("submit", submit_api)?;
+        if let (Some(limiter), Some(submit)) = (limiter, &self.submit) {
+            let submit_api = endpoints::submit::<_, _, _, ApiVer>(
+                limiter,
+                TxValidator::new(submit),
+                submit.wait_timeout,
+                submit.max_batch_count,
+            )?;
+            app.register_module(&name("submit"), submit_api)?;
         }
 
         // Initialize state API.
-        if self.catchup.is_some() {
+        if let (Some(limiter), Some(cache)) = (catchup_limiter, account_cache) {
             tracing::info!("initializing state API");
-            let catchup_api = endpoints::catchup(bind_version)?;
-            app.register_module("catchup", catchup_api)?;
+            let catchup_api = endpoints::catchup(limiter, cache, bind_version)?;
+            app.register_module(&name("catchup"), catchup_api)?;
         }
 
         let state_signature_api = endpoints::state_signature(bind_version)?;
-        app.register_module("state-signature", state_signature_api)?;
+        app.register_module(&name("state-signature"), state_signature_api)?;
+
+        app.register_module(&name("events"), endpoints::events(bind_version)?)?;
 
         if self.config.is_some() {
-            app.register_module("config", endpoints::config(bind_version)?)?;
+            app.register_module(&name("config"), endpoints::config(bind_version)?)?;
+        }
+
+        if self.marketplace.is_some() {
+            app.register_module(&name("marketplace"), endpoints::marketplace(bind_version)?)?;
         }
 
         Ok(())
@@ -450,8 +980,12 @@ impl Options {
         tasks.spawn(
             "Hotshot Events Streaming API server",
             self.listen(
-                self.hotshot_events.unwrap().events_service_port,
+                SocketAddr::from((
+                    self.http.bind_address,
+                    self.hotshot_events.unwrap().events_service_port,
+                )),
                 app,
+                &NoMetrics,
                 SequencerApiVersion::instance(),
             ),
         );
@@ -459,10 +993,119 @@ impl Options {
         Ok(())
     }
 
+    /// Start pushing metrics to an OTLP endpoint, if one is configured.
+    fn init_and_spawn_otlp_exporter(&self, metrics: &PrometheusMetrics, tasks: &mut TaskList) {
+        let Some(OtlpOptions {
+            otlp_metrics_endpoint: Some(endpoint),
+            otlp_metrics_headers,
+            otlp_metrics_interval,
+        }) = self.status.as_ref().map(|status| &status.otlp)
+        else {
+            return;
+        };
+        tracing::info!(%endpoint, "exporting metrics over OTLP");
+        let exporter = OtlpExporter::new(
+            metrics.clone(),
+            endpoint.clone(),
+            otlp_metrics_headers.clone(),
+        );
+        tasks.spawn(
+            "OTLP metrics exporter",
+            exporter.push(*otlp_metrics_interval),
+        );
+    }
+
+    /// Start the admin API server, if it is enabled.
+    ///
+    /// The admin API runs on its own port, separate from the public API, so that operators can
+    /// restrict access to it.
+    fn init_and_spawn_admin_server<N, P, V>(
+        &self,
+        state: ApiState<N, P, V>,
+        peers: QueryPeers,
+        tasks: &mut TaskList,
+    ) -> anyhow::Result<()>
+    where
+        N: ConnectedNetwork<PubKey>,
+        P: SequencerPersistence,
+        V: Versions + 'static,
+    {
+        let Some(admin) = &self.admin else {
+            return Ok(());
+        };
+
+        tracing::info!(port = admin.port, "initializing admin API");
+        let mut app =
+            App::<_, ApiError>::with_state(AppState::from(AdminState { peers, node: state }));
+        app.register_module(
+            "peers",
+            endpoints::peers::<_, SequencerApiVersion>(SequencerApiVersion::instance())?,
+        )?;
+        app.register_module(
+            "submit",
+            endpoints::submit_admin::<_, SequencerApiVersion>(SequencerApiVersion::instance())?,
+        )?;
+
+        tasks.spawn(
+            "admin API server",
+            self.listen(
+                admin.address(),
+                app,
+                &NoMetrics,
+                SequencerApiVersion::instance(),
+            ),
+        );
+        Ok(())
+    }
+
+    /// Start the gRPC API server, if it is enabled.
+    ///
+    /// The gRPC server shares the query data source and cache with the HTTP server. Transactions submitted
+    /// over gRPC are validated like those submitted to the HTTP submit API, and are subject to the
+    /// same per-namespace rate limits, if one is configured, but are counted separately.
+    #[cfg(feature = "grpc")]
+    fn init_and_spawn_grpc_server<N, P, D, V>(
+        &self,
+        ds: Arc<StorageState<N, P, D, V>>,
+        cache: QueryCache,
+        metrics: &dyn Metrics,
+        tasks: &mut TaskList,
+    ) where
+        N: ConnectedNetwork<PubKey>,
+        P: SequencerPersistence,
+        D: SequencerDataSource + Send + Sync + 'static,
+        V: Versions + 'static,
+    {
+        let Some(grpc) = &self.grpc else {
+            return;
+        };
+        let rate_limits = self
+            .submit
+            .as_ref()
+            .map(|submit| submit.rate_limits.as_slice())
+            .unwrap_or_default();
+        let limiter = SubmitRateLimiter::new(rate_limits, &*metrics.subgroup("grpc".into()));
+        let validator = self
+            .submit
+            .as_ref()
+            .map(TxValidator::new)
+            .unwrap_or_default();
+        tasks.spawn(
+            "gRPC server",
+            super::grpc::serve(grpc.port, ds, cache, limiter, validator),
+        );
+    }
+
+    /// Serve `app` on `addr`.
+    ///
+    /// The public app is also served on the addresses of any modules with their own listeners.
+    /// Per-route latency histograms and in-flight gauges are registered with `metrics`. If a TLS
+    /// certificate is configured, every address serves HTTPS (see [`super::tls`]).
     fn listen<S, E, ApiVer>(
         &self,
-        port: u16,
+        addr: SocketAddr,
         app: App<S, E>,
+        metrics: &dyn Metrics,
         bind_version: ApiVer,
     ) -> impl Future<Output = anyhow::Result<()>>
     where
@@ -471,24 +1114,91 @@ impl Options {
         ApiVer: StaticVersionType + 'static,
     {
         let max_connections = self.http.max_connections;
+        let tls = self.http.tls_cert.clone().zip(self.http.tls_key.clone());
+        // Only the admin API verifies client certificates.
+        let client_ca = self
+            .admin
+            .as_ref()
+            .filter(|admin| addr == admin.address())
+            .and_then(|admin| admin.tls_client_ca.clone());
+        let metrics = ApiMetrics::new(metrics);
+        let auth = self.auth.clone();
+        let limits = ApiLimits::new(&self.http);
+        let pagination = RangePagination::new(self.http.max_range_size);
+        let compression = self.http.compress_responses.then_some(ResponseCompression);
+        // The OpenAPI document describes the public API, not the admin or event streaming APIs.
+        let public = addr == self.http.address();
+        let spec = public.then_some(ApiSpec);
+        // Modules with their own addresses are served by the public app, on additional listeners.
+        let routing = public
+            .then(|| ModuleRouting::new(addr, &self.http.module_listeners))
+            .flatten();
+        let addrs: Vec<_> = once(addr)
+            .chain(routing.iter().flat_map(|routing| routing.addrs()))
+            .collect();
 
         async move {
-            if let Some(limit) = max_connections {
-                app.serve(RateLimitListener::with_port(port, limit), bind_version)
-                    .await?;
-            } else {
-                app.serve(format!("0.0.0.0:{}", port), bind_version).await?;
+            let auth = auth.as_ref().map(ApiAuth::new).transpose()?;
+            ensure!(
+                tls.is_some() || client_ca.is_none(),
+                "verifying admin API clients requires --api-tls-cert and --api-tls-key"
+            );
+            let tls = tls
+                .map(|(cert, key)| TlsConfig::load(&cert, &key, client_ca.as_deref()))
+                .transpose()?;
+            let mut listener = ConcurrentListener::new();
+            for addr in addrs {
+                match (&tls, max_connections) {
+                    (Some(tls), limit) => {
+                        listener.add(TlsListener::new(addr, tls.clone(), limit))?
+                    }
+                    (None, Some(limit)) => {
+                        listener.add(RateLimitListener::new(addr.to_string(), limit))?
+                    }
+                    (None, None) => listener.add(addr.to_string())?,
+                }
             }
+            app.serve(
+                ApiSpecListener::new(
+                    CompressionListener::new(
+                        LimitsListener::new(
+                            PaginationListener::new(
+                                RoutingListener::new(
+                                    AuthListener::new(
+                                        MetricsListener::new(listener, metrics),
+                                        auth,
+                                    ),
+                                    routing,
+                                ),
+                                pagination,
+                            ),
+                            limits,
+                        ),
+                        compression,
+                    ),
+                    spec,
+                ),
+                bind_version,
+            )
+            .await?;
             Ok(())
         }
     }
 }
 
+/// The name under which module `name` is served with serialization version `version`.
+///
+/// Modules are served with [`SequencerApiVersion`] at their usual names. During an upgrade window,
+/// they are also served with the upgraded version at versioned names, e.g. `submit-v0.3`.
+pub(crate) fn versioned_module(name: &str, version: Version) -> String {
+    format!("{name}-v{}.{}", version.major, version.minor)
+}
+
 /// The minimal HTTP API.
 ///
 /// The API automatically includes health and version endpoints. Additional API modules can be
 /// added by including the query-api or submit-api modules.
-#[derive(Parser, Clone, Copy, Debug)]
+#[derive(Parser, Clone, Debug)]
 pub struct Http {
     /// Port that the HTTP API will use.
     #[clap(long, env = "ESPRESSO_SEQUENCER_API_PORT")]
@@ -501,6 +1211,86 @@ pub struct Http {
     /// Leave unset for no connection limit.
     #[clap(long, env = "ESPRESSO_SEQUENCER_MAX_CONNECTIONS")]
     pub max_connections: Option<usize>,
+
+    /// Origins allowed to call the API from a browser, such as a rollup's block explorer.
+    ///
+    /// Multiple comma-separated origins may be given, each in `scheme://host[:port]` form.
+    /// Requests from browsers on other origins are rejected with 403.
+    ///
+    /// Leave unset (or use `*`) to allow any origin.
+    #[clap(
+        long = "api-cors-origins",
+        env = "ESPRESSO_SEQUENCER_API_CORS_ORIGINS",
+        value_delimiter = ','
+    )]
+    pub cors_origins: Vec<String>,
+
+    /// Maximum size in bytes of an HTTP request body.
+    ///
+    /// Requests with larger bodies receive a 413 response.
+    ///
+    /// Leave unset for no limit.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_MAX_REQUEST_BODY_BYTES")]
+    pub max_request_body_bytes: Option<u64>,
+
+    /// Maximum number of objects served by a single range query, such as
+    /// `availability/header/:from/:until`.
+    ///
+    /// Larger ranges are served one page at a time: the response contains the first page, and a
+    /// `Link` header with `rel="next"` points to the rest of the range.
+    ///
+    /// Defaults to 500 for leaves, headers and block summaries, and 100 for blocks, payloads and
+    /// VID common data.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_API_MAX_RANGE_SIZE")]
+    pub max_range_size: Option<usize>,
+
+    /// Compress responses with zstd for clients which accept it.
+    ///
+    /// Clients opt in by sending `Accept-Encoding: zstd`. Other clients receive uncompressed
+    /// responses.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_API_COMPRESS_RESPONSES")]
+    pub compress_responses: bool,
+
+    /// Address of the interface that the HTTP API listens on.
+    #[clap(
+        long = "api-bind-address",
+        env = "ESPRESSO_SEQUENCER_API_BIND_ADDRESS",
+        default_value = "0.0.0.0"
+    )]
+    pub bind_address: IpAddr,
+
+    /// API modules to serve on their own addresses instead of the HTTP API port.
+    ///
+    /// Each entry has the form `<module>=<ip>:<port>`, e.g. `catchup=10.0.0.1:8081`, and several
+    /// modules may share an address. Modules on their own address are not served on the HTTP API
+    /// port, so that, for example, catchup can be restricted to an internal interface while the
+    /// availability API is public. Versioned copies of a module, like `catchup-v0.3`, are served
+    /// with it. The admin and Hotshot events APIs have their own ports, and cannot be assigned here.
+    #[clap(
+        long = "api-module-listeners",
+        env = "ESPRESSO_SEQUENCER_API_MODULE_LISTENERS",
+        value_delimiter = ','
+    )]
+    pub module_listeners: Vec<ModuleListener>,
+
+    /// PEM file containing the certificate chain to serve the HTTP API with over TLS.
+    ///
+    /// When set, along with `--api-tls-key`, every HTTP server the node runs, including the admin
+    /// API and modules on their own addresses, serves HTTPS instead of plain HTTP.
+    #[clap(
+        long = "api-tls-cert",
+        env = "ESPRESSO_SEQUENCER_API_TLS_CERT",
+        requires = "tls_key"
+    )]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM file containing the private key for `--api-tls-cert`.
+    #[clap(
+        long = "api-tls-key",
+        env = "ESPRESSO_SEQUENCER_API_TLS_KEY",
+        requires = "tls_cert"
+    )]
+    pub tls_key: Option<PathBuf>,
 }
 
 impl Http {
@@ -509,32 +1299,392 @@ impl Http {
         Self {
             port,
             max_connections: None,
+            cors_origins: vec![],
+            max_request_body_bytes: None,
+            max_range_size: None,
+            compress_responses: false,
+            bind_address: Ipv4Addr::UNSPECIFIED.into(),
+            module_listeners: vec![],
+            tls_cert: None,
+            tls_key: None,
         }
     }
+
+    /// The address that the HTTP API listens on.
+    pub fn address(&self) -> SocketAddr {
+        (self.bind_address, self.port).into()
+    }
 }
 
 /// Options for the submission API module.
-#[derive(Parser, Clone, Copy, Debug, Default)]
-pub struct Submit;
+#[derive(Parser, Clone, Debug)]
+pub struct Submit {
+    /// Limit the rate of transactions submitted to a namespace.
+    ///
+    /// Each limit has the form `ns=<id>,rate=<tx/s>`. Multiple limits can be given by repeating the
+    /// option, or separated by `;`. Transactions exceeding the limit for their namespace are
+    /// rejected with status 429. Namespaces without a limit are not limited.
+    #[clap(
+        long = "submit-rate-limit",
+        env = "ESPRESSO_SEQUENCER_SUBMIT_RATE_LIMIT",
+        value_delimiter = ';'
+    )]
+    pub rate_limits: Vec<NamespaceRateLimit>,
+
+    /// The longest a client may wait for a submitted transaction to be decided.
+    ///
+    /// Clients which submit a transaction via `submit/submit/wait` receive an error if it is not
+    /// decided within this time.
+    #[clap(
+        long = "submit-wait-timeout",
+        env = "ESPRESSO_SEQUENCER_SUBMIT_WAIT_TIMEOUT",
+        value_parser = parse_duration,
+        default_value = "1m"
+    )]
+    pub wait_timeout: Duration,
+
+    /// The maximum number of transactions in a single `submit/batch` request.
+    ///
+    /// Larger batches are rejected with status 400, without submitting any of their transactions.
+    #[clap(
+        long = "submit-max-batch-count",
+        env = "ESPRESSO_SEQUENCER_SUBMIT_MAX_BATCH_COUNT",
+        default_value = "100"
+    )]
+    pub max_batch_count: usize,
+
+    /// Namespaces accepted by the submission API.
+    ///
+    /// Each entry is a namespace ID or an inclusive range of IDs, like `100-200`, and multiple
+    /// entries are separated by `,`. Transactions in other namespaces are rejected with status 400.
+    /// If no namespaces are given, all are accepted.
+    #[clap(
+        long = "submit-allowed-namespaces",
+        env = "ESPRESSO_SEQUENCER_SUBMIT_ALLOWED_NAMESPACES",
+        value_delimiter = ','
+    )]
+    pub allowed_namespaces: Vec<NamespaceRange>,
+
+    /// Forward submitted transactions without validating them.
+    ///
+    /// This is only useful for testing how the rest of the system handles invalid transactions.
+    #[clap(
+        long = "submit-skip-validation",
+        env = "ESPRESSO_SEQUENCER_SUBMIT_SKIP_VALIDATION",
+        hide = true
+    )]
+    pub skip_validation: bool,
+}
+
+impl Default for Submit {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
 
 /// Options for the status API module.
-#[derive(Parser, Clone, Copy, Debug, Default)]
-pub struct Status;
+#[derive(Parser, Clone, Debug, Default)]
+pub struct Status {
+    /// Reporting of this node's identity to a central telemetry collector.
+    #[clap(flatten)]
+    pub telemetry: TelemetryOptions,
+
+    /// Pushing of metrics to an OpenTelemetry collector.
+    #[clap(flatten)]
+    pub otlp: OtlpOptions,
+}
 
 /// Options for the catchup API module.
-#[derive(Parser, Clone, Copy, Debug, Default)]
-pub struct Catchup;
+#[derive(Parser, Clone, Copy, Debug)]
+pub struct Catchup {
+    /// Maximum number of account requests to serve at once.
+    ///
+    /// This covers both `catchup/account` and `catchup/accounts`, the most expensive catchup
+    /// requests to serve.
+    #[clap(
+        long = "catchup-account-workers",
+        env = "ESPRESSO_SEQUENCER_CATCHUP_ACCOUNT_WORKERS",
+        default_value = "4"
+    )]
+    pub account_workers: usize,
+
+    /// Maximum number of blocks frontier requests to serve at once.
+    #[clap(
+        long = "catchup-frontier-workers",
+        env = "ESPRESSO_SEQUENCER_CATCHUP_FRONTIER_WORKERS",
+        default_value = "4"
+    )]
+    pub frontier_workers: usize,
+
+    /// Maximum number of chain config requests to serve at once.
+    #[clap(
+        long = "catchup-chain-config-workers",
+        env = "ESPRESSO_SEQUENCER_CATCHUP_CHAIN_CONFIG_WORKERS",
+        default_value = "8"
+    )]
+    pub chain_config_workers: usize,
+
+    /// Maximum number of requests of each kind waiting to be served.
+    ///
+    /// Requests beyond this are rejected with status 429, so that peers try another node.
+    #[clap(
+        long = "catchup-max-queue",
+        env = "ESPRESSO_SEQUENCER_CATCHUP_MAX_QUEUE",
+        default_value = "64"
+    )]
+    pub max_queue: usize,
+
+    /// Limit the sustained rate of requests of each kind, in requests per second.
+    ///
+    /// Requests exceeding the limit are rejected with status 429. By default, requests are only
+    /// limited by the number of workers and the queue size.
+    #[clap(
+        long = "catchup-rate-limit",
+        env = "ESPRESSO_SEQUENCER_CATCHUP_RATE_LIMIT"
+    )]
+    pub rate_limit: Option<f64>,
+
+    /// Maximum number of account proofs to cache for `catchup/account`.
+    ///
+    /// When many peers catch up at once, they mostly request the same accounts at the same height
+    /// and view; cached proofs are served without reading the state tree. Set to 0 to disable the
+    /// cache.
+    #[clap(
+        long = "catchup-account-cache-size",
+        env = "ESPRESSO_SEQUENCER_CATCHUP_ACCOUNT_CACHE_SIZE",
+        default_value = "1000"
+    )]
+    pub account_cache_size: usize,
+
+    /// How long to keep a cached account proof.
+    #[clap(
+        long = "catchup-account-cache-ttl",
+        env = "ESPRESSO_SEQUENCER_CATCHUP_ACCOUNT_CACHE_TTL",
+        value_parser = parse_duration,
+        default_value = "30s"
+    )]
+    pub account_cache_ttl: Duration,
+}
+
+impl Default for Catchup {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
 
 /// Options for the config API module.
 #[derive(Parser, Clone, Copy, Debug, Default)]
 pub struct Config;
 
+/// Options for the marketplace API module.
+#[derive(Parser, Clone, Copy, Debug, Default)]
+pub struct Marketplace;
+
 /// Options for the query API module.
 #[derive(Parser, Clone, Debug, Default)]
 pub struct Query {
     /// Peers for fetching missing data for the query service.
     #[clap(long, env = "ESPRESSO_SEQUENCER_API_PEERS", value_delimiter = ',')]
     pub peers: Vec<Url>,
+
+    /// Maximum number of peers to send each request for missing data to at once.
+    ///
+    /// Requests go to the peers which have responded fastest in the past, and the first valid
+    /// response is used. If none of them can provide the data, the next fastest peers are tried.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_PEER_FETCH_CONCURRENCY",
+        default_value = "2"
+    )]
+    pub peer_fetch_concurrency: usize,
+
+    /// Retention policy for block payloads and VID data.
+    #[clap(flatten)]
+    pub pruning: PayloadPruningOptions,
+
+    /// Object store to archive pruned payloads and VID data to.
+    #[clap(flatten)]
+    pub archive: PayloadArchiveOptions,
+
+    /// In-memory cache of recently queried blocks.
+    #[clap(flatten)]
+    pub cache: QueryCacheOptions,
+
+    /// Backfilling of missing historical data from peers.
+    #[clap(flatten)]
+    pub backfill: BackfillOptions,
+
+    /// Repair of missing VID shares.
+    #[clap(flatten)]
+    pub vid_repair: VidRepairOptions,
+
+    /// Which data the query service stores.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_QUERY_MODE",
+        value_enum,
+        default_value_t = QueryMode::Full
+    )]
+    pub query_mode: QueryMode,
+}
+
+/// Which data the query service stores.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum QueryMode {
+    /// Store all data, including full block payloads.
+    #[default]
+    Full,
+    /// Store headers, leaves, VID data and transaction indices, but discard block payloads.
+    ///
+    /// Payloads are pruned shortly after each block is decided, using the same mechanism as the
+    /// payload retention policy. Queries which need a full payload, such as namespace proofs, are
+    /// answered by fetching the payload from peers on demand, so peers should be configured. This
+    /// mode requires SQL storage.
+    HeadersOnly,
+}
+
+/// Options for the admin API server.
+///
+/// The admin API allows operators to reconfigure a running node. It is served on a separate port
+/// from the public API, which should not be exposed publicly.
+#[derive(Parser, Clone, Debug)]
+pub struct Admin {
+    /// Port that the admin API will use.
+    #[clap(long = "admin-port", env = "ESPRESSO_SEQUENCER_ADMIN_API_PORT")]
+    pub port: u16,
+
+    /// Address of the interface that the admin API listens on.
+    #[clap(
+        long = "admin-bind-address",
+        env = "ESPRESSO_SEQUENCER_ADMIN_API_BIND_ADDRESS",
+        default_value = "0.0.0.0"
+    )]
+    pub bind_address: IpAddr,
+
+    /// PEM file containing the CA certificates which admin API clients must be signed by.
+    ///
+    /// When set, clients of the admin API must present a certificate signed by one of these CAs
+    /// (mutual TLS), and connections without one are refused. This requires the HTTP API to be
+    /// served over TLS, with `--api-tls-cert` and `--api-tls-key`.
+    #[clap(
+        long = "admin-tls-client-ca",
+        env = "ESPRESSO_SEQUENCER_ADMIN_API_TLS_CLIENT_CA"
+    )]
+    pub tls_client_ca: Option<PathBuf>,
+}
+
+impl Admin {
+    /// The address that the admin API listens on.
+    pub fn address(&self) -> SocketAddr {
+        (self.bind_address, self.port).into()
+    }
+}
+
+/// Options for authenticating requests to protected API modules.
+///
+/// Requests for a protected module must include one of the configured API keys in an
+/// `Authorization: Bearer <key>` header. This applies to every HTTP server the node runs, including
+/// the admin API, but not to the gRPC API. All keys are accepted at once, so a key can be rotated by
+/// adding its replacement, migrating clients, and then removing the old key.
+#[derive(Parser, Clone, Derivative)]
+#[derivative(Debug)]
+pub struct Auth {
+    /// API keys accepted by protected modules.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_API_KEYS", value_delimiter = ',')]
+    // Hide from debug output since may contain sensitive data.
+    #[derivative(Debug = "ignore")]
+    pub api_keys: Vec<String>,
+
+    /// File containing additional API keys, one per line.
+    ///
+    /// Blank lines and lines starting with `#` are ignored. The file is reloaded whenever it is
+    /// modified, so keys can be rotated without restarting the node.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_API_KEYS_FILE")]
+    pub api_keys_file: Option<PathBuf>,
+
+    /// API modules which require an API key.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_API_PROTECTED_MODULES",
+        value_delimiter = ',',
+        default_value = "submit,config,peers"
+    )]
+    pub protected_modules: Vec<String>,
+}
+
+/// Options for publishing decided blocks to a NATS server.
+///
+/// See [`super::stream`] for the subjects and messages published.
+#[derive(Parser, Clone, Derivative)]
+#[derivative(Debug)]
+pub struct Stream {
+    /// URL of the NATS server, of the form `nats://[<user>:<password>@]<host>[:<port>]`.
+    #[clap(long = "stream-nats-url", env = "ESPRESSO_SEQUENCER_STREAM_NATS_URL")]
+    // Hide from debug output since may contain sensitive data.
+    #[derivative(Debug = "ignore")]
+    pub nats_url: Url,
+
+    /// Prefix of the subjects messages are published on.
+    #[clap(
+        long = "stream-subject-prefix",
+        env = "ESPRESSO_SEQUENCER_STREAM_SUBJECT_PREFIX",
+        default_value = "espresso"
+    )]
+    pub subject_prefix: String,
+
+    /// How messages are encoded.
+    #[clap(
+        long = "stream-format",
+        env = "ESPRESSO_SEQUENCER_STREAM_FORMAT",
+        value_enum,
+        default_value_t = StreamFormat::Json
+    )]
+    pub format: StreamFormat,
+
+    /// Maximum number of decided events waiting to be published.
+    ///
+    /// Events are dropped while the queue is full.
+    #[clap(
+        long = "stream-queue-capacity",
+        env = "ESPRESSO_SEQUENCER_STREAM_QUEUE_CAPACITY",
+        default_value = "1000"
+    )]
+    pub queue_capacity: usize,
+
+    /// Maximum number of attempts to publish each decided event before giving up.
+    #[clap(
+        long = "stream-max-attempts",
+        env = "ESPRESSO_SEQUENCER_STREAM_MAX_ATTEMPTS",
+        default_value = "5"
+    )]
+    pub max_attempts: usize,
+
+    /// Delay before retrying a failed publish. The delay doubles with each failed attempt.
+    #[clap(
+        long = "stream-retry-delay",
+        env = "ESPRESSO_SEQUENCER_STREAM_RETRY_DELAY",
+        default_value = "1s",
+        value_parser = parse_duration,
+    )]
+    pub retry_delay: Duration,
+
+    /// Timeout for publishing a single block.
+    #[clap(
+        long = "stream-timeout",
+        env = "ESPRESSO_SEQUENCER_STREAM_TIMEOUT",
+        default_value = "10s",
+        value_parser = parse_duration,
+    )]
+    pub timeout: Duration,
+}
+
+/// Options for the gRPC API server.
+#[cfg(feature = "grpc")]
+#[derive(Parser, Clone, Copy, Debug)]
+pub struct Grpc {
+    /// Port that the gRPC API will use.
+    #[clap(long = "grpc-port", env = "ESPRESSO_SEQUENCER_GRPC_PORT")]
+    pub port: u16,
 }
 
 /// Options for the state API module.
@@ -550,5 +1700,39 @@ pub struct HotshotEvents {
 }
 
 /// Options for the explorer API module.
-#[derive(Parser, Clone, Copy, Debug, Default)]
-pub struct Explorer;
+#[derive(Parser, Clone, Debug, Default)]
+pub struct Explorer {
+    #[clap(flatten)]
+    pub stats: ExplorerStatsOptions,
+
+    /// Names for namespaces, used to label them in explorer statistics and metrics.
+    #[clap(flatten)]
+    pub registry: NamespaceRegistryOptions,
+}
+
+/// Options for the fee account API module.
+#[derive(Parser, Clone, Copy, Debug)]
+pub struct Fee {
+    /// L1 block to start indexing fee deposits from.
+    ///
+    /// Defaults to the L1 block the chain started at, if known, and otherwise to the L1 genesis.
+    /// Only used the first time the index is built; after that, indexing resumes where it left
+    /// off.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_FEE_DEPOSIT_INDEX_START")]
+    pub fee_deposit_index_start: Option<u64>,
+
+    /// How often to scan newly finalized L1 blocks for fee deposits.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_FEE_DEPOSIT_INDEX_INTERVAL",
+        value_parser = parse_duration,
+        default_value = "1m"
+    )]
+    pub fee_deposit_index_interval: Duration,
+}
+
+impl Default for Fee {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
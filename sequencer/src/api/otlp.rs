@@ -0,0 +1,540 @@
+//! Push-based export of metrics to an OpenTelemetry collector.
+//!
+//! The status API exposes this node's metrics at `status/metrics`, in the Prometheus text format,
+//! for scrape-based monitoring. Infrastructure built on OpenTelemetry collectors instead expects
+//! nodes to push their metrics. When an OTLP endpoint is configured, [`OtlpExporter`] periodically
+//! converts the same metrics into an OTLP export request and posts it to the collector, using the
+//! JSON encoding of OTLP/HTTP.
+//!
+//! Counters are exported as cumulative monotonic sums, histograms as cumulative explicit-bucket
+//! histograms, and everything else as gauges.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context};
+use clap::Parser;
+use espresso_types::parse_duration;
+use hotshot_query_service::metrics::PrometheusMetrics;
+use serde_json::{json, Map, Value};
+use tide_disco::metrics::Metrics as _;
+use tokio::time::sleep;
+use url::Url;
+
+/// `AGGREGATION_TEMPORALITY_CUMULATIVE` in the OTLP protocol.
+const CUMULATIVE: u64 = 2;
+
+/// Options for pushing metrics to an OpenTelemetry collector.
+#[derive(Parser, Clone, Debug)]
+pub struct OtlpOptions {
+    /// URL of an OTLP/HTTP metrics endpoint to periodically push this node's metrics to.
+    ///
+    /// This is the full URL of the endpoint, usually ending in `/v1/metrics`. The metrics are the
+    /// same as those served at `status/metrics`. If not set, metrics are only available for
+    /// scraping.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_OTLP_METRICS_ENDPOINT")]
+    pub otlp_metrics_endpoint: Option<Url>,
+
+    /// Headers to add to each OTLP export request, such as for authentication.
+    ///
+    /// A comma-separated list of `name=value` pairs.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_OTLP_METRICS_HEADERS",
+        value_delimiter = ','
+    )]
+    pub otlp_metrics_headers: Vec<OtlpHeader>,
+
+    /// How often to push metrics to the OTLP endpoint.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_OTLP_METRICS_INTERVAL",
+        value_parser = parse_duration,
+        default_value = "60s"
+    )]
+    pub otlp_metrics_interval: Duration,
+}
+
+impl Default for OtlpOptions {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+/// An HTTP header added to OTLP export requests, parsed from `name=value`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OtlpHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for OtlpHeader {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (name, value) = s
+            .split_once('=')
+            .context(format!("header {s:?} is not of the form name=value"))?;
+        Ok(Self {
+            name: name.trim().into(),
+            value: value.trim().into(),
+        })
+    }
+}
+
+impl Display for OtlpHeader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)
+    }
+}
+
+/// Periodically pushes metrics to an OTLP endpoint.
+#[derive(Clone, Debug)]
+pub struct OtlpExporter {
+    metrics: PrometheusMetrics,
+    endpoint: Url,
+    headers: Vec<OtlpHeader>,
+    /// When the exporter started, in nanoseconds since the Unix epoch, which is reported as the
+    /// start of every cumulative series.
+    started: u64,
+}
+
+impl OtlpExporter {
+    pub fn new(metrics: PrometheusMetrics, endpoint: Url, headers: Vec<OtlpHeader>) -> Self {
+        Self {
+            metrics,
+            endpoint,
+            headers,
+            started: now(),
+        }
+    }
+
+    /// Push metrics to the endpoint every `interval`.
+    ///
+    /// Failed exports are logged and retried at the next interval; this task never exits.
+    pub async fn push(self, interval: Duration) -> anyhow::Result<()> {
+        let client = reqwest::Client::new();
+        loop {
+            if let Err(err) = self.push_once(&client).await {
+                tracing::warn!(endpoint = %self.endpoint, "failed to export metrics: {err:#}");
+            }
+            sleep(interval).await;
+        }
+    }
+
+    async fn push_once(&self, client: &reqwest::Client) -> anyhow::Result<()> {
+        let text = self
+            .metrics
+            .export()
+            .map_err(|err| anyhow!("exporting metrics: {err}"))?;
+        let request = export_request(&parse(&text)?, self.started, now());
+        post(client, &self.endpoint, &self.headers, &request).await
+    }
+}
+
+/// Post an OTLP export request, in the JSON encoding, to `endpoint`.
+pub(crate) async fn post(
+    client: &reqwest::Client,
+    endpoint: &Url,
+    headers: &[OtlpHeader],
+    request: &Value,
+) -> anyhow::Result<()> {
+    let mut req = client
+        .post(endpoint.clone())
+        .header("Content-Type", "application/json");
+    for header in headers {
+        req = req.header(&header.name, &header.value);
+    }
+    req.body(serde_json::to_vec(request)?)
+        .send()
+        .await
+        .context("sending export request")?
+        .error_for_status()
+        .context("OTLP endpoint rejected export request")?;
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+/// Label names and values of a series, sorted by name.
+type Labels = Vec<(String, String)>;
+
+/// A metric parsed from the Prometheus text format.
+#[derive(Debug, Default)]
+struct Metric {
+    kind: Option<Kind>,
+    help: String,
+    /// Values of each series of a counter or gauge.
+    points: Vec<(Labels, f64)>,
+    /// Each series of a histogram.
+    histograms: BTreeMap<Labels, HistogramPoint>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct HistogramPoint {
+    /// Upper bounds of the buckets, with the cumulative count of observations in each.
+    buckets: Vec<(f64, f64)>,
+    sum: f64,
+    count: f64,
+}
+
+/// Parse metrics in the Prometheus text exposition format.
+fn parse(text: &str) -> anyhow::Result<BTreeMap<String, Metric>> {
+    let mut metrics = BTreeMap::<String, Metric>::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut words = comment.trim_start().splitn(3, ' ');
+            match (words.next(), words.next(), words.next()) {
+                (Some("TYPE"), Some(name), kind) => {
+                    metrics.entry(name.into()).or_default().kind = Some(match kind {
+                        Some("counter") => Kind::Counter,
+                        Some("histogram") => Kind::Histogram,
+                        // Untyped metrics are exported as gauges.
+                        _ => Kind::Gauge,
+                    });
+                }
+                (Some("HELP"), Some(name), help) => {
+                    metrics.entry(name.into()).or_default().help = help.unwrap_or_default().into();
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        let (name, mut labels, value) = parse_sample(line)?;
+        // The series of a histogram are named after the metric, with a suffix.
+        let histogram = ["_bucket", "_sum", "_count"]
+            .into_iter()
+            .find_map(|suffix| {
+                let base = name.strip_suffix(suffix)?;
+                (metrics.get(base)?.kind == Some(Kind::Histogram)).then_some((base, suffix))
+            });
+        let Some((base, suffix)) = histogram else {
+            metrics
+                .entry(name)
+                .or_default()
+                .points
+                .push((labels, value));
+            continue;
+        };
+
+        let le = labels
+            .iter()
+            .position(|(label, _)| label == "le")
+            .map(|i| labels.remove(i).1);
+        let point = metrics
+            .get_mut(base)
+            .context("histogram exists")?
+            .histograms
+            .entry(labels)
+            .or_default();
+        match (suffix, le) {
+            ("_bucket", Some(le)) => point.buckets.push((parse_value(&le)?, value)),
+            ("_bucket", None) => bail!("histogram bucket {name} has no upper bound"),
+            ("_sum", _) => point.sum = value,
+            _ => point.count = value,
+        }
+    }
+    Ok(metrics)
+}
+
+/// Parse a sample line, like `name{label="value"} 1`, into its name, labels and value.
+fn parse_sample(line: &str) -> anyhow::Result<(String, Labels, f64)> {
+    let end = line
+        .find(|c: char| c == '{' || c.is_whitespace())
+        .context(format!("sample {line:?} has no value"))?;
+    let name = line[..end].to_string();
+    let mut rest = &line[end..];
+
+    let mut labels = vec![];
+    if let Some(mut body) = rest.strip_prefix('{') {
+        loop {
+            body = body.trim_start_matches([',', ' ']);
+            if let Some(after) = body.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+            let (label, after) = body
+                .split_once("=\"")
+                .context(format!("malformed labels in {line:?}"))?;
+            let mut value = String::new();
+            let mut chars = after.char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '"')) => break i,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => value.push('\n'),
+                        Some((_, c)) => value.push(c),
+                        None => break after.len(),
+                    },
+                    Some((_, c)) => value.push(c),
+                    None => bail!("unterminated label value in {line:?}"),
+                }
+            };
+            labels.push((label.trim().to_string(), value));
+            body = after.get(end + 1..).unwrap_or_default();
+        }
+    }
+    labels.sort();
+
+    let value = rest
+        .split_whitespace()
+        .next()
+        .context(format!("sample {line:?} has no value"))?;
+    Ok((name, labels, parse_value(value)?))
+}
+
+fn parse_value(value: &str) -> anyhow::Result<f64> {
+    match value {
+        "+Inf" => Ok(f64::INFINITY),
+        "-Inf" => Ok(f64::NEG_INFINITY),
+        "NaN" => Ok(f64::NAN),
+        _ => value
+            .parse()
+            .context(format!("malformed sample value {value:?}")),
+    }
+}
+
+/// Build an OTLP export request, in the JSON encoding, for the metrics at time `now`.
+///
+/// Times are in nanoseconds since the Unix epoch, and cumulative series start at `started`.
+fn export_request(metrics: &BTreeMap<String, Metric>, started: u64, now: u64) -> Value {
+    let attributes = |labels: &Labels| {
+        labels
+            .iter()
+            .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+            .collect::<Vec<_>>()
+    };
+    let number_points = |metric: &Metric| {
+        metric
+            .points
+            .iter()
+            .map(|(labels, value)| {
+                json!({
+                    "attributes": attributes(labels),
+                    "startTimeUnixNano": started.to_string(),
+                    "timeUnixNano": now.to_string(),
+                    "asDouble": value,
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let metrics = metrics
+        .iter()
+        .filter(|(_, metric)| !metric.points.is_empty() || !metric.histograms.is_empty())
+        .map(|(name, metric)| {
+            let mut object = Map::new();
+            object.insert("name".into(), name.clone().into());
+            object.insert("description".into(), metric.help.clone().into());
+            match metric.kind.unwrap_or(Kind::Gauge) {
+                Kind::Counter => object.insert(
+                    "sum".into(),
+                    json!({
+                        "dataPoints": number_points(metric),
+                        "aggregationTemporality": CUMULATIVE,
+                        "isMonotonic": true,
+                    }),
+                ),
+                Kind::Gauge => object.insert(
+                    "gauge".into(),
+                    json!({ "dataPoints": number_points(metric) }),
+                ),
+                Kind::Histogram => {
+                    let points = metric
+                        .histograms
+                        .iter()
+                        .map(|(labels, point)| {
+                            let (bounds, counts) = bucket_counts(point);
+                            json!({
+                                "attributes": attributes(labels),
+                                "startTimeUnixNano": started.to_string(),
+                                "timeUnixNano": now.to_string(),
+                                "count": (point.count as u64).to_string(),
+                                "sum": point.sum,
+                                "bucketCounts": counts
+                                    .into_iter()
+                                    .map(|count| count.to_string())
+                                    .collect::<Vec<_>>(),
+                                "explicitBounds": bounds,
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    object.insert(
+                        "histogram".into(),
+                        json!({
+                            "dataPoints": points,
+                            "aggregationTemporality": CUMULATIVE,
+                        }),
+                    )
+                }
+            };
+            Value::Object(object)
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "resourceMetrics": [{
+            "resource": {
+                "attributes": [{
+                    "key": "service.name",
+                    "value": { "stringValue": "espresso-sequencer" },
+                }],
+            },
+            "scopeMetrics": [{
+                "scope": {
+                    "name": "espresso-sequencer",
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "metrics": metrics,
+            }],
+        }],
+    })
+}
+
+/// Convert cumulative Prometheus buckets into OTLP explicit bounds and per-bucket counts.
+///
+/// OTLP has one more count than bounds, for observations above the last bound, which corresponds to
+/// the Prometheus `+Inf` bucket.
+fn bucket_counts(point: &HistogramPoint) -> (Vec<f64>, Vec<u64>) {
+    let mut buckets = point.buckets.clone();
+    buckets.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+    let mut bounds = vec![];
+    let mut counts = vec![];
+    let mut below = 0.0;
+    for (le, cumulative) in buckets {
+        if le.is_finite() {
+            bounds.push(le);
+        }
+        counts.push((cumulative - below).max(0.0) as u64);
+        below = cumulative;
+    }
+    if bounds.len() == counts.len() {
+        counts.push((point.count - below).max(0.0) as u64);
+    }
+    (bounds, counts)
+}
+
+/// The current time, in nanoseconds since the Unix epoch.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEXT: &str = r#"
+# HELP consensus_current_view current view
+# TYPE consensus_current_view gauge
+consensus_current_view 42
+# HELP api_requests requests served
+# TYPE api_requests counter
+api_requests{module="availability",status="200"} 7
+api_requests{module="submit",status="400"} 1
+# HELP api_latency request latency
+# TYPE api_latency histogram
+api_latency_bucket{module="node",le="0.1"} 2
+api_latency_bucket{module="node",le="1"} 5
+api_latency_bucket{module="node",le="+Inf"} 6
+api_latency_sum{module="node"} 4.5
+api_latency_count{module="node"} 6
+"#;
+
+    #[test]
+    fn test_parse_prometheus() {
+        let metrics = parse(TEXT).unwrap();
+
+        let view = &metrics["consensus_current_view"];
+        assert_eq!(view.kind, Some(Kind::Gauge));
+        assert_eq!(view.help, "current view");
+        assert_eq!(view.points, vec![(vec![], 42.0)]);
+
+        let requests = &metrics["api_requests"];
+        assert_eq!(requests.kind, Some(Kind::Counter));
+        assert_eq!(
+            requests.points[1],
+            (
+                vec![
+                    ("module".into(), "submit".into()),
+                    ("status".into(), "400".into())
+                ],
+                1.0
+            )
+        );
+
+        let latency = &metrics["api_latency"];
+        assert!(!metrics.contains_key("api_latency_bucket"));
+        let point = &latency.histograms[&vec![("module".into(), "node".into())]];
+        assert_eq!(
+            *point,
+            HistogramPoint {
+                buckets: vec![(0.1, 2.0), (1.0, 5.0), (f64::INFINITY, 6.0)],
+                sum: 4.5,
+                count: 6.0,
+            }
+        );
+        assert_eq!(bucket_counts(point), (vec![0.1, 1.0], vec![2, 3, 1]));
+
+        // Escaped label values are unescaped.
+        let (_, labels, _) = parse_sample(r#"m{a="x\"y",b="1\\2"} 3"#).unwrap();
+        assert_eq!(
+            labels,
+            vec![("a".into(), "x\"y".into()), ("b".into(), "1\\2".into())]
+        );
+    }
+
+    #[test]
+    fn test_export_request() {
+        let request = export_request(&parse(TEXT).unwrap(), 1, 2);
+        let metrics = request["resourceMetrics"][0]["scopeMetrics"][0]["metrics"]
+            .as_array()
+            .unwrap();
+        let metric = |name: &str| {
+            metrics
+                .iter()
+                .find(|metric| metric["name"] == name)
+                .unwrap()
+        };
+
+        let requests = &metric("api_requests")["sum"];
+        assert_eq!(requests["isMonotonic"], true);
+        assert_eq!(requests["dataPoints"].as_array().unwrap().len(), 2);
+        assert_eq!(requests["dataPoints"][0]["asDouble"], 7.0);
+        assert_eq!(requests["dataPoints"][0]["startTimeUnixNano"], "1");
+
+        let view = &metric("consensus_current_view")["gauge"]["dataPoints"][0];
+        assert_eq!(view["asDouble"], 42.0);
+        assert_eq!(view["timeUnixNano"], "2");
+
+        let latency = &metric("api_latency")["histogram"]["dataPoints"][0];
+        assert_eq!(latency["count"], "6");
+        assert_eq!(latency["bucketCounts"], json!(["2", "3", "1"]));
+        assert_eq!(latency["explicitBounds"], json!([0.1, 1.0]));
+    }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            "Authorization=Bearer abc=".parse::<OtlpHeader>().unwrap(),
+            OtlpHeader {
+                name: "Authorization".into(),
+                value: "Bearer abc=".into(),
+            }
+        );
+        "no-value".parse::<OtlpHeader>().unwrap_err();
+    }
+}
@@ -0,0 +1,274 @@
+//! Pagination of range queries.
+//!
+//! The availability API serves ranges of objects, such as `availability/header/:from/:until`. On
+//! its own, the query service rejects ranges larger than a limit, leaving clients to guess how much
+//! they may ask for. [`RangePagination`] instead answers an oversized range with its first page, of
+//! at most the limit number of objects, and points to the rest of the range with an
+//! [RFC 8288](https://datatracker.ietf.org/doc/html/rfc8288) `Link` header:
+//!
+//! ```text
+//! Link: </availability/header/500/1000000>; rel="next"
+//! ```
+//!
+//! The link is a continuation token: following `next` links until a response has none retrieves
+//! the whole range, one page at a time, while a single request never makes the node load more than
+//! a page of objects. Ranges within the limit are served as usual, without a link.
+//!
+//! Tide selects the route for a request before running any middleware, so a page cannot be served
+//! by just rewriting the request path. Instead, [`PaginationListener`] wraps the API server in an
+//! outer server which rewrites the path and then routes the request through the API server.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    io,
+};
+
+use async_trait::async_trait;
+use hotshot_query_service::availability;
+use tide::{
+    http::Url,
+    listener::{ListenInfo, Listener, ToListener},
+    Middleware, Next, Request, Server,
+};
+
+/// Middleware which serves oversized range queries one page at a time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct RangePagination {
+    /// Page size for small objects: leaves, headers and block summaries.
+    small: u64,
+    /// Page size for large objects: blocks, payloads and VID common data.
+    large: u64,
+}
+
+/// The part of a range to serve now, and where to find the rest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Page {
+    url: Url,
+    next: String,
+}
+
+impl RangePagination {
+    /// Pages of at most `max_range_size` objects, or the query service's range limits if unset.
+    pub(crate) fn new(max_range_size: Option<usize>) -> Self {
+        match max_range_size {
+            Some(max) => Self {
+                small: max as u64,
+                large: max as u64,
+            },
+            None => {
+                let defaults = availability::Options::default();
+                Self {
+                    small: defaults.small_object_range_limit as u64,
+                    large: defaults.large_object_range_limit as u64,
+                }
+            }
+        }
+    }
+
+    /// The page size for ranges of `resource`, if it is a range endpoint.
+    fn limit(&self, resource: &[&str]) -> Option<u64> {
+        match resource {
+            ["leaf"] | ["header"] | ["block", "summaries"] => Some(self.small),
+            ["block"] | ["payload"] | ["vid", "common"] => Some(self.large),
+            _ => None,
+        }
+    }
+
+    /// The first page of the range requested by `url`, if it is an oversized range query.
+    fn page(&self, url: &Url) -> Option<Page> {
+        let mut segments = url.path_segments()?.collect::<Vec<_>>();
+        // Skip the API version prefix, if any, e.g. `/v0/availability/...`.
+        if segments
+            .first()
+            .and_then(|segment| segment.strip_prefix('v'))
+            .is_some_and(|version| version.parse::<u64>().is_ok())
+        {
+            segments.remove(0);
+        }
+        // The module may be served under a versioned name during upgrades, e.g.
+        // `availability-v0.3`.
+        let (module, rest) = segments.split_first()?;
+        if *module != "availability" && !module.starts_with("availability-v") {
+            return None;
+        }
+        let [resource @ .., from, until] = rest else {
+            return None;
+        };
+        let from = from.parse::<u64>().ok()?;
+        let until = until.parse::<u64>().ok()?;
+        let limit = self.limit(resource)?;
+        if until.saturating_sub(from) <= limit {
+            return None;
+        }
+        let end = from + limit;
+
+        let with_range = |from: u64, until: u64| {
+            let mut url = url.clone();
+            url.path_segments_mut()
+                .ok()?
+                .pop()
+                .pop()
+                .push(&from.to_string())
+                .push(&until.to_string());
+            Some(url)
+        };
+        let url = with_range(from, end)?;
+        let next = with_range(end, until)?;
+        let next = match next.query() {
+            Some(query) => format!("{}?{query}", next.path()),
+            None => next.path().to_string(),
+        };
+        Some(Page { url, next })
+    }
+}
+
+#[async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for RangePagination {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let Some(page) = self.page(req.url()) else {
+            return Ok(next.run(req).await);
+        };
+        tracing::debug!(url = %req.url(), page = %page.url, "paginating range query");
+        *AsMut::<tide::http::Request>::as_mut(&mut req).url_mut() = page.url;
+
+        let mut res = next.run(req).await;
+        if res.status().is_success() {
+            res.insert_header("Link", format!("<{}>; rel=\"next\"", page.next));
+        }
+        Ok(res)
+    }
+}
+
+/// A [`Listener`] which serves the API server behind [`RangePagination`].
+#[derive(Debug)]
+pub(crate) struct PaginationListener<L> {
+    inner: L,
+    pagination: RangePagination,
+}
+
+impl<L> PaginationListener<L> {
+    pub(crate) fn new(inner: L, pagination: RangePagination) -> Self {
+        Self { inner, pagination }
+    }
+}
+
+impl<L: Display> Display for PaginationListener<L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+#[async_trait]
+impl<State, L> Listener<State> for PaginationListener<L>
+where
+    State: Clone + Send + Sync + 'static,
+    L: Listener<State>,
+{
+    async fn bind(&mut self, app: Server<State>) -> io::Result<()> {
+        // Route every request through `app` after rewriting its path. Route parameters are looked
+        // up innermost first, so those of `app` take precedence over the wildcard here.
+        let mut outer = tide::with_state(app.state().clone());
+        outer.with(self.pagination);
+        outer.at("/").all(app.clone());
+        outer.at("*").all(app);
+        self.inner.bind(outer).await
+    }
+
+    async fn accept(&mut self) -> io::Result<()> {
+        self.inner.accept().await
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        self.inner.info()
+    }
+}
+
+impl<State, L> ToListener<State> for PaginationListener<L>
+where
+    State: Clone + Send + Sync + 'static,
+    L: Listener<State>,
+{
+    type Listener = Self;
+
+    fn to_listener(self) -> io::Result<Self::Listener> {
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn page(pagination: &RangePagination, path: &str) -> Option<(String, String)> {
+        let url = Url::parse(&format!("http://localhost:8080{path}")).unwrap();
+        pagination
+            .page(&url)
+            .map(|page| (page.url.path().to_string(), page.next))
+    }
+
+    #[test]
+    fn test_range_pagination() {
+        let pagination = RangePagination {
+            small: 500,
+            large: 100,
+        };
+
+        assert_eq!(
+            page(&pagination, "/availability/header/0/1000000"),
+            Some((
+                "/availability/header/0/500".into(),
+                "/availability/header/500/1000000".into()
+            ))
+        );
+        assert_eq!(
+            page(&pagination, "/v0/availability/block/summaries/10/1000"),
+            Some((
+                "/v0/availability/block/summaries/10/510".into(),
+                "/v0/availability/block/summaries/510/1000".into()
+            ))
+        );
+        assert_eq!(
+            page(&pagination, "/availability-v0.3/payload/0/101"),
+            Some((
+                "/availability-v0.3/payload/0/100".into(),
+                "/availability-v0.3/payload/100/101".into()
+            ))
+        );
+
+        // The query string is carried over to the next page.
+        let url = Url::parse("http://localhost:8080/availability/block/0/1000?x=1").unwrap();
+        assert_eq!(
+            pagination.page(&url).unwrap().next,
+            "/availability/block/100/1000?x=1"
+        );
+
+        // Ranges within the limit, and other endpoints, are not paginated.
+        assert_eq!(page(&pagination, "/availability/block/0/100"), None);
+        assert_eq!(page(&pagination, "/availability/header/1000"), None);
+        assert_eq!(page(&pagination, "/availability/block/1/namespace/2"), None);
+        assert_eq!(
+            page(&pagination, "/node/transactions/count/0/1000000"),
+            None
+        );
+        assert_eq!(page(&pagination, "/availability/header/x/1000000"), None);
+    }
+
+    #[test]
+    fn test_max_range_size() {
+        let pagination = RangePagination::new(Some(10));
+        assert_eq!(
+            page(&pagination, "/availability/block/0/11"),
+            Some((
+                "/availability/block/0/10".into(),
+                "/availability/block/10/11".into()
+            ))
+        );
+        assert_eq!(
+            page(&pagination, "/availability/leaf/5/100"),
+            Some((
+                "/availability/leaf/5/15".into(),
+                "/availability/leaf/15/100".into()
+            ))
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! A reconfigurable set of peers for fetching missing query service data.
+//!
+//! The query service fetches data it is missing, such as payloads for blocks it did not receive
+//! from consensus, from the peer query services given by `--peers`. [`QueryPeers`] lets the set of
+//! peers be changed while the node is running, via the `peers` API module served by the admin API
+//! server, without restarting consensus. Changes made this way are not persisted: after a restart,
+//! the node again uses the peers given on the command line.
+//!
+//! Each request is raced against several peers at once, preferring the peers which have responded
+//! fastest in the past, and the first valid response wins. This keeps a single slow or unresponsive
+//! peer from delaying every fetch.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_lock::RwLock;
+use async_trait::async_trait;
+use derivative::Derivative;
+use futures::stream::{FuturesUnordered, StreamExt};
+use hotshot_query_service::fetching::{
+    provider::{Provider, QueryServiceProvider},
+    Request,
+};
+use parking_lot::Mutex;
+use tide_disco::Url;
+
+use crate::{SeqTypes, SequencerApiVersion};
+
+/// Weight of the latest measurement in each peer's moving average latency.
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// Latency recorded for a peer which fails to provide the requested data.
+///
+/// Failures are recorded as slow responses, so that peers which are often missing data, or down,
+/// drift to the back of the queue without being removed outright.
+const FAILURE_PENALTY: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+struct Peer {
+    url: Url,
+    provider: Arc<QueryServiceProvider<SequencerApiVersion>>,
+    /// Moving average of the time this peer takes to respond, if it has been measured.
+    latency: Arc<Mutex<Option<Duration>>>,
+}
+
+impl Peer {
+    fn new(url: Url, bind_version: SequencerApiVersion) -> Self {
+        tracing::info!("will fetch missing data from {url}");
+        Self {
+            provider: Arc::new(QueryServiceProvider::new(url.clone(), bind_version)),
+            url,
+            latency: Default::default(),
+        }
+    }
+
+    /// The expected latency of this peer.
+    ///
+    /// Peers which have not been measured yet are assumed to be fast, so that they get a chance to
+    /// be measured.
+    fn latency(&self) -> Duration {
+        self.latency.lock().unwrap_or_default()
+    }
+
+    fn record_latency(&self, elapsed: Duration) {
+        let mut latency = self.latency.lock();
+        *latency = Some(match *latency {
+            Some(avg) => avg.mul_f64(1. - LATENCY_WEIGHT) + elapsed.mul_f64(LATENCY_WEIGHT),
+            None => elapsed,
+        });
+    }
+
+    async fn fetch<T>(&self, req: T) -> Option<T::Response>
+    where
+        T: Request<SeqTypes> + 'static,
+        QueryServiceProvider<SequencerApiVersion>: Provider<SeqTypes, T>,
+    {
+        let start = Instant::now();
+        let res = self.provider.fetch(req).await;
+        match &res {
+            Some(_) => self.record_latency(start.elapsed()),
+            None => {
+                tracing::debug!(peer = %self.url, ?req, "failed to fetch from peer");
+                self.record_latency(start.elapsed().max(FAILURE_PENALTY));
+            }
+        }
+        res
+    }
+}
+
+/// Peer query services to fetch missing data from, which can be updated at runtime.
+///
+/// Each request is sent concurrently to the `concurrency` peers with the lowest average latency,
+/// and the first valid response is used. If none of them can provide the data, the request is
+/// raced against the next fastest group of peers, and so on, until every peer has been tried.
+#[derive(Clone, Derivative)]
+#[derivative(Debug)]
+pub struct QueryPeers {
+    #[derivative(Debug = "ignore")]
+    peers: Arc<RwLock<Vec<Peer>>>,
+    concurrency: usize,
+    bind_version: SequencerApiVersion,
+}
+
+impl QueryPeers {
+    pub fn new(peers: impl IntoIterator<Item = Url>, bind_version: SequencerApiVersion) -> Self {
+        let peers = peers
+            .into_iter()
+            .map(|url| Peer::new(url, bind_version))
+            .collect();
+        Self {
+            peers: Arc::new(RwLock::new(peers)),
+            concurrency: 1,
+            bind_version,
+        }
+    }
+
+    /// Send each request to up to `concurrency` peers at once.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// The peers in the order requests are sent to them: fastest first, and otherwise in the order
+    /// they were added.
+    async fn by_latency(&self) -> Vec<Peer> {
+        // Take a snapshot of the peers so that the lock is not held while fetching, which would
+        // block updates for as long as the slowest peer takes to respond.
+        let mut peers = self.peers.read().await.clone();
+        peers.sort_by_key(Peer::latency);
+        peers
+    }
+
+    /// The URLs of the current peers, in the order they were added.
+    pub async fn list(&self) -> Vec<Url> {
+        self.peers
+            .read()
+            .await
+            .iter()
+            .map(|peer| peer.url.clone())
+            .collect()
+    }
+
+    /// Start fetching missing data from `url`.
+    ///
+    /// Returns `false` if `url` is already a peer.
+    pub async fn add(&self, url: Url) -> bool {
+        let mut peers = self.peers.write().await;
+        if peers.iter().any(|peer| peer.url == url) {
+            return false;
+        }
+        peers.push(Peer::new(url, self.bind_version));
+        true
+    }
+
+    /// Stop fetching missing data from `url`.
+    ///
+    /// Returns `false` if `url` is not a peer. Requests already in flight to this peer are allowed
+    /// to complete.
+    pub async fn remove(&self, url: &Url) -> bool {
+        let mut peers = self.peers.write().await;
+        let len = peers.len();
+        peers.retain(|peer| &peer.url != url);
+        if peers.len() == len {
+            return false;
+        }
+        tracing::info!("will no longer fetch missing data from {url}");
+        true
+    }
+}
+
+#[async_trait]
+impl<T> Provider<SeqTypes, T> for QueryPeers
+where
+    T: Request<SeqTypes> + 'static,
+    QueryServiceProvider<SequencerApiVersion>: Provider<SeqTypes, T>,
+{
+    async fn fetch(&self, req: T) -> Option<T::Response> {
+        let peers = self.by_latency().await;
+        for group in peers.chunks(self.concurrency) {
+            // Dropping the remaining fetches once one succeeds cancels them.
+            let mut fetches = group
+                .iter()
+                .map(|peer| peer.fetch(req))
+                .collect::<FuturesUnordered<_>>();
+            while let Some(res) = fetches.next().await {
+                if res.is_some() {
+                    return res;
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use vbs::version::StaticVersionType;
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_update_query_peers() {
+        let a: Url = "http://a.com".parse().unwrap();
+        let b: Url = "http://b.com".parse().unwrap();
+        let c: Url = "http://c.com".parse().unwrap();
+        let peers = QueryPeers::new([a.clone(), b.clone()], SequencerApiVersion::instance());
+        assert_eq!(peers.list().await, [a.clone(), b.clone()]);
+
+        // New peers are tried last, and duplicates are ignored.
+        assert!(peers.add(c.clone()).await);
+        assert!(!peers.add(a.clone()).await);
+        assert_eq!(peers.list().await, [a.clone(), b.clone(), c.clone()]);
+
+        // Updates are visible through clones.
+        let clone = peers.clone();
+        assert!(clone.remove(&b).await);
+        assert!(!clone.remove(&b).await);
+        assert_eq!(peers.list().await, [a, c]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_query_peers_by_latency() {
+        let a: Url = "http://a.com".parse().unwrap();
+        let b: Url = "http://b.com".parse().unwrap();
+        let c: Url = "http://c.com".parse().unwrap();
+        let peers = QueryPeers::new(
+            [a.clone(), b.clone(), c.clone()],
+            SequencerApiVersion::instance(),
+        );
+        let order = |peers: Vec<Peer>| peers.into_iter().map(|peer| peer.url).collect::<Vec<_>>();
+
+        // Unmeasured peers are tried in the order they were added.
+        assert_eq!(
+            order(peers.by_latency().await),
+            [a.clone(), b.clone(), c.clone()]
+        );
+
+        // Measured peers are ordered by latency, after unmeasured ones.
+        {
+            let snapshot = peers.peers.read().await;
+            snapshot[0].record_latency(Duration::from_millis(500));
+            snapshot[1].record_latency(Duration::from_millis(100));
+        }
+        assert_eq!(
+            order(peers.by_latency().await),
+            [c.clone(), b.clone(), a.clone()]
+        );
+
+        // A failure pushes a peer to the back.
+        {
+            let snapshot = peers.peers.read().await;
+            snapshot[2].record_latency(FAILURE_PENALTY);
+        }
+        assert_eq!(order(peers.by_latency().await), [b, a, c]);
+    }
+
+    #[test]
+    fn test_latency_moving_average() {
+        let peer = Peer::new(
+            "http://a.com".parse().unwrap(),
+            SequencerApiVersion::instance(),
+        );
+        assert_eq!(peer.latency(), Duration::ZERO);
+        peer.record_latency(Duration::from_millis(100));
+        assert_eq!(peer.latency(), Duration::from_millis(100));
+        peer.record_latency(Duration::from_millis(600));
+        assert!((peer.latency().as_secs_f64() - 0.2).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,787 @@
+//! Pruning of old payloads and VID data from query storage.
+//!
+//! Block payloads and VID data make up the bulk of the storage used by a query node, but most
+//! consumers only ever need recent payloads. The [`PayloadPruner`] periodically discards payloads
+//! and VID data for old blocks, according to a retention policy given by
+//! [`PayloadPruningOptions`], while keeping headers and leaves, so that the node can still serve
+//! the full chain of headers and merklized state.
+//!
+//! Namespaces can also be given their own, tighter retention windows with [`NamespaceRetention`]
+//! policies, so that a few namespaces posting large blobs do not force short retention on everyone
+//! else. A block is pruned early once every namespace in it has a policy and has outlived its
+//! window, while blocks containing any other namespace are kept according to the global policy.
+//!
+//! If a [`PayloadArchive`] is configured, pruned data is uploaded to the archive before it is deleted
+//! from the database, rather than being discarded.
+//!
+//! Pruning progress is reported via metrics in the `pruner` group, which are served on the
+//! `status/metrics` endpoint.
+
+use std::{
+    collections::HashMap,
+    ops::Range,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use clap::Parser;
+use espresso_types::{parse_duration, parse_size, NamespaceId};
+use futures::{
+    future,
+    stream::{self, StreamExt, TryStreamExt},
+};
+use hotshot_query_service::{
+    availability::AvailabilityDataSource, data_source::ExtensibleDataSource,
+    status::StatusDataSource,
+};
+use hotshot_types::traits::metrics::{Counter, Gauge, Metrics};
+use tokio::time::sleep;
+
+use super::archive::PayloadArchive;
+use crate::SeqTypes;
+
+/// How long to wait for a block which is about to be archived to be available locally.
+const ARCHIVE_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum number of blocks to upload to the archive concurrently.
+const ARCHIVE_CONCURRENCY: usize = 16;
+
+/// How often to run the payload pruner in headers-only mode.
+const HEADERS_ONLY_PRUNER_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long the payloads of a namespace are kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetentionWindow {
+    /// Keep payloads for this many of the most recent blocks.
+    Blocks(u64),
+    /// Keep payloads for blocks newer than this.
+    Period(Duration),
+}
+
+/// A retention policy for the payloads of a single namespace.
+///
+/// Parsed from strings of the form `ns=<id>,blocks=<n>` or `ns=<id>,period=<duration>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NamespaceRetention {
+    pub namespace: NamespaceId,
+    pub window: RetentionWindow,
+}
+
+impl FromStr for NamespaceRetention {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut namespace = None;
+        let mut window = None;
+        for field in s.split(',') {
+            let (key, value) = field.split_once('=').context(format!(
+                "malformed namespace retention field {field:?}; expected key=value"
+            ))?;
+            let value = value.trim();
+            let parsed = match key.trim() {
+                "ns" => {
+                    let ns: u32 = value
+                        .parse()
+                        .context(format!("invalid namespace {value:?}"))?;
+                    namespace = Some(NamespaceId::from(ns));
+                    continue;
+                }
+                "blocks" => RetentionWindow::Blocks(
+                    value
+                        .parse()
+                        .context(format!("invalid number of blocks {value:?}"))?,
+                ),
+                "period" => RetentionWindow::Period(
+                    parse_duration(value).context(format!("invalid period {value:?}"))?,
+                ),
+                key => bail!("unknown namespace retention field {key:?}"),
+            };
+            if window.replace(parsed).is_some() {
+                bail!("namespace retention must have exactly one of blocks or period");
+            }
+        }
+        Ok(Self {
+            namespace: namespace.context("namespace retention is missing namespace (ns=<id>)")?,
+            window: window.context(
+                "namespace retention is missing window (blocks=<n> or period=<duration>)",
+            )?,
+        })
+    }
+}
+
+/// Retention policy for block payloads and VID data.
+///
+/// Data for a block is pruned if _any_ of the configured retention policies allows it. The latest
+/// block is never pruned. Headers and leaves are never pruned.
+///
+/// Pruning is only supported for SQL storage, and replaces the query service's own pruner: it
+/// cannot be combined with `--prune` or `--archive`. File system storage is out of scope, since
+/// the file system data source of the query service has no way to delete individual payloads.
+#[derive(Parser, Clone, Debug, Default)]
+pub struct PayloadPruningOptions {
+    /// Keep payloads and VID data for only this many of the most recent blocks.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_PAYLOAD_RETENTION_BLOCKS")]
+    pub payload_retention_blocks: Option<u64>,
+
+    /// Keep payloads and VID data for only blocks newer than this.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_PAYLOAD_RETENTION_PERIOD",
+        value_parser = parse_duration,
+    )]
+    pub payload_retention_period: Option<Duration>,
+
+    /// Prune payloads and VID data, oldest first, while storage usage exceeds this size.
+    ///
+    /// Accepts sizes like `500GB`. At most one batch of blocks is pruned per pruner interval when
+    /// this policy is in effect, since some databases only return freed space to the operating
+    /// system lazily.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_PAYLOAD_RETENTION_TARGET_USAGE",
+        value_parser = parse_size,
+    )]
+    pub payload_retention_target_usage: Option<u64>,
+
+    /// Retention policies for the payloads of individual namespaces.
+    ///
+    /// Each policy has the form `ns=<id>,blocks=<n>` or `ns=<id>,period=<duration>`. Multiple
+    /// policies can be given by repeating the option, or separated by `;`. A block is pruned once
+    /// it is outside the window of every namespace it contains, provided they all have a policy.
+    /// Blocks containing other namespaces are only pruned by the global retention policies.
+    #[clap(
+        long = "namespace-retention",
+        env = "ESPRESSO_SEQUENCER_NAMESPACE_RETENTION",
+        value_delimiter = ';'
+    )]
+    pub namespace_retention: Vec<NamespaceRetention>,
+
+    /// How often to run the payload pruner.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_PAYLOAD_PRUNER_INTERVAL",
+        value_parser = parse_duration,
+        default_value = "1h",
+    )]
+    pub payload_pruner_interval: Duration,
+
+    /// Maximum number of blocks to prune in a single database transaction.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_PAYLOAD_PRUNER_BATCH_SIZE",
+        default_value = "1000"
+    )]
+    pub payload_pruner_batch_size: u64,
+
+    /// Keep VID data when pruning payloads.
+    ///
+    /// This is set by the headers-only query mode, rather than on the command line.
+    #[clap(skip)]
+    pub keep_vid: bool,
+}
+
+impl PayloadPruningOptions {
+    /// Whether any retention policy is configured.
+    pub fn is_enabled(&self) -> bool {
+        self.payload_retention_blocks.is_some()
+            || self.payload_retention_period.is_some()
+            || self.payload_retention_target_usage.is_some()
+            || !self.namespace_retention.is_empty()
+    }
+
+    /// The retention policy for headers-only mode.
+    ///
+    /// Each payload is discarded soon after a newer block is decided, but VID data is kept.
+    pub fn headers_only(self) -> Self {
+        Self {
+            payload_retention_blocks: Some(0),
+            payload_pruner_interval: self
+                .payload_pruner_interval
+                .min(HEADERS_ONLY_PRUNER_INTERVAL),
+            keep_vid: true,
+            ..self
+        }
+    }
+
+    /// The height below which payloads may be pruned according to this policy.
+    fn cutoff(&self, status: &PruningStatus) -> u64 {
+        let mut cutoff = status.pruned_height;
+        if let Some(blocks) = self.payload_retention_blocks {
+            cutoff = cutoff.max(status.block_height.saturating_sub(blocks));
+        }
+        if self.payload_retention_period.is_some() {
+            // If no block is recent enough to keep, every block is old enough to prune.
+            cutoff = cutoff.max(status.first_retained_block.unwrap_or(status.block_height));
+        }
+        if let (Some(target), Some(usage)) =
+            (self.payload_retention_target_usage, status.storage_usage)
+        {
+            if usage > target {
+                cutoff = cutoff.max(status.pruned_height + self.payload_pruner_batch_size.max(1));
+            }
+        }
+
+        // Always keep the latest block.
+        cutoff.min(status.block_height.saturating_sub(1))
+    }
+}
+
+/// Progress of namespace-based pruning.
+///
+/// Blocks below `resolved` have been pruned or kept for good: every namespace with a policy had
+/// outlived its window when they were checked. Blocks in `[resolved, scanned)` have been checked,
+/// but some were kept only because a namespace in them was still within its window; they are
+/// checked again once `resolved` reaches them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NamespacePruningProgress {
+    pub resolved: u64,
+    pub scanned: u64,
+}
+
+/// Whether a block at `height` containing `namespaces` has outlived every namespace's window.
+///
+/// `cutoffs` gives, for each namespace with a policy, the height below which its payloads may be
+/// pruned. Namespaces without a policy (or which could not be read) are kept forever, and so are
+/// empty blocks, which cost next to nothing to store.
+fn is_expired(
+    namespaces: impl IntoIterator<Item = Option<NamespaceId>>,
+    height: u64,
+    cutoffs: &HashMap<NamespaceId, u64>,
+) -> bool {
+    let mut namespaces = namespaces.into_iter().peekable();
+    namespaces.peek().is_some()
+        && namespaces.all(|ns| {
+            ns.and_then(|ns| cutoffs.get(&ns))
+                .is_some_and(|&cutoff| height < cutoff)
+        })
+}
+
+/// The Unix timestamp `period` ago.
+fn timestamp_before(period: Duration) -> u64 {
+    SystemTime::now()
+        .checked_sub(period)
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|t| t.as_secs())
+        .unwrap_or_default()
+}
+
+/// Storage which supports pruning payloads and VID data.
+#[async_trait]
+pub trait PayloadPruning: Send + Sync {
+    /// The height below which payloads and VID data have been pruned.
+    async fn payload_pruned_height(&self) -> anyhow::Result<u64>;
+
+    /// The height of the first block with a timestamp at or after `timestamp`, if there is one.
+    async fn first_block_since(&self, timestamp: u64) -> anyhow::Result<Option<u64>>;
+
+    /// The storage space currently in use, in bytes.
+    async fn storage_usage(&self) -> anyhow::Result<u64>;
+
+    /// Delete payloads, and VID data if `vid` is set, for blocks in the range `[from, to)`.
+    ///
+    /// On success, the pruned height is updated to `to`.
+    async fn prune_payloads(&self, from: u64, to: u64, vid: bool) -> anyhow::Result<()>;
+
+    /// The progress of namespace-based pruning.
+    async fn namespace_pruning_progress(&self) -> anyhow::Result<NamespacePruningProgress>;
+
+    /// Delete payloads, and VID data if `vid` is set, for the blocks at `heights`.
+    ///
+    /// On success, the progress of namespace-based pruning is updated to `progress`. The pruned
+    /// height is not affected.
+    async fn prune_namespace_payloads(
+        &self,
+        heights: &[u64],
+        vid: bool,
+        progress: NamespacePruningProgress,
+    ) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+impl<T, S> PayloadPruning for ExtensibleDataSource<T, S>
+where
+    T: PayloadPruning,
+    S: Send + Sync,
+{
+    async fn payload_pruned_height(&self) -> anyhow::Result<u64> {
+        self.inner().payload_pruned_height().await
+    }
+
+    async fn first_block_since(&self, timestamp: u64) -> anyhow::Result<Option<u64>> {
+        self.inner().first_block_since(timestamp).await
+    }
+
+    async fn storage_usage(&self) -> anyhow::Result<u64> {
+        self.inner().storage_usage().await
+    }
+
+    async fn prune_payloads(&self, from: u64, to: u64, vid: bool) -> anyhow::Result<()> {
+        self.inner().prune_payloads(from, to, vid).await
+    }
+
+    async fn namespace_pruning_progress(&self) -> anyhow::Result<NamespacePruningProgress> {
+        self.inner().namespace_pruning_progress().await
+    }
+
+    async fn prune_namespace_payloads(
+        &self,
+        heights: &[u64],
+        vid: bool,
+        progress: NamespacePruningProgress,
+    ) -> anyhow::Result<()> {
+        self.inner()
+            .prune_namespace_payloads(heights, vid, progress)
+            .await
+    }
+}
+
+/// Inputs to the retention policy, as of a single run of the pruner.
+#[derive(Clone, Copy, Debug, Default)]
+struct PruningStatus {
+    block_height: u64,
+    pruned_height: u64,
+    first_retained_block: Option<u64>,
+    storage_usage: Option<u64>,
+}
+
+#[derive(Debug)]
+struct PrunerMetrics {
+    pruned_height: Box<dyn Gauge>,
+    storage_usage: Box<dyn Gauge>,
+    pruned_blocks: Box<dyn Counter>,
+    namespace_pruned_blocks: Box<dyn Counter>,
+    archived_blocks: Box<dyn Counter>,
+}
+
+impl PrunerMetrics {
+    fn new(metrics: &dyn Metrics) -> Self {
+        let metrics = metrics.subgroup("pruner".into());
+        Self {
+            pruned_height: metrics.create_gauge("payload_pruned_height".into(), None),
+            storage_usage: metrics.create_gauge("storage_usage".into(), Some("bytes".into())),
+            pruned_blocks: metrics.create_counter("payload_pruned_blocks".into(), None),
+            namespace_pruned_blocks: metrics
+                .create_counter("namespace_payload_pruned_blocks".into(), None),
+            archived_blocks: metrics.create_counter("payload_archived_blocks".into(), None),
+        }
+    }
+}
+
+/// Background task which prunes payloads and VID data according to a retention policy.
+#[derive(Debug)]
+pub(crate) struct PayloadPruner<D> {
+    storage: Arc<D>,
+    opt: PayloadPruningOptions,
+    archive: Option<PayloadArchive>,
+    metrics: PrunerMetrics,
+}
+
+impl<D> PayloadPruner<D>
+where
+    D: PayloadPruning + StatusDataSource + AvailabilityDataSource<SeqTypes>,
+{
+    pub(crate) fn new(
+        storage: Arc<D>,
+        opt: PayloadPruningOptions,
+        archive: Option<PayloadArchive>,
+        metrics: &dyn Metrics,
+    ) -> Self {
+        Self {
+            storage,
+            opt,
+            archive,
+            metrics: PrunerMetrics::new(metrics),
+        }
+    }
+
+    /// Run the pruner forever.
+    pub(crate) async fn run(&self) {
+        tracing::info!(opt = ?self.opt, "starting payload pruner");
+        loop {
+            if let Err(err) = self.prune().await {
+                tracing::warn!("error pruning payloads: {err:#}");
+            }
+            sleep(self.opt.payload_pruner_interval).await;
+        }
+    }
+
+    async fn prune(&self) -> anyhow::Result<()> {
+        let status = self.status().await?;
+        self.metrics
+            .pruned_height
+            .set(status.pruned_height as usize);
+        if let Some(usage) = status.storage_usage {
+            self.metrics.storage_usage.set(usage as usize);
+        }
+
+        let cutoff = self.opt.cutoff(&status);
+        if cutoff > status.pruned_height {
+            self.prune_range(status.pruned_height, cutoff).await?;
+        } else {
+            tracing::debug!(?status, "nothing to prune");
+        }
+
+        if !self.opt.namespace_retention.is_empty() {
+            self.prune_namespaces(cutoff.max(status.pruned_height), status.block_height)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Prune all blocks in `[from, cutoff)`, advancing the pruned height.
+    async fn prune_range(&self, mut from: u64, cutoff: u64) -> anyhow::Result<()> {
+        tracing::info!(from, to = cutoff, "pruning payloads");
+
+        let batch_size = self.opt.payload_pruner_batch_size.max(1);
+        while from < cutoff {
+            let to = cutoff.min(from + batch_size);
+            if let Some(archive) = &self.archive {
+                self.archive(archive, from, to)
+                    .await
+                    .context(format!("archiving payloads in [{from}, {to})"))?;
+            }
+            self.storage
+                .prune_payloads(from, to, !self.opt.keep_vid)
+                .await
+                .context(format!("pruning payloads in [{from}, {to})"))?;
+            self.metrics.pruned_height.set(to as usize);
+            self.metrics.pruned_blocks.add((to - from) as usize);
+            tracing::debug!(from, to, "pruned payloads");
+            from = to;
+        }
+        Ok(())
+    }
+
+    /// Prune blocks whose namespaces have all outlived their retention windows.
+    ///
+    /// `pruned_height` is the height below which everything has already been pruned.
+    async fn prune_namespaces(&self, pruned_height: u64, block_height: u64) -> anyhow::Result<()> {
+        let cutoffs = self.namespace_cutoffs(block_height).await?;
+        // Always keep the latest block.
+        let latest = block_height.saturating_sub(1);
+        let lo = cutoffs
+            .values()
+            .copied()
+            .min()
+            .unwrap_or_default()
+            .min(latest);
+        let hi = cutoffs
+            .values()
+            .copied()
+            .max()
+            .unwrap_or_default()
+            .min(latest);
+
+        let mut progress = self.storage.namespace_pruning_progress().await?;
+        progress.resolved = progress.resolved.max(pruned_height);
+        progress.scanned = progress.scanned.max(progress.resolved);
+
+        // Below `lo`, every namespace with a policy has outlived its window, so each block is either
+        // pruned or kept for good. Above it, blocks are pruned only if all their namespaces have
+        // outlived their windows, and the rest are checked again once `lo` passes them.
+        let settled = progress.resolved..lo;
+        let unsettled = progress.scanned.max(lo)..hi;
+        let batch_size = self.opt.payload_pruner_batch_size.max(1);
+        for (range, settle) in [(settled, true), (unsettled, false)] {
+            let mut from = range.start;
+            while from < range.end {
+                let to = range.end.min(from + batch_size);
+                let heights = self.expired_blocks(from..to, &cutoffs).await?;
+                if let Some(archive) = &self.archive {
+                    for &height in &heights {
+                        self.archive(archive, height, height + 1)
+                            .await
+                            .context(format!("archiving payload {height}"))?;
+                    }
+                }
+
+                if settle {
+                    progress.resolved = to;
+                }
+                progress.scanned = progress.scanned.max(to);
+                self.storage
+                    .prune_namespace_payloads(&heights, !self.opt.keep_vid, progress)
+                    .await
+                    .context(format!("pruning namespace payloads in [{from}, {to})"))?;
+                self.metrics.namespace_pruned_blocks.add(heights.len());
+                tracing::debug!(
+                    from,
+                    to,
+                    pruned = heights.len(),
+                    "pruned namespace payloads"
+                );
+                from = to;
+            }
+        }
+        Ok(())
+    }
+
+    /// The height below which each namespace with a retention policy may be pruned.
+    async fn namespace_cutoffs(
+        &self,
+        block_height: u64,
+    ) -> anyhow::Result<HashMap<NamespaceId, u64>> {
+        let mut cutoffs = HashMap::new();
+        for policy in &self.opt.namespace_retention {
+            let cutoff = match policy.window {
+                RetentionWindow::Blocks(blocks) => block_height.saturating_sub(blocks),
+                RetentionWindow::Period(period) => self
+                    .storage
+                    .first_block_since(timestamp_before(period))
+                    .await?
+                    .unwrap_or(block_height),
+            };
+            // As with the global policies, a namespace is pruned if any of its policies allows it.
+            let entry = cutoffs.entry(policy.namespace).or_default();
+            *entry = cutoff.max(*entry);
+        }
+        Ok(cutoffs)
+    }
+
+    /// The heights in `range` of blocks which have outlived the window of every namespace in them.
+    async fn expired_blocks(
+        &self,
+        range: Range<u64>,
+        cutoffs: &HashMap<NamespaceId, u64>,
+    ) -> anyhow::Result<Vec<u64>> {
+        stream::iter(range)
+            .map(|height| async move {
+                let leaf = self
+                    .storage
+                    .get_leaf(height as usize)
+                    .await
+                    .with_timeout(ARCHIVE_FETCH_TIMEOUT)
+                    .await
+                    .context(format!("leaf {height} is not available"))?;
+                let ns_table = leaf.header().ns_table();
+                let namespaces = ns_table.iter().map(|index| ns_table.read_ns_id(&index));
+                anyhow::Ok(is_expired(namespaces, height, cutoffs).then_some(height))
+            })
+            .buffered(ARCHIVE_CONCURRENCY)
+            .try_filter_map(|height| future::ready(Ok(height)))
+            .try_collect()
+            .await
+    }
+
+    /// Upload payloads and VID common data for blocks in `[from, to)` to `archive`.
+    async fn archive(&self, archive: &PayloadArchive, from: u64, to: u64) -> anyhow::Result<()> {
+        stream::iter((from..to).map(Ok))
+            .try_for_each_concurrent(ARCHIVE_CONCURRENCY, |height| async move {
+                let block = self
+                    .storage
+                    .get_block(height as usize)
+                    .await
+                    .with_timeout(ARCHIVE_FETCH_TIMEOUT)
+                    .await
+                    .context(format!("block {height} is not available"))?;
+                let common = self
+                    .storage
+                    .get_vid_common(height as usize)
+                    .await
+                    .with_timeout(ARCHIVE_FETCH_TIMEOUT)
+                    .await
+                    .context(format!("VID common {height} is not available"))?;
+                archive
+                    .put_payload(block.payload_hash(), block.payload())
+                    .await?;
+                archive
+                    .put_vid_common(block.payload_hash(), common.common())
+                    .await
+            })
+            .await?;
+        self.metrics.archived_blocks.add((to - from) as usize);
+        tracing::debug!(from, to, "archived payloads");
+        Ok(())
+    }
+
+    async fn status(&self) -> anyhow::Result<PruningStatus> {
+        let block_height = self.storage.block_height().await? as u64;
+        let pruned_height = self.storage.payload_pruned_height().await?;
+        let first_retained_block = match self.opt.payload_retention_period {
+            Some(period) => {
+                self.storage
+                    .first_block_since(timestamp_before(period))
+                    .await?
+            }
+            None => None,
+        };
+        let storage_usage = match self.opt.payload_retention_target_usage {
+            Some(_) => Some(self.storage.storage_usage().await?),
+            None => None,
+        };
+        Ok(PruningStatus {
+            block_height,
+            pruned_height,
+            first_retained_block,
+            storage_usage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn options() -> PayloadPruningOptions {
+        PayloadPruningOptions::parse_from(std::iter::empty::<String>())
+    }
+
+    #[test]
+    fn test_pruning_disabled_by_default() {
+        let opt = options();
+        assert!(!opt.is_enabled());
+        let status = PruningStatus {
+            block_height: 100,
+            pruned_height: 10,
+            ..Default::default()
+        };
+        assert_eq!(opt.cutoff(&status), 10);
+    }
+
+    #[test]
+    fn test_pruning_cutoff() {
+        let status = PruningStatus {
+            block_height: 100,
+            pruned_height: 10,
+            first_retained_block: Some(50),
+            storage_usage: Some(1000),
+        };
+
+        // Retention by height.
+        let opt = PayloadPruningOptions {
+            payload_retention_blocks: Some(30),
+            ..options()
+        };
+        assert_eq!(opt.cutoff(&status), 70);
+
+        // Retention by age.
+        let opt = PayloadPruningOptions {
+            payload_retention_period: Some(Duration::from_secs(60)),
+            ..options()
+        };
+        assert_eq!(opt.cutoff(&status), 50);
+
+        // Retention by storage usage prunes one batch at a time, only when over the target.
+        let opt = PayloadPruningOptions {
+            payload_retention_target_usage: Some(500),
+            payload_pruner_batch_size: 5,
+            ..options()
+        };
+        assert_eq!(opt.cutoff(&status), 15);
+        let opt = PayloadPruningOptions {
+            payload_retention_target_usage: Some(2000),
+            ..opt
+        };
+        assert_eq!(opt.cutoff(&status), 10);
+
+        // Data is pruned if any policy allows it.
+        let opt = PayloadPruningOptions {
+            payload_retention_blocks: Some(30),
+            payload_retention_period: Some(Duration::from_secs(60)),
+            ..options()
+        };
+        assert_eq!(opt.cutoff(&status), 70);
+
+        // The latest block is never pruned, and pruning never goes backwards.
+        let opt = PayloadPruningOptions {
+            payload_retention_blocks: Some(0),
+            ..options()
+        };
+        assert_eq!(opt.cutoff(&status), 99);
+        let opt = PayloadPruningOptions {
+            payload_retention_period: Some(Duration::from_secs(60)),
+            ..options()
+        };
+        let status = PruningStatus {
+            first_retained_block: None,
+            ..status
+        };
+        assert_eq!(opt.cutoff(&status), 99);
+        let status = PruningStatus {
+            pruned_height: 80,
+            first_retained_block: Some(50),
+            ..status
+        };
+        assert_eq!(opt.cutoff(&status), 80);
+    }
+
+    #[test]
+    fn test_parse_namespace_retention() {
+        assert_eq!(
+            "ns=1,blocks=100".parse::<NamespaceRetention>().unwrap(),
+            NamespaceRetention {
+                namespace: NamespaceId::from(1u32),
+                window: RetentionWindow::Blocks(100),
+            }
+        );
+        assert_eq!(
+            "period=24h, ns=2".parse::<NamespaceRetention>().unwrap(),
+            NamespaceRetention {
+                namespace: NamespaceId::from(2u32),
+                window: RetentionWindow::Period(Duration::from_secs(24 * 60 * 60)),
+            }
+        );
+        "ns=1".parse::<NamespaceRetention>().unwrap_err();
+        "blocks=100".parse::<NamespaceRetention>().unwrap_err();
+        "ns=1,blocks=100,period=24h"
+            .parse::<NamespaceRetention>()
+            .unwrap_err();
+        "ns=1,blocks=many"
+            .parse::<NamespaceRetention>()
+            .unwrap_err();
+        "ns=1,size=1GB".parse::<NamespaceRetention>().unwrap_err();
+
+        let opt = PayloadPruningOptions {
+            namespace_retention: vec!["ns=1,blocks=100".parse().unwrap()],
+            ..options()
+        };
+        assert!(opt.is_enabled());
+    }
+
+    #[test]
+    fn test_namespace_expiry() {
+        let ns = |id: u32| Some(NamespaceId::from(id));
+        let cutoffs = [(NamespaceId::from(1u32), 50), (NamespaceId::from(2u32), 80)]
+            .into_iter()
+            .collect();
+
+        // A block is pruned once it is outside the window of every namespace in it.
+        assert!(is_expired([ns(1)], 49, &cutoffs));
+        assert!(!is_expired([ns(1)], 50, &cutoffs));
+        assert!(is_expired([ns(2)], 79, &cutoffs));
+        assert!(!is_expired([ns(1), ns(2)], 60, &cutoffs));
+        assert!(is_expired([ns(1), ns(2)], 40, &cutoffs));
+
+        // Namespaces without a policy, unreadable namespaces and empty blocks are kept.
+        assert!(!is_expired([ns(1), ns(3)], 10, &cutoffs));
+        assert!(!is_expired([ns(1), None], 10, &cutoffs));
+        assert!(!is_expired([], 10, &cutoffs));
+    }
+
+    #[test]
+    fn test_headers_only() {
+        let opt = options().headers_only();
+        assert!(opt.is_enabled());
+        assert!(opt.keep_vid);
+        assert_eq!(opt.payload_pruner_interval, HEADERS_ONLY_PRUNER_INTERVAL);
+
+        // Everything but the latest block is pruned.
+        let status = PruningStatus {
+            block_height: 100,
+            pruned_height: 10,
+            ..Default::default()
+        };
+        assert_eq!(opt.cutoff(&status), 99);
+
+        // A shorter configured interval is respected.
+        let opt = PayloadPruningOptions {
+            payload_pruner_interval: Duration::from_secs(1),
+            ..options()
+        }
+        .headers_only();
+        assert_eq!(opt.payload_pruner_interval, Duration::from_secs(1));
+    }
+}
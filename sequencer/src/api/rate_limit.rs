@@ -0,0 +1,215 @@
+//! Per-namespace rate limiting for transaction submission.
+
+use std::{collections::HashMap, str::FromStr, time::Instant};
+
+use anyhow::{bail, ensure, Context};
+use espresso_types::NamespaceId;
+use hotshot_types::traits::metrics::{Counter, Metrics};
+use parking_lot::Mutex;
+
+/// A limit on the rate of transactions submitted to a single namespace.
+///
+/// Parsed from strings of the form `ns=<id>,rate=<tx/s>`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NamespaceRateLimit {
+    pub namespace: NamespaceId,
+    /// Sustained number of transactions per second allowed for this namespace.
+    pub rate: f64,
+}
+
+impl FromStr for NamespaceRateLimit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut namespace = None;
+        let mut rate = None;
+        for field in s.split(',') {
+            let (key, value) = field.split_once('=').context(format!(
+                "malformed rate limit field {field:?}; expected key=value"
+            ))?;
+            match key.trim() {
+                "ns" => {
+                    let ns: u32 = value
+                        .trim()
+                        .parse()
+                        .context(format!("invalid namespace {value:?}"))?;
+                    namespace = Some(NamespaceId::from(ns));
+                }
+                "rate" => {
+                    let value: f64 = value
+                        .trim()
+                        .parse()
+                        .context(format!("invalid rate {value:?}"))?;
+                    ensure!(
+                        value.is_finite() && value >= 0.0,
+                        "rate must be a non-negative number"
+                    );
+                    rate = Some(value);
+                }
+                key => bail!("unknown rate limit field {key:?}"),
+            }
+        }
+        Ok(Self {
+            namespace: namespace.context("rate limit is missing namespace (ns=<id>)")?,
+            rate: rate.context("rate limit is missing rate (rate=<tx/s>)")?,
+        })
+    }
+}
+
+/// Token buckets enforcing [`NamespaceRateLimit`]s on submitted transactions.
+///
+/// Each limited namespace has a bucket holding up to one second's worth of transactions, which
+/// refills continuously at the configured rate. Namespaces without a configured limit are not
+/// limited.
+#[derive(Debug, Default)]
+pub(crate) struct SubmitRateLimiter {
+    buckets: HashMap<NamespaceId, TokenBucket>,
+}
+
+impl SubmitRateLimiter {
+    pub(crate) fn new(limits: &[NamespaceRateLimit], metrics: &dyn Metrics) -> Self {
+        let metrics = metrics.subgroup("submit".into());
+        let buckets = limits
+            .iter()
+            .map(|limit| {
+                let rejected =
+                    metrics.create_counter(format!("rate_limited_{}", limit.namespace), None);
+                (
+                    limit.namespace,
+                    TokenBucket::new(limit.rate, Instant::now(), rejected),
+                )
+            })
+            .collect();
+        Self { buckets }
+    }
+
+    /// Take a token for a transaction in namespace `ns`.
+    ///
+    /// Returns `false` if the namespace has exceeded its rate limit, in which case the transaction
+    /// should be rejected.
+    pub(crate) fn check(&self, ns: NamespaceId) -> bool {
+        self.check_at(ns, Instant::now())
+    }
+
+    fn check_at(&self, ns: NamespaceId, now: Instant) -> bool {
+        match self.buckets.get(&ns) {
+            Some(bucket) => bucket.take(now),
+            None => true,
+        }
+    }
+}
+
+/// A token bucket which refills continuously at a fixed rate.
+#[derive(Debug)]
+pub(super) struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    state: Mutex<BucketState>,
+    /// Number of transactions rejected because the bucket was empty.
+    rejected: Box<dyn Counter>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub(super) fn new(rate: f64, now: Instant, rejected: Box<dyn Counter>) -> Self {
+        // Allow bursts of up to one second's worth of transactions, but always allow at least one
+        // transaction at a time so that fractional rates are not starved.
+        let capacity = rate.max(1.0);
+        Self {
+            rate,
+            capacity,
+            state: Mutex::new(BucketState {
+                tokens: capacity,
+                updated: now,
+            }),
+            rejected,
+        }
+    }
+
+    /// Take a token, if one is available.
+    pub(super) fn take(&self, now: Instant) -> bool {
+        let mut state = self.state.lock();
+        let elapsed = now.saturating_duration_since(state.updated).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.rate).min(self.capacity);
+        state.updated = now;
+
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            true
+        } else {
+            self.rejected.add(1);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use hotshot_types::traits::metrics::NoMetrics;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_rate_limit() {
+        assert_eq!(
+            "ns=42,rate=2.5".parse::<NamespaceRateLimit>().unwrap(),
+            NamespaceRateLimit {
+                namespace: 42u32.into(),
+                rate: 2.5
+            }
+        );
+        assert_eq!(
+            "rate=10, ns=1".parse::<NamespaceRateLimit>().unwrap(),
+            NamespaceRateLimit {
+                namespace: 1u32.into(),
+                rate: 10.0
+            }
+        );
+        "ns=1".parse::<NamespaceRateLimit>().unwrap_err();
+        "rate=1".parse::<NamespaceRateLimit>().unwrap_err();
+        "ns=1,rate=-1".parse::<NamespaceRateLimit>().unwrap_err();
+        "ns=x,rate=1".parse::<NamespaceRateLimit>().unwrap_err();
+        "ns=1,rate=1,burst=2"
+            .parse::<NamespaceRateLimit>()
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_submit_rate_limiter() {
+        let limited = NamespaceId::from(1u32);
+        let unlimited = NamespaceId::from(2u32);
+        let limiter = SubmitRateLimiter::new(
+            &[NamespaceRateLimit {
+                namespace: limited,
+                rate: 2.0,
+            }],
+            &NoMetrics,
+        );
+        let start = Instant::now();
+
+        // We can burst up to the rate, then get limited.
+        assert!(limiter.check_at(limited, start));
+        assert!(limiter.check_at(limited, start));
+        assert!(!limiter.check_at(limited, start));
+
+        // Other namespaces are not affected.
+        for _ in 0..10 {
+            assert!(limiter.check_at(unlimited, start));
+        }
+
+        // Tokens refill over time, but never beyond the burst capacity.
+        assert!(limiter.check_at(limited, start + Duration::from_millis(500)));
+        assert!(!limiter.check_at(limited, start + Duration::from_millis(500)));
+        let later = start + Duration::from_secs(60);
+        assert!(limiter.check_at(limited, later));
+        assert!(limiter.check_at(limited, later));
+        assert!(!limiter.check_at(limited, later));
+    }
+}
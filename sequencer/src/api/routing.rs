@@ -0,0 +1,306 @@
+//! Serving API modules on their own addresses.
+//!
+//! By default every API module is served on the HTTP API port. Operators who want some modules on a
+//! different interface, for example catchup on an internal network while the availability API is
+//! public, can assign modules to their own addresses with [`ModuleListener`]s. The app is then
+//! served on the main address and on each module address at once, and the [`ModuleRouting`]
+//! middleware makes sure each module is only reachable through its own address: a module address
+//! serves only the modules assigned to it, and the main address serves every module which is not
+//! assigned elsewhere. Requests for a module through the wrong address get a 404, as if the module
+//! did not exist.
+//!
+//! Like [`ApiAuth`](super::auth::ApiAuth), the middleware is installed by wrapping the listener the
+//! app is served on, in a [`RoutingListener`].
+
+use std::{
+    collections::HashSet,
+    fmt::{self, Display, Formatter},
+    io,
+    net::SocketAddr,
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{ensure, Context};
+use async_trait::async_trait;
+use tide::{
+    listener::{ListenInfo, Listener, ToListener},
+    Middleware, Next, Request, Response, Server, StatusCode,
+};
+
+use super::metrics::is_version;
+
+/// Routes served by the app itself rather than by a module, which are available on every address.
+const APP_ROUTES: &[&str] = &["healthcheck", "version"];
+
+/// An API module and the address it is served on.
+///
+/// Parsed from strings of the form `<module>=<ip>:<port>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModuleListener {
+    pub module: String,
+    pub addr: SocketAddr,
+}
+
+impl FromStr for ModuleListener {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (module, addr) = s
+            .split_once('=')
+            .context("module listener must have the form <module>=<ip>:<port>")?;
+        let module = module.trim();
+        ensure!(!module.is_empty(), "module listener is missing module name");
+        let addr = addr
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid address for module {module}"))?;
+        Ok(Self {
+            module: module.to_string(),
+            addr,
+        })
+    }
+}
+
+/// The module requested by a request for `path`, if any.
+///
+/// Skips a leading API version segment, e.g. `/v0/availability/...` is a request for
+/// `availability`.
+pub(super) fn request_module(path: &str) -> Option<&str> {
+    let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+    match segments.next() {
+        Some(segment) if is_version(segment) => segments.next(),
+        segment => segment,
+    }
+}
+
+/// The name of the module served under `name`, with any serialization version suffix removed.
+///
+/// See [`versioned_module`](super::options::versioned_module).
+pub(super) fn unversioned(name: &str) -> &str {
+    match name.rsplit_once("-v") {
+        Some((module, version))
+            if version
+                .split('.')
+                .all(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())) =>
+        {
+            module
+        }
+        _ => name,
+    }
+}
+
+/// Whether a connection accepted on `local` was accepted by a listener bound to `bind`.
+fn is_bound_to(bind: SocketAddr, local: SocketAddr) -> bool {
+    bind.port() == local.port() && (bind.ip().is_unspecified() || bind.ip() == local.ip())
+}
+
+/// Middleware which only serves each module through the address it is assigned to.
+#[derive(Clone, Debug)]
+pub(crate) struct ModuleRouting {
+    /// Each module address, with the modules served on it.
+    listeners: Arc<Vec<(SocketAddr, HashSet<String>)>>,
+}
+
+impl ModuleRouting {
+    /// Routing for modules served on their own addresses, or [`None`] if there are none.
+    ///
+    /// Modules assigned to `main`, the address of the HTTP API, are served there as usual.
+    pub(crate) fn new(main: SocketAddr, modules: &[ModuleListener]) -> Option<Self> {
+        let mut listeners: Vec<(SocketAddr, HashSet<String>)> = vec![];
+        for ModuleListener { module, addr } in modules {
+            if *addr == main {
+                continue;
+            }
+            match listeners.iter_mut().find(|(a, _)| a == addr) {
+                Some((_, modules)) => {
+                    modules.insert(module.clone());
+                }
+                None => listeners.push((*addr, [module.clone()].into())),
+            }
+        }
+        if listeners.is_empty() {
+            return None;
+        }
+        Some(Self {
+            listeners: Arc::new(listeners),
+        })
+    }
+
+    /// The addresses the app must be served on, in addition to the main address.
+    pub(crate) fn addrs(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.listeners.iter().map(|(addr, _)| *addr)
+    }
+
+    /// Whether a request for `path` may be served on a connection accepted on `local`.
+    ///
+    /// If the local address of the connection is not known, we cannot tell which listener accepted
+    /// it, so modules assigned to their own addresses are not served at all. Everything else is
+    /// served, as it would be on the main address.
+    fn is_allowed(&self, local: Option<SocketAddr>, path: &str) -> bool {
+        let Some(module) = request_module(path).map(unversioned) else {
+            return true;
+        };
+        if APP_ROUTES.contains(&module) {
+            return true;
+        }
+        match local.and_then(|local| {
+            self.listeners
+                .iter()
+                .find(|(addr, _)| is_bound_to(*addr, local))
+        }) {
+            Some((_, modules)) => modules.contains(module),
+            None => !self
+                .listeners
+                .iter()
+                .any(|(_, modules)| modules.contains(module)),
+        }
+    }
+}
+
+#[async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for ModuleRouting {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let local = req.local_addr().and_then(|addr| addr.parse().ok());
+        if local.is_none() {
+            tracing::debug!(
+                local_addr = req.local_addr(),
+                "unknown local address, not serving modules assigned to other addresses"
+            );
+        }
+        if !self.is_allowed(local, req.url().path()) {
+            return Ok(Response::new(StatusCode::NotFound));
+        }
+        Ok(next.run(req).await)
+    }
+}
+
+/// A [`Listener`] which installs [`ModuleRouting`], if enabled, on the server before delegating to
+/// another listener.
+#[derive(Debug)]
+pub(crate) struct RoutingListener<L> {
+    inner: L,
+    routing: Option<ModuleRouting>,
+}
+
+impl<L> RoutingListener<L> {
+    pub(crate) fn new(inner: L, routing: Option<ModuleRouting>) -> Self {
+        Self { inner, routing }
+    }
+}
+
+impl<L: Display> Display for RoutingListener<L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+#[async_trait]
+impl<State, L> Listener<State> for RoutingListener<L>
+where
+    State: Clone + Send + Sync + 'static,
+    L: Listener<State>,
+{
+    async fn bind(&mut self, mut app: Server<State>) -> io::Result<()> {
+        if let Some(routing) = &self.routing {
+            app.with(routing.clone());
+        }
+        self.inner.bind(app).await
+    }
+
+    async fn accept(&mut self) -> io::Result<()> {
+        self.inner.accept().await
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        self.inner.info()
+    }
+}
+
+impl<State, L> ToListener<State> for RoutingListener<L>
+where
+    State: Clone + Send + Sync + 'static,
+    L: Listener<State>,
+{
+    type Listener = Self;
+
+    fn to_listener(self) -> io::Result<Self::Listener> {
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_module_listener() {
+        assert_eq!(
+            "catchup=127.0.0.1:8081".parse::<ModuleListener>().unwrap(),
+            ModuleListener {
+                module: "catchup".into(),
+                addr: addr("127.0.0.1:8081"),
+            }
+        );
+        "catchup".parse::<ModuleListener>().unwrap_err();
+        "=127.0.0.1:8081".parse::<ModuleListener>().unwrap_err();
+        "catchup=8081".parse::<ModuleListener>().unwrap_err();
+    }
+
+    #[test]
+    fn test_module_routing() {
+        let main = addr("0.0.0.0:8080");
+        let listeners = [
+            "catchup=10.0.0.1:8081".parse().unwrap(),
+            "config=10.0.0.1:8081".parse().unwrap(),
+            "submit=0.0.0.0:8082".parse().unwrap(),
+            "status=0.0.0.0:8080".parse().unwrap(),
+        ];
+        let routing = ModuleRouting::new(main, &listeners).unwrap();
+        assert_eq!(
+            routing.addrs().collect::<Vec<_>>(),
+            [addr("10.0.0.1:8081"), addr("0.0.0.0:8082")]
+        );
+
+        let public = Some(addr("1.2.3.4:8080"));
+        let internal = Some(addr("10.0.0.1:8081"));
+        let submit = Some(addr("1.2.3.4:8082"));
+
+        // Unassigned modules and modules assigned to the main address are served there.
+        assert!(routing.is_allowed(public, "/availability/block/1"));
+        assert!(routing.is_allowed(public, "/v0/status/block-height"));
+        assert!(!routing.is_allowed(internal, "/availability/block/1"));
+        assert!(!routing.is_allowed(submit, "/v0/availability/block/1"));
+
+        // Assigned modules, including their versioned copies, are only served on their address.
+        assert!(routing.is_allowed(internal, "/catchup/1/2/account/0x00"));
+        assert!(routing.is_allowed(internal, "/v0/catchup-v0.3/1/2/account/0x00"));
+        assert!(routing.is_allowed(internal, "/config/env"));
+        assert!(!routing.is_allowed(public, "/catchup/1/2/account/0x00"));
+        assert!(!routing.is_allowed(public, "/catchup-v0.3/1/2/account/0x00"));
+        assert!(!routing.is_allowed(submit, "/catchup/1/2/account/0x00"));
+        assert!(routing.is_allowed(submit, "/submit/submit"));
+        assert!(!routing.is_allowed(public, "/submit/submit"));
+
+        // App routes are served everywhere.
+        assert!(routing.is_allowed(internal, "/healthcheck"));
+        assert!(routing.is_allowed(internal, "/v0/version"));
+        assert!(routing.is_allowed(internal, "/"));
+
+        // If we can't tell which address a connection was accepted on, assigned modules are not
+        // served, but everything else is.
+        assert!(!routing.is_allowed(None, "/catchup/1/2/account/0x00"));
+        assert!(!routing.is_allowed(None, "/v0/catchup-v0.3/1/2/account/0x00"));
+        assert!(!routing.is_allowed(None, "/submit/submit"));
+        assert!(routing.is_allowed(None, "/availability/block/1"));
+        assert!(routing.is_allowed(None, "/status/block-height"));
+        assert!(routing.is_allowed(None, "/healthcheck"));
+
+        // With no module listeners, there is no routing to do.
+        assert!(ModuleRouting::new(main, &[]).is_none());
+    }
+}
@@ -4,7 +4,7 @@ use committable::{Commitment, Committable};
 use espresso_types::{
     get_l1_deposits,
     v0_3::{ChainConfig, IterableFeeInfo},
-    BlockMerkleTree, FeeAccount, FeeMerkleTree, Leaf, NodeState, ValidatedState,
+    BlockMerkleTree, FeeAccount, FeeMerkleTree, Leaf, NamespaceId, NodeState, ValidatedState,
 };
 use hotshot::traits::ValidatedState as _;
 use hotshot_query_service::{
@@ -12,12 +12,12 @@ use hotshot_query_service::{
     data_source::{
         sql::{Config, SqlDataSource, Transaction},
         storage::{
-            sql::{query_as, Db, TransactionMode, Write},
+            sql::{query, query_as, Db, TransactionMode, Write},
             AvailabilityStorage, MerklizedStateStorage, NodeStorage, SqlStorage,
         },
-        VersionedDataSource,
+        Transaction as _, VersionedDataSource,
     },
-    merklized_state::Snapshot,
+    merklized_state::{MerklizedStateHeightPersistence, Snapshot, UpdateStateData},
     Resolvable,
 };
 use hotshot_types::{
@@ -29,17 +29,27 @@ use jf_merkle_tree::{
     prelude::MerkleNode, ForgetableMerkleTreeScheme, ForgetableUniversalMerkleTreeScheme,
     LookupResult, MerkleTreeScheme,
 };
-use sqlx::{Encode, Type};
-use std::collections::{HashSet, VecDeque};
+use sqlx::{types::Json, Encode, Type};
+use std::{
+    collections::{HashSet, VecDeque},
+    time::{Duration, Instant},
+};
 
 use super::{
-    data_source::{Provider, SequencerDataSource},
+    data_source::{BlockAtTime, ChainConfigActivation, Provider, SequencerDataSource},
+    db_pool::{ConnectionPool, PoolSample},
+    namespaces::NamespaceStats,
+    pruner::{NamespacePruningProgress, PayloadPruning},
+    stats::{
+        BlockStats, ExplorerStatsStorage, ExplorerSummary, NamespaceBytes, NamespaceThroughput,
+        WindowSummary,
+    },
     BlocksFrontier,
 };
 use crate::{
     catchup::{CatchupStorage, NullStateCatchup},
     persistence::{sql::Options, ChainConfigPersistence},
-    state::compute_state_update,
+    state::{compute_state_update, store_state_snapshot},
     SeqTypes,
 };
 
@@ -53,6 +63,7 @@ impl SequencerDataSource for DataSource {
         let fetch_limit = opt.fetch_rate_limit;
         let active_fetch_delay = opt.active_fetch_delay;
         let chunk_fetch_delay = opt.chunk_fetch_delay;
+        let disable_proactive_fetching = opt.disable_proactive_fetching;
         let mut cfg = Config::try_from(opt)?;
 
         if reset {
@@ -70,9 +81,555 @@ impl SequencerDataSource for DataSource {
         if let Some(delay) = chunk_fetch_delay {
             builder = builder.with_chunk_fetch_delay(delay);
         }
+        if disable_proactive_fetching {
+            builder = builder.disable_proactive_fetching();
+        }
 
         builder.build().await
     }
+
+    async fn load_state_snapshot(&self, height: u64) -> anyhow::Result<Option<ValidatedState>> {
+        let state_height = self.get_last_state_height().await? as u64;
+        ensure!(
+            height <= state_height,
+            "state at height {height} is not available; merklized state is only stored up to \
+             height {state_height}"
+        );
+
+        let mut tx = self.read().await.context(format!(
+            "opening transaction to load state snapshot at height {height}"
+        ))?;
+        load_state_snapshot(&mut tx, height).await.map(Some)
+    }
+
+    async fn import_state_snapshot(
+        &self,
+        height: u64,
+        state: &ValidatedState,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.write().await.context(format!(
+            "opening transaction to import state snapshot at height {height}"
+        ))?;
+        store_state_snapshot(&mut tx, height, state).await?;
+        tx.commit().await
+    }
+
+    async fn pruned_height(&self) -> anyhow::Result<Option<u64>> {
+        Ok(Some(self.payload_pruned_height().await?))
+    }
+
+    async fn get_block_proof(
+        &self,
+        height: u64,
+        anchor: u64,
+    ) -> anyhow::Result<Option<BlocksFrontier>> {
+        ensure!(
+            height < anchor,
+            "block {height} is not in the history of block {anchor}"
+        );
+        let state_height = self.get_last_state_height().await? as u64;
+        ensure!(
+            anchor <= state_height,
+            "state at height {anchor} is not available; merklized state is only stored up to \
+             height {state_height}"
+        );
+
+        let mut tx = self.read().await.context(format!(
+            "opening transaction to prove block {height} at height {anchor}"
+        ))?;
+        let proof = tx
+            .get_path(
+                Snapshot::<SeqTypes, BlockMerkleTree, { BlockMerkleTree::ARITY }>::Index(anchor),
+                height,
+            )
+            .await
+            .context(format!(
+                "fetching proof of block {height} at height {anchor}"
+            ))?;
+        Ok(Some(proof))
+    }
+
+    async fn get_block_by_time(&self, timestamp: u64) -> anyhow::Result<Option<BlockAtTime>> {
+        let mut tx = self.read().await.context(format!(
+            "opening transaction to find block at time {timestamp}"
+        ))?;
+        let row = query_as::<(i64, i64)>(
+            "SELECT height, timestamp FROM header
+              WHERE timestamp <= $1
+              ORDER BY timestamp DESC, height DESC
+              LIMIT 1",
+        )
+        .bind(timestamp as i64)
+        .fetch_optional(tx.as_mut())
+        .await
+        .context(format!("finding block at time {timestamp}"))?;
+        Ok(row.map(|(height, timestamp)| BlockAtTime {
+            height: height as u64,
+            timestamp: timestamp as u64,
+        }))
+    }
+}
+
+#[async_trait]
+impl PayloadPruning for DataSource {
+    async fn payload_pruned_height(&self) -> anyhow::Result<u64> {
+        let mut tx = self.read().await?;
+        let height = query_as::<(i64,)>("SELECT height FROM payload_pruned_height WHERE id = 0")
+            .fetch_optional(tx.as_mut())
+            .await
+            .context("loading payload pruned height")?;
+        Ok(height.map(|(height,)| height as u64).unwrap_or_default())
+    }
+
+    async fn first_block_since(&self, timestamp: u64) -> anyhow::Result<Option<u64>> {
+        let mut tx = self.read().await?;
+        let height = query_as::<(i64,)>(
+            "SELECT height FROM header WHERE timestamp >= $1 ORDER BY height LIMIT 1",
+        )
+        .bind(timestamp as i64)
+        .fetch_optional(tx.as_mut())
+        .await
+        .context(format!("finding first block since timestamp {timestamp}"))?;
+        Ok(height.map(|(height,)| height as u64))
+    }
+
+    async fn storage_usage(&self) -> anyhow::Result<u64> {
+        #[cfg(not(feature = "embedded-db"))]
+        let stmt = "SELECT pg_database_size(current_database())";
+        #[cfg(feature = "embedded-db")]
+        let stmt = "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()";
+
+        let mut tx = self.read().await?;
+        let (usage,) = query_as::<(i64,)>(stmt)
+            .fetch_one(tx.as_mut())
+            .await
+            .context("computing storage usage")?;
+        Ok(usage as u64)
+    }
+
+    async fn prune_payloads(&self, from: u64, to: u64, vid: bool) -> anyhow::Result<()> {
+        let tables: &[&str] = if vid {
+            &["payload", "vid"]
+        } else {
+            &["payload"]
+        };
+        let mut tx = self.write().await?;
+        for table in tables {
+            query(&format!(
+                "DELETE FROM {table} WHERE height >= $1 AND height < $2"
+            ))
+            .bind(from as i64)
+            .bind(to as i64)
+            .execute(tx.as_mut())
+            .await
+            .context(format!("pruning {table}"))?;
+        }
+        tx.upsert(
+            "payload_pruned_height",
+            ["id", "height"],
+            ["id"],
+            [(0i32, to as i64)],
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    async fn namespace_pruning_progress(&self) -> anyhow::Result<NamespacePruningProgress> {
+        let mut tx = self.read().await?;
+        let progress = query_as::<(i64, i64)>(
+            "SELECT resolved, scanned FROM namespace_pruning_progress WHERE id = 0",
+        )
+        .fetch_optional(tx.as_mut())
+        .await
+        .context("loading namespace pruning progress")?;
+        Ok(progress
+            .map(|(resolved, scanned)| NamespacePruningProgress {
+                resolved: resolved as u64,
+                scanned: scanned as u64,
+            })
+            .unwrap_or_default())
+    }
+
+    async fn prune_namespace_payloads(
+        &self,
+        heights: &[u64],
+        vid: bool,
+        progress: NamespacePruningProgress,
+    ) -> anyhow::Result<()> {
+        let tables: &[&str] = if vid {
+            &["payload", "vid"]
+        } else {
+            &["payload"]
+        };
+        let mut tx = self.write().await?;
+        for table in tables {
+            for height in heights {
+                query(&format!("DELETE FROM {table} WHERE height = $1"))
+                    .bind(*height as i64)
+                    .execute(tx.as_mut())
+                    .await
+                    .context(format!("pruning {table} {height}"))?;
+            }
+        }
+        tx.upsert(
+            "namespace_pruning_progress",
+            ["id", "resolved", "scanned"],
+            ["id"],
+            [(0i32, progress.resolved as i64, progress.scanned as i64)],
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    async fn truncate(&self, height: u64) -> anyhow::Result<()> {
+        let mut tx = self.write().await?;
+
+        // Leaves, payloads, VID data and transactions all reference the header table, so they are
+        // deleted along with the headers.
+        query("DELETE FROM header WHERE height >= $1")
+            .bind(height as i64)
+            .execute(tx.as_mut())
+            .await
+            .context("truncating blocks")?;
+
+        // Merklized state and chain config history.
+        for (table, column) in [
+            ("fee_merkle_tree", "created"),
+            ("block_merkle_tree", "created"),
+            ("chain_config_history", "height"),
+        ] {
+            query(&format!("DELETE FROM {table} WHERE {column} >= $1"))
+                .bind(height as i64)
+                .execute(tx.as_mut())
+                .await
+                .context(format!("truncating {table}"))?;
+        }
+        if tx.get_last_state_height().await? as u64 >= height {
+            UpdateStateData::<SeqTypes, _, { BlockMerkleTree::ARITY }>::set_last_state_height(
+                &mut tx,
+                height.saturating_sub(1) as usize,
+            )
+            .await
+            .context("resetting state height")?;
+        }
+
+        // Explorer statistics, and the totals computed from them.
+        for table in ["explorer_block_stats", "explorer_namespace_stats"] {
+            query(&format!("DELETE FROM {table} WHERE height >= $1"))
+                .bind(height as i64)
+                .execute(tx.as_mut())
+                .await
+                .context(format!("truncating {table}"))?;
+        }
+        query("DELETE FROM explorer_namespace_totals")
+            .execute(tx.as_mut())
+            .await
+            .context("clearing namespace totals")?;
+        query(
+            "INSERT INTO explorer_namespace_totals (namespace, num_blocks, bytes, last_height)
+                SELECT namespace, count(*), sum(bytes), max(height)
+                  FROM explorer_namespace_stats
+                 GROUP BY namespace",
+        )
+        .execute(tx.as_mut())
+        .await
+        .context("recomputing namespace totals")?;
+        query(
+            "UPDATE explorer_stats_totals
+                SET height = CASE WHEN height > $1 THEN $1 ELSE height END,
+                    num_transactions =
+                        (SELECT coalesce(sum(num_transactions), 0) FROM explorer_block_stats),
+                    bytes = (SELECT coalesce(sum(size), 0) FROM explorer_block_stats)",
+        )
+        .bind(height as i64)
+        .execute(tx.as_mut())
+        .await
+        .context("recomputing statistics totals")?;
+
+        tx.commit().await
+    }
+}
+
+#[async_trait]
+impl ExplorerStatsStorage for DataSource {
+    async fn stats_height(&self) -> anyhow::Result<u64> {
+        let mut tx = self.read().await?;
+        let (height, _, _) = load_stats_totals(&mut tx).await?;
+        Ok(height as u64)
+    }
+
+    async fn add_block_stats(&self, stats: &BlockStats) -> anyhow::Result<()> {
+        let mut tx = self.write().await?;
+        let (height, num_transactions, bytes) = load_stats_totals(&mut tx).await?;
+        if (stats.height as i64) < height {
+            // These statistics have already been recorded.
+            return Ok(());
+        }
+        ensure!(
+            stats.height as i64 == height,
+            "cannot add statistics for block {}; statistics are only recorded up to height \
+             {height}",
+            stats.height
+        );
+
+        tx.upsert(
+            "explorer_block_stats",
+            ["height", "timestamp", "num_transactions", "size"],
+            ["height"],
+            [(
+                stats.height as i64,
+                stats.timestamp as i64,
+                stats.num_transactions as i64,
+                stats.size as i64,
+            )],
+        )
+        .await?;
+        if !stats.namespaces.is_empty() {
+            tx.upsert(
+                "explorer_namespace_stats",
+                ["height", "namespace", "bytes", "num_transactions"],
+                ["height", "namespace"],
+                stats.namespaces.iter().map(|ns| {
+                    (
+                        stats.height as i64,
+                        u32::from(ns.namespace) as i64,
+                        ns.bytes as i64,
+                        ns.num_transactions as i64,
+                    )
+                }),
+            )
+            .await?;
+        }
+        for ns in &stats.namespaces {
+            query(
+                "INSERT INTO explorer_namespace_totals (namespace, num_blocks, bytes, last_height)
+                      VALUES ($1, 1, $2, $3)
+                 ON CONFLICT (namespace) DO UPDATE
+                         SET num_blocks = explorer_namespace_totals.num_blocks + 1,
+                             bytes = explorer_namespace_totals.bytes + excluded.bytes,
+                             last_height = excluded.last_height",
+            )
+            .bind(u32::from(ns.namespace) as i64)
+            .bind(ns.bytes as i64)
+            .bind(stats.height as i64)
+            .execute(tx.as_mut())
+            .await
+            .context(format!("updating totals for namespace {}", ns.namespace))?;
+        }
+        tx.upsert(
+            "explorer_stats_totals",
+            ["id", "height", "num_transactions", "bytes"],
+            ["id"],
+            [(
+                0i32,
+                height + 1,
+                num_transactions + stats.num_transactions as i64,
+                bytes + stats.size as i64,
+            )],
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    async fn explorer_summary(&self, windows: &[Duration]) -> anyhow::Result<ExplorerSummary> {
+        let mut tx = self.read().await?;
+        let (block_height, num_transactions, bytes) = load_stats_totals(&mut tx).await?;
+        let latest = query_as::<(i64,)>(
+            "SELECT timestamp FROM explorer_block_stats ORDER BY height DESC LIMIT 1",
+        )
+        .fetch_optional(tx.as_mut())
+        .await
+        .context("loading latest block timestamp")?;
+
+        let mut summary = ExplorerSummary {
+            block_height: block_height as u64,
+            num_transactions: num_transactions as u64,
+            bytes: bytes as u64,
+            windows: Vec::with_capacity(windows.len()),
+        };
+        for window in windows {
+            let Some((latest,)) = latest else {
+                summary.windows.push(WindowSummary::empty(*window));
+                continue;
+            };
+            let since = latest.saturating_sub(window.as_secs() as i64);
+            let (
+                num_blocks,
+                num_transactions,
+                bytes,
+                first_height,
+                first_timestamp,
+                last_timestamp,
+            ) = query_as::<(
+                i64,
+                Option<i64>,
+                Option<i64>,
+                Option<i64>,
+                Option<i64>,
+                Option<i64>,
+            )>(
+                "SELECT count(*), CAST(sum(num_transactions) AS BIGINT), CAST(sum(size) AS BIGINT),
+                        min(height), min(timestamp), max(timestamp)
+                   FROM explorer_block_stats
+                  WHERE timestamp >= $1",
+            )
+            .bind(since)
+            .fetch_one(tx.as_mut())
+            .await
+            .context(format!("summarizing blocks since {since}"))?;
+            let Some(first_height) = first_height else {
+                summary.windows.push(WindowSummary::empty(*window));
+                continue;
+            };
+
+            let namespaces = query_as::<(i64, i64)>(
+                "SELECT namespace, CAST(sum(bytes) AS BIGINT)
+                   FROM explorer_namespace_stats
+                  WHERE height >= $1
+                  GROUP BY namespace
+                  ORDER BY namespace",
+            )
+            .bind(first_height)
+            .fetch_all(tx.as_mut())
+            .await
+            .context(format!("summarizing namespaces since block {first_height}"))?
+            .into_iter()
+            .map(|(namespace, bytes)| {
+                NamespaceBytes::new(NamespaceId::from(namespace as u32), bytes as u64)
+            })
+            .collect();
+
+            summary.windows.push(WindowSummary::new(
+                *window,
+                num_blocks as u64,
+                num_transactions.unwrap_or_default() as u64,
+                bytes.unwrap_or_default() as u64,
+                first_timestamp
+                    .zip(last_timestamp)
+                    .map(|(first, last)| (first as u64, last as u64)),
+                namespaces,
+            ));
+        }
+        Ok(summary)
+    }
+
+    async fn namespace_stats(&self) -> anyhow::Result<Vec<NamespaceStats>> {
+        let mut tx = self.read().await?;
+        let stats = query_as::<(i64, i64, i64, i64)>(
+            "SELECT namespace, num_blocks, bytes, last_height
+               FROM explorer_namespace_totals
+              ORDER BY namespace",
+        )
+        .fetch_all(tx.as_mut())
+        .await
+        .context("loading namespace statistics")?;
+        Ok(stats
+            .into_iter()
+            .map(
+                |(namespace, num_blocks, bytes, last_height)| NamespaceStats {
+                    namespace: NamespaceId::from(namespace as u32),
+                    num_blocks: num_blocks as u64,
+                    bytes: bytes as u64,
+                    last_height: last_height as u64,
+                },
+            )
+            .collect())
+    }
+
+    async fn namespace_throughput(
+        &self,
+        namespace: NamespaceId,
+        window: Duration,
+    ) -> anyhow::Result<NamespaceThroughput> {
+        let mut tx = self.read().await?;
+        let latest = query_as::<(i64,)>(
+            "SELECT timestamp FROM explorer_block_stats ORDER BY height DESC LIMIT 1",
+        )
+        .fetch_optional(tx.as_mut())
+        .await
+        .context("loading latest block timestamp")?
+        .map(|(timestamp,)| timestamp as u64)
+        .unwrap_or_default();
+        let (since, bucket) = NamespaceThroughput::buckets(latest, window);
+
+        let totals = query_as::<(i64, i64, Option<i64>, Option<i64>)>(
+            "SELECT (b.timestamp - $1) / $2 AS bucket, count(*),
+                    CAST(sum(n.num_transactions) AS BIGINT), CAST(sum(n.bytes) AS BIGINT)
+               FROM explorer_namespace_stats AS n
+               JOIN explorer_block_stats AS b ON b.height = n.height
+              WHERE n.namespace = $3 AND b.timestamp >= $1
+              GROUP BY bucket
+              ORDER BY bucket",
+        )
+        .bind(since as i64)
+        .bind(bucket as i64)
+        .bind(u32::from(namespace) as i64)
+        .fetch_all(tx.as_mut())
+        .await
+        .context(format!("loading throughput of namespace {namespace}"))?;
+
+        Ok(NamespaceThroughput::new(
+            namespace,
+            window,
+            since,
+            bucket,
+            totals
+                .into_iter()
+                .map(|(bucket, num_blocks, num_transactions, bytes)| {
+                    (
+                        bucket as u64,
+                        num_blocks as u64,
+                        num_transactions.unwrap_or_default() as u64,
+                        bytes.unwrap_or_default() as u64,
+                    )
+                }),
+        ))
+    }
+}
+
+/// Load the running explorer statistics totals: (height, number of transactions, bytes).
+async fn load_stats_totals<Mode: TransactionMode>(
+    tx: &mut Transaction<Mode>,
+) -> anyhow::Result<(i64, i64, i64)> {
+    let totals = query_as::<(i64, i64, i64)>(
+        "SELECT height, num_transactions, bytes FROM explorer_stats_totals WHERE id = 0",
+    )
+    .fetch_optional(tx.as_mut())
+    .await
+    .context("loading explorer statistics totals")?;
+    Ok(totals.unwrap_or_default())
+}
+
+#[async_trait]
+impl ConnectionPool for DataSource {
+    async fn sample_pool(&self) -> anyhow::Result<PoolSample> {
+        let start = Instant::now();
+        #[cfg_attr(feature = "embedded-db", allow(unused_mut, unused_variables))]
+        let mut tx = self.read().await.context("acquiring connection")?;
+        let wait = start.elapsed();
+
+        // The server's view of our connections. This also counts connections opened by any other
+        // client using the same credentials, such as another process sharing the database.
+        #[cfg(not(feature = "embedded-db"))]
+        let (open, in_use) = {
+            let (open, in_use) = query_as::<(i64, i64)>(
+                "SELECT count(*), count(*) FILTER (WHERE state <> 'idle')
+                   FROM pg_stat_activity
+                  WHERE datname = current_database()
+                    AND usename = current_user
+                    AND pid <> pg_backend_pid()",
+            )
+            .fetch_one(tx.as_mut())
+            .await
+            .context("counting connections")?;
+            (Some(open as usize), Some(in_use as usize))
+        };
+        // SQLite has no server to ask.
+        #[cfg(feature = "embedded-db")]
+        let (open, in_use) = (None, None);
+
+        Ok(PoolSample { wait, open, in_use })
+    }
 }
 
 impl CatchupStorage for SqlStorage {
@@ -108,6 +665,28 @@ impl CatchupStorage for SqlStorage {
         }
     }
 
+    async fn get_historical_accounts(
+        &self,
+        height: u64,
+        accounts: &[FeeAccount],
+    ) -> anyhow::Result<(FeeMerkleTree, Leaf)> {
+        let mut tx = self.read().await.context(format!(
+            "opening transaction to fetch historical accounts {accounts:?}; height {height}"
+        ))?;
+
+        // Every version of the fee state is kept in merklized state storage, so we can load the
+        // accounts directly from the snapshot at `height`, as long as the header for that block has
+        // not been pruned.
+        let block_height = NodeStorage::<SeqTypes>::block_height(&mut tx)
+            .await
+            .context("getting block height")? as u64;
+        ensure!(
+            height < block_height,
+            "fee state for height {height} is not available; block height is {block_height}"
+        );
+        load_accounts(&mut tx, height, accounts).await
+    }
+
     async fn get_frontier(
         &self,
         instance: &NodeState,
@@ -153,6 +732,14 @@ impl CatchupStorage for SqlStorage {
         ))?;
         load_chain_config(&mut tx, commitment).await
     }
+
+    async fn get_chain_config_history(&self) -> anyhow::Result<Vec<ChainConfigActivation>> {
+        let mut tx = self
+            .read()
+            .await
+            .context("opening transaction to fetch chain config history")?;
+        load_chain_config_history(&mut tx).await
+    }
 }
 
 impl CatchupStorage for DataSource {
@@ -177,24 +764,49 @@ impl CatchupStorage for DataSource {
         self.as_ref().get_frontier(instance, height, view).await
     }
 
+    async fn get_historical_accounts(
+        &self,
+        height: u64,
+        accounts: &[FeeAccount],
+    ) -> anyhow::Result<(FeeMerkleTree, Leaf)> {
+        self.as_ref()
+            .get_historical_accounts(height, accounts)
+            .await
+    }
+
     async fn get_chain_config(
         &self,
         commitment: Commitment<ChainConfig>,
     ) -> anyhow::Result<ChainConfig> {
         self.as_ref().get_chain_config(commitment).await
     }
+
+    async fn get_chain_config_history(&self) -> anyhow::Result<Vec<ChainConfigActivation>> {
+        self.as_ref().get_chain_config_history().await
+    }
 }
 
 #[async_trait]
 impl ChainConfigPersistence for Transaction<Write> {
-    async fn insert_chain_config(&mut self, chain_config: ChainConfig) -> anyhow::Result<()> {
-        let commitment = chain_config.commitment();
+    async fn insert_chain_config(
+        &mut self,
+        height: u64,
+        chain_config: ChainConfig,
+    ) -> anyhow::Result<()> {
+        let commitment = chain_config.commitment().to_string();
         let data = bincode::serialize(&chain_config)?;
         self.upsert(
             "chain_config",
             ["commitment", "data"],
             ["commitment"],
-            [(commitment.to_string(), data)],
+            [(commitment.clone(), data)],
+        )
+        .await?;
+        self.upsert(
+            "chain_config_history",
+            ["height", "commitment"],
+            ["height"],
+            [(height as i64, commitment)],
         )
         .await
         .map_err(Into::into)
@@ -257,6 +869,84 @@ async fn load_accounts<Mode: TransactionMode>(
     Ok((snapshot, leaf.leaf().clone()))
 }
 
+/// Load the full fee state and blocks frontier as of block `height`.
+async fn load_state_snapshot<Mode: TransactionMode>(
+    tx: &mut Transaction<Mode>,
+    height: u64,
+) -> anyhow::Result<ValidatedState> {
+    // Find every account which has ever been inserted in the fee tree, up to this height.
+    #[cfg(not(feature = "embedded-db"))]
+    let stmt = "SELECT DISTINCT index FROM fee_merkle_tree
+        WHERE index IS NOT NULL AND created <= $1";
+    #[cfg(feature = "embedded-db")]
+    let stmt = "SELECT DISTINCT idx FROM fee_merkle_tree
+        WHERE idx IS NOT NULL AND created <= $1";
+    let accounts = query_as::<(Json<FeeAccount>,)>(stmt)
+        .bind(height as i64)
+        .fetch_all(tx.as_mut())
+        .await
+        .context(format!("enumerating fee accounts at height {height}"))?
+        .into_iter()
+        .map(|(Json(account),)| account)
+        .collect::<Vec<_>>();
+    tracing::info!(height, accounts = accounts.len(), "loading state snapshot");
+
+    let (fee_merkle_tree, leaf) = load_accounts(tx, height, &accounts).await?;
+    let header = leaf.block_header();
+    let mut state = ValidatedState::from_header(header);
+    state.fee_merkle_tree = fee_merkle_tree;
+
+    // The block tree is empty at genesis, so there is no frontier to load.
+    if height > 0 {
+        let frontier = load_frontier(tx, height).await?;
+        remember_frontier(&mut state.block_merkle_tree, frontier)?;
+    }
+
+    if state.chain_config.resolve().is_none() {
+        let chain_config = load_chain_config(tx, header.chain_config().commit()).await?;
+        state.chain_config = chain_config.into();
+    }
+
+    Ok(state)
+}
+
+fn remember_frontier(
+    block_merkle_tree: &mut BlockMerkleTree,
+    frontier: BlocksFrontier,
+) -> anyhow::Result<()> {
+    match frontier.proof.first().context("empty proof for frontier")? {
+        MerkleNode::Leaf { pos, elem, .. } => block_merkle_tree
+            .remember(*pos, *elem, &frontier)
+            .context("failed to remember frontier"),
+        _ => bail!("invalid frontier proof"),
+    }
+}
+
+async fn load_chain_config_history<Mode: TransactionMode>(
+    tx: &mut Transaction<Mode>,
+) -> anyhow::Result<Vec<ChainConfigActivation>> {
+    let rows = query_as::<(i64, Vec<u8>)>(
+        "SELECT h.height, c.data FROM chain_config_history AS h
+            JOIN chain_config AS c ON h.commitment = c.commitment
+            ORDER BY h.height",
+    )
+    .fetch_all(tx.as_mut())
+    .await
+    .context("loading chain config history")?;
+
+    rows.into_iter()
+        .map(|(height, data)| {
+            let chain_config: ChainConfig =
+                bincode::deserialize(&data).context("failed to deserialize")?;
+            Ok(ChainConfigActivation {
+                height: height as u64,
+                commitment: chain_config.commit(),
+                chain_config,
+            })
+        })
+        .collect()
+}
+
 async fn load_chain_config<Mode: TransactionMode>(
     tx: &mut Transaction<Mode>,
     commitment: Commitment<ChainConfig>,
@@ -336,17 +1026,7 @@ async fn reconstruct_state<Mode: TransactionMode>(
     let frontier = load_frontier(tx, from_height)
         .await
         .context("unable to reconstruct state because frontier is not available at origin")?;
-    match frontier
-        .proof
-        .first()
-        .context("empty proof for frontier at origin")?
-    {
-        MerkleNode::Leaf { pos, elem, .. } => state
-            .block_merkle_tree
-            .remember(*pos, *elem, frontier)
-            .context("failed to remember frontier")?,
-        _ => bail!("invalid frontier proof"),
-    }
+    remember_frontier(&mut state.block_merkle_tree, frontier)?;
 
     // Apply subsequent headers to compute the later state.
     for proposal in &leaves {
@@ -454,6 +1134,7 @@ mod impl_testable_data_source {
     use super::*;
     use crate::api::{self, data_source::testing::TestableSequencerDataSource};
 
+    #[cfg(not(feature = "embedded-db"))]
     fn tmp_options(db: &TmpDb) -> Options {
         Options {
             port: Some(db.port()),
@@ -464,6 +1145,14 @@ mod impl_testable_data_source {
         }
     }
 
+    #[cfg(feature = "embedded-db")]
+    fn tmp_options(db: &TmpDb) -> Options {
+        Options {
+            path: Some(db.path()),
+            ..Default::default()
+        }
+    }
+
     #[async_trait]
     impl TestableSequencerDataSource for DataSource {
         type Storage = TmpDb;
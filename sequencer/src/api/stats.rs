@@ -0,0 +1,444 @@
+//! Aggregate chain statistics for the explorer.
+//!
+//! Dashboards want headline numbers, like the total number of transactions or the average block
+//! time over the last day, which would be expensive to compute from scratch on every request. The
+//! sql data source instead maintains these statistics incrementally: a background task started by
+//! [`update_explorer_stats_loop`] follows the stream of decided blocks and records a small summary
+//! of each one, along with running totals over the whole chain. The `explorer-stats/summary`
+//! endpoint then only has to aggregate the per-block summaries within each rolling window. The
+//! summaries include the bytes and transactions in each namespace, so the throughput history of a
+//! single namespace, served by `namespaces/namespace/:namespace/stats`, is just as cheap.
+//!
+//! Windows are measured back from the timestamp of the latest block, not the current wall clock
+//! time, so that a node which is catching up reports consistent statistics for the blocks it has.
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use clap::Parser;
+use espresso_types::{parse_duration, NamespaceId};
+use futures::StreamExt;
+use hotshot_query_service::{
+    availability::{AvailabilityDataSource, BlockQueryData},
+    data_source::ExtensibleDataSource,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use super::namespaces::{NamespaceMetrics, NamespaceStats};
+use crate::SeqTypes;
+
+/// Options for the explorer statistics.
+#[derive(Parser, Clone, Debug)]
+pub struct ExplorerStatsOptions {
+    /// Rolling windows over which to report explorer statistics.
+    ///
+    /// Each window is measured back from the timestamp of the latest block.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_EXPLORER_STATS_WINDOWS",
+        value_parser = parse_duration,
+        value_delimiter = ',',
+        default_value = "1h,24h,7d"
+    )]
+    pub explorer_stats_windows: Vec<Duration>,
+}
+
+impl Default for ExplorerStatsOptions {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+/// Summary statistics for the chain, as returned by `explorer-stats/summary`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExplorerSummary {
+    /// Number of blocks included in the statistics.
+    pub block_height: u64,
+    /// Total number of transactions in all blocks.
+    pub num_transactions: u64,
+    /// Total payload bytes in all blocks.
+    pub bytes: u64,
+    /// Statistics for recent blocks, one for each configured window.
+    pub windows: Vec<WindowSummary>,
+}
+
+/// Statistics for blocks within a rolling window.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WindowSummary {
+    /// Length of the window, in seconds.
+    pub window: u64,
+    /// Number of blocks in the window.
+    pub num_blocks: u64,
+    /// Number of transactions in the window.
+    pub num_transactions: u64,
+    /// Payload bytes in the window.
+    pub bytes: u64,
+    /// Average time between consecutive blocks in the window, in seconds.
+    ///
+    /// This is [`None`] if the window contains fewer than two blocks.
+    pub average_block_time: Option<f64>,
+    /// Payload bytes sequenced in each namespace within the window.
+    pub namespaces: Vec<NamespaceBytes>,
+}
+
+impl WindowSummary {
+    /// Summarize a window whose blocks have timestamps in `time_range`.
+    pub(crate) fn new(
+        window: Duration,
+        num_blocks: u64,
+        num_transactions: u64,
+        bytes: u64,
+        time_range: Option<(u64, u64)>,
+        namespaces: Vec<NamespaceBytes>,
+    ) -> Self {
+        let average_block_time = match time_range {
+            Some((first, last)) if num_blocks > 1 => {
+                Some(last.saturating_sub(first) as f64 / (num_blocks - 1) as f64)
+            }
+            _ => None,
+        };
+        Self {
+            window: window.as_secs(),
+            num_blocks,
+            num_transactions,
+            bytes,
+            average_block_time,
+            namespaces,
+        }
+    }
+
+    /// Summarize a window with no blocks.
+    pub(crate) fn empty(window: Duration) -> Self {
+        Self::new(window, 0, 0, 0, None, vec![])
+    }
+}
+
+/// The number of payload bytes sequenced in a namespace.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespaceBytes {
+    pub namespace: NamespaceId,
+    /// The registered name of the namespace, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub bytes: u64,
+}
+
+impl NamespaceBytes {
+    pub fn new(namespace: NamespaceId, bytes: u64) -> Self {
+        Self {
+            namespace,
+            name: None,
+            bytes,
+        }
+    }
+}
+
+/// The data sequenced in a namespace within a single block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockNamespaceStats {
+    pub namespace: NamespaceId,
+    pub num_transactions: u64,
+    pub bytes: u64,
+}
+
+/// The statistics recorded for a single block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockStats {
+    pub height: u64,
+    pub timestamp: u64,
+    pub num_transactions: u64,
+    pub size: u64,
+    pub namespaces: Vec<BlockNamespaceStats>,
+}
+
+impl BlockStats {
+    pub fn new(block: &BlockQueryData<SeqTypes>) -> Self {
+        let payload = block.payload();
+        Self {
+            height: block.height(),
+            timestamp: block.header().timestamp(),
+            num_transactions: block.num_transactions(),
+            size: block.size(),
+            namespaces: namespace_totals(payload.ns_byte_lens(), payload.ns_num_txs()),
+        }
+    }
+}
+
+/// Total the bytes and transactions in each namespace, merging duplicate namespace table entries.
+fn namespace_totals(
+    byte_lens: impl IntoIterator<Item = (NamespaceId, usize)>,
+    num_txs: impl IntoIterator<Item = (NamespaceId, usize)>,
+) -> Vec<BlockNamespaceStats> {
+    let mut totals = BTreeMap::<NamespaceId, (u64, u64)>::new();
+    for (namespace, bytes) in byte_lens {
+        totals.entry(namespace).or_default().1 += bytes as u64;
+    }
+    for (namespace, num_transactions) in num_txs {
+        totals.entry(namespace).or_default().0 += num_transactions as u64;
+    }
+    totals
+        .into_iter()
+        .map(
+            |(namespace, (num_transactions, bytes))| BlockNamespaceStats {
+                namespace,
+                num_transactions,
+                bytes,
+            },
+        )
+        .collect()
+}
+
+/// The number of buckets in the throughput history returned for a namespace.
+pub const THROUGHPUT_BUCKETS: u64 = 24;
+
+/// The throughput of a namespace over time, as returned by
+/// `namespaces/namespace/:namespace/stats`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NamespaceThroughput {
+    pub namespace: NamespaceId,
+    /// The registered name of the namespace, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Length of the window, in seconds.
+    pub window: u64,
+    /// Length of each bucket, in seconds.
+    pub bucket: u64,
+    /// The data sequenced in the namespace within each bucket, oldest first.
+    ///
+    /// Buckets with no data are included, so that the history is contiguous.
+    pub buckets: Vec<ThroughputBucket>,
+}
+
+/// The data sequenced in a namespace within a bucket of time.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThroughputBucket {
+    /// The timestamp at which the bucket starts.
+    pub start: u64,
+    /// Number of blocks with data in the namespace.
+    pub num_blocks: u64,
+    /// Number of transactions in the namespace.
+    pub num_transactions: u64,
+    /// Payload bytes in the namespace.
+    pub bytes: u64,
+}
+
+impl NamespaceThroughput {
+    /// Split `window` into [`THROUGHPUT_BUCKETS`] buckets ending at `latest`.
+    ///
+    /// Returns the timestamp at which the window starts and the length of each bucket, in seconds.
+    pub(crate) fn buckets(latest: u64, window: Duration) -> (u64, u64) {
+        let bucket = window.as_secs().div_ceil(THROUGHPUT_BUCKETS).max(1);
+        (latest.saturating_sub(bucket * THROUGHPUT_BUCKETS), bucket)
+    }
+
+    /// Collect throughput history from per-bucket totals.
+    ///
+    /// Each entry of `totals` is a bucket index, counted from `since`, with the number of blocks,
+    /// transactions and bytes in that bucket. Entries past the last bucket, from blocks at exactly
+    /// the end of the window, are counted in the last bucket.
+    pub(crate) fn new(
+        namespace: NamespaceId,
+        window: Duration,
+        since: u64,
+        bucket: u64,
+        totals: impl IntoIterator<Item = (u64, u64, u64, u64)>,
+    ) -> Self {
+        let mut buckets = (0..THROUGHPUT_BUCKETS)
+            .map(|i| ThroughputBucket {
+                start: since + i * bucket,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        for (i, num_blocks, num_transactions, bytes) in totals {
+            let bucket = &mut buckets[i.min(THROUGHPUT_BUCKETS - 1) as usize];
+            bucket.num_blocks += num_blocks;
+            bucket.num_transactions += num_transactions;
+            bucket.bytes += bytes;
+        }
+        Self {
+            namespace,
+            name: None,
+            window: window.as_secs(),
+            bucket,
+            buckets,
+        }
+    }
+}
+
+/// Storage which maintains explorer statistics.
+#[async_trait]
+pub trait ExplorerStatsStorage: Send + Sync {
+    /// The number of blocks whose statistics have been recorded.
+    async fn stats_height(&self) -> anyhow::Result<u64>;
+
+    /// Record statistics for the block at the current stats height.
+    ///
+    /// Statistics for blocks which have already been recorded are ignored, so this is safe to
+    /// retry.
+    async fn add_block_stats(&self, stats: &BlockStats) -> anyhow::Result<()>;
+
+    /// Summarize the recorded statistics over the whole chain and over each of `windows`.
+    async fn explorer_summary(&self, windows: &[Duration]) -> anyhow::Result<ExplorerSummary>;
+
+    /// Statistics for each namespace with data in any recorded block.
+    async fn namespace_stats(&self) -> anyhow::Result<Vec<NamespaceStats>>;
+
+    /// The data sequenced in `namespace` over `window`, split into time buckets.
+    ///
+    /// Like the summary windows, `window` is measured back from the timestamp of the latest block.
+    async fn namespace_throughput(
+        &self,
+        namespace: NamespaceId,
+        window: Duration,
+    ) -> anyhow::Result<NamespaceThroughput>;
+}
+
+#[async_trait]
+impl<T, S> ExplorerStatsStorage for ExtensibleDataSource<T, S>
+where
+    T: ExplorerStatsStorage,
+    S: Send + Sync,
+{
+    async fn stats_height(&self) -> anyhow::Result<u64> {
+        self.inner().stats_height().await
+    }
+
+    async fn add_block_stats(&self, stats: &BlockStats) -> anyhow::Result<()> {
+        self.inner().add_block_stats(stats).await
+    }
+
+    async fn explorer_summary(&self, windows: &[Duration]) -> anyhow::Result<ExplorerSummary> {
+        self.inner().explorer_summary(windows).await
+    }
+
+    async fn namespace_stats(&self) -> anyhow::Result<Vec<NamespaceStats>> {
+        self.inner().namespace_stats().await
+    }
+
+    async fn namespace_throughput(
+        &self,
+        namespace: NamespaceId,
+        window: Duration,
+    ) -> anyhow::Result<NamespaceThroughput> {
+        self.inner().namespace_throughput(namespace, window).await
+    }
+}
+
+/// Record statistics for each new block as it is added to `storage`.
+///
+/// The data in each block is also counted in the metrics of its registered namespaces.
+#[tracing::instrument(skip_all)]
+pub(crate) async fn update_explorer_stats_loop<D>(
+    storage: Arc<D>,
+    metrics: NamespaceMetrics,
+) -> anyhow::Result<()>
+where
+    D: ExplorerStatsStorage + AvailabilityDataSource<SeqTypes>,
+{
+    let height = storage
+        .stats_height()
+        .await
+        .context("loading explorer stats height")?;
+    tracing::info!(height, "updating explorer statistics");
+
+    let mut blocks = storage.subscribe_blocks(height as usize).await;
+    while let Some(block) = blocks.next().await {
+        let stats = BlockStats::new(&block);
+        while let Err(err) = storage.add_block_stats(&stats).await {
+            tracing::error!(
+                height = stats.height,
+                "failed to update explorer statistics: {err:#}"
+            );
+            // If we fail, delay for a second and retry.
+            sleep(Duration::from_secs(1)).await;
+        }
+        metrics.record(&stats);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_namespace_totals() {
+        let ns = |id: u32| NamespaceId::from(id);
+        let stats = |id, num_transactions, bytes| BlockNamespaceStats {
+            namespace: ns(id),
+            num_transactions,
+            bytes,
+        };
+        assert_eq!(
+            namespace_totals(
+                [(ns(2), 10), (ns(1), 5), (ns(2), 3)],
+                [(ns(2), 2), (ns(1), 1), (ns(2), 1)]
+            ),
+            vec![stats(1, 1, 5), stats(2, 3, 13)]
+        );
+        assert_eq!(namespace_totals([], []), vec![]);
+    }
+
+    #[test]
+    fn test_namespace_throughput() {
+        let window = Duration::from_secs(86400);
+        let (since, bucket) = NamespaceThroughput::buckets(100_000, window);
+        assert_eq!(bucket, 3600);
+        assert_eq!(since, 100_000 - 86400);
+
+        // Empty buckets are filled in, and the block at the very end of the window is counted in
+        // the last bucket.
+        let throughput = NamespaceThroughput::new(
+            NamespaceId::from(1u32),
+            window,
+            since,
+            bucket,
+            [(0, 1, 2, 10), (5, 2, 4, 20), (24, 1, 1, 5)],
+        );
+        assert_eq!(throughput.window, 86400);
+        assert_eq!(throughput.buckets.len(), THROUGHPUT_BUCKETS as usize);
+        assert_eq!(throughput.buckets[0].num_transactions, 2);
+        assert_eq!(
+            throughput.buckets[1],
+            ThroughputBucket {
+                start: since + 3600,
+                ..Default::default()
+            }
+        );
+        assert_eq!(throughput.buckets[5].bytes, 20);
+        assert_eq!(throughput.buckets[23].num_blocks, 1);
+        assert_eq!(throughput.buckets[23].start, since + 23 * 3600);
+
+        // Short windows still have buckets at least a second long.
+        assert_eq!(
+            NamespaceThroughput::buckets(10, Duration::from_secs(5)),
+            (0, 1)
+        );
+    }
+
+    #[test]
+    fn test_average_block_time() {
+        let window = Duration::from_secs(3600);
+        let summary = WindowSummary::new(window, 5, 10, 100, Some((1000, 1008)), vec![]);
+        assert_eq!(summary.window, 3600);
+        assert_eq!(summary.average_block_time, Some(2.0));
+
+        // A single block has no block time.
+        let summary = WindowSummary::new(window, 1, 10, 100, Some((1000, 1000)), vec![]);
+        assert_eq!(summary.average_block_time, None);
+
+        assert_eq!(WindowSummary::empty(window).average_block_time, None);
+        assert_eq!(WindowSummary::empty(window).num_blocks, 0);
+    }
+
+    #[test]
+    fn test_default_windows() {
+        assert_eq!(
+            ExplorerStatsOptions::default().explorer_stats_windows,
+            [3600, 86400, 7 * 86400].map(Duration::from_secs)
+        );
+    }
+}
@@ -0,0 +1,468 @@
+//! Streaming of decided data to a NATS server.
+//!
+//! Data pipelines which want every decided block can subscribe to a message broker instead of
+//! polling the availability API. The [`StreamSink`] is an [`EventSink`] which publishes, for each
+//! decided block:
+//! * the [`Header`], on the subject `<prefix>.headers`
+//! * a [`NamespacePointer`] for each namespace in the block, on `<prefix>.namespaces.<namespace>`
+//! * a [`StreamTransaction`] for each transaction in the block, on
+//!   `<prefix>.transactions.<namespace>`
+//!
+//! Messages are encoded as JSON or bincode. Consumers interested in a single rollup can subscribe
+//! to the subjects for its namespace only. A namespace pointer locates the namespace within the
+//! block payload, so consumers which need the full namespace payload and its proof can fetch it
+//! from `availability/block/<height>/namespace/<namespace>`.
+//!
+//! The sink speaks the core NATS protocol over plain TCP. Kafka and other brokers can be fed using
+//! one of the NATS bridges. Messages for a block are published before the next block, and a block
+//! is only considered published once the server has acknowledged it. A block which fails to
+//! publish is published again in full, so delivery is at least once: consumers should deduplicate
+//! by height and transaction index.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{bail, ensure, Context};
+use async_trait::async_trait;
+use clap::ValueEnum;
+use committable::{Commitment, Committable};
+use derivative::Derivative;
+use espresso_types::{Header, Leaf, NamespaceId, SeqTypes, Transaction};
+use hotshot::types::{Event, EventType};
+use hotshot_query_service::{data_source::UpdateDataSource, VidCommitment};
+use hotshot_types::{event::LeafInfo, traits::BlockPayload};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+    sync::Mutex,
+    time::timeout,
+};
+use url::Url;
+
+use super::{
+    fanout::{EventSink, EventSinkConfig},
+    options::Stream,
+};
+
+/// The port NATS servers listen on by default.
+const DEFAULT_NATS_PORT: u16 = 4222;
+
+/// How messages published to the stream are encoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum StreamFormat {
+    #[default]
+    Json,
+    Bincode,
+}
+
+impl StreamFormat {
+    fn encode<T: Serialize>(&self, msg: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(match self {
+            Self::Json => serde_json::to_vec(msg)?,
+            Self::Bincode => bincode::serialize(msg)?,
+        })
+    }
+}
+
+/// The location of a namespace within a decided block.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespacePointer {
+    pub height: u64,
+    pub block_hash: Commitment<Header>,
+    pub payload_commitment: VidCommitment,
+    pub namespace: NamespaceId,
+    /// Offset of the namespace payload, in bytes from the start of the block payload.
+    pub offset: u64,
+    /// Size of the namespace payload in bytes.
+    pub size: u64,
+    pub num_transactions: u64,
+}
+
+/// A decided transaction.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamTransaction {
+    pub height: u64,
+    pub block_hash: Commitment<Header>,
+    /// Position of the transaction in the block.
+    pub index: u64,
+    pub hash: Commitment<Transaction>,
+    pub transaction: Transaction,
+}
+
+/// Publishes decided blocks to a NATS server.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct StreamSink {
+    // Hide from debug output since the URL may contain credentials.
+    #[derivative(Debug = "ignore")]
+    url: Url,
+    prefix: String,
+    format: StreamFormat,
+    timeout: Duration,
+    #[derivative(Debug = "ignore")]
+    conn: Mutex<Option<Connection>>,
+}
+
+impl StreamSink {
+    pub fn new(opt: &Stream) -> Self {
+        Self {
+            url: opt.nats_url.clone(),
+            prefix: opt.subject_prefix.clone(),
+            format: opt.format,
+            timeout: opt.timeout,
+            conn: Default::default(),
+        }
+    }
+
+    /// An [`EventSink`] publishing to the stream configured by `opt`.
+    pub fn event_sink(opt: &Stream) -> EventSink {
+        EventSink::new("stream", Arc::new(Self::new(opt))).with_config(EventSinkConfig {
+            queue_capacity: opt.queue_capacity,
+            max_attempts: opt.max_attempts,
+            retry_delay: opt.retry_delay,
+        })
+    }
+
+    /// The messages to publish for a decided leaf, with their subjects.
+    fn messages(&self, leaf: &Leaf) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        let header = leaf.block_header();
+        let height = header.height();
+        let block_hash = header.commit();
+        let mut messages = vec![(
+            format!("{}.headers", self.prefix),
+            self.format.encode(header)?,
+        )];
+
+        // Without the payload we can only publish the header. Consensus always provides payloads
+        // for decided leaves, except those recovered from storage which predate pruning.
+        let Some(payload) = leaf.block_payload() else {
+            tracing::warn!(height, "decided leaf is missing payload");
+            return Ok(messages);
+        };
+
+        let mut offset = 0;
+        for ((namespace, size), (_, num_transactions)) in
+            payload.ns_byte_lens().zip(payload.ns_num_txs())
+        {
+            let pointer = NamespacePointer {
+                height,
+                block_hash,
+                payload_commitment: header.payload_commitment(),
+                namespace,
+                offset: offset as u64,
+                size: size as u64,
+                num_transactions: num_transactions as u64,
+            };
+            messages.push((
+                format!("{}.namespaces.{namespace}", self.prefix),
+                self.format.encode(&pointer)?,
+            ));
+            offset += size;
+        }
+
+        for (index, transaction) in payload.transactions(payload.ns_table()).enumerate() {
+            let subject = format!("{}.transactions.{}", self.prefix, transaction.namespace());
+            let msg = StreamTransaction {
+                height,
+                block_hash,
+                index: index as u64,
+                hash: transaction.commit(),
+                transaction,
+            };
+            messages.push((subject, self.format.encode(&msg)?));
+        }
+
+        Ok(messages)
+    }
+
+    async fn publish(&self, messages: &[(String, Vec<u8>)]) -> anyhow::Result<()> {
+        let mut conn = self.conn.lock().await;
+        let res = async {
+            if conn.is_none() {
+                *conn = Some(Connection::connect(&self.url).await?);
+            }
+            let conn = conn.as_mut().unwrap();
+            for (subject, payload) in messages {
+                conn.publish(subject, payload).await?;
+            }
+            conn.flush().await
+        };
+        let res = timeout(self.timeout, res).await;
+        match res {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(err)) => {
+                // The connection may be in an inconsistent state, so start over with a new one.
+                *conn = None;
+                Err(err)
+            }
+            Err(_) => {
+                *conn = None;
+                bail!("timed out publishing to NATS server");
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl UpdateDataSource<SeqTypes> for StreamSink {
+    async fn update(&self, event: &Event<SeqTypes>) -> Result<(), u64> {
+        let EventType::Decide { leaf_chain, .. } = &event.event else {
+            return Ok(());
+        };
+        for LeafInfo { leaf, .. } in leaf_chain.iter().rev() {
+            let height = leaf.height();
+            let messages = match self.messages(leaf) {
+                Ok(messages) => messages,
+                Err(err) => {
+                    tracing::error!(height, "failed to encode stream messages: {err:#}");
+                    return Err(height);
+                }
+            };
+            if let Err(err) = self.publish(&messages).await {
+                tracing::warn!(height, "failed to publish decided block: {err:#}");
+                return Err(height);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A connection to a NATS server.
+struct Connection {
+    stream: BufStream<TcpStream>,
+}
+
+impl Connection {
+    async fn connect(url: &Url) -> anyhow::Result<Self> {
+        ensure!(
+            url.scheme() == "nats",
+            "unsupported NATS URL scheme {}",
+            url.scheme()
+        );
+        let host = url.host_str().context("NATS URL is missing host")?;
+        let port = url.port().unwrap_or(DEFAULT_NATS_PORT);
+        let stream = TcpStream::connect((host, port))
+            .await
+            .with_context(|| format!("connecting to NATS server {host}:{port}"))?;
+        let mut conn = Self {
+            stream: BufStream::new(stream),
+        };
+
+        // The server greets each new client with information about itself.
+        let info = conn.read_line().await?;
+        ensure!(
+            info.starts_with("INFO "),
+            "unexpected greeting from NATS server: {info}"
+        );
+
+        let mut options = json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": "espresso-sequencer",
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+        });
+        if !url.username().is_empty() {
+            options["user"] = url.username().into();
+            options["pass"] = url.password().unwrap_or_default().into();
+        }
+        conn.write(format!("CONNECT {options}\r\n").as_bytes())
+            .await?;
+
+        // Make sure the server accepted the connection before using it.
+        conn.flush().await?;
+        Ok(conn)
+    }
+
+    async fn publish(&mut self, subject: &str, payload: &[u8]) -> anyhow::Result<()> {
+        self.write(format!("PUB {subject} {}\r\n", payload.len()).as_bytes())
+            .await?;
+        self.write(payload).await?;
+        self.write(b"\r\n").await
+    }
+
+    /// Wait until the server has processed everything sent on this connection.
+    async fn flush(&mut self) -> anyhow::Result<()> {
+        self.write(b"PING\r\n").await?;
+        self.stream.flush().await?;
+        loop {
+            let line = self.read_line().await?;
+            match line.as_str() {
+                "PONG" => return Ok(()),
+                "PING" => {
+                    self.write(b"PONG\r\n").await?;
+                    self.stream.flush().await?;
+                }
+                "+OK" => {}
+                line if line.starts_with("INFO ") => {}
+                line => bail!("NATS server error: {line}"),
+            }
+        }
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        Ok(self.stream.write_all(bytes).await?)
+    }
+
+    async fn read_line(&mut self) -> anyhow::Result<String> {
+        let mut line = String::new();
+        ensure!(
+            self.stream.read_line(&mut line).await? > 0,
+            "NATS server closed the connection"
+        );
+        Ok(line.trim_end().to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+    use espresso_types::{MockSequencerVersions, NodeState, Payload};
+    use hotshot_types::{
+        data::ViewNumber, simple_certificate::QuorumCertificate,
+        traits::node_implementation::ConsensusTime,
+    };
+    use sequencer_utils::test_utils::setup_test;
+    use tokio::{
+        io::{AsyncReadExt, BufReader},
+        net::TcpListener,
+        spawn,
+        sync::mpsc,
+    };
+
+    use super::*;
+
+    fn options(url: &Url) -> Stream {
+        Stream::parse_from(["stream", "--stream-nats-url", url.as_str()])
+    }
+
+    /// Run a minimal NATS server which forwards each published message to `published`.
+    async fn mock_server(published: mpsc::UnboundedSender<(String, Vec<u8>)>) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("nats://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut read = BufReader::new(read);
+            write.write_all(b"INFO {}\r\n").await.unwrap();
+            loop {
+                let mut line = String::new();
+                if read.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                let mut words = line.split_whitespace();
+                match words.next().unwrap() {
+                    "CONNECT" => {}
+                    "PING" => write.write_all(b"PONG\r\n").await.unwrap(),
+                    "PUB" => {
+                        let subject = words.next().unwrap().to_string();
+                        let len: usize = words.next().unwrap().parse().unwrap();
+                        let mut payload = vec![0; len + 2];
+                        read.read_exact(&mut payload).await.unwrap();
+                        payload.truncate(len);
+                        published.send((subject, payload)).unwrap();
+                    }
+                    cmd => panic!("unexpected command {cmd}"),
+                }
+            }
+        });
+        url
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_stream_decided_block() {
+        setup_test();
+
+        let (send, mut published) = mpsc::unbounded_channel();
+        let sink = StreamSink::new(&options(&mock_server(send).await));
+
+        let txs = vec![
+            Transaction::new(NamespaceId::from(1u32), vec![1, 2, 3]),
+            Transaction::new(NamespaceId::from(2u32), vec![4, 5]),
+        ];
+        let (payload, _) =
+            Payload::from_transactions(txs.clone(), &Default::default(), &NodeState::mock())
+                .await
+                .unwrap();
+        let mut leaf = Leaf::genesis(&Default::default(), &NodeState::mock()).await;
+        leaf.fill_block_payload_unchecked(payload);
+        let header = leaf.block_header().clone();
+
+        let event = Event {
+            view_number: ViewNumber::genesis(),
+            event: EventType::Decide {
+                leaf_chain: Arc::new(vec![LeafInfo {
+                    leaf,
+                    vid_share: None,
+                    state: Default::default(),
+                    delta: None,
+                }]),
+                qc: Arc::new(
+                    QuorumCertificate::genesis::<MockSequencerVersions>(
+                        &Default::default(),
+                        &NodeState::mock(),
+                    )
+                    .await,
+                ),
+                block_size: None,
+            },
+        };
+        sink.update(&event).await.unwrap();
+
+        let mut messages = vec![];
+        while let Ok(msg) = published.try_recv() {
+            messages.push(msg);
+        }
+        let subjects = messages
+            .iter()
+            .map(|(subject, _)| subject.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            subjects,
+            [
+                "espresso.headers",
+                "espresso.namespaces.1",
+                "espresso.namespaces.2",
+                "espresso.transactions.1",
+                "espresso.transactions.2",
+            ]
+        );
+
+        let decoded: Header = serde_json::from_slice(&messages[0].1).unwrap();
+        assert_eq!(decoded, header);
+
+        let pointer: NamespacePointer = serde_json::from_slice(&messages[2].1).unwrap();
+        assert_eq!(pointer.namespace, NamespaceId::from(2u32));
+        assert_eq!(pointer.num_transactions, 1);
+        let first: NamespacePointer = serde_json::from_slice(&messages[1].1).unwrap();
+        assert_eq!(pointer.offset, first.offset + first.size);
+
+        for (i, (_, msg)) in messages[3..].iter().enumerate() {
+            let tx: StreamTransaction = serde_json::from_slice(msg).unwrap();
+            assert_eq!(tx.index, i as u64);
+            assert_eq!(tx.transaction, txs[i]);
+            assert_eq!(tx.hash, txs[i].commit());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_stream_unreachable() {
+        setup_test();
+
+        // Nothing is listening on this port once the listener is dropped.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("nats://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        drop(listener);
+
+        let sink = StreamSink::new(&options(&url));
+        let leaf = Leaf::genesis(&Default::default(), &NodeState::mock()).await;
+        let messages = sink.messages(&leaf).unwrap();
+        sink.publish(&messages).await.unwrap_err();
+        assert!(sink.conn.lock().await.is_none());
+    }
+}
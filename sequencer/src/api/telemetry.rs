@@ -0,0 +1,137 @@
+//! Self-reported node identity and telemetry.
+//!
+//! Every node serves a [`NodeIdentity`] at `status/node-identity`, describing the software it is
+//! running, which API modules it has enabled, where it stores data, and how long it has been up.
+//! This lets network operators take an inventory of the network without access to each node's
+//! configuration.
+//!
+//! Optionally, the same report is pushed periodically to a central telemetry collector, as the JSON
+//! body of a `POST` request, so that operators do not need to poll every node.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use clap::Parser;
+use espresso_types::parse_duration;
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+use url::Url;
+
+/// Options for reporting telemetry to a central collector.
+#[derive(Parser, Clone, Debug)]
+pub struct TelemetryOptions {
+    /// URL of a telemetry collector to periodically report this node's identity to.
+    ///
+    /// The report is the same as the one served at `status/node-identity`. If not set, the node
+    /// does not push telemetry anywhere.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_TELEMETRY_URL")]
+    pub telemetry_url: Option<Url>,
+
+    /// How often to report to the telemetry collector.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_TELEMETRY_INTERVAL",
+        value_parser = parse_duration,
+        default_value = "5m"
+    )]
+    pub telemetry_interval: Duration,
+}
+
+impl Default for TelemetryOptions {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+/// The storage backend a node keeps its data in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StorageBackend {
+    Postgres,
+    Sqlite,
+    FileSystem,
+    /// The node does not run a query service, and stores no API data.
+    None,
+}
+
+/// Information a node reports about itself, as returned by `status/node-identity`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeIdentity {
+    /// The version of the sequencer software.
+    pub version: String,
+    /// The git commit the software was built from.
+    pub git_commit: String,
+    /// Human-readable description of the git commit, including the nearest tag.
+    pub git_describe: String,
+    /// The optional modules enabled on this node, such as `query` or `submit`.
+    pub modules: Vec<String>,
+    /// Where the node stores data for the query service.
+    pub storage: StorageBackend,
+    /// When the node started, in seconds since the Unix epoch.
+    pub started_at: u64,
+    /// How long the node has been running, in seconds.
+    pub uptime: u64,
+}
+
+/// Source of [`NodeIdentity`] reports for this node.
+#[derive(Clone, Debug)]
+pub struct Telemetry {
+    modules: Vec<String>,
+    storage: StorageBackend,
+    started: Instant,
+    started_at: u64,
+}
+
+impl Telemetry {
+    pub fn new(modules: Vec<String>, storage: StorageBackend) -> Self {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        Self {
+            modules,
+            storage,
+            started: Instant::now(),
+            started_at,
+        }
+    }
+
+    /// The current identity report for this node.
+    pub fn identity(&self) -> NodeIdentity {
+        NodeIdentity {
+            version: env!("CARGO_PKG_VERSION").into(),
+            git_commit: env!("VERGEN_GIT_SHA").into(),
+            git_describe: env!("VERGEN_GIT_DESCRIBE").into(),
+            modules: self.modules.clone(),
+            storage: self.storage,
+            started_at: self.started_at,
+            uptime: self.started.elapsed().as_secs(),
+        }
+    }
+
+    /// Periodically push this node's identity to the collector at `url`.
+    ///
+    /// Failed reports are logged and retried at the next interval; this task never exits.
+    pub async fn push(self, url: Url, interval: Duration) -> anyhow::Result<()> {
+        let client = reqwest::Client::new();
+        loop {
+            if let Err(err) = self.push_once(&client, url.clone()).await {
+                tracing::warn!(%url, "failed to report telemetry: {err:#}");
+            }
+            sleep(interval).await;
+        }
+    }
+
+    async fn push_once(&self, client: &reqwest::Client, url: Url) -> anyhow::Result<()> {
+        let body = serde_json::to_vec(&self.identity()).context("serializing node identity")?;
+        client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await
+            .context("sending telemetry report")?
+            .error_for_status()
+            .context("telemetry collector rejected report")?;
+        Ok(())
+    }
+}
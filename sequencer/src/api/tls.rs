@@ -0,0 +1,478 @@
+//! Native TLS for the HTTP API servers.
+//!
+//! Simple deployments can serve the API over HTTPS without a reverse proxy in front of the node.
+//! tide itself only serves plain TCP, so when TLS is enabled each address is served by a
+//! [`TlsListener`] instead. It accepts TCP connections, performs the TLS handshake with rustls, and
+//! then serves HTTP over the encrypted stream with async-h1, the same way tide's own TCP listener
+//! serves plain connections. It is the innermost listener the app is served on, so the middleware
+//! installed by the listeners wrapping it applies to HTTPS requests as usual.
+//!
+//! A server can additionally require clients to present a certificate signed by a trusted CA
+//! (mutual TLS). This is used to restrict the admin API to operators holding such a certificate.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io::{self, BufReader},
+    net::SocketAddr,
+    path::Path,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context as TaskContext, Poll},
+    time::Duration,
+};
+
+use anyhow::{ensure, Context};
+use async_std::net::{TcpListener, TcpStream};
+use async_trait::async_trait;
+use derivative::Derivative;
+use futures::{AsyncRead, AsyncWrite, StreamExt};
+use futures_rustls::{
+    rustls::{
+        crypto::ring::default_provider, server::WebPkiClientVerifier, RootCertStore, ServerConfig,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+use parking_lot::Mutex;
+use tide::{
+    http::{Response, StatusCode},
+    listener::{ListenInfo, Listener, ToListener},
+    Server,
+};
+
+/// How long to wait before accepting more connections after a persistent accept error.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(500);
+
+/// How long a client has to complete the TLS handshake before its connection is dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// TLS settings for an API server.
+#[derive(Clone, Debug)]
+pub(crate) struct TlsConfig(Arc<ServerConfig>);
+
+impl TlsConfig {
+    /// Load a certificate chain and private key from PEM files.
+    ///
+    /// If `client_ca` is given, clients must present a certificate signed by one of the CA
+    /// certificates in that PEM file.
+    pub(crate) fn load(cert: &Path, key: &Path, client_ca: Option<&Path>) -> anyhow::Result<Self> {
+        let certs = rustls_pemfile::certs(&mut open(cert)?)
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("reading certificates from {}", cert.display()))?;
+        ensure!(!certs.is_empty(), "no certificates in {}", cert.display());
+        let key = rustls_pemfile::private_key(&mut open(key)?)
+            .with_context(|| format!("reading private key from {}", key.display()))?
+            .with_context(|| format!("no private key in {}", key.display()))?;
+
+        let provider = Arc::new(default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .context("configuring TLS")?;
+        let builder = match client_ca {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for ca in rustls_pemfile::certs(&mut open(path)?) {
+                    let ca = ca.with_context(|| format!("reading CA from {}", path.display()))?;
+                    roots
+                        .add(ca)
+                        .with_context(|| format!("invalid CA in {}", path.display()))?;
+                }
+                ensure!(
+                    !roots.is_empty(),
+                    "no CA certificates in {}",
+                    path.display()
+                );
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()
+                        .context("configuring client certificate verification")?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder
+            .with_single_cert(certs, key)
+            .context("invalid TLS certificate or key")?;
+        // async-h1 only speaks HTTP/1.1.
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Self(Arc::new(config)))
+    }
+}
+
+fn open(path: &Path) -> anyhow::Result<BufReader<File>> {
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    Ok(BufReader::new(file))
+}
+
+/// A [`Listener`] which serves HTTPS on a single address.
+///
+/// Like tide-disco's `RateLimitListener`, if `max_connections` is set, requests on connections
+/// beyond the limit receive an immediate 429 response. Only connections which completed the TLS
+/// handshake count towards the limit, and a client which does not complete the handshake within
+/// [`HANDSHAKE_TIMEOUT`] is disconnected, so idle clients cannot lock others out.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+pub(crate) struct TlsListener<State> {
+    addr: SocketAddr,
+    config: TlsConfig,
+    max_connections: Option<usize>,
+    #[derivative(Debug = "ignore")]
+    listener: Option<TcpListener>,
+    #[derivative(Debug = "ignore")]
+    server: Option<Server<State>>,
+}
+
+impl<State> TlsListener<State> {
+    pub(crate) fn new(addr: SocketAddr, config: TlsConfig, max_connections: Option<usize>) -> Self {
+        Self {
+            addr,
+            config,
+            max_connections,
+            listener: None,
+            server: None,
+        }
+    }
+}
+
+impl<State> Display for TlsListener<State> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "https://{}", self.addr)
+    }
+}
+
+#[async_trait]
+impl<State> Listener<State> for TlsListener<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    async fn bind(&mut self, server: Server<State>) -> io::Result<()> {
+        self.listener = Some(TcpListener::bind(self.addr).await?);
+        self.server = Some(server);
+        Ok(())
+    }
+
+    async fn accept(&mut self) -> io::Result<()> {
+        let (Some(listener), Some(server)) = (self.listener.take(), self.server.take()) else {
+            return Err(io::Error::other(
+                "TLS listener must be bound before accepting",
+            ));
+        };
+        let acceptor = TlsAcceptor::from(self.config.0.clone());
+        let open = Arc::new(AtomicUsize::new(0));
+
+        let mut incoming = listener.incoming();
+        while let Some(stream) = incoming.next().await {
+            match stream {
+                Ok(stream) => {
+                    async_std::task::spawn(serve(
+                        server.clone(),
+                        acceptor.clone(),
+                        stream,
+                        open.clone(),
+                        self.max_connections,
+                    ));
+                }
+                Err(err) if is_transient(&err) => continue,
+                Err(err) => {
+                    tracing::error!(addr = %self.addr, "failed to accept connection: {err}");
+                    async_std::task::sleep(ACCEPT_ERROR_DELAY).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        vec![ListenInfo::new(self.to_string(), "tcp".into(), true)]
+    }
+}
+
+impl<State> ToListener<State> for TlsListener<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    type Listener = Self;
+
+    fn to_listener(self) -> io::Result<Self::Listener> {
+        Ok(self)
+    }
+}
+
+/// Perform the TLS handshake with a client and serve HTTP requests over the encrypted stream.
+async fn serve<State>(
+    server: Server<State>,
+    acceptor: TlsAcceptor,
+    stream: TcpStream,
+    open: Arc<AtomicUsize>,
+    max_connections: Option<usize>,
+) where
+    State: Clone + Send + Sync + 'static,
+{
+    let local_addr = stream.local_addr().ok();
+    let peer_addr = stream.peer_addr().ok();
+    // Connections are served on the async-std executor, so use its timer rather than Tokio's.
+    let stream = match async_std::future::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await
+    {
+        Ok(Ok(stream)) => SharedStream(Arc::new(Mutex::new(stream))),
+        Ok(Err(err)) => {
+            tracing::debug!(?peer_addr, "TLS handshake failed: {err}");
+            return;
+        }
+        Err(_) => {
+            tracing::debug!(?peer_addr, "TLS handshake timed out");
+            return;
+        }
+    };
+    let (connection, count) = Connection::open(&open);
+    let limited = max_connections.is_some_and(|max| count > max);
+
+    let res = if limited {
+        async_h1::accept(stream, |_| async {
+            Ok(Response::new(StatusCode::TooManyRequests))
+        })
+        .await
+    } else {
+        async_h1::accept(stream, |mut req| async {
+            req.set_local_addr(local_addr);
+            req.set_peer_addr(peer_addr);
+            server.respond(req).await
+        })
+        .await
+    };
+    if let Err(err) = res {
+        tracing::debug!(?peer_addr, "error serving HTTPS connection: {err}");
+    }
+    drop(connection);
+}
+
+/// Errors accepting a connection which only affect that connection.
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+/// Counts a connection as open until it is dropped.
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    /// Count a new connection, returning it and the number of connections now open.
+    fn open(count: &Arc<AtomicUsize>) -> (Self, usize) {
+        let open = count.fetch_add(1, Ordering::SeqCst) + 1;
+        (Self(count.clone()), open)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A TLS stream which can be cloned.
+///
+/// async-h1 reads requests and writes responses through separate handles to the same connection.
+#[derive(Clone)]
+struct SharedStream(Arc<Mutex<TlsStream<TcpStream>>>);
+
+impl AsyncRead for SharedStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.0.lock()).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for SharedStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.0.lock()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.0.lock()).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.0.lock()).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write, path::PathBuf};
+
+    use futures::{AsyncReadExt, AsyncWriteExt};
+    use futures_rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName},
+        rustls::ClientConfig,
+        TlsConnector,
+    };
+    use portpicker::pick_unused_port;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use tempfile::TempDir;
+    use tide::listener::ConcurrentListener;
+    use tokio::time::sleep;
+
+    use super::*;
+
+    const REQUEST: &[u8] =
+        b"GET /healthcheck HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+    /// A CA, and a certificate signed by it for `name`.
+    struct Pki {
+        ca: CertificateDer<'static>,
+        ca_pem: String,
+        cert: CertificateDer<'static>,
+        cert_pem: String,
+        key: KeyPair,
+    }
+
+    impl Pki {
+        fn new(name: &str) -> Self {
+            let mut ca_params = CertificateParams::new(vec![]).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca_key = KeyPair::generate().unwrap();
+            let ca = ca_params.self_signed(&ca_key).unwrap();
+
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(vec![name.into()])
+                .unwrap()
+                .signed_by(&key, &ca, &ca_key)
+                .unwrap();
+            Self {
+                ca: ca.der().clone(),
+                ca_pem: ca.pem(),
+                cert: cert.der().clone(),
+                cert_pem: cert.pem(),
+                key,
+            }
+        }
+
+        fn key_der(&self) -> PrivateKeyDer<'static> {
+            PrivatePkcs8KeyDer::from(self.key.serialize_der()).into()
+        }
+    }
+
+    fn write(dir: &TempDir, name: &str, pem: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        File::create(&path)
+            .unwrap()
+            .write_all(pem.as_bytes())
+            .unwrap();
+        path
+    }
+
+    /// Serve a healthcheck endpoint over TLS, requiring client certificates from `client_ca`.
+    async fn serve_tls(
+        server: &Pki,
+        client_ca: Option<&Pki>,
+        max_connections: Option<usize>,
+    ) -> (SocketAddr, TempDir) {
+        let dir = TempDir::new().unwrap();
+        let cert = write(&dir, "cert.pem", &server.cert_pem);
+        let key = write(&dir, "key.pem", &server.key.serialize_pem());
+        let ca = client_ca.map(|ca| write(&dir, "ca.pem", &ca.ca_pem));
+        let config = TlsConfig::load(&cert, &key, ca.as_deref()).unwrap();
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], pick_unused_port().unwrap()));
+        let mut app = tide::new();
+        app.at("/healthcheck").get(|_| async { Ok("healthy") });
+        let mut listener = ConcurrentListener::new();
+        listener
+            .add(TlsListener::new(addr, config, max_connections))
+            .unwrap();
+        tokio::spawn(app.listen(listener));
+        while TcpStream::connect(addr).await.is_err() {
+            sleep(Duration::from_millis(10)).await;
+        }
+        (addr, dir)
+    }
+
+    /// Request the healthcheck from a TLS server, returning the raw response.
+    async fn get(addr: SocketAddr, server: &Pki, client: Option<&Pki>) -> io::Result<String> {
+        let mut roots = RootCertStore::empty();
+        roots.add(server.ca.clone()).unwrap();
+        let builder = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let config = match client {
+            Some(client) => builder
+                .with_client_auth_cert(vec![client.cert.clone()], client.key_der())
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut stream = TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await?;
+        stream.write_all(REQUEST).await?;
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await?;
+        Ok(res)
+    }
+
+    fn is_healthy(res: &io::Result<String>) -> bool {
+        matches!(res, Ok(res) if res.starts_with("HTTP/1.1 200") && res.ends_with("healthy"))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_tls_handshake() {
+        let server = Pki::new("localhost");
+        let (addr, _dir) = serve_tls(&server, None, None).await;
+
+        let res = get(addr, &server, None).await;
+        assert!(is_healthy(&res), "{res:?}");
+
+        // Plain HTTP is not served.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(REQUEST).await.unwrap();
+        let mut res = vec![];
+        stream.read_to_end(&mut res).await.ok();
+        assert!(!String::from_utf8_lossy(&res).contains("healthy"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_tls_client_auth() {
+        let server = Pki::new("localhost");
+        let client = Pki::new("operator");
+        let (addr, _dir) = serve_tls(&server, Some(&client), None).await;
+
+        // Clients without a certificate, or with a certificate from another CA, are rejected.
+        let res = get(addr, &server, None).await;
+        assert!(!is_healthy(&res), "{res:?}");
+        let res = get(addr, &server, Some(&Pki::new("operator"))).await;
+        assert!(!is_healthy(&res), "{res:?}");
+
+        // Clients with a certificate signed by the trusted CA are served.
+        let res = get(addr, &server, Some(&client)).await;
+        assert!(is_healthy(&res), "{res:?}");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_tls_connection_limit_counts_handshaken_connections() {
+        let server = Pki::new("localhost");
+        let (addr, _dir) = serve_tls(&server, None, Some(1)).await;
+
+        // A client which connects but never starts the handshake does not take up the only
+        // connection slot.
+        let _idle = TcpStream::connect(addr).await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+        let res = get(addr, &server, None).await;
+        assert!(is_healthy(&res), "{res:?}");
+    }
+}
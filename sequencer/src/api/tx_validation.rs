@@ -0,0 +1,178 @@
+//! Validation of submitted transactions.
+//!
+//! Consensus accepts any transaction, so one which can never be sequenced, because it does not fit
+//! in a block, only fails later in the builder, without any feedback to the client. Transactions
+//! submitted through the API are instead checked up front against the chain config in effect as of
+//! the latest decided block, and against the namespaces this node is configured to accept, and
+//! rejected with status 400 describing the problem.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+use anyhow::{ensure, Context};
+use espresso_types::{v0_3::ChainConfig, NamespaceId, Transaction};
+use hotshot_types::traits::block_contents::Transaction as _;
+use itertools::Itertools;
+
+use super::{error::ApiError, options::Submit};
+
+/// An inclusive range of namespace IDs.
+///
+/// Parsed from a single ID, like `42`, or a range, like `100-200`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NamespaceRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl NamespaceRange {
+    pub fn contains(&self, ns: NamespaceId) -> bool {
+        (self.start..=self.end).contains(&u32::from(ns))
+    }
+}
+
+impl FromStr for NamespaceRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let parse = |id: &str| {
+            id.trim()
+                .parse::<u32>()
+                .context(format!("invalid namespace {id:?}"))
+        };
+        let (start, end) = match s.split_once('-') {
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            None => {
+                let id = parse(s)?;
+                (id, id)
+            }
+        };
+        ensure!(start <= end, "empty namespace range {s:?}");
+        Ok(Self { start, end })
+    }
+}
+
+impl Display for NamespaceRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+/// Checks submitted transactions before they are forwarded to consensus.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TxValidator {
+    allowed_namespaces: Vec<NamespaceRange>,
+    skip: bool,
+}
+
+impl TxValidator {
+    pub(crate) fn new(opt: &Submit) -> Self {
+        if opt.skip_validation {
+            tracing::warn!("validation of submitted transactions is disabled");
+        }
+        Self {
+            allowed_namespaces: opt.allowed_namespaces.clone(),
+            skip: opt.skip_validation,
+        }
+    }
+
+    /// Check that `tx` can be sequenced under the chain config `cf`.
+    pub(crate) fn validate(&self, cf: &ChainConfig, tx: &Transaction) -> Result<(), ApiError> {
+        if self.skip {
+            return Ok(());
+        }
+
+        let ns = tx.namespace();
+        if !self.allowed_namespaces.is_empty()
+            && !self
+                .allowed_namespaces
+                .iter()
+                .any(|range| range.contains(ns))
+        {
+            return Err(ApiError::BadRequest(format!(
+                "namespace {ns} is not accepted by this node; accepted namespaces are {}",
+                self.allowed_namespaces.iter().join(",")
+            )));
+        }
+
+        // A transaction must fit in a block on its own, including the overhead of its namespace.
+        let max_block_size = u64::from(cf.max_block_size);
+        let size = tx.minimum_block_size();
+        if size > max_block_size {
+            return Err(ApiError::BadRequest(format!(
+                "transaction of {} bytes takes {size} bytes in a block, which is more than the \
+                 maximum block size of {max_block_size} bytes",
+                tx.payload().len(),
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tx_validation() {
+        let cf = ChainConfig {
+            max_block_size: 100u64.into(),
+            ..Default::default()
+        };
+        let opt = Submit {
+            allowed_namespaces: vec!["1".parse().unwrap(), "10-20".parse().unwrap()],
+            ..Default::default()
+        };
+        let validator = TxValidator::new(&opt);
+
+        validator
+            .validate(&cf, &Transaction::new(1u32.into(), vec![0; 10]))
+            .unwrap();
+        validator
+            .validate(&cf, &Transaction::new(15u32.into(), vec![0; 10]))
+            .unwrap();
+
+        // Namespaces outside the allowed ranges are rejected.
+        let err = validator
+            .validate(&cf, &Transaction::new(2u32.into(), vec![0; 10]))
+            .unwrap_err();
+        assert_eq!(err.code(), "bad_request");
+        assert!(err.message().contains("1,10-20"), "{err}");
+
+        // A transaction which does not fit in a block, with its namespace overhead, is rejected.
+        let tx = Transaction::new(1u32.into(), vec![0; 100]);
+        let err = validator.validate(&cf, &tx).unwrap_err();
+        assert_eq!(err.code(), "bad_request");
+
+        // Unless validation is disabled.
+        let validator = TxValidator::new(&Submit {
+            skip_validation: true,
+            ..opt
+        });
+        validator.validate(&cf, &tx).unwrap();
+        validator
+            .validate(&cf, &Transaction::new(2u32.into(), vec![]))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_parse_namespace_range() {
+        assert_eq!(
+            "42".parse::<NamespaceRange>().unwrap(),
+            NamespaceRange { start: 42, end: 42 }
+        );
+        assert_eq!(
+            " 1 - 5 ".parse::<NamespaceRange>().unwrap(),
+            NamespaceRange { start: 1, end: 5 }
+        );
+        "5-1".parse::<NamespaceRange>().unwrap_err();
+        "x".parse::<NamespaceRange>().unwrap_err();
+        "4294967296".parse::<NamespaceRange>().unwrap_err();
+    }
+}
@@ -1,13 +1,20 @@
 //! Update loop for query API state.
 
-use anyhow::bail;
+use anyhow::{anyhow, bail, Context};
 use async_trait::async_trait;
+use committable::Committable;
 use derivative::Derivative;
 use derive_more::From;
-use espresso_types::{v0::traits::SequencerPersistence, PubKey};
-use hotshot::types::Event;
-use hotshot_query_service::data_source::UpdateDataSource;
-use hotshot_types::traits::{network::ConnectedNetwork, node_implementation::Versions};
+use espresso_types::{v0::traits::SequencerPersistence, Leaf, PubKey};
+use hotshot::types::{Event, EventType};
+use hotshot_query_service::{
+    availability::AvailabilityDataSource, data_source::UpdateDataSource, node::NodeDataSource,
+};
+use hotshot_types::{
+    event::LeafInfo,
+    simple_certificate::QuorumCertificate,
+    traits::{network::ConnectedNetwork, node_implementation::Versions},
+};
 use std::fmt::Debug;
 use std::sync::Arc;
 
@@ -39,4 +46,72 @@ where
         }
         Ok(())
     }
+
+    /// Repair query storage which has diverged from consensus storage.
+    ///
+    /// If query storage is behind the anchor leaf, the anchor leaf is added to it, after which the
+    /// missing blocks before it are fetched from peers. If query storage is ahead, it is left
+    /// alone as long as it agrees with the anchor leaf, since consensus will decide the same blocks
+    /// again. Otherwise it holds a different chain, and is truncated back to the anchor leaf.
+    async fn reconcile(
+        &self,
+        anchor: &Leaf,
+        qc: &QuorumCertificate<SeqTypes>,
+    ) -> anyhow::Result<()> {
+        let height = anchor.height();
+        let block_height = NodeDataSource::<SeqTypes>::block_height(&*self.inner)
+            .await
+            .context("loading query storage block height")? as u64;
+
+        if block_height > height {
+            match self.inner.get_leaf(height as usize).await.try_resolve() {
+                Ok(stored) if stored.leaf().commit() == anchor.commit() => {
+                    tracing::info!(height, block_height, "query storage is consistent");
+                    return Ok(());
+                }
+                Ok(stored) => {
+                    tracing::warn!(
+                        height,
+                        block_height,
+                        stored = %stored.leaf().commit(),
+                        anchor = %anchor.commit(),
+                        "query storage has a different leaf than consensus storage, truncating"
+                    );
+                    self.inner
+                        .inner()
+                        .truncate(height)
+                        .await
+                        .context(format!("truncating query storage to height {height}"))?;
+                }
+                Err(_) => {
+                    tracing::warn!(height, block_height, "query storage is missing anchor leaf");
+                }
+            }
+        } else {
+            tracing::warn!(
+                height,
+                block_height,
+                "query storage is behind consensus storage"
+            );
+        }
+
+        let info = LeafInfo {
+            leaf: anchor.clone(),
+            vid_share: None,
+            state: Default::default(),
+            delta: None,
+        };
+        let event = Event {
+            view_number: anchor.view_number(),
+            event: EventType::Decide {
+                leaf_chain: Arc::new(vec![info]),
+                qc: Arc::new(qc.clone()),
+                block_size: None,
+            },
+        };
+        self.inner
+            .update(&event)
+            .await
+            .map_err(|height| anyhow!("failed to add anchor leaf {height} to query storage"))
+    }
 }
@@ -0,0 +1,372 @@
+//! Repair of missing VID shares.
+//!
+//! Each storage node stores its own VID share of every decided block, so that the payload can be
+//! recovered from the shares of a subset of nodes. A node which loses its shares, for example
+//! because it was offline when a block was disseminated or restored from a snapshot, cannot help
+//! with availability for those blocks. When enabled, the [`VidRepair`] task periodically looks for
+//! decided blocks which are missing this node's share, recovers their payloads, and re-derives the
+//! share from the payload.
+//!
+//! A payload which is stored locally is used as is. Otherwise, the task fetches the shares of the
+//! configured query service peers, which serve their own shares at `node/vid/share/:height`, and
+//! recovers the payload from them, falling back to fetching the full payload from peers if not
+//! enough valid shares are available. Either way, the re-derived share is only stored if the
+//! payload matches the commitment in the block header.
+//!
+//! Progress is reported via metrics in the `vid_repair` group, which are served on the
+//! `status/metrics` endpoint.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{ensure, Context};
+use clap::Parser;
+use espresso_types::{parse_duration, Header, Payload};
+use futures::stream::{FuturesUnordered, StreamExt};
+use hotshot::traits::BlockPayload;
+use hotshot_query_service::{
+    availability::{AvailabilityDataSource, BlockInfo, BlockQueryData, UpdateAvailabilityData},
+    node::NodeDataSource,
+    VidShare,
+};
+use hotshot_types::{
+    traits::{
+        metrics::{Counter, Gauge, Metrics},
+        EncodeBytes,
+    },
+    vid::{vid_scheme, VidCommitment, VidCommon, VidSchemeType},
+};
+use jf_vid::VidScheme;
+use surf_disco::Client;
+use tide_disco::error::ServerError;
+use tokio::time::{sleep, timeout};
+
+use super::peers::QueryPeers;
+use crate::{SeqTypes, SequencerApiVersion};
+
+/// Options for repairing missing VID shares.
+#[derive(Parser, Clone, Copy, Debug)]
+pub struct VidRepairOptions {
+    /// Periodically recover this node's VID share for decided blocks which are missing it.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_VID_REPAIR")]
+    pub vid_repair: bool,
+
+    /// How long to wait between scans for missing VID shares.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_VID_REPAIR_INTERVAL",
+        value_parser = parse_duration,
+        default_value = "1m"
+    )]
+    pub vid_repair_interval: Duration,
+
+    /// How long to wait for peers to provide the data needed to repair a single share.
+    ///
+    /// Shares which time out are retried on the next scan.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_VID_REPAIR_FETCH_TIMEOUT",
+        value_parser = parse_duration,
+        default_value = "30s"
+    )]
+    pub vid_repair_fetch_timeout: Duration,
+}
+
+impl Default for VidRepairOptions {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+#[derive(Debug)]
+struct VidRepairMetrics {
+    /// Number of decided blocks missing a VID share, as of the latest scan.
+    missing_shares: Box<dyn Gauge>,
+    /// The height below which every share has been checked and, if necessary, repaired.
+    repaired_height: Box<dyn Gauge>,
+    repaired_shares: Box<dyn Counter>,
+    recovered_payloads: Box<dyn Counter>,
+    failed_repairs: Box<dyn Counter>,
+}
+
+impl VidRepairMetrics {
+    fn new(metrics: &dyn Metrics) -> Self {
+        let metrics = metrics.subgroup("vid_repair".into());
+        Self {
+            missing_shares: metrics.create_gauge("missing_shares".into(), None),
+            repaired_height: metrics.create_gauge("repaired_height".into(), None),
+            repaired_shares: metrics.create_counter("repaired_shares".into(), None),
+            recovered_payloads: metrics.create_counter("recovered_payloads".into(), None),
+            failed_repairs: metrics.create_counter("failed_repairs".into(), None),
+        }
+    }
+}
+
+/// Background task which repairs missing VID shares.
+#[derive(Debug)]
+pub(crate) struct VidRepair<D> {
+    storage: Arc<D>,
+    peers: QueryPeers,
+    opt: VidRepairOptions,
+    metrics: VidRepairMetrics,
+}
+
+impl<D> VidRepair<D>
+where
+    D: AvailabilityDataSource<SeqTypes>
+        + NodeDataSource<SeqTypes>
+        + UpdateAvailabilityData<SeqTypes>
+        + Send
+        + Sync,
+{
+    pub(crate) fn new(
+        storage: Arc<D>,
+        peers: QueryPeers,
+        opt: VidRepairOptions,
+        metrics: &dyn Metrics,
+    ) -> Self {
+        Self {
+            storage,
+            peers,
+            opt,
+            metrics: VidRepairMetrics::new(metrics),
+        }
+    }
+
+    /// Repair shares forever.
+    ///
+    /// `index` is this node's position among the storage nodes, which determines which share of
+    /// each block it stores. A node which is not a storage node has no shares to repair.
+    pub(crate) async fn run(self, index: Option<usize>) {
+        let Some(index) = index else {
+            tracing::warn!("node is not a storage node, not repairing VID shares");
+            return;
+        };
+        tracing::info!(opt = ?self.opt, index, "starting VID repair");
+
+        let mut from = 0;
+        loop {
+            match self.scan(index, from).await {
+                Ok(next) => from = next,
+                Err(err) => tracing::warn!("error repairing VID shares: {err:#}"),
+            }
+            sleep(self.opt.vid_repair_interval).await;
+        }
+    }
+
+    /// Repair missing shares for every decided block at or above `from`.
+    ///
+    /// Returns the height of the first block whose share could not be repaired, or the block
+    /// height if all shares are now present, as the starting point for the next scan.
+    async fn scan(&self, index: usize, from: u64) -> anyhow::Result<u64> {
+        let status = self
+            .storage
+            .sync_status()
+            .await
+            .context("loading sync status")?;
+        let block_height = self
+            .storage
+            .block_height()
+            .await
+            .context("loading block height")? as u64;
+        self.metrics.missing_shares.set(status.missing_vid_shares);
+        if status.missing_vid_shares == 0 {
+            self.metrics.repaired_height.set(block_height as usize);
+            return Ok(block_height);
+        }
+
+        // Shares below the pruned height are deleted on purpose.
+        let from = from.max(status.pruned_height.map_or(0, |h| h as u64 + 1));
+        tracing::info!(
+            from,
+            block_height,
+            missing = status.missing_vid_shares,
+            "scanning for missing VID shares"
+        );
+
+        let mut next = None;
+        for height in from..block_height {
+            if self.storage.vid_share(height as usize).await.is_ok() {
+                continue;
+            }
+            match self.repair(height, index).await {
+                Ok(recovered) => {
+                    tracing::info!(height, recovered, "repaired VID share");
+                    self.metrics.repaired_shares.add(1);
+                    if recovered {
+                        self.metrics.recovered_payloads.add(1);
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!(height, "failed to repair VID share: {err:#}");
+                    self.metrics.failed_repairs.add(1);
+                    next.get_or_insert(height);
+                }
+            }
+        }
+        let next = next.unwrap_or(block_height);
+        self.metrics.repaired_height.set(next as usize);
+        Ok(next)
+    }
+
+    /// Re-derive and store this node's share of the block at `height`.
+    ///
+    /// Returns whether the payload had to be recovered from the shares of peers.
+    async fn repair(&self, height: u64, index: usize) -> anyhow::Result<bool> {
+        let fetch_timeout = self.opt.vid_repair_fetch_timeout;
+        let leaf = self
+            .storage
+            .get_leaf(height as usize)
+            .await
+            .with_timeout(fetch_timeout)
+            .await
+            .context("fetching leaf")?;
+        let common = self
+            .storage
+            .get_vid_common(height as usize)
+            .await
+            .with_timeout(fetch_timeout)
+            .await
+            .context("fetching VID common")?;
+        let header = leaf.header();
+        let commit = header.payload_commitment();
+
+        let (block, recovered) = match self.storage.get_block(height as usize).await.try_resolve() {
+            Ok(block) => (block, false),
+            Err(fetch) => match self.recover(height, header, common.common()).await {
+                Ok(block) => (block, true),
+                Err(err) => {
+                    tracing::info!(
+                        height,
+                        "could not recover payload from shares, fetching it: {err:#}"
+                    );
+                    let block = fetch
+                        .with_timeout(fetch_timeout)
+                        .await
+                        .context("fetching payload")?;
+                    (block, false)
+                }
+            },
+        };
+
+        let share = derive_share(&block.payload().encode(), common.common(), commit, index)?;
+        self.storage
+            .append(BlockInfo::new(leaf, Some(block), Some(common), Some(share)))
+            .await
+            .context("storing repaired VID share")?;
+        Ok(recovered)
+    }
+
+    /// Recover the payload of the block at `height` from the shares of peers.
+    async fn recover(
+        &self,
+        height: u64,
+        header: &Header,
+        common: &VidCommon,
+    ) -> anyhow::Result<BlockQueryData<SeqTypes>> {
+        let fetch_timeout = self.opt.vid_repair_fetch_timeout;
+        let shares = self
+            .peers
+            .list()
+            .await
+            .into_iter()
+            .map(|url| async move {
+                let client = Client::<ServerError, SequencerApiVersion>::new(url.clone());
+                let res = timeout(
+                    fetch_timeout,
+                    client
+                        .get::<VidShare>(&format!("node/vid/share/{height}"))
+                        .send(),
+                )
+                .await;
+                match res {
+                    Ok(Ok(share)) => Some(share),
+                    Ok(Err(err)) => {
+                        tracing::debug!(height, %url, "failed to fetch VID share: {err:#}");
+                        None
+                    }
+                    Err(_) => {
+                        tracing::debug!(height, %url, "timed out fetching VID share");
+                        None
+                    }
+                }
+            })
+            .collect::<FuturesUnordered<_>>()
+            .filter_map(|share| async move { share })
+            .collect::<Vec<_>>()
+            .await;
+
+        let bytes = recover_payload(&shares, common, header.payload_commitment())?;
+        let payload = Payload::from_bytes(&bytes, header.ns_table());
+        Ok(BlockQueryData::new(header.clone(), payload))
+    }
+}
+
+/// Recover a payload from VID shares, ignoring any shares which are invalid for `commit`.
+fn recover_payload(
+    shares: &[VidShare],
+    common: &VidCommon,
+    commit: VidCommitment,
+) -> anyhow::Result<Vec<u8>> {
+    let vid = vid_scheme(VidSchemeType::get_num_storage_nodes(common) as usize);
+    let shares = shares
+        .iter()
+        .filter(|share| matches!(vid.verify_share(share, common, &commit), Ok(Ok(()))))
+        .cloned()
+        .collect::<Vec<_>>();
+    vid.recover_payload(&shares, common).context(format!(
+        "recovering payload from {} valid shares",
+        shares.len()
+    ))
+}
+
+/// Derive the VID share at `index` from a payload, checking that it matches `commit`.
+fn derive_share(
+    payload: &[u8],
+    common: &VidCommon,
+    commit: VidCommitment,
+    index: usize,
+) -> anyhow::Result<VidShare> {
+    let mut vid = vid_scheme(VidSchemeType::get_num_storage_nodes(common) as usize);
+    let disperse = vid.disperse(payload).context("dispersing payload")?;
+    ensure!(
+        disperse.commit == commit,
+        "payload does not match commitment {commit}"
+    );
+    disperse
+        .shares
+        .into_iter()
+        .nth(index)
+        .context(format!("no VID share for storage node {index}"))
+}
+
+#[cfg(test)]
+mod test {
+    use espresso_types::{NamespaceId, NodeState, Transaction};
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_repair_share() {
+        let txs = vec![Transaction::new(NamespaceId::from(1u32), vec![1, 2, 3])];
+        let (payload, _) = Payload::from_transactions(txs, &Default::default(), &NodeState::mock())
+            .await
+            .unwrap();
+        let bytes = payload.encode();
+        let vid = vid_scheme(10).disperse(&bytes).unwrap();
+
+        // Recover the payload without the share of node 2, ignoring an invalid share.
+        let other = vid_scheme(10).disperse(vec![0; 100]).unwrap();
+        let mut shares = vid.shares[3..].to_vec();
+        shares.push(other.shares[0].clone());
+        let recovered = recover_payload(&shares, &vid.common, vid.commit).unwrap();
+        assert_eq!(*recovered, *bytes);
+
+        // Re-derive the missing share.
+        let share = derive_share(&recovered, &vid.common, vid.commit, 2).unwrap();
+        assert_eq!(share, vid.shares[2]);
+
+        // A payload which does not match the commitment is rejected.
+        derive_share(&[0; 100], &vid.common, vid.commit, 2).unwrap_err();
+        derive_share(&recovered, &vid.common, vid.commit, 10).unwrap_err();
+    }
+}
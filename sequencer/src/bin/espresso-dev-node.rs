@@ -172,6 +172,14 @@ async fn main() -> anyhow::Result<()> {
     let api_options = options::Options::from(options::Http {
         port: sequencer_api_port,
         max_connections: sequencer_api_max_connections,
+        cors_origins: vec![],
+        max_request_body_bytes: None,
+        max_range_size: None,
+        compress_responses: false,
+        bind_address: [0, 0, 0, 0].into(),
+        module_listeners: vec![],
+        tls_cert: None,
+        tls_key: None,
     })
     .status(Default::default())
     .state(Default::default())
@@ -99,6 +99,14 @@ struct Options {
     #[clap(short, long, name = "OUT")]
     out: PathBuf,
 
+    /// Encrypt private keys with the password in PASSWORD_FILE.
+    ///
+    /// Instead of plaintext .env files, each setup is written to an encrypted keystore under DIR,
+    /// with names like 0.json, 1.json, etc. The keystores can be used to configure a sequencer node
+    /// with `--key-provider keystore`.
+    #[clap(long, name = "PASSWORD_FILE")]
+    keystore_password_file: Option<PathBuf>,
+
     #[clap(flatten)]
     logging: logging::Config,
 }
@@ -142,6 +150,22 @@ fn main() -> anyhow::Result<()> {
         let _enter = span.enter();
         tracing::info!("generating new key set");
 
+        if let Some(password_file) = &opts.keystore_password_file {
+            let password = fs::read_to_string(password_file)?;
+            let mut keys = vec![];
+            opts.scheme.gen(seed, index as u64, &mut keys)?;
+            let name = format!("{index}.json");
+            eth_keystore::encrypt_key(
+                &opts.out,
+                &mut rand::thread_rng(),
+                keys,
+                password.trim(),
+                Some(&name),
+            )?;
+            tracing::info!("private keys written to {}", opts.out.join(name).display());
+            continue;
+        }
+
         let path = opts.out.join(format!("{index}.env"));
         let mut file = File::options()
             .write(true)
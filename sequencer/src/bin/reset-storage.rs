@@ -24,6 +24,8 @@ enum Command {
     Fs(persistence::fs::Options),
     /// Reset SQL storage.
     Sql(Box<persistence::sql::Options>),
+    /// Reset RocksDB storage.
+    Rocksdb(persistence::rocksdb::Options),
 }
 
 #[tokio::main]
@@ -40,12 +42,16 @@ async fn main() -> anyhow::Result<()> {
             tracing::warn!("resetting SQL storage {opt:?}");
             reset_storage(*opt).await
         }
+        Command::Rocksdb(opt) => {
+            tracing::warn!("resetting RocksDB storage {opt:?}");
+            reset_storage(opt).await
+        }
     }
 }
 
 async fn reset_storage<O: DataSourceOptions>(opt: O) -> anyhow::Result<()> {
     // Reset query service storage.
-    O::DataSource::create(opt.clone(), Default::default(), true).await?;
+    O::DataSource::create(opt.query_options(), Default::default(), true).await?;
     // Reset consensus storage.
     opt.reset().await?;
 
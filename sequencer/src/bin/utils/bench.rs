@@ -0,0 +1,422 @@
+//! Benchmark a sequencer deployment with synthetic transaction load.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, ensure, Context};
+use async_lock::Mutex;
+use clap::Parser;
+use committable::{Commitment, Committable};
+use espresso_types::{parse_duration, parse_size, SeqTypes, Transaction};
+use futures::stream::StreamExt;
+use hotshot_query_service::availability::BlockQueryData;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaChaRng;
+use sequencer::api::client::SequencerClient;
+use serde::Serialize;
+use tokio::{
+    sync::Semaphore,
+    time::{interval, sleep},
+};
+use url::Url;
+
+/// Generate synthetic transaction load and measure sequencing latency.
+///
+/// Transactions are generated deterministically from a seed, so two runs with the same options
+/// submit the same transactions in the same order. Each transaction's latency is measured from the
+/// time it is submitted to the time its block arrives on the block stream.
+#[derive(Clone, Debug, Parser)]
+pub struct Options {
+    /// URL of a sequencer node running the submit and query APIs.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_URL")]
+    url: Url,
+
+    /// Namespaces to submit to.
+    ///
+    /// Each transaction goes to a namespace chosen uniformly from this list.
+    #[clap(
+        long,
+        env = "ESPRESSO_BENCH_NAMESPACES",
+        value_delimiter = ',',
+        default_value = "10000"
+    )]
+    namespaces: Vec<u32>,
+
+    /// Minimum size of a transaction payload.
+    #[clap(long, env = "ESPRESSO_BENCH_MIN_SIZE", value_parser = parse_size, default_value = "1")]
+    min_size: u64,
+
+    /// Maximum size of a transaction payload.
+    #[clap(long, env = "ESPRESSO_BENCH_MAX_SIZE", value_parser = parse_size, default_value = "1kb")]
+    max_size: u64,
+
+    /// How the submission rate, in transactions per second, changes over the run.
+    ///
+    /// One of:
+    /// * `constant=RATE`
+    /// * `ramp=FROM..TO/PERIOD`, increasing linearly from FROM to TO over PERIOD, then holding
+    /// * `step=RATE1,RATE2,.../PERIOD`, submitting at each rate for PERIOD in turn, then holding
+    #[clap(long, env = "ESPRESSO_BENCH_PROFILE", default_value = "constant=10")]
+    profile: RateProfile,
+
+    /// How long to submit transactions for.
+    #[clap(long, env = "ESPRESSO_BENCH_DURATION", value_parser = parse_duration, default_value = "1m")]
+    duration: Duration,
+
+    /// How long to wait for outstanding transactions to be sequenced after submission stops.
+    #[clap(long, env = "ESPRESSO_BENCH_DRAIN_TIMEOUT", value_parser = parse_duration, default_value = "30s")]
+    drain_timeout: Duration,
+
+    /// Number of transactions to submit per request.
+    ///
+    /// With a batch size greater than 1, transactions are submitted via the batch endpoint.
+    #[clap(long, env = "ESPRESSO_BENCH_BATCH_SIZE", default_value = "1")]
+    batch_size: usize,
+
+    /// Maximum number of submission requests in flight at once.
+    #[clap(long, env = "ESPRESSO_BENCH_MAX_IN_FLIGHT", default_value = "100")]
+    max_in_flight: usize,
+
+    /// Seed for generating transactions.
+    #[clap(long, env = "ESPRESSO_BENCH_SEED", default_value = "0")]
+    seed: u64,
+
+    /// File to write the report to, as JSON, in addition to logging it.
+    #[clap(short, long, env = "ESPRESSO_BENCH_OUTPUT")]
+    output: Option<PathBuf>,
+}
+
+/// How the submission rate changes over the course of a run.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RateProfile {
+    Constant(f64),
+    Ramp {
+        from: f64,
+        to: f64,
+        period: Duration,
+    },
+    Step {
+        rates: Vec<f64>,
+        period: Duration,
+    },
+}
+
+impl RateProfile {
+    /// The submission rate, in transactions per second, at `elapsed` into the run.
+    fn rate(&self, elapsed: Duration) -> f64 {
+        match self {
+            Self::Constant(rate) => *rate,
+            Self::Ramp { from, to, period } => {
+                let progress = (elapsed.as_secs_f64() / period.as_secs_f64()).min(1.);
+                from + (to - from) * progress
+            }
+            Self::Step { rates, period } => {
+                let step = (elapsed.as_secs_f64() / period.as_secs_f64()) as usize;
+                rates[step.min(rates.len() - 1)]
+            }
+        }
+    }
+}
+
+impl FromStr for RateProfile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let parse_rate = |s: &str| -> anyhow::Result<f64> {
+            let rate = s.parse().with_context(|| format!("invalid rate {s}"))?;
+            ensure!(rate >= 0., "rate must not be negative");
+            Ok(rate)
+        };
+        let parse_period = |s: &str| -> anyhow::Result<Duration> {
+            let period = parse_duration(s).map_err(|err| anyhow::anyhow!("{err}"))?;
+            ensure!(!period.is_zero(), "period must not be zero");
+            Ok(period)
+        };
+
+        let Some((kind, params)) = s.split_once('=') else {
+            bail!("rate profile must have the form KIND=PARAMS");
+        };
+        match kind {
+            "constant" => Ok(Self::Constant(parse_rate(params)?)),
+            "ramp" => {
+                let (rates, period) = params
+                    .split_once('/')
+                    .context("ramp profile must have the form FROM..TO/PERIOD")?;
+                let (from, to) = rates
+                    .split_once("..")
+                    .context("ramp profile must have the form FROM..TO/PERIOD")?;
+                Ok(Self::Ramp {
+                    from: parse_rate(from)?,
+                    to: parse_rate(to)?,
+                    period: parse_period(period)?,
+                })
+            }
+            "step" => {
+                let (rates, period) = params
+                    .split_once('/')
+                    .context("step profile must have the form RATE1,RATE2,.../PERIOD")?;
+                let rates = rates
+                    .split(',')
+                    .map(parse_rate)
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                Ok(Self::Step {
+                    rates,
+                    period: parse_period(period)?,
+                })
+            }
+            _ => bail!("unknown rate profile {kind}; expected constant, ramp, or step"),
+        }
+    }
+}
+
+/// Summary of a benchmark run.
+#[derive(Clone, Debug, Serialize)]
+pub struct Report {
+    pub seed: u64,
+    /// Transactions accepted by the submit API.
+    pub submitted: usize,
+    /// Transactions rejected by the submit API.
+    pub rejected: usize,
+    /// Submitted transactions which were observed in a block.
+    pub sequenced: usize,
+    /// Submitted transactions which were not observed in a block before the run ended.
+    pub pending: usize,
+    /// Sequenced transactions per second, over the submission period.
+    pub throughput_tx_per_sec: f64,
+    /// Sequenced payload bytes per second, over the submission period.
+    pub throughput_bytes_per_sec: f64,
+    /// Distribution of sequencing latency, in milliseconds.
+    pub latency_ms: Option<LatencySummary>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct LatencySummary {
+    pub min: u128,
+    pub mean: u128,
+    pub p50: u128,
+    pub p90: u128,
+    pub p99: u128,
+    pub max: u128,
+}
+
+impl LatencySummary {
+    fn new(mut latencies: Vec<Duration>) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort();
+        let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100].as_millis();
+        let total: Duration = latencies.iter().sum();
+        Some(Self {
+            min: latencies[0].as_millis(),
+            mean: (total / latencies.len() as u32).as_millis(),
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: latencies[latencies.len() - 1].as_millis(),
+        })
+    }
+}
+
+/// Transactions accepted by the submit API and not yet seen in a block.
+type Pending = Arc<Mutex<HashMap<Commitment<Transaction>, Instant>>>;
+
+#[derive(Debug, Default)]
+struct Results {
+    latencies: Vec<Duration>,
+    bytes: usize,
+}
+
+pub async fn run(opt: Options) -> anyhow::Result<()> {
+    ensure!(
+        !opt.namespaces.is_empty(),
+        "at least one namespace is required"
+    );
+    ensure!(opt.min_size <= opt.max_size, "MIN_SIZE exceeds MAX_SIZE");
+    ensure!(opt.batch_size > 0, "batch size must be positive");
+
+    let client = SequencerClient::new(opt.url.clone());
+    client.connect().await;
+
+    // Start listening for blocks before submitting anything, so no transaction is missed.
+    let height = client
+        .block_height()
+        .await
+        .context("fetching block height")?;
+    let mut blocks = client
+        .inner()
+        .socket(&format!("availability/stream/blocks/{height}"))
+        .subscribe::<BlockQueryData<SeqTypes>>()
+        .await
+        .context("subscribing to blocks")?;
+    tracing::info!(height, "listening for blocks");
+
+    let pending = Pending::default();
+    let results = Arc::new(Mutex::new(Results::default()));
+    let collector = {
+        let pending = pending.clone();
+        let results = results.clone();
+        tokio::spawn(async move {
+            while let Some(block) = blocks.next().await {
+                let block = match block {
+                    Ok(block) => block,
+                    Err(err) => {
+                        tracing::warn!("error getting block: {err}");
+                        continue;
+                    }
+                };
+                let received_at = Instant::now();
+                let mut pending = pending.lock().await;
+                let mut results = results.lock().await;
+                for (_, tx) in block.enumerate() {
+                    if let Some(submitted_at) = pending.remove(&tx.commit()) {
+                        results.latencies.push(received_at - submitted_at);
+                        results.bytes += tx.payload().len();
+                    }
+                }
+            }
+        })
+    };
+
+    let (submitted, rejected) = submit(&opt, &client, &pending).await;
+    tracing::info!(
+        submitted,
+        rejected,
+        "finished submitting, waiting for transactions"
+    );
+
+    let deadline = tokio::time::Instant::now() + opt.drain_timeout;
+    while !pending.lock().await.is_empty() && tokio::time::Instant::now() < deadline {
+        sleep(Duration::from_secs(1)).await;
+    }
+    collector.abort();
+
+    let results = std::mem::take(&mut *results.lock().await);
+    let sequenced = results.latencies.len();
+    let secs = opt.duration.as_secs_f64();
+    let report = Report {
+        seed: opt.seed,
+        submitted,
+        rejected,
+        sequenced,
+        pending: pending.lock().await.len(),
+        throughput_tx_per_sec: sequenced as f64 / secs,
+        throughput_bytes_per_sec: results.bytes as f64 / secs,
+        latency_ms: LatencySummary::new(results.latencies),
+    };
+    tracing::info!(?report, "benchmark complete");
+
+    if let Some(path) = &opt.output {
+        let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+        serde_json::to_writer_pretty(file, &report).context("writing report")?;
+    }
+    Ok(())
+}
+
+/// Submit transactions according to the rate profile, returning the number accepted and rejected.
+async fn submit(opt: &Options, client: &SequencerClient, pending: &Pending) -> (usize, usize) {
+    // Schedule submissions in small ticks, so that the actual rate tracks the profile closely.
+    let mut ticker = interval(Duration::from_millis(10));
+    let tick = ticker.period().as_secs_f64();
+
+    let mut rng = ChaChaRng::seed_from_u64(opt.seed);
+    let in_flight = Arc::new(Semaphore::new(opt.max_in_flight));
+    let mut tasks = vec![];
+
+    let start = Instant::now();
+    let mut due = 0f64;
+    let mut batch = vec![];
+    while start.elapsed() < opt.duration {
+        ticker.tick().await;
+        due += opt.profile.rate(start.elapsed()) * tick;
+        while due >= 1. {
+            due -= 1.;
+            batch.push(random_transaction(opt, &mut rng));
+            if batch.len() < opt.batch_size {
+                continue;
+            }
+
+            let txs = std::mem::take(&mut batch);
+            let permit = in_flight.clone().acquire_owned().await.unwrap();
+            let client = client.clone();
+            let pending = pending.clone();
+            tasks.push(tokio::spawn(async move {
+                let counts = submit_batch(&client, txs, &pending).await;
+                drop(permit);
+                counts
+            }));
+        }
+    }
+
+    let mut counts = (0, 0);
+    for task in tasks {
+        if let Ok((accepted, rejected)) = task.await {
+            counts.0 += accepted;
+            counts.1 += rejected;
+        }
+    }
+    counts
+}
+
+/// Submit one request worth of transactions, returning the number accepted and rejected.
+async fn submit_batch(
+    client: &SequencerClient,
+    txs: Vec<Transaction>,
+    pending: &Pending,
+) -> (usize, usize) {
+    // Register the transactions before submitting them, in case they are sequenced before the
+    // submission returns.
+    let submitted_at = Instant::now();
+    let hashes = txs.iter().map(|tx| tx.commit()).collect::<Vec<_>>();
+    {
+        let mut pending = pending.lock().await;
+        for hash in &hashes {
+            pending.insert(*hash, submitted_at);
+        }
+    }
+
+    let rejected = if let [tx] = txs.as_slice() {
+        match client.submit(tx).await {
+            Ok(_) => vec![],
+            Err(err) => {
+                tracing::warn!("failed to submit transaction: {err:#}");
+                hashes
+            }
+        }
+    } else {
+        match client.submit_batch(&txs).await {
+            Ok(results) => results
+                .into_iter()
+                .filter(|res| !res.is_accepted())
+                .map(|res| res.hash)
+                .collect(),
+            Err(err) => {
+                tracing::warn!(
+                    "failed to submit batch of {} transactions: {err:#}",
+                    txs.len()
+                );
+                hashes
+            }
+        }
+    };
+
+    let mut pending = pending.lock().await;
+    for hash in &rejected {
+        pending.remove(hash);
+    }
+    (txs.len() - rejected.len(), rejected.len())
+}
+
+fn random_transaction(opt: &Options, rng: &mut ChaChaRng) -> Transaction {
+    let namespace = opt.namespaces[rng.gen_range(0..opt.namespaces.len())];
+    let len = rng.gen_range(opt.min_size..=opt.max_size);
+    let mut payload = vec![0; len as usize];
+    rng.fill_bytes(&mut payload);
+    Transaction::new(namespace.into(), payload)
+}
@@ -3,9 +3,12 @@
 use clap::{Parser, Subcommand};
 
 use sequencer_utils::logging;
+mod bench;
 mod keygen;
 mod pubkey;
 mod reset_storage;
+mod snapshot;
+mod storage;
 
 #[derive(Debug, Parser)]
 struct Options {
@@ -18,10 +21,15 @@ struct Options {
 
 #[derive(Debug, Subcommand)]
 enum Command {
+    Bench(bench::Options),
     Keygen(keygen::Options),
     Pubkey(pubkey::Options),
     #[command(subcommand)]
     ResetStorage(reset_storage::Commands),
+    #[command(subcommand)]
+    Snapshot(snapshot::Commands),
+    #[command(subcommand)]
+    Storage(storage::Commands),
 }
 
 #[tokio::main]
@@ -30,11 +38,14 @@ async fn main() -> anyhow::Result<()> {
     opt.logging.init();
 
     match opt.command {
+        Command::Bench(opt) => bench::run(opt).await,
         Command::Keygen(opt) => keygen::run(opt),
         Command::Pubkey(opt) => {
             pubkey::run(opt);
             Ok(())
         }
         Command::ResetStorage(opt) => reset_storage::run(opt).await,
+        Command::Snapshot(opt) => snapshot::run(opt).await,
+        Command::Storage(opt) => storage::run(opt).await,
     }
 }
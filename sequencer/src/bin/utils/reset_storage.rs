@@ -24,6 +24,8 @@ pub enum SequencerStorage {
     Fs(persistence::fs::Options),
     /// Reset SQL storage.
     Sql(Box<persistence::sql::Options>),
+    /// Reset RocksDB storage.
+    Rocksdb(persistence::rocksdb::Options),
 }
 
 pub async fn run(opt: Commands) -> anyhow::Result<()> {
@@ -37,6 +39,10 @@ pub async fn run(opt: Commands) -> anyhow::Result<()> {
                 tracing::warn!("resetting sequencer SQL storage {opt:?}");
                 reset_storage(*opt).await
             }
+            SequencerStorage::Rocksdb(opt) => {
+                tracing::warn!("resetting sequencer RocksDB storage {opt:?}");
+                reset_storage(opt).await
+            }
         },
 
         Commands::Solver(opt) => {
@@ -51,7 +57,7 @@ pub async fn run(opt: Commands) -> anyhow::Result<()> {
 
 async fn reset_storage<O: DataSourceOptions>(opt: O) -> anyhow::Result<()> {
     // Reset query service storage.
-    O::DataSource::create(opt.clone(), Default::default(), true).await?;
+    O::DataSource::create(opt.query_options(), Default::default(), true).await?;
     // Reset consensus storage.
     opt.reset().await?;
 
@@ -0,0 +1,101 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use sequencer::{api::data_source::DataSourceOptions, persistence, snapshot::Snapshot};
+
+/// Export or import a snapshot of node state.
+///
+/// A snapshot contains the consensus state and merklized state of a node as of a decided block,
+/// and can be used to bootstrap a new node without syncing from genesis. Do not run this program
+/// while the sequencer is running.
+#[derive(Clone, Debug, Subcommand)]
+pub enum Commands {
+    /// Export a snapshot from existing storage.
+    Export(ExportOptions),
+    /// Restore a snapshot into empty storage.
+    Import(ImportOptions),
+}
+
+#[derive(Clone, Debug, Parser)]
+pub struct ExportOptions {
+    /// File to write the snapshot to.
+    #[clap(short, long)]
+    output: PathBuf,
+
+    /// Block height to take the snapshot at.
+    ///
+    /// Defaults to the latest decided block available in storage.
+    #[clap(long)]
+    height: Option<u64>,
+
+    #[command(subcommand)]
+    storage: SequencerStorage,
+}
+
+#[derive(Clone, Debug, Parser)]
+pub struct ImportOptions {
+    /// File to read the snapshot from.
+    #[clap(short, long)]
+    input: PathBuf,
+
+    #[command(subcommand)]
+    storage: SequencerStorage,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum SequencerStorage {
+    /// Use file system storage.
+    Fs(persistence::fs::Options),
+    /// Use SQL storage.
+    Sql(Box<persistence::sql::Options>),
+    /// Use RocksDB storage.
+    Rocksdb(persistence::rocksdb::Options),
+}
+
+pub async fn run(opt: Commands) -> anyhow::Result<()> {
+    match opt {
+        Commands::Export(opt) => {
+            let snapshot = match opt.storage {
+                SequencerStorage::Fs(storage) => Snapshot::export(storage, opt.height).await?,
+                SequencerStorage::Sql(storage) => Snapshot::export(*storage, opt.height).await?,
+                SequencerStorage::Rocksdb(storage) => Snapshot::export(storage, opt.height).await?,
+            };
+
+            let file =
+                File::create(&opt.output).context(format!("creating {}", opt.output.display()))?;
+            let mut writer = BufWriter::new(file);
+            snapshot.write(&mut writer)?;
+            writer.flush()?;
+            tracing::info!(
+                height = snapshot.height(),
+                "wrote snapshot to {}",
+                opt.output.display()
+            );
+            Ok(())
+        }
+        Commands::Import(opt) => {
+            let file =
+                File::open(&opt.input).context(format!("opening {}", opt.input.display()))?;
+            let snapshot = Snapshot::read(BufReader::new(file))?;
+            match opt.storage {
+                SequencerStorage::Fs(storage) => import(&snapshot, storage).await,
+                SequencerStorage::Sql(storage) => import(&snapshot, *storage).await,
+                SequencerStorage::Rocksdb(storage) => import(&snapshot, storage).await,
+            }
+        }
+    }
+}
+
+async fn import<O: DataSourceOptions>(snapshot: &Snapshot, opt: O) -> anyhow::Result<()> {
+    snapshot.import(opt).await?;
+    tracing::info!(
+        height = snapshot.height(),
+        "snapshot imported; start the node with --config-peers to fetch the network config"
+    );
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use anyhow::bail;
+use clap::{Parser, Subcommand};
+use espresso_types::parse_duration;
+use hotshot_query_service::availability::UpdateAvailabilityData;
+use sequencer::{
+    api::data_source::DataSourceOptions,
+    integrity::{self, VerifyOptions},
+    SeqTypes,
+};
+use url::Url;
+
+use crate::snapshot::SequencerStorage;
+
+/// Check the integrity of availability storage.
+///
+/// Do not run this program while the sequencer is running.
+#[derive(Clone, Debug, Subcommand)]
+pub enum Commands {
+    /// Check that stored leaves, headers, payloads and VID data are consistent with each other.
+    ///
+    /// Exits with an error if any problems were found which could not be repaired.
+    Verify(Options),
+}
+
+#[derive(Clone, Debug, Parser)]
+pub struct Options {
+    /// First block to check.
+    #[clap(long, default_value = "0")]
+    from: u64,
+
+    /// Block to stop checking at, exclusive.
+    ///
+    /// Defaults to the block height of the storage.
+    #[clap(long)]
+    to: Option<u64>,
+
+    /// Also check the merklized state at the last block against its header.
+    ///
+    /// This loads the full state, which can take a long time for a long chain.
+    #[clap(long)]
+    check_state: bool,
+
+    /// Repair missing or corrupted entries by fetching them from this peer query service.
+    ///
+    /// May be given multiple times. Without any peers, problems are only reported.
+    #[clap(long = "repair-from")]
+    repair_from: Vec<Url>,
+
+    /// How long to wait for each replacement entry to be fetched.
+    #[clap(long, value_parser = parse_duration, default_value = "30s")]
+    fetch_timeout: Duration,
+
+    #[command(subcommand)]
+    storage: SequencerStorage,
+}
+
+pub async fn run(opt: Commands) -> anyhow::Result<()> {
+    match opt {
+        Commands::Verify(opt) => {
+            let cfg = VerifyOptions {
+                from: opt.from,
+                to: opt.to,
+                check_state: opt.check_state,
+                repair_from: opt.repair_from,
+                fetch_timeout: opt.fetch_timeout,
+            };
+            match opt.storage {
+                SequencerStorage::Fs(storage) => verify(storage, cfg).await,
+                SequencerStorage::Sql(storage) => verify(*storage, cfg).await,
+                SequencerStorage::Rocksdb(storage) => verify(storage, cfg).await,
+            }
+        }
+    }
+}
+
+async fn verify<O>(opt: O, cfg: VerifyOptions) -> anyhow::Result<()>
+where
+    O: DataSourceOptions,
+    O::DataSource: UpdateAvailabilityData<SeqTypes>,
+{
+    let report = integrity::verify(opt, cfg).await?;
+    let repaired = report.problems.iter().filter(|p| p.repaired).count();
+    let unrepaired = report.unrepaired().count();
+    tracing::info!(
+        from = report.from,
+        to = report.to,
+        problems = report.problems.len(),
+        repaired,
+        "verified storage"
+    );
+    if unrepaired > 0 {
+        bail!("found {unrepaired} problems which were not repaired");
+    }
+    Ok(())
+}
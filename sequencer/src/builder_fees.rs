@@ -0,0 +1,171 @@
+//! Accounting of the fees charged to builders for the blocks they build.
+//!
+//! Every block header records the fees charged to the fee accounts of the builders which built the
+//! block. The [`BuilderFeeLedger`] follows these through consensus events. Fees in proposed blocks
+//! are held as pending until the block is decided, at which point they are added to per-account
+//! totals in [`SequencerPersistence`]. Both are served by `fee/builder/:address/earnings`, so that
+//! builder operators can reconcile their revenue without parsing every header.
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use espresso_types::{
+    v0::traits::SequencerPersistence, BuilderFeeTotals, FeeAccount, FeeAmount, Header, SeqTypes,
+};
+use hotshot::types::{Event, EventType};
+use hotshot_types::{data::ViewNumber, event::LeafInfo};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+/// The most undecided proposals whose fees are held as pending.
+///
+/// Proposals are dropped once they are decided or a later view is decided, so this only limits
+/// memory use while decisions are stalled.
+const MAX_PENDING_PROPOSALS: usize = 100;
+
+/// The fees charged to a builder's fee account, as reported by `fee/builder/:address/earnings`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuilderEarnings {
+    pub account: FeeAccount,
+    /// The number of decided blocks which charged a fee to the account.
+    pub blocks: u64,
+    /// The total fees charged to the account by decided blocks.
+    pub earned: FeeAmount,
+    /// The height of the first decided block which charged a fee to the account.
+    pub first_height: Option<u64>,
+    /// The height of the latest decided block which charged a fee to the account.
+    pub last_height: Option<u64>,
+    /// The number of proposed blocks, not yet decided, which charge a fee to the account.
+    pub pending_blocks: u64,
+    /// The total fees charged to the account by proposed blocks which are not yet decided.
+    pub pending: FeeAmount,
+}
+
+/// Tracks the fees charged to builders by proposed and decided blocks.
+#[derive(Debug)]
+pub struct BuilderFeeLedger<P> {
+    persistence: Arc<P>,
+    /// Fees charged by undecided proposals, by view.
+    pending: Mutex<BTreeMap<ViewNumber, BTreeMap<FeeAccount, FeeAmount>>>,
+}
+
+impl<P: SequencerPersistence> BuilderFeeLedger<P> {
+    pub fn new(persistence: Arc<P>) -> Self {
+        Self {
+            persistence,
+            pending: Default::default(),
+        }
+    }
+
+    /// The fees charged to `account` by decided and pending blocks.
+    pub async fn earnings(&self, account: FeeAccount) -> anyhow::Result<BuilderEarnings> {
+        let totals = self
+            .persistence
+            .load_builder_fees(account)
+            .await
+            .context("loading builder fees")?;
+        let (pending_blocks, pending) = self
+            .pending
+            .lock()
+            .values()
+            .filter_map(|fees| fees.get(&account))
+            .fold((0, FeeAmount::from(0)), |(blocks, total), amount| {
+                (blocks + 1, FeeAmount(total.0.saturating_add(amount.0)))
+            });
+        Ok(match totals {
+            Some(totals) => BuilderEarnings {
+                account,
+                blocks: totals.blocks,
+                earned: totals.amount,
+                first_height: Some(totals.first_height),
+                last_height: Some(totals.last_height),
+                pending_blocks,
+                pending,
+            },
+            None => BuilderEarnings {
+                account,
+                blocks: 0,
+                earned: 0.into(),
+                first_height: None,
+                last_height: None,
+                pending_blocks,
+                pending,
+            },
+        })
+    }
+
+    /// Update fee accounting based on an event from consensus.
+    pub async fn handle_event(&self, event: &Event<SeqTypes>) {
+        match &event.event {
+            EventType::QuorumProposal { proposal, .. } => {
+                let fees = block_fees(&proposal.data.block_header);
+                if fees.is_empty() {
+                    return;
+                }
+                let mut pending = self.pending.lock();
+                pending.insert(proposal.data.view_number, fees);
+                while pending.len() > MAX_PENDING_PROPOSALS {
+                    pending.pop_first();
+                }
+            }
+            EventType::Decide { leaf_chain, .. } => {
+                // Leaves are ordered newest first.
+                let Some(newest) = leaf_chain.first() else {
+                    return;
+                };
+                let decided_view = newest.leaf.view_number();
+                self.pending.lock().retain(|view, _| *view > decided_view);
+
+                if let Err(err) = self.record_decided(leaf_chain).await {
+                    tracing::warn!(?decided_view, "failed to record builder fees: {err:#}");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Add the fees charged by newly decided blocks to the persisted totals.
+    async fn record_decided(&self, leaf_chain: &[LeafInfo<SeqTypes>]) -> anyhow::Result<()> {
+        let mut totals = BTreeMap::<FeeAccount, BuilderFeeTotals>::new();
+        for LeafInfo { leaf, .. } in leaf_chain.iter().rev() {
+            let height = leaf.height();
+            for (account, amount) in block_fees(leaf.block_header()) {
+                let account_totals = match totals.entry(account) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(
+                        self.persistence
+                            .load_builder_fees(account)
+                            .await?
+                            .unwrap_or_else(|| BuilderFeeTotals::new(account)),
+                    ),
+                };
+                account_totals.add(height, amount);
+            }
+        }
+        if totals.is_empty() {
+            return Ok(());
+        }
+        self.persistence
+            .store_builder_fees(&totals.into_values().collect::<Vec<_>>())
+            .await
+    }
+}
+
+/// The fees charged to each builder account by the block with `header`.
+///
+/// Accounts which are charged nothing, like the placeholder account of the genesis block, are
+/// omitted.
+fn block_fees(header: &Header) -> BTreeMap<FeeAccount, FeeAmount> {
+    let mut fees = BTreeMap::<FeeAccount, FeeAmount>::new();
+    for fee in header.fee_info() {
+        if fee.amount() == FeeAmount::from(0) {
+            continue;
+        }
+        let total = fees.entry(fee.account()).or_insert_with(|| 0.into());
+        *total = FeeAmount(total.0.saturating_add(fee.amount().0));
+    }
+    fees
+}
@@ -0,0 +1,321 @@
+//! Discovery and health of the builders this node fetches blocks from.
+//!
+//! Builders are normally configured statically, through the `builder_urls` of the network config.
+//! When several builders are run, a node can instead discover them from a registry: an HTTP
+//! endpoint serving a JSON array of builder URLs. The registry is queried once at startup, before
+//! consensus starts, and the builders it lists are added to those HotShot connects to. After that,
+//! the registry is refreshed periodically, and every known builder is health checked, so that
+//! `config/builders` can show which builders are available.
+//!
+//! HotShot connects to its builders once, when consensus starts, so a builder discovered after
+//! startup is reported as known but not in use until the node restarts.
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use async_lock::RwLock;
+use clap::Parser;
+use espresso_types::parse_duration;
+use serde::{Deserialize, Serialize};
+use tokio::time::{interval, MissedTickBehavior};
+use url::Url;
+use vec1::Vec1;
+
+/// The path of the health check of a builder, relative to its URL.
+///
+/// This is the same health check HotShot uses when it connects to a builder.
+const HEALTHCHECK_PATH: &str = "block_info/healthcheck";
+
+#[derive(Clone, Debug, Parser)]
+pub struct BuilderRegistryConfig {
+    /// URL of a registry from which to discover builders.
+    ///
+    /// The registry must serve a JSON array of builder URLs. Discovered builders are used in
+    /// addition to the `builder_urls` of the network config.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_BUILDER_REGISTRY_URL")]
+    pub builder_registry_url: Option<Url>,
+
+    /// How often to refresh the builder registry and check the health of known builders.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_BUILDER_REGISTRY_INTERVAL",
+        default_value = "1m",
+        value_parser = parse_duration,
+    )]
+    pub builder_registry_interval: Duration,
+
+    /// Timeout for requests to the builder registry and for builder health checks.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_BUILDER_HEALTH_TIMEOUT",
+        default_value = "5s",
+        value_parser = parse_duration,
+    )]
+    pub builder_health_timeout: Duration,
+}
+
+impl Default for BuilderRegistryConfig {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+impl BuilderRegistryConfig {
+    /// Discover builders and add them to `builder_urls`, the builders HotShot will connect to.
+    ///
+    /// Returns a registry tracking both the discovered and the statically configured builders. If
+    /// the registry cannot be reached, the node starts with only the configured builders.
+    pub(crate) async fn init(&self, builder_urls: &mut Vec1<Url>) -> BuilderRegistry {
+        let registry = BuilderRegistry::new(builder_urls.iter().cloned());
+        let Some(registry_url) = &self.builder_registry_url else {
+            return registry;
+        };
+        match fetch_registry(&reqwest::Client::new(), registry_url, self).await {
+            Ok(discovered) => {
+                let discovered = discovered
+                    .into_iter()
+                    .filter(|url| !builder_urls.contains(url))
+                    .collect::<Vec<_>>();
+                for url in &discovered {
+                    tracing::info!(%url, "discovered builder");
+                    builder_urls.push(url.clone());
+                }
+                registry.discover(discovered, true).await;
+            }
+            Err(err) => {
+                tracing::warn!(%registry_url, "failed to discover builders: {err:#}");
+            }
+        }
+        registry
+    }
+}
+
+/// Where a builder became known from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuilderSource {
+    /// The builder is listed in the network config.
+    Config,
+    /// The builder was discovered from the builder registry.
+    Registry,
+}
+
+/// A builder known to this node, as returned by `config/builders`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuilderInfo {
+    pub url: Url,
+    pub source: BuilderSource,
+    /// Whether HotShot fetches blocks from this builder.
+    ///
+    /// Builders discovered after startup are not used until the node restarts.
+    pub in_use: bool,
+    /// Whether the latest health check succeeded, or [`None`] if the builder has not been checked.
+    pub healthy: Option<bool>,
+    /// When the builder was last checked, as a Unix timestamp.
+    pub last_checked: Option<u64>,
+    /// Response time of the latest successful health check, in milliseconds.
+    pub latency_ms: Option<u64>,
+    /// Why the latest health check failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BuilderInfo {
+    fn new(url: Url, source: BuilderSource, in_use: bool) -> Self {
+        Self {
+            url,
+            source,
+            in_use,
+            healthy: None,
+            last_checked: None,
+            latency_ms: None,
+            error: None,
+        }
+    }
+
+    fn record_check(&mut self, result: Result<Duration, String>) {
+        self.last_checked = Some(unix_timestamp());
+        match result {
+            Ok(latency) => {
+                self.healthy = Some(true);
+                self.latency_ms = Some(latency.as_millis() as u64);
+                self.error = None;
+            }
+            Err(err) => {
+                self.healthy = Some(false);
+                self.latency_ms = None;
+                self.error = Some(err);
+            }
+        }
+    }
+}
+
+/// The builders known to this node, and their health.
+#[derive(Clone, Debug, Default)]
+pub struct BuilderRegistry {
+    builders: Arc<RwLock<BTreeMap<Url, BuilderInfo>>>,
+}
+
+impl BuilderRegistry {
+    /// A registry of the builders HotShot is using.
+    pub fn new(in_use: impl IntoIterator<Item = Url>) -> Self {
+        let builders = in_use
+            .into_iter()
+            .map(|url| {
+                (
+                    url.clone(),
+                    BuilderInfo::new(url, BuilderSource::Config, true),
+                )
+            })
+            .collect();
+        Self {
+            builders: Arc::new(RwLock::new(builders)),
+        }
+    }
+
+    /// Every known builder, ordered by URL.
+    pub async fn builders(&self) -> Vec<BuilderInfo> {
+        self.builders.read().await.values().cloned().collect()
+    }
+
+    /// Add builders listed by the registry which are not already known.
+    async fn discover(&self, urls: impl IntoIterator<Item = Url>, in_use: bool) {
+        let mut builders = self.builders.write().await;
+        for url in urls {
+            builders.entry(url.clone()).or_insert_with(|| {
+                if !in_use {
+                    tracing::info!(%url, "discovered builder, it will be used after a restart");
+                }
+                BuilderInfo::new(url, BuilderSource::Registry, in_use)
+            });
+        }
+    }
+
+    /// Refresh the registry and check the health of known builders every
+    /// `--builder-registry-interval`.
+    #[tracing::instrument(skip_all)]
+    pub(crate) async fn run(self, cfg: BuilderRegistryConfig) {
+        let client = reqwest::Client::new();
+        let mut ticker = interval(cfg.builder_registry_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+
+            if let Some(registry_url) = &cfg.builder_registry_url {
+                match fetch_registry(&client, registry_url, &cfg).await {
+                    Ok(discovered) => self.discover(discovered, false).await,
+                    Err(err) => {
+                        tracing::warn!(%registry_url, "failed to refresh builder registry: {err:#}")
+                    }
+                }
+            }
+
+            let urls = self
+                .builders
+                .read()
+                .await
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>();
+            for url in urls {
+                let result = check_health(&client, &url, &cfg)
+                    .await
+                    .map_err(|err| format!("{err:#}"));
+                if let Err(err) = &result {
+                    tracing::debug!(%url, "builder health check failed: {err}");
+                }
+                if let Some(info) = self.builders.write().await.get_mut(&url) {
+                    info.record_check(result);
+                }
+            }
+        }
+    }
+}
+
+/// Fetch the list of builders from the registry at `url`.
+async fn fetch_registry(
+    client: &reqwest::Client,
+    url: &Url,
+    cfg: &BuilderRegistryConfig,
+) -> anyhow::Result<Vec<Url>> {
+    let body = client
+        .get(url.clone())
+        .timeout(cfg.builder_health_timeout)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    serde_json::from_slice(&body).context("malformed builder registry")
+}
+
+/// Check the health of the builder at `url`, returning the response time.
+async fn check_health(
+    client: &reqwest::Client,
+    url: &Url,
+    cfg: &BuilderRegistryConfig,
+) -> anyhow::Result<Duration> {
+    let start = Instant::now();
+    client
+        .get(url.join(HEALTHCHECK_PATH)?)
+        .timeout(cfg.builder_health_timeout)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(start.elapsed())
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_discover_builders() {
+        let configured: Url = "http://builder-0:8080".parse().unwrap();
+        let discovered: Url = "http://builder-1:8080".parse().unwrap();
+        let registry = BuilderRegistry::new([configured.clone()]);
+
+        // Builders which are already known keep their source.
+        registry
+            .discover([configured.clone(), discovered.clone()], false)
+            .await;
+        let builders = registry.builders().await;
+        assert_eq!(builders.len(), 2);
+        assert_eq!(builders[0].url, configured);
+        assert_eq!(builders[0].source, BuilderSource::Config);
+        assert!(builders[0].in_use);
+        assert_eq!(builders[1].url, discovered);
+        assert_eq!(builders[1].source, BuilderSource::Registry);
+        assert!(!builders[1].in_use);
+        assert_eq!(builders[1].healthy, None);
+    }
+
+    #[test]
+    fn test_record_check() {
+        let mut info = BuilderInfo::new(
+            "http://builder:8080".parse().unwrap(),
+            BuilderSource::Config,
+            true,
+        );
+        info.record_check(Err("connection refused".into()));
+        assert_eq!(info.healthy, Some(false));
+        assert_eq!(info.error.as_deref(), Some("connection refused"));
+        assert!(info.last_checked.is_some());
+
+        info.record_check(Ok(Duration::from_millis(12)));
+        assert_eq!(info.healthy, Some(true));
+        assert_eq!(info.latency_ms, Some(12));
+        assert_eq!(info.error, None);
+    }
+}
@@ -1,4 +1,8 @@
-use std::sync::Arc;
+use std::{
+    cmp::Ordering,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::{bail, Context};
 use async_trait::async_trait;
@@ -17,6 +21,7 @@ use hotshot_types::{
 };
 use itertools::Itertools;
 use jf_merkle_tree::{prelude::MerkleNode, ForgetableMerkleTreeScheme, MerkleTreeScheme};
+use parking_lot::Mutex;
 use serde::de::DeserializeOwned;
 use std::collections::HashMap;
 use surf_disco::Request;
@@ -25,16 +30,92 @@ use url::Url;
 use vbs::version::StaticVersionType;
 
 use crate::{
-    api::{data_source::PublicNetworkConfig, BlocksFrontier},
+    api::{
+        data_source::{ChainConfigActivation, PublicNetworkConfig},
+        BlocksFrontier,
+    },
     PubKey,
 };
 
+/// Number of consecutive invalid proofs after which a peer is temporarily blacklisted.
+const INVALID_PROOF_THRESHOLD: u32 = 3;
+
+/// How long a peer is blacklisted for after repeatedly returning invalid proofs.
+const BLACKLIST_DURATION: Duration = Duration::from_secs(10 * 60);
+
+/// Weight given to the most recent sample in the moving average of peer latency.
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+/// Health statistics for a single catchup peer.
+#[derive(Debug, Default)]
+struct PeerScore {
+    /// Number of successful requests.
+    successes: u64,
+    /// Number of failed requests, including those which returned invalid data.
+    failures: u64,
+    /// Exponential moving average of the latency of successful requests, in seconds.
+    latency: Option<f64>,
+    /// Number of invalid proofs received from this peer since its last valid response.
+    invalid_proofs: u32,
+    /// If set, the peer should not be used until this time.
+    blacklisted_until: Option<Instant>,
+}
+
+impl PeerScore {
+    fn record_success(&mut self, latency: Duration) {
+        self.successes += 1;
+        self.invalid_proofs = 0;
+        let latency = latency.as_secs_f64();
+        self.latency = Some(match self.latency {
+            Some(avg) => avg + LATENCY_SMOOTHING * (latency - avg),
+            None => latency,
+        });
+    }
+
+    fn record_failure(&mut self) {
+        self.failures += 1;
+    }
+
+    /// Record an invalid response, returning `true` if this caused the peer to be blacklisted.
+    fn record_invalid_proof(&mut self, now: Instant) -> bool {
+        self.failures += 1;
+        self.invalid_proofs += 1;
+        if self.invalid_proofs < INVALID_PROOF_THRESHOLD {
+            return false;
+        }
+        self.invalid_proofs = 0;
+        self.blacklisted_until = Some(now + BLACKLIST_DURATION);
+        true
+    }
+
+    fn is_blacklisted(&self, now: Instant) -> bool {
+        self.blacklisted_until.is_some_and(|until| now < until)
+    }
+
+    /// Estimated fraction of requests to this peer which fail.
+    ///
+    /// Peers we have not heard from yet start with an estimate of 50%, which quickly converges to
+    /// the observed rate as requests are made.
+    fn error_rate(&self) -> f64 {
+        (self.failures as f64 + 1.) / ((self.successes + self.failures) as f64 + 2.)
+    }
+
+    /// Sort key for peers, where lower is better.
+    ///
+    /// Peers are ordered primarily by error rate. Latency breaks ties; peers with no latency
+    /// measurement yet are tried before slower peers, so that they get a chance to be measured.
+    fn rank(&self) -> (f64, f64) {
+        (self.error_rate(), self.latency.unwrap_or_default())
+    }
+}
+
 // This newtype is probably not worth having. It's only used to be able to log
 // URLs before doing requests.
 #[derive(Debug, Clone)]
 struct Client<ServerError, ApiVer: StaticVersionType> {
     inner: surf_disco::Client<ServerError, ApiVer>,
     url: Url,
+    score: Arc<Mutex<PeerScore>>,
 }
 
 impl<ApiVer: StaticVersionType> Client<ServerError, ApiVer> {
@@ -42,12 +123,30 @@ impl<ApiVer: StaticVersionType> Client<ServerError, ApiVer> {
         Self {
             inner: surf_disco::Client::new(url.clone()),
             url,
+            score: Default::default(),
         }
     }
 
     pub fn get<T: DeserializeOwned>(&self, route: &str) -> Request<T, ServerError, ApiVer> {
         self.inner.get(route)
     }
+
+    fn record_success(&self, start: Instant) {
+        self.score.lock().record_success(start.elapsed());
+    }
+
+    fn record_failure(&self) {
+        self.score.lock().record_failure();
+    }
+
+    fn record_invalid_proof(&self) {
+        if self.score.lock().record_invalid_proof(Instant::now()) {
+            tracing::warn!(
+                peer = %self.url,
+                "blacklisting peer for {BLACKLIST_DURATION:?} after repeated invalid proofs"
+            );
+        }
+    }
 }
 
 /// A catchup implementation that falls back to a remote provider, but prefers a local provider when
@@ -83,6 +182,27 @@ impl<ApiVer: StaticVersionType> StatePeers<ApiVer> {
         }
     }
 
+    /// The peers to try for a request, healthiest first.
+    ///
+    /// Blacklisted peers are excluded, unless every peer is blacklisted, in which case we try all
+    /// of them rather than failing outright.
+    fn ranked_clients(&self) -> Vec<&Client<ServerError, ApiVer>> {
+        let now = Instant::now();
+        let mut ranked = self
+            .clients
+            .iter()
+            .map(|client| {
+                let score = client.score.lock();
+                (client, score.is_blacklisted(now), score.rank())
+            })
+            .collect::<Vec<_>>();
+        if ranked.iter().any(|(_, blacklisted, _)| !blacklisted) {
+            ranked.retain(|(_, blacklisted, _)| !blacklisted);
+        }
+        ranked.sort_by(|(_, _, a), (_, _, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        ranked.into_iter().map(|(client, _, _)| client).collect()
+    }
+
     pub async fn fetch_config(
         &self,
         my_own_validator_config: ValidatorConfig<PubKey>,
@@ -91,19 +211,22 @@ impl<ApiVer: StaticVersionType> StatePeers<ApiVer> {
             .retry(self, move |provider| {
                 let my_own_validator_config = my_own_validator_config.clone();
                 async move {
-                    for client in &provider.clients {
+                    for client in provider.ranked_clients() {
                         tracing::info!("fetching config from {}", client.url);
+                        let start = Instant::now();
                         match client
                             .get::<PublicNetworkConfig>("config/hotshot")
                             .send()
                             .await
                         {
                             Ok(res) => {
+                                client.record_success(start);
                                 return res.into_network_config(my_own_validator_config)
                                     .context(format!("fetched config from {}, but failed to convert to private config", client.url));
                             }
                             Err(err) => {
                                 tracing::warn!("error fetching config from peer: {err:#}");
+                                client.record_failure();
                             }
                         }
                     }
@@ -126,8 +249,9 @@ impl<ApiVer: StaticVersionType> StateCatchup for StatePeers<ApiVer> {
         fee_merkle_tree_root: FeeMerkleCommitment,
         accounts: &[FeeAccount],
     ) -> anyhow::Result<FeeMerkleTree> {
-        for client in self.clients.iter() {
+        'peers: for client in self.ranked_clients() {
             tracing::info!("Fetching accounts from {}", client.url);
+            let start = Instant::now();
             let req = match client
                 .inner
                 .post::<FeeMerkleTree>(&format!("catchup/{height}/{}/accounts", view.u64(),))
@@ -143,6 +267,7 @@ impl<ApiVer: StaticVersionType> StateCatchup for StatePeers<ApiVer> {
                 Ok(res) => res,
                 Err(err) => {
                     tracing::info!(peer = %client.url, "error fetching accounts from peer: {err:#}");
+                    client.record_failure();
                     continue;
                 }
             };
@@ -151,14 +276,17 @@ impl<ApiVer: StaticVersionType> StateCatchup for StatePeers<ApiVer> {
             for account in accounts {
                 let Some((proof, _)) = FeeAccountProof::prove(&snapshot, (*account).into()) else {
                     tracing::warn!(peer = %client.url, "response from peer missing account {account}");
-                    continue;
+                    client.record_invalid_proof();
+                    continue 'peers;
                 };
                 if let Err(err) = proof.verify(&fee_merkle_tree_root) {
                     tracing::warn!(peer = %client.url, "peer gave invalid proof for account {account}: {err:#}");
-                    continue;
+                    client.record_invalid_proof();
+                    continue 'peers;
                 }
             }
 
+            client.record_success(start);
             return Ok(snapshot);
         }
         bail!("Could not fetch account from any peer");
@@ -172,8 +300,9 @@ impl<ApiVer: StaticVersionType> StateCatchup for StatePeers<ApiVer> {
         view: ViewNumber,
         mt: &mut BlockMerkleTree,
     ) -> anyhow::Result<()> {
-        for client in self.clients.iter() {
+        for client in self.ranked_clients() {
             tracing::debug!(peer = %client.url, "fetching frontier from peer");
+            let start = Instant::now();
             match client
                 .get::<BlocksFrontier>(&format!("catchup/{height}/{}/blocks", view.u64()))
                 .send()
@@ -182,18 +311,24 @@ impl<ApiVer: StaticVersionType> StateCatchup for StatePeers<ApiVer> {
                 Ok(frontier) => {
                     let Some(elem) = frontier.elem() else {
                         tracing::warn!(peer = %client.url, "Provided frontier is missing leaf element");
+                        client.record_invalid_proof();
                         continue;
                     };
                     match mt.remember(mt.num_leaves() - 1, *elem, &frontier) {
-                        Ok(_) => return Ok(()),
+                        Ok(_) => {
+                            client.record_success(start);
+                            return Ok(());
+                        }
                         Err(err) => {
                             tracing::warn!(peer = %client.url, "Error verifying block proof: {err:#}");
+                            client.record_invalid_proof();
                             continue;
                         }
                     }
                 }
                 Err(err) => {
                     tracing::info!(peer = %client.url, "error fetching blocks from peer: {err:#}");
+                    client.record_failure();
                 }
             }
         }
@@ -204,8 +339,9 @@ impl<ApiVer: StaticVersionType> StateCatchup for StatePeers<ApiVer> {
         &self,
         commitment: Commitment<ChainConfig>,
     ) -> anyhow::Result<ChainConfig> {
-        for client in self.clients.iter() {
+        for client in self.ranked_clients() {
             tracing::info!("Fetching chain config from {}", client.url);
+            let start = Instant::now();
             match client
                 .get::<ChainConfig>(&format!("catchup/chain-config/{}", commitment))
                 .send()
@@ -213,8 +349,10 @@ impl<ApiVer: StaticVersionType> StateCatchup for StatePeers<ApiVer> {
             {
                 Ok(cf) => {
                     if cf.commit() == commitment {
+                        client.record_success(start);
                         return Ok(cf);
                     } else {
+                        client.record_invalid_proof();
                         tracing::error!(
                             "Received chain config with mismatched commitment from {}: expected {}, got {}",
                             client.url,
@@ -225,6 +363,7 @@ impl<ApiVer: StaticVersionType> StateCatchup for StatePeers<ApiVer> {
                 }
                 Err(err) => {
                     tracing::warn!("Error fetching chain config from peer: {}", err);
+                    client.record_failure();
                 }
             }
         }
@@ -273,6 +412,21 @@ pub(crate) trait CatchupStorage: Sync {
         }
     }
 
+    /// Get the state of the requested `accounts` as of the decided block at `height`.
+    ///
+    /// Unlike [`get_accounts`](Self::get_accounts), which is intended for catchup near the head of
+    /// the chain, `height` may refer to any historical block whose fee state is still retained in
+    /// storage. Returns the requested accounts along with the leaf at `height`.
+    fn get_historical_accounts(
+        &self,
+        _height: u64,
+        _accounts: &[FeeAccount],
+    ) -> impl Send + Future<Output = anyhow::Result<(FeeMerkleTree, Leaf)>> {
+        async {
+            bail!("historical fee state is not supported for this data source");
+        }
+    }
+
     /// Get the blocks Merkle tree frontier.
     ///
     /// The state is fetched from a snapshot at the given height and view, which _must_ correspond!
@@ -302,6 +456,15 @@ pub(crate) trait CatchupStorage: Sync {
             bail!("chain config catchup is not supported for this data source");
         }
     }
+
+    /// Get every chain config that has been active, ordered by activation height.
+    fn get_chain_config_history(
+        &self,
+    ) -> impl Send + Future<Output = anyhow::Result<Vec<ChainConfigActivation>>> {
+        async {
+            bail!("chain config history is not supported for this data source");
+        }
+    }
 }
 
 impl CatchupStorage for hotshot_query_service::data_source::MetricsDataSource {}
@@ -332,12 +495,24 @@ where
         self.inner().get_frontier(instance, height, view).await
     }
 
+    async fn get_historical_accounts(
+        &self,
+        height: u64,
+        accounts: &[FeeAccount],
+    ) -> anyhow::Result<(FeeMerkleTree, Leaf)> {
+        self.inner().get_historical_accounts(height, accounts).await
+    }
+
     async fn get_chain_config(
         &self,
         commitment: Commitment<ChainConfig>,
     ) -> anyhow::Result<ChainConfig> {
         self.inner().get_chain_config(commitment).await
     }
+
+    async fn get_chain_config_history(&self) -> anyhow::Result<Vec<ChainConfigActivation>> {
+        self.inner().get_chain_config_history().await
+    }
 }
 
 #[derive(Debug)]
@@ -499,3 +674,64 @@ impl StateCatchup for NullStateCatchup {
         "NullStateCatchup".into()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_peer_score_ranking() {
+        let mut healthy = PeerScore::default();
+        let mut flaky = PeerScore::default();
+        let fresh = PeerScore::default();
+
+        for _ in 0..5 {
+            healthy.record_success(Duration::from_millis(100));
+            flaky.record_success(Duration::from_millis(10));
+            flaky.record_failure();
+        }
+
+        // Error rate dominates latency.
+        assert!(healthy.rank() < flaky.rank());
+        // A peer that has never failed is preferred to an unknown peer.
+        assert!(healthy.rank() < fresh.rank());
+        // An unknown peer is preferred to one which fails half the time or more.
+        assert!(fresh.rank() <= flaky.rank());
+    }
+
+    #[test]
+    fn test_peer_score_latency() {
+        let mut fast = PeerScore::default();
+        let mut slow = PeerScore::default();
+        fast.record_success(Duration::from_millis(10));
+        slow.record_success(Duration::from_millis(500));
+        assert!(fast.rank() < slow.rank());
+
+        // The moving average adapts to a change in latency.
+        for _ in 0..50 {
+            slow.record_success(Duration::from_millis(10));
+        }
+        assert!((slow.latency.unwrap() - 0.01).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_peer_score_blacklist() {
+        let now = Instant::now();
+        let mut score = PeerScore::default();
+
+        for _ in 1..INVALID_PROOF_THRESHOLD {
+            assert!(!score.record_invalid_proof(now));
+        }
+        assert!(!score.is_blacklisted(now));
+
+        // A valid response resets the count of consecutive invalid proofs.
+        score.record_success(Duration::from_millis(10));
+        for _ in 1..INVALID_PROOF_THRESHOLD {
+            assert!(!score.record_invalid_proof(now));
+        }
+        assert!(score.record_invalid_proof(now));
+        assert!(score.is_blacklisted(now));
+        assert!(score.is_blacklisted(now + BLACKLIST_DURATION / 2));
+        assert!(!score.is_blacklisted(now + BLACKLIST_DURATION));
+    }
+}
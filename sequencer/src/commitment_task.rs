@@ -0,0 +1,552 @@
+//! Posting block commitments to L1.
+//!
+//! When configured with the address of a `BlockCommitments` contract, a node runs a commitment
+//! task which records the commitment of every decided block on L1. Commitments are collected from
+//! decide events and posted in batches, each of which must start exactly at the height the
+//! contract has recorded so far. Blocks decided before the task started, or lost to an L1 reorg
+//! deeper than the configured number of confirmations, are fetched from a query service.
+//!
+//! Posting is driven by the contract's state rather than by which transactions were sent: each
+//! round reads the contract's height and posts the next batch from there. This makes the task
+//! robust to restarts, to other posters, and to L1 reorgs, which simply cause the height to go
+//! back and the lost batches to be posted again. Commitments are only forgotten once the batch
+//! containing them has enough confirmations.
+//!
+//! Transactions use the L1 gas price, up to a configurable maximum. If a transaction is not mined
+//! in time, the next round replaces it, reusing its nonce with a higher gas price. Progress is
+//! reported via metrics in the `commitment_task` group.
+
+use std::{collections::VecDeque, ops::Range, sync::Arc, time::Duration};
+
+use anyhow::{bail, ensure, Context};
+use clap::Parser;
+use committable::Committable;
+use espresso_types::{eth_signature_key::EthKeyPair, parse_duration, Header, SeqTypes};
+use ethers::{
+    contract::abigen,
+    middleware::SignerMiddleware,
+    providers::{Http, Middleware, Provider},
+    signers::LocalWallet,
+    types::{Address, BlockId, BlockNumber, U256},
+    utils::parse_units,
+};
+use futures::{future, stream::Stream, StreamExt};
+use hotshot::types::{Event, EventType};
+use hotshot_types::{
+    event::LeafInfo,
+    traits::metrics::{Counter, Gauge, Metrics},
+};
+use parking_lot::Mutex;
+use surf_disco::Client;
+use tide_disco::error::ServerError;
+use tokio::time::{sleep, timeout};
+use url::Url;
+
+use crate::SequencerApiVersion;
+
+abigen!(
+    BlockCommitments,
+    r#"[
+        function blockHeight() external view returns (uint256)
+        function newBlocks(uint256 firstBlockNumber, bytes32[] blockCommitments) external
+    ]"#
+);
+
+type L1 = SignerMiddleware<Provider<Http>, LocalWallet>;
+
+#[derive(Clone, Debug, Parser)]
+pub struct CommitmentTaskConfig {
+    /// Address of the `BlockCommitments` contract on L1.
+    ///
+    /// If set, this node posts the commitment of each decided block to the contract.
+    #[clap(
+        long = "commitment-task-contract",
+        env = "ESPRESSO_SEQUENCER_COMMITMENT_TASK_CONTRACT"
+    )]
+    pub contract: Option<Address>,
+
+    /// URL of the L1 JSON-RPC provider used to post commitments.
+    ///
+    /// Defaults to the first L1 provider of the node.
+    #[clap(
+        long = "commitment-task-l1-provider",
+        env = "ESPRESSO_SEQUENCER_COMMITMENT_TASK_L1_PROVIDER"
+    )]
+    pub l1_provider: Option<Url>,
+
+    /// Mnemonic for the L1 account which posts commitments.
+    #[clap(
+        long = "commitment-task-mnemonic",
+        env = "ESPRESSO_SEQUENCER_COMMITMENT_TASK_MNEMONIC"
+    )]
+    pub mnemonic: Option<String>,
+
+    /// Index of the posting account derived from the mnemonic.
+    #[clap(
+        long = "commitment-task-account-index",
+        env = "ESPRESSO_SEQUENCER_COMMITMENT_TASK_ACCOUNT_INDEX",
+        default_value = "0"
+    )]
+    pub account_index: u32,
+
+    /// Query service used to fetch the headers of blocks which were decided while the task was not
+    /// running.
+    #[clap(
+        long = "commitment-task-query-service",
+        env = "ESPRESSO_SEQUENCER_COMMITMENT_TASK_QUERY_SERVICE"
+    )]
+    pub query_service: Option<Url>,
+
+    /// Maximum number of commitments to post in a single transaction.
+    #[clap(
+        long = "commitment-task-max-batch-size",
+        env = "ESPRESSO_SEQUENCER_COMMITMENT_TASK_MAX_BATCH_SIZE",
+        default_value = "100"
+    )]
+    pub max_batch_size: usize,
+
+    /// How often to post a batch of commitments.
+    #[clap(
+        long = "commitment-task-interval",
+        env = "ESPRESSO_SEQUENCER_COMMITMENT_TASK_INTERVAL",
+        default_value = "30s",
+        value_parser = parse_duration,
+    )]
+    pub interval: Duration,
+
+    /// Maximum gas price to pay for posting commitments, in gwei.
+    ///
+    /// While the L1 gas price is higher, posting is paused.
+    #[clap(
+        long = "commitment-task-max-gas-price",
+        env = "ESPRESSO_SEQUENCER_COMMITMENT_TASK_MAX_GAS_PRICE"
+    )]
+    pub max_gas_price: Option<u64>,
+
+    /// Percentage by which to raise the gas price when replacing a transaction which was not mined.
+    #[clap(
+        long = "commitment-task-gas-price-bump",
+        env = "ESPRESSO_SEQUENCER_COMMITMENT_TASK_GAS_PRICE_BUMP",
+        default_value = "20"
+    )]
+    pub gas_price_bump: u64,
+
+    /// How long to wait for a transaction to be mined before replacing it.
+    #[clap(
+        long = "commitment-task-tx-timeout",
+        env = "ESPRESSO_SEQUENCER_COMMITMENT_TASK_TX_TIMEOUT",
+        default_value = "2m",
+        value_parser = parse_duration,
+    )]
+    pub tx_timeout: Duration,
+
+    /// Number of L1 blocks after which posted commitments are considered safe from reorgs.
+    #[clap(
+        long = "commitment-task-confirmations",
+        env = "ESPRESSO_SEQUENCER_COMMITMENT_TASK_CONFIRMATIONS",
+        default_value = "3"
+    )]
+    pub confirmations: u64,
+}
+
+impl Default for CommitmentTaskConfig {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+impl CommitmentTaskConfig {
+    /// Connect to L1 and create the commitment task, if it is enabled.
+    pub(crate) async fn task(
+        &self,
+        metrics: &dyn Metrics,
+    ) -> anyhow::Result<Option<CommitmentTask>> {
+        let Some(address) = self.contract else {
+            return Ok(None);
+        };
+        ensure!(
+            self.max_batch_size > 0,
+            "commitment task batch size must be positive"
+        );
+        let url = self
+            .l1_provider
+            .as_ref()
+            .context("commitment task requires an L1 provider")?;
+        let mnemonic = self
+            .mnemonic
+            .as_ref()
+            .context("commitment task requires a mnemonic")?;
+        let key_pair = EthKeyPair::from_mnemonic(mnemonic, self.account_index)
+            .context("deriving commitment task account")?;
+        let max_gas_price = self
+            .max_gas_price
+            .map(|gwei| parse_units(gwei, "gwei").map(U256::from))
+            .transpose()
+            .context("invalid maximum gas price")?;
+
+        let provider = Provider::try_from(url.to_string())?;
+        let l1 = Arc::new(
+            SignerMiddleware::new_with_provider_chain(provider, key_pair.signer())
+                .await
+                .context("connecting to L1")?,
+        );
+        tracing::info!(%address, poster = %l1.address(), "posting block commitments to L1");
+
+        Ok(Some(CommitmentTask {
+            contract: BlockCommitments::new(address, l1.clone()),
+            l1,
+            query_service: self
+                .query_service
+                .clone()
+                .map(Client::<ServerError, SequencerApiVersion>::new),
+            max_gas_price,
+            cfg: self.clone(),
+            pending: Default::default(),
+            metrics: CommitmentTaskMetrics::new(metrics),
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct CommitmentTaskMetrics {
+    /// Height of the chain, according to decide events.
+    decided_height: Box<dyn Gauge>,
+    /// Number of blocks whose commitments are recorded in the contract.
+    committed_height: Box<dyn Gauge>,
+    /// Number of decided blocks whose commitments are not yet recorded in the contract.
+    lag: Box<dyn Gauge>,
+    /// Number of transactions sent, including replacements.
+    transactions: Box<dyn Counter>,
+    /// Number of rounds which failed to post a batch.
+    failures: Box<dyn Counter>,
+    /// Number of times the contract's height went back due to an L1 reorg.
+    reorgs: Box<dyn Counter>,
+}
+
+impl CommitmentTaskMetrics {
+    fn new(metrics: &dyn Metrics) -> Self {
+        let metrics = metrics.subgroup("commitment_task".into());
+        Self {
+            decided_height: metrics.create_gauge("decided_height".into(), None),
+            committed_height: metrics.create_gauge("committed_height".into(), None),
+            lag: metrics.create_gauge("lag".into(), Some("blocks".into())),
+            transactions: metrics.create_counter("transactions".into(), None),
+            failures: metrics.create_counter("failures".into(), None),
+            reorgs: metrics.create_counter("reorgs".into(), None),
+        }
+    }
+}
+
+/// Commitments of decided blocks which may not be safely recorded on L1 yet.
+#[derive(Debug, Default)]
+struct PendingCommitments {
+    /// Height of the first block in `commitments`.
+    start: u64,
+    /// Commitments of consecutive blocks, starting at `start`.
+    commitments: VecDeque<[u8; 32]>,
+}
+
+impl PendingCommitments {
+    /// The height of the chain, as far as we know.
+    fn end(&self) -> u64 {
+        self.start + self.commitments.len() as u64
+    }
+
+    fn push(&mut self, height: u64, commitment: [u8; 32]) {
+        if self.commitments.is_empty() {
+            self.start = height;
+        } else if height < self.end() {
+            // Already have this one.
+            return;
+        } else if height > self.end() {
+            // Decide events are contiguous, so this should never happen. If it does, start over;
+            // the missing blocks will be fetched from the query service.
+            tracing::warn!(height, expected = self.end(), "gap in decided blocks");
+            self.commitments.clear();
+            self.start = height;
+        }
+        self.commitments.push_back(commitment);
+    }
+
+    /// Forget commitments below `height`, which are safely recorded on L1.
+    fn prune(&mut self, height: u64) {
+        let n = height
+            .saturating_sub(self.start)
+            .min(self.commitments.len() as u64);
+        self.commitments.drain(..n as usize);
+        self.start = self.start.max(height);
+    }
+
+    /// Blocks from `height` which are decided but whose commitments we no longer have.
+    fn missing(&self, height: u64) -> Range<u64> {
+        if self.commitments.is_empty() {
+            height..height
+        } else {
+            height..self.start.max(height)
+        }
+    }
+
+    /// Up to `max` commitments starting at `height`, if we have them.
+    fn batch(&self, height: u64, max: usize) -> Vec<[u8; 32]> {
+        if height < self.start {
+            return vec![];
+        }
+        self.commitments
+            .iter()
+            .skip((height - self.start) as usize)
+            .take(max)
+            .copied()
+            .collect()
+    }
+}
+
+/// Posts the commitments of decided blocks to L1.
+#[derive(Debug)]
+pub(crate) struct CommitmentTask {
+    cfg: CommitmentTaskConfig,
+    l1: Arc<L1>,
+    contract: BlockCommitments<L1>,
+    query_service: Option<Client<ServerError, SequencerApiVersion>>,
+    max_gas_price: Option<U256>,
+    pending: Mutex<PendingCommitments>,
+    metrics: CommitmentTaskMetrics,
+}
+
+/// What the task remembers about L1 between rounds.
+#[derive(Debug, Default)]
+struct L1State {
+    /// Height of the contract the last time we read it.
+    height: Option<u64>,
+    /// Nonce and gas price of the last transaction we sent.
+    last_tx: Option<(U256, U256)>,
+}
+
+impl CommitmentTask {
+    /// Collect commitments from `events` and post them to L1 until the event stream ends.
+    pub(crate) async fn run(self, mut events: impl Stream<Item = Event<SeqTypes>> + Unpin) {
+        let collect = async {
+            while let Some(event) = events.next().await {
+                self.handle_event(&event);
+            }
+            tracing::warn!("event stream ended, commitment task exiting");
+        };
+        let post = async {
+            let mut state = L1State::default();
+            loop {
+                sleep(self.cfg.interval).await;
+                if let Err(err) = self.post(&mut state).await {
+                    tracing::warn!("failed to post block commitments: {err:#}");
+                    self.metrics.failures.add(1);
+                }
+            }
+        };
+        future::select(Box::pin(collect), Box::pin(post)).await;
+    }
+
+    fn handle_event(&self, event: &Event<SeqTypes>) {
+        let EventType::Decide { leaf_chain, .. } = &event.event else {
+            return;
+        };
+        let mut pending = self.pending.lock();
+        // Leaves are ordered newest first.
+        for LeafInfo { leaf, .. } in leaf_chain.iter().rev() {
+            let header = leaf.block_header();
+            pending.push(header.height(), header.commit().into());
+        }
+        self.metrics.decided_height.set(pending.end() as usize);
+    }
+
+    /// Run one round: post the next batch of commitments after the contract's current height.
+    async fn post(&self, state: &mut L1State) -> anyhow::Result<()> {
+        let l1_height = self.l1.get_block_number().await?.as_u64();
+        let height = self.contract_height(BlockNumber::Latest.into()).await?;
+        if let Some(prev) = state.height {
+            if height < prev {
+                tracing::warn!(height, prev, "contract height went back, L1 reorg detected");
+                self.metrics.reorgs.add(1);
+            }
+        }
+        state.height = Some(height);
+        self.metrics.committed_height.set(height as usize);
+
+        // Commitments which are deep enough in L1 are safe from reorgs, and can be forgotten.
+        let confirmed = self
+            .contract_height(l1_height.saturating_sub(self.cfg.confirmations).into())
+            .await?;
+        let batch = {
+            let mut pending = self.pending.lock();
+            pending.prune(confirmed);
+            self.metrics
+                .lag
+                .set(pending.end().saturating_sub(height) as usize);
+            let missing = pending.missing(height);
+            if missing.is_empty() {
+                Ok(pending.batch(height, self.cfg.max_batch_size))
+            } else {
+                Err(missing)
+            }
+        };
+        let batch = match batch {
+            Ok(batch) => batch,
+            Err(missing) => {
+                let end = missing
+                    .end
+                    .min(missing.start + self.cfg.max_batch_size as u64);
+                self.fetch(missing.start..end).await?
+            }
+        };
+        if batch.is_empty() {
+            tracing::debug!(height, "no new block commitments to post");
+            return Ok(());
+        }
+        self.send(state, height, batch).await
+    }
+
+    /// The number of blocks recorded in the contract as of the L1 block `block`.
+    async fn contract_height(&self, block: BlockId) -> anyhow::Result<u64> {
+        let height = self
+            .contract
+            .block_height()
+            .block(block)
+            .call()
+            .await
+            .context("reading contract height")?;
+        Ok(height.as_u64())
+    }
+
+    /// Fetch the commitments of `blocks` from the query service.
+    async fn fetch(&self, blocks: Range<u64>) -> anyhow::Result<Vec<[u8; 32]>> {
+        let Some(client) = &self.query_service else {
+            bail!(
+                "commitments of blocks {blocks:?} are missing, and no query service is configured"
+            );
+        };
+        tracing::info!(?blocks, "fetching missing headers");
+        let mut commitments = vec![];
+        for height in blocks {
+            let header: Header = client
+                .get(&format!("availability/header/{height}"))
+                .send()
+                .await
+                .context(format!("fetching header {height}"))?;
+            commitments.push(header.commit().into());
+        }
+        Ok(commitments)
+    }
+
+    /// Send a transaction posting `batch` starting at `height`, and wait for it to be mined.
+    async fn send(
+        &self,
+        state: &mut L1State,
+        height: u64,
+        batch: Vec<[u8; 32]>,
+    ) -> anyhow::Result<()> {
+        let nonce = self
+            .l1
+            .get_transaction_count(self.l1.address(), Some(BlockNumber::Latest.into()))
+            .await
+            .context("getting nonce")?;
+        let mut gas_price = self.l1.get_gas_price().await.context("getting gas price")?;
+        if let Some((last_nonce, last_gas_price)) = state.last_tx {
+            if last_nonce == nonce {
+                // Our last transaction was not mined. Replace it, which requires a higher gas price.
+                let bumped = last_gas_price * (100 + self.cfg.gas_price_bump) / 100;
+                gas_price = gas_price.max(bumped);
+            }
+        }
+        if let Some(max) = self.max_gas_price {
+            ensure!(
+                gas_price <= max,
+                "gas price {gas_price} exceeds maximum {max}, not posting"
+            );
+        }
+
+        let num_blocks = batch.len();
+        let call = self
+            .contract
+            .new_blocks(height.into(), batch)
+            .legacy()
+            .nonce(nonce)
+            .gas_price(gas_price);
+        let tx = call.send().await.context("sending transaction")?;
+        let hash = tx.tx_hash();
+        state.last_tx = Some((nonce, gas_price));
+        self.metrics.transactions.add(1);
+        tracing::info!(height, num_blocks, %nonce, %gas_price, ?hash, "posted block commitments");
+
+        let receipt = match timeout(self.cfg.tx_timeout, tx).await {
+            Ok(res) => res
+                .context(format!("waiting for transaction {hash:?}"))?
+                .context(format!("transaction {hash:?} dropped from mempool"))?,
+            Err(_) => bail!(
+                "transaction {hash:?} not mined after {:?}, will replace it",
+                self.cfg.tx_timeout
+            ),
+        };
+        state.last_tx = None;
+        if receipt.status != Some(1.into()) {
+            bail!("transaction {hash:?} reverted");
+        }
+        tracing::info!(
+            height,
+            num_blocks,
+            block = ?receipt.block_number,
+            "block commitments recorded"
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn comm(height: u64) -> [u8; 32] {
+        let mut comm = [0; 32];
+        comm[..8].copy_from_slice(&height.to_le_bytes());
+        comm
+    }
+
+    #[test]
+    fn test_pending_commitments() {
+        let mut pending = PendingCommitments::default();
+        assert_eq!(pending.batch(0, 10), Vec::<[u8; 32]>::new());
+        assert!(pending.missing(0).is_empty());
+
+        // The task starts after blocks 0..5 were decided.
+        for height in 5..10 {
+            pending.push(height, comm(height));
+        }
+        // Duplicates are ignored.
+        pending.push(7, comm(100));
+        assert_eq!(pending.end(), 10);
+
+        // If the contract is behind, the earlier blocks must be fetched.
+        assert_eq!(pending.missing(3), 3..5);
+        assert_eq!(pending.batch(3, 10), Vec::<[u8; 32]>::new());
+
+        // Once it catches up, batches come from the pending commitments.
+        assert!(pending.missing(5).is_empty());
+        assert_eq!(pending.batch(5, 2), vec![comm(5), comm(6)]);
+        assert_eq!(pending.batch(8, 10), vec![comm(8), comm(9)]);
+        assert_eq!(pending.batch(10, 10), Vec::<[u8; 32]>::new());
+
+        // Confirmed commitments are forgotten...
+        pending.prune(7);
+        assert_eq!(pending.start, 7);
+        assert_eq!(pending.batch(7, 10), vec![comm(7), comm(8), comm(9)]);
+        // ...so if a deep reorg takes the contract below them, they must be fetched again.
+        assert_eq!(pending.missing(6), 6..7);
+
+        // Pruning past the end keeps track of where the next block goes.
+        pending.prune(12);
+        assert_eq!(pending.end(), 12);
+        pending.push(12, comm(12));
+        assert_eq!(pending.batch(12, 10), vec![comm(12)]);
+
+        // A gap in decided blocks starts over from the new block.
+        pending.push(15, comm(15));
+        assert_eq!(pending.start, 15);
+        assert_eq!(pending.missing(13), 13..15);
+    }
+}
@@ -1,4 +1,4 @@
-use std::{fmt::Display, sync::Arc};
+use std::{collections::HashSet, fmt::Display, sync::Arc};
 
 use anyhow::Context;
 use async_broadcast::{broadcast, Receiver, Sender};
@@ -9,7 +9,8 @@ use derivative::Derivative;
 use espresso_types::{
     parse_duration,
     v0::traits::{EventConsumer as PersistenceEventConsumer, SequencerPersistence},
-    NodeState, PubKey, Transaction, ValidatedState,
+    EpochStakeTable, NodeState, PubKey, ShutdownCheckpoint, SolverAuctionResultsProvider,
+    StakeTableNode, Transaction, ValidatedState,
 };
 use futures::{
     future::{join_all, Future},
@@ -34,6 +35,7 @@ use hotshot_types::{
     data::{EpochNumber, ViewNumber},
     network::NetworkConfig,
     traits::{
+        election::Membership,
         metrics::Metrics,
         network::ConnectedNetwork,
         node_implementation::{ConsensusTime, NodeType, Versions},
@@ -47,9 +49,31 @@ use tracing::{Instrument, Level};
 use url::Url;
 
 use crate::{
+    builder_fees::BuilderFeeLedger,
+    builder_registry::{BuilderRegistry, BuilderRegistryConfig},
+    commitment_task::CommitmentTaskConfig,
+    da_mirror::DaMirrorConfig,
+    decide_log::DecideEventLog,
     external_event_handler::{self, ExternalEventHandler},
-    state_signature::StateSigner,
-    static_stake_table_commitment, Node, SeqTypes, SequencerApiVersion,
+    mempool::{Mempool, MempoolConfig},
+    network::{
+        health::NetworkHealth,
+        misbehavior::{persist_bans, MisbehaviorConfig, MisbehaviorTracker},
+    },
+    shutdown::Shutdown,
+    state_signature::{
+        aggregator::StateSignatureAggregator, signer::RemoteSignerConfig, StateSigner,
+    },
+    state_sync::StateSyncClient,
+    static_stake_table_commitment,
+    supervisor::{supervise, RestartPolicy, TaskHealth, TaskStatus},
+    tx_trace::{TxTraceConfig, TxTracer},
+    upgrade::UpgradeManager,
+    view_log::record_views,
+    view_timeout::{adapt_view_timeout, AdaptiveViewTimeout, ViewTimeoutConfig},
+    watchdog::{watch_health, RecoveryRequests, WatchdogAction, WatchdogConfig},
+    webhook::{WebhookConfig, WebhookDispatcher},
+    Node, SeqTypes, SequencerApiVersion,
 };
 
 /// The consensus handle
@@ -78,6 +102,17 @@ pub struct ProposalFetcherConfig {
         value_parser = parse_duration,
     )]
     pub fetch_timeout: Duration,
+
+    /// How many views ahead to look for views in which this node is leader.
+    ///
+    /// Before each such view, the proposal the node will extend is fetched from peers if it is
+    /// missing, so that a node which restarts shortly before its turn as leader can still propose.
+    #[clap(
+        long = "proposal-fetcher-leader-lookahead",
+        env = "ESPRESSO_SEQUENCER_PROPOSAL_FETCHER_LEADER_LOOKAHEAD",
+        default_value = "5"
+    )]
+    pub leader_lookahead: u64,
 }
 
 impl Default for ProposalFetcherConfig {
@@ -86,6 +121,43 @@ impl Default for ProposalFetcherConfig {
     }
 }
 
+/// Configuration for the tasks a node runs alongside consensus.
+#[derive(Clone, Debug, Default, Parser)]
+pub struct NodeConfig {
+    #[clap(flatten)]
+    pub proposal_fetcher: ProposalFetcherConfig,
+
+    #[clap(flatten)]
+    pub mempool: MempoolConfig,
+
+    #[clap(flatten)]
+    pub webhook: WebhookConfig,
+
+    #[clap(flatten)]
+    pub view_timeout: ViewTimeoutConfig,
+
+    #[clap(flatten)]
+    pub remote_signer: RemoteSignerConfig,
+
+    #[clap(flatten)]
+    pub commitment_task: CommitmentTaskConfig,
+
+    #[clap(flatten)]
+    pub misbehavior: MisbehaviorConfig,
+
+    #[clap(flatten)]
+    pub da_mirror: DaMirrorConfig,
+
+    #[clap(flatten)]
+    pub tx_trace: TxTraceConfig,
+
+    #[clap(flatten)]
+    pub watchdog: WatchdogConfig,
+
+    #[clap(flatten)]
+    pub builder_registry: BuilderRegistryConfig,
+}
+
 /// The sequencer context contains a consensus handle and other sequencer specific information.
 #[derive(Derivative, Clone)]
 #[derivative(Debug(bound = ""))]
@@ -94,16 +166,50 @@ pub struct SequencerContext<N: ConnectedNetwork<PubKey>, P: SequencerPersistence
     #[derivative(Debug = "ignore")]
     handle: Arc<RwLock<Consensus<N, P, V>>>,
 
+    /// Persistent storage for consensus and node-local data.
+    #[derivative(Debug = "ignore")]
+    persistence: Arc<P>,
+
     /// Context for generating state signatures.
     state_signer: Arc<StateSigner<SequencerApiVersion>>,
 
+    /// Pending transactions submitted through this node or gossiped by peers.
+    #[derivative(Debug = "ignore")]
+    mempool: Arc<Mempool<P>>,
+
+    /// Progress of scheduled protocol upgrades.
+    #[derivative(Debug = "ignore")]
+    upgrades: Arc<UpgradeManager<P>>,
+
+    /// Fees charged to builders by proposed and decided blocks.
+    #[derivative(Debug = "ignore")]
+    builder_fees: Arc<BuilderFeeLedger<P>>,
+
+    /// Traces of transactions submitted through this node.
+    tx_tracer: Arc<TxTracer>,
+
+    /// The builders known to this node, and their health.
+    builder_registry: BuilderRegistry,
+
+    /// The marketplace solver, which runs namespace sequencing auctions.
+    solver: Arc<SolverAuctionResultsProvider>,
+
     /// An orchestrator to wait for before starting consensus.
     #[derivative(Debug = "ignore")]
     wait_for_orchestrator: Option<Arc<OrchestratorClient>>,
 
+    /// Health of the transports of the production network, if this node uses it.
+    network_health: Option<NetworkHealth>,
+
+    /// Recovery actions requested by the chain health watchdog.
+    recovery: RecoveryRequests,
+
     /// Background tasks to shut down when the node is dropped.
     tasks: TaskList,
 
+    /// Signals for coordinating a graceful shutdown with the event handler.
+    shutdown: Shutdown,
+
     /// events streamer to stream hotshot events to external clients
     events_streamer: Arc<RwLock<EventsStreamer<SeqTypes>>>,
 
@@ -121,21 +227,52 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> Sequence
     #[tracing::instrument(skip_all, fields(node_id = instance_state.node_id))]
     #[allow(clippy::too_many_arguments)]
     pub async fn init(
-        network_config: NetworkConfig<PubKey>,
+        mut network_config: NetworkConfig<PubKey>,
         validator_config: ValidatorConfig<<SeqTypes as NodeType>::SignatureKey>,
         memberships: Memberships<SeqTypes>,
         instance_state: NodeState,
         persistence: P,
         network: Arc<N>,
         state_relay_server: Option<Url>,
+        state_signature_peers: Vec<Url>,
         metrics: &dyn Metrics,
         stake_table_capacity: u64,
         public_api_url: Option<Url>,
         event_consumer: impl PersistenceEventConsumer + 'static,
         _: V,
         marketplace_config: MarketplaceConfig<SeqTypes, Node<N, P>>,
-        proposal_fetcher_cfg: ProposalFetcherConfig,
+        node_cfg: NodeConfig,
+        state_sync: Option<&StateSyncClient<N>>,
     ) -> anyhow::Result<Self> {
+        let NodeConfig {
+            proposal_fetcher: proposal_fetcher_cfg,
+            mempool: mempool_cfg,
+            webhook: webhook_cfg,
+            view_timeout: view_timeout_cfg,
+            remote_signer: remote_signer_cfg,
+            commitment_task: commitment_task_cfg,
+            misbehavior: misbehavior_cfg,
+            da_mirror: da_mirror_cfg,
+            tx_trace: tx_trace_cfg,
+            watchdog: watchdog_cfg,
+            builder_registry: builder_registry_cfg,
+        } = node_cfg;
+
+        // Start from the last adapted view timeout, kept within the currently configured bounds.
+        let view_timeout = view_timeout_cfg.adaptive.then(|| {
+            let controller = AdaptiveViewTimeout::new(
+                view_timeout_cfg,
+                Duration::from_millis(network_config.config.next_view_timeout),
+            );
+            network_config.config.next_view_timeout = controller.timeout().as_millis() as u64;
+            controller
+        });
+
+        // Discover builders before consensus starts, so that HotShot connects to them.
+        let builder_registry = builder_registry_cfg
+            .init(&mut network_config.config.builder_urls)
+            .await;
+
         let config = &network_config.config;
         let pub_key = validator_config.public_key;
         tracing::info!(%pub_key, "initializing consensus");
@@ -149,10 +286,51 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> Sequence
         instance_state.l1_client.spawn_tasks().await;
 
         // Load saved consensus state from storage.
-        let (initializer, anchor_view) = persistence
+        let (initializer, mut anchor_view) = persistence
             .load_consensus_state::<V>(instance_state.clone())
             .await?;
 
+        // If we shut down cleanly, every decided leaf was processed before we stopped, so there is
+        // nothing to recover. The checkpoint is removed as it is read, so that if we crash this
+        // time, the next startup does not mistake it for a clean shutdown.
+        match persistence.take_shutdown_checkpoint().await {
+            Ok(Some(checkpoint)) if Some(checkpoint.anchor_view) == anchor_view => {
+                tracing::info!(?checkpoint, "restarting after clean shutdown");
+                anchor_view = None;
+            }
+            Ok(Some(checkpoint)) => {
+                tracing::warn!(
+                    ?checkpoint,
+                    ?anchor_view,
+                    "shutdown checkpoint does not match consensus state, recovering"
+                );
+            }
+            Ok(None) => {
+                tracing::info!("no clean shutdown recorded, recovering decided leaves");
+            }
+            Err(err) => {
+                tracing::warn!("failed to load shutdown checkpoint: {err:#}");
+            }
+        }
+
+        // Make sure query storage agrees with consensus storage before consensus adds to it.
+        match persistence.load_anchor_leaf().await {
+            Ok(Some((leaf, qc))) => {
+                if let Err(err) = event_consumer.reconcile(&leaf, &qc).await {
+                    tracing::error!(
+                        height = leaf.height(),
+                        "failed to reconcile query storage with consensus storage: {err:#}"
+                    );
+                }
+            }
+            Ok(None) => {}
+            Err(err) => {
+                tracing::warn!(
+                    "failed to load anchor leaf, not reconciling query storage: {err:#}"
+                );
+            }
+        }
+
         let stake_table_commit = static_stake_table_commitment(
             &config.known_nodes_with_stake,
             stake_table_capacity
@@ -167,6 +345,7 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> Sequence
         )));
 
         let persistence = Arc::new(persistence);
+        let solver = marketplace_config.auction_results_provider.clone();
 
         let handle = SystemContext::init(
             validator_config.public_key,
@@ -183,25 +362,133 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> Sequence
         .await?
         .0;
 
-        let mut state_signer = StateSigner::new(state_key_pair, stake_table_commit);
-        if let Some(url) = state_relay_server {
-            state_signer = state_signer.with_relay_server(url);
-        }
+        let mut tasks = TaskList::default();
 
         // Create the roll call info we will be using
         let roll_call_info = external_event_handler::RollCallInfo { public_api_url };
 
+        // Ban peers which misbehave on the external message protocol, starting with those banned
+        // before we last shut down.
+        let banned = match persistence.load_banned_peers().await {
+            Ok(banned) => banned,
+            Err(err) => {
+                tracing::warn!("failed to load banned peers: {err:#}");
+                vec![]
+            }
+        };
+        let (misbehavior, bans) = MisbehaviorTracker::new(misbehavior_cfg, banned, metrics);
+        tasks.spawn(
+            "ban list persistence",
+            persist_bans(persistence.clone(), bans),
+        );
+
         // Create the external event handler
-        let mut tasks = TaskList::default();
+        let external_event_handler = ExternalEventHandler::new(
+            &mut tasks,
+            network,
+            roll_call_info,
+            pub_key,
+            state_sync.map(StateSyncClient::requests),
+        )
+        .await
+        .with_context(|| "Failed to create external event handler")?
+        .with_misbehavior_tracker(Arc::new(misbehavior));
+
+        // Exchange state signatures with our peers over the network, so that every node can
+        // aggregate them.
+        let (aggregator, signatures) = StateSignatureAggregator::new(
+            &config.known_nodes_with_stake,
+            stake_table_commit,
+            state_signature_peers,
+        );
+        let aggregator = Arc::new(aggregator.with_gossip(
+            pub_key,
+            external_event_handler.outbound_message_sender.clone(),
+        ));
+        tasks.spawn(
+            "state signature aggregator",
+            aggregator.clone().run(signatures),
+        );
         let external_event_handler =
-            ExternalEventHandler::new(&mut tasks, network, roll_call_info, pub_key)
-                .await
-                .with_context(|| "Failed to create external event handler")?;
+            external_event_handler.with_state_signatures(aggregator.clone());
+
+        let signer = remote_signer_cfg.signer(state_key_pair, metrics)?;
+        let mut state_signer =
+            StateSigner::new(signer, stake_table_commit).with_aggregator(aggregator);
+        if let Some(url) = state_relay_server {
+            state_signer = state_signer.with_relay_server(url);
+        }
+
+        // Restore transactions which were pending when we last shut down.
+        let mempool = Mempool::new(
+            persistence.clone(),
+            mempool_cfg,
+            instance_state.chain_config.base_fee,
+        )
+        .with_gossip(
+            pub_key,
+            external_event_handler.outbound_message_sender.clone(),
+        );
+        if let Err(err) = mempool.restore().await {
+            tracing::warn!("failed to restore mempool: {err:#}");
+        }
+
+        let upgrades = UpgradeManager::new(persistence.clone(), instance_state.upgrades.clone());
+        if let Err(err) = upgrades.restore().await {
+            tracing::warn!("failed to restore upgrade statuses: {err:#}");
+        }
+
+        let webhooks = WebhookDispatcher::new(&webhook_cfg, metrics, &mut tasks);
+        let tx_tracer = TxTracer::new(&tx_trace_cfg, &mut tasks);
+
+        if let Some(task) = commitment_task_cfg.task(metrics).await? {
+            tasks.spawn("commitment task", task.run(handle.event_stream()));
+        }
+
+        if let Some(mirror) = da_mirror_cfg.mirror(persistence.clone(), metrics)? {
+            tasks.spawn("DA mirror", mirror.run(handle.event_stream()));
+        }
+
+        tasks.spawn(
+            "builder registry",
+            builder_registry.clone().run(builder_registry_cfg),
+        );
+
+        let recovery = RecoveryRequests::default();
+        if watchdog_cfg.enabled {
+            tasks.spawn(
+                "chain health watchdog",
+                watch_health(
+                    handle.event_stream(),
+                    instance_state.l1_client.clone(),
+                    watchdog_cfg,
+                    instance_state.node_id,
+                    recovery.clone(),
+                ),
+            );
+        }
+
+        if let Some(controller) = view_timeout {
+            tasks.spawn(
+                "view timeout controller",
+                adapt_view_timeout(
+                    handle.event_stream(),
+                    persistence.clone(),
+                    network_config.clone(),
+                    controller,
+                    metrics.create_gauge("adaptive_view_timeout".into(), Some("ms".into())),
+                ),
+            );
+        }
 
         Ok(Self::new(
             handle,
             persistence,
             state_signer,
+            Arc::new(mempool),
+            Arc::new(upgrades),
+            Arc::new(tx_tracer),
+            webhooks,
             external_event_handler,
             event_streamer,
             instance_state,
@@ -211,6 +498,9 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> Sequence
             anchor_view,
             proposal_fetcher_cfg,
         )
+        .with_recovery_requests(recovery)
+        .with_builder_registry(builder_registry)
+        .with_solver(solver)
         .with_task_list(tasks))
     }
 
@@ -220,6 +510,10 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> Sequence
         handle: Consensus<N, P, V>,
         persistence: Arc<P>,
         state_signer: StateSigner<SequencerApiVersion>,
+        mempool: Arc<Mempool<P>>,
+        upgrades: Arc<UpgradeManager<P>>,
+        tx_tracer: Arc<TxTracer>,
+        webhooks: WebhookDispatcher,
         external_event_handler: ExternalEventHandler<V>,
         event_streamer: Arc<RwLock<EventsStreamer<SeqTypes>>>,
         node_state: NodeState,
@@ -232,12 +526,23 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> Sequence
         let events = handle.event_stream();
 
         let node_id = node_state.node_id;
+        let builder_fees = Arc::new(BuilderFeeLedger::new(persistence.clone()));
         let mut ctx = Self {
             handle: Arc::new(RwLock::new(handle)),
+            persistence: persistence.clone(),
             state_signer: Arc::new(state_signer),
+            mempool: mempool.clone(),
+            upgrades: upgrades.clone(),
+            builder_fees: builder_fees.clone(),
+            tx_tracer: tx_tracer.clone(),
+            builder_registry: Default::default(),
+            solver: Default::default(),
             tasks: Default::default(),
+            shutdown: Default::default(),
             detached: false,
             wait_for_orchestrator: None,
+            network_health: None,
+            recovery: Default::default(),
             events_streamer: event_streamer.clone(),
             node_state,
             network_config,
@@ -246,7 +551,18 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> Sequence
 
         // Spawn proposal fetching tasks.
         let (send, recv) = broadcast(proposal_fetcher_cfg.channel_capacity);
-        ctx.spawn("proposal scanner", scan_proposals(ctx.handle.clone(), send));
+        ctx.spawn(
+            "proposal scanner",
+            scan_proposals(ctx.handle.clone(), send.clone()),
+        );
+        ctx.spawn(
+            "leader proposal prefetcher",
+            prefetch_leader_proposals(
+                ctx.handle.clone(),
+                send,
+                proposal_fetcher_cfg.leader_lookahead,
+            ),
+        );
         for i in 0..proposal_fetcher_cfg.num_workers {
             ctx.spawn(
                 format!("proposal fetcher {i}"),
@@ -259,7 +575,40 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> Sequence
             );
         }
 
+        // Serve state sync requests from our peers out of consensus memory.
+        let external_event_handler = external_event_handler
+            .with_state_source(Arc::new(ctx.handle.clone()))
+            .with_mempool(mempool.clone());
+
+        // Record the stake table of each epoch as it begins, so its history can be queried.
+        ctx.spawn(
+            "stake table recorder",
+            record_stake_tables(
+                ctx.handle.clone(),
+                persistence.clone(),
+                ctx.network_config.config.known_nodes_with_stake.clone(),
+            ),
+        );
+
+        // Record what we observe in each view, for post-mortems of failed views.
+        ctx.spawn(
+            "view log recorder",
+            record_views(ctx.handle.clone(), persistence.clone()),
+        );
+
+        // Periodically resubmit pending transactions, so they reach whichever builder is active.
+        {
+            let handle = ctx.handle.clone();
+            let mempool = mempool.clone();
+            ctx.tasks.spawn_supervised(
+                "mempool resubmission",
+                RestartPolicy::restart(),
+                move || resubmit_mempool(handle.clone(), mempool.clone()),
+            );
+        }
+
         // Spawn event handling loop.
+        let decide_log = DecideEventLog::new(persistence.clone());
         ctx.spawn(
             "event handler",
             handle_events(
@@ -267,10 +616,17 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> Sequence
                 events,
                 persistence,
                 ctx.state_signer.clone(),
+                mempool,
+                upgrades,
+                decide_log,
+                builder_fees,
+                tx_tracer,
+                webhooks,
                 external_event_handler,
                 Some(event_streamer.clone()),
                 event_consumer,
                 anchor_view,
+                ctx.shutdown.clone(),
             ),
         );
 
@@ -283,6 +639,30 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> Sequence
         self
     }
 
+    /// Report the health of the network this node is connected through.
+    pub(crate) fn with_network_health(mut self, health: NetworkHealth) -> Self {
+        self.network_health = Some(health);
+        self
+    }
+
+    /// Report recovery actions requested by the chain health watchdog through `recovery`.
+    pub(crate) fn with_recovery_requests(mut self, recovery: RecoveryRequests) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// Report the builders known to this node through `registry`.
+    pub(crate) fn with_builder_registry(mut self, registry: BuilderRegistry) -> Self {
+        self.builder_registry = registry;
+        self
+    }
+
+    /// Read auction results from and submit bids to `solver`.
+    pub(crate) fn with_solver(mut self, solver: Arc<SolverAuctionResultsProvider>) -> Self {
+        self.solver = solver;
+        self
+    }
+
     /// Add a list of tasks to the given context.
     pub(crate) fn with_task_list(mut self, tasks: TaskList) -> Self {
         self.tasks.extend(tasks);
@@ -294,6 +674,51 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> Sequence
         self.state_signer.clone()
     }
 
+    /// Return a reference to the persistent storage.
+    pub fn persistence(&self) -> Arc<P> {
+        self.persistence.clone()
+    }
+
+    /// Return a reference to the mempool.
+    pub fn mempool(&self) -> Arc<Mempool<P>> {
+        self.mempool.clone()
+    }
+
+    /// Return a reference to the upgrade manager.
+    pub fn upgrades(&self) -> Arc<UpgradeManager<P>> {
+        self.upgrades.clone()
+    }
+
+    /// Return a reference to the builder fee ledger.
+    pub fn builder_fees(&self) -> Arc<BuilderFeeLedger<P>> {
+        self.builder_fees.clone()
+    }
+
+    /// Return a reference to the tracer of submitted transactions.
+    pub fn tx_tracer(&self) -> Arc<TxTracer> {
+        self.tx_tracer.clone()
+    }
+
+    /// Return the builders known to this node, and their health.
+    pub fn builder_registry(&self) -> BuilderRegistry {
+        self.builder_registry.clone()
+    }
+
+    /// Return a reference to the marketplace solver.
+    pub fn solver(&self) -> Arc<SolverAuctionResultsProvider> {
+        self.solver.clone()
+    }
+
+    /// Return a handle for observing the health of this node's background tasks.
+    pub(crate) fn task_monitor(&self) -> TaskMonitor {
+        self.tasks.monitor()
+    }
+
+    /// Return the health of the network, if this node uses the production network.
+    pub fn network_health(&self) -> Option<NetworkHealth> {
+        self.network_health.clone()
+    }
+
     /// Stream consensus events.
     pub async fn event_stream(&self) -> impl Stream<Item = Event<SeqTypes>> {
         self.handle.read().await.event_stream()
@@ -384,6 +809,49 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> Sequence
         self.detached = true;
     }
 
+    /// Stop participating in consensus, first saving all in-flight state.
+    ///
+    /// The node stops accepting transactions and stops consensus, and then waits up to `timeout`
+    /// for the event handler to persist the events it has already received and record a
+    /// [`ShutdownCheckpoint`]. Background tasks are stopped either way.
+    pub async fn shut_down_gracefully(&mut self, timeout_duration: Duration) {
+        tracing::warn!("shutting down gracefully");
+        self.shutdown.draining.raise();
+        self.handle.write().await.shut_down().await;
+
+        self.shutdown.stopped.raise();
+        if timeout(timeout_duration, self.shutdown.done.wait())
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                ?timeout_duration,
+                "timed out waiting for events to be persisted, shutting down anyway"
+            );
+        }
+
+        self.tasks.shut_down();
+        self.node_state.l1_client.shut_down_tasks().await;
+        self.detached = true;
+        tracing::warn!("shutdown complete");
+    }
+
+    /// Wait until the chain health watchdog asks for this node to be restarted or stopped.
+    ///
+    /// This never returns if the watchdog is disabled, or not configured to take either action.
+    pub async fn recovery_requested(&self) -> WatchdogAction {
+        self.recovery.wait().await
+    }
+
+    /// Whether this node has started shutting down, and is no longer accepting transactions.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.draining.is_raised()
+    }
+
+    pub(crate) fn shutdown_signals(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
     /// Wait for consensus to complete.
     ///
     /// Under normal conditions, this function will block forever, which is a convenient way of
@@ -429,15 +897,22 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence, V: Versions> Drop
 
 #[tracing::instrument(skip_all, fields(node_id))]
 #[allow(clippy::too_many_arguments)]
-async fn handle_events<V: Versions>(
+async fn handle_events<V: Versions, P: SequencerPersistence>(
     node_id: u64,
     mut events: impl Stream<Item = Event<SeqTypes>> + Unpin,
-    persistence: Arc<impl SequencerPersistence>,
+    persistence: Arc<P>,
     state_signer: Arc<StateSigner<SequencerApiVersion>>,
+    mempool: Arc<Mempool<P>>,
+    upgrades: Arc<UpgradeManager<P>>,
+    mut decide_log: DecideEventLog<P>,
+    builder_fees: Arc<BuilderFeeLedger<P>>,
+    tx_tracer: Arc<TxTracer>,
+    webhooks: WebhookDispatcher,
     external_event_handler: ExternalEventHandler<V>,
     events_streamer: Option<Arc<RwLock<EventsStreamer<SeqTypes>>>>,
     event_consumer: impl PersistenceEventConsumer + 'static,
     anchor_view: Option<ViewNumber>,
+    shutdown: Shutdown,
 ) {
     if let Some(view) = anchor_view {
         // Process and clean up any leaves that we may have persisted last time we were running but
@@ -452,8 +927,20 @@ async fn handle_events<V: Versions>(
         }
     }
 
-    while let Some(event) = events.next().await {
+    let mut last_event_view = None;
+    loop {
+        // Once consensus has stopped, prefer draining events which were already emitted over
+        // exiting, so that nothing it produced goes unpersisted.
+        let event = tokio::select! {
+            biased;
+            event = events.next() => event,
+            _ = shutdown.stopped.wait() => None,
+        };
+        let Some(event) = event else {
+            break;
+        };
         tracing::debug!(node_id, ?event, "consensus event");
+        last_event_view = Some(event.view_number);
 
         // Store latest consensus state.
         persistence.handle_event(&event, &event_consumer).await;
@@ -461,9 +948,27 @@ async fn handle_events<V: Versions>(
         // Generate state signature.
         state_signer.handle_event(&event).await;
 
+        // Drop sequenced transactions from the mempool.
+        mempool.handle_event(&event).await;
+
+        // Track the progress of protocol upgrades.
+        upgrades.handle_event(&event).await;
+
+        // Log decided blocks, before they are streamed, so consumers can replay what they miss.
+        decide_log.handle_event(&event).await;
+
+        // Account for the fees charged to builders.
+        builder_fees.handle_event(&event).await;
+
+        // Follow traced transactions into blocks.
+        tx_tracer.handle_event(&event);
+
+        // Notify webhooks.
+        webhooks.handle_event(&event);
+
         // Handle external messages
-        if let EventType::ExternalMessageReceived { data, .. } = &event.event {
-            if let Err(err) = external_event_handler.handle_event(data).await {
+        if let EventType::ExternalMessageReceived { sender, data } = &event.event {
+            if let Err(err) = external_event_handler.handle_event(*sender, data).await {
                 tracing::warn!("Failed to handle external message: {:?}", err);
             };
         }
@@ -473,6 +978,147 @@ async fn handle_events<V: Versions>(
             events_streamer.write().await.handle_event(event).await;
         }
     }
+
+    if shutdown.stopped.is_raised() {
+        checkpoint(&*persistence, &event_consumer, last_event_view).await;
+        shutdown.done.raise();
+    }
+}
+
+/// Record a clean shutdown, once all decided leaves have been processed.
+async fn checkpoint<P: SequencerPersistence>(
+    persistence: &P,
+    event_consumer: &(impl PersistenceEventConsumer + 'static),
+    last_event_view: Option<ViewNumber>,
+) {
+    let anchor_view = match persistence.load_anchor_view().await {
+        Ok(view) => view,
+        Err(err) => {
+            tracing::warn!("failed to load anchor view, not recording clean shutdown: {err:#}");
+            return;
+        }
+    };
+
+    // Make sure no decided leaves are left unprocessed, so that none need to be recovered when we
+    // start up again.
+    if let Err(err) = persistence
+        .append_decided_leaves(anchor_view, vec![], event_consumer)
+        .await
+    {
+        tracing::warn!("failed to process decided leaves, not recording clean shutdown: {err:#}");
+        return;
+    }
+
+    let checkpoint = ShutdownCheckpoint {
+        anchor_view,
+        last_event_view,
+    };
+    match persistence.store_shutdown_checkpoint(&checkpoint).await {
+        Ok(()) => tracing::info!(?checkpoint, "recorded clean shutdown"),
+        Err(err) => tracing::warn!("failed to record clean shutdown: {err:#}"),
+    }
+}
+
+/// The stake table in effect during `epoch`, according to the consensus memberships.
+///
+/// The memberships do not include state verification keys, so these are looked up in `peers`.
+pub(crate) fn epoch_stake_table<N, P, V>(
+    consensus: &Consensus<N, P, V>,
+    peers: &[PeerConfig<PubKey>],
+    epoch: EpochNumber,
+) -> EpochStakeTable
+where
+    N: ConnectedNetwork<PubKey>,
+    P: SequencerPersistence,
+    V: Versions,
+{
+    let memberships = &consensus.memberships;
+    let da = memberships
+        .da_membership
+        .stake_table(epoch)
+        .into_iter()
+        .map(|entry| entry.stake_key)
+        .collect::<HashSet<_>>();
+    let nodes = memberships
+        .quorum_membership
+        .stake_table(epoch)
+        .into_iter()
+        .filter_map(|entry| {
+            let Some(peer) = peers
+                .iter()
+                .find(|peer| peer.stake_table_entry.stake_key == entry.stake_key)
+            else {
+                tracing::warn!(key = %entry.stake_key, "no state key for node in stake table");
+                return None;
+            };
+            Some(StakeTableNode {
+                da: da.contains(&entry.stake_key),
+                state_ver_key: peer.state_ver_key.clone(),
+                stake_table_entry: entry,
+            })
+        })
+        .collect();
+    EpochStakeTable { epoch, nodes }
+}
+
+#[tracing::instrument(skip_all)]
+async fn record_stake_tables<N, P, V>(
+    consensus: Arc<RwLock<Consensus<N, P, V>>>,
+    persistence: Arc<P>,
+    peers: Vec<PeerConfig<PubKey>>,
+) where
+    N: ConnectedNetwork<PubKey>,
+    P: SequencerPersistence,
+    V: Versions,
+{
+    let mut events = consensus.read().await.event_stream();
+    let mut recorded = None;
+    while let Some(event) = events.next().await {
+        if !matches!(event.event, EventType::Decide { .. }) {
+            continue;
+        }
+        let stake_table = {
+            let handle = consensus.read().await;
+            let epoch = handle.cur_epoch().await;
+            if recorded == Some(epoch) {
+                continue;
+            }
+            epoch_stake_table(&handle, &peers, epoch)
+        };
+        let epoch = stake_table.epoch;
+        match persistence.store_stake_table(&stake_table).await {
+            Ok(()) => {
+                tracing::info!(?epoch, "recorded stake table");
+                recorded = Some(epoch);
+            }
+            Err(err) => tracing::warn!(?epoch, "failed to record stake table: {err:#}"),
+        }
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn resubmit_mempool<N, P, V>(
+    consensus: Arc<RwLock<Consensus<N, P, V>>>,
+    mempool: Arc<Mempool<P>>,
+) where
+    N: ConnectedNetwork<PubKey>,
+    P: SequencerPersistence,
+    V: Versions,
+{
+    loop {
+        sleep(mempool.config().resubmit_interval).await;
+        let txs = mempool.due();
+        if txs.is_empty() {
+            continue;
+        }
+        tracing::debug!(count = txs.len(), "resubmitting pending transactions");
+        let handle = consensus.read().await;
+        for tx in txs {
+            if let Err(err) = handle.submit_transaction(tx).await {
+                tracing::warn!("failed to resubmit pending transaction: {err:#}");
+            }
+        }
+    }
 }
 
 #[tracing::instrument(skip_all)]
@@ -500,6 +1146,70 @@ async fn scan_proposals<N, P, V>(
     }
 }
 
+/// Fetch the parent of our next proposal ahead of the views in which we are leader.
+///
+/// A leader which restarts shortly before its view may not have seen the proposal it is supposed
+/// to extend, and would then fail to propose. Whenever the view changes, if we are leader in one of
+/// the next `lookahead` views, this sends the proposal justified by our high QC to the proposal
+/// fetchers, which fetch it from peers and persist it if it is missing from storage.
+#[tracing::instrument(skip_all)]
+async fn prefetch_leader_proposals<N, P, V>(
+    consensus: Arc<RwLock<Consensus<N, P, V>>>,
+    fetcher: Sender<(ViewNumber, Commitment<Leaf<SeqTypes>>)>,
+    lookahead: u64,
+) where
+    N: ConnectedNetwork<PubKey>,
+    P: SequencerPersistence,
+    V: Versions,
+{
+    let mut events = consensus.read().await.event_stream();
+    let mut last_parent = None;
+    loop {
+        let parent = {
+            let handle = consensus.read().await;
+            let view = handle.cur_view().await;
+            let epoch = handle.cur_epoch().await;
+            let key = handle.public_key();
+            let membership = &handle.memberships.quorum_membership;
+            let leader_view = (1..=lookahead).map(|i| view + i).find(|view| {
+                membership
+                    .leader(*view, epoch)
+                    .is_ok_and(|leader| leader == key)
+            });
+            match leader_view {
+                Some(leader_view) => {
+                    let consensus = handle.consensus();
+                    let consensus = consensus.read().await;
+                    let high_qc = consensus.high_qc();
+                    tracing::debug!(
+                        ?leader_view,
+                        parent_view = ?high_qc.view_number,
+                        "leader soon, ensuring we have the parent proposal"
+                    );
+                    Some((high_qc.view_number, high_qc.data.leaf_commit))
+                }
+                None => None,
+            }
+        };
+        if let Some(parent) = parent.filter(|parent| Some(*parent) != last_parent) {
+            fetcher.broadcast_direct(parent).await.ok();
+            last_parent = Some(parent);
+        }
+
+        // Check again once the view changes.
+        loop {
+            match events.next().await {
+                Some(Event {
+                    event: EventType::ViewFinished { .. },
+                    ..
+                }) => break,
+                Some(_) => continue,
+                None => return,
+            }
+        }
+    }
+}
+
 #[tracing::instrument(skip_all)]
 async fn fetch_proposals<N, P, V>(
     consensus: Arc<RwLock<Consensus<N, P, V>>>,
@@ -606,14 +1316,22 @@ async fn load_anchor_view(persistence: &impl SequencerPersistence) -> ViewNumber
     }
 }
 
+/// A background task attached to a [`TaskList`].
+#[derive(Debug)]
+struct Task {
+    name: String,
+    handle: JoinHandle<()>,
+    /// The health of the task, if it is a supervised, long-running task.
+    health: Option<TaskHealth>,
+}
+
 #[derive(Debug, Default, Clone)]
-#[allow(clippy::type_complexity)]
-pub(crate) struct TaskList(Arc<Mutex<Vec<(String, JoinHandle<()>)>>>);
+pub(crate) struct TaskList(Arc<Mutex<Vec<Task>>>);
 
 macro_rules! spawn_with_log_level {
-    ($this:expr, $lvl:expr, $name:expr, $task: expr) => {
+    ($this:expr, $lvl:expr, $name:expr, $health:expr, $task: expr) => {
         let name = $name.to_string();
-        let task = {
+        let handle = {
             let name = name.clone();
             let span = tracing::span!($lvl, "background task", name);
             spawn(
@@ -625,7 +1343,11 @@ macro_rules! spawn_with_log_level {
                 .instrument(span),
             )
         };
-        $this.0.lock().push((name, task));
+        $this.0.lock().push(Task {
+            name,
+            handle,
+            health: $health,
+        });
     };
 }
 
@@ -634,8 +1356,35 @@ impl TaskList {
     ///
     /// When this [`TaskList`] is dropped or [`shut_down`](Self::shut_down), background tasks will
     /// be cancelled in the reverse order that they were spawned.
+    ///
+    /// The task is supervised, so if it panics or exits, this is logged and reported in
+    /// [`status`](Self::status), but it is not restarted. Use
+    /// [`spawn_supervised`](Self::spawn_supervised) for tasks which can be restarted.
     pub fn spawn(&mut self, name: impl Display, task: impl Future + Send + 'static) {
-        spawn_with_log_level!(self, Level::INFO, name, task);
+        let health = TaskHealth::new(&name, RestartPolicy::Never);
+        let mut task = Some(task);
+        let supervised = supervise(health.clone(), RestartPolicy::Never, move || {
+            task.take()
+                .expect("task without restart policy is only started once")
+        });
+        spawn_with_log_level!(self, Level::INFO, name, Some(health), supervised);
+    }
+
+    /// Spawn a supervised background task attached to this [`TaskList`].
+    ///
+    /// The task is created by calling `make`, which is called again to restart the task according
+    /// to `policy` whenever it panics or exits.
+    pub fn spawn_supervised<F>(
+        &mut self,
+        name: impl Display,
+        policy: RestartPolicy,
+        make: impl Fn() -> F + Send + 'static,
+    ) where
+        F: Future + Send + 'static,
+    {
+        let health = TaskHealth::new(&name, policy);
+        let supervised = supervise(health.clone(), policy, make);
+        spawn_with_log_level!(self, Level::INFO, name, Some(health), supervised);
     }
 
     /// Spawn a short-lived background task attached to this [`TaskList`].
@@ -644,34 +1393,58 @@ impl TaskList {
     /// be cancelled in the reverse order that they were spawned.
     ///
     /// The only difference between a short-lived background task and a [long-lived](Self::spawn)
-    /// one is how urgently logging related to the task is treated.
+    /// one is how urgently logging related to the task is treated, and that short-lived tasks are
+    /// not reported in [`status`](Self::status).
     pub fn spawn_short_lived(&mut self, name: impl Display, task: impl Future + Send + 'static) {
-        spawn_with_log_level!(self, Level::DEBUG, name, task);
+        spawn_with_log_level!(self, Level::DEBUG, name, None, task);
+    }
+
+    /// The health of each long-running background task, in the order they were spawned.
+    pub fn status(&self) -> Vec<TaskStatus> {
+        self.monitor().status()
+    }
+
+    /// A handle for observing the health of tasks in this list.
+    ///
+    /// Unlike a clone of the list, dropping the monitor does not stop the tasks.
+    pub(crate) fn monitor(&self) -> TaskMonitor {
+        TaskMonitor(self.0.clone())
     }
 
     /// Stop all background tasks.
     pub fn shut_down(&self) {
-        let tasks: Vec<(String, JoinHandle<()>)> = self.0.lock().drain(..).collect();
-        for (name, task) in tasks.into_iter().rev() {
-            tracing::info!(name, "cancelling background task");
-            task.abort();
+        let tasks: Vec<Task> = self.0.lock().drain(..).collect();
+        for task in tasks.into_iter().rev() {
+            tracing::info!(name = task.name, "cancelling background task");
+            task.handle.abort();
         }
     }
 
     /// Wait for all background tasks to complete.
     pub async fn join(&mut self) {
-        let tasks: Vec<(String, JoinHandle<()>)> = self.0.lock().drain(..).collect();
-        join_all(tasks.into_iter().map(|(_, task)| task)).await;
+        let tasks: Vec<Task> = self.0.lock().drain(..).collect();
+        join_all(tasks.into_iter().map(|task| task.handle)).await;
     }
 
     pub fn extend(&mut self, tasks: TaskList) {
-        self.0.lock().extend(
-            tasks
-                .0
-                .lock()
-                .drain(..)
-                .collect::<Vec<(String, JoinHandle<()>)>>(),
-        );
+        self.0
+            .lock()
+            .extend(tasks.0.lock().drain(..).collect::<Vec<Task>>());
+    }
+}
+
+/// Reports the health of the tasks in a [`TaskList`].
+#[derive(Clone, Debug)]
+pub(crate) struct TaskMonitor(Arc<Mutex<Vec<Task>>>);
+
+impl TaskMonitor {
+    /// The health of each long-running background task, in the order they were spawned.
+    pub(crate) fn status(&self) -> Vec<TaskStatus> {
+        self.0
+            .lock()
+            .iter()
+            .filter_map(|task| Some(task.health.as_ref()?.status()))
+            .collect()
     }
 }
 
@@ -0,0 +1,395 @@
+//! Mirroring block payloads to an external data availability layer.
+//!
+//! Espresso's own DA committee guarantees that the payload of every decided block is available.
+//! Some deployments additionally want payloads posted to an external DA layer, so that they can be
+//! retrieved even without access to Espresso nodes. When configured, a node runs a mirror task
+//! which posts the payload of each block it sees decided to the layer through an
+//! [`ExternalDaClient`], and records a [`DaPointer`] to the posted payload in persistence. Pointers
+//! are served by the availability API at `availability/block/:height/external-da`.
+//!
+//! Payloads are posted as their raw bytes. The namespace table needed to split a payload into
+//! namespaces is part of the block header, which is not mirrored. Empty payloads are not posted.
+//!
+//! Clients for specific layers are compiled in with features of the same name:
+//! * `celestia` posts blobs through the JSON-RPC API of a Celestia node
+//! * `eigenda` posts blobs through an EigenDA proxy
+//!
+//! Only blocks decided while the mirror is running are posted. A payload which cannot be posted
+//! after the configured number of attempts is skipped, and progress is reported via metrics in the
+//! `da_mirror` group.
+
+use std::{fmt::Debug, sync::Arc, time::Duration};
+
+use anyhow::bail;
+use async_trait::async_trait;
+use clap::{Parser, ValueEnum};
+use derive_more::Display;
+use espresso_types::{parse_duration, v0::traits::SequencerPersistence, DaPointer, SeqTypes};
+use ethers::utils::hex;
+use futures::{future, stream::Stream, StreamExt};
+use hotshot::{
+    traits::BlockPayload,
+    types::{Event, EventType},
+};
+use hotshot_types::{
+    event::LeafInfo,
+    traits::metrics::{Counter, Gauge, Metrics},
+};
+use tokio::{
+    sync::mpsc::{channel, error::TrySendError},
+    time::sleep,
+};
+use url::Url;
+
+#[cfg(feature = "celestia")]
+mod celestia;
+#[cfg(feature = "eigenda")]
+mod eigenda;
+
+/// An external data availability layer.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, ValueEnum)]
+pub enum DaLayer {
+    #[display("celestia")]
+    Celestia,
+    #[display("eigenda")]
+    #[value(name = "eigenda")]
+    EigenDa,
+}
+
+#[derive(Clone, Debug, Parser)]
+pub struct DaMirrorConfig {
+    /// External DA layer to mirror the payloads of decided blocks to.
+    ///
+    /// Each layer is only available if the node was built with the feature of the same name.
+    #[clap(long = "da-mirror", env = "ESPRESSO_SEQUENCER_DA_MIRROR")]
+    pub layer: Option<DaLayer>,
+
+    /// Endpoint of the external DA layer: the JSON-RPC API of a Celestia node, or an EigenDA proxy.
+    #[clap(long = "da-mirror-url", env = "ESPRESSO_SEQUENCER_DA_MIRROR_URL")]
+    pub url: Option<Url>,
+
+    /// Token to authenticate with the external DA layer, if it requires one.
+    #[clap(
+        long = "da-mirror-auth-token",
+        env = "ESPRESSO_SEQUENCER_DA_MIRROR_AUTH_TOKEN"
+    )]
+    pub auth_token: Option<String>,
+
+    /// Celestia namespace ID to post payloads under, as up to 10 hex-encoded bytes.
+    #[clap(
+        long = "da-mirror-celestia-namespace",
+        env = "ESPRESSO_SEQUENCER_DA_MIRROR_CELESTIA_NAMESPACE",
+        default_value = "657370726573736f"
+    )]
+    pub celestia_namespace: String,
+
+    /// Maximum number of decided payloads waiting to be posted.
+    ///
+    /// Payloads decided while the queue is full are not mirrored.
+    #[clap(
+        long = "da-mirror-queue-capacity",
+        env = "ESPRESSO_SEQUENCER_DA_MIRROR_QUEUE_CAPACITY",
+        default_value = "100"
+    )]
+    pub queue_capacity: usize,
+
+    /// Maximum number of attempts to post each payload before giving up.
+    #[clap(
+        long = "da-mirror-max-attempts",
+        env = "ESPRESSO_SEQUENCER_DA_MIRROR_MAX_ATTEMPTS",
+        default_value = "5"
+    )]
+    pub max_attempts: usize,
+
+    /// Delay before retrying a failed post. The delay doubles with each failed attempt.
+    #[clap(
+        long = "da-mirror-retry-delay",
+        env = "ESPRESSO_SEQUENCER_DA_MIRROR_RETRY_DELAY",
+        default_value = "1s",
+        value_parser = parse_duration,
+    )]
+    pub retry_delay: Duration,
+
+    /// Timeout for a single request to the external DA layer.
+    ///
+    /// Posting a blob waits for it to be included on the DA layer, so this should cover at least a
+    /// block time of the layer.
+    #[clap(
+        long = "da-mirror-timeout",
+        env = "ESPRESSO_SEQUENCER_DA_MIRROR_TIMEOUT",
+        default_value = "1m",
+        value_parser = parse_duration,
+    )]
+    pub timeout: Duration,
+}
+
+impl Default for DaMirrorConfig {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+impl DaMirrorConfig {
+    /// Create the DA mirror, if it is enabled.
+    pub(crate) fn mirror<P: SequencerPersistence>(
+        &self,
+        persistence: Arc<P>,
+        metrics: &dyn Metrics,
+    ) -> anyhow::Result<Option<DaMirror<P>>> {
+        let Some(layer) = self.layer else {
+            return Ok(None);
+        };
+        let client = self.client(layer)?;
+        tracing::info!(%layer, "mirroring block payloads to external DA");
+        Ok(Some(DaMirror::new(
+            client,
+            persistence,
+            self.clone(),
+            metrics,
+        )))
+    }
+
+    fn client(&self, layer: DaLayer) -> anyhow::Result<Arc<dyn ExternalDaClient>> {
+        match layer {
+            #[cfg(feature = "celestia")]
+            DaLayer::Celestia => Ok(Arc::new(celestia::CelestiaClient::new(self)?)),
+            #[cfg(feature = "eigenda")]
+            DaLayer::EigenDa => Ok(Arc::new(eigenda::EigenDaClient::new(self)?)),
+            #[allow(unreachable_patterns)]
+            layer => bail!("mirroring to {layer} requires building with the `{layer}` feature"),
+        }
+    }
+}
+
+/// A payload posted to an external DA layer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PostedPayload {
+    /// The commitment by which the DA layer identifies the payload.
+    pub commitment: Vec<u8>,
+    /// The height of the block which includes the payload on the DA layer, if it has one.
+    pub da_height: Option<u64>,
+}
+
+/// A client which posts payloads to an external DA layer.
+#[async_trait]
+pub trait ExternalDaClient: Debug + Send + Sync {
+    /// The name of the DA layer, recorded in [`DaPointer`]s.
+    fn layer(&self) -> String;
+
+    /// Post `payload`, returning once the DA layer has accepted it.
+    async fn post(&self, payload: &[u8]) -> anyhow::Result<PostedPayload>;
+}
+
+#[derive(Debug)]
+struct DaMirrorMetrics {
+    /// Number of payloads posted to the external DA layer.
+    mirrored: Box<dyn Counter>,
+    /// Number of failed attempts to post a payload which were retried.
+    retried: Box<dyn Counter>,
+    /// Number of payloads which could not be posted after the maximum number of attempts.
+    failed: Box<dyn Counter>,
+    /// Number of payloads dropped because the queue was full.
+    dropped: Box<dyn Counter>,
+    /// Height of the last block whose payload was posted.
+    mirrored_height: Box<dyn Gauge>,
+}
+
+impl DaMirrorMetrics {
+    fn new(metrics: &dyn Metrics) -> Self {
+        let metrics = metrics.subgroup("da_mirror".into());
+        Self {
+            mirrored: metrics.create_counter("mirrored".into(), None),
+            retried: metrics.create_counter("retried".into(), None),
+            failed: metrics.create_counter("failed".into(), None),
+            dropped: metrics.create_counter("dropped".into(), None),
+            mirrored_height: metrics.create_gauge("mirrored_height".into(), None),
+        }
+    }
+}
+
+/// Posts the payloads of decided blocks to an external DA layer.
+#[derive(Debug)]
+pub(crate) struct DaMirror<P> {
+    client: Arc<dyn ExternalDaClient>,
+    persistence: Arc<P>,
+    cfg: DaMirrorConfig,
+    metrics: DaMirrorMetrics,
+}
+
+impl<P: SequencerPersistence> DaMirror<P> {
+    fn new(
+        client: Arc<dyn ExternalDaClient>,
+        persistence: Arc<P>,
+        cfg: DaMirrorConfig,
+        metrics: &dyn Metrics,
+    ) -> Self {
+        Self {
+            client,
+            persistence,
+            cfg,
+            metrics: DaMirrorMetrics::new(metrics),
+        }
+    }
+
+    /// Mirror the payloads of blocks decided in `events` until the event stream ends.
+    pub(crate) async fn run(self, mut events: impl Stream<Item = Event<SeqTypes>> + Unpin) {
+        let (queue, mut payloads) = channel(self.cfg.queue_capacity.max(1));
+        let collect = async {
+            while let Some(event) = events.next().await {
+                let EventType::Decide { leaf_chain, .. } = &event.event else {
+                    continue;
+                };
+                // Leaves are ordered newest first.
+                for LeafInfo { leaf, .. } in leaf_chain.iter().rev() {
+                    let Some(payload) = leaf.block_payload() else {
+                        tracing::warn!(height = leaf.height(), "decided leaf has no payload");
+                        continue;
+                    };
+                    let bytes = payload.encode();
+                    if bytes.is_empty() {
+                        continue;
+                    }
+                    match queue.try_send((leaf.height(), bytes)) {
+                        Ok(()) => {}
+                        Err(TrySendError::Full(_)) => {
+                            tracing::warn!(
+                                height = leaf.height(),
+                                "DA mirror queue full, dropping payload"
+                            );
+                            self.metrics.dropped.add(1);
+                        }
+                        Err(TrySendError::Closed(_)) => return,
+                    }
+                }
+            }
+            tracing::warn!("event stream ended, DA mirror exiting");
+        };
+        let post = async {
+            while let Some((height, payload)) = payloads.recv().await {
+                self.mirror(height, &payload).await;
+            }
+        };
+        future::select(Box::pin(collect), Box::pin(post)).await;
+    }
+
+    /// Post the payload of block `height`, retrying failures, and record where it was posted.
+    ///
+    /// Returns whether the payload was mirrored.
+    async fn mirror(&self, height: u64, payload: &[u8]) -> bool {
+        let mut delay = self.cfg.retry_delay;
+        let max_attempts = self.cfg.max_attempts.max(1);
+        let mut attempt = 1;
+        let posted = loop {
+            match self.client.post(payload).await {
+                Ok(posted) => break posted,
+                Err(err) if attempt < max_attempts => {
+                    tracing::info!(
+                        height,
+                        attempt,
+                        "failed to post payload, will retry after {delay:?}: {err:#}"
+                    );
+                    self.metrics.retried.add(1);
+                    sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(err) => {
+                    tracing::warn!(height, attempt, "giving up on posting payload: {err:#}");
+                    self.metrics.failed.add(1);
+                    return false;
+                }
+            }
+        };
+
+        let pointer = DaPointer {
+            height,
+            layer: self.client.layer(),
+            commitment: format!("0x{}", hex::encode(&posted.commitment)),
+            da_height: posted.da_height,
+        };
+        tracing::debug!(?pointer, "mirrored payload");
+        if let Err(err) = self.persistence.store_da_pointer(&pointer).await {
+            // The payload is available on the DA layer, we just don't know where. Posting it again
+            // would not help, so move on.
+            tracing::warn!(height, "failed to store DA pointer: {err:#}");
+        }
+        self.metrics.mirrored.add(1);
+        self.metrics.mirrored_height.set(height as usize);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use espresso_types::v0::traits::PersistenceOptions;
+    use hotshot_types::traits::metrics::NoMetrics;
+    use parking_lot::Mutex;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::persistence::fs;
+
+    /// A client which fails a number of times before accepting each payload.
+    #[derive(Debug, Default)]
+    struct MockClient {
+        failures: Mutex<usize>,
+        posted: Mutex<Vec<Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl ExternalDaClient for MockClient {
+        fn layer(&self) -> String {
+            "mock".into()
+        }
+
+        async fn post(&self, payload: &[u8]) -> anyhow::Result<PostedPayload> {
+            {
+                let mut failures = self.failures.lock();
+                if *failures > 0 {
+                    *failures -= 1;
+                    bail!("mock failure");
+                }
+            }
+            let mut posted = self.posted.lock();
+            posted.push(payload.to_vec());
+            Ok(PostedPayload {
+                commitment: vec![0xab, posted.len() as u8],
+                da_height: Some(posted.len() as u64),
+            })
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_mirror_retries() {
+        let tmp = TempDir::new().unwrap();
+        let persistence = Arc::new(fs::Options::new(tmp.path().into()).create().await.unwrap());
+        let client = Arc::new(MockClient {
+            failures: Mutex::new(2),
+            ..Default::default()
+        });
+        let cfg = DaMirrorConfig {
+            retry_delay: Duration::from_millis(10),
+            max_attempts: 3,
+            ..Default::default()
+        };
+        let mirror = DaMirror::new(client.clone(), persistence.clone(), cfg, &NoMetrics);
+
+        // The payload is posted on the third attempt.
+        assert!(mirror.mirror(5, b"payload").await);
+        assert_eq!(*client.posted.lock(), vec![b"payload".to_vec()]);
+        assert_eq!(
+            persistence.load_da_pointer(5).await.unwrap(),
+            Some(DaPointer {
+                height: 5,
+                layer: "mock".into(),
+                commitment: "0xab01".into(),
+                da_height: Some(1),
+            })
+        );
+
+        // A payload which keeps failing is given up on, without recording a pointer.
+        *client.failures.lock() = 3;
+        assert!(!mirror.mirror(6, b"other").await);
+        assert_eq!(persistence.load_da_pointer(6).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,139 @@
+//! Mirroring payloads to Celestia, through the JSON-RPC API of a Celestia node.
+
+use anyhow::{bail, ensure, Context};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use ethers::utils::hex;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use url::Url;
+
+use super::{DaMirrorConfig, ExternalDaClient, PostedPayload};
+
+/// The length of a Celestia namespace ID.
+const NAMESPACE_ID_LEN: usize = 10;
+
+/// The length of a version 0 namespace: a version byte followed by 28 bytes, of which all but the
+/// namespace ID must be zero.
+const NAMESPACE_LEN: usize = 29;
+
+/// Posts payloads as blobs in a Celestia namespace.
+#[derive(Debug)]
+pub(super) struct CelestiaClient {
+    client: reqwest::Client,
+    url: Url,
+    auth_token: Option<String>,
+    namespace: Vec<u8>,
+}
+
+/// A blob, as returned by `blob.GetAll`.
+#[derive(Debug, Deserialize)]
+struct Blob {
+    data: String,
+    commitment: String,
+}
+
+impl CelestiaClient {
+    pub(super) fn new(cfg: &DaMirrorConfig) -> anyhow::Result<Self> {
+        let url = cfg.url.clone().context("Celestia mirror requires a URL")?;
+        let client = reqwest::Client::builder().timeout(cfg.timeout).build()?;
+        Ok(Self {
+            client,
+            url,
+            auth_token: cfg.auth_token.clone(),
+            namespace: namespace(&cfg.celestia_namespace)?,
+        })
+    }
+
+    /// Call the JSON-RPC method `method`, returning its result.
+    async fn call(&self, method: &str, params: Value) -> anyhow::Result<Value> {
+        let mut req = self.client.post(self.url.clone()).json(&json!({
+            "id": 1,
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        }));
+        if let Some(token) = &self.auth_token {
+            req = req.bearer_auth(token);
+        }
+        let mut res: Value = req
+            .send()
+            .await
+            .context("sending request")?
+            .error_for_status()?
+            .json()
+            .await
+            .context("reading response")?;
+        if let Some(err) = res.get("error") {
+            bail!("{method} failed: {err}");
+        }
+        Ok(res["result"].take())
+    }
+}
+
+#[async_trait]
+impl ExternalDaClient for CelestiaClient {
+    fn layer(&self) -> String {
+        "celestia".into()
+    }
+
+    async fn post(&self, payload: &[u8]) -> anyhow::Result<PostedPayload> {
+        let namespace = BASE64.encode(&self.namespace);
+        let data = BASE64.encode(payload);
+        let height = self
+            .call(
+                "blob.Submit",
+                json!([[{ "namespace": namespace, "data": data, "share_version": 0 }], {}]),
+            )
+            .await?
+            .as_u64()
+            .context("blob.Submit did not return a height")?;
+
+        // `blob.Submit` only tells us the height, so look up the commitment of our blob among
+        // those in our namespace at that height.
+        let blobs: Vec<Blob> = serde_json::from_value(
+            self.call("blob.GetAll", json!([height, [namespace]]))
+                .await?,
+        )
+        .context("malformed blob.GetAll response")?;
+        let blob = blobs
+            .into_iter()
+            .find(|blob| blob.data == data)
+            .context(format!("posted blob not found at height {height}"))?;
+        Ok(PostedPayload {
+            commitment: BASE64
+                .decode(&blob.commitment)
+                .context("malformed blob commitment")?,
+            da_height: Some(height),
+        })
+    }
+}
+
+/// Build a version 0 namespace from a hex-encoded namespace ID.
+fn namespace(id: &str) -> anyhow::Result<Vec<u8>> {
+    let id = hex::decode(id.trim_start_matches("0x")).context("invalid Celestia namespace ID")?;
+    ensure!(
+        !id.is_empty() && id.len() <= NAMESPACE_ID_LEN,
+        "Celestia namespace ID must be between 1 and {NAMESPACE_ID_LEN} bytes"
+    );
+    let mut namespace = vec![0; NAMESPACE_LEN - id.len()];
+    namespace.extend(id);
+    Ok(namespace)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_namespace() {
+        let namespace = namespace("0x0102").unwrap();
+        assert_eq!(namespace.len(), NAMESPACE_LEN);
+        assert_eq!(&namespace[NAMESPACE_LEN - 2..], &[1, 2]);
+        assert!(namespace[..NAMESPACE_LEN - 2].iter().all(|b| *b == 0));
+
+        namespace("").unwrap_err();
+        namespace("00112233445566778899aa").unwrap_err();
+        namespace("xyz").unwrap_err();
+    }
+}
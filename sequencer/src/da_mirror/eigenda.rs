@@ -0,0 +1,55 @@
+//! Mirroring payloads to EigenDA, through an EigenDA proxy.
+
+use anyhow::Context;
+use async_trait::async_trait;
+use url::Url;
+
+use super::{DaMirrorConfig, ExternalDaClient, PostedPayload};
+
+/// Posts payloads as blobs through the REST API of an EigenDA proxy.
+///
+/// The proxy disperses each blob to EigenDA and returns its certificate, which is the commitment
+/// by which the blob can be retrieved from the proxy with `GET /get/0x<commitment>`.
+#[derive(Debug)]
+pub(super) struct EigenDaClient {
+    client: reqwest::Client,
+    url: Url,
+}
+
+impl EigenDaClient {
+    pub(super) fn new(cfg: &DaMirrorConfig) -> anyhow::Result<Self> {
+        let url = cfg.url.clone().context("EigenDA mirror requires a URL")?;
+        let client = reqwest::Client::builder().timeout(cfg.timeout).build()?;
+        Ok(Self { client, url })
+    }
+}
+
+#[async_trait]
+impl ExternalDaClient for EigenDaClient {
+    fn layer(&self) -> String {
+        "eigenda".into()
+    }
+
+    async fn post(&self, payload: &[u8]) -> anyhow::Result<PostedPayload> {
+        let mut url = self.url.join("put")?;
+        url.query_pairs_mut()
+            .append_pair("commitment_mode", "standard");
+        let commitment = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/octet-stream")
+            .body(payload.to_vec())
+            .send()
+            .await
+            .context("sending request")?
+            .error_for_status()
+            .context("EigenDA proxy rejected payload")?
+            .bytes()
+            .await
+            .context("reading commitment")?;
+        Ok(PostedPayload {
+            commitment: commitment.to_vec(),
+            da_height: None,
+        })
+    }
+}
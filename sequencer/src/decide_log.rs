@@ -0,0 +1,80 @@
+//! A replayable log of decide events.
+//!
+//! Consumers of the decide event stream lose every block decided while they are disconnected. The
+//! decide event log records each decided block in persistent storage as part of handling the
+//! decide event, before the event is sent to the event stream, so that a consumer which reconnects
+//! can replay everything after the last block it processed from `events/replay/:from_height` and
+//! then return to the live stream. Delivery is at least once: blocks decided between the end of the
+//! replay and the consumer resubscribing may be received from both, and are identified by height.
+//!
+//! Events for blocks more than [`DECIDE_EVENT_LOG_RETENTION`] below the latest decided block are
+//! pruned.
+
+use std::sync::Arc;
+
+use espresso_types::{v0::traits::SequencerPersistence, DecideEvent};
+use hotshot::types::{Event, EventType};
+use hotshot_types::traits::node_implementation::ConsensusTime;
+
+use crate::SeqTypes;
+
+/// Number of blocks for which decide events are kept.
+pub const DECIDE_EVENT_LOG_RETENTION: u64 = 10_000;
+
+/// How often, in blocks, to prune old events.
+const PRUNE_INTERVAL: u64 = 100;
+
+/// Records decided blocks in persistent storage, for replay.
+#[derive(Debug)]
+pub(crate) struct DecideEventLog<P> {
+    persistence: Arc<P>,
+    pruned: u64,
+}
+
+impl<P: SequencerPersistence> DecideEventLog<P> {
+    pub(crate) fn new(persistence: Arc<P>) -> Self {
+        Self {
+            persistence,
+            pruned: 0,
+        }
+    }
+
+    /// Record the blocks decided by `event`, if it is a decide event.
+    pub(crate) async fn handle_event(&mut self, event: &Event<SeqTypes>) {
+        let events = decide_events(event);
+        let Some(height) = events.last().map(|event| event.height) else {
+            return;
+        };
+        if let Err(err) = self.persistence.store_decide_events(&events).await {
+            tracing::warn!(height, "failed to save decide events: {err:#}");
+        }
+
+        if height >= self.pruned + PRUNE_INTERVAL {
+            match self
+                .persistence
+                .prune_decide_events(height.saturating_sub(DECIDE_EVENT_LOG_RETENTION))
+                .await
+            {
+                Ok(()) => self.pruned = height,
+                Err(err) => tracing::warn!(height, "failed to prune decide events: {err:#}"),
+            }
+        }
+    }
+}
+
+/// The blocks decided by `event`, in order of height.
+fn decide_events(event: &Event<SeqTypes>) -> Vec<DecideEvent> {
+    let EventType::Decide { leaf_chain, .. } = &event.event else {
+        return vec![];
+    };
+    // The leaf chain is ordered from newest to oldest.
+    leaf_chain
+        .iter()
+        .rev()
+        .map(|info| DecideEvent {
+            height: info.leaf.height(),
+            view: event.view_number.u64(),
+            leaf: info.leaf.clone(),
+        })
+        .collect()
+}
@@ -1,10 +1,19 @@
 //! Should probably rename this to "external" or something
 
-use crate::context::TaskList;
+use crate::{
+    context::TaskList,
+    mempool::MempoolSink,
+    network::misbehavior::{Misbehavior, MisbehaviorTracker},
+    state_signature::aggregator::{is_valid_signature, StateSignatureSink},
+    state_sync::{
+        PendingRequests, StateSource, StateSyncRequest, StateSyncResponse, StateSyncServer,
+    },
+};
 use anyhow::{Context, Result};
-use espresso_types::{PubKey, SeqTypes};
+use espresso_types::{PubKey, SeqTypes, Transaction};
 use hotshot::types::{BLSPubKey, Message};
 use hotshot_types::{
+    light_client::StateSignatureRequestBody,
     message::MessageKind,
     traits::{
         network::{BroadcastDelay, ConnectedNetwork, Topic},
@@ -27,6 +36,25 @@ pub enum ExternalMessage {
     /// A response to a roll call request
     /// Contains the identifier of the node
     RollCallResponse(RollCallInfo),
+
+    /// A request for merklized state, to be answered directly to the requester
+    StateSyncRequest {
+        requester: BLSPubKey,
+        id: u64,
+        request: StateSyncRequest,
+    },
+
+    /// A response to a state sync request
+    StateSyncResponse {
+        id: u64,
+        response: StateSyncResponse,
+    },
+
+    /// Pending transactions submitted to another node, to be added to our mempool
+    MempoolTransactions(Vec<Transaction>),
+
+    /// A peer's signature on a light client state, to be aggregated into a quorum bundle
+    StateSignature(StateSignatureRequestBody),
 }
 
 /// Information about a node that is used in a roll call response
@@ -47,9 +75,39 @@ pub struct ExternalEventHandler<V: Versions> {
     // The outbound message queue
     pub outbound_message_sender: Sender<OutboundMessage>,
 
+    // State sync requests we are waiting on responses for, if we are a state sync client
+    state_sync_requests: Option<PendingRequests>,
+
+    // Serves peers' state sync requests from our state
+    state_sync_server: Option<StateSyncServer>,
+
+    // The mempool which receives transactions gossiped by peers
+    mempool: Option<Arc<dyn MempoolSink>>,
+
+    // The aggregator which receives state signatures gossiped by peers
+    state_signatures: Option<Arc<dyn StateSignatureSink>>,
+
+    // Offenses committed by peers, and the peers banned for them
+    misbehavior: Option<Arc<MisbehaviorTracker>>,
+
     _pd: PhantomData<V>,
 }
 
+/// Serializes an external message from `sender` so it can be sent over the network
+pub(crate) fn encode_external_message(
+    sender: &BLSPubKey,
+    message: &ExternalMessage,
+) -> Result<Vec<u8>> {
+    let message_bytes = bincode::serialize(message)?;
+
+    let message = Message::<SeqTypes> {
+        sender: *sender,
+        kind: MessageKind::<SeqTypes>::External(message_bytes),
+    };
+
+    Ok(bincode::serialize(&message)?)
+}
+
 // The different types of outbound messages (broadcast or direct)
 #[derive(Debug)]
 pub enum OutboundMessage {
@@ -64,6 +122,7 @@ impl<V: Versions> ExternalEventHandler<V> {
         network: Arc<N>,
         roll_call_info: RollCallInfo,
         public_key: BLSPubKey,
+        state_sync_requests: Option<PendingRequests>,
     ) -> Result<Self> {
         // Create the outbound message queue
         let (outbound_message_sender, outbound_message_receiver) = channel(10);
@@ -90,22 +149,85 @@ impl<V: Versions> ExternalEventHandler<V> {
             roll_call_info,
             public_key,
             outbound_message_sender,
+            state_sync_requests,
+            state_sync_server: None,
+            mempool: None,
+            state_signatures: None,
+            misbehavior: None,
             _pd: Default::default(),
         })
     }
 
-    /// Handles an event
+    /// Answer state sync requests from peers using `source`
+    pub(crate) fn with_state_source(mut self, source: Arc<dyn StateSource>) -> Self {
+        self.state_sync_server = Some(StateSyncServer::new(source));
+        self
+    }
+
+    /// Add transactions gossiped by peers to `mempool`
+    pub(crate) fn with_mempool(mut self, mempool: Arc<dyn MempoolSink>) -> Self {
+        self.mempool = Some(mempool);
+        self
+    }
+
+    /// Aggregate state signatures gossiped by peers using `aggregator`
+    pub(crate) fn with_state_signatures(mut self, aggregator: Arc<dyn StateSignatureSink>) -> Self {
+        self.state_signatures = Some(aggregator);
+        self
+    }
+
+    /// Report misbehaving peers to `tracker`, and ignore messages from peers it has banned
+    pub(crate) fn with_misbehavior_tracker(mut self, tracker: Arc<MisbehaviorTracker>) -> Self {
+        self.misbehavior = Some(tracker);
+        self
+    }
+
+    /// Records an offense committed by `peer`, if we are tracking misbehavior
+    fn report(&self, peer: BLSPubKey, offense: Misbehavior) {
+        if let Some(tracker) = &self.misbehavior {
+            tracker.report(peer, offense);
+        }
+    }
+
+    /// Handles an event sent by `sender`
     ///
     /// # Errors
     /// If the message type is unknown or if there is an error serializing or deserializing the message
-    pub async fn handle_event(&self, external_message_bytes: &[u8]) -> Result<()> {
+    pub async fn handle_event(
+        &self,
+        sender: BLSPubKey,
+        external_message_bytes: &[u8],
+    ) -> Result<()> {
+        if self
+            .misbehavior
+            .as_ref()
+            .is_some_and(|tracker| tracker.is_banned(&sender))
+        {
+            tracing::debug!(%sender, "ignoring external message from banned peer");
+            return Ok(());
+        }
+
         // Deserialize the external message
-        let external_message = bincode::deserialize(external_message_bytes)
-            .with_context(|| "Failed to deserialize external message")?;
+        let external_message = match bincode::deserialize(external_message_bytes) {
+            Ok(message) => message,
+            Err(err) => {
+                self.report(sender, Misbehavior::MalformedMessage);
+                return Err(err).with_context(|| "Failed to deserialize external message");
+            }
+        };
 
         // Match the type
         match external_message {
             ExternalMessage::RollCallRequest(pub_key) => {
+                if pub_key != sender {
+                    // Responses go to `pub_key`, so a peer could use roll calls to flood another
+                    // node with messages.
+                    self.report(sender, Misbehavior::ProtocolViolation);
+                    return Err(anyhow::anyhow!(
+                        "Roll call requested on behalf of another node"
+                    ));
+                }
+
                 if self.roll_call_info.public_api_url.is_none() {
                     // We don't have a public API URL, so we can't respond to the roll call
                     return Ok(());
@@ -124,6 +246,59 @@ impl<V: Versions> ExternalEventHandler<V> {
                     .with_context(|| "External outbound message queue is full")?;
             }
 
+            ExternalMessage::StateSyncRequest {
+                requester,
+                id,
+                request,
+            } => {
+                if requester != sender {
+                    // As with roll calls, responses go to the requester.
+                    self.report(sender, Misbehavior::ProtocolViolation);
+                    return Err(anyhow::anyhow!(
+                        "State sync requested on behalf of another node"
+                    ));
+                }
+                let Some(server) = &self.state_sync_server else {
+                    // We aren't serving state yet
+                    return Ok(());
+                };
+                let Some(response) = server.respond(requester, request).await else {
+                    // The requester is over its rate limit
+                    return Ok(());
+                };
+
+                let response = ExternalMessage::StateSyncResponse { id, response };
+                let response_bytes = encode_external_message(&self.public_key, &response)
+                    .with_context(|| "Failed to serialize state sync response")?;
+
+                // Send the response
+                self.outbound_message_sender
+                    .try_send(OutboundMessage::Direct(response_bytes, requester))
+                    .with_context(|| "External outbound message queue is full")?;
+            }
+
+            ExternalMessage::StateSyncResponse { id, response } => {
+                if let Some(requests) = &self.state_sync_requests {
+                    requests.deliver(id, response);
+                }
+            }
+
+            ExternalMessage::MempoolTransactions(txs) => {
+                if let Some(mempool) = &self.mempool {
+                    mempool.receive_gossip(txs).await;
+                }
+            }
+
+            ExternalMessage::StateSignature(signature) => {
+                if !is_valid_signature(&signature) {
+                    self.report(sender, Misbehavior::InvalidSignature);
+                    return Err(anyhow::anyhow!("Invalid state signature"));
+                }
+                if let Some(aggregator) = &self.state_signatures {
+                    aggregator.receive_gossip(signature).await;
+                }
+            }
+
             _ => {
                 return Err(anyhow::anyhow!("Unknown external message type"));
             }
@@ -138,19 +313,8 @@ impl<V: Versions> ExternalEventHandler<V> {
     ) -> Result<Vec<u8>> {
         let response = ExternalMessage::RollCallResponse(roll_call_info.clone());
 
-        // Serialize the response
-        let response_bytes = bincode::serialize(&response)
-            .with_context(|| "Failed to serialize roll call response")?;
-
-        let message = Message::<SeqTypes> {
-            sender: *public_key,
-            kind: MessageKind::<SeqTypes>::External(response_bytes),
-        };
-
-        let response_bytes = bincode::serialize(&message)
-            .with_context(|| "Failed to serialize roll call response")?;
-
-        Ok(response_bytes)
+        encode_external_message(public_key, &response)
+            .with_context(|| "Failed to serialize roll call response")
     }
 
     /// The main loop for sending outbound messages.
@@ -3,12 +3,16 @@ use std::{
     path::Path,
 };
 
-use anyhow::Context;
+use anyhow::{ensure, Context};
 use espresso_types::{
-    v0_3::ChainConfig, FeeAccount, FeeAmount, GenesisHeader, L1BlockInfo, L1Client, Timestamp,
-    Upgrade, UpgradeType,
+    v0_3::ChainConfig, FeeAccount, FeeAmount, GenesisHeader, L1BlockInfo, L1Client, PubKey,
+    Timestamp, Upgrade, UpgradeMode, UpgradeType,
 };
 use ethers::types::H160;
+use hotshot_types::{
+    light_client::StateVerKey, network::NetworkConfig, traits::signature_key::SignatureKey,
+    PeerConfig,
+};
 use sequencer_utils::deployer::is_proxy_contract;
 use serde::{Deserialize, Serialize};
 use vbs::version::Version;
@@ -17,6 +21,97 @@ use vbs::version::Version;
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct StakeTableConfig {
     pub capacity: u64,
+
+    /// The initial stake table.
+    ///
+    /// If empty, the stake table is taken from the network config provided by the orchestrator or
+    /// by peers.
+    #[serde(rename = "node", default, skip_serializing_if = "Vec::is_empty")]
+    pub nodes: Vec<GenesisNode>,
+}
+
+/// A node in the initial stake table.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct GenesisNode {
+    pub stake_key: PubKey,
+    pub state_key: StateVerKey,
+    pub stake: u64,
+    /// Whether this node is a member of the DA committee.
+    #[serde(default)]
+    pub da: bool,
+}
+
+impl StakeTableConfig {
+    /// Replace the stake table in `config` with the initial stake table, if there is one.
+    ///
+    /// `pub_key` is the staking key of this node, which must be in the stake table.
+    pub fn apply(&self, config: &mut NetworkConfig<PubKey>, pub_key: PubKey) -> anyhow::Result<()> {
+        if self.nodes.is_empty() {
+            return Ok(());
+        }
+
+        let peers = self
+            .nodes
+            .iter()
+            .map(|node| PeerConfig {
+                stake_table_entry: node.stake_key.stake_table_entry(node.stake),
+                state_ver_key: node.state_key.clone(),
+            })
+            .collect::<Vec<_>>();
+        let da_peers = self
+            .nodes
+            .iter()
+            .zip(&peers)
+            .filter(|(node, _)| node.da)
+            .map(|(_, peer)| peer.clone())
+            .collect::<Vec<_>>();
+
+        config.node_index = self
+            .nodes
+            .iter()
+            .position(|node| node.stake_key == pub_key)
+            .context(format!("node {pub_key} is not in the genesis stake table"))?
+            as u64;
+        config.config.num_nodes_with_stake = peers.len().try_into()?;
+        config.config.da_staked_committee_size = da_peers.len();
+        config.config.known_nodes_with_stake = peers;
+        config.config.known_da_nodes = da_peers;
+        Ok(())
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.nodes.len() as u64 <= self.capacity,
+            "genesis stake table has {} nodes, but capacity is {}",
+            self.nodes.len(),
+            self.capacity
+        );
+        if self.nodes.is_empty() {
+            return Ok(());
+        }
+        ensure!(
+            self.nodes.iter().any(|node| node.da),
+            "genesis stake table has no DA committee members"
+        );
+        for (i, node) in self.nodes.iter().enumerate() {
+            ensure!(node.stake > 0, "node {} has no stake", node.stake_key);
+            ensure!(
+                !self.nodes[..i]
+                    .iter()
+                    .any(|other| other.stake_key == node.stake_key),
+                "duplicate stake key {}",
+                node.stake_key
+            );
+            ensure!(
+                !self.nodes[..i]
+                    .iter()
+                    .any(|other| other.state_key == node.state_key),
+                "duplicate state key {}",
+                node.state_key
+            );
+        }
+        Ok(())
+    }
 }
 
 /// An L1 block from which an Espresso chain should start syncing.
@@ -85,6 +180,65 @@ impl Genesis {
 }
 
 impl Genesis {
+    /// Check that the genesis configuration is consistent.
+    ///
+    /// This does not require an L1 connection; see [`validate_fee_contract`](Self::validate_fee_contract)
+    /// for checks against the L1.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.base_version <= self.upgrade_version,
+            "upgrade version {} is older than base version {}",
+            self.upgrade_version,
+            self.base_version
+        );
+        for (version, upgrade) in &self.upgrades {
+            ensure!(
+                *version > self.base_version && *version <= self.upgrade_version,
+                "upgrade to {version} is outside of the range from base version {} to upgrade \
+                 version {}",
+                self.base_version,
+                self.upgrade_version
+            );
+
+            let (UpgradeType::Fee { chain_config } | UpgradeType::Marketplace { chain_config }) =
+                &upgrade.upgrade_type;
+            ensure!(
+                chain_config.chain_id == self.chain_config.chain_id,
+                "upgrade to {version} changes the chain ID"
+            );
+
+            match &upgrade.mode {
+                UpgradeMode::View(mode) => {
+                    ensure!(
+                        mode.start_proposing_view < mode.stop_proposing_view,
+                        "upgrade to {version} has an empty proposing window"
+                    );
+                    ensure!(
+                        mode.start_voting_view.unwrap_or(0)
+                            < mode.stop_voting_view.unwrap_or(u64::MAX),
+                        "upgrade to {version} has an empty voting window"
+                    );
+                }
+                UpgradeMode::Time(mode) => {
+                    ensure!(
+                        mode.start_proposing_time.unix_timestamp()
+                            < mode.stop_proposing_time.unix_timestamp(),
+                        "upgrade to {version} has an empty proposing window"
+                    );
+                    ensure!(
+                        mode.start_voting_time.unwrap_or_default().unix_timestamp()
+                            < mode
+                                .stop_voting_time
+                                .unwrap_or(Timestamp::max())
+                                .unix_timestamp(),
+                        "upgrade to {version} has an empty voting window"
+                    );
+                }
+            }
+        }
+        self.stake_table.validate()
+    }
+
     pub async fn validate_fee_contract(&self, l1_rpc_url: String) -> anyhow::Result<()> {
         let l1 = L1Client::new(l1_rpc_url.parse().context("invalid url")?)
             .await
@@ -313,7 +467,11 @@ impl Genesis {
         let bytes = std::fs::read(path).context(format!("genesis file {}", path.display()))?;
         let text = std::str::from_utf8(&bytes).context("genesis file must be UTF-8")?;
 
-        toml::from_str(text).context("malformed genesis file")
+        let genesis: Self = toml::from_str(text).context("malformed genesis file")?;
+        genesis
+            .validate()
+            .context(format!("invalid genesis file {}", path.display()))?;
+        Ok(genesis)
     }
 }
 
@@ -334,6 +492,7 @@ mod test {
     use espresso_types::{
         L1BlockInfo, TimeBasedUpgrade, Timestamp, UpgradeMode, UpgradeType, ViewBasedUpgrade,
     };
+    use hotshot_types::light_client::StateKeyPair;
 
     use sequencer_utils::deployer;
     use sequencer_utils::ser::FromStringOrInteger;
@@ -408,7 +567,13 @@ mod test {
         .to_string();
 
         let genesis: Genesis = toml::from_str(&toml).unwrap_or_else(|err| panic!("{err:#}"));
-        assert_eq!(genesis.stake_table, StakeTableConfig { capacity: 10 });
+        assert_eq!(
+            genesis.stake_table,
+            StakeTableConfig {
+                capacity: 10,
+                nodes: vec![]
+            }
+        );
         assert_eq!(
             genesis.chain_config,
             ChainConfig {
@@ -479,7 +644,13 @@ mod test {
         .to_string();
 
         let genesis: Genesis = toml::from_str(&toml).unwrap_or_else(|err| panic!("{err:#}"));
-        assert_eq!(genesis.stake_table, StakeTableConfig { capacity: 10 });
+        assert_eq!(
+            genesis.stake_table,
+            StakeTableConfig {
+                capacity: 10,
+                nodes: vec![]
+            }
+        );
         assert_eq!(
             genesis.chain_config,
             ChainConfig {
@@ -943,7 +1114,13 @@ mod test {
         .to_string();
 
         let genesis: Genesis = toml::from_str(&toml).unwrap_or_else(|err| panic!("{err:#}"));
-        assert_eq!(genesis.stake_table, StakeTableConfig { capacity: 10 });
+        assert_eq!(
+            genesis.stake_table,
+            StakeTableConfig {
+                capacity: 10,
+                nodes: vec![]
+            }
+        );
         assert_eq!(*genesis.chain_config.max_block_size, 30000000);
         assert_eq!(genesis.chain_config.base_fee, 1_000_000_000.into());
         assert_eq!(
@@ -1250,4 +1427,161 @@ mod test {
 
         toml::from_str::<Genesis>(&toml).unwrap();
     }
+
+    fn stake_table_toml() -> (Vec<PubKey>, String) {
+        let mut keys = vec![];
+        let mut toml = toml! {
+            base_version = "0.2"
+            upgrade_version = "0.3"
+
+            [chain_config]
+            chain_id = 12345
+            max_block_size = 30000
+            base_fee = 1
+            fee_recipient = "0x0000000000000000000000000000000000000000"
+
+            [header]
+            timestamp = 123456
+
+            [l1_finalized]
+            number = 0
+
+            [stake_table]
+            capacity = 10
+        }
+        .to_string();
+        for i in 0..3 {
+            let (stake_key, _) = PubKey::generated_from_seed_indexed([0; 32], i);
+            let state_key = StateKeyPair::generate_from_seed_indexed([0; 32], i).ver_key();
+            toml += &format!(
+                "\n[[stake_table.node]]\nstake_key = \"{stake_key}\"\nstate_key = \"{state_key}\"\nstake = {}\nda = {}\n",
+                i + 1,
+                i != 2
+            );
+            keys.push(stake_key);
+        }
+        (keys, toml)
+    }
+
+    #[test]
+    fn test_genesis_stake_table() {
+        let (keys, toml) = stake_table_toml();
+        let genesis: Genesis = toml::from_str(&toml).unwrap_or_else(|err| panic!("{err:#}"));
+        genesis.validate().unwrap();
+        assert_eq!(genesis.stake_table.nodes.len(), 3);
+
+        // The stake table round trips through TOML.
+        let round_trip: Genesis =
+            toml::from_str(&toml::to_string_pretty(&genesis).unwrap()).unwrap();
+        assert_eq!(round_trip.stake_table, genesis.stake_table);
+
+        let mut config = NetworkConfig::<PubKey>::default();
+        genesis.stake_table.apply(&mut config, keys[1]).unwrap();
+        assert_eq!(config.node_index, 1);
+        assert_eq!(config.config.num_nodes_with_stake.get(), 3);
+        assert_eq!(config.config.da_staked_committee_size, 2);
+        assert_eq!(
+            config
+                .config
+                .known_nodes_with_stake
+                .iter()
+                .map(|peer| (
+                    peer.stake_table_entry.stake_key,
+                    peer.stake_table_entry.stake_amount.as_u64()
+                ))
+                .collect::<Vec<_>>(),
+            [(keys[0], 1), (keys[1], 2), (keys[2], 3)]
+        );
+        assert_eq!(
+            config
+                .config
+                .known_da_nodes
+                .iter()
+                .map(|peer| peer.stake_table_entry.stake_key)
+                .collect::<Vec<_>>(),
+            keys[..2]
+        );
+
+        // A node which is not in the stake table cannot start.
+        let (outsider, _) = PubKey::generated_from_seed_indexed([1; 32], 0);
+        genesis
+            .stake_table
+            .apply(&mut NetworkConfig::default(), outsider)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_genesis_validation() {
+        let (_, toml) = stake_table_toml();
+        let genesis: Genesis = toml::from_str(&toml).unwrap();
+
+        // Too many nodes for the stake table.
+        let mut invalid = genesis.clone();
+        invalid.stake_table.capacity = 2;
+        invalid.validate().unwrap_err();
+
+        // Duplicate nodes.
+        let mut invalid = genesis.clone();
+        invalid.stake_table.nodes[2].stake_key = invalid.stake_table.nodes[0].stake_key;
+        invalid.validate().unwrap_err();
+
+        // No DA committee.
+        let mut invalid = genesis.clone();
+        for node in &mut invalid.stake_table.nodes {
+            node.da = false;
+        }
+        invalid.validate().unwrap_err();
+
+        // Upgrade to an older version.
+        let mut invalid = genesis.clone();
+        invalid.upgrade_version = Version { major: 0, minor: 1 };
+        invalid.validate().unwrap_err();
+
+        // Upgrade which changes the chain ID.
+        let upgrade = |chain_id: u64, mode| Upgrade {
+            mode,
+            upgrade_type: UpgradeType::Marketplace {
+                chain_config: ChainConfig {
+                    chain_id: chain_id.into(),
+                    ..genesis.chain_config
+                },
+            },
+        };
+        let view_mode = |start, stop| {
+            UpgradeMode::View(ViewBasedUpgrade {
+                start_proposing_view: start,
+                stop_proposing_view: stop,
+                start_voting_view: None,
+                stop_voting_view: None,
+            })
+        };
+        let mut valid = genesis.clone();
+        valid.upgrades.insert(
+            Version { major: 0, minor: 3 },
+            upgrade(12345, view_mode(5, 15)),
+        );
+        valid.validate().unwrap();
+
+        let mut invalid = genesis.clone();
+        invalid
+            .upgrades
+            .insert(Version { major: 0, minor: 3 }, upgrade(1, view_mode(5, 15)));
+        invalid.validate().unwrap_err();
+
+        // Upgrade with an empty window.
+        let mut invalid = genesis.clone();
+        invalid.upgrades.insert(
+            Version { major: 0, minor: 3 },
+            upgrade(12345, view_mode(15, 5)),
+        );
+        invalid.validate().unwrap_err();
+
+        // Upgrade to a version beyond the upgrade version.
+        let mut invalid = genesis.clone();
+        invalid.upgrades.insert(
+            Version { major: 0, minor: 4 },
+            upgrade(12345, view_mode(5, 15)),
+        );
+        invalid.validate().unwrap_err();
+    }
 }
@@ -0,0 +1,538 @@
+//! Verification and repair of availability storage.
+//!
+//! Query service storage is written once, as blocks are decided, and afterwards only read, so
+//! corruption caused by disk errors, interrupted writes or manual tampering can go unnoticed until
+//! a client is served bad data. [`verify`] walks a range of blocks in storage and checks that each
+//! entry is consistent with the rest of the chain:
+//!
+//! * each leaf is certified by its QC and extends the leaf before it,
+//! * each header matches its leaf, and its block Merkle root commits to exactly the blocks before
+//!   it,
+//! * each VID common and payload match the payload commitment in the header.
+//!
+//! Optionally, the merklized state at the last block checked is compared against the roots in its
+//! header. Entries which are missing or fail a check can be repaired by fetching them from peer
+//! query services, in which case the replacement is subject to the same checks before it is
+//! stored.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, ensure, Context};
+use committable::Committable;
+use derive_more::Display;
+use espresso_types::{Header, Payload};
+use hotshot_query_service::{
+    availability::{
+        AvailabilityDataSource, BlockInfo, BlockQueryData, LeafQueryData, UpdateAvailabilityData,
+        VidCommonQueryData,
+    },
+    fetching::{
+        provider::Provider as _,
+        request::{PayloadRequest, VidCommonRequest},
+    },
+    status::StatusDataSource,
+};
+use hotshot_types::{
+    traits::{block_contents::vid_commitment, EncodeBytes},
+    vid::{VidCommon, VidSchemeType},
+};
+use jf_merkle_tree::MerkleCommitment;
+use jf_vid::VidScheme;
+use surf_disco::Client;
+use tide_disco::{error::ServerError, Url};
+use tokio::time::timeout;
+use vbs::version::StaticVersionType;
+
+use crate::{
+    api::{
+        data_source::{provider, DataSourceOptions, Provider, SequencerDataSource},
+        peers::QueryPeers,
+    },
+    SeqTypes, SequencerApiVersion,
+};
+
+/// Options for verifying availability storage.
+#[derive(Clone, Debug, Default)]
+pub struct VerifyOptions {
+    /// First block to check.
+    pub from: u64,
+    /// Block to stop checking at, exclusive. Defaults to the block height of the storage.
+    pub to: Option<u64>,
+    /// Whether to compare the merklized state at the last block checked against its header.
+    pub check_state: bool,
+    /// Peer query services to fetch replacements for bad entries from.
+    ///
+    /// If empty, problems are only reported.
+    pub repair_from: Vec<Url>,
+    /// How long to wait for each replacement to be fetched.
+    pub fetch_timeout: Duration,
+}
+
+/// A kind of entry in availability storage.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq)]
+pub enum Entry {
+    #[display("leaf")]
+    Leaf,
+    #[display("block")]
+    Block,
+    #[display("VID common")]
+    VidCommon,
+    #[display("state")]
+    State,
+}
+
+/// An entry which is missing from storage, or inconsistent with the rest of the chain.
+#[derive(Clone, Debug)]
+pub struct Problem {
+    pub height: u64,
+    pub entry: Entry,
+    pub error: String,
+    /// Whether a valid replacement was fetched and stored.
+    pub repaired: bool,
+}
+
+/// The outcome of verifying storage.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub from: u64,
+    pub to: u64,
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    /// Problems which have not been repaired.
+    pub fn unrepaired(&self) -> impl Iterator<Item = &Problem> {
+        self.problems.iter().filter(|problem| !problem.repaired)
+    }
+}
+
+/// Verify the availability storage described by `opt`.
+///
+/// The node using this storage must not be running.
+pub async fn verify<O>(opt: O, cfg: VerifyOptions) -> anyhow::Result<Report>
+where
+    O: DataSourceOptions,
+    O::DataSource: UpdateAvailabilityData<SeqTypes>,
+{
+    // Missing data must be reported rather than fetched, so the data source gets no provider of
+    // its own; repairs are fetched explicitly so that they can be checked before being stored.
+    let storage = O::DataSource::create(opt.query_options(), Default::default(), false).await?;
+    let to = match cfg.to {
+        Some(to) => to,
+        None => storage
+            .block_height()
+            .await
+            .context("loading block height")? as u64,
+    };
+    let peers = QueryPeers::new(cfg.repair_from.clone(), SequencerApiVersion::instance());
+    let verifier = Verifier {
+        storage,
+        repair: (!cfg.repair_from.is_empty()).then(|| provider(peers.clone(), None)),
+        peers,
+        fetch_timeout: cfg.fetch_timeout,
+    };
+    tracing::info!(
+        from = cfg.from,
+        to,
+        repair = verifier.repair.is_some(),
+        "verifying storage"
+    );
+
+    let mut report = Report {
+        from: cfg.from,
+        to,
+        problems: vec![],
+    };
+    let mut parent = None;
+    for height in cfg.from..to {
+        parent = verifier.verify_block(height, parent, &mut report).await;
+        if height % 10_000 == 0 {
+            tracing::info!(
+                height,
+                problems = report.problems.len(),
+                "verifying storage"
+            );
+        }
+    }
+
+    if cfg.check_state && to > cfg.from {
+        if let Err(err) = verifier.verify_state(to - 1).await {
+            report.problems.push(Problem {
+                height: to - 1,
+                entry: Entry::State,
+                error: format!("{err:#}"),
+                repaired: false,
+            });
+        }
+    }
+
+    for problem in &report.problems {
+        tracing::warn!(
+            height = problem.height,
+            entry = %problem.entry,
+            repaired = problem.repaired,
+            "{}",
+            problem.error
+        );
+    }
+    Ok(report)
+}
+
+struct Verifier<D> {
+    storage: D,
+    peers: QueryPeers,
+    repair: Option<Provider>,
+    fetch_timeout: Duration,
+}
+
+impl<D> Verifier<D>
+where
+    D: SequencerDataSource + UpdateAvailabilityData<SeqTypes>,
+{
+    /// Verify and, if possible, repair the entries for the block at `height`.
+    ///
+    /// Returns the leaf at `height`, if a valid one is stored, to check the next leaf against.
+    async fn verify_block(
+        &self,
+        height: u64,
+        parent: Option<LeafQueryData<SeqTypes>>,
+        report: &mut Report,
+    ) -> Option<LeafQueryData<SeqTypes>> {
+        let mut problem = |entry, err: anyhow::Error, repaired| {
+            report.problems.push(Problem {
+                height,
+                entry,
+                error: format!("{err:#}"),
+                repaired,
+            })
+        };
+
+        // Everything else is checked against the leaf, so without a valid leaf there is nothing
+        // more we can check for this block.
+        let leaf = match self.stored_leaf(height, parent.as_ref()).await {
+            Ok(leaf) => leaf,
+            Err(err) => match self.repair_leaf(height, parent.as_ref()).await {
+                Ok(leaf) => {
+                    problem(Entry::Leaf, err, true);
+                    leaf
+                }
+                Err(repair_err) => {
+                    problem(Entry::Leaf, self.unrepaired(err, repair_err), false);
+                    return None;
+                }
+            },
+        };
+        let header = leaf.header();
+
+        // The payload can only be checked against a valid VID common.
+        let common = match self.stored_vid_common(height, header).await {
+            Ok(common) => Some(common),
+            Err(err) => match self.repair_vid_common(&leaf).await {
+                Ok(common) => {
+                    problem(Entry::VidCommon, err, true);
+                    Some(common)
+                }
+                Err(repair_err) => {
+                    problem(Entry::VidCommon, self.unrepaired(err, repair_err), false);
+                    None
+                }
+            },
+        };
+        if let Some(common) = common {
+            if let Err(err) = self.stored_block(height, header, &common).await {
+                match self.repair_block(&leaf, &common).await {
+                    Ok(()) => problem(Entry::Block, err, true),
+                    Err(repair_err) => {
+                        problem(Entry::Block, self.unrepaired(err, repair_err), false)
+                    }
+                }
+            }
+        }
+
+        Some(leaf)
+    }
+
+    async fn stored_leaf(
+        &self,
+        height: u64,
+        parent: Option<&LeafQueryData<SeqTypes>>,
+    ) -> anyhow::Result<LeafQueryData<SeqTypes>> {
+        let leaf = self
+            .storage
+            .get_leaf(height as usize)
+            .await
+            .try_resolve()
+            .map_err(|_| anyhow!("leaf is missing"))?;
+        check_leaf(height, &leaf, parent)?;
+        Ok(leaf)
+    }
+
+    async fn stored_vid_common(&self, height: u64, header: &Header) -> anyhow::Result<VidCommon> {
+        let common = self
+            .storage
+            .get_vid_common(height as usize)
+            .await
+            .try_resolve()
+            .map_err(|_| anyhow!("VID common is missing"))?;
+        check_vid_common(header, common.common())?;
+        Ok(common.common().clone())
+    }
+
+    async fn stored_block(
+        &self,
+        height: u64,
+        header: &Header,
+        common: &VidCommon,
+    ) -> anyhow::Result<()> {
+        let block = self
+            .storage
+            .get_block(height as usize)
+            .await
+            .try_resolve()
+            .map_err(|_| anyhow!("block is missing"))?;
+        ensure!(
+            block.header().commit() == header.commit(),
+            "header does not match leaf"
+        );
+        check_payload(header, block.payload(), common)
+    }
+
+    /// Fetch the leaf at `height` from peers, and store it if it is valid.
+    async fn repair_leaf(
+        &self,
+        height: u64,
+        parent: Option<&LeafQueryData<SeqTypes>>,
+    ) -> anyhow::Result<LeafQueryData<SeqTypes>> {
+        ensure!(self.repair.is_some(), "repair not enabled");
+
+        // Try each peer until one gives us a leaf which is consistent with the chain.
+        let mut err = anyhow!("no peers to fetch from");
+        for url in self.peers.list().await {
+            let client = Client::<ServerError, SequencerApiVersion>::new(url.clone());
+            let res = timeout(
+                self.fetch_timeout,
+                client
+                    .get::<LeafQueryData<SeqTypes>>(&format!("availability/leaf/{height}"))
+                    .send(),
+            )
+            .await;
+            let leaf = match res {
+                Ok(Ok(leaf)) => leaf,
+                Ok(Err(fetch_err)) => {
+                    err = anyhow!("fetching leaf from {url}: {fetch_err}");
+                    continue;
+                }
+                Err(_) => {
+                    err = anyhow!("timed out fetching leaf from {url}");
+                    continue;
+                }
+            };
+            if let Err(check_err) = check_leaf(height, &leaf, parent) {
+                err = check_err.context(format!("invalid leaf from {url}"));
+                continue;
+            }
+            self.storage
+                .append(BlockInfo::new(leaf.clone(), None, None, None))
+                .await
+                .context("storing repaired leaf")?;
+            return Ok(leaf);
+        }
+        Err(err)
+    }
+
+    /// Fetch the VID common for `leaf` from peers, and store it if it is valid.
+    async fn repair_vid_common(&self, leaf: &LeafQueryData<SeqTypes>) -> anyhow::Result<VidCommon> {
+        let provider = self.repair.as_ref().context("repair not enabled")?;
+        let header = leaf.header();
+        let common = timeout(
+            self.fetch_timeout,
+            provider.fetch(VidCommonRequest(header.payload_commitment())),
+        )
+        .await
+        .ok()
+        .flatten()
+        .context("VID common not available from peers")?;
+        check_vid_common(header, &common).context("invalid VID common from peers")?;
+        self.storage
+            .append(BlockInfo::new(
+                leaf.clone(),
+                None,
+                Some(VidCommonQueryData::new(header.clone(), common.clone())),
+                None,
+            ))
+            .await
+            .context("storing repaired VID common")?;
+        Ok(common)
+    }
+
+    /// Fetch the payload for `leaf` from peers, and store it if it is valid.
+    async fn repair_block(
+        &self,
+        leaf: &LeafQueryData<SeqTypes>,
+        common: &VidCommon,
+    ) -> anyhow::Result<()> {
+        let provider = self.repair.as_ref().context("repair not enabled")?;
+        let header = leaf.header();
+        let payload = timeout(
+            self.fetch_timeout,
+            provider.fetch(PayloadRequest(header.payload_commitment())),
+        )
+        .await
+        .ok()
+        .flatten()
+        .context("payload not available from peers")?;
+        check_payload(header, &payload, common).context("invalid payload from peers")?;
+        self.storage
+            .append(BlockInfo::new(
+                leaf.clone(),
+                Some(BlockQueryData::new(header.clone(), payload)),
+                None,
+                None,
+            ))
+            .await
+            .context("storing repaired block")
+    }
+
+    fn unrepaired(&self, err: anyhow::Error, repair_err: anyhow::Error) -> anyhow::Error {
+        if self.repair.is_some() {
+            err.context(format!("could not repair: {repair_err:#}"))
+        } else {
+            err
+        }
+    }
+
+    /// Check the merklized state at `height` against the roots in its header.
+    async fn verify_state(&self, height: u64) -> anyhow::Result<()> {
+        let Some(state) = self.storage.load_state_snapshot(height).await? else {
+            tracing::warn!("storage does not persist merklized state, not checking state");
+            return Ok(());
+        };
+        let leaf = self
+            .storage
+            .get_leaf(height as usize)
+            .await
+            .try_resolve()
+            .map_err(|_| anyhow!("leaf is missing"))?;
+        let header = leaf.header();
+        ensure!(
+            state.fee_merkle_tree.commitment() == header.fee_merkle_tree_root(),
+            "fee state does not match header"
+        );
+        ensure!(
+            state.block_merkle_tree.commitment() == header.block_merkle_tree_root(),
+            "block state does not match header"
+        );
+        Ok(())
+    }
+}
+
+/// Check that `leaf` is a valid leaf at `height`, extending `parent` if it is known.
+fn check_leaf(
+    height: u64,
+    leaf: &LeafQueryData<SeqTypes>,
+    parent: Option<&LeafQueryData<SeqTypes>>,
+) -> anyhow::Result<()> {
+    ensure!(leaf.height() == height, "leaf has height {}", leaf.height());
+    ensure!(
+        leaf.qc().data.leaf_commit == leaf.leaf().commit(),
+        "leaf is not certified by its QC"
+    );
+    if let Some(parent) = parent {
+        ensure!(
+            leaf.leaf().parent_commitment() == parent.leaf().commit(),
+            "leaf does not extend leaf {}",
+            parent.height()
+        );
+    }
+
+    // The block Merkle tree as of each block contains every block before it.
+    let header = leaf.header();
+    ensure!(
+        header.height() == height,
+        "header has height {}",
+        header.height()
+    );
+    let root = header.block_merkle_tree_root();
+    ensure!(
+        root.size() == height,
+        "block Merkle root commits to {} blocks",
+        root.size()
+    );
+    Ok(())
+}
+
+fn check_vid_common(header: &Header, common: &VidCommon) -> anyhow::Result<()> {
+    VidSchemeType::is_consistent(&header.payload_commitment(), common)
+        .map_err(|err| anyhow!("VID common does not match payload commitment: {err}"))
+}
+
+fn check_payload(header: &Header, payload: &Payload, common: &VidCommon) -> anyhow::Result<()> {
+    let num_storage_nodes = VidSchemeType::get_num_storage_nodes(common) as usize;
+    ensure!(
+        vid_commitment(&payload.encode(), num_storage_nodes) == header.payload_commitment(),
+        "payload does not match payload commitment"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use espresso_types::{Leaf, NamespaceId, NodeState, Transaction, ValidatedState};
+    use hotshot::traits::BlockPayload;
+    use hotshot_example_types::node_types::TestVersions;
+    use hotshot_types::{
+        simple_certificate::QuorumCertificate,
+        traits::block_contents::GENESIS_VID_NUM_STORAGE_NODES, vid::vid_scheme,
+    };
+
+    use super::*;
+
+    async fn genesis() -> LeafQueryData<SeqTypes> {
+        let state = ValidatedState::default();
+        let instance = NodeState::mock();
+        LeafQueryData::new(
+            Leaf::genesis(&state, &instance).await,
+            QuorumCertificate::genesis::<TestVersions>(&state, &instance).await,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_check_leaf() {
+        let leaf = genesis().await;
+        check_leaf(0, &leaf, None).unwrap();
+        check_leaf(1, &leaf, None).unwrap_err();
+
+        // The genesis leaf does not extend itself.
+        check_leaf(0, &leaf, Some(&leaf)).unwrap_err();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_check_payload() {
+        // The genesis block has an empty payload, committed for a single storage node.
+        let leaf = genesis().await;
+        let header = leaf.header();
+        let payload = Payload::empty().0;
+        let common = vid_scheme(GENESIS_VID_NUM_STORAGE_NODES)
+            .disperse(payload.encode())
+            .unwrap()
+            .common;
+        check_vid_common(header, &common).unwrap();
+        check_payload(header, &payload, &common).unwrap();
+
+        let (other, _) = Payload::from_transactions(
+            [Transaction::new(NamespaceId::from(1u32), vec![1])],
+            &Default::default(),
+            &NodeState::mock(),
+        )
+        .await
+        .unwrap();
+        check_payload(header, &other, &common).unwrap_err();
+
+        let other_common = vid_scheme(GENESIS_VID_NUM_STORAGE_NODES)
+            .disperse(other.encode())
+            .unwrap()
+            .common;
+        check_vid_common(header, &other_common).unwrap_err();
+    }
+}
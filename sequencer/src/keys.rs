@@ -0,0 +1,504 @@
+//! Loading of node private keys from external key management systems.
+//!
+//! By default, a node reads its private staking and state keys from a key file or from environment
+//! variables. Operators who do not want plaintext keys on the node host can instead select a
+//! [`KeyProvider`] with `--key-provider`:
+//!
+//! * `vault`: the keys are read from a HashiCorp Vault KV secret, with the same variable names as a
+//!   key file.
+//! * `aws-kms`: the node decrypts a key file which was encrypted with an AWS KMS key.
+//! * `keystore`: the node decrypts a key file stored in an encrypted keystore file (the Web3 Secret
+//!   Storage format used for Ethereum keys), which can be generated with `keygen
+//!   --keystore-password-file`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use clap::{Parser, ValueEnum};
+use derivative::Derivative;
+use derive_more::Display;
+use ethers::utils::hex;
+use hmac::{Hmac, Mac};
+use hotshot_types::{light_client::StateSignKey, signature_key::BLSPrivKey};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tagged_base64::TaggedBase64;
+use time::OffsetDateTime;
+use url::Url;
+
+/// The variable holding the private staking key, in a key file or secret.
+pub const STAKING_KEY_VAR: &str = "ESPRESSO_SEQUENCER_PRIVATE_STAKING_KEY";
+
+/// The variable holding the private state key, in a key file or secret.
+pub const STATE_KEY_VAR: &str = "ESPRESSO_SEQUENCER_PRIVATE_STATE_KEY";
+
+/// Where a node loads its private keys from.
+#[derive(Clone, Copy, Debug, Default, Display, PartialEq, Eq, ValueEnum)]
+pub enum KeyProvider {
+    /// A key file or environment variables.
+    #[default]
+    #[display("local")]
+    Local,
+    /// A HashiCorp Vault KV secret.
+    #[display("vault")]
+    Vault,
+    /// A key file encrypted with AWS KMS.
+    #[display("aws-kms")]
+    AwsKms,
+    /// A key file in an encrypted keystore.
+    #[display("keystore")]
+    Keystore,
+}
+
+/// Options for loading private keys from an external key provider.
+#[derive(Parser, Clone, Derivative)]
+#[derivative(Debug)]
+pub struct KeyProviderOptions {
+    /// Where to load the node's private keys from.
+    ///
+    /// With `local`, keys are read from KEY_FILE or from the private key options. Other providers
+    /// are configured by the `--vault-*`, `--kms-*` and `--keystore-*` options respectively.
+    #[clap(
+        long = "key-provider",
+        env = "ESPRESSO_SEQUENCER_KEY_PROVIDER",
+        default_value = "local"
+    )]
+    pub provider: KeyProvider,
+
+    /// Address of the Vault server, like `https://vault.example.com:8200`.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_VAULT_ADDR")]
+    pub vault_addr: Option<Url>,
+
+    /// Path of the Vault secret holding the private keys, like `secret/data/sequencer`.
+    ///
+    /// Both KV version 1 and version 2 secrets are supported. The secret must have the fields
+    /// ESPRESSO_SEQUENCER_PRIVATE_STAKING_KEY and ESPRESSO_SEQUENCER_PRIVATE_STATE_KEY.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_VAULT_SECRET_PATH")]
+    pub vault_secret_path: Option<String>,
+
+    /// File containing the token used to authenticate with Vault.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_VAULT_TOKEN_FILE")]
+    pub vault_token_file: Option<PathBuf>,
+
+    /// Token used to authenticate with Vault, if no token file is given.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_VAULT_TOKEN",
+        hide_env_values = true,
+        conflicts_with = "vault_token_file"
+    )]
+    #[derivative(Debug = "ignore")]
+    pub vault_token: Option<String>,
+
+    /// AWS region of the KMS key used to encrypt the key file.
+    ///
+    /// AWS credentials are read from the standard AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and
+    /// (optionally) AWS_SESSION_TOKEN environment variables.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_KMS_REGION")]
+    pub kms_region: Option<String>,
+
+    /// Custom KMS endpoint, such as a VPC endpoint.
+    ///
+    /// Defaults to the public KMS endpoint for the region.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_KMS_ENDPOINT")]
+    pub kms_endpoint: Option<Url>,
+
+    /// File containing the KMS ciphertext of a key file.
+    ///
+    /// This is the raw (not base64-encoded) `CiphertextBlob` returned by the KMS `Encrypt`
+    /// operation when encrypting a key file.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_KMS_CIPHERTEXT_FILE")]
+    pub kms_ciphertext_file: Option<PathBuf>,
+
+    /// Encrypted keystore file containing a key file.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_KEYSTORE_FILE")]
+    pub keystore_file: Option<PathBuf>,
+
+    /// File containing the password for the keystore.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_KEYSTORE_PASSWORD_FILE")]
+    pub keystore_password_file: Option<PathBuf>,
+}
+
+impl KeyProviderOptions {
+    /// Load private keys from the configured external provider.
+    pub async fn load(&self) -> anyhow::Result<(BLSPrivKey, StateSignKey)> {
+        tracing::info!(provider = %self.provider, "loading private keys");
+        let vars = match self.provider {
+            KeyProvider::Local => bail!("no external key provider is configured"),
+            KeyProvider::Vault => self.load_from_vault().await?,
+            KeyProvider::AwsKms => self.load_from_kms().await?,
+            KeyProvider::Keystore => self.load_from_keystore()?,
+        };
+        parse_private_keys(&vars)
+    }
+
+    async fn load_from_vault(&self) -> anyhow::Result<HashMap<String, String>> {
+        let addr = self
+            .vault_addr
+            .as_ref()
+            .context("vault key provider requires --vault-addr")?;
+        let path = self
+            .vault_secret_path
+            .as_ref()
+            .context("vault key provider requires --vault-secret-path")?;
+        let token = match (&self.vault_token_file, &self.vault_token) {
+            (Some(file), _) => read_secret_file(file)?,
+            (None, Some(token)) => token.clone(),
+            (None, None) => {
+                bail!("vault key provider requires --vault-token-file or --vault-token")
+            }
+        };
+
+        let url = addr.join(&format!("v1/{}", path.trim_start_matches('/')))?;
+        let res = reqwest::Client::new()
+            .get(url.clone())
+            .header("X-Vault-Token", token)
+            .send()
+            .await
+            .context(format!("requesting secret from {url}"))?;
+        let status = res.status();
+        let body = res.text().await.context("reading Vault response")?;
+        ensure!(
+            status.is_success(),
+            "Vault returned {status} for {url}: {body}"
+        );
+        vault_secret(serde_json::from_str(&body).context("malformed Vault response")?)
+    }
+
+    async fn load_from_kms(&self) -> anyhow::Result<HashMap<String, String>> {
+        let region = self
+            .kms_region
+            .as_ref()
+            .context("aws-kms key provider requires --kms-region")?;
+        let path = self
+            .kms_ciphertext_file
+            .as_ref()
+            .context("aws-kms key provider requires --kms-ciphertext-file")?;
+        let ciphertext =
+            fs::read(path).context(format!("reading KMS ciphertext {}", path.display()))?;
+        let endpoint = match &self.kms_endpoint {
+            Some(endpoint) => endpoint.clone(),
+            None => format!("https://kms.{region}.amazonaws.com/").parse()?,
+        };
+
+        let plaintext = kms_decrypt(&AwsCredentials::from_env()?, region, &endpoint, &ciphertext)
+            .await
+            .context("decrypting key file with KMS")?;
+        parse_key_file(&plaintext)
+    }
+
+    fn load_from_keystore(&self) -> anyhow::Result<HashMap<String, String>> {
+        let path = self
+            .keystore_file
+            .as_ref()
+            .context("keystore key provider requires --keystore-file")?;
+        let password = read_secret_file(
+            self.keystore_password_file
+                .as_ref()
+                .context("keystore key provider requires --keystore-password-file")?,
+        )?;
+        let plaintext = eth_keystore::decrypt_key(path, password)
+            .context(format!("decrypting keystore {}", path.display()))?;
+        parse_key_file(&plaintext)
+    }
+}
+
+/// Parse private keys from the variables of a key file or secret.
+pub fn parse_private_keys(
+    vars: &HashMap<String, String>,
+) -> anyhow::Result<(BLSPrivKey, StateSignKey)> {
+    let staking = TaggedBase64::parse(
+        vars.get(STAKING_KEY_VAR)
+            .context(format!("key file missing {STAKING_KEY_VAR}"))?,
+    )?
+    .try_into()?;
+    let state = TaggedBase64::parse(
+        vars.get(STATE_KEY_VAR)
+            .context(format!("key file missing {STATE_KEY_VAR}"))?,
+    )?
+    .try_into()?;
+    Ok((staking, state))
+}
+
+/// Parse the variables of a key file in .env format.
+fn parse_key_file(contents: &[u8]) -> anyhow::Result<HashMap<String, String>> {
+    Ok(dotenvy::from_read_iter(contents).collect::<Result<_, _>>()?)
+}
+
+/// Read a secret, such as a token or password, from a file, ignoring surrounding whitespace.
+fn read_secret_file(path: &Path) -> anyhow::Result<String> {
+    Ok(fs::read_to_string(path)
+        .context(format!("reading {}", path.display()))?
+        .trim()
+        .to_string())
+}
+
+/// Extract the fields of a secret from a Vault KV read response.
+fn vault_secret(res: Value) -> anyhow::Result<HashMap<String, String>> {
+    let data = res.get("data").context("Vault response has no data")?;
+    // KV version 2 nests the secret inside a second `data` object, alongside metadata.
+    let secret = match data.get("data") {
+        Some(inner) if inner.is_object() => inner,
+        _ => data,
+    };
+    Ok(serde_json::from_value(secret.clone()).context("malformed Vault secret")?)
+}
+
+/// AWS credentials for signing requests.
+#[derive(Clone, Derivative)]
+#[derivative(Debug)]
+struct AwsCredentials {
+    access_key_id: String,
+    #[derivative(Debug = "ignore")]
+    secret_access_key: String,
+    #[derivative(Debug = "ignore")]
+    session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            access_key_id: std::env::var("AWS_ACCESS_KEY_ID")
+                .context("AWS_ACCESS_KEY_ID not set")?,
+            secret_access_key: std::env::var("AWS_SECRET_ACCESS_KEY")
+                .context("AWS_SECRET_ACCESS_KEY not set")?,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+}
+
+/// Decrypt `ciphertext` with the KMS `Decrypt` operation.
+async fn kms_decrypt(
+    creds: &AwsCredentials,
+    region: &str,
+    endpoint: &Url,
+    ciphertext: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    let host = endpoint.host_str().context("KMS endpoint has no host")?;
+    let host = match endpoint.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    };
+    let body = serde_json::to_vec(&json!({ "CiphertextBlob": BASE64.encode(ciphertext) }))?;
+    let amz_date = amz_date(OffsetDateTime::now_utc());
+
+    let mut headers = BTreeMap::from([
+        ("content-type", "application/x-amz-json-1.1".to_string()),
+        ("host", host),
+        ("x-amz-date", amz_date.clone()),
+        ("x-amz-target", "TrentService.Decrypt".to_string()),
+    ]);
+    if let Some(token) = &creds.session_token {
+        headers.insert("x-amz-security-token", token.clone());
+    }
+    let authorization = sign_v4(creds, region, "kms", &amz_date, "POST", &headers, &body);
+
+    let mut req = reqwest::Client::new().post(endpoint.clone());
+    for (name, value) in &headers {
+        // The HTTP client sets the host header itself, from the URL.
+        if *name != "host" {
+            req = req.header(*name, value);
+        }
+    }
+    let res = req
+        .header("authorization", authorization)
+        .body(body)
+        .send()
+        .await
+        .context(format!("requesting {endpoint}"))?;
+    let status = res.status();
+    let body = res.text().await.context("reading KMS response")?;
+    ensure!(status.is_success(), "KMS returned {status}: {body}");
+
+    let res: Value = serde_json::from_str(&body).context("malformed KMS response")?;
+    let plaintext = res
+        .get("Plaintext")
+        .and_then(Value::as_str)
+        .context("KMS response has no plaintext")?;
+    Ok(BASE64.decode(plaintext)?)
+}
+
+/// Format a time as an AWS timestamp, like `20150830T123600Z`.
+fn amz_date(time: OffsetDateTime) -> String {
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+fn hmac_sha256(key: &[u8], msg: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(msg.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Compute the AWS Signature Version 4 `Authorization` header for a request to the root path.
+///
+/// `headers` must have lowercase names, and include at least `host` and `x-amz-date`.
+fn sign_v4(
+    creds: &AwsCredentials,
+    region: &str,
+    service: &str,
+    amz_date: &str,
+    method: &str,
+    headers: &BTreeMap<&str, String>,
+    body: &[u8],
+) -> String {
+    let date = &amz_date[..8];
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let signed_headers = headers.keys().copied().collect::<Vec<_>>().join(";");
+    let canonical_request = format!(
+        "{method}\n/\n\n{canonical_headers}\n{signed_headers}\n{}",
+        hex::encode(Sha256::digest(body))
+    );
+
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key = hmac_sha256(format!("AWS4{}", creds.secret_access_key).as_bytes(), date);
+    let key = hmac_sha256(&key, region);
+    let key = hmac_sha256(&key, service);
+    let key = hmac_sha256(&key, "aws4_request");
+    let signature = hex::encode(hmac_sha256(&key, &string_to_sign));
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, \
+         Signature={signature}",
+        creds.access_key_id
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use hotshot::types::SignatureKey;
+    use hotshot_types::{
+        light_client::{StateKeyPair, StateVerKey},
+        signature_key::BLSPubKey,
+    };
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// The contents of a key file, and the public keys corresponding to its private keys.
+    fn key_file() -> (String, BLSPubKey, StateVerKey) {
+        let (staking, staking_priv) = BLSPubKey::generated_from_seed_indexed([0; 32], 0);
+        let state = StateKeyPair::generate_from_seed_indexed([0; 32], 0);
+        let contents = format!(
+            "{STAKING_KEY_VAR}={}\n{STATE_KEY_VAR}={}\n",
+            staking_priv.to_tagged_base64().unwrap(),
+            state.sign_key_ref().to_tagged_base64().unwrap(),
+        );
+        (contents, staking, state.ver_key())
+    }
+
+    #[test]
+    fn test_sign_v4() {
+        // The `get-vanilla` example from the AWS Signature Version 4 test suite.
+        let creds = AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".into(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into(),
+            session_token: None,
+        };
+        let amz_date = "20150830T123600Z";
+        let headers = BTreeMap::from([
+            ("host", "example.amazonaws.com".to_string()),
+            ("x-amz-date", amz_date.to_string()),
+        ]);
+        assert_eq!(
+            sign_v4(
+                &creds,
+                "us-east-1",
+                "service",
+                amz_date,
+                "GET",
+                &headers,
+                b""
+            ),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+
+        let time = OffsetDateTime::from_unix_timestamp(1440938160).unwrap();
+        assert_eq!(amz_date(time), "20150830T123600Z");
+    }
+
+    #[test]
+    fn test_vault_secret() {
+        let secret = json!({
+            STAKING_KEY_VAR: "staking",
+            STATE_KEY_VAR: "state",
+        });
+        let expected = HashMap::from([
+            (STAKING_KEY_VAR.to_string(), "staking".to_string()),
+            (STATE_KEY_VAR.to_string(), "state".to_string()),
+        ]);
+
+        // KV version 1.
+        assert_eq!(vault_secret(json!({ "data": secret })).unwrap(), expected);
+        // KV version 2.
+        assert_eq!(
+            vault_secret(json!({
+                "data": {
+                    "data": secret,
+                    "metadata": { "version": 1 },
+                },
+            }))
+            .unwrap(),
+            expected
+        );
+        assert!(vault_secret(json!({ "errors": [] })).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_keystore() {
+        let dir = TempDir::new().unwrap();
+        let (contents, staking, state) = key_file();
+        eth_keystore::encrypt_key(
+            dir.path(),
+            &mut rand::thread_rng(),
+            contents,
+            "password",
+            Some("keys.json"),
+        )
+        .unwrap();
+        let password_file = dir.path().join("password");
+        fs::write(&password_file, "password\n").unwrap();
+
+        let opt = KeyProviderOptions {
+            provider: KeyProvider::Keystore,
+            vault_addr: None,
+            vault_secret_path: None,
+            vault_token_file: None,
+            vault_token: None,
+            kms_region: None,
+            kms_endpoint: None,
+            kms_ciphertext_file: None,
+            keystore_file: Some(dir.path().join("keys.json")),
+            keystore_password_file: Some(password_file.clone()),
+        };
+        let (staking_priv, state_priv) = opt.load().await.unwrap();
+        assert_eq!(BLSPubKey::from_private(&staking_priv), staking);
+        assert_eq!(StateKeyPair::from_sign_key(state_priv).ver_key(), state);
+
+        // The wrong password is rejected.
+        fs::write(&password_file, "wrong").unwrap();
+        opt.load().await.unwrap_err();
+    }
+}
@@ -1,21 +1,38 @@
 pub mod api;
+pub mod builder_fees;
+pub mod builder_registry;
 pub mod catchup;
+pub mod commitment_task;
 pub mod context;
+pub mod da_mirror;
+pub mod decide_log;
 pub mod genesis;
+pub mod integrity;
 
 mod external_event_handler;
+pub mod keys;
+pub mod mempool;
 pub mod options;
+pub mod shutdown;
 pub mod state_signature;
+pub mod state_sync;
+pub mod supervisor;
+pub mod tx_trace;
+pub mod upgrade;
+pub mod view_log;
+pub mod view_timeout;
+pub mod watchdog;
+pub mod webhook;
 
 mod message_compat_tests;
 
 use anyhow::Context;
 use async_lock::RwLock;
 use catchup::StatePeers;
-use context::{ProposalFetcherConfig, SequencerContext};
+use context::{NodeConfig, SequencerContext, TaskList};
 use espresso_types::{
     traits::EventConsumer, BackoffParams, L1Client, L1ClientOptions, NodeState, PubKey, SeqTypes,
-    SolverAuctionResultsProvider, ValidatedState,
+    SolverAuctionResultsProvider, ValidatedState, ValidationParallelism,
 };
 use ethers::types::U256;
 use futures::FutureExt;
@@ -25,18 +42,27 @@ use hotshot_types::traits::election::Membership;
 use std::sync::Arc;
 // Should move `STAKE_TABLE_CAPACITY` in the sequencer repo when we have variate stake table support
 use libp2p::Multiaddr;
-use network::libp2p::split_off_peer_id;
+use network::{
+    health::NetworkHealth,
+    libp2p::{
+        check_advertise_address, join_peer_id, merge_peers, split_off_peer_id,
+        validate_gossip_config, validate_transport, PrivateAddressPolicy, Transport,
+    },
+    marshal::{MarshalOptions, MarshalRelay},
+};
 use options::Identity;
 use state_signature::static_stake_table_commitment;
+use state_sync::StateSyncClient;
 use tracing::info;
 use url::Url;
 pub mod persistence;
+pub mod snapshot;
 pub mod state;
 use derivative::Derivative;
-use espresso_types::v0::traits::{PersistenceOptions, SequencerPersistence};
+use espresso_types::v0::traits::{PersistenceOptions, SequencerPersistence, StateCatchup};
 pub use genesis::Genesis;
 use hotshot::traits::implementations::{
-    derive_libp2p_multiaddr, CombinedNetworks, GossipConfig, Libp2pNetwork, RequestResponseConfig,
+    CombinedNetworks, GossipConfig, Libp2pNetwork, RequestResponseConfig,
 };
 use hotshot::{
     traits::implementations::{
@@ -101,15 +127,21 @@ impl<N: ConnectedNetwork<PubKey>, P: SequencerPersistence> NodeImplementation<Se
 
 #[derive(Clone, Debug)]
 pub struct NetworkParams {
-    /// The address where a CDN marshal is located
-    pub cdn_endpoint: String,
+    /// The addresses where CDN marshals are located
+    pub cdn_endpoints: Vec<String>,
+    /// How to fail over between CDN marshals, if there are several
+    pub cdn_marshal: MarshalOptions,
     pub orchestrator_url: Url,
     pub state_relay_server_url: Url,
     pub private_staking_key: BLSPrivKey,
     pub private_state_key: StateSignKey,
     pub state_peers: Vec<Url>,
+    /// Whether to sync missing state from consensus peers over the network
+    pub state_sync: bool,
     pub config_peers: Option<Vec<Url>>,
     pub catchup_backoff: BackoffParams,
+    /// Threads used to validate proposed headers
+    pub validation_parallelism: ValidationParallelism,
     /// The address to advertise as our public API's URL
     pub public_api_url: Option<Url>,
 
@@ -120,6 +152,14 @@ pub struct NetworkParams {
     /// The (optional) bootstrap node addresses for Libp2p. If supplied, these will
     /// override the bootstrap nodes specified in the config file.
     pub libp2p_bootstrap_nodes: Option<Vec<Multiaddr>>,
+    /// Libp2p peers which are always dialed on startup and never dropped from the peer store.
+    pub libp2p_pinned_peers: Vec<Multiaddr>,
+    /// The transport to run Libp2p over
+    pub libp2p_transport: Transport,
+    /// The maximum number of unpinned Libp2p peers to bootstrap from and remember across restarts
+    pub libp2p_max_bootstrap_peers: usize,
+    /// What to do if the Libp2p advertise address is not publicly dialable
+    pub libp2p_private_address: PrivateAddressPolicy,
 
     /// The heartbeat interval
     pub libp2p_heartbeat_interval: Duration,
@@ -182,7 +222,7 @@ pub struct NetworkParams {
 }
 
 pub struct L1Params {
-    pub url: Url,
+    pub urls: Vec<Url>,
     pub options: L1ClientOptions,
 }
 
@@ -198,7 +238,7 @@ pub async fn init_node<P: PersistenceOptions, V: Versions>(
     is_da: bool,
     identity: Identity,
     marketplace_config: MarketplaceConfig<SeqTypes, Node<network::Production, P::Persistence>>,
-    proposal_fetcher_config: ProposalFetcherConfig,
+    mut node_config: NodeConfig,
 ) -> anyhow::Result<SequencerContext<network::Production, P::Persistence, V>> {
     // Expose git information via status API.
     metrics
@@ -262,15 +302,18 @@ pub async fn init_node<P: PersistenceOptions, V: Versions>(
         .create(vec![pub_key.to_string()]);
 
     // Parse the Libp2p bind and advertise addresses to multiaddresses
-    let libp2p_bind_address = derive_libp2p_multiaddr(&network_params.libp2p_bind_address)
+    let libp2p_transport = network_params.libp2p_transport;
+    let libp2p_bind_address = libp2p_transport
+        .multiaddr(&network_params.libp2p_bind_address)
         .with_context(|| {
             format!(
                 "Failed to derive Libp2p bind address of {}",
                 &network_params.libp2p_bind_address
             )
         })?;
-    let libp2p_advertise_address =
-        derive_libp2p_multiaddr(&network_params.libp2p_advertise_address).with_context(|| {
+    let libp2p_advertise_address = libp2p_transport
+        .multiaddr(&network_params.libp2p_advertise_address)
+        .with_context(|| {
             format!(
                 "Failed to derive Libp2p advertise address of {}",
                 &network_params.libp2p_advertise_address
@@ -279,6 +322,14 @@ pub async fn init_node<P: PersistenceOptions, V: Versions>(
 
     info!("Libp2p bind address: {}", libp2p_bind_address);
     info!("Libp2p advertise address: {}", libp2p_advertise_address);
+    let public = check_advertise_address(
+        &libp2p_advertise_address,
+        network_params.libp2p_private_address,
+    )?;
+    metrics
+        .subgroup("libp2p".into())
+        .create_gauge("public_advertise_address".into(), None)
+        .set(public as usize);
 
     // Orchestrator client
     let orchestrator_client = OrchestratorClient::new(network_params.orchestrator_url);
@@ -352,6 +403,10 @@ pub async fn init_node<P: PersistenceOptions, V: Versions>(
         }
     };
 
+    genesis
+        .stake_table
+        .apply(&mut network_config, pub_key)
+        .context("applying genesis stake table")?;
     if let Some(upgrade) = genesis.upgrades.get(&V::Upgrade::VERSION) {
         upgrade.set_hotshot_config_parameters(&mut network_config.config);
     }
@@ -362,12 +417,15 @@ pub async fn init_node<P: PersistenceOptions, V: Versions>(
         if let Some(libp2p_config) = network_config.libp2p_config.as_mut() {
             // If the libp2p configuration is present, we can override the bootstrap nodes.
 
-            // Split off the peer ID from the addresses
+            // Check the transport and split off the peer ID from the addresses
             libp2p_config.bootstrap_nodes = bootstrap_nodes
                 .into_iter()
-                .map(split_off_peer_id)
+                .map(|address| {
+                    validate_transport(&address, libp2p_transport)?;
+                    split_off_peer_id(address)
+                })
                 .collect::<Result<Vec<_>, _>>()
-                .with_context(|| "Failed to parse peer ID from bootstrap node")?;
+                .with_context(|| "Failed to parse bootstrap node")?;
         } else {
             // If not, don't try launching with them. Eventually we may want to
             // provide a default configuration here instead.
@@ -375,6 +433,54 @@ pub async fn init_node<P: PersistenceOptions, V: Versions>(
         }
     }
 
+    // Bootstrap from pinned peers and from peers remembered from previous runs, as well as the
+    // configured bootstrap nodes, and remember them all for the next run.
+    if let Some(libp2p_config) = network_config.libp2p_config.as_mut() {
+        let pinned = network_params
+            .libp2p_pinned_peers
+            .into_iter()
+            .map(|address| {
+                validate_transport(&address, libp2p_transport)?;
+                split_off_peer_id(address)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to parse pinned peer")?;
+        let stored = match persistence.load_libp2p_peers().await {
+            Ok(peers) => peers
+                .into_iter()
+                .filter_map(|peer| {
+                    let address = peer.parse::<Multiaddr>().ok()?;
+                    if let Err(err) = validate_transport(&address, libp2p_transport) {
+                        tracing::warn!("ignoring stored libp2p peer: {err:#}");
+                        return None;
+                    }
+                    split_off_peer_id(address).ok()
+                })
+                .collect(),
+            Err(err) => {
+                tracing::warn!("failed to load stored libp2p peers: {err:#}");
+                vec![]
+            }
+        };
+        libp2p_config.bootstrap_nodes = merge_peers(
+            &pinned,
+            &libp2p_config.bootstrap_nodes,
+            &stored,
+            network_params.libp2p_max_bootstrap_peers,
+        );
+
+        let peers = libp2p_config
+            .bootstrap_nodes
+            .iter()
+            .map(|(peer_id, address)| join_peer_id(*peer_id, address.clone()).to_string())
+            .collect::<Vec<_>>();
+        if let Err(err) = persistence.store_libp2p_peers(&peers).await {
+            tracing::warn!("failed to store libp2p peers: {err:#}");
+        }
+    } else if !network_params.libp2p_pinned_peers.is_empty() {
+        tracing::warn!("No libp2p configuration found, ignoring pinned peers");
+    }
+
     let node_index = network_config.node_index;
 
     // If we are a DA node, we need to subscribe to the DA topic
@@ -404,15 +510,32 @@ pub async fn init_node<P: PersistenceOptions, V: Versions>(
         da_membership,
     };
 
+    // Track the health of both transports, so operators can tell which one is in use.
+    let network_health = NetworkHealth::default();
+
+    // If there are several CDN marshals, connect through a relay which picks between them.
+    let mut tasks = TaskList::default();
+    let cdn_endpoint = match &network_params.cdn_endpoints[..] {
+        [endpoint] => endpoint.clone(),
+        endpoints => {
+            let relay = MarshalRelay::bind(endpoints.to_vec(), network_params.cdn_marshal, metrics)
+                .await
+                .context("Failed to start CDN marshal relay")?;
+            let endpoint = relay.local_addr()?.to_string();
+            tasks.spawn("CDN marshal relay", relay.run());
+            endpoint
+        }
+    };
+
     // Initialize the push CDN network (and perform the initial connection)
     let cdn_network = PushCdnNetwork::new(
-        network_params.cdn_endpoint,
+        cdn_endpoint,
         topics,
         KeyPair {
             public_key: WrappedSignatureKey(validator_config.public_key),
             private_key: validator_config.private_key.clone(),
         },
-        CdnMetricsValue::new(metrics),
+        network_health.instrument_cdn(CdnMetricsValue::new(metrics)),
     )
     .with_context(|| format!("Failed to create CDN network {node_index}"))?;
 
@@ -439,6 +562,7 @@ pub async fn init_node<P: PersistenceOptions, V: Versions>(
         gossip_factor: network_params.libp2p_gossip_factor,
         gossip_lazy: network_params.libp2p_gossip_lazy,
     };
+    validate_gossip_config(&gossip_config).context("invalid libp2p gossip options")?;
 
     // Configure request/response based on the command line options
     let request_response_config = RequestResponseConfig {
@@ -458,7 +582,9 @@ pub async fn init_node<P: PersistenceOptions, V: Versions>(
             // We need the private key so we can derive our Libp2p keypair
             // (using https://docs.rs/blake3/latest/blake3/fn.derive_key.html)
             &validator_config.private_key,
-            hotshot::traits::implementations::Libp2pMetricsValue::new(metrics),
+            network_health.instrument_libp2p(
+                hotshot::traits::implementations::Libp2pMetricsValue::new(metrics),
+            ),
         )
         .await
         .with_context(|| {
@@ -480,11 +606,12 @@ pub async fn init_node<P: PersistenceOptions, V: Versions>(
 
         // Combine the CDN and P2P networks
         Arc::from(CombinedNetworks::new(
-            cdn_network,
+            cdn_network.clone(),
             p2p_network,
             Some(Duration::from_secs(1)),
         ))
     };
+    let network_monitor = network_health.clone().monitor(network.clone(), cdn_network);
 
     let mut genesis_state = ValidatedState {
         chain_config: genesis.chain_config.into(),
@@ -495,10 +622,14 @@ pub async fn init_node<P: PersistenceOptions, V: Versions>(
         genesis_state.prefund_account(address, amount);
     }
 
+    // Post block commitments through our own L1 provider, unless told otherwise.
+    if node_config.commitment_task.l1_provider.is_none() {
+        node_config.commitment_task.l1_provider = l1_params.urls.first().cloned();
+    }
     let l1_client = l1_params
         .options
         .with_metrics(metrics)
-        .connect(l1_params.url)
+        .connect_failover(l1_params.urls)
         .await?;
     l1_client.spawn_tasks().await;
     let l1_genesis = match genesis.l1_finalized {
@@ -510,23 +641,42 @@ pub async fn init_node<P: PersistenceOptions, V: Versions>(
                 .await
         }
     };
+    // Fetch state from the HTTP APIs of our state peers if we have any, and, if enabled, fall back
+    // to syncing state from consensus peers over the network.
+    let state_sync = network_params.state_sync.then(|| {
+        StateSyncClient::new(
+            network.clone(),
+            validator_config.public_key,
+            network_config
+                .config
+                .known_nodes_with_stake
+                .iter()
+                .map(|peer| peer.stake_table_entry.stake_key),
+            network_params.catchup_backoff,
+        )
+    });
+    let mut remote_catchup: Vec<Arc<dyn StateCatchup>> = vec![];
+    if !network_params.state_peers.is_empty() {
+        remote_catchup.push(Arc::new(StatePeers::<SequencerApiVersion>::from_urls(
+            network_params.state_peers.clone(),
+            network_params.catchup_backoff,
+        )));
+    }
+    if let Some(state_sync) = &state_sync {
+        remote_catchup.push(Arc::new(state_sync.clone()));
+    }
+
     let instance_state = NodeState {
         chain_config: genesis.chain_config,
         l1_client,
         genesis_header: genesis.header,
         genesis_state,
         l1_genesis: Some(l1_genesis),
-        peers: catchup::local_and_remote(
-            persistence_opt,
-            StatePeers::<SequencerApiVersion>::from_urls(
-                network_params.state_peers,
-                network_params.catchup_backoff,
-            ),
-        )
-        .await,
+        peers: catchup::local_and_remote(persistence_opt, remote_catchup).await,
         node_id: node_index,
         upgrades: genesis.upgrades,
         current_version: V::Base::VERSION,
+        validation: network_params.validation_parallelism,
     };
 
     let mut ctx = SequencerContext::init(
@@ -537,15 +687,20 @@ pub async fn init_node<P: PersistenceOptions, V: Versions>(
         persistence,
         network,
         Some(network_params.state_relay_server_url),
+        network_params.state_peers,
         metrics,
         genesis.stake_table.capacity,
         network_params.public_api_url,
         event_consumer,
         seq_versions,
         marketplace_config,
-        proposal_fetcher_config,
+        node_config,
+        state_sync.as_ref(),
     )
-    .await?;
+    .await?
+    .with_network_health(network_health)
+    .with_task_list(tasks);
+    ctx.spawn("network health monitor", network_monitor);
     if wait_for_orchestrator {
         ctx = ctx.wait_for_orchestrator(orchestrator_client);
     }
@@ -1051,6 +1206,7 @@ pub mod testing {
                 persistence_opt.create().await.unwrap(),
                 network,
                 self.state_relay_url.clone(),
+                vec![],
                 metrics,
                 stake_table_capacity,
                 None, // The public API URL
@@ -1061,6 +1217,7 @@ pub mod testing {
                     fallback_builder_url: marketplace_builder_url,
                 },
                 Default::default(),
+                None,
             )
             .await
             .unwrap()
@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use anyhow::Context;
 use clap::Parser;
 use espresso_types::{
     traits::NullEventConsumer, FeeVersion, MarketplaceVersion, SequencerVersions,
@@ -12,8 +13,11 @@ use sequencer::{
     api::{self, data_source::DataSourceOptions},
     context::SequencerContext,
     init_node, network,
-    options::{Modules, Options},
-    persistence, Genesis, L1Params, NetworkParams,
+    options::{Modules, NodeRole, Options},
+    persistence::{self, DaProfile},
+    shutdown,
+    watchdog::{WatchdogAction, WATCHDOG_EXIT_CODE},
+    Genesis, L1Params, NetworkParams,
 };
 use vbs::version::StaticVersionType;
 
@@ -22,14 +26,20 @@ async fn main() -> anyhow::Result<()> {
     let opt = Options::parse();
     opt.logging.init();
 
-    let modules = opt.modules();
-    tracing::warn!(?modules, "sequencer starting up");
+    let mut modules = opt.modules();
+    tracing::warn!(?modules, role = ?opt.role, "sequencer starting up");
+
+    if let Some(storage) = modules.storage_sql_replica.take() {
+        // A read replica does not participate in consensus, so it needs no genesis or L1
+        // connection.
+        return run_replica(modules, storage).await;
+    }
 
     let genesis = Genesis::from_file(&opt.genesis_file)?;
 
     // validate that the fee contract is a proxy and panic otherwise
     genesis
-        .validate_fee_contract(opt.l1_provider_url.to_string())
+        .validate_fee_contract(opt.l1_provider_url[0].to_string())
         .await
         .unwrap();
 
@@ -72,6 +82,27 @@ async fn main() -> anyhow::Result<()> {
     }
 }
 
+async fn run_replica(
+    modules: Modules,
+    storage: persistence::sql::ReplicaOptions,
+) -> anyhow::Result<()> {
+    let http = modules
+        .http
+        .context("storage-sql-replica requires the http module")?;
+    let query = modules
+        .query
+        .context("storage-sql-replica requires the query module")?;
+
+    let mut http_opt = api::Options::from(http).query_sql(query, storage.into());
+    if let Some(explorer) = modules.explorer {
+        http_opt = http_opt.explorer(explorer);
+    }
+    if let Some(auth) = modules.auth {
+        http_opt = http_opt.auth(auth);
+    }
+    http_opt.serve_replica().await
+}
+
 async fn run<V>(
     genesis: Genesis,
     mut modules: Modules,
@@ -81,10 +112,24 @@ async fn run<V>(
 where
     V: Versions,
 {
-    if let Some(storage) = modules.storage_fs.take() {
+    if let Some(migrate) = modules.storage_migrate.take() {
+        // The module parser guarantees that both backends are present.
+        let old = modules
+            .storage_fs
+            .take()
+            .context("storage-migrate requires storage-fs")?;
+        let new = modules
+            .storage_sql
+            .take()
+            .context("storage-migrate requires storage-sql")?;
+        let storage = persistence::migrating::Options::new(old, new, migrate);
+        run_with_storage(genesis, modules, opt, storage, versions).await
+    } else if let Some(storage) = modules.storage_fs.take() {
         run_with_storage(genesis, modules, opt, storage, versions).await
     } else if let Some(storage) = modules.storage_sql.take() {
         run_with_storage(genesis, modules, opt, storage, versions).await
+    } else if let Some(storage) = modules.storage_rocksdb.take() {
+        run_with_storage(genesis, modules, opt, storage, versions).await
     } else {
         // Persistence is required. If none is provided, just use the local file system.
         run_with_storage(
@@ -106,16 +151,40 @@ async fn run_with_storage<S, V>(
     versions: V,
 ) -> anyhow::Result<()>
 where
-    S: DataSourceOptions,
+    S: DataSourceOptions + DaProfile,
     V: Versions,
 {
-    let ctx = init_with_storage(genesis, modules, opt, storage_opt, versions).await?;
-
-    // Start doing consensus.
-    ctx.start_consensus().await;
-    ctx.join().await;
+    let shutdown_config = opt.shutdown_config;
+    loop {
+        let mut ctx = init_with_storage(
+            genesis.clone(),
+            modules.clone(),
+            opt.clone(),
+            storage_opt.clone(),
+            versions,
+        )
+        .await?;
+
+        // Start doing consensus, until we are asked to stop, or the watchdog asks for the node to
+        // be restarted or stopped.
+        ctx.start_consensus().await;
+        let recovery = tokio::select! {
+            _ = shutdown::signal() => None,
+            action = ctx.recovery_requested() => Some(action),
+        };
+        ctx.shut_down_gracefully(shutdown_config.timeout).await;
 
-    Ok(())
+        match recovery {
+            None => return Ok(()),
+            Some(WatchdogAction::Exit) => {
+                tracing::error!("exiting at the request of the chain health watchdog");
+                std::process::exit(WATCHDOG_EXIT_CODE);
+            }
+            Some(_) => {
+                tracing::warn!("restarting node at the request of the chain health watchdog")
+            }
+        }
+    }
 }
 
 async fn init_with_storage<S, V>(
@@ -126,28 +195,40 @@ async fn init_with_storage<S, V>(
     versions: V,
 ) -> anyhow::Result<SequencerContext<network::Production, S::Persistence, V>>
 where
-    S: DataSourceOptions,
+    S: DataSourceOptions + DaProfile,
     V: Versions,
 {
-    let (private_staking_key, private_state_key) = opt.private_keys()?;
+    let (private_staking_key, private_state_key) = opt.private_keys().await?;
+    let is_da = opt.is_da_member();
+    let storage_opt = match opt.role {
+        NodeRole::Full => storage_opt,
+        NodeRole::Da => storage_opt.da_only(),
+    };
     let l1_params = L1Params {
-        url: opt.l1_provider_url,
+        urls: opt.l1_provider_url,
         options: opt.l1_options,
     };
 
     let network_params = NetworkParams {
-        cdn_endpoint: opt.cdn_endpoint,
+        cdn_endpoints: opt.cdn_endpoints,
+        cdn_marshal: opt.cdn_marshal,
         libp2p_advertise_address: opt.libp2p_advertise_address,
         libp2p_bind_address: opt.libp2p_bind_address,
         libp2p_bootstrap_nodes: opt.libp2p_bootstrap_nodes,
+        libp2p_pinned_peers: opt.libp2p_pinned_peers,
+        libp2p_transport: opt.libp2p_transport,
+        libp2p_max_bootstrap_peers: opt.libp2p_max_bootstrap_peers,
+        libp2p_private_address: opt.libp2p_private_address,
         orchestrator_url: opt.orchestrator_url,
         state_relay_server_url: opt.state_relay_server_url,
         public_api_url: opt.public_api_url,
         private_staking_key,
         private_state_key,
         state_peers: opt.state_peers,
+        state_sync: opt.state_sync,
         config_peers: opt.config_peers,
         catchup_backoff: opt.catchup_backoff,
+        validation_parallelism: opt.validation_parallelism,
         libp2p_history_gossip: opt.libp2p_history_gossip,
         libp2p_history_length: opt.libp2p_history_length,
         libp2p_max_ihave_length: opt.libp2p_max_ihave_length,
@@ -179,7 +260,7 @@ where
         }),
         fallback_builder_url: opt.fallback_builder_url,
     };
-    let proposal_fetcher_config = opt.proposal_fetcher_config;
+    let node_config = opt.node_config;
 
     // Initialize HotShot. If the user requested the HTTP module, we must initialize the handle in
     // a special way, in order to populate the API with consensus metrics. Otherwise, we initialize
@@ -213,6 +294,25 @@ where
             if let Some(config) = modules.config {
                 http_opt = http_opt.config(config);
             }
+            if let Some(marketplace) = modules.marketplace {
+                http_opt = http_opt.marketplace(marketplace);
+            }
+            if let Some(fee) = modules.fee {
+                http_opt = http_opt.fee(fee);
+            }
+            if let Some(admin) = modules.admin {
+                http_opt = http_opt.admin(admin);
+            }
+            if let Some(auth) = modules.auth {
+                http_opt = http_opt.auth(auth);
+            }
+            if let Some(stream) = modules.stream {
+                http_opt = http_opt.stream(stream);
+            }
+            #[cfg(feature = "grpc")]
+            if let Some(grpc) = modules.grpc {
+                http_opt = http_opt.grpc(grpc);
+            }
 
             http_opt
                 .serve(move |metrics, consumer| {
@@ -225,10 +325,10 @@ where
                             l1_params,
                             versions,
                             consumer,
-                            opt.is_da,
+                            is_da,
                             opt.identity,
                             marketplace_config,
-                            proposal_fetcher_config,
+                            node_config,
                         )
                         .await
                     }
@@ -245,10 +345,10 @@ where
                 l1_params,
                 versions,
                 NullEventConsumer,
-                opt.is_da,
+                is_da,
                 opt.identity,
                 marketplace_config,
-                proposal_fetcher_config,
+                node_config,
             )
             .await?
         }
@@ -269,7 +369,7 @@ mod test {
     use hotshot_types::{light_client::StateKeyPair, traits::signature_key::SignatureKey};
     use portpicker::pick_unused_port;
     use sequencer::{
-        api::options::{Http, Status},
+        api::options::Http,
         genesis::{L1Finalized, StakeTableConfig},
         persistence::fs,
         SequencerApiVersion,
@@ -294,7 +394,10 @@ mod test {
         let genesis_file = tmp.path().join("genesis.toml");
         let genesis = Genesis {
             chain_config: Default::default(),
-            stake_table: StakeTableConfig { capacity: 10 },
+            stake_table: StakeTableConfig {
+                capacity: 10,
+                nodes: vec![],
+            },
             accounts: Default::default(),
             l1_finalized: L1Finalized::Number { number: 0 },
             header: Default::default(),
@@ -306,7 +409,7 @@ mod test {
 
         let modules = Modules {
             http: Some(Http::with_port(port)),
-            status: Some(Status),
+            status: Some(Default::default()),
             ..Default::default()
         };
         let opt = Options::parse_from([
@@ -374,6 +477,14 @@ mod test {
             "{lines:#?}"
         );
 
+        // The metrics should also include latency histograms for the API requests we made above.
+        assert!(
+            lines
+                .iter()
+                .any(|line| line.starts_with("api_healthcheck_seconds_bucket")),
+            "{lines:#?}"
+        );
+
         task.abort();
     }
 }
@@ -0,0 +1,579 @@
+//! Node-local mempool.
+//!
+//! A transaction submitted to a node is handed to consensus, which forwards it to the builder. If
+//! that builder does not build the next block, or the node restarts before the transaction is
+//! sequenced, the transaction is lost. The mempool keeps every transaction submitted through this
+//! node, or gossiped to it by a peer, until it appears in a decided block. Pending transactions are
+//! persisted in [`SequencerPersistence`], so they survive restarts, and are periodically resubmitted
+//! to consensus, so they eventually reach whichever builder is active.
+//!
+//! Transactions submitted through this node are gossiped to all peers as [`ExternalMessage`]s.
+//! Gossiped transactions are added to the peers' mempools but not gossiped again, so each
+//! submission costs a single broadcast.
+//!
+//! The mempool is bounded both in number of transactions and in total size. Transactions do not
+//! carry a fee of their own: the fee a builder pays to include a transaction is `base_fee * size`,
+//! so this is the priority of a transaction in the mempool. When the mempool is full, a new
+//! transaction evicts the lowest priority transactions, and among transactions with the same
+//! priority the most recently added, or is rejected if it would not have a higher priority than
+//! the transactions it would evict.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use clap::Parser;
+use committable::{Commitment, Committable};
+use espresso_types::{
+    parse_duration, v0::traits::SequencerPersistence, FeeAmount, NamespaceId, PubKey, SeqTypes,
+    Transaction,
+};
+use hotshot::types::{Event, EventType};
+use hotshot_types::{event::LeafInfo, traits::BlockPayload};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::Sender;
+
+use crate::external_event_handler::{encode_external_message, ExternalMessage, OutboundMessage};
+
+#[derive(Clone, Copy, Debug, Parser)]
+pub struct MempoolConfig {
+    /// Maximum number of transactions to keep in the mempool.
+    #[clap(
+        long = "mempool-capacity",
+        env = "ESPRESSO_SEQUENCER_MEMPOOL_CAPACITY",
+        default_value = "10000"
+    )]
+    pub capacity: usize,
+
+    /// Maximum total size in bytes of the transactions in the mempool.
+    #[clap(
+        long = "mempool-max-bytes",
+        env = "ESPRESSO_SEQUENCER_MEMPOOL_MAX_BYTES",
+        default_value = "100000000"
+    )]
+    pub max_bytes: usize,
+
+    /// How long a transaction stays pending before it is resubmitted to consensus.
+    #[clap(
+        long = "mempool-resubmit-interval",
+        env = "ESPRESSO_SEQUENCER_MEMPOOL_RESUBMIT_INTERVAL",
+        default_value = "30s",
+        value_parser = parse_duration,
+    )]
+    pub resubmit_interval: Duration,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+/// A sink for transactions gossiped by peers.
+#[async_trait]
+pub(crate) trait MempoolSink: Send + Sync {
+    async fn receive_gossip(&self, txs: Vec<Transaction>);
+}
+
+/// Priority of a transaction in the mempool.
+///
+/// Ordered so that the first key is the first transaction to evict: lowest fee first, and among
+/// equal fees, most recently added first.
+type Priority = (FeeAmount, Reverse<u64>);
+
+#[derive(Debug)]
+struct Entry {
+    tx: Transaction,
+    priority: Priority,
+    /// When the transaction was last submitted to consensus, if ever.
+    submitted: Option<Instant>,
+    /// When the transaction was added to the mempool, or restored from storage.
+    added: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<Commitment<Transaction>, Entry>,
+    by_priority: BTreeMap<Priority, Commitment<Transaction>>,
+    bytes: usize,
+    next_seq: u64,
+}
+
+/// The outcome of inserting a transaction into the mempool.
+#[derive(Debug, PartialEq, Eq)]
+enum Inserted {
+    /// The transaction was already in the mempool.
+    Duplicate,
+    /// The transaction was added, evicting the given transactions.
+    Added(Vec<Commitment<Transaction>>),
+}
+
+impl Inner {
+    fn insert(
+        &mut self,
+        cfg: &MempoolConfig,
+        tx: Transaction,
+        fee: FeeAmount,
+        submitted: Option<Instant>,
+    ) -> anyhow::Result<Inserted> {
+        let hash = tx.commit();
+        if self.entries.contains_key(&hash) {
+            return Ok(Inserted::Duplicate);
+        }
+        let size = tx.payload().len();
+        if size > cfg.max_bytes {
+            bail!(
+                "transaction size ({size}) is greater than mempool limit ({})",
+                cfg.max_bytes
+            );
+        }
+        let priority = (fee, Reverse(self.next_seq));
+
+        // Find the lowest priority transactions we would have to evict to make room. We don't
+        // evict anything until we know the new transaction will fit.
+        let mut evict = vec![];
+        let mut len = self.entries.len();
+        let mut bytes = self.bytes;
+        let mut candidates = self.by_priority.iter();
+        while len >= cfg.capacity || bytes + size > cfg.max_bytes {
+            match candidates.next() {
+                Some((candidate, victim)) if *candidate < priority => {
+                    len -= 1;
+                    bytes -= self.entries[victim].tx.payload().len();
+                    evict.push(*victim);
+                }
+                _ => bail!("mempool is full"),
+            }
+        }
+
+        for hash in &evict {
+            self.remove(hash);
+        }
+        self.next_seq += 1;
+        self.bytes += size;
+        self.by_priority.insert(priority, hash);
+        self.entries.insert(
+            hash,
+            Entry {
+                tx,
+                priority,
+                submitted,
+                added: Instant::now(),
+            },
+        );
+        Ok(Inserted::Added(evict))
+    }
+
+    fn remove(&mut self, hash: &Commitment<Transaction>) -> bool {
+        let Some(entry) = self.entries.remove(hash) else {
+            return false;
+        };
+        self.by_priority.remove(&entry.priority);
+        self.bytes -= entry.tx.payload().len();
+        true
+    }
+
+    /// Summaries of the pending transactions, highest priority first.
+    fn pending(&self, now: Instant) -> Vec<PendingTransaction> {
+        self.by_priority
+            .values()
+            .rev()
+            .map(|hash| {
+                let entry = &self.entries[hash];
+                PendingTransaction {
+                    hash: *hash,
+                    namespace: entry.tx.namespace(),
+                    size: entry.tx.payload().len() as u64,
+                    age: now.saturating_duration_since(entry.added).as_secs(),
+                }
+            })
+            .collect()
+    }
+
+    /// Transactions which have not been submitted within `interval`, highest priority first.
+    ///
+    /// The returned transactions are marked as submitted at `now`.
+    fn due(&mut self, interval: Duration, now: Instant) -> Vec<Transaction> {
+        let mut due = vec![];
+        for hash in self.by_priority.values().rev() {
+            let entry = self
+                .entries
+                .get_mut(hash)
+                .expect("priority index is consistent with entries");
+            if entry
+                .submitted
+                .is_some_and(|submitted| now.duration_since(submitted) < interval)
+            {
+                continue;
+            }
+            entry.submitted = Some(now);
+            due.push(entry.tx.clone());
+        }
+        due
+    }
+}
+
+/// A transaction waiting in the mempool, as served by `submit/pending`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PendingTransaction {
+    pub hash: Commitment<Transaction>,
+    pub namespace: NamespaceId,
+    /// Size of the transaction payload in bytes.
+    pub size: u64,
+    /// Seconds since the transaction was added to the mempool, or since this node restarted.
+    pub age: u64,
+}
+
+/// Where to gossip transactions submitted through this node.
+#[derive(Debug)]
+struct Gossip {
+    public_key: PubKey,
+    sender: Sender<OutboundMessage>,
+}
+
+/// A bounded, persistent pool of transactions waiting to be sequenced.
+#[derive(Debug)]
+pub struct Mempool<P> {
+    persistence: Arc<P>,
+    config: MempoolConfig,
+    base_fee: FeeAmount,
+    gossip: Option<Gossip>,
+    inner: Mutex<Inner>,
+}
+
+impl<P: SequencerPersistence> Mempool<P> {
+    /// An empty mempool, persisted in `persistence`.
+    ///
+    /// Transactions are prioritized by the fee they would incur at `base_fee`.
+    pub fn new(persistence: Arc<P>, config: MempoolConfig, base_fee: FeeAmount) -> Self {
+        Self {
+            persistence,
+            config,
+            base_fee,
+            gossip: None,
+            inner: Default::default(),
+        }
+    }
+
+    /// Gossip transactions submitted through this node to peers, via the external message queue.
+    pub(crate) fn with_gossip(
+        mut self,
+        public_key: PubKey,
+        sender: Sender<OutboundMessage>,
+    ) -> Self {
+        self.gossip = Some(Gossip { public_key, sender });
+        self
+    }
+
+    pub fn config(&self) -> &MempoolConfig {
+        &self.config
+    }
+
+    /// The number of transactions in the mempool.
+    pub fn len(&self) -> usize {
+        self.inner.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the transaction with the given hash is in the mempool.
+    pub fn contains(&self, hash: Commitment<Transaction>) -> bool {
+        self.inner.lock().entries.contains_key(&hash)
+    }
+
+    fn fee(&self, tx: &Transaction) -> FeeAmount {
+        self.base_fee * tx.payload().len() as u64
+    }
+
+    /// Load transactions persisted by a previous run.
+    ///
+    /// Restored transactions have not been submitted to consensus by this run, so they are
+    /// resubmitted as soon as possible.
+    pub async fn restore(&self) -> anyhow::Result<()> {
+        let txs = self
+            .persistence
+            .load_mempool()
+            .await
+            .context("loading mempool")?;
+        let mut evicted = vec![];
+        {
+            let mut inner = self.inner.lock();
+            for tx in txs {
+                let hash = tx.commit();
+                let fee = self.fee(&tx);
+                match inner.insert(&self.config, tx, fee, None) {
+                    Ok(Inserted::Added(hashes)) => evicted.extend(hashes),
+                    Ok(Inserted::Duplicate) => {}
+                    // The mempool may have been configured with a smaller limit last time.
+                    Err(err) => {
+                        tracing::info!(%hash, "dropping persisted transaction: {err:#}");
+                        evicted.push(hash);
+                    }
+                }
+            }
+        }
+        tracing::info!(len = self.len(), "restored mempool");
+        self.persistence.remove_mempool_txs(&evicted).await
+    }
+
+    /// Add a transaction which was submitted through this node, and gossip it to peers.
+    ///
+    /// The transaction is assumed to have just been submitted to consensus. Returns `false` if the
+    /// transaction was already in the mempool.
+    pub async fn add(&self, tx: Transaction) -> anyhow::Result<bool> {
+        Ok(self.add_batch(vec![tx]).await? == 1)
+    }
+
+    /// Add a batch of transactions which were submitted through this node.
+    ///
+    /// Newly added transactions are gossiped to peers in a single message. Returns the number of
+    /// transactions which were not already in the mempool.
+    pub async fn add_batch(&self, txs: Vec<Transaction>) -> anyhow::Result<usize> {
+        let mut added = vec![];
+        for tx in txs {
+            if self.insert(tx.clone()).await? {
+                added.push(tx);
+            }
+        }
+        if added.is_empty() {
+            return Ok(0);
+        }
+
+        let count = added.len();
+        if let Some(gossip) = &self.gossip {
+            let message = ExternalMessage::MempoolTransactions(added);
+            let bytes = encode_external_message(&gossip.public_key, &message)
+                .context("serializing mempool gossip")?;
+            if let Err(err) = gossip.sender.try_send(OutboundMessage::Broadcast(bytes)) {
+                tracing::warn!("unable to gossip transactions: {err}");
+            }
+        }
+        Ok(count)
+    }
+
+    /// Insert and persist a transaction, returning whether it was newly added.
+    async fn insert(&self, tx: Transaction) -> anyhow::Result<bool> {
+        let fee = self.fee(&tx);
+        let inserted =
+            self.inner
+                .lock()
+                .insert(&self.config, tx.clone(), fee, Some(Instant::now()))?;
+        let evicted = match inserted {
+            Inserted::Duplicate => return Ok(false),
+            Inserted::Added(evicted) => evicted,
+        };
+
+        self.persistence.append_mempool_txs(&[tx]).await?;
+        self.persistence.remove_mempool_txs(&evicted).await?;
+        Ok(true)
+    }
+
+    /// Remove transactions which have been sequenced in a decided block.
+    pub async fn handle_event(&self, event: &Event<SeqTypes>) {
+        let EventType::Decide { leaf_chain, .. } = &event.event else {
+            return;
+        };
+
+        let mut removed = vec![];
+        {
+            let mut inner = self.inner.lock();
+            for LeafInfo { leaf, .. } in leaf_chain.iter() {
+                let Some(payload) = leaf.block_payload() else {
+                    continue;
+                };
+                for tx in payload.transactions(payload.ns_table()) {
+                    let hash = tx.commit();
+                    if inner.remove(&hash) {
+                        removed.push(hash);
+                    }
+                }
+            }
+        }
+
+        if removed.is_empty() {
+            return;
+        }
+        tracing::debug!(
+            count = removed.len(),
+            "removing sequenced transactions from mempool"
+        );
+        if let Err(err) = self.persistence.remove_mempool_txs(&removed).await {
+            tracing::warn!("failed to remove sequenced transactions from mempool: {err:#}");
+        }
+    }
+
+    /// The transactions in the mempool, highest priority first.
+    pub fn pending(&self) -> Vec<PendingTransaction> {
+        self.inner.lock().pending(Instant::now())
+    }
+
+    /// Drop a transaction from the mempool, so that it is no longer resubmitted.
+    ///
+    /// Returns `false` if the transaction was not in the mempool.
+    pub async fn evict(&self, hash: Commitment<Transaction>) -> anyhow::Result<bool> {
+        if !self.inner.lock().remove(&hash) {
+            return Ok(false);
+        }
+        tracing::info!(%hash, "evicting transaction from mempool");
+        self.persistence.remove_mempool_txs(&[hash]).await?;
+        Ok(true)
+    }
+
+    /// Pending transactions which are due to be resubmitted to consensus, highest priority first.
+    pub fn due(&self) -> Vec<Transaction> {
+        self.inner
+            .lock()
+            .due(self.config.resubmit_interval, Instant::now())
+    }
+}
+
+#[async_trait]
+impl<P: SequencerPersistence> MempoolSink for Mempool<P> {
+    async fn receive_gossip(&self, txs: Vec<Transaction>) {
+        for tx in txs {
+            let hash = tx.commit();
+            if let Err(err) = self.insert(tx).await {
+                tracing::debug!(%hash, "not adding gossiped transaction to mempool: {err:#}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tx(size: usize, byte: u8) -> Transaction {
+        Transaction::new(NamespaceId::from(1_u32), vec![byte; size])
+    }
+
+    fn config(capacity: usize, max_bytes: usize) -> MempoolConfig {
+        MempoolConfig {
+            capacity,
+            max_bytes,
+            ..Default::default()
+        }
+    }
+
+    fn insert(
+        inner: &mut Inner,
+        cfg: &MempoolConfig,
+        tx: &Transaction,
+    ) -> anyhow::Result<Inserted> {
+        let fee = FeeAmount::from(tx.payload().len() as u64);
+        inner.insert(cfg, tx.clone(), fee, None)
+    }
+
+    #[test]
+    fn test_mempool_duplicate() {
+        let cfg = config(10, 1000);
+        let mut inner = Inner::default();
+        let a = tx(10, 0);
+        assert_eq!(
+            insert(&mut inner, &cfg, &a).unwrap(),
+            Inserted::Added(vec![])
+        );
+        assert_eq!(insert(&mut inner, &cfg, &a).unwrap(), Inserted::Duplicate);
+        assert_eq!(inner.entries.len(), 1);
+        assert_eq!(inner.bytes, 10);
+    }
+
+    #[test]
+    fn test_mempool_evict_by_capacity() {
+        let cfg = config(2, 1000);
+        let mut inner = Inner::default();
+        let small = tx(10, 0);
+        let medium = tx(20, 1);
+        let large = tx(30, 2);
+        insert(&mut inner, &cfg, &small).unwrap();
+        insert(&mut inner, &cfg, &medium).unwrap();
+
+        // A higher fee transaction evicts the lowest fee one.
+        assert_eq!(
+            insert(&mut inner, &cfg, &large).unwrap(),
+            Inserted::Added(vec![small.commit()])
+        );
+
+        // A transaction with a lower fee than everything in the mempool is rejected.
+        insert(&mut inner, &cfg, &tx(10, 3)).unwrap_err();
+
+        // A transaction with the same fee as the lowest is rejected, so earlier transactions win.
+        insert(&mut inner, &cfg, &tx(20, 4)).unwrap_err();
+
+        assert_eq!(inner.entries.len(), 2);
+        assert_eq!(inner.bytes, 50);
+    }
+
+    #[test]
+    fn test_mempool_evict_by_size() {
+        let cfg = config(10, 50);
+        let mut inner = Inner::default();
+        let a = tx(10, 0);
+        let b = tx(15, 1);
+        let c = tx(20, 2);
+        insert(&mut inner, &cfg, &a).unwrap();
+        insert(&mut inner, &cfg, &b).unwrap();
+        insert(&mut inner, &cfg, &c).unwrap();
+
+        // Making room for 30 bytes requires evicting the two smallest transactions.
+        assert_eq!(
+            insert(&mut inner, &cfg, &tx(30, 3)).unwrap(),
+            Inserted::Added(vec![a.commit(), b.commit()])
+        );
+        assert_eq!(inner.bytes, 50);
+
+        // Transactions larger than the whole mempool are rejected.
+        insert(&mut inner, &cfg, &tx(51, 4)).unwrap_err();
+    }
+
+    #[test]
+    fn test_mempool_due() {
+        let cfg = config(10, 1000);
+        let mut inner = Inner::default();
+        let a = tx(10, 0);
+        let b = tx(20, 1);
+        insert(&mut inner, &cfg, &a).unwrap();
+        insert(&mut inner, &cfg, &b).unwrap();
+
+        // Everything is due at first, highest priority first.
+        let interval = Duration::from_secs(30);
+        let now = Instant::now();
+        assert_eq!(inner.due(interval, now), vec![b.clone(), a.clone()]);
+
+        // Nothing is due again until the interval has passed.
+        assert_eq!(inner.due(interval, now + interval / 2), vec![]);
+        assert_eq!(inner.due(interval, now + interval), vec![b, a.clone()]);
+
+        // Removed transactions are no longer resubmitted.
+        assert!(inner.remove(&a.commit()));
+        assert!(!inner.remove(&a.commit()));
+        assert_eq!(inner.due(interval, now + interval * 2).len(), 1);
+    }
+
+    #[test]
+    fn test_mempool_pending() {
+        let cfg = config(10, 1000);
+        let mut inner = Inner::default();
+        let a = tx(10, 0);
+        let b = tx(20, 1);
+        insert(&mut inner, &cfg, &a).unwrap();
+        insert(&mut inner, &cfg, &b).unwrap();
+
+        let pending = inner.pending(Instant::now() + Duration::from_secs(5));
+        assert_eq!(
+            pending.iter().map(|tx| tx.hash).collect::<Vec<_>>(),
+            vec![b.commit(), a.commit()]
+        );
+        assert_eq!(pending[0].namespace, b.namespace());
+        assert_eq!(pending[0].size, 20);
+        assert!(pending[0].age >= 5);
+    }
+}
@@ -0,0 +1,305 @@
+//! Health of the hybrid CDN and libp2p network.
+//!
+//! The production network sends consensus messages through the CDN, and falls back to libp2p when
+//! the CDN appears to be down. [`NetworkHealth`] tracks the state of each transport, so that
+//! operators can tell which one the node is relying on at any given time.
+//!
+//! The tracker is fed in two ways. The metrics which each transport already reports are
+//! [instrumented](NetworkHealth::instrument_cdn) so that their values can be read back, and a
+//! [monitor](NetworkHealth::monitor) task polls the combined network to detect failovers and to
+//! compute the rate of failed messages.
+
+use std::{
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use async_lock::RwLock;
+use espresso_types::PubKey;
+use hotshot::traits::implementations::{CdnMetricsValue, Libp2pMetricsValue};
+use hotshot_types::traits::{
+    metrics::{Counter, Gauge},
+    network::ConnectedNetwork,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use super::Production;
+
+/// How often the monitor samples the state of the network.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A transport the production network can send messages over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Cdn,
+    Libp2p,
+}
+
+/// The health of one transport.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransportStatus {
+    pub transport: Transport,
+    /// Whether the transport is currently able to carry messages.
+    pub connected: bool,
+    /// The number of peers connected over this transport.
+    ///
+    /// This is `None` for the CDN, which connects to a broker rather than to peers.
+    pub peers: Option<u64>,
+    /// The total number of messages the transport has failed to send.
+    pub failed_messages: u64,
+    /// Failed messages per second, over the most recent sampling interval.
+    pub failed_message_rate: f64,
+    /// When the transport first became ready, in seconds since the Unix epoch.
+    pub ready_since: Option<u64>,
+}
+
+/// The health of the network as a whole, as reported by `status/network`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NetworkStatus {
+    /// The transport consensus messages are currently sent over.
+    pub active: Transport,
+    /// The health of each transport.
+    pub transports: Vec<TransportStatus>,
+    /// The number of times the node has failed over from the CDN to libp2p.
+    pub failovers: u64,
+    /// When the node last failed over from the CDN to libp2p, in seconds since the Unix epoch.
+    pub last_failover: Option<u64>,
+    /// When the node last switched back to the CDN after a failover, in seconds since the Unix
+    /// epoch.
+    pub last_recovery: Option<u64>,
+}
+
+/// Values read back from the metrics reported by the transports.
+#[derive(Debug, Default)]
+struct Instruments {
+    cdn_failed_messages: AtomicU64,
+    libp2p_failed_messages: AtomicU64,
+    libp2p_peers: AtomicI64,
+    libp2p_ready: AtomicI64,
+}
+
+/// State maintained by the monitor.
+#[derive(Clone, Debug, Default)]
+struct Sampled {
+    primary_down: bool,
+    failovers: u64,
+    last_failover: Option<u64>,
+    last_recovery: Option<u64>,
+    cdn_ready_since: Option<u64>,
+    libp2p_ready_since: Option<u64>,
+    cdn_failed_message_rate: f64,
+    libp2p_failed_message_rate: f64,
+}
+
+/// Tracker for the health of the production network.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkHealth {
+    instruments: Arc<Instruments>,
+    sampled: Arc<RwLock<Sampled>>,
+}
+
+impl NetworkHealth {
+    /// Instrument the metrics reported by the CDN, so that they are also recorded here.
+    pub fn instrument_cdn(&self, mut metrics: CdnMetricsValue) -> CdnMetricsValue {
+        metrics.num_failed_messages = Box::new(InstrumentedCounter {
+            inner: metrics.num_failed_messages,
+            instruments: self.instruments.clone(),
+            read: |instruments| &instruments.cdn_failed_messages,
+        });
+        metrics
+    }
+
+    /// Instrument the metrics reported by libp2p, so that they are also recorded here.
+    pub fn instrument_libp2p(&self, mut metrics: Libp2pMetricsValue) -> Libp2pMetricsValue {
+        metrics.num_failed_messages = Box::new(InstrumentedCounter {
+            inner: metrics.num_failed_messages,
+            instruments: self.instruments.clone(),
+            read: |instruments| &instruments.libp2p_failed_messages,
+        });
+        metrics.num_connected_peers = Box::new(InstrumentedGauge {
+            inner: metrics.num_connected_peers,
+            instruments: self.instruments.clone(),
+            read: |instruments| &instruments.libp2p_peers,
+        });
+        metrics.is_ready = Box::new(InstrumentedGauge {
+            inner: metrics.is_ready,
+            instruments: self.instruments.clone(),
+            read: |instruments| &instruments.libp2p_ready,
+        });
+        metrics
+    }
+
+    /// The current health of the network.
+    pub async fn status(&self) -> NetworkStatus {
+        let sampled = self.sampled.read().await.clone();
+        let libp2p_ready = self.instruments.libp2p_ready.load(Ordering::Relaxed) > 0;
+        let libp2p_peers = self.instruments.libp2p_peers.load(Ordering::Relaxed).max(0) as u64;
+        NetworkStatus {
+            active: if sampled.primary_down {
+                Transport::Libp2p
+            } else {
+                Transport::Cdn
+            },
+            transports: vec![
+                TransportStatus {
+                    transport: Transport::Cdn,
+                    connected: sampled.cdn_ready_since.is_some() && !sampled.primary_down,
+                    peers: None,
+                    failed_messages: self.instruments.cdn_failed_messages.load(Ordering::Relaxed),
+                    failed_message_rate: sampled.cdn_failed_message_rate,
+                    ready_since: sampled.cdn_ready_since,
+                },
+                TransportStatus {
+                    transport: Transport::Libp2p,
+                    connected: libp2p_ready && libp2p_peers > 0,
+                    peers: Some(libp2p_peers),
+                    failed_messages: self
+                        .instruments
+                        .libp2p_failed_messages
+                        .load(Ordering::Relaxed),
+                    failed_message_rate: sampled.libp2p_failed_message_rate,
+                    ready_since: sampled.libp2p_ready_since,
+                },
+            ],
+            failovers: sampled.failovers,
+            last_failover: sampled.last_failover,
+            last_recovery: sampled.last_recovery,
+        }
+    }
+
+    /// Watch `network` for failovers between its transports, and sample failed message rates.
+    ///
+    /// `cdn` is the CDN transport of `network`, which is watched until it first becomes ready.
+    pub async fn monitor(self, network: Arc<Production>, cdn: impl ConnectedNetwork<PubKey>) {
+        let ready = async {
+            cdn.wait_for_ready().await;
+            self.sampled.write().await.cdn_ready_since = Some(unix_timestamp());
+        };
+        let poll = async {
+            let mut last_sample = Instant::now();
+            let mut last_cdn_failed = 0;
+            let mut last_libp2p_failed = 0;
+            loop {
+                sleep(POLL_INTERVAL).await;
+                let now = unix_timestamp();
+                let elapsed = last_sample.elapsed().as_secs_f64();
+                last_sample = Instant::now();
+                let cdn_failed = self.instruments.cdn_failed_messages.load(Ordering::Relaxed);
+                let libp2p_failed = self
+                    .instruments
+                    .libp2p_failed_messages
+                    .load(Ordering::Relaxed);
+                let primary_down = network.is_primary_down();
+
+                let mut sampled = self.sampled.write().await;
+                sampled.cdn_failed_message_rate =
+                    cdn_failed.saturating_sub(last_cdn_failed) as f64 / elapsed;
+                sampled.libp2p_failed_message_rate =
+                    libp2p_failed.saturating_sub(last_libp2p_failed) as f64 / elapsed;
+                last_cdn_failed = cdn_failed;
+                last_libp2p_failed = libp2p_failed;
+
+                if sampled.libp2p_ready_since.is_none()
+                    && self.instruments.libp2p_ready.load(Ordering::Relaxed) > 0
+                {
+                    sampled.libp2p_ready_since = Some(now);
+                }
+
+                if primary_down != sampled.primary_down {
+                    if primary_down {
+                        tracing::warn!("CDN is down, failing over to libp2p");
+                        sampled.failovers += 1;
+                        sampled.last_failover = Some(now);
+                    } else {
+                        tracing::info!("CDN is back up, no longer relying on libp2p");
+                        sampled.last_recovery = Some(now);
+                    }
+                    sampled.primary_down = primary_down;
+                }
+            }
+        };
+        futures::join!(ready, poll);
+    }
+}
+
+/// A counter which also records its total in [`Instruments`].
+#[derive(Clone, Debug)]
+struct InstrumentedCounter {
+    inner: Box<dyn Counter>,
+    instruments: Arc<Instruments>,
+    read: fn(&Instruments) -> &AtomicU64,
+}
+
+impl Counter for InstrumentedCounter {
+    fn add(&self, amount: usize) {
+        (self.read)(&self.instruments).fetch_add(amount as u64, Ordering::Relaxed);
+        self.inner.add(amount);
+    }
+}
+
+/// A gauge which also records its value in [`Instruments`].
+#[derive(Clone, Debug)]
+struct InstrumentedGauge {
+    inner: Box<dyn Gauge>,
+    instruments: Arc<Instruments>,
+    read: fn(&Instruments) -> &AtomicI64,
+}
+
+impl Gauge for InstrumentedGauge {
+    fn set(&self, amount: usize) {
+        (self.read)(&self.instruments).store(amount as i64, Ordering::Relaxed);
+        self.inner.set(amount);
+    }
+
+    fn update(&self, delta: i64) {
+        (self.read)(&self.instruments).fetch_add(delta, Ordering::Relaxed);
+        self.inner.update(delta);
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod test {
+    use hotshot_types::traits::metrics::NoMetrics;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_instrumented_metrics() {
+        let health = NetworkHealth::default();
+        let cdn = health.instrument_cdn(CdnMetricsValue::new(&NoMetrics));
+        let libp2p = health.instrument_libp2p(Libp2pMetricsValue::new(&NoMetrics));
+
+        cdn.num_failed_messages.add(3);
+        libp2p.num_failed_messages.add(1);
+        libp2p.num_connected_peers.set(4);
+        libp2p.num_connected_peers.update(-1);
+        libp2p.is_ready.set(1);
+
+        let status = health.status().await;
+        assert_eq!(status.active, Transport::Cdn);
+        assert_eq!(status.failovers, 0);
+
+        let [cdn, libp2p] = &status.transports[..] else {
+            panic!("expected two transports: {status:?}");
+        };
+        assert_eq!(cdn.transport, Transport::Cdn);
+        assert_eq!(cdn.failed_messages, 3);
+        // The CDN is not considered connected until it becomes ready.
+        assert!(!cdn.connected);
+        assert_eq!(libp2p.transport, Transport::Libp2p);
+        assert_eq!(libp2p.failed_messages, 1);
+        assert_eq!(libp2p.peers, Some(3));
+        assert!(libp2p.connected);
+    }
+}
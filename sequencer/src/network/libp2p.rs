@@ -1,4 +1,8 @@
-use anyhow::Result;
+use std::{collections::HashSet, net::IpAddr};
+
+use anyhow::{bail, ensure, Context, Result};
+use clap::ValueEnum;
+use hotshot::traits::implementations::GossipConfig;
 use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
 
 /// Split off the peer ID from a multiaddress, returning the shortened address and the peer ID.
@@ -12,3 +16,385 @@ pub fn split_off_peer_id(mut address: Multiaddr) -> Result<(PeerId, Multiaddr)>
 
     Ok((peer_id, address))
 }
+
+/// The transport libp2p connections are made over.
+///
+/// The transport is selected by the form of the addresses we listen on and dial. HotShot's libp2p
+/// swarm only supports QUIC, whose addresses look like `/ip4/10.0.0.1/udp/1769/quic-v1`. Its TLS
+/// certificates are derived from the node's libp2p key, so there are no certificates to configure.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Transport {
+    #[default]
+    Quic,
+}
+
+impl Transport {
+    /// Whether peers at `address` can be dialed over this transport.
+    pub fn supports(self, address: &Multiaddr) -> bool {
+        let protocols = address.iter().collect::<Vec<_>>();
+        match self {
+            Self::Quic => protocols
+                .windows(2)
+                .any(|pair| matches!(pair, [Protocol::Udp(_), Protocol::QuicV1])),
+        }
+    }
+
+    /// Convert a `host:port` address to a multiaddress for listening on this transport.
+    ///
+    /// # Errors
+    /// - If the address is not of the form `host:port`.
+    pub fn multiaddr(self, address: &str) -> Result<Multiaddr> {
+        let (host, port) = address
+            .rsplit_once(':')
+            .with_context(|| format!("address {address} is not of the form host:port"))?;
+        let port = port
+            .parse::<u16>()
+            .with_context(|| format!("invalid port in address {address}"))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let multiaddr = Multiaddr::empty().with(match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => Protocol::Ip4(ip),
+            Ok(IpAddr::V6(ip)) => Protocol::Ip6(ip),
+            Err(_) => Protocol::Dns(host.to_string().into()),
+        });
+        Ok(match self {
+            Self::Quic => multiaddr.with(Protocol::Udp(port)).with(Protocol::QuicV1),
+        })
+    }
+}
+
+/// Check that `address` is dialable over `transport`.
+///
+/// Peers listening on other transports can never be reached, so an address like
+/// `/ip4/10.0.0.1/tcp/1769` is rejected here rather than being dialed in vain.
+///
+/// # Errors
+/// - If the address does not contain a UDP port followed by `quic-v1`, when using QUIC.
+pub fn validate_transport(address: &Multiaddr, transport: Transport) -> Result<()> {
+    ensure!(
+        transport.supports(address),
+        "libp2p address {address} cannot be dialed over the {transport:?} transport; expected an \
+         address of the form {}",
+        match transport {
+            Transport::Quic => "/ip4/<ip>/udp/<port>/quic-v1",
+        }
+    );
+    Ok(())
+}
+
+/// Whether peers on the public internet could dial `address`.
+///
+/// The libp2p swarm is built by HotShot, and this crate cannot add a circuit relay or hole punching
+/// to it, so a node which advertises a private address can only be dialed by peers on the same
+/// network, unless the swarm itself discovers a public address. This is a best-effort check: an
+/// address which looks public may still be unreachable behind a firewall.
+pub fn is_publicly_dialable(address: &Multiaddr) -> bool {
+    match address.iter().next() {
+        Some(Protocol::Ip4(ip)) => {
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                // Carrier-grade NAT (`100.64.0.0/10`).
+                || (ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64))
+        }
+        Some(Protocol::Ip6(ip)) => {
+            // Unique local addresses (`fc00::/7`) are the IPv6 equivalent of private addresses.
+            !(ip.is_loopback() || ip.is_unspecified() || (ip.segments()[0] & 0xfe00) == 0xfc00)
+        }
+        Some(Protocol::Dns(name) | Protocol::Dns4(name) | Protocol::Dns6(name)) => {
+            name != "localhost"
+        }
+        _ => true,
+    }
+}
+
+/// What to do when the Libp2p advertise address is not publicly dialable.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PrivateAddressPolicy {
+    /// Start normally, for networks whose nodes all share a private network.
+    Allow,
+    /// Start, but warn that peers outside the local network will not be able to connect.
+    #[default]
+    Warn,
+    /// Refuse to start, so that a node behind NAT is not run unreachable by mistake.
+    Reject,
+}
+
+/// Check the address we advertise to peers against `policy`.
+///
+/// Returns whether the address is publicly dialable.
+///
+/// # Errors
+/// - If the address is not publicly dialable and `policy` is [`PrivateAddressPolicy::Reject`].
+pub fn check_advertise_address(address: &Multiaddr, policy: PrivateAddressPolicy) -> Result<bool> {
+    if is_publicly_dialable(address) {
+        return Ok(true);
+    }
+    match policy {
+        PrivateAddressPolicy::Allow => {}
+        PrivateAddressPolicy::Warn => tracing::warn!(
+            %address,
+            "Libp2p advertise address is not publicly reachable; peers outside this network will \
+             not be able to connect to this node over libp2p. If the node is behind NAT, forward \
+             the Libp2p port and advertise the public address"
+        ),
+        PrivateAddressPolicy::Reject => bail!(
+            "Libp2p advertise address {address} is not publicly reachable; forward the Libp2p port \
+             and advertise the public address, or allow private addresses"
+        ),
+    }
+    Ok(false)
+}
+
+/// Join a peer ID back onto its address, the inverse of [`split_off_peer_id`].
+pub fn join_peer_id(peer_id: PeerId, address: Multiaddr) -> Multiaddr {
+    address.with(Protocol::P2p(peer_id))
+}
+
+/// Combine the peers to bootstrap libp2p from, keeping one address per peer.
+///
+/// Pinned peers come first, followed by the configured bootstrap nodes and then peers remembered
+/// from previous runs, so that configuration takes precedence over a stale remembered address. At
+/// most `max_peers` peers which are not pinned are kept; pinned peers are never dropped.
+pub fn merge_peers(
+    pinned: &[(PeerId, Multiaddr)],
+    configured: &[(PeerId, Multiaddr)],
+    stored: &[(PeerId, Multiaddr)],
+    max_peers: usize,
+) -> Vec<(PeerId, Multiaddr)> {
+    let mut seen = HashSet::new();
+    let mut peers = vec![];
+    for (peer_id, address) in pinned {
+        if seen.insert(*peer_id) {
+            peers.push((*peer_id, address.clone()));
+        }
+    }
+    let num_pinned = peers.len();
+    for (peer_id, address) in configured.iter().chain(stored) {
+        if peers.len() >= num_pinned + max_peers {
+            break;
+        }
+        if seen.insert(*peer_id) {
+            peers.push((*peer_id, address.clone()));
+        }
+    }
+    peers
+}
+
+/// Check that gossip parameters are consistent with each other.
+///
+/// Gossipsub rejects inconsistent parameters deep inside network initialization, with an error that
+/// does not say which option to change. Checking them up front lets us name the offending options.
+///
+/// # Errors
+/// - If the mesh bounds are not ordered `mesh_outbound_min <= mesh_n_low <= mesh_n <= mesh_n_high`.
+/// - If more than half of the target mesh is required to be outbound.
+/// - If more heartbeats are gossiped about than are kept in history.
+/// - If the gossip factor is not between 0 and 1.
+pub fn validate_gossip_config(config: &GossipConfig) -> Result<()> {
+    ensure!(
+        config.mesh_outbound_min <= config.mesh_n_low
+            && config.mesh_n_low <= config.mesh_n
+            && config.mesh_n <= config.mesh_n_high,
+        "libp2p mesh bounds must satisfy mesh_outbound_min ({}) <= mesh_n_low ({}) <= mesh_n ({}) \
+         <= mesh_n_high ({})",
+        config.mesh_outbound_min,
+        config.mesh_n_low,
+        config.mesh_n,
+        config.mesh_n_high,
+    );
+    ensure!(
+        config.mesh_outbound_min * 2 <= config.mesh_n,
+        "libp2p mesh_outbound_min ({}) must be at most half of mesh_n ({})",
+        config.mesh_outbound_min,
+        config.mesh_n,
+    );
+    ensure!(
+        config.history_gossip <= config.history_length,
+        "libp2p history_gossip ({}) must not exceed history_length ({})",
+        config.history_gossip,
+        config.history_length,
+    );
+    ensure!(
+        (0.0..=1.0).contains(&config.gossip_factor),
+        "libp2p gossip_factor ({}) must be between 0 and 1",
+        config.gossip_factor,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate_transport() {
+        let with_peer_id: Multiaddr = "/dns/node.example.com/udp/1769/quic-v1".parse().unwrap();
+        for address in [
+            "/ip4/10.0.0.1/udp/1769/quic-v1".parse().unwrap(),
+            join_peer_id(PeerId::random(), with_peer_id),
+        ] {
+            validate_transport(&address, Transport::Quic).unwrap();
+        }
+        for address in [
+            "/ip4/10.0.0.1/tcp/1769",
+            "/ip4/10.0.0.1/udp/1769",
+            "/ip4/10.0.0.1/udp/1769/quic",
+        ] {
+            validate_transport(&address.parse().unwrap(), Transport::Quic).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_transport_multiaddr() {
+        for (address, quic) in [
+            ("0.0.0.0:1769", "/ip4/0.0.0.0/udp/1769/quic-v1"),
+            ("[::1]:1769", "/ip6/::1/udp/1769/quic-v1"),
+            ("localhost:1769", "/dns/localhost/udp/1769/quic-v1"),
+        ] {
+            assert_eq!(
+                Transport::Quic.multiaddr(address).unwrap().to_string(),
+                quic
+            );
+        }
+        for address in [
+            "localhost",
+            "localhost:",
+            "localhost:port",
+            "localhost:65536",
+        ] {
+            Transport::Quic.multiaddr(address).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_is_publicly_dialable() {
+        for address in [
+            "/ip4/8.8.8.8/udp/1769/quic-v1",
+            "/ip6/2001:4860:4860::8888/udp/1769/quic-v1",
+            "/dns/node.example.com/udp/1769/quic-v1",
+        ] {
+            assert!(is_publicly_dialable(&address.parse().unwrap()), "{address}");
+        }
+        for address in [
+            "/ip4/192.168.1.10/udp/1769/quic-v1",
+            "/ip4/10.0.0.1/udp/1769/quic-v1",
+            "/ip4/127.0.0.1/udp/1769/quic-v1",
+            "/ip4/100.64.0.1/udp/1769/quic-v1",
+            "/ip4/0.0.0.0/udp/1769/quic-v1",
+            "/ip6/::1/udp/1769/quic-v1",
+            "/ip6/fd00::1/udp/1769/quic-v1",
+            "/dns/localhost/udp/1769/quic-v1",
+        ] {
+            assert!(
+                !is_publicly_dialable(&address.parse().unwrap()),
+                "{address}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_advertise_address() {
+        let public = "/ip4/8.8.8.8/tcp/1769".parse().unwrap();
+        let private = "/ip4/192.168.1.10/tcp/1769".parse().unwrap();
+        for policy in [
+            PrivateAddressPolicy::Allow,
+            PrivateAddressPolicy::Warn,
+            PrivateAddressPolicy::Reject,
+        ] {
+            assert!(check_advertise_address(&public, policy).unwrap());
+        }
+        assert!(!check_advertise_address(&private, PrivateAddressPolicy::Allow).unwrap());
+        assert!(!check_advertise_address(&private, PrivateAddressPolicy::Warn).unwrap());
+        check_advertise_address(&private, PrivateAddressPolicy::Reject).unwrap_err();
+    }
+
+    #[test]
+    fn test_merge_peers() {
+        let peer = |port: u16| {
+            (
+                PeerId::random(),
+                format!("/ip4/127.0.0.1/udp/{port}/quic-v1")
+                    .parse()
+                    .unwrap(),
+            )
+        };
+        let pinned = vec![peer(1)];
+        let configured = vec![peer(2), peer(3)];
+        let mut stored = vec![configured[0].clone(), peer(4)];
+        // A remembered address is superseded by the configured one for the same peer.
+        stored[0].1 = "/ip4/127.0.0.1/udp/5/quic-v1".parse().unwrap();
+
+        assert_eq!(
+            merge_peers(&pinned, &configured, &stored, 10),
+            vec![
+                pinned[0].clone(),
+                configured[0].clone(),
+                configured[1].clone(),
+                stored[1].clone()
+            ]
+        );
+
+        // Unpinned peers are pruned beyond the limit, but pinned peers are always kept.
+        let pinned = (0..3).map(peer).collect::<Vec<_>>();
+        let stored = (0..20).map(peer).collect::<Vec<_>>();
+        for max_peers in [0, 1, 10] {
+            let merged = merge_peers(&pinned, &[], &stored, max_peers);
+            assert_eq!(merged.len(), pinned.len() + max_peers);
+            assert_eq!(merged[..pinned.len()], pinned);
+        }
+
+        // Peer IDs survive a round trip through the stored format.
+        let (peer_id, address) = peer(6);
+        assert_eq!(
+            split_off_peer_id(join_peer_id(peer_id, address.clone())).unwrap(),
+            (peer_id, address)
+        );
+    }
+
+    #[test]
+    fn test_validate_gossip_config() {
+        let config = GossipConfig {
+            mesh_n: 8,
+            mesh_n_high: 12,
+            mesh_n_low: 6,
+            mesh_outbound_min: 2,
+            history_gossip: 3,
+            history_length: 5,
+            gossip_factor: 0.25,
+            ..Default::default()
+        };
+        validate_gossip_config(&config).unwrap();
+
+        // A larger deployment may grow the mesh, as long as the bounds stay ordered.
+        validate_gossip_config(&GossipConfig {
+            mesh_n: 16,
+            mesh_n_high: 24,
+            mesh_n_low: 12,
+            mesh_outbound_min: 4,
+            ..config.clone()
+        })
+        .unwrap();
+
+        validate_gossip_config(&GossipConfig {
+            mesh_n_high: 7,
+            ..config.clone()
+        })
+        .unwrap_err();
+        validate_gossip_config(&GossipConfig {
+            mesh_outbound_min: 5,
+            ..config.clone()
+        })
+        .unwrap_err();
+        validate_gossip_config(&GossipConfig {
+            history_gossip: 6,
+            ..config.clone()
+        })
+        .unwrap_err();
+        validate_gossip_config(&GossipConfig {
+            gossip_factor: 1.5,
+            ..config
+        })
+        .unwrap_err();
+    }
+}
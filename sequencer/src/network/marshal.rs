@@ -0,0 +1,546 @@
+//! Selection of, and failover between, CDN marshals.
+//!
+//! A node joins the CDN by connecting to a marshal, which authenticates it and assigns it a broker.
+//! Operators can run marshals in several regions, but the CDN client only takes a single marshal
+//! endpoint, and keeps reconnecting to it even while it is unreachable. When several marshals are
+//! configured, the CDN client is instead pointed at a [`MarshalRelay`] on the loopback interface,
+//! which forwards the client's datagrams to one of the real marshals.
+//!
+//! Each time the client connects, the relay picks the marshal which has responded fastest so far,
+//! after trying each marshal once. A marshal which does not respond within the configured timeout
+//! is considered down and is not tried again until a backoff delay has passed. The client's next
+//! retransmission is then forwarded to the next best marshal. Since the assigned broker is
+//! contacted directly, only the connection to the marshal goes through the relay.
+//!
+//! The active marshal, its latency and the number of switches between marshals are reported in the
+//! `cdn_marshal` group of metrics.
+
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{ensure, Context};
+use clap::Parser;
+use espresso_types::parse_duration;
+use hotshot_types::traits::metrics::{Counter, Gauge, Metrics};
+use tokio::{
+    net::{lookup_host, UdpSocket},
+    sync::mpsc,
+    task::JoinHandle,
+    time::{interval, MissedTickBehavior},
+};
+
+/// Largest datagram the relay forwards.
+const MAX_DATAGRAM: usize = 65_535;
+
+/// How long to keep forwarding state for a client connection without any traffic.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How often to look for unresponsive marshals and idle connections.
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Weight of each new sample in the moving average of a marshal's latency.
+const LATENCY_WEIGHT: f64 = 0.3;
+
+/// Options for failing over between CDN marshals.
+#[derive(Clone, Copy, Debug, Parser)]
+pub struct MarshalOptions {
+    /// How long a marshal may take to respond before failing over to another one.
+    #[clap(
+        long = "cdn-marshal-timeout",
+        env = "ESPRESSO_SEQUENCER_CDN_MARSHAL_TIMEOUT",
+        default_value = "5s",
+        value_parser = parse_duration
+    )]
+    pub timeout: Duration,
+
+    /// How long to wait before trying a marshal again after it fails.
+    ///
+    /// The delay doubles each time the marshal fails again, up to the maximum retry delay.
+    #[clap(
+        long = "cdn-marshal-retry-delay",
+        env = "ESPRESSO_SEQUENCER_CDN_MARSHAL_RETRY_DELAY",
+        default_value = "10s",
+        value_parser = parse_duration
+    )]
+    pub retry_delay: Duration,
+
+    /// Maximum delay before trying a failed marshal again.
+    #[clap(
+        long = "cdn-marshal-max-retry-delay",
+        env = "ESPRESSO_SEQUENCER_CDN_MARSHAL_MAX_RETRY_DELAY",
+        default_value = "5m",
+        value_parser = parse_duration
+    )]
+    pub max_retry_delay: Duration,
+}
+
+impl Default for MarshalOptions {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+/// What we know about one marshal.
+#[derive(Clone, Debug)]
+struct MarshalState {
+    endpoint: String,
+    /// Moving average of the time the marshal takes to respond to a new connection.
+    latency: Option<Duration>,
+    /// Number of consecutive failures.
+    failures: u32,
+    /// When the marshal may be tried again after failing.
+    retry_at: Option<Instant>,
+}
+
+/// Chooses between marshals based on their latency and failures.
+#[derive(Clone, Debug)]
+struct Selector {
+    marshals: Vec<MarshalState>,
+    opt: MarshalOptions,
+}
+
+impl Selector {
+    fn new(endpoints: Vec<String>, opt: MarshalOptions) -> Self {
+        let marshals = endpoints
+            .into_iter()
+            .map(|endpoint| MarshalState {
+                endpoint,
+                latency: None,
+                failures: 0,
+                retry_at: None,
+            })
+            .collect();
+        Self { marshals, opt }
+    }
+
+    /// The marshal to use for a new connection.
+    ///
+    /// Marshals which have not been measured yet are tried first, in the configured order, and then
+    /// the one with the lowest latency. Marshals waiting to be retried after failing are skipped,
+    /// unless all of them are, in which case the one due to be retried first is used.
+    fn select(&self, now: Instant) -> usize {
+        let available = self
+            .marshals
+            .iter()
+            .enumerate()
+            .filter(|(_, marshal)| !matches!(marshal.retry_at, Some(at) if at > now));
+        if let Some((i, _)) = available
+            .clone()
+            .find(|(_, marshal)| marshal.latency.is_none())
+        {
+            return i;
+        }
+        if let Some((i, _)) = available.min_by_key(|(_, marshal)| marshal.latency) {
+            return i;
+        }
+        self.marshals
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, marshal)| marshal.retry_at)
+            .map_or(0, |(i, _)| i)
+    }
+
+    /// Record that marshal `i` responded to a new connection after `rtt`.
+    fn succeeded(&mut self, i: usize, rtt: Duration) -> Duration {
+        let marshal = &mut self.marshals[i];
+        let latency = match marshal.latency {
+            Some(latency) => latency.mul_f64(1. - LATENCY_WEIGHT) + rtt.mul_f64(LATENCY_WEIGHT),
+            None => rtt,
+        };
+        marshal.latency = Some(latency);
+        marshal.failures = 0;
+        marshal.retry_at = None;
+        latency
+    }
+
+    /// Record that marshal `i` failed, returning how long until it is tried again.
+    fn failed(&mut self, i: usize, now: Instant) -> Duration {
+        let marshal = &mut self.marshals[i];
+        let backoff = 2u32.saturating_pow(marshal.failures);
+        let delay = self
+            .opt
+            .retry_delay
+            .saturating_mul(backoff)
+            .min(self.opt.max_retry_delay);
+        marshal.failures += 1;
+        marshal.retry_at = Some(now + delay);
+        delay
+    }
+}
+
+#[derive(Debug)]
+struct RelayMetrics {
+    active: Box<dyn Gauge>,
+    latency: Box<dyn Gauge>,
+    switches: Box<dyn Counter>,
+    failures: Box<dyn Counter>,
+}
+
+impl RelayMetrics {
+    fn new(metrics: &dyn Metrics) -> Self {
+        let metrics = metrics.subgroup("cdn_marshal".into());
+        Self {
+            active: metrics.create_gauge("active".into(), None),
+            latency: metrics.create_gauge("latency".into(), Some("ms".into())),
+            switches: metrics.create_counter("switches".into(), None),
+            failures: metrics.create_counter("failures".into(), None),
+        }
+    }
+}
+
+/// A client connection being forwarded to a marshal.
+#[derive(Debug)]
+struct Flow {
+    marshal: usize,
+    upstream: Arc<UdpSocket>,
+    reader: JoinHandle<()>,
+    /// When we forwarded the oldest datagram which has not been answered yet.
+    waiting_since: Option<Instant>,
+    /// Whether the marshal has responded on this connection yet.
+    answered: bool,
+    last_active: Instant,
+}
+
+impl Flow {
+    fn new(
+        client: SocketAddr,
+        marshal: usize,
+        upstream: UdpSocket,
+        responses: mpsc::Sender<(SocketAddr, usize, Vec<u8>)>,
+    ) -> Self {
+        let upstream = Arc::new(upstream);
+        let reader = tokio::spawn({
+            let upstream = upstream.clone();
+            async move {
+                let mut buf = vec![0; MAX_DATAGRAM];
+                while let Ok(len) = upstream.recv(&mut buf).await {
+                    let response = (client, marshal, buf[..len].to_vec());
+                    if responses.send(response).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Self {
+            marshal,
+            upstream,
+            reader,
+            waiting_since: None,
+            answered: false,
+            last_active: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Flow {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Forwards the CDN client's connections to the best available marshal.
+#[derive(Debug)]
+pub struct MarshalRelay {
+    socket: UdpSocket,
+    selector: Selector,
+    active: Option<usize>,
+    metrics: RelayMetrics,
+}
+
+impl MarshalRelay {
+    /// Start relaying to `endpoints` on an ephemeral port on the loopback interface.
+    pub async fn bind(
+        endpoints: Vec<String>,
+        opt: MarshalOptions,
+        metrics: &dyn Metrics,
+    ) -> anyhow::Result<Self> {
+        ensure!(!endpoints.is_empty(), "no CDN marshal endpoints configured");
+        let socket = UdpSocket::bind(("127.0.0.1", 0)).await?;
+        Ok(Self {
+            socket,
+            selector: Selector::new(endpoints, opt),
+            active: None,
+            metrics: RelayMetrics::new(metrics),
+        })
+    }
+
+    /// The endpoint the CDN client should use as its marshal.
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Forward datagrams until cancelled.
+    pub async fn run(mut self) {
+        let (responses, mut recv) = mpsc::channel(1024);
+        let mut flows = HashMap::new();
+        let mut buf = vec![0; MAX_DATAGRAM];
+        let mut check = interval(CHECK_INTERVAL);
+        check.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            tokio::select! {
+                res = self.socket.recv_from(&mut buf) => match res {
+                    Ok((len, client)) => {
+                        self.forward(&mut flows, client, &buf[..len], &responses).await
+                    }
+                    Err(err) => tracing::debug!("error receiving from CDN client: {err:#}"),
+                },
+                Some((client, marshal, datagram)) = recv.recv() => {
+                    self.respond(&mut flows, client, marshal, &datagram).await
+                }
+                _ = check.tick() => self.check(&mut flows),
+            }
+        }
+    }
+
+    async fn forward(
+        &mut self,
+        flows: &mut HashMap<SocketAddr, Flow>,
+        client: SocketAddr,
+        datagram: &[u8],
+        responses: &mpsc::Sender<(SocketAddr, usize, Vec<u8>)>,
+    ) {
+        let flow = match flows.entry(client) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => match self.connect(client, responses).await {
+                Some(flow) => e.insert(flow),
+                None => return,
+            },
+        };
+        let now = Instant::now();
+        flow.last_active = now;
+        flow.waiting_since.get_or_insert(now);
+        if let Err(err) = flow.upstream.send(datagram).await {
+            tracing::debug!(
+                endpoint = self.selector.marshals[flow.marshal].endpoint,
+                "error forwarding to CDN marshal: {err:#}"
+            );
+        }
+    }
+
+    /// Open a connection to the best marshal on behalf of `client`.
+    async fn connect(
+        &mut self,
+        client: SocketAddr,
+        responses: &mpsc::Sender<(SocketAddr, usize, Vec<u8>)>,
+    ) -> Option<Flow> {
+        // Try each marshal at most once.
+        for _ in 0..self.selector.marshals.len() {
+            let now = Instant::now();
+            let marshal = self.selector.select(now);
+            let endpoint = &self.selector.marshals[marshal].endpoint;
+            match open(endpoint).await {
+                Ok(upstream) => {
+                    self.activate(marshal);
+                    return Some(Flow::new(client, marshal, upstream, responses.clone()));
+                }
+                Err(err) => {
+                    tracing::warn!(endpoint, "failed to connect to CDN marshal: {err:#}");
+                    self.fail(marshal, now);
+                }
+            }
+        }
+        None
+    }
+
+    async fn respond(
+        &mut self,
+        flows: &mut HashMap<SocketAddr, Flow>,
+        client: SocketAddr,
+        marshal: usize,
+        datagram: &[u8],
+    ) {
+        let Some(flow) = flows.get_mut(&client) else {
+            return;
+        };
+        // Ignore late responses from a marshal we have already failed over from.
+        if flow.marshal != marshal {
+            return;
+        }
+        let now = Instant::now();
+        if let Some(since) = flow.waiting_since.take() {
+            if !flow.answered {
+                flow.answered = true;
+                let latency = self.selector.succeeded(marshal, now - since);
+                if self.active == Some(marshal) {
+                    self.metrics.latency.set(latency.as_millis() as usize);
+                }
+            }
+        }
+        flow.last_active = now;
+        if let Err(err) = self.socket.send_to(datagram, client).await {
+            tracing::debug!("error forwarding to CDN client: {err:#}");
+        }
+    }
+
+    /// Fail over connections whose marshal is not responding, and forget idle connections.
+    fn check(&mut self, flows: &mut HashMap<SocketAddr, Flow>) {
+        let now = Instant::now();
+        let timeout = self.selector.opt.timeout;
+        flows.retain(|_, flow| now - flow.last_active < IDLE_TIMEOUT);
+
+        // Dropping the connection makes the client's next retransmission open a new one, to
+        // another marshal.
+        let mut failed = HashSet::new();
+        flows.retain(|_, flow| {
+            let stalled = flow
+                .waiting_since
+                .is_some_and(|since| now - since >= timeout);
+            if stalled {
+                failed.insert(flow.marshal);
+            }
+            !stalled
+        });
+        for marshal in failed {
+            tracing::warn!(
+                endpoint = self.selector.marshals[marshal].endpoint,
+                ?timeout,
+                "CDN marshal is not responding, failing over"
+            );
+            self.fail(marshal, now);
+        }
+    }
+
+    fn activate(&mut self, marshal: usize) {
+        if self.active == Some(marshal) {
+            return;
+        }
+        let endpoint = &self.selector.marshals[marshal].endpoint;
+        if let Some(prev) = self.active {
+            tracing::warn!(
+                from = self.selector.marshals[prev].endpoint,
+                to = endpoint,
+                "switching CDN marshal"
+            );
+            self.metrics.switches.add(1);
+        } else {
+            tracing::info!(endpoint, "using CDN marshal");
+        }
+        self.active = Some(marshal);
+        self.metrics.active.set(marshal);
+        if let Some(latency) = self.selector.marshals[marshal].latency {
+            self.metrics.latency.set(latency.as_millis() as usize);
+        }
+    }
+
+    fn fail(&mut self, marshal: usize, now: Instant) {
+        let delay = self.selector.failed(marshal, now);
+        self.metrics.failures.add(1);
+        tracing::info!(
+            endpoint = self.selector.marshals[marshal].endpoint,
+            ?delay,
+            "not using CDN marshal until retry delay has passed"
+        );
+    }
+}
+
+/// Open a socket connected to a marshal.
+async fn open(endpoint: &str) -> anyhow::Result<UdpSocket> {
+    let addr = lookup_host(endpoint)
+        .await?
+        .next()
+        .context("endpoint did not resolve to any address")?;
+    let local: SocketAddr = if addr.is_ipv4() {
+        ([0u8; 4], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(addr).await?;
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod test {
+    use hotshot_types::traits::metrics::NoMetrics;
+    use tokio::time::timeout;
+
+    use super::*;
+
+    fn selector(n: usize) -> Selector {
+        let opt = MarshalOptions {
+            timeout: Duration::from_secs(1),
+            retry_delay: Duration::from_secs(10),
+            max_retry_delay: Duration::from_secs(30),
+        };
+        Selector::new((0..n).map(|i| format!("marshal-{i}:1737")).collect(), opt)
+    }
+
+    #[test]
+    fn test_select_marshal() {
+        let now = Instant::now();
+        let mut selector = selector(3);
+
+        // Each marshal is tried in order until it has been measured.
+        assert_eq!(selector.select(now), 0);
+        selector.succeeded(0, Duration::from_millis(50));
+        assert_eq!(selector.select(now), 1);
+        selector.succeeded(1, Duration::from_millis(10));
+        assert_eq!(selector.select(now), 2);
+        selector.succeeded(2, Duration::from_millis(30));
+
+        // Then the fastest is used, unless it has failed.
+        assert_eq!(selector.select(now), 1);
+        assert_eq!(selector.failed(1, now), Duration::from_secs(10));
+        assert_eq!(selector.select(now), 2);
+        assert_eq!(selector.select(now + Duration::from_secs(10)), 1);
+
+        // Repeated failures back off up to the maximum delay.
+        assert_eq!(selector.failed(1, now), Duration::from_secs(20));
+        assert_eq!(selector.failed(1, now), Duration::from_secs(30));
+
+        // If every marshal has failed, use the one which is due to be retried first.
+        selector.failed(0, now + Duration::from_secs(2));
+        selector.failed(2, now + Duration::from_secs(1));
+        assert_eq!(selector.select(now), 2);
+    }
+
+    #[tokio::test]
+    async fn test_relay_failover() {
+        // The first marshal never responds, the second echoes what it receives.
+        let dead = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let endpoints = vec![
+            dead.local_addr().unwrap().to_string(),
+            echo.local_addr().unwrap().to_string(),
+        ];
+        tokio::spawn(async move {
+            let mut buf = [0; 64];
+            loop {
+                let (len, from) = echo.recv_from(&mut buf).await.unwrap();
+                echo.send_to(&buf[..len], from).await.unwrap();
+            }
+        });
+
+        let opt = MarshalOptions {
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let relay = MarshalRelay::bind(endpoints, opt, &NoMetrics)
+            .await
+            .unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        let relay = tokio::spawn(relay.run());
+
+        // Retransmit until the relay fails over to the responsive marshal.
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(relay_addr).await.unwrap();
+        let mut buf = [0; 64];
+        let len = timeout(Duration::from_secs(5), async {
+            loop {
+                client.send(b"hello").await.unwrap();
+                if let Ok(Ok(len)) = timeout(Duration::from_millis(50), client.recv(&mut buf)).await
+                {
+                    break len;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(&buf[..len], b"hello");
+        relay.abort();
+    }
+}
@@ -0,0 +1,235 @@
+//! Tracking of peers which misbehave on the external message protocol.
+//!
+//! Consensus messages are validated by HotShot, but the messages sequencer nodes exchange among
+//! themselves, such as state sync requests and gossiped transactions and state signatures, are
+//! handled by the [`ExternalEventHandler`](crate::external_event_handler::ExternalEventHandler).
+//! [`MisbehaviorTracker`] counts the offenses committed by each peer on this protocol and reports
+//! them as metrics. A peer which commits too many offenses is banned: its external messages are
+//! ignored from then on, and the ban list is persisted so that bans survive restarts.
+//!
+//! Peers are identified by the sender HotShot reports for each external message, so misbehavior is
+//! attributed to whichever node the transport delivered the message from.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+};
+
+use clap::Parser;
+use espresso_types::{v0::traits::SequencerPersistence, PubKey};
+use hotshot_types::traits::metrics::{Counter, CounterFamily, Gauge, Metrics};
+use parking_lot::Mutex;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+#[derive(Clone, Copy, Debug, Parser)]
+pub struct MisbehaviorConfig {
+    /// Number of offenses after which a peer is banned.
+    ///
+    /// Offenses are invalid signatures, malformed messages and protocol violations on the
+    /// messages sequencer nodes exchange outside of consensus. External messages from banned
+    /// peers are ignored. Set to 0 to count offenses without ever banning a peer.
+    #[clap(
+        long = "misbehavior-ban-threshold",
+        env = "ESPRESSO_SEQUENCER_MISBEHAVIOR_BAN_THRESHOLD",
+        default_value = "20"
+    )]
+    pub ban_threshold: u64,
+}
+
+impl Default for MisbehaviorConfig {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+/// A kind of offense a peer can commit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Misbehavior {
+    /// The peer sent a message with a signature which does not verify.
+    InvalidSignature,
+    /// The peer sent a message which could not be deserialized.
+    MalformedMessage,
+    /// The peer sent a well-formed message which it was not entitled to send, such as a request
+    /// on behalf of another node.
+    ProtocolViolation,
+}
+
+impl Display for Misbehavior {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSignature => write!(f, "invalid_signature"),
+            Self::MalformedMessage => write!(f, "malformed_message"),
+            Self::ProtocolViolation => write!(f, "protocol_violation"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MisbehaviorMetrics {
+    /// Number of offenses, by peer and kind.
+    offenses: Box<dyn CounterFamily>,
+    /// Number of banned peers.
+    banned_peers: Box<dyn Gauge>,
+}
+
+impl MisbehaviorMetrics {
+    fn new(metrics: &dyn Metrics) -> Self {
+        let metrics = metrics.subgroup("misbehavior".into());
+        Self {
+            offenses: metrics.counter_family("offenses".into(), vec!["peer".into(), "kind".into()]),
+            banned_peers: metrics.create_gauge("banned_peers".into(), None),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Total number of offenses committed by each peer.
+    offenses: HashMap<PubKey, u64>,
+    /// Counters for each peer and kind of offense, created on first use.
+    counters: HashMap<(PubKey, Misbehavior), Box<dyn Counter>>,
+    banned: HashSet<PubKey>,
+}
+
+/// Counts offenses by peer and bans peers which commit too many.
+#[derive(Debug)]
+pub struct MisbehaviorTracker {
+    ban_threshold: u64,
+    state: Mutex<State>,
+    metrics: MisbehaviorMetrics,
+    /// Where to send the full ban list each time it changes, to be persisted.
+    bans: UnboundedSender<Vec<PubKey>>,
+}
+
+impl MisbehaviorTracker {
+    /// Create a tracker which starts out with `banned` peers.
+    ///
+    /// The returned receiver yields the ban list whenever a peer is banned, and should be passed
+    /// to [`persist_bans`].
+    pub fn new(
+        config: MisbehaviorConfig,
+        banned: Vec<PubKey>,
+        metrics: &dyn Metrics,
+    ) -> (Self, UnboundedReceiver<Vec<PubKey>>) {
+        let metrics = MisbehaviorMetrics::new(metrics);
+        let banned = banned.into_iter().collect::<HashSet<_>>();
+        metrics.banned_peers.set(banned.len());
+        let (bans, receiver) = unbounded_channel();
+        let tracker = Self {
+            ban_threshold: config.ban_threshold,
+            state: Mutex::new(State {
+                banned,
+                ..Default::default()
+            }),
+            metrics,
+            bans,
+        };
+        (tracker, receiver)
+    }
+
+    /// Whether messages from `peer` should be ignored.
+    pub fn is_banned(&self, peer: &PubKey) -> bool {
+        self.state.lock().banned.contains(peer)
+    }
+
+    /// Record an offense committed by `peer`, banning it if it has reached the threshold.
+    pub fn report(&self, peer: PubKey, offense: Misbehavior) {
+        tracing::debug!(%peer, %offense, "peer misbehaved");
+        let mut state = self.state.lock();
+        state
+            .counters
+            .entry((peer, offense))
+            .or_insert_with(|| {
+                self.metrics
+                    .offenses
+                    .create(vec![peer.to_string(), offense.to_string()])
+            })
+            .add(1);
+
+        let offenses = state.offenses.entry(peer).or_default();
+        *offenses += 1;
+        let offenses = *offenses;
+        if self.ban_threshold == 0 || offenses < self.ban_threshold {
+            return;
+        }
+        if !state.banned.insert(peer) {
+            // The peer was already banned.
+            return;
+        }
+        tracing::warn!(%peer, offenses, "banning misbehaving peer");
+        self.metrics.banned_peers.set(state.banned.len());
+        // The receiver only goes away when the node is shutting down, in which case there is
+        // nothing left to persist the ban list for.
+        self.bans.send(state.banned.iter().copied().collect()).ok();
+    }
+}
+
+/// Persist each updated ban list sent by a [`MisbehaviorTracker`].
+pub async fn persist_bans<P: SequencerPersistence>(
+    persistence: Arc<P>,
+    mut bans: UnboundedReceiver<Vec<PubKey>>,
+) {
+    while let Some(mut banned) = bans.recv().await {
+        // Only the latest list matters, so skip any which were superseded while we were busy.
+        while let Ok(next) = bans.try_recv() {
+            banned = next;
+        }
+        if let Err(err) = persistence.store_banned_peers(&banned).await {
+            tracing::warn!("failed to store banned peers: {err:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hotshot::types::{BLSPubKey, SignatureKey};
+    use hotshot_types::traits::metrics::NoMetrics;
+
+    use super::*;
+
+    fn peer(i: u64) -> PubKey {
+        BLSPubKey::generated_from_seed_indexed([0; 32], i).0
+    }
+
+    #[test]
+    fn test_ban_threshold() {
+        let (tracker, mut bans) = MisbehaviorTracker::new(
+            MisbehaviorConfig { ban_threshold: 3 },
+            vec![peer(0)],
+            &NoMetrics,
+        );
+        assert!(tracker.is_banned(&peer(0)));
+
+        // Offenses of any kind count towards the threshold.
+        tracker.report(peer(1), Misbehavior::MalformedMessage);
+        tracker.report(peer(1), Misbehavior::InvalidSignature);
+        tracker.report(peer(2), Misbehavior::ProtocolViolation);
+        assert!(!tracker.is_banned(&peer(1)));
+        assert!(bans.try_recv().is_err());
+
+        tracker.report(peer(1), Misbehavior::ProtocolViolation);
+        assert!(tracker.is_banned(&peer(1)));
+        assert!(!tracker.is_banned(&peer(2)));
+        let mut banned = bans.try_recv().unwrap();
+        banned.sort();
+        let mut expected = vec![peer(0), peer(1)];
+        expected.sort();
+        assert_eq!(banned, expected);
+
+        // Further offenses by a banned peer do not update the ban list again.
+        tracker.report(peer(1), Misbehavior::ProtocolViolation);
+        assert!(bans.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_ban_disabled() {
+        let (tracker, mut bans) =
+            MisbehaviorTracker::new(MisbehaviorConfig { ban_threshold: 0 }, vec![], &NoMetrics);
+        for _ in 0..100 {
+            tracker.report(peer(1), Misbehavior::MalformedMessage);
+        }
+        assert!(!tracker.is_banned(&peer(1)));
+        assert!(bans.try_recv().is_err());
+    }
+}
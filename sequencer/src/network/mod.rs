@@ -3,7 +3,10 @@ use espresso_types::PubKey;
 use super::*;
 
 pub mod cdn;
+pub mod health;
 pub mod libp2p;
+pub mod marshal;
+pub mod misbehavior;
 
 pub type Production = CombinedNetworks<SeqTypes>;
 
@@ -13,15 +13,25 @@ use std::{
 };
 use tagged_base64::TaggedBase64;
 
-use anyhow::{bail, Context};
-use clap::{error::ErrorKind, Args, FromArgMatches, Parser};
+use anyhow::{bail, ensure};
+use clap::{error::ErrorKind, Args, FromArgMatches, Parser, ValueEnum};
 use derivative::Derivative;
-use espresso_types::{parse_duration, BackoffParams, L1ClientOptions};
+use espresso_types::{parse_duration, BackoffParams, L1ClientOptions, ValidationParallelism};
 use hotshot_types::{light_client::StateSignKey, signature_key::BLSPrivKey};
 use libp2p::Multiaddr;
 use url::Url;
 
-use crate::{api, context::ProposalFetcherConfig, persistence};
+use crate::{
+    api,
+    context::NodeConfig,
+    keys::{self, KeyProvider, KeyProviderOptions},
+    network::{
+        libp2p::{PrivateAddressPolicy, Transport},
+        marshal::MarshalOptions,
+    },
+    persistence,
+    shutdown::ShutdownConfig,
+};
 
 // This options struct is a bit unconventional. The sequencer has multiple optional modules which
 // can be added, in any combination, to the service. These include, for example, the API server.
@@ -57,13 +67,21 @@ pub struct Options {
 
     /// The socket address of the HotShot CDN's main entry point (the marshal)
     /// in `IP:port` form
+    ///
+    /// Multiple comma-separated marshals may be given, for example one in each region. The node
+    /// then connects through whichever responds fastest, and fails over to another one if it stops
+    /// responding.
     #[clap(
         short,
-        long,
+        long = "cdn-endpoint",
         env = "ESPRESSO_SEQUENCER_CDN_ENDPOINT",
-        default_value = "127.0.0.1:8081"
+        default_value = "127.0.0.1:8081",
+        value_delimiter = ','
     )]
-    pub cdn_endpoint: String,
+    pub cdn_endpoints: Vec<String>,
+
+    #[clap(flatten)]
+    pub cdn_marshal: MarshalOptions,
 
     /// The address to bind to for Libp2p (in `host:port` form)
     #[clap(
@@ -220,6 +238,11 @@ pub struct Options {
 
     /// The address we advertise to other nodes as being a Libp2p endpoint.
     /// Should be supplied in `host:port` form.
+    ///
+    /// The sequencer does not configure a circuit relay or hole punching, so a node behind NAT
+    /// should forward the Libp2p port and advertise its public address to accept inbound
+    /// connections. See
+    /// `--libp2p-private-address` for what happens if the advertised address is not public.
     #[clap(
         long,
         env = "ESPRESSO_SEQUENCER_LIBP2P_ADVERTISE_ADDRESS",
@@ -230,6 +253,9 @@ pub struct Options {
     /// A comma-separated list of Libp2p multiaddresses to use as bootstrap
     /// nodes.
     ///
+    /// Each address must be dialable over the selected Libp2p transport, so for QUIC it has the
+    /// form `/ip4/<ip>/udp/<port>/quic-v1/p2p/<peer ID>`.
+    ///
     /// Overrides those loaded from the `HotShot` config.
     #[clap(
         long,
@@ -239,6 +265,60 @@ pub struct Options {
     )]
     pub libp2p_bootstrap_nodes: Option<Vec<Multiaddr>>,
 
+    /// A comma-separated list of Libp2p multiaddresses of peers to always connect to.
+    ///
+    /// Pinned peers are dialed on startup in addition to the bootstrap nodes. The node remembers
+    /// the peers it bootstraps from across restarts, and pinned peers are never dropped from this
+    /// peer store. Addresses have the same form as the bootstrap nodes.
+    #[clap(
+        long = "pinned-peers",
+        env = "ESPRESSO_SEQUENCER_LIBP2P_PINNED_PEERS",
+        value_delimiter = ',',
+        num_args = 1..
+    )]
+    pub libp2p_pinned_peers: Vec<Multiaddr>,
+
+    /// The maximum number of Libp2p peers, other than pinned peers, to bootstrap from.
+    ///
+    /// Configured bootstrap nodes take precedence over peers remembered from previous runs. Only
+    /// this many peers are remembered for the next run.
+    ///
+    /// This does not limit the number of connections the node keeps once it is running. Connection
+    /// limits and per-peer bandwidth are managed by HotShot's Libp2p network, which does not expose
+    /// them; only the maximum message sizes (`--libp2p-max-gossip-transmit-size` and
+    /// `--libp2p-max-direct-transmit-size`) can be configured.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_LIBP2P_MAX_BOOTSTRAP_PEERS",
+        default_value = "100"
+    )]
+    pub libp2p_max_bootstrap_peers: usize,
+
+    /// What to do if the Libp2p advertise address is not publicly dialable: `allow`, `warn`, or
+    /// `reject`.
+    ///
+    /// Whether the address is public is also reported by the `libp2p_public_advertise_address`
+    /// metric.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_LIBP2P_PRIVATE_ADDRESS",
+        value_enum,
+        default_value_t = PrivateAddressPolicy::Warn
+    )]
+    pub libp2p_private_address: PrivateAddressPolicy,
+
+    /// The transport to run Libp2p over.
+    ///
+    /// The bind and advertise addresses are used for this transport, and bootstrap and pinned peers
+    /// must be reachable over it. Only `quic` is currently supported by the Libp2p network.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_LIBP2P_TRANSPORT",
+        value_enum,
+        default_value_t = Transport::Quic
+    )]
+    pub libp2p_transport: Transport,
+
     /// URL of the Light Client State Relay Server
     #[clap(
         long,
@@ -298,6 +378,9 @@ pub struct Options {
     #[clap(long, name = "KEY_FILE", env = "ESPRESSO_SEQUENCER_KEY_FILE")]
     pub key_file: Option<PathBuf>,
 
+    #[clap(flatten)]
+    pub key_provider: KeyProviderOptions,
+
     /// Private staking key.
     ///
     /// This can be used as an alternative to KEY_FILE.
@@ -336,14 +419,18 @@ pub struct Options {
     #[clap(raw = true)]
     modules: Vec<String>,
 
-    /// Url we will use for RPC communication with L1.
+    /// Urls we will use for RPC communication with L1.
+    ///
+    /// Multiple comma-separated URLs may be given, in order of priority. The client uses one
+    /// provider at a time, failing over to the next one if the active provider becomes unhealthy.
     #[clap(
         long,
         env = "ESPRESSO_SEQUENCER_L1_PROVIDER",
-        default_value = "http://localhost:8545"
+        default_value = "http://localhost:8545",
+        value_delimiter = ','
     )]
-    #[derivative(Debug(format_with = "Display::fmt"))]
-    pub l1_provider_url: Url,
+    #[derivative(Debug(format_with = "fmt_urls"))]
+    pub l1_provider_url: Vec<Url>,
 
     /// Configuration for the L1 client.
     #[clap(flatten)]
@@ -353,11 +440,34 @@ pub struct Options {
     #[clap(long, env = "ESPRESSO_SEQUENCER_IS_DA", action)]
     pub is_da: bool,
 
+    /// The role this node plays in the network.
+    ///
+    /// A `da` node is a DA committee member which persists only consensus state and VID shares. It
+    /// implies `--is-da`, and cannot run modules which require query service storage (query, state
+    /// and explorer, as well as any modules depending on them).
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_ROLE",
+        value_enum,
+        default_value_t = NodeRole::Full
+    )]
+    pub role: NodeRole,
+
     /// Peer nodes use to fetch missing state
+    ///
+    /// State is fetched from the catchup APIs of these peers first. If none of them has the state,
+    /// it can be synced from consensus peers over the network (see `--state-sync`).
     #[clap(long, env = "ESPRESSO_SEQUENCER_STATE_PEERS", value_delimiter = ',')]
     #[derivative(Debug(format_with = "fmt_urls"))]
     pub state_peers: Vec<Url>,
 
+    /// Sync missing state from consensus peers over the network
+    ///
+    /// This is tried after the catchup APIs of `--state-peers`. Every node answers state sync
+    /// requests from its peers regardless of this option, subject to a per-peer rate limit.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_STATE_SYNC")]
+    pub state_sync: bool,
+
     /// Peer nodes use to fetch missing config
     ///
     /// Typically, the network-wide config is fetched from the orchestrator on startup and then
@@ -372,6 +482,10 @@ pub struct Options {
     #[clap(flatten)]
     pub catchup_backoff: BackoffParams,
 
+    /// Threads used to validate proposed headers.
+    #[clap(flatten)]
+    pub validation_parallelism: ValidationParallelism,
+
     #[clap(flatten)]
     pub logging: logging::Config,
 
@@ -379,30 +493,37 @@ pub struct Options {
     pub identity: Identity,
 
     #[clap(flatten)]
-    pub proposal_fetcher_config: ProposalFetcherConfig,
+    pub node_config: NodeConfig,
+
+    #[clap(flatten)]
+    pub shutdown_config: ShutdownConfig,
 }
 
 impl Options {
     pub fn modules(&self) -> Modules {
-        ModuleArgs(self.modules.clone()).parse()
+        ModuleArgs(self.modules.clone()).parse(self.role)
+    }
+
+    /// Whether this node participates in the DA committee.
+    pub fn is_da_member(&self) -> bool {
+        self.is_da || self.role == NodeRole::Da
     }
 
-    pub fn private_keys(&self) -> anyhow::Result<(BLSPrivKey, StateSignKey)> {
+    pub async fn private_keys(&self) -> anyhow::Result<(BLSPrivKey, StateSignKey)> {
+        if self.key_provider.provider != KeyProvider::Local {
+            ensure!(
+                self.key_file.is_none()
+                    && self.private_staking_key.is_none()
+                    && self.private_state_key.is_none(),
+                "private keys must not be given directly when using the {} key provider",
+                self.key_provider.provider
+            );
+            return self.key_provider.load().await;
+        }
+
         if let Some(path) = &self.key_file {
             let vars = dotenvy::from_path_iter(path)?.collect::<Result<HashMap<_, _>, _>>()?;
-            let staking = TaggedBase64::parse(
-                vars.get("ESPRESSO_SEQUENCER_PRIVATE_STAKING_KEY")
-                    .context("key file missing ESPRESSO_SEQUENCER_PRIVATE_STAKING_KEY")?,
-            )?
-            .try_into()?;
-
-            let state = TaggedBase64::parse(
-                vars.get("ESPRESSO_SEQUENCER_PRIVATE_STATE_KEY")
-                    .context("key file missing ESPRESSO_SEQUENCER_PRIVATE_STATE_KEY")?,
-            )?
-            .try_into()?;
-
-            Ok((staking, state))
+            keys::parse_private_keys(&vars)
         } else if let (Some(staking), Some(state)) = (
             self.private_staking_key.clone(),
             self.private_state_key.clone(),
@@ -417,6 +538,26 @@ impl Options {
     }
 }
 
+/// The role a node plays in the network.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum NodeRole {
+    /// A node which may run any combination of modules.
+    #[default]
+    Full,
+    /// A DA committee member without query service storage.
+    Da,
+}
+
+impl NodeRole {
+    /// Modules which need query service storage, and so are not available in this role.
+    fn excluded_modules(&self) -> &'static [&'static str] {
+        match self {
+            Self::Full => &[],
+            Self::Da => &["query", "state", "explorer"],
+        }
+    }
+}
+
 /// Identity represents identifying information concerning the sequencer node.
 /// This information is used to populate relevant information in the metrics
 /// endpoint.  This information will also potentially be scraped and displayed
@@ -512,14 +653,14 @@ impl Ord for Ratio {
 struct ModuleArgs(Vec<String>);
 
 impl ModuleArgs {
-    fn parse(&self) -> Modules {
-        match self.try_parse() {
+    fn parse(&self, role: NodeRole) -> Modules {
+        match self.try_parse(role) {
             Ok(modules) => modules,
             Err(err) => err.exit(),
         }
     }
 
-    fn try_parse(&self) -> Result<Modules, clap::Error> {
+    fn try_parse(&self, role: NodeRole) -> Result<Modules, clap::Error> {
         let mut modules = Modules::default();
         let mut curr = self.0.clone();
         let mut provided = Default::default();
@@ -541,6 +682,15 @@ impl ModuleArgs {
                 SequencerModule::StorageSql(m) => {
                     curr = m.add(&mut modules.storage_sql, &mut provided)?
                 }
+                SequencerModule::StorageRocksdb(m) => {
+                    curr = m.add(&mut modules.storage_rocksdb, &mut provided)?
+                }
+                SequencerModule::StorageMigrate(m) => {
+                    curr = m.add(&mut modules.storage_migrate, &mut provided)?
+                }
+                SequencerModule::StorageSqlReplica(m) => {
+                    curr = m.add(&mut modules.storage_sql_replica, &mut provided)?
+                }
                 SequencerModule::Http(m) => curr = m.add(&mut modules.http, &mut provided)?,
                 SequencerModule::Query(m) => curr = m.add(&mut modules.query, &mut provided)?,
                 SequencerModule::Submit(m) => curr = m.add(&mut modules.submit, &mut provided)?,
@@ -554,6 +704,38 @@ impl ModuleArgs {
                 SequencerModule::Explorer(m) => {
                     curr = m.add(&mut modules.explorer, &mut provided)?
                 }
+                SequencerModule::Fee(m) => curr = m.add(&mut modules.fee, &mut provided)?,
+                SequencerModule::Marketplace(m) => {
+                    curr = m.add(&mut modules.marketplace, &mut provided)?
+                }
+                SequencerModule::Admin(m) => curr = m.add(&mut modules.admin, &mut provided)?,
+                SequencerModule::Auth(m) => curr = m.add(&mut modules.auth, &mut provided)?,
+                SequencerModule::Stream(m) => curr = m.add(&mut modules.stream, &mut provided)?,
+                #[cfg(feature = "grpc")]
+                SequencerModule::Grpc(m) => curr = m.add(&mut modules.grpc, &mut provided)?,
+            }
+        }
+
+        for module in role.excluded_modules() {
+            if provided.contains(module) {
+                return Err(clap::Error::raw(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "module {module} is not available in role {}",
+                        role.to_possible_value().unwrap().get_name()
+                    ),
+                ));
+            }
+        }
+
+        if provided.contains("storage-sql-replica") {
+            for module in &provided {
+                if !REPLICA_MODULES.contains(module) {
+                    return Err(clap::Error::raw(
+                        ErrorKind::ArgumentConflict,
+                        format!("module {module} is not available on a read replica"),
+                    ));
+                }
             }
         }
 
@@ -561,6 +743,9 @@ impl ModuleArgs {
     }
 }
 
+/// Modules which can run on a read replica, without a consensus instance.
+const REPLICA_MODULES: &[&str] = &["storage-sql-replica", "http", "query", "explorer", "auth"];
+
 trait ModuleInfo: Args + FromArgMatches {
     const NAME: &'static str;
     fn requires() -> Vec<&'static str>;
@@ -580,6 +765,9 @@ macro_rules! module {
 
 module!("storage-fs", persistence::fs::Options);
 module!("storage-sql", persistence::sql::Options);
+module!("storage-rocksdb", persistence::rocksdb::Options);
+module!("storage-sql-replica", persistence::sql::ReplicaOptions);
+module!("storage-migrate", persistence::migrating::Config, requires: "storage-fs", "storage-sql");
 module!("http", api::options::Http);
 module!("query", api::options::Query, requires: "http");
 module!("submit", api::options::Submit, requires: "http");
@@ -589,6 +777,13 @@ module!("catchup", api::options::Catchup, requires: "http");
 module!("config", api::options::Config, requires: "http");
 module!("hotshot-events", api::options::HotshotEvents, requires: "http");
 module!("explorer", api::options::Explorer, requires: "http", "storage-sql");
+module!("fee", api::options::Fee, requires: "http", "query");
+module!("marketplace", api::options::Marketplace, requires: "http");
+module!("admin", api::options::Admin, requires: "http", "query");
+module!("auth", api::options::Auth, requires: "http");
+module!("stream", api::options::Stream, requires: "http");
+#[cfg(feature = "grpc")]
+module!("grpc", api::options::Grpc, requires: "http", "query");
 
 #[derive(Clone, Debug, Args)]
 struct Module<Options: ModuleInfo> {
@@ -640,8 +835,25 @@ enum SequencerModule {
     Storage(Module<persistence::fs::Options>),
     /// Use the file system for persistent storage.
     StorageFs(Module<persistence::fs::Options>),
-    /// Use a Postgres database for persistent storage.
+    /// Use a SQL database for persistent storage.
+    ///
+    /// This is Postgres by default, or an embedded SQLite database if the sequencer is built with
+    /// the `embedded-db` feature.
     StorageSql(Module<persistence::sql::Options>),
+    /// Use an embedded RocksDB database for persistent storage.
+    StorageRocksdb(Module<persistence::rocksdb::Options>),
+    /// Migrate persistent storage from the file system to a SQL database without downtime.
+    ///
+    /// While migrating, every write goes to both backends, and reads are served from the file
+    /// system until the SQL database has caught up. This module requires the storage-fs and
+    /// storage-sql modules to be started.
+    StorageMigrate(Module<persistence::migrating::Config>),
+    /// Serve the query API from a read-only replica of another node's SQL database.
+    ///
+    /// The node does not participate in consensus, and only serves data written to the database by
+    /// the node populating it. Only the http, query, explorer and auth modules may be used with
+    /// this module.
+    StorageSqlReplica(Module<persistence::sql::ReplicaOptions>),
     /// Run the query API module.
     ///
     /// This module requires the http module to be started.
@@ -671,12 +883,40 @@ enum SequencerModule {
     ///
     /// This module requires the http and storage-sql modules to be started.
     Explorer(Module<api::options::Explorer>),
+    /// Run the fee account API module.
+    ///
+    /// This module requires the http and query modules to be started.
+    Fee(Module<api::options::Fee>),
+    /// Run the marketplace API module, for bidding in namespace sequencing auctions.
+    ///
+    /// This module requires the http module to be started.
+    Marketplace(Module<api::options::Marketplace>),
+    /// Run the admin API server, for reconfiguring the node at runtime.
+    ///
+    /// This module requires the http and query modules to be started.
+    Admin(Module<api::options::Admin>),
+    /// Require API keys for protected API modules, such as submit and config.
+    ///
+    /// This module requires the http module to be started.
+    Auth(Module<api::options::Auth>),
+    /// Publish decided headers, transactions and namespace pointers to a NATS server.
+    ///
+    /// This module requires the http module to be started.
+    Stream(Module<api::options::Stream>),
+    /// Run a gRPC mirror of the availability and submit APIs.
+    ///
+    /// This module requires the http and query modules to be started.
+    #[cfg(feature = "grpc")]
+    Grpc(Module<api::options::Grpc>),
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct Modules {
     pub storage_fs: Option<persistence::fs::Options>,
     pub storage_sql: Option<persistence::sql::Options>,
+    pub storage_rocksdb: Option<persistence::rocksdb::Options>,
+    pub storage_migrate: Option<persistence::migrating::Config>,
+    pub storage_sql_replica: Option<persistence::sql::ReplicaOptions>,
     pub http: Option<api::options::Http>,
     pub query: Option<api::options::Query>,
     pub submit: Option<api::options::Submit>,
@@ -686,4 +926,11 @@ pub struct Modules {
     pub config: Option<api::options::Config>,
     pub hotshot_events: Option<api::options::HotshotEvents>,
     pub explorer: Option<api::options::Explorer>,
+    pub fee: Option<api::options::Fee>,
+    pub marketplace: Option<api::options::Marketplace>,
+    pub admin: Option<api::options::Admin>,
+    pub auth: Option<api::options::Auth>,
+    pub stream: Option<api::options::Stream>,
+    #[cfg(feature = "grpc")]
+    pub grpc: Option<api::options::Grpc>,
 }
@@ -8,16 +8,43 @@
 //! an extension that node operators can opt into. This module defines the minimum level of
 //! persistence which is _required_ to run a node.
 
+use anyhow::anyhow;
 use async_trait::async_trait;
-use espresso_types::v0_3::ChainConfig;
+use committable::Commitment;
+use espresso_types::{v0::traits::PersistenceOptions, v0_3::ChainConfig, Transaction};
+use tagged_base64::TaggedBase64;
 
+pub mod compression;
 pub mod fs;
+pub mod migrating;
 pub mod no_storage;
+pub mod rocksdb;
 pub mod sql;
 
 #[async_trait]
 pub trait ChainConfigPersistence: Sized + Send + Sync {
-    async fn insert_chain_config(&mut self, chain_config: ChainConfig) -> anyhow::Result<()>;
+    /// Store `chain_config`, recording that it became active in the block at `height`.
+    async fn insert_chain_config(
+        &mut self,
+        height: u64,
+        chain_config: ChainConfig,
+    ) -> anyhow::Result<()>;
+}
+
+/// Persistence options which can be restricted to the storage profile of a DA-only node.
+pub trait DaProfile: PersistenceOptions {
+    /// Persist only what a DA committee member needs: consensus state and VID shares.
+    ///
+    /// Anything kept solely for the benefit of the query service, such as undecided state or
+    /// reconstructed historical data, is disabled.
+    fn da_only(self) -> Self;
+}
+
+/// Parse the hash of a transaction from the string it is keyed by in storage.
+fn parse_tx_hash(s: &str) -> anyhow::Result<Commitment<Transaction>> {
+    let tb64 =
+        TaggedBase64::parse(s).map_err(|err| anyhow!("malformed transaction hash {s}: {err}"))?;
+    Commitment::try_from(&tb64).map_err(|_| anyhow!("malformed transaction hash {s}"))
 }
 
 #[cfg(any(test, feature = "testing"))]
@@ -45,17 +72,25 @@ mod persistence_tests {
     use async_lock::RwLock;
     use committable::Committable;
     use espresso_types::{
-        traits::EventConsumer, Event, Leaf, NodeState, PubKey, SeqTypes, ValidatedState,
+        traits::EventConsumer, BuilderFeeTotals, ChainConfig, DaPointer, DecideEvent,
+        EpochStakeTable, Event, FeeAccount, FeeDeposit, Leaf, NamespaceId, NodeState, Payload,
+        PubKey, SeqTypes, StakeTableNode, Transaction, TxStatus, UpgradeRecord, UpgradeStatus,
+        ValidatedState, ViewOutcome, ViewRecord,
     };
+    use ethers::types::{Address, H256};
     use hotshot::types::{BLSPubKey, SignatureKey};
     use hotshot_example_types::node_types::TestVersions;
     use hotshot_types::{
-        data::{DaProposal, QuorumProposal, VidDisperseShare, ViewNumber},
+        data::{DaProposal, EpochNumber, QuorumProposal, VidDisperseShare, ViewNumber},
         event::{EventType, HotShotAction, LeafInfo},
+        light_client::StateKeyPair,
         message::Proposal,
         simple_certificate::{QuorumCertificate, UpgradeCertificate},
         simple_vote::UpgradeProposalData,
-        traits::{block_contents::vid_commitment, node_implementation::ConsensusTime, EncodeBytes},
+        traits::{
+            block_contents::vid_commitment, node_implementation::ConsensusTime, BlockPayload,
+            EncodeBytes,
+        },
         vid::vid_scheme,
     };
     use jf_vid::VidScheme;
@@ -547,6 +582,551 @@ mod persistence_tests {
         assert_eq!(view_number, new_view_number_for_certificate);
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_tx_status<P: TestablePersistence>() {
+        setup_test();
+
+        let tmp = P::tmp_storage().await;
+        let storage = P::connect(&tmp).await;
+
+        let tx = Transaction::new(NamespaceId::from(1_u32), vec![1, 2, 3]);
+        let other = Transaction::new(NamespaceId::from(1_u32), vec![4, 5, 6]);
+        let hash = tx.commit();
+
+        // Initially, the status is unknown.
+        assert_eq!(storage.load_tx_status(hash).await.unwrap(), None);
+
+        storage
+            .store_tx_status(hash, TxStatus::Pending)
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.load_tx_status(hash).await.unwrap(),
+            Some(TxStatus::Pending)
+        );
+
+        // Decide a block containing the transaction, after some other transaction.
+        let mut leaf = Leaf::genesis(&ValidatedState::default(), &NodeState::mock()).await;
+        let (payload, _) = Payload::from_transactions(
+            [other.clone(), tx],
+            &ValidatedState::default(),
+            &NodeState::mock(),
+        )
+        .await
+        .unwrap();
+        leaf.fill_block_payload_unchecked(payload);
+        storage
+            .update_sequenced_tx_statuses(&[leaf_info(leaf.clone())])
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.load_tx_status(hash).await.unwrap(),
+            Some(TxStatus::Sequenced {
+                block: leaf.height(),
+                index: 1
+            })
+        );
+
+        // Transactions not submitted through this node are not tracked.
+        assert_eq!(storage.load_tx_status(other.commit()).await.unwrap(), None);
+
+        // All recorded statuses can be loaded at once.
+        assert_eq!(
+            storage.load_all_tx_statuses().await.unwrap(),
+            [(
+                hash,
+                TxStatus::Sequenced {
+                    block: leaf.height(),
+                    index: 1
+                }
+            )]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_mempool<P: TestablePersistence>() {
+        setup_test();
+
+        let tmp = P::tmp_storage().await;
+        let storage = P::connect(&tmp).await;
+
+        // Initially, the mempool is empty.
+        assert_eq!(storage.load_mempool().await.unwrap(), vec![]);
+
+        let tx1 = Transaction::new(NamespaceId::from(1_u32), vec![1, 2, 3]);
+        let tx2 = Transaction::new(NamespaceId::from(2_u32), vec![4, 5, 6]);
+        storage
+            .append_mempool_txs(&[tx1.clone(), tx2.clone()])
+            .await
+            .unwrap();
+
+        // Appending a transaction twice is idempotent.
+        storage.append_mempool_txs(&[tx1.clone()]).await.unwrap();
+
+        let mut loaded = storage.load_mempool().await.unwrap();
+        loaded.sort_by_key(|tx| tx.namespace());
+        assert_eq!(loaded, vec![tx1.clone(), tx2.clone()]);
+
+        // Removing a transaction which is not in the mempool is a no-op.
+        storage
+            .remove_mempool_txs(&[tx1.commit(), tx1.commit()])
+            .await
+            .unwrap();
+        assert_eq!(storage.load_mempool().await.unwrap(), vec![tx2.clone()]);
+
+        // The mempool survives reconnecting.
+        let storage = P::connect(&tmp).await;
+        assert_eq!(storage.load_mempool().await.unwrap(), vec![tx2]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_stake_table_history<P: TestablePersistence>() {
+        setup_test();
+
+        let tmp = P::tmp_storage().await;
+        let storage = P::connect(&tmp).await;
+
+        let stake_table = |epoch: u64, stake: u64| EpochStakeTable {
+            epoch: EpochNumber::new(epoch),
+            nodes: (0..3)
+                .map(|i| StakeTableNode {
+                    stake_table_entry: BLSPubKey::generated_from_seed_indexed([0; 32], i)
+                        .0
+                        .stake_table_entry(stake),
+                    state_ver_key: StateKeyPair::generate_from_seed_indexed([0; 32], i).ver_key(),
+                    da: i == 0,
+                })
+                .collect(),
+        };
+
+        // Nothing is recorded initially.
+        assert_eq!(
+            storage.load_stake_table(EpochNumber::new(1)).await.unwrap(),
+            None
+        );
+
+        let epoch1 = stake_table(1, 1);
+        let epoch2 = stake_table(2, 2);
+        storage.store_stake_table(&epoch1).await.unwrap();
+        storage.store_stake_table(&epoch2).await.unwrap();
+        assert_eq!(
+            storage.load_stake_table(EpochNumber::new(1)).await.unwrap(),
+            Some(epoch1.clone())
+        );
+        assert_eq!(
+            storage.load_stake_table(EpochNumber::new(2)).await.unwrap(),
+            Some(epoch2)
+        );
+
+        // Recording an epoch again replaces the previous stake table.
+        let epoch2 = stake_table(2, 3);
+        storage.store_stake_table(&epoch2).await.unwrap();
+        assert_eq!(
+            storage.load_stake_table(EpochNumber::new(2)).await.unwrap(),
+            Some(epoch2.clone())
+        );
+
+        let mut all = storage.load_all_stake_tables().await.unwrap();
+        all.sort_by_key(|stake_table| stake_table.epoch);
+        assert_eq!(all, [epoch1, epoch2]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_upgrade_status<P: TestablePersistence>() {
+        setup_test();
+
+        let tmp = P::tmp_storage().await;
+        let storage = P::connect(&tmp).await;
+
+        // Nothing is recorded initially.
+        assert_eq!(storage.load_upgrades().await.unwrap(), vec![]);
+
+        let v2 = UpgradeRecord {
+            version: Version { major: 0, minor: 2 },
+            status: UpgradeStatus::Activated { view: 5, height: 4 },
+        };
+        let mut v3 = UpgradeRecord {
+            version: Version { major: 0, minor: 3 },
+            status: UpgradeStatus::Proposed {
+                view: 10,
+                new_version_first_view: 20,
+            },
+        };
+        storage.store_upgrade(&v2).await.unwrap();
+        storage.store_upgrade(&v3).await.unwrap();
+        let mut loaded = storage.load_upgrades().await.unwrap();
+        loaded.sort_by_key(|upgrade| upgrade.version);
+        assert_eq!(loaded, vec![v2, v3]);
+
+        // Recording an upgrade again replaces its status, and survives reconnecting.
+        v3.status = UpgradeStatus::Certified {
+            view: 12,
+            new_version_first_view: 20,
+        };
+        storage.store_upgrade(&v3).await.unwrap();
+        let storage = P::connect(&tmp).await;
+        let mut loaded = storage.load_upgrades().await.unwrap();
+        loaded.sort_by_key(|upgrade| upgrade.version);
+        assert_eq!(loaded, vec![v2, v3]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_libp2p_peers<P: TestablePersistence>() {
+        setup_test();
+
+        let tmp = P::tmp_storage().await;
+        let storage = P::connect(&tmp).await;
+
+        // Nothing is recorded initially.
+        assert_eq!(
+            storage.load_libp2p_peers().await.unwrap(),
+            Vec::<String>::new()
+        );
+
+        let peers = vec![
+            "/ip4/10.0.0.1/udp/1769/quic-v1/p2p/12D3KooWA".to_string(),
+            "/dns/node.example.com/udp/1769/quic-v1/p2p/12D3KooWB".to_string(),
+        ];
+        storage.store_libp2p_peers(&peers).await.unwrap();
+        assert_eq!(storage.load_libp2p_peers().await.unwrap(), peers);
+
+        // Storing peers again replaces the list, and survives reconnecting.
+        storage.store_libp2p_peers(&peers[1..]).await.unwrap();
+        let storage = P::connect(&tmp).await;
+        assert_eq!(storage.load_libp2p_peers().await.unwrap(), peers[1..]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_da_pointers<P: TestablePersistence>() {
+        setup_test();
+
+        let tmp = P::tmp_storage().await;
+        let storage = P::connect(&tmp).await;
+
+        assert_eq!(storage.load_da_pointer(1).await.unwrap(), None);
+
+        let pointer = |height: u64, commitment: &str| DaPointer {
+            height,
+            layer: "celestia".into(),
+            commitment: commitment.into(),
+            da_height: Some(height * 10),
+        };
+        storage.store_da_pointer(&pointer(1, "0x01")).await.unwrap();
+        storage.store_da_pointer(&pointer(2, "0x02")).await.unwrap();
+        assert_eq!(
+            storage.load_da_pointer(1).await.unwrap(),
+            Some(pointer(1, "0x01"))
+        );
+        assert_eq!(storage.load_da_pointer(3).await.unwrap(), None);
+
+        // Mirroring a block again replaces its pointer, and pointers survive reconnecting.
+        storage.store_da_pointer(&pointer(1, "0x03")).await.unwrap();
+        let storage = P::connect(&tmp).await;
+        assert_eq!(
+            storage.load_da_pointer(1).await.unwrap(),
+            Some(pointer(1, "0x03"))
+        );
+        assert_eq!(
+            storage.load_da_pointer(2).await.unwrap(),
+            Some(pointer(2, "0x02"))
+        );
+
+        let mut all = storage.load_all_da_pointers().await.unwrap();
+        all.sort_by_key(|pointer| pointer.height);
+        assert_eq!(all, [pointer(1, "0x03"), pointer(2, "0x02")]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_fee_deposits<P: TestablePersistence>() {
+        setup_test();
+
+        let tmp = P::tmp_storage().await;
+        let storage = P::connect(&tmp).await;
+
+        let alice = FeeAccount::from(Address::repeat_byte(1));
+        let bob = FeeAccount::from(Address::repeat_byte(2));
+        assert_eq!(storage.load_fee_deposits_l1_block().await.unwrap(), None);
+        assert_eq!(
+            storage.load_fee_deposits(alice, 0, 10).await.unwrap(),
+            vec![]
+        );
+
+        let deposit = |account: FeeAccount, l1_block: u64, log_index: u64| FeeDeposit {
+            account,
+            amount: (l1_block * 100 + log_index).into(),
+            l1_block,
+            tx_hash: H256::repeat_byte(l1_block as u8),
+            log_index,
+        };
+        let deposits = [
+            deposit(alice, 5, 0),
+            deposit(bob, 5, 1),
+            deposit(alice, 5, 2),
+            deposit(alice, 7, 0),
+        ];
+
+        // Scanning a range with no deposits still records progress.
+        storage.store_fee_deposits(&[], 4).await.unwrap();
+        assert_eq!(storage.load_fee_deposits_l1_block().await.unwrap(), Some(4));
+
+        // Deposits may be stored out of order and more than once.
+        storage
+            .store_fee_deposits(&[deposits[2].clone(), deposits[1].clone()], 5)
+            .await
+            .unwrap();
+        storage
+            .store_fee_deposits(
+                &[
+                    deposits[3].clone(),
+                    deposits[0].clone(),
+                    deposits[2].clone(),
+                ],
+                8,
+            )
+            .await
+            .unwrap();
+
+        let storage = P::connect(&tmp).await;
+        assert_eq!(storage.load_fee_deposits_l1_block().await.unwrap(), Some(8));
+        assert_eq!(
+            storage.load_fee_deposits(alice, 0, 10).await.unwrap(),
+            [
+                deposits[0].clone(),
+                deposits[2].clone(),
+                deposits[3].clone()
+            ]
+        );
+        assert_eq!(
+            storage.load_fee_deposits(bob, 0, 10).await.unwrap(),
+            [deposits[1].clone()]
+        );
+
+        // Deposits can be paged through.
+        assert_eq!(
+            storage.load_fee_deposits(alice, 1, 1).await.unwrap(),
+            [deposits[2].clone()]
+        );
+        assert_eq!(
+            storage.load_fee_deposits(alice, 3, 10).await.unwrap(),
+            vec![]
+        );
+
+        // All deposits can be loaded at once, in L1 order.
+        assert_eq!(storage.load_all_fee_deposits().await.unwrap(), deposits);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_builder_fees<P: TestablePersistence>() {
+        setup_test();
+
+        let tmp = P::tmp_storage().await;
+        let storage = P::connect(&tmp).await;
+
+        let alice = FeeAccount::from(Address::repeat_byte(1));
+        let bob = FeeAccount::from(Address::repeat_byte(2));
+        assert_eq!(storage.load_builder_fees(alice).await.unwrap(), None);
+
+        let mut alice_totals = BuilderFeeTotals::new(alice);
+        alice_totals.add(3, 10.into());
+        let mut bob_totals = BuilderFeeTotals::new(bob);
+        bob_totals.add(4, 20.into());
+        storage
+            .store_builder_fees(&[alice_totals.clone(), bob_totals.clone()])
+            .await
+            .unwrap();
+
+        // Storing totals again replaces them.
+        alice_totals.add(5, 30.into());
+        storage
+            .store_builder_fees(&[alice_totals.clone()])
+            .await
+            .unwrap();
+
+        let storage = P::connect(&tmp).await;
+        assert_eq!(
+            storage.load_builder_fees(alice).await.unwrap(),
+            Some(alice_totals.clone())
+        );
+        assert_eq!(
+            storage.load_builder_fees(bob).await.unwrap(),
+            Some(bob_totals.clone())
+        );
+
+        // The totals of all accounts can be loaded at once.
+        let mut all = storage.load_all_builder_fees().await.unwrap();
+        all.sort_by_key(|totals| totals.account);
+        assert_eq!(all, [alice_totals, bob_totals]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_shutdown_checkpoint<P: TestablePersistence>() {
+        setup_test();
+
+        let tmp = P::tmp_storage().await;
+        let storage = P::connect(&tmp).await;
+        assert_eq!(storage.take_shutdown_checkpoint().await.unwrap(), None);
+
+        let checkpoint = |view: u64| ShutdownCheckpoint {
+            anchor_view: ViewNumber::new(view),
+            last_event_view: Some(ViewNumber::new(view + 1)),
+        };
+        storage
+            .store_shutdown_checkpoint(&checkpoint(1))
+            .await
+            .unwrap();
+        storage
+            .store_shutdown_checkpoint(&checkpoint(2))
+            .await
+            .unwrap();
+
+        // The latest checkpoint survives reconnecting, but can only be taken once.
+        let storage = P::connect(&tmp).await;
+        assert_eq!(
+            storage.take_shutdown_checkpoint().await.unwrap(),
+            Some(checkpoint(2))
+        );
+        assert_eq!(storage.take_shutdown_checkpoint().await.unwrap(), None);
+        let storage = P::connect(&tmp).await;
+        assert_eq!(storage.take_shutdown_checkpoint().await.unwrap(), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_anchor_state<P: TestablePersistence>() {
+        setup_test();
+
+        let tmp = P::tmp_storage().await;
+        let storage = P::connect(&tmp).await;
+        assert_eq!(storage.load_anchor_state().await.unwrap(), None);
+
+        let state = ValidatedState::default();
+        storage
+            .store_anchor_state(ViewNumber::new(2), &state)
+            .await
+            .unwrap();
+
+        // The state of an older anchor leaf does not replace a newer one.
+        let mut old_state = state.clone();
+        old_state.chain_config = ChainConfig {
+            max_block_size: 1u64.into(),
+            ..Default::default()
+        }
+        .into();
+        storage
+            .store_anchor_state(ViewNumber::new(1), &old_state)
+            .await
+            .unwrap();
+
+        let storage = P::connect(&tmp).await;
+        assert_eq!(
+            storage.load_anchor_state().await.unwrap(),
+            Some((ViewNumber::new(2), state))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_view_log<P: TestablePersistence>() {
+        setup_test();
+
+        let tmp = P::tmp_storage().await;
+        let storage = P::connect(&tmp).await;
+        assert_eq!(storage.load_view_records(0, 10).await.unwrap(), vec![]);
+
+        let record = |view: u64, outcome: Option<ViewOutcome>| ViewRecord {
+            view,
+            leader: None,
+            started_at: view * 1000,
+            proposal_received_at: Some(view * 1000 + 100),
+            da_proposal_received_at: None,
+            votes: None,
+            ended_at: outcome.map(|_| view * 1000 + 500),
+            outcome,
+        };
+        for view in [1, 2, 4] {
+            storage
+                .store_view_record(&record(view, None))
+                .await
+                .unwrap();
+        }
+
+        // A later record for a view replaces the earlier one.
+        let completed = record(2, Some(ViewOutcome::Completed));
+        storage.store_view_record(&completed).await.unwrap();
+
+        let storage = P::connect(&tmp).await;
+        assert_eq!(
+            storage.load_view_records(2, 5).await.unwrap(),
+            vec![completed.clone(), record(4, None)]
+        );
+
+        storage.prune_view_records(2).await.unwrap();
+        assert_eq!(
+            storage.load_view_records(0, 10).await.unwrap(),
+            vec![completed, record(4, None)]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_decide_event_log<P: TestablePersistence>() {
+        setup_test();
+
+        let tmp = P::tmp_storage().await;
+        let storage = P::connect(&tmp).await;
+        assert_eq!(storage.load_decide_events(0, 10).await.unwrap(), vec![]);
+
+        let leaf = Leaf::genesis(&ValidatedState::default(), &NodeState::mock()).await;
+        let event = |height: u64| DecideEvent {
+            height,
+            view: height + 1,
+            leaf: leaf.clone(),
+        };
+        storage
+            .store_decide_events(&[event(1), event(2), event(4)])
+            .await
+            .unwrap();
+        storage.store_decide_events(&[event(5)]).await.unwrap();
+
+        let storage = P::connect(&tmp).await;
+        assert_eq!(
+            storage.load_decide_events(2, 2).await.unwrap(),
+            vec![event(2), event(4)]
+        );
+        assert_eq!(
+            storage.load_decide_events(3, 10).await.unwrap(),
+            vec![event(4), event(5)]
+        );
+
+        storage.prune_decide_events(4).await.unwrap();
+        assert_eq!(
+            storage.load_decide_events(0, 10).await.unwrap(),
+            vec![event(4), event(5)]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_banned_peers<P: TestablePersistence>() {
+        setup_test();
+
+        let tmp = P::tmp_storage().await;
+        let storage = P::connect(&tmp).await;
+
+        // Nothing is recorded initially.
+        assert_eq!(
+            storage.load_banned_peers().await.unwrap(),
+            Vec::<PubKey>::new()
+        );
+
+        let peers = (0..2)
+            .map(|i| BLSPubKey::generated_from_seed_indexed([0; 32], i).0)
+            .collect::<Vec<_>>();
+        storage.store_banned_peers(&peers).await.unwrap();
+        assert_eq!(storage.load_banned_peers().await.unwrap(), peers);
+
+        // Storing peers again replaces the list, and survives reconnecting.
+        storage.store_banned_peers(&peers[1..]).await.unwrap();
+        let storage = P::connect(&tmp).await;
+        assert_eq!(storage.load_banned_peers().await.unwrap(), peers[1..]);
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     pub async fn test_decide_with_failing_event_consumer<P: TestablePersistence>() {
         #[derive(Clone, Copy, Debug)]
@@ -0,0 +1,91 @@
+//! Optional compression of large blobs in persistent storage.
+//!
+//! DA proposals carry the full block payload, which for rollup data typically compresses several
+//! times over. When enabled, the SQL and file system backends compress these blobs with zstd before
+//! writing them.
+//!
+//! Compressed blobs are recognized by the zstd frame magic number, so blobs written before
+//! compression was enabled (or after it is disabled again) remain readable. A bincode-serialized
+//! proposal cannot start with the magic number, since that would make the length prefix of its
+//! payload over 4 GB.
+
+use std::borrow::Cow;
+
+use anyhow::Context;
+use clap::Parser;
+
+/// The first bytes of every zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Options for compressing payloads in persistent storage.
+#[derive(Parser, Clone, Copy, Debug)]
+pub struct CompressionOptions {
+    /// Compress stored payloads (as part of DA proposals) with zstd.
+    ///
+    /// Payloads stored without compression remain readable after this is enabled, and vice versa.
+    #[clap(
+        long = "store-compress-payloads",
+        env = "ESPRESSO_SEQUENCER_STORE_COMPRESS_PAYLOADS"
+    )]
+    pub enabled: bool,
+
+    /// zstd compression level for stored payloads, from 1 (fastest) to 22 (smallest).
+    #[clap(
+        long = "store-compression-level",
+        env = "ESPRESSO_SEQUENCER_STORE_COMPRESSION_LEVEL",
+        default_value = "3"
+    )]
+    pub level: i32,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+impl CompressionOptions {
+    /// Prepare a serialized blob for storage, compressing it if enabled.
+    pub(crate) fn encode(&self, bytes: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        if !self.enabled {
+            return Ok(bytes);
+        }
+        zstd::encode_all(bytes.as_slice(), self.level).context("compressing blob")
+    }
+}
+
+/// Recover a serialized blob from storage, decompressing it if it was compressed.
+pub(crate) fn decode(bytes: &[u8]) -> anyhow::Result<Cow<'_, [u8]>> {
+    if !bytes.starts_with(&ZSTD_MAGIC) {
+        return Ok(Cow::Borrowed(bytes));
+    }
+    Ok(Cow::Owned(
+        zstd::decode_all(bytes).context("decompressing blob")?,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compression_round_trip() {
+        let blob = bincode::serialize(&vec![7u8; 10_000]).unwrap();
+
+        // Uncompressed blobs are stored and read as-is.
+        let disabled = CompressionOptions::default();
+        assert!(!disabled.enabled);
+        let stored = disabled.encode(blob.clone()).unwrap();
+        assert_eq!(stored, blob);
+        assert_eq!(decode(&stored).unwrap(), blob.as_slice());
+
+        // Compressed blobs are smaller, and are transparently decompressed.
+        let enabled = CompressionOptions {
+            enabled: true,
+            ..Default::default()
+        };
+        let stored = enabled.encode(blob.clone()).unwrap();
+        assert!(stored.len() < blob.len());
+        assert_eq!(decode(&stored).unwrap(), blob.as_slice());
+    }
+}
@@ -1,14 +1,17 @@
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, ensure, Context};
 use async_lock::RwLock;
 use async_trait::async_trait;
 use clap::Parser;
+use committable::{Commitment, Committable};
 use espresso_types::{
     v0::traits::{EventConsumer, PersistenceOptions, SequencerPersistence},
-    Leaf, NetworkConfig, Payload, SeqTypes,
+    BuilderFeeTotals, DaPointer, DecideEvent, EpochStakeTable, FeeAccount, FeeDeposit, Leaf,
+    NetworkConfig, Payload, PubKey, SeqTypes, ShutdownCheckpoint, Transaction, TxStatus,
+    UpgradeRecord, ValidatedState, ViewRecord,
 };
 use hotshot_types::{
     consensus::CommitmentMap,
-    data::{DaProposal, QuorumProposal, VidDisperseShare},
+    data::{DaProposal, EpochNumber, QuorumProposal, VidDisperseShare},
     event::{Event, EventType, HotShotAction, LeafInfo},
     message::Proposal,
     simple_certificate::{QuorumCertificate, UpgradeCertificate},
@@ -21,13 +24,22 @@ use jf_vid::VidScheme;
 use std::sync::Arc;
 use std::{
     collections::BTreeMap,
-    fs::{self, File, OpenOptions},
+    fs::{self, File},
     io::{Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
 };
 
+use super::{
+    compression::{self, CompressionOptions},
+    parse_tx_hash, DaProfile,
+};
 use crate::ViewNumber;
 
+mod journal;
+
+pub use journal::FsyncPolicy;
+use journal::{Journal, Transaction};
+
 /// Options for file system backed persistence.
 #[derive(Parser, Clone, Debug)]
 pub struct Options {
@@ -37,6 +49,22 @@ pub struct Options {
 
     #[clap(long, env = "ESPRESSO_SEQUENCER_STORE_UNDECIDED_STATE", hide = true)]
     store_undecided_state: bool,
+
+    /// Compression of stored payloads.
+    #[clap(flatten)]
+    compression: CompressionOptions,
+
+    /// When to flush writes to disk.
+    ///
+    /// With `always`, storage survives power loss at the cost of slower writes. With `never`,
+    /// storage only survives the process crashing.
+    #[clap(
+        long = "storage-fsync",
+        env = "ESPRESSO_SEQUENCER_STORAGE_FSYNC",
+        value_enum,
+        default_value_t = FsyncPolicy::Always
+    )]
+    fsync: FsyncPolicy,
 }
 
 impl Default for Options {
@@ -50,6 +78,8 @@ impl Options {
         Self {
             path,
             store_undecided_state: false,
+            compression: Default::default(),
+            fsync: Default::default(),
         }
     }
 
@@ -58,14 +88,32 @@ impl Options {
     }
 }
 
+impl DaProfile for Options {
+    fn da_only(mut self) -> Self {
+        self.store_undecided_state = false;
+        self
+    }
+}
+
 #[async_trait]
 impl PersistenceOptions for Options {
     type Persistence = Persistence;
 
     async fn create(self) -> anyhow::Result<Persistence> {
+        // Finish any write we were in the middle of when we last stopped, before anything reads
+        // from storage.
+        let journal = Journal::new(self.path.clone(), self.fsync);
+        journal
+            .recover()
+            .context("recovering from interrupted write")?;
+
         Ok(Persistence {
             store_undecided_state: self.store_undecided_state,
-            inner: Arc::new(RwLock::new(Inner { path: self.path })),
+            compression: self.compression,
+            inner: Arc::new(RwLock::new(Inner {
+                path: self.path,
+                journal,
+            })),
         })
     }
 
@@ -78,6 +126,7 @@ impl PersistenceOptions for Options {
 #[derive(Clone, Debug)]
 pub struct Persistence {
     store_undecided_state: bool,
+    compression: CompressionOptions,
 
     // We enforce mutual exclusion on access to the data source, as the current file system
     // implementation does not support transaction isolation for concurrent reads and writes. We can
@@ -88,6 +137,7 @@ pub struct Persistence {
 #[derive(Debug)]
 struct Inner {
     path: PathBuf,
+    journal: Journal,
 }
 
 impl Inner {
@@ -121,6 +171,18 @@ impl Inner {
         self.path.join("undecided_state")
     }
 
+    fn view_log_dir_path(&self) -> PathBuf {
+        self.path.join("view_log")
+    }
+
+    fn decide_event_log_dir_path(&self) -> PathBuf {
+        self.path.join("decide_event_log")
+    }
+
+    fn anchor_state_path(&self) -> PathBuf {
+        self.path.join("anchor_state")
+    }
+
     fn quorum_proposals_dir_path(&self) -> PathBuf {
         self.path.join("quorum_proposals")
     }
@@ -129,6 +191,58 @@ impl Inner {
         self.path.join("upgrade_certificate")
     }
 
+    fn tx_status_dir_path(&self) -> PathBuf {
+        self.path.join("tx_status")
+    }
+
+    fn mempool_dir_path(&self) -> PathBuf {
+        self.path.join("mempool")
+    }
+
+    fn stake_table_dir_path(&self) -> PathBuf {
+        self.path.join("stake_tables")
+    }
+
+    fn da_pointer_dir_path(&self) -> PathBuf {
+        self.path.join("da_pointers")
+    }
+
+    fn upgrade_dir_path(&self) -> PathBuf {
+        self.path.join("upgrades")
+    }
+
+    fn libp2p_peers_path(&self) -> PathBuf {
+        self.path.join("libp2p_peers")
+    }
+
+    fn banned_peers_path(&self) -> PathBuf {
+        self.path.join("banned_peers")
+    }
+
+    fn shutdown_checkpoint_path(&self) -> PathBuf {
+        self.path.join("shutdown_checkpoint")
+    }
+
+    fn builder_fees_dir_path(&self) -> PathBuf {
+        self.path.join("builder_fees")
+    }
+
+    fn fee_deposits_path(&self) -> PathBuf {
+        self.path.join("fee_deposits")
+    }
+
+    /// Load the last L1 block scanned for fee deposits and all deposits found so far.
+    fn load_fee_deposits(&self) -> anyhow::Result<Option<(u64, Vec<FeeDeposit>)>> {
+        let path = self.fee_deposits_path();
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let bytes = fs::read(&path).context("read")?;
+        Ok(Some(
+            bincode::deserialize(&bytes).context("deserialize fee deposits")?,
+        ))
+    }
+
     /// Overwrite a file if a condition is met.
     ///
     /// The file at `path`, if it exists, is opened in read mode and passed to `pred`. If `pred`
@@ -137,7 +251,8 @@ impl Inner {
     /// contents of the file.
     ///
     /// The final replacement of the original file is atomic; that is, `path` will be modified only
-    /// if the entire update succeeds.
+    /// if the entire update succeeds, and the update survives crashes according to the configured
+    /// [`FsyncPolicy`].
     fn replace(
         &mut self,
         path: &Path,
@@ -156,21 +271,8 @@ impl Inner {
             }
         }
 
-        // Either there is no existing file or we have decided to overwrite the file. Write the new
-        // contents into a temporary file so we can update `path` atomically using `rename`.
-        let mut swap_path = path.to_owned();
-        swap_path.set_extension("swp");
-        let swap = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(&swap_path)?;
-        write(swap)?;
-
-        // Now we can replace the original file.
-        fs::rename(swap_path, path)?;
-
-        Ok(())
+        // Either there is no existing file or we have decided to overwrite the file.
+        self.journal.write(path, write)
     }
 
     fn collect_garbage(&mut self, view: ViewNumber) -> anyhow::Result<()> {
@@ -312,7 +414,7 @@ impl Inner {
         let da_bytes = fs::read(file_path)?;
 
         let da_proposal: Proposal<SeqTypes, DaProposal<SeqTypes>> =
-            bincode::deserialize(&da_bytes)?;
+            bincode::deserialize(&compression::decode(&da_bytes)?)?;
         Ok(Some(da_proposal))
     }
 
@@ -398,10 +500,21 @@ impl SequencerPersistence for Persistence {
     }
 
     async fn save_config(&self, cfg: &NetworkConfig) -> anyhow::Result<()> {
-        let inner = self.inner.write().await;
+        let mut inner = self.inner.write().await;
         let path = inner.config_path();
         tracing::info!("saving config to {}", path.display());
-        Ok(cfg.to_file(path.display().to_string())?)
+        inner.replace(
+            &path,
+            |_| {
+                // Always overwrite the previous config.
+                Ok(true)
+            },
+            |mut file| {
+                let bytes = serde_json::to_vec_pretty(cfg).context("serializing config")?;
+                file.write_all(&bytes)?;
+                Ok(())
+            },
+        )
     }
 
     async fn load_latest_acted_view(&self) -> anyhow::Result<Option<ViewNumber>> {
@@ -428,6 +541,10 @@ impl SequencerPersistence for Persistence {
         // Ensure the anchor leaf directory exists.
         fs::create_dir_all(&path).context("creating anchor leaf directory")?;
 
+        // All the new leaves are written together, so that we never end up with a gap in the
+        // chain of decided leaves.
+        let mut tx = Transaction::default();
+
         // Earlier versions stored only a single decided leaf in a regular file. If our storage is
         // still on this version, migrate to a directory structure storing (possibly) many leaves.
         let legacy_path = inner.legacy_anchor_leaf_path();
@@ -440,31 +557,22 @@ impl SequencerPersistence for Persistence {
                 .context("anchor leaf file exists but unable to load contents")?;
             let view = leaf.view_number().u64();
             let bytes = bincode::serialize(&(leaf, qc))?;
-            let new_file = path.join(view.to_string()).with_extension("txt");
-            fs::write(new_file, bytes).context(format!("writing anchor leaf file {view}"))?;
-
-            // Now we can remove the old file.
-            fs::remove_file(&legacy_path).context("removing legacy anchor leaf file")?;
+            tx.write(path.join(view.to_string()).with_extension("txt"), bytes);
+            tx.remove(legacy_path);
         }
 
         for (info, qc) in leaf_chain {
             let view = info.leaf.view_number().u64();
             let file_path = path.join(view.to_string()).with_extension("txt");
-            inner.replace(
-                &file_path,
-                |_| {
-                    // Don't overwrite an existing leaf, but warn about it as this is likely not
-                    // intended behavior from HotShot.
-                    tracing::warn!(view, "duplicate decided leaf");
-                    Ok(false)
-                },
-                |mut file| {
-                    let bytes = bincode::serialize(&(&info.leaf, qc))?;
-                    file.write_all(&bytes)?;
-                    Ok(())
-                },
-            )?;
+            if file_path.is_file() {
+                // Don't overwrite an existing leaf, but warn about it as this is likely not
+                // intended behavior from HotShot.
+                tracing::warn!(view, "duplicate decided leaf");
+                continue;
+            }
+            tx.write(file_path, bincode::serialize(&(&info.leaf, qc))?);
         }
+        inner.journal.commit(tx).context("writing decided leaves")?;
 
         // Event processing failure is not an error, since by this point we have at least managed to
         // persist the decided leaves successfully, and the event processing will just run again at
@@ -564,7 +672,9 @@ impl SequencerPersistence for Persistence {
                 Ok(false)
             },
             |mut file| {
-                let proposal_bytes = bincode::serialize(&proposal).context("serialize proposal")?;
+                let proposal_bytes = self
+                    .compression
+                    .encode(bincode::serialize(&proposal).context("serialize proposal")?)?;
                 file.write_all(&proposal_bytes)?;
                 Ok(())
             },
@@ -750,6 +860,730 @@ impl SequencerPersistence for Persistence {
             },
         )
     }
+
+    async fn store_tx_status(
+        &self,
+        hash: Commitment<Transaction>,
+        status: TxStatus,
+    ) -> anyhow::Result<()> {
+        let mut inner = self.inner.write().await;
+        let dir_path = inner.tx_status_dir_path();
+
+        fs::create_dir_all(dir_path.clone()).context("failed to create tx status dir")?;
+
+        let file_path = dir_path.join(hash.to_string()).with_extension("txt");
+        inner.replace(
+            &file_path,
+            |_| {
+                // Always overwrite the previous file.
+                Ok(true)
+            },
+            |mut file| {
+                let bytes = bincode::serialize(&status).context("serializing tx status")?;
+                file.write_all(&bytes)?;
+                Ok(())
+            },
+        )
+    }
+
+    async fn load_tx_status(
+        &self,
+        hash: Commitment<Transaction>,
+    ) -> anyhow::Result<Option<TxStatus>> {
+        let inner = self.inner.read().await;
+        let file_path = inner
+            .tx_status_dir_path()
+            .join(hash.to_string())
+            .with_extension("txt");
+        if !file_path.is_file() {
+            return Ok(None);
+        }
+        let bytes = fs::read(&file_path).context("read")?;
+        Ok(Some(
+            bincode::deserialize(&bytes).context("deserialize tx status")?,
+        ))
+    }
+
+    async fn load_all_tx_statuses(
+        &self,
+    ) -> anyhow::Result<Vec<(Commitment<Transaction>, TxStatus)>> {
+        let inner = self.inner.read().await;
+        let dir_path = inner.tx_status_dir_path();
+        if !dir_path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut statuses = vec![];
+        for entry in fs::read_dir(&dir_path)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("txt") {
+                // Skip swap files left over from an interrupted write.
+                continue;
+            }
+            let hash = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .context(format!("malformed tx status file {}", path.display()))?;
+            let bytes = fs::read(&path).context(format!("reading {}", path.display()))?;
+            statuses.push((
+                parse_tx_hash(hash)?,
+                bincode::deserialize(&bytes).context("deserialize tx status")?,
+            ));
+        }
+        Ok(statuses)
+    }
+
+    async fn append_mempool_txs(&self, txs: &[Transaction]) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        let dir_path = inner.mempool_dir_path();
+
+        fs::create_dir_all(dir_path.clone()).context("failed to create mempool dir")?;
+
+        let mut batch = Transaction::default();
+        for tx in txs {
+            let file_path = dir_path.join(tx.commit().to_string()).with_extension("txt");
+            if file_path.is_file() {
+                // The file name is the hash of the transaction, so an existing file already has
+                // the same contents.
+                continue;
+            }
+            batch.write(
+                file_path,
+                bincode::serialize(tx).context("serializing transaction")?,
+            );
+        }
+        inner.journal.commit(batch)
+    }
+
+    async fn remove_mempool_txs(&self, hashes: &[Commitment<Transaction>]) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        let dir_path = inner.mempool_dir_path();
+        let mut tx = Transaction::default();
+        for hash in hashes {
+            let file_path = dir_path.join(hash.to_string()).with_extension("txt");
+            if file_path.is_file() {
+                tx.remove(file_path);
+            }
+        }
+        inner.journal.commit(tx).context("removing mempool files")
+    }
+
+    async fn load_mempool(&self) -> anyhow::Result<Vec<Transaction>> {
+        let inner = self.inner.read().await;
+        let dir_path = inner.mempool_dir_path();
+        if !dir_path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut txs = vec![];
+        for entry in fs::read_dir(&dir_path)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("txt") {
+                // Skip swap files left over from an interrupted write.
+                continue;
+            }
+            let bytes = fs::read(&path).context(format!("reading {}", path.display()))?;
+            txs.push(bincode::deserialize(&bytes).context("deserialize transaction")?);
+        }
+        Ok(txs)
+    }
+
+    async fn store_stake_table(&self, stake_table: &EpochStakeTable) -> anyhow::Result<()> {
+        let mut inner = self.inner.write().await;
+        let dir_path = inner.stake_table_dir_path();
+
+        fs::create_dir_all(dir_path.clone()).context("failed to create stake table dir")?;
+
+        let file_path = dir_path
+            .join(stake_table.epoch.u64().to_string())
+            .with_extension("txt");
+        inner.replace(
+            &file_path,
+            |_| {
+                // Always overwrite the previous file.
+                Ok(true)
+            },
+            |mut file| {
+                let bytes = bincode::serialize(stake_table).context("serializing stake table")?;
+                file.write_all(&bytes)?;
+                Ok(())
+            },
+        )
+    }
+
+    async fn load_stake_table(
+        &self,
+        epoch: EpochNumber,
+    ) -> anyhow::Result<Option<EpochStakeTable>> {
+        let inner = self.inner.read().await;
+        let file_path = inner
+            .stake_table_dir_path()
+            .join(epoch.u64().to_string())
+            .with_extension("txt");
+        if !file_path.is_file() {
+            return Ok(None);
+        }
+        let bytes = fs::read(&file_path).context("read")?;
+        Ok(Some(
+            bincode::deserialize(&bytes).context("deserialize stake table")?,
+        ))
+    }
+
+    async fn load_all_stake_tables(&self) -> anyhow::Result<Vec<EpochStakeTable>> {
+        let inner = self.inner.read().await;
+        let dir_path = inner.stake_table_dir_path();
+        if !dir_path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut stake_tables = vec![];
+        for entry in fs::read_dir(&dir_path)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("txt") {
+                // Skip swap files left over from an interrupted write.
+                continue;
+            }
+            let bytes = fs::read(&path).context(format!("reading {}", path.display()))?;
+            stake_tables.push(bincode::deserialize(&bytes).context("deserialize stake table")?);
+        }
+        Ok(stake_tables)
+    }
+
+    async fn store_da_pointer(&self, pointer: &DaPointer) -> anyhow::Result<()> {
+        let mut inner = self.inner.write().await;
+        let dir_path = inner.da_pointer_dir_path();
+
+        fs::create_dir_all(dir_path.clone()).context("failed to create DA pointer dir")?;
+
+        let file_path = dir_path
+            .join(pointer.height.to_string())
+            .with_extension("txt");
+        inner.replace(
+            &file_path,
+            |_| {
+                // Always overwrite the previous file.
+                Ok(true)
+            },
+            |mut file| {
+                let bytes = bincode::serialize(pointer).context("serializing DA pointer")?;
+                file.write_all(&bytes)?;
+                Ok(())
+            },
+        )
+    }
+
+    async fn load_da_pointer(&self, height: u64) -> anyhow::Result<Option<DaPointer>> {
+        let inner = self.inner.read().await;
+        let file_path = inner
+            .da_pointer_dir_path()
+            .join(height.to_string())
+            .with_extension("txt");
+        if !file_path.is_file() {
+            return Ok(None);
+        }
+        let bytes = fs::read(&file_path).context("read")?;
+        Ok(Some(
+            bincode::deserialize(&bytes).context("deserialize DA pointer")?,
+        ))
+    }
+
+    async fn load_all_da_pointers(&self) -> anyhow::Result<Vec<DaPointer>> {
+        let inner = self.inner.read().await;
+        let dir_path = inner.da_pointer_dir_path();
+        if !dir_path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut pointers = vec![];
+        for entry in fs::read_dir(&dir_path)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("txt") {
+                // Skip swap files left over from an interrupted write.
+                continue;
+            }
+            let bytes = fs::read(&path).context(format!("reading {}", path.display()))?;
+            pointers.push(bincode::deserialize(&bytes).context("deserialize DA pointer")?);
+        }
+        Ok(pointers)
+    }
+
+    async fn store_upgrade(&self, upgrade: &UpgradeRecord) -> anyhow::Result<()> {
+        let mut inner = self.inner.write().await;
+        let dir_path = inner.upgrade_dir_path();
+
+        fs::create_dir_all(dir_path.clone()).context("failed to create upgrade dir")?;
+
+        // Build the file name explicitly: `with_extension` would treat the minor version as an
+        // extension and replace it, so that every `0.x` upgrade would share the file `0.txt`.
+        let file_path = dir_path.join(format!(
+            "{}.{}.txt",
+            upgrade.version.major, upgrade.version.minor
+        ));
+        inner.replace(
+            &file_path,
+            |_| {
+                // Always overwrite the previous status.
+                Ok(true)
+            },
+            |mut file| {
+                let bytes = bincode::serialize(upgrade).context("serializing upgrade")?;
+                file.write_all(&bytes)?;
+                Ok(())
+            },
+        )
+    }
+
+    async fn load_upgrades(&self) -> anyhow::Result<Vec<UpgradeRecord>> {
+        let inner = self.inner.read().await;
+        let dir_path = inner.upgrade_dir_path();
+        if !dir_path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut upgrades = vec![];
+        for entry in fs::read_dir(&dir_path)? {
+            let path = entry?.path();
+            // Files are named `<major>.<minor>.txt`. This also skips swap files left over from an
+            // interrupted write.
+            let Some(version) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".txt"))
+                .and_then(|version| version.split_once('.'))
+                .and_then(|(major, minor)| {
+                    Some((major.parse::<u16>().ok()?, minor.parse::<u16>().ok()?))
+                })
+            else {
+                continue;
+            };
+            let bytes = fs::read(&path).context(format!("reading {}", path.display()))?;
+            let upgrade: UpgradeRecord =
+                bincode::deserialize(&bytes).context("deserialize upgrade")?;
+            ensure!(
+                (upgrade.version.major, upgrade.version.minor) == version,
+                "upgrade file {} contains the record for version {}",
+                path.display(),
+                upgrade.version
+            );
+            upgrades.push(upgrade);
+        }
+        Ok(upgrades)
+    }
+
+    async fn store_libp2p_peers(&self, peers: &[String]) -> anyhow::Result<()> {
+        let mut inner = self.inner.write().await;
+        let path = inner.libp2p_peers_path();
+        inner.replace(
+            &path,
+            |_| {
+                // Always overwrite the previous list.
+                Ok(true)
+            },
+            |mut file| {
+                let bytes = bincode::serialize(peers).context("serializing libp2p peers")?;
+                file.write_all(&bytes)?;
+                Ok(())
+            },
+        )
+    }
+
+    async fn load_libp2p_peers(&self) -> anyhow::Result<Vec<String>> {
+        let inner = self.inner.read().await;
+        let path = inner.libp2p_peers_path();
+        if !path.is_file() {
+            return Ok(vec![]);
+        }
+        let bytes = fs::read(&path).context("read")?;
+        bincode::deserialize(&bytes).context("deserialize libp2p peers")
+    }
+
+    async fn store_fee_deposits(
+        &self,
+        deposits: &[FeeDeposit],
+        l1_block: u64,
+    ) -> anyhow::Result<()> {
+        let mut inner = self.inner.write().await;
+        let (_, mut all) = inner.load_fee_deposits()?.unwrap_or_default();
+        for deposit in deposits {
+            if !all.iter().any(|existing| {
+                (existing.l1_block, existing.log_index) == (deposit.l1_block, deposit.log_index)
+            }) {
+                all.push(deposit.clone());
+            }
+        }
+        all.sort_by_key(|deposit| (deposit.l1_block, deposit.log_index));
+
+        let path = inner.fee_deposits_path();
+        inner.replace(
+            &path,
+            |_| {
+                // Always overwrite the previous index.
+                Ok(true)
+            },
+            |mut file| {
+                let bytes =
+                    bincode::serialize(&(l1_block, all)).context("serializing fee deposits")?;
+                file.write_all(&bytes)?;
+                Ok(())
+            },
+        )
+    }
+
+    async fn load_fee_deposits_l1_block(&self) -> anyhow::Result<Option<u64>> {
+        let inner = self.inner.read().await;
+        Ok(inner.load_fee_deposits()?.map(|(l1_block, _)| l1_block))
+    }
+
+    async fn load_fee_deposits(
+        &self,
+        account: FeeAccount,
+        offset: u64,
+        limit: u64,
+    ) -> anyhow::Result<Vec<FeeDeposit>> {
+        let inner = self.inner.read().await;
+        let (_, all) = inner.load_fee_deposits()?.unwrap_or_default();
+        Ok(all
+            .into_iter()
+            .filter(|deposit| deposit.account == account)
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect())
+    }
+
+    async fn load_all_fee_deposits(&self) -> anyhow::Result<Vec<FeeDeposit>> {
+        let inner = self.inner.read().await;
+        let (_, all) = inner.load_fee_deposits()?.unwrap_or_default();
+        Ok(all)
+    }
+
+    async fn store_builder_fees(&self, totals: &[BuilderFeeTotals]) -> anyhow::Result<()> {
+        let mut inner = self.inner.write().await;
+        let dir_path = inner.builder_fees_dir_path();
+        fs::create_dir_all(dir_path.clone()).context("failed to create builder fees dir")?;
+
+        for account in totals {
+            let file_path = dir_path
+                .join(format!("{:x}", account.account.0))
+                .with_extension("txt");
+            inner.replace(
+                &file_path,
+                |_| {
+                    // Always overwrite the previous totals.
+                    Ok(true)
+                },
+                |mut file| {
+                    let bytes = bincode::serialize(account).context("serializing builder fees")?;
+                    file.write_all(&bytes)?;
+                    Ok(())
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    async fn load_builder_fees(
+        &self,
+        account: FeeAccount,
+    ) -> anyhow::Result<Option<BuilderFeeTotals>> {
+        let inner = self.inner.read().await;
+        let path = inner
+            .builder_fees_dir_path()
+            .join(format!("{:x}", account.0))
+            .with_extension("txt");
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let bytes = fs::read(&path).context(format!("reading {}", path.display()))?;
+        Ok(Some(
+            bincode::deserialize(&bytes).context("deserialize builder fees")?,
+        ))
+    }
+
+    async fn load_all_builder_fees(&self) -> anyhow::Result<Vec<BuilderFeeTotals>> {
+        let inner = self.inner.read().await;
+        let dir_path = inner.builder_fees_dir_path();
+        if !dir_path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut totals = vec![];
+        for entry in fs::read_dir(&dir_path)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("txt") {
+                // Skip swap files left over from an interrupted write.
+                continue;
+            }
+            let bytes = fs::read(&path).context(format!("reading {}", path.display()))?;
+            totals.push(bincode::deserialize(&bytes).context("deserialize builder fees")?);
+        }
+        Ok(totals)
+    }
+
+    async fn store_banned_peers(&self, peers: &[PubKey]) -> anyhow::Result<()> {
+        let mut inner = self.inner.write().await;
+        let path = inner.banned_peers_path();
+        inner.replace(
+            &path,
+            |_| {
+                // Always overwrite the previous list.
+                Ok(true)
+            },
+            |mut file| {
+                let bytes = bincode::serialize(peers).context("serializing banned peers")?;
+                file.write_all(&bytes)?;
+                Ok(())
+            },
+        )
+    }
+
+    async fn store_shutdown_checkpoint(
+        &self,
+        checkpoint: &ShutdownCheckpoint,
+    ) -> anyhow::Result<()> {
+        let mut inner = self.inner.write().await;
+        let path = inner.shutdown_checkpoint_path();
+        inner.replace(
+            &path,
+            |_| {
+                // Always overwrite a previous checkpoint.
+                Ok(true)
+            },
+            |mut file| {
+                let bytes =
+                    bincode::serialize(checkpoint).context("serializing shutdown checkpoint")?;
+                file.write_all(&bytes)?;
+                Ok(())
+            },
+        )
+    }
+
+    async fn take_shutdown_checkpoint(&self) -> anyhow::Result<Option<ShutdownCheckpoint>> {
+        let inner = self.inner.write().await;
+        let path = inner.shutdown_checkpoint_path();
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let bytes = fs::read(&path).context("read")?;
+        inner
+            .journal
+            .remove(&path)
+            .context("remove shutdown checkpoint")?;
+        Ok(Some(
+            bincode::deserialize(&bytes).context("deserialize shutdown checkpoint")?,
+        ))
+    }
+
+    async fn store_view_record(&self, record: &ViewRecord) -> anyhow::Result<()> {
+        let mut inner = self.inner.write().await;
+        let dir_path = inner.view_log_dir_path();
+
+        fs::create_dir_all(dir_path.clone()).context("failed to create view log dir")?;
+
+        let file_path = dir_path.join(record.view.to_string()).with_extension("txt");
+        inner.replace(
+            &file_path,
+            |_| {
+                // Always overwrite the previous record.
+                Ok(true)
+            },
+            |mut file| {
+                let bytes = bincode::serialize(record).context("serializing view record")?;
+                file.write_all(&bytes)?;
+                Ok(())
+            },
+        )
+    }
+
+    async fn load_view_records(&self, from: u64, until: u64) -> anyhow::Result<Vec<ViewRecord>> {
+        let inner = self.inner.read().await;
+        let dir_path = inner.view_log_dir_path();
+        if !dir_path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        // Scan the directory rather than the range, which may be much larger than the number of
+        // recorded views.
+        let mut paths = BTreeMap::new();
+        for entry in fs::read_dir(dir_path)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("txt") {
+                continue;
+            }
+            let Some(view) = path
+                .file_stem()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            if (from..until).contains(&view) {
+                paths.insert(view, path);
+            }
+        }
+
+        let mut records = vec![];
+        for path in paths.into_values() {
+            let bytes = fs::read(&path).context("read")?;
+            records.push(bincode::deserialize(&bytes).context("deserialize view record")?);
+        }
+        Ok(records)
+    }
+
+    async fn prune_view_records(&self, view: u64) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        let dir_path = inner.view_log_dir_path();
+        if !dir_path.is_dir() {
+            return Ok(());
+        }
+        for entry in fs::read_dir(dir_path)? {
+            let path = entry?.path();
+            let Some(v) = path
+                .file_stem()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            if v < view {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn store_decide_events(&self, events: &[DecideEvent]) -> anyhow::Result<()> {
+        let mut inner = self.inner.write().await;
+        let dir_path = inner.decide_event_log_dir_path();
+
+        fs::create_dir_all(dir_path.clone()).context("failed to create decide event log dir")?;
+
+        for event in events {
+            let file_path = dir_path
+                .join(event.height.to_string())
+                .with_extension("txt");
+            inner.replace(
+                &file_path,
+                |_| {
+                    // Always overwrite the previous event.
+                    Ok(true)
+                },
+                |mut file| {
+                    let bytes = bincode::serialize(event).context("serializing decide event")?;
+                    file.write_all(&bytes)?;
+                    Ok(())
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    async fn load_decide_events(
+        &self,
+        from: u64,
+        limit: usize,
+    ) -> anyhow::Result<Vec<DecideEvent>> {
+        let inner = self.inner.read().await;
+        let dir_path = inner.decide_event_log_dir_path();
+        if !dir_path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut heights = vec![];
+        for entry in fs::read_dir(&dir_path)? {
+            let path = entry?.path();
+            let Some(height) = path
+                .file_stem()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            if height >= from {
+                heights.push(height);
+            }
+        }
+        heights.sort_unstable();
+
+        heights
+            .into_iter()
+            .take(limit)
+            .map(|height| {
+                let file_path = dir_path.join(height.to_string()).with_extension("txt");
+                let bytes = fs::read(&file_path).context("read")?;
+                bincode::deserialize(&bytes).context("deserialize decide event")
+            })
+            .collect()
+    }
+
+    async fn prune_decide_events(&self, height: u64) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        let dir_path = inner.decide_event_log_dir_path();
+        if !dir_path.is_dir() {
+            return Ok(());
+        }
+        for entry in fs::read_dir(dir_path)? {
+            let path = entry?.path();
+            let Some(h) = path
+                .file_stem()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            if h < height {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn store_anchor_state(
+        &self,
+        view: ViewNumber,
+        state: &ValidatedState,
+    ) -> anyhow::Result<()> {
+        let mut inner = self.inner.write().await;
+        let path = inner.anchor_state_path();
+        inner.replace(
+            &path,
+            |mut file| {
+                // The file starts with the view of the saved state. Overwrite it only with the
+                // state of a newer anchor leaf.
+                let mut bytes = [0; 8];
+                file.read_exact(&mut bytes)?;
+                Ok(ViewNumber::new(u64::from_le_bytes(bytes)) < view)
+            },
+            |mut file| {
+                file.write_all(&view.u64().to_le_bytes())?;
+                bincode::serialize_into(file, state).context("serializing anchor state")?;
+                Ok(())
+            },
+        )
+    }
+
+    async fn load_anchor_state(&self) -> anyhow::Result<Option<(ViewNumber, ValidatedState)>> {
+        let inner = self.inner.read().await;
+        let path = inner.anchor_state_path();
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let bytes = fs::read(&path).context("read")?;
+        let (view, state) = bytes
+            .split_first_chunk::<8>()
+            .ok_or_else(|| anyhow!("malformed anchor state file"))?;
+        let state = bincode::deserialize(state).context("deserialize anchor state")?;
+        Ok(Some((ViewNumber::new(u64::from_le_bytes(*view)), state)))
+    }
+
+    async fn load_banned_peers(&self) -> anyhow::Result<Vec<PubKey>> {
+        let inner = self.inner.read().await;
+        let path = inner.banned_peers_path();
+        if !path.is_file() {
+            return Ok(vec![]);
+        }
+        let bytes = fs::read(&path).context("read")?;
+        bincode::deserialize(&bytes).context("deserialize banned peers")
+    }
 }
 
 /// Update a `NetworkConfig` that may have originally been persisted with an old version.
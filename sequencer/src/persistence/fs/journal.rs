@@ -0,0 +1,319 @@
+//! Crash-consistent writes for file system persistence.
+//!
+//! Every file is replaced atomically, by writing the new contents to a swap file and renaming it
+//! over the original. Renaming alone only protects against the process crashing, though: after a
+//! power loss, the rename may have reached the disk before the data it points to. Depending on the
+//! [`FsyncPolicy`], the swap file and the directory containing it are therefore synced before and
+//! after the rename.
+//!
+//! Mutations which touch more than one file are first recorded in a write-ahead journal. The
+//! journal is itself written atomically, so once it exists the mutation is committed: if we crash
+//! while applying it, [`Journal::recover`] replays it in full on the next startup. Every operation
+//! in the journal is idempotent, so it does not matter how much of it was applied before the
+//! crash.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Name of the journal file, relative to the storage directory.
+const JOURNAL_FILE: &str = "journal";
+
+/// Extension of the swap files new contents are written to before they replace the original.
+const SWAP_EXTENSION: &str = "swp";
+
+/// When to flush writes to disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum FsyncPolicy {
+    /// Sync every file and directory as it is written, so that storage survives power loss.
+    #[default]
+    Always,
+    /// Leave flushing to the operating system.
+    ///
+    /// Writes are still atomic, so storage survives the process crashing, but recent writes may be
+    /// lost or left inconsistent if the whole machine goes down.
+    Never,
+}
+
+/// A set of file system mutations to be applied together.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(super) struct Transaction {
+    ops: Vec<Op>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+enum Op {
+    Write { path: PathBuf, contents: Vec<u8> },
+    Remove { path: PathBuf },
+}
+
+impl Op {
+    fn path(&self) -> &Path {
+        match self {
+            Self::Write { path, .. } | Self::Remove { path } => path,
+        }
+    }
+}
+
+impl Transaction {
+    /// Replace the contents of the file at `path`, creating it if necessary.
+    pub(super) fn write(&mut self, path: impl Into<PathBuf>, contents: Vec<u8>) {
+        self.ops.push(Op::Write {
+            path: path.into(),
+            contents,
+        });
+    }
+
+    /// Remove the file at `path`, if it exists.
+    pub(super) fn remove(&mut self, path: impl Into<PathBuf>) {
+        self.ops.push(Op::Remove { path: path.into() });
+    }
+}
+
+/// Applies file system mutations to a storage directory, so that they survive crashes.
+#[derive(Clone, Debug)]
+pub(super) struct Journal {
+    root: PathBuf,
+    fsync: FsyncPolicy,
+}
+
+impl Journal {
+    pub(super) fn new(root: PathBuf, fsync: FsyncPolicy) -> Self {
+        Self { root, fsync }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.root.join(JOURNAL_FILE)
+    }
+
+    /// Apply all the mutations in `tx`, or none of them.
+    ///
+    /// If this fails or we crash partway through, the transaction is completed by the next call to
+    /// [`recover`](Self::recover) if it was committed to the journal, and otherwise has no effect.
+    pub(super) fn commit(&self, tx: Transaction) -> anyhow::Result<()> {
+        match tx.ops.as_slice() {
+            [] => return Ok(()),
+            // A single operation is atomic on its own, so it does not need to be journaled.
+            [op] => return self.apply(op),
+            _ => {}
+        }
+
+        // Record paths relative to the storage directory, so the journal can still be replayed if
+        // the directory is moved.
+        let tx = Transaction {
+            ops: tx.ops.into_iter().map(|op| self.relative(op)).collect(),
+        };
+        let bytes = bincode::serialize(&tx).context("serializing journal")?;
+        self.write(&self.path(), |mut file| {
+            file.write_all(&bytes)?;
+            Ok(())
+        })
+        .context("writing journal")?;
+
+        self.replay(&tx)?;
+        self.remove(&self.path()).context("removing journal")
+    }
+
+    /// Complete a transaction which was interrupted by a crash, if there is one.
+    ///
+    /// This must be called before reading from storage.
+    pub(super) fn recover(&self) -> anyhow::Result<()> {
+        // A journal which was only partially written was never committed, and will be cleaned up
+        // along with the other swap files.
+        let path = self.path();
+        if path.is_file() {
+            let bytes = fs::read(&path).context("reading journal")?;
+            let tx: Transaction = bincode::deserialize(&bytes).context("malformed journal")?;
+            tracing::warn!(
+                ops = tx.ops.len(),
+                "completing write interrupted by previous shutdown"
+            );
+            self.replay(&tx)?;
+            self.remove(&path).context("removing journal")?;
+        }
+
+        remove_swap_files(&self.root).context("removing swap files")
+    }
+
+    fn replay(&self, tx: &Transaction) -> anyhow::Result<()> {
+        for op in &tx.ops {
+            self.apply(op)?;
+        }
+        Ok(())
+    }
+
+    fn apply(&self, op: &Op) -> anyhow::Result<()> {
+        let path = self.root.join(op.path());
+        match op {
+            Op::Write { contents, .. } => self.write(&path, |mut file| {
+                file.write_all(contents)?;
+                Ok(())
+            }),
+            Op::Remove { .. } => self.remove(&path),
+        }
+        .with_context(|| format!("updating {}", path.display()))
+    }
+
+    fn relative(&self, op: Op) -> Op {
+        let relative = |path: PathBuf| match path.strip_prefix(&self.root) {
+            Ok(relative) => relative.to_owned(),
+            Err(_) => path,
+        };
+        match op {
+            Op::Write { path, contents } => Op::Write {
+                path: relative(path),
+                contents,
+            },
+            Op::Remove { path } => Op::Remove {
+                path: relative(path),
+            },
+        }
+    }
+
+    /// Atomically replace the file at `path` with contents set by `write`.
+    ///
+    /// `write` receives a truncated swap file open in write mode. Once it has set the contents,
+    /// the swap file replaces `path`.
+    pub(super) fn write(
+        &self,
+        path: &Path,
+        write: impl FnOnce(File) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let dir = parent(path);
+        fs::create_dir_all(dir)?;
+
+        let mut swap_path = path.to_owned();
+        swap_path.set_extension(SWAP_EXTENSION);
+        let swap = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&swap_path)?;
+        write(swap.try_clone()?)?;
+
+        // Make sure the contents are on disk before the rename can be.
+        if self.fsync == FsyncPolicy::Always {
+            swap.sync_all()?;
+        }
+        fs::rename(swap_path, path)?;
+        self.sync_dir(dir)
+    }
+
+    /// Remove the file at `path`, if it exists.
+    pub(super) fn remove(&self, path: &Path) -> anyhow::Result<()> {
+        match fs::remove_file(path) {
+            Ok(()) => self.sync_dir(parent(path)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Make the creation, renaming and removal of files in `dir` durable.
+    fn sync_dir(&self, dir: &Path) -> anyhow::Result<()> {
+        if self.fsync == FsyncPolicy::Always {
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Remove swap files left over from writes which were interrupted before they took effect.
+fn remove_swap_files(dir: &Path) -> anyhow::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            remove_swap_files(&path)?;
+        } else if path.extension().and_then(|ext| ext.to_str()) == Some(SWAP_EXTENSION) {
+            tracing::info!("removing interrupted write {}", path.display());
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_recover_committed_transaction() {
+        let tmp = TempDir::new().unwrap();
+        let journal = Journal::new(tmp.path().into(), FsyncPolicy::Always);
+        fs::write(tmp.path().join("old"), b"old").unwrap();
+
+        // Commit a transaction to the journal, but crash before applying it.
+        let mut tx = Transaction::default();
+        tx.write(tmp.path().join("dir").join("new"), b"new".to_vec());
+        tx.remove(tmp.path().join("old"));
+        let tx = Transaction {
+            ops: tx.ops.into_iter().map(|op| journal.relative(op)).collect(),
+        };
+        fs::write(journal.path(), bincode::serialize(&tx).unwrap()).unwrap();
+
+        journal.recover().unwrap();
+        assert_eq!(
+            fs::read(tmp.path().join("dir").join("new")).unwrap(),
+            b"new"
+        );
+        assert!(!tmp.path().join("old").exists());
+        assert!(!journal.path().exists());
+
+        // Recovering again is a no-op.
+        journal.recover().unwrap();
+        assert_eq!(
+            fs::read(tmp.path().join("dir").join("new")).unwrap(),
+            b"new"
+        );
+    }
+
+    #[test]
+    fn test_recover_uncommitted_transaction() {
+        let tmp = TempDir::new().unwrap();
+        let journal = Journal::new(tmp.path().into(), FsyncPolicy::Never);
+        fs::write(tmp.path().join("file"), b"old").unwrap();
+
+        // Crash while writing the journal and while replacing a file: neither takes effect.
+        fs::write(journal.path().with_extension(SWAP_EXTENSION), b"garbage").unwrap();
+        fs::write(tmp.path().join("file.swp"), b"new").unwrap();
+
+        journal.recover().unwrap();
+        assert_eq!(fs::read(tmp.path().join("file")).unwrap(), b"old");
+        assert!(!tmp.path().join("file.swp").exists());
+        assert!(!journal.path().with_extension(SWAP_EXTENSION).exists());
+    }
+
+    #[test]
+    fn test_commit() {
+        let tmp = TempDir::new().unwrap();
+        let journal = Journal::new(tmp.path().into(), FsyncPolicy::Always);
+        fs::write(tmp.path().join("a"), b"old").unwrap();
+
+        let mut tx = Transaction::default();
+        tx.write(tmp.path().join("a"), b"new".to_vec());
+        tx.write(tmp.path().join("b"), b"new".to_vec());
+        tx.remove(tmp.path().join("missing"));
+        journal.commit(tx).unwrap();
+
+        assert_eq!(fs::read(tmp.path().join("a")).unwrap(), b"new");
+        assert_eq!(fs::read(tmp.path().join("b")).unwrap(), b"new");
+        assert!(!journal.path().exists());
+    }
+}
@@ -0,0 +1,1494 @@
+//! Persistence which migrates data from one storage backend to another while the node is running.
+//!
+//! A migrating persistence wraps an old and a new backend. Every write goes to both backends, so
+//! that once the new backend has caught up with the data the old backend had when the migration
+//! started, it stays up to date without the node ever needing to stop. Reads are served by the old
+//! backend until the new backend is caught up and reads are explicitly switched over; after that,
+//! the new backend is the primary and the old backend is written to only as a fallback.
+//!
+//! Catching up happens in the background: data already in the old backend (the anchor leaf,
+//! undecided state, saved proposals and so on) is copied to the new backend. Consensus storage is
+//! garbage collected and only ever holds a few views worth of data, but history such as transaction
+//! statuses and decide events is copied too, so on a node with a long history the copy can take a
+//! while. Writes are not paused during the copy. Every write is an upsert, so copying data which
+//! was also written to both backends in the meantime is harmless, but the copy may overwrite the
+//! newer data with an older version, so data written during the copy is recorded and copied again
+//! afterwards. Writes are only paused while copying the last few of these.
+//!
+//! If a write to the new backend fails while the old backend is still the primary, the new backend
+//! is considered out of sync, and only the data touched by the failed write is copied again.
+//!
+//! Everything the old backend stores is copied except the shutdown checkpoint, which is only ever
+//! read once, on the next startup, and which is taken from both backends at that point.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{ensure, Context};
+use async_lock::RwLock;
+use async_trait::async_trait;
+use clap::Parser;
+use committable::{Commitment, Committable};
+use espresso_types::{
+    parse_duration,
+    traits::NullEventConsumer,
+    v0::traits::{EventConsumer, PersistenceOptions, SequencerPersistence, StateCatchup},
+    BackoffParams, BuilderFeeTotals, DaPointer, DecideEvent, EpochStakeTable, FeeAccount,
+    FeeDeposit, Leaf, NetworkConfig, PubKey, ShutdownCheckpoint, Transaction, TxStatus,
+    UpgradeRecord, ValidatedState, ViewRecord,
+};
+use hotshot_types::{
+    consensus::CommitmentMap,
+    data::{DaProposal, EpochNumber, QuorumProposal, VidDisperseShare},
+    event::{HotShotAction, LeafInfo},
+    message::Proposal,
+    simple_certificate::{QuorumCertificate, UpgradeCertificate},
+    utils::View,
+    vid::VidSchemeType,
+};
+use jf_vid::VidScheme;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use super::DaProfile;
+use crate::{SeqTypes, ViewNumber};
+
+/// The number of logged decide events to copy to the new backend at a time.
+const DECIDE_EVENTS_BATCH_SIZE: usize = 100;
+
+/// The maximum number of deltas to keep track of before copying everything again instead.
+const MAX_DELTAS: usize = 10_000;
+
+/// The maximum number of deltas to copy while writes are paused.
+const MAX_PAUSED_DELTAS: usize = 100;
+
+/// The maximum number of times to copy deltas without pausing writes before pausing them anyway.
+const MAX_DELTA_ROUNDS: usize = 5;
+
+/// Options for migrating between storage backends.
+#[derive(Parser, Clone, Debug)]
+pub struct Config {
+    /// Serve reads from the new storage backend as soon as it has caught up with the old one.
+    ///
+    /// If not set, the new backend is kept in sync with the old one, but the old backend remains
+    /// the source of truth.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_MIGRATION_READ_FROM_NEW", action)]
+    pub read_from_new: bool,
+
+    /// How often to check whether the new storage backend has fallen behind and needs to catch up.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_MIGRATION_SYNC_INTERVAL",
+        value_parser = parse_duration,
+        default_value = "30s"
+    )]
+    pub sync_interval: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+/// Options for a persistence migrating from `Old` to `New`.
+#[derive(Clone, Debug)]
+pub struct Options<Old, New> {
+    old: Old,
+    new: New,
+    config: Config,
+}
+
+impl<Old, New> Options<Old, New> {
+    pub fn new(old: Old, new: New, config: Config) -> Self {
+        Self { old, new, config }
+    }
+
+    pub fn old(&self) -> &Old {
+        &self.old
+    }
+
+    pub fn new_backend(&self) -> &New {
+        &self.new
+    }
+}
+
+#[async_trait]
+impl<Old: PersistenceOptions, New: PersistenceOptions> PersistenceOptions for Options<Old, New> {
+    type Persistence = Persistence<Old::Persistence, New::Persistence>;
+
+    async fn create(self) -> anyhow::Result<Self::Persistence> {
+        let old = self.old.create().await.context("opening old storage")?;
+        let new = self.new.create().await.context("opening new storage")?;
+        let persistence = Persistence::new(old, new);
+        tokio::spawn(persistence.clone().sync_loop(self.config));
+        Ok(persistence)
+    }
+
+    async fn reset(self) -> anyhow::Result<()> {
+        self.old.reset().await.context("resetting old storage")?;
+        self.new.reset().await.context("resetting new storage")
+    }
+
+    async fn create_catchup_provider(
+        self,
+        backoff: BackoffParams,
+    ) -> anyhow::Result<Arc<dyn StateCatchup>> {
+        // The old backend is kept up to date for the duration of the migration, so it can always
+        // serve catchup.
+        self.old.create_catchup_provider(backoff).await
+    }
+}
+
+impl<Old: DaProfile, New: DaProfile> DaProfile for Options<Old, New> {
+    fn da_only(self) -> Self {
+        Self {
+            old: self.old.da_only(),
+            new: self.new.da_only(),
+            config: self.config,
+        }
+    }
+}
+
+/// Progress of a migration between storage backends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Progress {
+    /// Whether the new backend has caught up with the old one.
+    pub synced: bool,
+    /// Whether reads are being served by the new backend.
+    pub reading_from_new: bool,
+    /// The number of saved views copied to the new backend in the current catchup.
+    pub views_copied: usize,
+    /// The number of saved views to copy to the new backend in the current catchup.
+    pub views_total: usize,
+    /// The number of writes which succeeded on the primary backend but failed on the secondary.
+    pub failed_writes: usize,
+}
+
+/// Data which the new backend may be missing, or may have an outdated copy of.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Delta {
+    Config,
+    LatestActedView,
+    Anchor,
+    UndecidedState,
+    View(ViewNumber),
+    UpgradeCertificate,
+    TxStatus(Commitment<Transaction>),
+    Mempool,
+    StakeTable(EpochNumber),
+    DaPointer(u64),
+    Upgrades,
+    Libp2pPeers,
+    BannedPeers,
+    FeeDeposits,
+    BuilderFees(FeeAccount),
+    ViewRecord(u64),
+    PruneViewRecords(u64),
+    DecideEvent(u64),
+    PruneDecideEvents(u64),
+}
+
+impl Delta {
+    /// Deltas are copied in increasing order of rank.
+    ///
+    /// The anchor leaf is copied before any view, since storing it garbage collects older views,
+    /// and records are pruned after everything else is copied.
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Config | Self::LatestActedView => 0,
+            Self::Anchor => 1,
+            Self::PruneViewRecords(_) | Self::PruneDecideEvents(_) => 3,
+            _ => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Migration {
+    /// Held for reading by each write, and for writing while copying the last few deltas.
+    lock: RwLock<()>,
+    synced: AtomicBool,
+    /// Whether everything has to be copied to the new backend, rather than just the deltas.
+    copy_all: AtomicBool,
+    /// Whether a copy is in progress, and may overwrite concurrent writes with older data.
+    copying: AtomicBool,
+    /// Data written to the old backend which has yet to be copied to the new backend.
+    deltas: Mutex<HashSet<Delta>>,
+    read_from_new: AtomicBool,
+    views_copied: AtomicUsize,
+    views_total: AtomicUsize,
+    failed_writes: AtomicUsize,
+}
+
+impl Default for Migration {
+    fn default() -> Self {
+        Self {
+            lock: Default::default(),
+            synced: Default::default(),
+            copy_all: AtomicBool::new(true),
+            copying: Default::default(),
+            deltas: Default::default(),
+            read_from_new: Default::default(),
+            views_copied: Default::default(),
+            views_total: Default::default(),
+            failed_writes: Default::default(),
+        }
+    }
+}
+
+impl Migration {
+    /// Remember that `deltas` have to be copied to the new backend.
+    ///
+    /// If too many deltas pile up, they are forgotten, and everything is copied again instead.
+    fn record(&self, deltas: impl IntoIterator<Item = Delta>) {
+        let mut pending = self.deltas.lock();
+        pending.extend(deltas);
+        if pending.len() > MAX_DELTAS {
+            pending.clear();
+            self.copy_all.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn take_deltas(&self) -> Vec<Delta> {
+        let mut deltas = self.deltas.lock().drain().collect::<Vec<_>>();
+        deltas.sort_by_key(Delta::rank);
+        deltas
+    }
+}
+
+/// Persistence which writes to two backends while migrating from `Old` to `New`.
+#[derive(Debug)]
+pub struct Persistence<Old, New> {
+    old: Arc<Old>,
+    new: Arc<New>,
+    migration: Arc<Migration>,
+}
+
+impl<Old, New> Clone for Persistence<Old, New> {
+    fn clone(&self) -> Self {
+        Self {
+            old: self.old.clone(),
+            new: self.new.clone(),
+            migration: self.migration.clone(),
+        }
+    }
+}
+
+impl<Old: SequencerPersistence, New: SequencerPersistence> Persistence<Old, New> {
+    pub fn new(old: Old, new: New) -> Self {
+        Self {
+            old: Arc::new(old),
+            new: Arc::new(new),
+            migration: Default::default(),
+        }
+    }
+
+    /// Report the progress of the migration.
+    pub fn progress(&self) -> Progress {
+        Progress {
+            synced: self.migration.synced.load(Ordering::SeqCst),
+            reading_from_new: self.reading_from_new(),
+            views_copied: self.migration.views_copied.load(Ordering::SeqCst),
+            views_total: self.migration.views_total.load(Ordering::SeqCst),
+            failed_writes: self.migration.failed_writes.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Serve reads from the new backend from now on.
+    ///
+    /// Fails if the new backend has not caught up with the old one.
+    pub fn switch_reads(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.migration.synced.load(Ordering::SeqCst),
+            "new storage has not caught up with old storage"
+        );
+        self.migration.read_from_new.store(true, Ordering::SeqCst);
+        tracing::warn!("storage migration: serving reads from new storage");
+        Ok(())
+    }
+
+    fn reading_from_new(&self) -> bool {
+        self.migration.read_from_new.load(Ordering::SeqCst)
+    }
+
+    /// Catch the new backend up with the old backend.
+    ///
+    /// The first time, everything in the old backend is copied. After that, only the data missed
+    /// by failed writes is. Writes continue while copying, and are recorded as deltas to copy again
+    /// afterwards, since the copy may have overwritten them with older data. Writes are only paused
+    /// to copy the last few deltas.
+    pub async fn sync(&self) -> anyhow::Result<()> {
+        self.migration.synced.store(false, Ordering::SeqCst);
+        self.migration.copying.store(true, Ordering::SeqCst);
+        let res = self.catch_up().await;
+        self.migration.copying.store(false, Ordering::SeqCst);
+        res
+    }
+
+    async fn catch_up(&self) -> anyhow::Result<()> {
+        if self.migration.copy_all.swap(false, Ordering::SeqCst) {
+            self.migration.deltas.lock().clear();
+            if let Err(err) = self.copy_all().await {
+                self.migration.copy_all.store(true, Ordering::SeqCst);
+                return Err(err);
+            }
+        }
+
+        // Copy data written in the meantime until little enough is left to copy with writes
+        // paused.
+        for _ in 0..MAX_DELTA_ROUNDS {
+            let deltas = self.migration.take_deltas();
+            if deltas.len() <= MAX_PAUSED_DELTAS {
+                self.migration.record(deltas);
+                break;
+            }
+            self.copy_deltas(deltas).await?;
+        }
+
+        let _guard = self.migration.lock.write().await;
+        ensure!(
+            !self.migration.copy_all.load(Ordering::SeqCst),
+            "too many writes to keep track of while copying, copying everything again"
+        );
+        self.copy_deltas(self.migration.take_deltas()).await?;
+        self.migration.copying.store(false, Ordering::SeqCst);
+        self.migration.failed_writes.store(0, Ordering::SeqCst);
+        self.migration.synced.store(true, Ordering::SeqCst);
+        tracing::info!(progress = ?self.progress(), "storage migration: new storage caught up");
+        Ok(())
+    }
+
+    /// Copy `deltas` in order, keeping those not copied if one fails.
+    async fn copy_deltas(&self, deltas: Vec<Delta>) -> anyhow::Result<()> {
+        for (i, delta) in deltas.iter().enumerate() {
+            if let Err(err) = self.copy(delta).await {
+                self.migration.record(deltas[i..].iter().cloned());
+                return Err(err.context(format!("copying {delta:?}")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy all data in the old backend to the new backend.
+    ///
+    /// Every write is an upsert, so data which was already copied, or written to both backends in
+    /// the meantime, can safely be copied again.
+    async fn copy_all(&self) -> anyhow::Result<()> {
+        for delta in [
+            Delta::Config,
+            Delta::LatestActedView,
+            Delta::Anchor,
+            Delta::UndecidedState,
+        ] {
+            self.copy(&delta)
+                .await
+                .with_context(|| format!("copying {delta:?}"))?;
+        }
+
+        let proposals = self
+            .old
+            .load_quorum_proposals()
+            .await
+            .context("loading quorum proposals")?;
+        self.migration.views_copied.store(0, Ordering::SeqCst);
+        self.migration
+            .views_total
+            .store(proposals.len(), Ordering::SeqCst);
+        for (view, proposal) in proposals {
+            self.copy_view(view, Some(&proposal))
+                .await
+                .with_context(|| format!("copying view {view:?}"))?;
+            let copied = self.migration.views_copied.fetch_add(1, Ordering::SeqCst) + 1;
+            tracing::info!(?view, copied, "storage migration: copied view");
+        }
+
+        for delta in [
+            Delta::UpgradeCertificate,
+            Delta::Mempool,
+            Delta::Upgrades,
+            Delta::BannedPeers,
+            Delta::FeeDeposits,
+            Delta::Libp2pPeers,
+        ] {
+            self.copy(&delta)
+                .await
+                .with_context(|| format!("copying {delta:?}"))?;
+        }
+        let builder_fees = self
+            .old
+            .load_all_builder_fees()
+            .await
+            .context("loading builder fees")?;
+        self.new
+            .store_builder_fees(&builder_fees)
+            .await
+            .context("copying builder fees")?;
+        for (hash, status) in self
+            .old
+            .load_all_tx_statuses()
+            .await
+            .context("loading tx statuses")?
+        {
+            self.new
+                .store_tx_status(hash, status)
+                .await
+                .context("copying tx statuses")?;
+        }
+        for stake_table in self
+            .old
+            .load_all_stake_tables()
+            .await
+            .context("loading stake tables")?
+        {
+            self.new
+                .store_stake_table(&stake_table)
+                .await
+                .context("copying stake tables")?;
+        }
+        for pointer in self
+            .old
+            .load_all_da_pointers()
+            .await
+            .context("loading DA pointers")?
+        {
+            self.new
+                .store_da_pointer(&pointer)
+                .await
+                .context("copying DA pointers")?;
+        }
+        // View numbers are stored as signed integers by some backends.
+        for record in self
+            .old
+            .load_view_records(0, i64::MAX as u64)
+            .await
+            .context("loading view records")?
+        {
+            self.new
+                .store_view_record(&record)
+                .await
+                .context("copying view records")?;
+        }
+        let mut from = 0;
+        loop {
+            let events = self
+                .old
+                .load_decide_events(from, DECIDE_EVENTS_BATCH_SIZE)
+                .await
+                .context("loading decide events")?;
+            let Some(last) = events.last() else {
+                break;
+            };
+            from = last.height + 1;
+            self.new
+                .store_decide_events(&events)
+                .await
+                .context("copying decide events")?;
+        }
+        Ok(())
+    }
+
+    /// Copy the current contents of the old backend for `delta` to the new backend.
+    async fn copy(&self, delta: &Delta) -> anyhow::Result<()> {
+        match delta {
+            Delta::Config => {
+                if let Some(cfg) = self.old.load_config().await? {
+                    self.new.save_config(&cfg).await?;
+                }
+            }
+            Delta::LatestActedView => {
+                if let Some(view) = self.old.load_latest_acted_view().await? {
+                    self.new.record_action(view, HotShotAction::Vote).await?;
+                }
+            }
+            Delta::Anchor => {
+                if let Some((leaf, qc)) = self.old.load_anchor_leaf().await? {
+                    let info = LeafInfo {
+                        leaf,
+                        vid_share: None,
+                        state: Default::default(),
+                        delta: None,
+                    };
+                    self.new
+                        .append_decided_leaves(
+                            info.leaf.view_number(),
+                            [(&info, qc)],
+                            &NullEventConsumer,
+                        )
+                        .await?;
+                }
+                if let Some((view, state)) = self.old.load_anchor_state().await? {
+                    self.new.store_anchor_state(view, &state).await?;
+                }
+            }
+            Delta::UndecidedState => {
+                if let Some((leaves, state)) = self.old.load_undecided_state().await? {
+                    self.new.update_undecided_state(leaves, state).await?;
+                }
+            }
+            Delta::View(view) => {
+                let proposal = self.old.load_quorum_proposals().await?.remove(view);
+                self.copy_view(*view, proposal.as_ref()).await?;
+            }
+            Delta::UpgradeCertificate => {
+                let cert = self.old.load_upgrade_certificate().await?;
+                self.new.store_upgrade_certificate(cert).await?;
+            }
+            Delta::TxStatus(hash) => {
+                if let Some(status) = self.old.load_tx_status(*hash).await? {
+                    self.new.store_tx_status(*hash, status).await?;
+                }
+            }
+            Delta::Mempool => {
+                // Make the new mempool match the old one, including removals.
+                let txs = self.old.load_mempool().await?;
+                let hashes = txs.iter().map(|tx| tx.commit()).collect::<HashSet<_>>();
+                let removed = self
+                    .new
+                    .load_mempool()
+                    .await?
+                    .into_iter()
+                    .map(|tx| tx.commit())
+                    .filter(|hash| !hashes.contains(hash))
+                    .collect::<Vec<_>>();
+                self.new.remove_mempool_txs(&removed).await?;
+                self.new.append_mempool_txs(&txs).await?;
+            }
+            Delta::StakeTable(epoch) => {
+                if let Some(stake_table) = self.old.load_stake_table(*epoch).await? {
+                    self.new.store_stake_table(&stake_table).await?;
+                }
+            }
+            Delta::DaPointer(height) => {
+                if let Some(pointer) = self.old.load_da_pointer(*height).await? {
+                    self.new.store_da_pointer(&pointer).await?;
+                }
+            }
+            Delta::Upgrades => {
+                for upgrade in self.old.load_upgrades().await? {
+                    self.new.store_upgrade(&upgrade).await?;
+                }
+            }
+            Delta::Libp2pPeers => {
+                let peers = self.old.load_libp2p_peers().await?;
+                if !peers.is_empty() {
+                    self.new.store_libp2p_peers(&peers).await?;
+                }
+            }
+            Delta::BannedPeers => {
+                let banned = self.old.load_banned_peers().await?;
+                self.new.store_banned_peers(&banned).await?;
+            }
+            Delta::FeeDeposits => {
+                if let Some(l1_block) = self.old.load_fee_deposits_l1_block().await? {
+                    let deposits = self.old.load_all_fee_deposits().await?;
+                    self.new.store_fee_deposits(&deposits, l1_block).await?;
+                }
+            }
+            Delta::BuilderFees(account) => {
+                if let Some(totals) = self.old.load_builder_fees(*account).await? {
+                    self.new.store_builder_fees(&[totals]).await?;
+                }
+            }
+            Delta::ViewRecord(view) => {
+                for record in self.old.load_view_records(*view, view + 1).await? {
+                    self.new.store_view_record(&record).await?;
+                }
+            }
+            Delta::PruneViewRecords(view) => self.new.prune_view_records(*view).await?,
+            Delta::DecideEvent(height) => {
+                let events = self
+                    .old
+                    .load_decide_events(*height, 1)
+                    .await?
+                    .into_iter()
+                    .filter(|event| event.height == *height)
+                    .collect::<Vec<_>>();
+                self.new.store_decide_events(&events).await?;
+            }
+            Delta::PruneDecideEvents(height) => self.new.prune_decide_events(*height).await?,
+        }
+        Ok(())
+    }
+
+    async fn copy_view(
+        &self,
+        view: ViewNumber,
+        proposal: Option<&Proposal<SeqTypes, QuorumProposal<SeqTypes>>>,
+    ) -> anyhow::Result<()> {
+        if let Some(proposal) = proposal {
+            self.new.append_quorum_proposal(proposal).await?;
+        }
+        let share = self.old.load_vid_share(view).await?;
+        if let Some(share) = &share {
+            self.new.append_vid(share).await?;
+        }
+        if let Some(da) = self.old.load_da_proposal(view).await? {
+            let vid_commit = match (proposal, &share) {
+                (Some(proposal), _) => proposal.data.block_header.payload_commitment(),
+                (None, Some(share)) => share.data.payload_commitment,
+                (None, None) => {
+                    tracing::warn!(
+                        ?view,
+                        "storage migration: not copying DA proposal without a payload commitment"
+                    );
+                    return Ok(());
+                }
+            };
+            self.new.append_da(&da, vid_commit).await?;
+        }
+        Ok(())
+    }
+
+    /// Keep the new backend caught up, switching reads over once it is if so configured.
+    async fn sync_loop(self, config: Config) {
+        loop {
+            if !self.migration.synced.load(Ordering::SeqCst) {
+                match self.sync().await {
+                    Ok(()) if config.read_from_new && !self.reading_from_new() => {
+                        if let Err(err) = self.switch_reads() {
+                            tracing::warn!("storage migration: failed to switch reads: {err:#}");
+                        }
+                    }
+                    Ok(()) => {}
+                    Err(err) => {
+                        tracing::warn!("storage migration: failed to catch up new storage: {err:#}")
+                    }
+                }
+            }
+            sleep(config.sync_interval).await;
+        }
+    }
+
+    /// Apply a write to both backends.
+    ///
+    /// The result of the write to the primary backend is returned. A failure on the secondary
+    /// backend is only logged; if the secondary is the new backend, it is marked as out of sync,
+    /// and `deltas`, the data affected by the write, are copied to it when it next catches up.
+    async fn write<T>(
+        &self,
+        old: impl Future<Output = anyhow::Result<T>>,
+        new: impl Future<Output = anyhow::Result<T>>,
+        deltas: impl IntoIterator<Item = Delta>,
+    ) -> anyhow::Result<T> {
+        let _guard = self.migration.lock.read().await;
+        let (old, new) = futures::join!(old, new);
+        let (primary, secondary) = if self.reading_from_new() {
+            (new, old)
+        } else {
+            (old, new)
+        };
+        let mut missed = false;
+        if let Err(err) = secondary {
+            self.migration.failed_writes.fetch_add(1, Ordering::SeqCst);
+            if self.reading_from_new() {
+                tracing::warn!("storage migration: write to old storage failed: {err:#}");
+            } else {
+                missed = true;
+                self.migration.synced.store(false, Ordering::SeqCst);
+                tracing::warn!("storage migration: write to new storage failed: {err:#}");
+            }
+        }
+        // A copy in progress may overwrite this write with older data, so it has to copy the same
+        // data again once it is done.
+        if missed || self.migration.copying.load(Ordering::SeqCst) {
+            self.migration.record(deltas);
+        }
+        primary
+    }
+}
+
+/// Dispatch a read to whichever backend is currently the primary.
+macro_rules! read {
+    ($self:ident.$method:ident($($arg:expr),*)) => {
+        if $self.reading_from_new() {
+            $self.new.$method($($arg),*).await
+        } else {
+            $self.old.$method($($arg),*).await
+        }
+    };
+}
+
+#[async_trait]
+impl<Old: SequencerPersistence, New: SequencerPersistence> SequencerPersistence
+    for Persistence<Old, New>
+{
+    async fn load_config(&self) -> anyhow::Result<Option<NetworkConfig>> {
+        read!(self.load_config())
+    }
+
+    async fn save_config(&self, cfg: &NetworkConfig) -> anyhow::Result<()> {
+        self.write(
+            self.old.save_config(cfg),
+            self.new.save_config(cfg),
+            [Delta::Config],
+        )
+        .await
+    }
+
+    async fn load_latest_acted_view(&self) -> anyhow::Result<Option<ViewNumber>> {
+        read!(self.load_latest_acted_view())
+    }
+
+    async fn load_undecided_state(
+        &self,
+    ) -> anyhow::Result<Option<(CommitmentMap<Leaf>, BTreeMap<ViewNumber, View<SeqTypes>>)>> {
+        read!(self.load_undecided_state())
+    }
+
+    async fn load_quorum_proposals(
+        &self,
+    ) -> anyhow::Result<BTreeMap<ViewNumber, Proposal<SeqTypes, QuorumProposal<SeqTypes>>>> {
+        read!(self.load_quorum_proposals())
+    }
+
+    async fn load_quorum_proposal(
+        &self,
+        view: ViewNumber,
+    ) -> anyhow::Result<Proposal<SeqTypes, QuorumProposal<SeqTypes>>> {
+        read!(self.load_quorum_proposal(view))
+    }
+
+    async fn load_vid_share(
+        &self,
+        view: ViewNumber,
+    ) -> anyhow::Result<Option<Proposal<SeqTypes, VidDisperseShare<SeqTypes>>>> {
+        read!(self.load_vid_share(view))
+    }
+
+    async fn load_da_proposal(
+        &self,
+        view: ViewNumber,
+    ) -> anyhow::Result<Option<Proposal<SeqTypes, DaProposal<SeqTypes>>>> {
+        read!(self.load_da_proposal(view))
+    }
+
+    async fn load_upgrade_certificate(
+        &self,
+    ) -> anyhow::Result<Option<UpgradeCertificate<SeqTypes>>> {
+        read!(self.load_upgrade_certificate())
+    }
+
+    async fn append_decided_leaves(
+        &self,
+        decided_view: ViewNumber,
+        leaf_chain: impl IntoIterator<Item = (&LeafInfo<SeqTypes>, QuorumCertificate<SeqTypes>)> + Send,
+        consumer: &(impl EventConsumer + 'static),
+    ) -> anyhow::Result<()> {
+        let leaf_chain = leaf_chain.into_iter().collect::<Vec<_>>();
+        let chain = || leaf_chain.iter().map(|(info, qc)| (*info, qc.clone()));
+
+        // Only the primary backend feeds decide events to the consumer, so that each leaf is
+        // processed once.
+        if self.reading_from_new() {
+            self.write(
+                self.old
+                    .append_decided_leaves(decided_view, chain(), &NullEventConsumer),
+                self.new
+                    .append_decided_leaves(decided_view, chain(), consumer),
+                [Delta::Anchor],
+            )
+            .await
+        } else {
+            self.write(
+                self.old
+                    .append_decided_leaves(decided_view, chain(), consumer),
+                self.new
+                    .append_decided_leaves(decided_view, chain(), &NullEventConsumer),
+                [Delta::Anchor],
+            )
+            .await
+        }
+    }
+
+    async fn load_anchor_leaf(
+        &self,
+    ) -> anyhow::Result<Option<(Leaf, QuorumCertificate<SeqTypes>)>> {
+        read!(self.load_anchor_leaf())
+    }
+
+    async fn append_vid(
+        &self,
+        proposal: &Proposal<SeqTypes, VidDisperseShare<SeqTypes>>,
+    ) -> anyhow::Result<()> {
+        self.write(
+            self.old.append_vid(proposal),
+            self.new.append_vid(proposal),
+            [Delta::View(proposal.data.view_number)],
+        )
+        .await
+    }
+
+    async fn append_da(
+        &self,
+        proposal: &Proposal<SeqTypes, DaProposal<SeqTypes>>,
+        vid_commit: <VidSchemeType as VidScheme>::Commit,
+    ) -> anyhow::Result<()> {
+        self.write(
+            self.old.append_da(proposal, vid_commit),
+            self.new.append_da(proposal, vid_commit),
+            [Delta::View(proposal.data.view_number)],
+        )
+        .await
+    }
+
+    async fn record_action(&self, view: ViewNumber, action: HotShotAction) -> anyhow::Result<()> {
+        self.write(
+            self.old.record_action(view, action),
+            self.new.record_action(view, action),
+            [Delta::LatestActedView],
+        )
+        .await
+    }
+
+    async fn update_undecided_state(
+        &self,
+        leaves: CommitmentMap<Leaf>,
+        state: BTreeMap<ViewNumber, View<SeqTypes>>,
+    ) -> anyhow::Result<()> {
+        self.write(
+            self.old
+                .update_undecided_state(leaves.clone(), state.clone()),
+            self.new.update_undecided_state(leaves, state),
+            [Delta::UndecidedState],
+        )
+        .await
+    }
+
+    async fn append_quorum_proposal(
+        &self,
+        proposal: &Proposal<SeqTypes, QuorumProposal<SeqTypes>>,
+    ) -> anyhow::Result<()> {
+        self.write(
+            self.old.append_quorum_proposal(proposal),
+            self.new.append_quorum_proposal(proposal),
+            [Delta::View(proposal.data.view_number)],
+        )
+        .await
+    }
+
+    async fn store_upgrade_certificate(
+        &self,
+        decided_upgrade_certificate: Option<UpgradeCertificate<SeqTypes>>,
+    ) -> anyhow::Result<()> {
+        self.write(
+            self.old
+                .store_upgrade_certificate(decided_upgrade_certificate.clone()),
+            self.new
+                .store_upgrade_certificate(decided_upgrade_certificate),
+            [Delta::UpgradeCertificate],
+        )
+        .await
+    }
+
+    async fn store_tx_status(
+        &self,
+        hash: Commitment<Transaction>,
+        status: TxStatus,
+    ) -> anyhow::Result<()> {
+        self.write(
+            self.old.store_tx_status(hash, status.clone()),
+            self.new.store_tx_status(hash, status),
+            [Delta::TxStatus(hash)],
+        )
+        .await
+    }
+
+    async fn load_tx_status(
+        &self,
+        hash: Commitment<Transaction>,
+    ) -> anyhow::Result<Option<TxStatus>> {
+        read!(self.load_tx_status(hash))
+    }
+
+    async fn load_all_tx_statuses(
+        &self,
+    ) -> anyhow::Result<Vec<(Commitment<Transaction>, TxStatus)>> {
+        read!(self.load_all_tx_statuses())
+    }
+
+    async fn append_mempool_txs(&self, txs: &[Transaction]) -> anyhow::Result<()> {
+        self.write(
+            self.old.append_mempool_txs(txs),
+            self.new.append_mempool_txs(txs),
+            [Delta::Mempool],
+        )
+        .await
+    }
+
+    async fn remove_mempool_txs(&self, hashes: &[Commitment<Transaction>]) -> anyhow::Result<()> {
+        self.write(
+            self.old.remove_mempool_txs(hashes),
+            self.new.remove_mempool_txs(hashes),
+            [Delta::Mempool],
+        )
+        .await
+    }
+
+    async fn load_mempool(&self) -> anyhow::Result<Vec<Transaction>> {
+        read!(self.load_mempool())
+    }
+
+    async fn store_stake_table(&self, stake_table: &EpochStakeTable) -> anyhow::Result<()> {
+        self.write(
+            self.old.store_stake_table(stake_table),
+            self.new.store_stake_table(stake_table),
+            [Delta::StakeTable(stake_table.epoch)],
+        )
+        .await
+    }
+
+    async fn load_stake_table(
+        &self,
+        epoch: EpochNumber,
+    ) -> anyhow::Result<Option<EpochStakeTable>> {
+        read!(self.load_stake_table(epoch))
+    }
+
+    async fn load_all_stake_tables(&self) -> anyhow::Result<Vec<EpochStakeTable>> {
+        read!(self.load_all_stake_tables())
+    }
+
+    async fn store_da_pointer(&self, pointer: &DaPointer) -> anyhow::Result<()> {
+        self.write(
+            self.old.store_da_pointer(pointer),
+            self.new.store_da_pointer(pointer),
+            [Delta::DaPointer(pointer.height)],
+        )
+        .await
+    }
+
+    async fn load_da_pointer(&self, height: u64) -> anyhow::Result<Option<DaPointer>> {
+        read!(self.load_da_pointer(height))
+    }
+
+    async fn load_all_da_pointers(&self) -> anyhow::Result<Vec<DaPointer>> {
+        read!(self.load_all_da_pointers())
+    }
+
+    async fn store_upgrade(&self, upgrade: &UpgradeRecord) -> anyhow::Result<()> {
+        self.write(
+            self.old.store_upgrade(upgrade),
+            self.new.store_upgrade(upgrade),
+            [Delta::Upgrades],
+        )
+        .await
+    }
+
+    async fn load_upgrades(&self) -> anyhow::Result<Vec<UpgradeRecord>> {
+        read!(self.load_upgrades())
+    }
+
+    async fn store_libp2p_peers(&self, peers: &[String]) -> anyhow::Result<()> {
+        self.write(
+            self.old.store_libp2p_peers(peers),
+            self.new.store_libp2p_peers(peers),
+            [Delta::Libp2pPeers],
+        )
+        .await
+    }
+
+    async fn load_libp2p_peers(&self) -> anyhow::Result<Vec<String>> {
+        read!(self.load_libp2p_peers())
+    }
+
+    async fn store_fee_deposits(
+        &self,
+        deposits: &[FeeDeposit],
+        l1_block: u64,
+    ) -> anyhow::Result<()> {
+        self.write(
+            self.old.store_fee_deposits(deposits, l1_block),
+            self.new.store_fee_deposits(deposits, l1_block),
+            [Delta::FeeDeposits],
+        )
+        .await
+    }
+
+    async fn load_fee_deposits_l1_block(&self) -> anyhow::Result<Option<u64>> {
+        read!(self.load_fee_deposits_l1_block())
+    }
+
+    async fn load_fee_deposits(
+        &self,
+        account: FeeAccount,
+        offset: u64,
+        limit: u64,
+    ) -> anyhow::Result<Vec<FeeDeposit>> {
+        read!(self.load_fee_deposits(account, offset, limit))
+    }
+
+    async fn load_all_fee_deposits(&self) -> anyhow::Result<Vec<FeeDeposit>> {
+        read!(self.load_all_fee_deposits())
+    }
+
+    async fn store_builder_fees(&self, totals: &[BuilderFeeTotals]) -> anyhow::Result<()> {
+        self.write(
+            self.old.store_builder_fees(totals),
+            self.new.store_builder_fees(totals),
+            totals
+                .iter()
+                .map(|totals| Delta::BuilderFees(totals.account)),
+        )
+        .await
+    }
+
+    async fn load_builder_fees(
+        &self,
+        account: FeeAccount,
+    ) -> anyhow::Result<Option<BuilderFeeTotals>> {
+        read!(self.load_builder_fees(account))
+    }
+
+    async fn load_all_builder_fees(&self) -> anyhow::Result<Vec<BuilderFeeTotals>> {
+        read!(self.load_all_builder_fees())
+    }
+
+    async fn store_shutdown_checkpoint(
+        &self,
+        checkpoint: &ShutdownCheckpoint,
+    ) -> anyhow::Result<()> {
+        self.write(
+            self.old.store_shutdown_checkpoint(checkpoint),
+            self.new.store_shutdown_checkpoint(checkpoint),
+            [],
+        )
+        .await
+    }
+
+    async fn take_shutdown_checkpoint(&self) -> anyhow::Result<Option<ShutdownCheckpoint>> {
+        // Take the checkpoint from both backends, so that neither keeps a stale one. The
+        // checkpoint only applies if both backends recorded it.
+        let (old, new) = futures::join!(
+            self.old.take_shutdown_checkpoint(),
+            self.new.take_shutdown_checkpoint()
+        );
+        match (old?, new?) {
+            (Some(old), Some(new)) if old == new => Ok(Some(old)),
+            _ => Ok(None),
+        }
+    }
+
+    async fn store_view_record(&self, record: &ViewRecord) -> anyhow::Result<()> {
+        self.write(
+            self.old.store_view_record(record),
+            self.new.store_view_record(record),
+            [Delta::ViewRecord(record.view)],
+        )
+        .await
+    }
+
+    async fn load_view_records(&self, from: u64, until: u64) -> anyhow::Result<Vec<ViewRecord>> {
+        read!(self.load_view_records(from, until))
+    }
+
+    async fn prune_view_records(&self, view: u64) -> anyhow::Result<()> {
+        self.write(
+            self.old.prune_view_records(view),
+            self.new.prune_view_records(view),
+            [Delta::PruneViewRecords(view)],
+        )
+        .await
+    }
+
+    async fn store_decide_events(&self, events: &[DecideEvent]) -> anyhow::Result<()> {
+        self.write(
+            self.old.store_decide_events(events),
+            self.new.store_decide_events(events),
+            events.iter().map(|event| Delta::DecideEvent(event.height)),
+        )
+        .await
+    }
+
+    async fn load_decide_events(
+        &self,
+        from: u64,
+        limit: usize,
+    ) -> anyhow::Result<Vec<DecideEvent>> {
+        read!(self.load_decide_events(from, limit))
+    }
+
+    async fn prune_decide_events(&self, height: u64) -> anyhow::Result<()> {
+        self.write(
+            self.old.prune_decide_events(height),
+            self.new.prune_decide_events(height),
+            [Delta::PruneDecideEvents(height)],
+        )
+        .await
+    }
+
+    async fn store_anchor_state(
+        &self,
+        view: ViewNumber,
+        state: &ValidatedState,
+    ) -> anyhow::Result<()> {
+        self.write(
+            self.old.store_anchor_state(view, state),
+            self.new.store_anchor_state(view, state),
+            [Delta::Anchor],
+        )
+        .await
+    }
+
+    async fn load_anchor_state(&self) -> anyhow::Result<Option<(ViewNumber, ValidatedState)>> {
+        read!(self.load_anchor_state())
+    }
+
+    async fn store_banned_peers(&self, peers: &[PubKey]) -> anyhow::Result<()> {
+        self.write(
+            self.old.store_banned_peers(peers),
+            self.new.store_banned_peers(peers),
+            [Delta::BannedPeers],
+        )
+        .await
+    }
+
+    async fn load_banned_peers(&self) -> anyhow::Result<Vec<PubKey>> {
+        read!(self.load_banned_peers())
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::{super::testing::TestablePersistence, *};
+
+    #[async_trait]
+    impl<Old, New> TestablePersistence for Persistence<Old, New>
+    where
+        Old: TestablePersistence,
+        New: TestablePersistence,
+        Old::Storage: Send + Sync,
+        New::Storage: Send + Sync,
+    {
+        type Storage = (Old::Storage, New::Storage);
+
+        async fn tmp_storage() -> Self::Storage {
+            (Old::tmp_storage().await, New::tmp_storage().await)
+        }
+
+        async fn connect(storage: &Self::Storage) -> Self {
+            Self::new(
+                Old::connect(&storage.0).await,
+                New::connect(&storage.1).await,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod generic_tests {
+    use super::{super::fs, super::persistence_tests, Persistence};
+    // For some reason this is the only way to import the macro defined in another module of this
+    // crate.
+    use crate::*;
+
+    type FsToFs = Persistence<fs::Persistence, fs::Persistence>;
+
+    instantiate_persistence_tests!(FsToFs);
+}
+
+#[cfg(test)]
+mod test {
+    use committable::Committable;
+    use espresso_types::{NodeState, UpgradeStatus, ValidatedState};
+    use ethers::types::{Address, H256};
+    use hotshot_example_types::node_types::TestVersions;
+    use hotshot_types::traits::{node_implementation::ConsensusTime, signature_key::SignatureKey};
+    use tempfile::TempDir;
+    use vbs::version::Version;
+
+    use super::{super::fs, *};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_migrate_and_switch_reads() {
+        let old_dir = TempDir::new().unwrap();
+        let new_dir = TempDir::new().unwrap();
+        let old = fs::Options::new(old_dir.path().into())
+            .create()
+            .await
+            .unwrap();
+
+        // Populate the old storage before the migration starts.
+        let leaf = Leaf::genesis(&ValidatedState::default(), &NodeState::mock()).await;
+        let qc = QuorumCertificate::genesis::<TestVersions>(
+            &ValidatedState::default(),
+            &NodeState::mock(),
+        )
+        .await;
+        let info = LeafInfo {
+            leaf: leaf.clone(),
+            vid_share: None,
+            state: Default::default(),
+            delta: None,
+        };
+        old.append_decided_leaves(
+            leaf.view_number(),
+            [(&info, qc.clone())],
+            &NullEventConsumer,
+        )
+        .await
+        .unwrap();
+        old.record_action(ViewNumber::new(1), HotShotAction::Vote)
+            .await
+            .unwrap();
+
+        let new = fs::Options::new(new_dir.path().into())
+            .create()
+            .await
+            .unwrap();
+        let storage = Persistence::new(old, new);
+
+        // Reads cannot be switched until the new storage has caught up.
+        assert!(!storage.progress().synced);
+        storage.switch_reads().unwrap_err();
+
+        storage.sync().await.unwrap();
+        let progress = storage.progress();
+        assert!(progress.synced);
+        assert_eq!(progress.views_copied, progress.views_total);
+
+        // Writes after the copy go to both backends.
+        storage
+            .record_action(ViewNumber::new(2), HotShotAction::Vote)
+            .await
+            .unwrap();
+
+        storage.switch_reads().unwrap();
+        assert!(storage.progress().reading_from_new);
+        assert_eq!(
+            storage.load_anchor_leaf().await.unwrap().unwrap(),
+            (leaf, qc)
+        );
+        assert_eq!(
+            storage.load_latest_acted_view().await.unwrap(),
+            Some(ViewNumber::new(2))
+        );
+        assert_eq!(
+            storage.new.load_latest_acted_view().await.unwrap(),
+            Some(ViewNumber::new(2))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sync_copies_history() {
+        let old_dir = TempDir::new().unwrap();
+        let new_dir = TempDir::new().unwrap();
+        let old = fs::Options::new(old_dir.path().into())
+            .create()
+            .await
+            .unwrap();
+
+        // Populate the old storage with history which is not part of the consensus state.
+        let upgrade = UpgradeRecord {
+            version: Version { major: 0, minor: 2 },
+            status: UpgradeStatus::Activated { view: 5, height: 4 },
+        };
+        old.store_upgrade(&upgrade).await.unwrap();
+        let banned = vec![PubKey::generated_from_seed_indexed([0; 32], 0).0];
+        old.store_banned_peers(&banned).await.unwrap();
+        let deposit = FeeDeposit {
+            account: FeeAccount::from(Address::repeat_byte(1)),
+            amount: 100_u64.into(),
+            l1_block: 5,
+            tx_hash: H256::repeat_byte(5),
+            log_index: 0,
+        };
+        old.store_fee_deposits(&[deposit.clone()], 8).await.unwrap();
+        let mut totals = BuilderFeeTotals::new(deposit.account);
+        totals.add(3, 10_u64.into());
+        old.store_builder_fees(&[totals.clone()]).await.unwrap();
+        let leaf = Leaf::genesis(&ValidatedState::default(), &NodeState::mock()).await;
+        let events = (0..DECIDE_EVENTS_BATCH_SIZE as u64 + 1)
+            .map(|height| DecideEvent {
+                height,
+                view: height,
+                leaf: leaf.clone(),
+            })
+            .collect::<Vec<_>>();
+        old.store_decide_events(&events).await.unwrap();
+        let tx = Transaction::new(1_u32.into(), vec![1, 2, 3]);
+        let status = TxStatus::Sequenced { block: 1, index: 0 };
+        old.store_tx_status(tx.commit(), status.clone())
+            .await
+            .unwrap();
+        let stake_table = EpochStakeTable {
+            epoch: EpochNumber::new(1),
+            nodes: vec![],
+        };
+        old.store_stake_table(&stake_table).await.unwrap();
+        let pointer = DaPointer {
+            height: 1,
+            layer: "celestia".into(),
+            commitment: "0x01".into(),
+            da_height: Some(10),
+        };
+        old.store_da_pointer(&pointer).await.unwrap();
+        let peers = vec!["/ip4/127.0.0.1/tcp/1000".to_string()];
+        old.store_libp2p_peers(&peers).await.unwrap();
+        let record = ViewRecord {
+            view: 3,
+            leader: None,
+            started_at: 3000,
+            proposal_received_at: Some(3100),
+            da_proposal_received_at: None,
+            votes: None,
+            ended_at: None,
+            outcome: None,
+        };
+        old.store_view_record(&record).await.unwrap();
+
+        let new = fs::Options::new(new_dir.path().into())
+            .create()
+            .await
+            .unwrap();
+        let storage = Persistence::new(old, new);
+        storage.sync().await.unwrap();
+        storage.switch_reads().unwrap();
+
+        // All of it is copied to the new storage, which serves reads from now on.
+        assert_eq!(
+            storage.new.load_upgrades().await.unwrap(),
+            [upgrade.clone()]
+        );
+        assert_eq!(storage.load_upgrades().await.unwrap(), [upgrade]);
+        assert_eq!(storage.new.load_banned_peers().await.unwrap(), banned);
+        assert_eq!(storage.load_banned_peers().await.unwrap(), banned);
+        assert_eq!(
+            storage.new.load_fee_deposits_l1_block().await.unwrap(),
+            Some(8)
+        );
+        assert_eq!(
+            storage.new.load_all_fee_deposits().await.unwrap(),
+            [deposit.clone()]
+        );
+        assert_eq!(
+            storage
+                .load_fee_deposits(deposit.account, 0, 10)
+                .await
+                .unwrap(),
+            [deposit.clone()]
+        );
+        assert_eq!(
+            storage.new.load_all_builder_fees().await.unwrap(),
+            [totals.clone()]
+        );
+        assert_eq!(
+            storage.load_builder_fees(deposit.account).await.unwrap(),
+            Some(totals)
+        );
+        assert_eq!(
+            storage
+                .new
+                .load_decide_events(0, events.len())
+                .await
+                .unwrap(),
+            events
+        );
+        assert_eq!(
+            storage.load_decide_events(0, events.len()).await.unwrap(),
+            events
+        );
+        assert_eq!(
+            storage.new.load_all_tx_statuses().await.unwrap(),
+            [(tx.commit(), status.clone())]
+        );
+        assert_eq!(
+            storage.load_tx_status(tx.commit()).await.unwrap(),
+            Some(status)
+        );
+        assert_eq!(
+            storage.new.load_all_stake_tables().await.unwrap(),
+            [stake_table.clone()]
+        );
+        assert_eq!(
+            storage.load_stake_table(EpochNumber::new(1)).await.unwrap(),
+            Some(stake_table)
+        );
+        assert_eq!(
+            storage.new.load_all_da_pointers().await.unwrap(),
+            [pointer.clone()]
+        );
+        assert_eq!(storage.load_da_pointer(1).await.unwrap(), Some(pointer));
+        assert_eq!(storage.new.load_libp2p_peers().await.unwrap(), peers);
+        assert_eq!(storage.load_libp2p_peers().await.unwrap(), peers);
+        assert_eq!(
+            storage.new.load_view_records(0, 10).await.unwrap(),
+            [record.clone()]
+        );
+        assert_eq!(storage.load_view_records(0, 10).await.unwrap(), [record]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sync_copies_only_deltas() {
+        let old_dir = TempDir::new().unwrap();
+        let new_dir = TempDir::new().unwrap();
+        let old = fs::Options::new(old_dir.path().into())
+            .create()
+            .await
+            .unwrap();
+        let new = fs::Options::new(new_dir.path().into())
+            .create()
+            .await
+            .unwrap();
+        let storage = Persistence::new(old, new);
+        storage.sync().await.unwrap();
+
+        // Writes while a copy is in progress are recorded, to be copied again.
+        let tx = Transaction::new(1_u32.into(), vec![1]);
+        let status = TxStatus::Sequenced { block: 1, index: 0 };
+        storage.migration.copying.store(true, Ordering::SeqCst);
+        storage
+            .store_tx_status(tx.commit(), status.clone())
+            .await
+            .unwrap();
+        storage.migration.copying.store(false, Ordering::SeqCst);
+        assert_eq!(
+            storage.migration.take_deltas(),
+            [Delta::TxStatus(tx.commit())]
+        );
+
+        // Writes after the copy are not.
+        storage
+            .store_tx_status(tx.commit(), status.clone())
+            .await
+            .unwrap();
+        assert!(storage.migration.take_deltas().is_empty());
+
+        // Simulate a write which only reached the old storage, as well as a write which
+        // bypassed the migration entirely, and so is not known to be missing.
+        let missed = Transaction::new(1_u32.into(), vec![2]);
+        let bypassed = Transaction::new(1_u32.into(), vec![3]);
+        storage
+            .old
+            .store_tx_status(missed.commit(), status.clone())
+            .await
+            .unwrap();
+        storage
+            .old
+            .store_tx_status(bypassed.commit(), status.clone())
+            .await
+            .unwrap();
+        storage.migration.record([Delta::TxStatus(missed.commit())]);
+        storage.migration.synced.store(false, Ordering::SeqCst);
+
+        // Catching up copies only the missed write.
+        storage.sync().await.unwrap();
+        assert!(storage.progress().synced);
+        assert_eq!(
+            storage.new.load_tx_status(missed.commit()).await.unwrap(),
+            Some(status)
+        );
+        assert_eq!(
+            storage.new.load_tx_status(bypassed.commit()).await.unwrap(),
+            None
+        );
+    }
+}
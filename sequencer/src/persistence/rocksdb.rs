@@ -0,0 +1,986 @@
+use anyhow::{anyhow, Context};
+use async_lock::RwLock;
+use async_trait::async_trait;
+use clap::Parser;
+use committable::{Commitment, Committable};
+use espresso_types::{
+    v0::traits::{EventConsumer, PersistenceOptions, SequencerPersistence},
+    BuilderFeeTotals, DaPointer, DecideEvent, EpochStakeTable, FeeAccount, FeeDeposit, Leaf,
+    NetworkConfig, Payload, PubKey, SeqTypes, ShutdownCheckpoint, Transaction, TxStatus,
+    UpgradeRecord, ValidatedState, ViewRecord,
+};
+use hotshot_types::{
+    consensus::CommitmentMap,
+    data::{DaProposal, EpochNumber, QuorumProposal, VidDisperseShare},
+    event::{Event, EventType, HotShotAction, LeafInfo},
+    message::Proposal,
+    simple_certificate::{QuorumCertificate, UpgradeCertificate},
+    traits::{block_contents::BlockPayload, node_implementation::ConsensusTime},
+    utils::View,
+    vid::VidSchemeType,
+    vote::HasViewNumber,
+};
+use jf_vid::VidScheme;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug, Formatter},
+    path::{Path, PathBuf},
+};
+
+use super::{parse_tx_hash, DaProfile};
+use crate::ViewNumber;
+
+/// Column family holding the HotShot network config.
+const CONFIG_CF: &str = "config";
+/// Column family holding singleton values, keyed by name.
+const META_CF: &str = "meta";
+/// Column family holding decided leaves and their QCs, keyed by view.
+const DECIDED_LEAVES_CF: &str = "decided_leaves";
+/// Column family holding VID shares, keyed by view.
+const VID_CF: &str = "vid";
+/// Column family holding DA proposals, keyed by view.
+const DA_CF: &str = "da";
+/// Column family holding quorum proposals, keyed by view.
+const QUORUM_PROPOSALS_CF: &str = "quorum_proposals";
+/// Column family holding statuses of submitted transactions, keyed by transaction hash.
+const TX_STATUS_CF: &str = "tx_status";
+/// Column family holding transactions in the node-local mempool, keyed by transaction hash.
+const MEMPOOL_CF: &str = "mempool";
+/// Column family holding historical stake tables, keyed by epoch.
+const STAKE_TABLE_CF: &str = "stake_tables";
+/// Column family holding the statuses of protocol upgrades, keyed by version.
+const UPGRADES_CF: &str = "upgrades";
+/// Column family holding pointers to payloads mirrored on an external DA layer, keyed by height.
+const DA_POINTERS_CF: &str = "da_pointers";
+/// Column family holding fee deposits, keyed by account followed by L1 block and log index.
+const FEE_DEPOSITS_CF: &str = "fee_deposits";
+/// Column family holding the fee totals of builder accounts, keyed by account.
+const BUILDER_FEES_CF: &str = "builder_fees";
+/// Column family holding records of what this node observed in each view, keyed by view.
+const VIEW_LOG_CF: &str = "view_log";
+/// Column family holding the replayable log of decide events, keyed by block height.
+const DECIDE_EVENT_LOG_CF: &str = "decide_event_log";
+
+const COLUMN_FAMILIES: [&str; 15] = [
+    CONFIG_CF,
+    META_CF,
+    DECIDED_LEAVES_CF,
+    VID_CF,
+    DA_CF,
+    QUORUM_PROPOSALS_CF,
+    TX_STATUS_CF,
+    MEMPOOL_CF,
+    STAKE_TABLE_CF,
+    UPGRADES_CF,
+    DA_POINTERS_CF,
+    FEE_DEPOSITS_CF,
+    BUILDER_FEES_CF,
+    VIEW_LOG_CF,
+    DECIDE_EVENT_LOG_CF,
+];
+
+const CONFIG_KEY: &[u8] = b"hotshot.cfg";
+const VOTED_VIEW_KEY: &[u8] = b"highest_voted_view";
+const UNDECIDED_STATE_KEY: &[u8] = b"undecided_state";
+const UPGRADE_CERTIFICATE_KEY: &[u8] = b"upgrade_certificate";
+const LAST_PROCESSED_VIEW_KEY: &[u8] = b"last_processed_view";
+const LIBP2P_PEERS_KEY: &[u8] = b"libp2p_peers";
+const BANNED_PEERS_KEY: &[u8] = b"banned_peers";
+const FEE_DEPOSITS_L1_BLOCK_KEY: &[u8] = b"fee_deposits_l1_block";
+const SHUTDOWN_CHECKPOINT_KEY: &[u8] = b"shutdown_checkpoint";
+const ANCHOR_STATE_KEY: &[u8] = b"anchor_state";
+
+/// Options for RocksDB backed persistence.
+#[derive(Parser, Clone, Debug)]
+pub struct Options {
+    /// Path to the RocksDB database directory.
+    ///
+    /// Query service data, if the query module is enabled, is stored in a `query` subdirectory of
+    /// this path.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_ROCKSDB_PATH")]
+    path: PathBuf,
+
+    #[clap(long, env = "ESPRESSO_SEQUENCER_STORE_UNDECIDED_STATE", hide = true)]
+    store_undecided_state: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+impl Options {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            store_undecided_state: false,
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn db_path(&self) -> PathBuf {
+        self.path.join("consensus")
+    }
+
+    /// Path for query service storage colocated with this database.
+    pub(crate) fn query_path(&self) -> PathBuf {
+        self.path.join("query")
+    }
+}
+
+impl DaProfile for Options {
+    fn da_only(mut self) -> Self {
+        self.store_undecided_state = false;
+        self
+    }
+}
+
+#[async_trait]
+impl PersistenceOptions for Options {
+    type Persistence = Persistence;
+
+    async fn create(self) -> anyhow::Result<Persistence> {
+        let mut opts = ::rocksdb::Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        let db = ::rocksdb::DB::open_cf(&opts, self.db_path(), COLUMN_FAMILIES)
+            .context(format!("opening RocksDB at {}", self.db_path().display()))?;
+
+        Ok(Persistence {
+            store_undecided_state: self.store_undecided_state,
+            inner: Arc::new(RwLock::new(Inner { db })),
+        })
+    }
+
+    async fn reset(self) -> anyhow::Result<()> {
+        let path = self.db_path();
+        if !path.exists() {
+            return Ok(());
+        }
+        ::rocksdb::DB::destroy(&::rocksdb::Options::default(), &path)
+            .context(format!("destroying RocksDB at {}", path.display()))
+    }
+}
+
+/// RocksDB backed persistence.
+#[derive(Clone, Debug)]
+pub struct Persistence {
+    store_undecided_state: bool,
+
+    // RocksDB is safe for concurrent use on its own, but decide processing reads and deletes
+    // across several column families, which must not interleave with concurrent writes. We enforce
+    // the same reader/writer discipline as the file system backend.
+    inner: Arc<RwLock<Inner>>,
+}
+
+struct Inner {
+    db: ::rocksdb::DB,
+}
+
+impl Debug for Inner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Inner")
+            .field("path", &self.db.path())
+            .finish()
+    }
+}
+
+/// Encode a view number as a key which sorts in view order.
+fn view_key(view: u64) -> [u8; 8] {
+    view.to_be_bytes()
+}
+
+fn parse_view_key(key: &[u8]) -> anyhow::Result<u64> {
+    let bytes = key
+        .try_into()
+        .map_err(|_| anyhow!("malformed view key: {key:?}"))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// The key of a fee deposit, which sorts the deposits to each account in L1 order.
+fn fee_deposit_key(deposit: &FeeDeposit) -> Vec<u8> {
+    let mut key = deposit.account.0.as_bytes().to_vec();
+    key.extend(deposit.l1_block.to_be_bytes());
+    key.extend(deposit.log_index.to_be_bytes());
+    key
+}
+
+impl Inner {
+    fn cf(&self, name: &str) -> anyhow::Result<&::rocksdb::ColumnFamily> {
+        self.db
+            .cf_handle(name)
+            .context(format!("missing column family {name}"))
+    }
+
+    fn get<T: DeserializeOwned>(&self, cf: &str, key: &[u8]) -> anyhow::Result<Option<T>> {
+        let Some(bytes) = self.db.get_cf(self.cf(cf)?, key)? else {
+            return Ok(None);
+        };
+        Ok(Some(
+            bincode::deserialize(&bytes).context(format!("deserializing {cf} entry"))?,
+        ))
+    }
+
+    fn put<T: Serialize>(&self, cf: &str, key: &[u8], value: &T) -> anyhow::Result<()> {
+        let bytes = bincode::serialize(value).context(format!("serializing {cf} entry"))?;
+        self.db.put_cf(self.cf(cf)?, key, bytes)?;
+        Ok(())
+    }
+
+    /// Insert a value keyed by view, unless there is already a value for that view.
+    ///
+    /// Returns `false` if there was an existing value, in which case nothing is written.
+    fn put_if_absent<T: Serialize>(&self, cf: &str, view: u64, value: &T) -> anyhow::Result<bool> {
+        let key = view_key(view);
+        if self.db.get_pinned_cf(self.cf(cf)?, key)?.is_some() {
+            return Ok(false);
+        }
+        self.put(cf, &key, value)?;
+        Ok(true)
+    }
+
+    /// Load all entries in a view-keyed column family with view at most `max_view`.
+    fn load_range<T: DeserializeOwned>(
+        &self,
+        cf: &str,
+        max_view: Option<u64>,
+    ) -> anyhow::Result<BTreeMap<u64, T>> {
+        let mut map = BTreeMap::new();
+        for entry in self
+            .db
+            .iterator_cf(self.cf(cf)?, ::rocksdb::IteratorMode::Start)
+        {
+            let (key, value) = entry?;
+            let view = parse_view_key(&key)?;
+            if max_view.is_some_and(|max| view > max) {
+                break;
+            }
+            let value =
+                bincode::deserialize(&value).context(format!("deserializing {cf} at {view}"))?;
+            map.insert(view, value);
+        }
+        Ok(map)
+    }
+
+    fn last_processed_view(&self) -> anyhow::Result<Option<u64>> {
+        self.get(META_CF, LAST_PROCESSED_VIEW_KEY)
+    }
+
+    fn collect_garbage(&self, view: ViewNumber) -> anyhow::Result<()> {
+        let view_number = view.u64();
+
+        // Range deletes are exclusive of the end key, so delete up to and including `view`.
+        let mut batch = ::rocksdb::WriteBatch::default();
+        let start = view_key(0);
+        let end = view_key(view_number + 1);
+        batch.delete_range_cf(self.cf(DA_CF)?, start, end);
+        batch.delete_range_cf(self.cf(VID_CF)?, start, end);
+        batch.delete_range_cf(self.cf(QUORUM_PROPOSALS_CF)?, start, end);
+
+        // Save the most recent leaf as it will be our anchor point if the node restarts.
+        batch.delete_range_cf(self.cf(DECIDED_LEAVES_CF)?, start, view_key(view_number));
+
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    async fn generate_decide_events(
+        &self,
+        view: ViewNumber,
+        consumer: &impl EventConsumer,
+    ) -> anyhow::Result<()> {
+        let last_processed_view = self.last_processed_view()?;
+
+        // Generate a decide event for each leaf, to be processed by the event consumer. We make a
+        // separate event for each leaf because it is possible we have non-consecutive leaves in our
+        // storage, which would not be valid as a single decide with a single leaf chain.
+        let leaves = self.load_range::<(Leaf, QuorumCertificate<SeqTypes>)>(
+            DECIDED_LEAVES_CF,
+            Some(view.u64()),
+        )?;
+        for (v, (mut leaf, qc)) in leaves {
+            if last_processed_view.is_some_and(|last| v <= last) {
+                continue;
+            }
+
+            // Include the VID share if available.
+            let vid_share = self
+                .get::<Proposal<SeqTypes, VidDisperseShare<SeqTypes>>>(VID_CF, &view_key(v))?
+                .map(|proposal| proposal.data);
+            if vid_share.is_none() {
+                tracing::debug!(view = v, "VID share not available at decide");
+            }
+
+            // Fill in the full block payload using the DA proposals we had persisted.
+            if let Some(proposal) =
+                self.get::<Proposal<SeqTypes, DaProposal<SeqTypes>>>(DA_CF, &view_key(v))?
+            {
+                let payload = Payload::from_bytes(
+                    &proposal.data.encoded_transactions,
+                    &proposal.data.metadata,
+                );
+                leaf.fill_block_payload_unchecked(payload);
+            } else {
+                tracing::debug!(view = v, "DA proposal not available at decide");
+            }
+
+            let info = LeafInfo {
+                leaf,
+                vid_share,
+
+                // Note: the following fields are not used in Decide event processing, and should be
+                // removed. For now, we just default them.
+                state: Default::default(),
+                delta: Default::default(),
+            };
+
+            consumer
+                .handle_event(&Event {
+                    view_number: ViewNumber::new(v),
+                    event: EventType::Decide {
+                        qc: Arc::new(qc),
+                        leaf_chain: Arc::new(vec![info]),
+                        block_size: None,
+                    },
+                })
+                .await?;
+
+            // Record progress after each event, so that if a later event fails, we do not
+            // reprocess the earlier ones on retry.
+            self.put(META_CF, LAST_PROCESSED_VIEW_KEY, &v)?;
+        }
+
+        Ok(())
+    }
+
+    fn load_anchor_leaf(&self) -> anyhow::Result<Option<(Leaf, QuorumCertificate<SeqTypes>)>> {
+        let Some(entry) = self
+            .db
+            .iterator_cf(self.cf(DECIDED_LEAVES_CF)?, ::rocksdb::IteratorMode::End)
+            .next()
+        else {
+            return Ok(None);
+        };
+        let (_, bytes) = entry?;
+        Ok(Some(
+            bincode::deserialize(&bytes).context("deserializing anchor leaf")?,
+        ))
+    }
+}
+
+#[async_trait]
+impl SequencerPersistence for Persistence {
+    async fn load_config(&self) -> anyhow::Result<Option<NetworkConfig>> {
+        let inner = self.inner.read().await;
+        let Some(bytes) = inner.db.get_cf(inner.cf(CONFIG_CF)?, CONFIG_KEY)? else {
+            tracing::info!("config not found in RocksDB");
+            return Ok(None);
+        };
+        tracing::info!("loading config from RocksDB");
+        let config = serde_json::from_slice(&bytes).context("malformed config")?;
+        Ok(Some(config))
+    }
+
+    async fn save_config(&self, cfg: &NetworkConfig) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        tracing::info!("saving config to RocksDB");
+        let bytes = serde_json::to_vec(cfg).context("serializing config")?;
+        inner.db.put_cf(inner.cf(CONFIG_CF)?, CONFIG_KEY, bytes)?;
+        Ok(())
+    }
+
+    async fn load_latest_acted_view(&self) -> anyhow::Result<Option<ViewNumber>> {
+        let inner = self.inner.read().await;
+        Ok(inner
+            .get::<u64>(META_CF, VOTED_VIEW_KEY)?
+            .map(ViewNumber::new))
+    }
+
+    async fn append_decided_leaves(
+        &self,
+        view: ViewNumber,
+        leaf_chain: impl IntoIterator<Item = (&LeafInfo<SeqTypes>, QuorumCertificate<SeqTypes>)> + Send,
+        consumer: &impl EventConsumer,
+    ) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+
+        for (info, qc) in leaf_chain {
+            let view = info.leaf.view_number().u64();
+            if !inner.put_if_absent(DECIDED_LEAVES_CF, view, &(&info.leaf, qc))? {
+                // Don't overwrite an existing leaf, but warn about it as this is likely not
+                // intended behavior from HotShot.
+                tracing::warn!(view, "duplicate decided leaf");
+            }
+        }
+
+        // Event processing failure is not an error, since by this point we have at least managed to
+        // persist the decided leaves successfully, and the event processing will just run again at
+        // the next decide. If there is an error here, we just log it and return early with success
+        // to prevent GC from running before the decided leaves are processed.
+        if let Err(err) = inner.generate_decide_events(view, consumer).await {
+            tracing::warn!(?view, "event processing failed: {err:#}");
+            return Ok(());
+        }
+
+        if let Err(err) = inner.collect_garbage(view) {
+            // Similarly, garbage collection is not an error. We have done everything we strictly
+            // needed to do, and GC will run again at the next decide. Log the error but do not
+            // return it.
+            tracing::warn!(?view, "GC failed: {err:#}");
+        }
+
+        Ok(())
+    }
+
+    async fn load_anchor_leaf(
+        &self,
+    ) -> anyhow::Result<Option<(Leaf, QuorumCertificate<SeqTypes>)>> {
+        self.inner.read().await.load_anchor_leaf()
+    }
+
+    async fn load_undecided_state(
+        &self,
+    ) -> anyhow::Result<Option<(CommitmentMap<Leaf>, BTreeMap<ViewNumber, View<SeqTypes>>)>> {
+        self.inner.read().await.get(META_CF, UNDECIDED_STATE_KEY)
+    }
+
+    async fn load_da_proposal(
+        &self,
+        view: ViewNumber,
+    ) -> anyhow::Result<Option<Proposal<SeqTypes, DaProposal<SeqTypes>>>> {
+        self.inner.read().await.get(DA_CF, &view_key(view.u64()))
+    }
+
+    async fn load_vid_share(
+        &self,
+        view: ViewNumber,
+    ) -> anyhow::Result<Option<Proposal<SeqTypes, VidDisperseShare<SeqTypes>>>> {
+        self.inner.read().await.get(VID_CF, &view_key(view.u64()))
+    }
+
+    async fn append_vid(
+        &self,
+        proposal: &Proposal<SeqTypes, VidDisperseShare<SeqTypes>>,
+    ) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        let view_number = proposal.data.view_number().u64();
+        if !inner.put_if_absent(VID_CF, view_number, proposal)? {
+            // Don't overwrite an existing share, but warn about it as this is likely not intended
+            // behavior from HotShot.
+            tracing::warn!(view_number, "duplicate VID share");
+        }
+        Ok(())
+    }
+
+    async fn append_da(
+        &self,
+        proposal: &Proposal<SeqTypes, DaProposal<SeqTypes>>,
+        _vid_commit: <VidSchemeType as VidScheme>::Commit,
+    ) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        let view_number = proposal.data.view_number().u64();
+        if !inner.put_if_absent(DA_CF, view_number, proposal)? {
+            // Don't overwrite an existing proposal, but warn about it as this is likely not
+            // intended behavior from HotShot.
+            tracing::warn!(view_number, "duplicate DA proposal");
+        }
+        Ok(())
+    }
+
+    async fn record_action(&self, view: ViewNumber, action: HotShotAction) -> anyhow::Result<()> {
+        // Todo Remove this after https://github.com/EspressoSystems/espresso-sequencer/issues/1931
+        if !matches!(action, HotShotAction::Propose | HotShotAction::Vote) {
+            return Ok(());
+        }
+        let inner = self.inner.write().await;
+        if let Some(saved_view) = inner.get::<u64>(META_CF, VOTED_VIEW_KEY)? {
+            // Only overwrite the saved view if it is older than the new view.
+            if saved_view >= view.u64() {
+                return Ok(());
+            }
+        }
+        inner.put(META_CF, VOTED_VIEW_KEY, &view.u64())
+    }
+
+    async fn update_undecided_state(
+        &self,
+        leaves: CommitmentMap<Leaf>,
+        state: BTreeMap<ViewNumber, View<SeqTypes>>,
+    ) -> anyhow::Result<()> {
+        if !self.store_undecided_state {
+            return Ok(());
+        }
+
+        let inner = self.inner.write().await;
+        inner.put(META_CF, UNDECIDED_STATE_KEY, &(leaves, state))
+    }
+
+    async fn append_quorum_proposal(
+        &self,
+        proposal: &Proposal<SeqTypes, QuorumProposal<SeqTypes>>,
+    ) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        let view_number = proposal.data.view_number().u64();
+        inner.put(QUORUM_PROPOSALS_CF, &view_key(view_number), proposal)
+    }
+
+    async fn load_quorum_proposals(
+        &self,
+    ) -> anyhow::Result<BTreeMap<ViewNumber, Proposal<SeqTypes, QuorumProposal<SeqTypes>>>> {
+        let inner = self.inner.read().await;
+        Ok(inner
+            .load_range(QUORUM_PROPOSALS_CF, None)?
+            .into_iter()
+            .map(|(view, proposal)| (ViewNumber::new(view), proposal))
+            .collect())
+    }
+
+    async fn load_quorum_proposal(
+        &self,
+        view: ViewNumber,
+    ) -> anyhow::Result<Proposal<SeqTypes, QuorumProposal<SeqTypes>>> {
+        let inner = self.inner.read().await;
+        inner
+            .get(QUORUM_PROPOSALS_CF, &view_key(view.u64()))?
+            .context(format!("missing quorum proposal for view {view:?}"))
+    }
+
+    async fn load_upgrade_certificate(
+        &self,
+    ) -> anyhow::Result<Option<UpgradeCertificate<SeqTypes>>> {
+        self.inner
+            .read()
+            .await
+            .get(META_CF, UPGRADE_CERTIFICATE_KEY)
+    }
+
+    async fn store_upgrade_certificate(
+        &self,
+        decided_upgrade_certificate: Option<UpgradeCertificate<SeqTypes>>,
+    ) -> anyhow::Result<()> {
+        let certificate = match decided_upgrade_certificate {
+            Some(cert) => cert,
+            None => return Ok(()),
+        };
+        let inner = self.inner.write().await;
+        inner.put(META_CF, UPGRADE_CERTIFICATE_KEY, &certificate)
+    }
+
+    async fn store_tx_status(
+        &self,
+        hash: Commitment<Transaction>,
+        status: TxStatus,
+    ) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        inner.put(TX_STATUS_CF, hash.to_string().as_bytes(), &status)
+    }
+
+    async fn load_tx_status(
+        &self,
+        hash: Commitment<Transaction>,
+    ) -> anyhow::Result<Option<TxStatus>> {
+        self.inner
+            .read()
+            .await
+            .get(TX_STATUS_CF, hash.to_string().as_bytes())
+    }
+
+    async fn load_all_tx_statuses(
+        &self,
+    ) -> anyhow::Result<Vec<(Commitment<Transaction>, TxStatus)>> {
+        let inner = self.inner.read().await;
+        inner
+            .db
+            .iterator_cf(inner.cf(TX_STATUS_CF)?, ::rocksdb::IteratorMode::Start)
+            .map(|entry| {
+                let (key, value) = entry?;
+                let hash = std::str::from_utf8(&key).context("malformed tx status key")?;
+                let status = bincode::deserialize(&value).context("deserializing tx status")?;
+                Ok((parse_tx_hash(hash)?, status))
+            })
+            .collect()
+    }
+
+    async fn append_mempool_txs(&self, txs: &[Transaction]) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        for tx in txs {
+            inner.put(MEMPOOL_CF, tx.commit().to_string().as_bytes(), tx)?;
+        }
+        Ok(())
+    }
+
+    async fn remove_mempool_txs(&self, hashes: &[Commitment<Transaction>]) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        let mut batch = ::rocksdb::WriteBatch::default();
+        for hash in hashes {
+            batch.delete_cf(inner.cf(MEMPOOL_CF)?, hash.to_string().as_bytes());
+        }
+        inner.db.write(batch)?;
+        Ok(())
+    }
+
+    async fn load_mempool(&self) -> anyhow::Result<Vec<Transaction>> {
+        let inner = self.inner.read().await;
+        inner
+            .db
+            .iterator_cf(inner.cf(MEMPOOL_CF)?, ::rocksdb::IteratorMode::Start)
+            .map(|entry| {
+                let (_, value) = entry?;
+                Ok(bincode::deserialize(&value).context("deserializing mempool entry")?)
+            })
+            .collect()
+    }
+
+    async fn store_stake_table(&self, stake_table: &EpochStakeTable) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        inner.put(
+            STAKE_TABLE_CF,
+            &view_key(stake_table.epoch.u64()),
+            stake_table,
+        )
+    }
+
+    async fn load_stake_table(
+        &self,
+        epoch: EpochNumber,
+    ) -> anyhow::Result<Option<EpochStakeTable>> {
+        self.inner
+            .read()
+            .await
+            .get(STAKE_TABLE_CF, &view_key(epoch.u64()))
+    }
+
+    async fn load_all_stake_tables(&self) -> anyhow::Result<Vec<EpochStakeTable>> {
+        let inner = self.inner.read().await;
+        inner
+            .db
+            .iterator_cf(inner.cf(STAKE_TABLE_CF)?, ::rocksdb::IteratorMode::Start)
+            .map(|entry| {
+                let (_, value) = entry?;
+                Ok(bincode::deserialize(&value).context("deserializing stake table")?)
+            })
+            .collect()
+    }
+
+    async fn store_da_pointer(&self, pointer: &DaPointer) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        inner.put(DA_POINTERS_CF, &view_key(pointer.height), pointer)
+    }
+
+    async fn load_da_pointer(&self, height: u64) -> anyhow::Result<Option<DaPointer>> {
+        self.inner
+            .read()
+            .await
+            .get(DA_POINTERS_CF, &view_key(height))
+    }
+
+    async fn load_all_da_pointers(&self) -> anyhow::Result<Vec<DaPointer>> {
+        let inner = self.inner.read().await;
+        inner
+            .db
+            .iterator_cf(inner.cf(DA_POINTERS_CF)?, ::rocksdb::IteratorMode::Start)
+            .map(|entry| {
+                let (_, value) = entry?;
+                Ok(bincode::deserialize(&value).context("deserializing DA pointer")?)
+            })
+            .collect()
+    }
+
+    async fn store_fee_deposits(
+        &self,
+        deposits: &[FeeDeposit],
+        l1_block: u64,
+    ) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        let mut batch = ::rocksdb::WriteBatch::default();
+        for deposit in deposits {
+            let bytes = bincode::serialize(deposit).context("serializing fee deposit")?;
+            batch.put_cf(inner.cf(FEE_DEPOSITS_CF)?, fee_deposit_key(deposit), bytes);
+        }
+        batch.put_cf(
+            inner.cf(META_CF)?,
+            FEE_DEPOSITS_L1_BLOCK_KEY,
+            bincode::serialize(&l1_block)?,
+        );
+        inner.db.write(batch)?;
+        Ok(())
+    }
+
+    async fn load_fee_deposits_l1_block(&self) -> anyhow::Result<Option<u64>> {
+        self.inner
+            .read()
+            .await
+            .get(META_CF, FEE_DEPOSITS_L1_BLOCK_KEY)
+    }
+
+    async fn load_fee_deposits(
+        &self,
+        account: FeeAccount,
+        offset: u64,
+        limit: u64,
+    ) -> anyhow::Result<Vec<FeeDeposit>> {
+        let inner = self.inner.read().await;
+        let prefix = account.0.as_bytes();
+        inner
+            .db
+            .iterator_cf(
+                inner.cf(FEE_DEPOSITS_CF)?,
+                ::rocksdb::IteratorMode::From(prefix, ::rocksdb::Direction::Forward),
+            )
+            .take_while(|entry| {
+                entry
+                    .as_ref()
+                    .map_or(true, |(key, _)| key.starts_with(prefix))
+            })
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|entry| {
+                let (_, value) = entry?;
+                Ok(bincode::deserialize(&value).context("deserializing fee deposit")?)
+            })
+            .collect()
+    }
+
+    async fn load_all_fee_deposits(&self) -> anyhow::Result<Vec<FeeDeposit>> {
+        let inner = self.inner.read().await;
+        let mut deposits = inner
+            .db
+            .iterator_cf(inner.cf(FEE_DEPOSITS_CF)?, ::rocksdb::IteratorMode::Start)
+            .map(|entry| {
+                let (_, value) = entry?;
+                Ok(bincode::deserialize(&value).context("deserializing fee deposit")?)
+            })
+            .collect::<anyhow::Result<Vec<FeeDeposit>>>()?;
+        // Deposits are keyed by account, so restore the L1 order.
+        deposits.sort_by_key(|deposit| (deposit.l1_block, deposit.log_index));
+        Ok(deposits)
+    }
+
+    async fn store_builder_fees(&self, totals: &[BuilderFeeTotals]) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        let mut batch = ::rocksdb::WriteBatch::default();
+        for account in totals {
+            let bytes = bincode::serialize(account).context("serializing builder fees")?;
+            batch.put_cf(
+                inner.cf(BUILDER_FEES_CF)?,
+                account.account.0.as_bytes(),
+                bytes,
+            );
+        }
+        inner.db.write(batch)?;
+        Ok(())
+    }
+
+    async fn load_builder_fees(
+        &self,
+        account: FeeAccount,
+    ) -> anyhow::Result<Option<BuilderFeeTotals>> {
+        self.inner
+            .read()
+            .await
+            .get(BUILDER_FEES_CF, account.0.as_bytes())
+    }
+
+    async fn load_all_builder_fees(&self) -> anyhow::Result<Vec<BuilderFeeTotals>> {
+        let inner = self.inner.read().await;
+        inner
+            .db
+            .iterator_cf(inner.cf(BUILDER_FEES_CF)?, ::rocksdb::IteratorMode::Start)
+            .map(|entry| {
+                let (_, value) = entry?;
+                Ok(bincode::deserialize(&value).context("deserializing builder fees")?)
+            })
+            .collect()
+    }
+
+    async fn store_upgrade(&self, upgrade: &UpgradeRecord) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        inner.put(UPGRADES_CF, upgrade.version.to_string().as_bytes(), upgrade)
+    }
+
+    async fn load_upgrades(&self) -> anyhow::Result<Vec<UpgradeRecord>> {
+        let inner = self.inner.read().await;
+        inner
+            .db
+            .iterator_cf(inner.cf(UPGRADES_CF)?, ::rocksdb::IteratorMode::Start)
+            .map(|entry| {
+                let (_, value) = entry?;
+                Ok(bincode::deserialize(&value).context("deserializing upgrade")?)
+            })
+            .collect()
+    }
+
+    async fn store_libp2p_peers(&self, peers: &[String]) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        inner.put(META_CF, LIBP2P_PEERS_KEY, &peers)
+    }
+
+    async fn load_libp2p_peers(&self) -> anyhow::Result<Vec<String>> {
+        let inner = self.inner.read().await;
+        Ok(inner.get(META_CF, LIBP2P_PEERS_KEY)?.unwrap_or_default())
+    }
+
+    async fn store_banned_peers(&self, peers: &[PubKey]) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        inner.put(META_CF, BANNED_PEERS_KEY, &peers)
+    }
+
+    async fn store_shutdown_checkpoint(
+        &self,
+        checkpoint: &ShutdownCheckpoint,
+    ) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        inner.put(META_CF, SHUTDOWN_CHECKPOINT_KEY, checkpoint)?;
+        // Make sure everything written before the checkpoint is on disk.
+        inner.db.flush()?;
+        Ok(())
+    }
+
+    async fn take_shutdown_checkpoint(&self) -> anyhow::Result<Option<ShutdownCheckpoint>> {
+        let inner = self.inner.write().await;
+        let checkpoint = inner.get(META_CF, SHUTDOWN_CHECKPOINT_KEY)?;
+        inner
+            .db
+            .delete_cf(inner.cf(META_CF)?, SHUTDOWN_CHECKPOINT_KEY)?;
+        Ok(checkpoint)
+    }
+
+    async fn store_view_record(&self, record: &ViewRecord) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        inner.put(VIEW_LOG_CF, &view_key(record.view), record)
+    }
+
+    async fn load_view_records(&self, from: u64, until: u64) -> anyhow::Result<Vec<ViewRecord>> {
+        let inner = self.inner.read().await;
+        let start = view_key(from);
+        let mut records = vec![];
+        for entry in inner.db.iterator_cf(
+            inner.cf(VIEW_LOG_CF)?,
+            ::rocksdb::IteratorMode::From(&start, ::rocksdb::Direction::Forward),
+        ) {
+            let (key, value) = entry?;
+            if parse_view_key(&key)? >= until {
+                break;
+            }
+            records.push(bincode::deserialize(&value).context("deserializing view record")?);
+        }
+        Ok(records)
+    }
+
+    async fn prune_view_records(&self, view: u64) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        inner
+            .db
+            .delete_range_cf(inner.cf(VIEW_LOG_CF)?, view_key(0), view_key(view))?;
+        Ok(())
+    }
+
+    async fn store_decide_events(&self, events: &[DecideEvent]) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        let mut batch = ::rocksdb::WriteBatch::default();
+        for event in events {
+            let bytes = bincode::serialize(event).context("serializing decide event")?;
+            batch.put_cf(
+                inner.cf(DECIDE_EVENT_LOG_CF)?,
+                view_key(event.height),
+                bytes,
+            );
+        }
+        inner.db.write(batch)?;
+        Ok(())
+    }
+
+    async fn load_decide_events(
+        &self,
+        from: u64,
+        limit: usize,
+    ) -> anyhow::Result<Vec<DecideEvent>> {
+        let inner = self.inner.read().await;
+        let start = view_key(from);
+        inner
+            .db
+            .iterator_cf(
+                inner.cf(DECIDE_EVENT_LOG_CF)?,
+                ::rocksdb::IteratorMode::From(&start, ::rocksdb::Direction::Forward),
+            )
+            .take(limit)
+            .map(|entry| {
+                let (_, value) = entry?;
+                bincode::deserialize(&value).context("deserializing decide event")
+            })
+            .collect()
+    }
+
+    async fn prune_decide_events(&self, height: u64) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        inner.db.delete_range_cf(
+            inner.cf(DECIDE_EVENT_LOG_CF)?,
+            view_key(0),
+            view_key(height),
+        )?;
+        Ok(())
+    }
+
+    async fn store_anchor_state(
+        &self,
+        view: ViewNumber,
+        state: &ValidatedState,
+    ) -> anyhow::Result<()> {
+        let inner = self.inner.write().await;
+        // Never replace the state with that of an older anchor leaf.
+        if let Some((saved, _)) =
+            inner.get::<(ViewNumber, ValidatedState)>(META_CF, ANCHOR_STATE_KEY)?
+        {
+            if saved >= view {
+                return Ok(());
+            }
+        }
+        inner.put(META_CF, ANCHOR_STATE_KEY, &(view, state))
+    }
+
+    async fn load_anchor_state(&self) -> anyhow::Result<Option<(ViewNumber, ValidatedState)>> {
+        let inner = self.inner.read().await;
+        inner.get(META_CF, ANCHOR_STATE_KEY)
+    }
+
+    async fn load_banned_peers(&self) -> anyhow::Result<Vec<PubKey>> {
+        let inner = self.inner.read().await;
+        Ok(inner.get(META_CF, BANNED_PEERS_KEY)?.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use tempfile::TempDir;
+
+    use super::{super::testing::TestablePersistence, *};
+
+    #[async_trait]
+    impl TestablePersistence for Persistence {
+        type Storage = TempDir;
+
+        async fn tmp_storage() -> Self::Storage {
+            TempDir::new().unwrap()
+        }
+
+        async fn connect(storage: &Self::Storage) -> Self {
+            Options::new(storage.path().into()).create().await.unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod generic_tests {
+    use super::{super::persistence_tests, Persistence};
+    // For some reason this is the only way to import the macro defined in another module of this
+    // crate.
+    use crate::*;
+
+    instantiate_persistence_tests!(Persistence);
+}
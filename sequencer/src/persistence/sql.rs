@@ -1,12 +1,14 @@
-use anyhow::Context;
+use anyhow::{ensure, Context};
 use async_trait::async_trait;
 use clap::Parser;
-use committable::Committable;
+use committable::{Commitment, Committable};
 use derivative::Derivative;
 use espresso_types::{
     parse_duration,
     v0::traits::{EventConsumer, PersistenceOptions, SequencerPersistence, StateCatchup},
-    BackoffParams, Leaf, NetworkConfig, Payload,
+    BackoffParams, BuilderFeeTotals, DaPointer, DecideEvent, EpochStakeTable, FeeAccount,
+    FeeDeposit, Leaf, NetworkConfig, Payload, PubKey, ShutdownCheckpoint, TxStatus, UpgradeRecord,
+    ValidatedState, ViewRecord,
 };
 use futures::stream::StreamExt;
 use hotshot_query_service::data_source::storage::sql::Write;
@@ -19,7 +21,7 @@ use hotshot_query_service::data_source::{
 };
 use hotshot_types::{
     consensus::CommitmentMap,
-    data::{DaProposal, QuorumProposal, VidDisperseShare},
+    data::{DaProposal, EpochNumber, QuorumProposal, VidDisperseShare},
     event::{Event, EventType, HotShotAction, LeafInfo},
     message::Proposal,
     simple_certificate::{QuorumCertificate, UpgradeCertificate},
@@ -32,21 +34,38 @@ use jf_vid::VidScheme;
 use sqlx::Row;
 use sqlx::{query, Executor};
 use std::sync::Arc;
-use std::{collections::BTreeMap, time::Duration};
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
 
+use super::{
+    compression::{self, CompressionOptions},
+    parse_tx_hash, DaProfile,
+};
 use crate::{catchup::SqlStateCatchup, SeqTypes, ViewNumber};
 
-/// Options for Postgres-backed persistence.
-#[derive(Parser, Clone, Derivative, Default)]
+/// Options for SQL-backed persistence.
+///
+/// By default this connects to a Postgres server. When built with the `embedded-db` feature, it
+/// instead uses an embedded SQLite database, and the Postgres connection options are ignored.
+///
+/// The backend is fixed when the binary is built, not chosen by the scheme of the database URI:
+/// the storage layer of hotshot-query-service, which this builds on, is compiled for exactly one of
+/// the two databases. A URI for the other database is rejected. The schema is kept in two migration
+/// trees, `api/migrations/postgres` and `api/migrations/sqlite`, and every schema change must be
+/// added to both; `scripts/check-migrations` checks this in CI.
+#[derive(Parser, Clone, Derivative)]
 #[derivative(Debug)]
 pub struct Options {
-    /// Postgres URI.
+    /// Database URI.
     ///
     /// This is a shorthand for setting a number of other options all at once. The URI has the
     /// following format ([brackets] indicate optional segments):
     ///
     ///   postgres[ql]://[username[:password]@][host[:port],]/database[?parameter_list]
     ///
+    /// or, when built with the `embedded-db` feature:
+    ///
+    ///   sqlite://path/to/database.sqlite
+    ///
     /// Options set explicitly via other env vars or flags will take precedence, so you can use this
     /// URI to set a baseline and then use other parameters to override or add configuration. In
     /// addition, there are some parameters which cannot be set via the URI, such as TLS.
@@ -54,6 +73,12 @@ pub struct Options {
     #[derivative(Debug = "ignore")]
     pub(crate) uri: Option<String>,
 
+    /// Path to the SQLite database file.
+    ///
+    /// Only used when built with the `embedded-db` feature.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_SQLITE_PATH")]
+    pub(crate) path: Option<PathBuf>,
+
     /// Hostname for the remote Postgres database server.
     #[clap(long, env = "ESPRESSO_SEQUENCER_POSTGRES_HOST")]
     pub(crate) host: Option<String>,
@@ -98,6 +123,10 @@ pub struct Options {
     #[clap(long, env = "ESPRESSO_SEQUENCER_STORE_UNDECIDED_STATE", hide = true)]
     pub(crate) store_undecided_state: bool,
 
+    /// Compression of stored payloads.
+    #[clap(flatten)]
+    pub(crate) compression: CompressionOptions,
+
     /// Specifies the maximum number of concurrent fetch requests allowed from peers.
     #[clap(long, env = "ESPRESSO_SEQUENCER_FETCH_RATE_LIMIT")]
     pub(crate) fetch_rate_limit: Option<usize>,
@@ -157,35 +186,151 @@ pub struct Options {
         default_value = "25"
     )]
     pub(crate) max_connections: u32,
+
+    /// The maximum time to wait for a database connection.
+    ///
+    /// Once `max` connections are in use, an attempt to acquire a connection (or begin a
+    /// transaction) fails if none is released within this duration, instead of waiting
+    /// indefinitely.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_POSTGRES_ACQUIRE_TIMEOUT",
+        value_parser = parse_duration,
+        default_value = "30s"
+    )]
+    pub(crate) acquire_timeout: Duration,
+
+    /// The maximum time a single statement may run before the server cancels it.
+    ///
+    /// This keeps a few expensive queries from holding on to connections while other requests
+    /// wait for one. By default, statements are not time limited. Only supported for Postgres.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_POSTGRES_STATEMENT_TIMEOUT",
+        value_parser = parse_duration
+    )]
+    pub(crate) statement_timeout: Option<Duration>,
+
+    /// Do not proactively fetch missing data from peers.
+    ///
+    /// This is set when payloads are being pruned, so that pruned payloads are not fetched again.
+    #[clap(skip)]
+    pub(crate) disable_proactive_fetching: bool,
+
+    /// Only read from the database, which is populated by another node.
+    ///
+    /// This is set when running as a read replica. Migrations, pruning and fetching of missing
+    /// data are all disabled, since they would write to the database.
+    #[clap(skip)]
+    pub(crate) read_only: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+/// Options for serving the query API from a read-only replica of another node's database.
+#[derive(Parser, Clone, Debug, Default)]
+pub struct ReplicaOptions {
+    #[clap(flatten)]
+    pub storage: Options,
+}
+
+impl From<ReplicaOptions> for Options {
+    fn from(opt: ReplicaOptions) -> Self {
+        Self {
+            read_only: true,
+            disable_proactive_fetching: true,
+            ..opt.storage
+        }
+    }
 }
 
 impl TryFrom<Options> for Config {
     type Error = anyhow::Error;
 
     fn try_from(opt: Options) -> Result<Self, Self::Error> {
+        if let Some((scheme, _)) = opt.uri.as_deref().and_then(|uri| uri.split_once("://")) {
+            #[cfg(not(feature = "embedded-db"))]
+            ensure!(
+                scheme != "sqlite",
+                "SQLite database URI given, but this binary was built for Postgres; SQLite \
+                 requires a build with the `embedded-db` feature"
+            );
+            #[cfg(feature = "embedded-db")]
+            ensure!(
+                scheme == "sqlite",
+                "{scheme} database URI given, but this binary was built for SQLite with the \
+                 `embedded-db` feature"
+            );
+        }
+
         let mut cfg = match opt.uri {
             Some(uri) => uri.parse()?,
             None => Self::default(),
         };
-        cfg = cfg.migrations(include_migrations!("$CARGO_MANIFEST_DIR/api/migrations"));
 
-        if let Some(host) = opt.host {
-            cfg = cfg.host(host);
-        }
-        if let Some(port) = opt.port {
-            cfg = cfg.port(port);
-        }
-        if let Some(database) = &opt.database {
-            cfg = cfg.database(database);
-        }
-        if let Some(user) = &opt.user {
-            cfg = cfg.user(user);
+        #[cfg(not(feature = "embedded-db"))]
+        {
+            cfg = cfg.migrations(include_migrations!(
+                "$CARGO_MANIFEST_DIR/api/migrations/postgres"
+            ));
+
+            if let Some(host) = opt.host {
+                cfg = cfg.host(host);
+            }
+            if let Some(port) = opt.port {
+                cfg = cfg.port(port);
+            }
+            if let Some(database) = &opt.database {
+                cfg = cfg.database(database);
+            }
+            if let Some(user) = &opt.user {
+                cfg = cfg.user(user);
+            }
+            if let Some(password) = &opt.password {
+                cfg = cfg.password(password);
+            }
+            if opt.use_tls {
+                cfg = cfg.tls();
+            }
+            if let Some(timeout) = opt.statement_timeout {
+                cfg = cfg.statement_timeout(timeout);
+            }
         }
-        if let Some(password) = &opt.password {
-            cfg = cfg.password(password);
+
+        #[cfg(feature = "embedded-db")]
+        {
+            cfg = cfg.migrations(include_migrations!(
+                "$CARGO_MANIFEST_DIR/api/migrations/sqlite"
+            ));
+
+            if let Some(path) = opt.path {
+                cfg = cfg.db_path(path);
+            }
+            if opt.host.is_some() || opt.port.is_some() || opt.database.is_some() || opt.use_tls {
+                tracing::warn!("Postgres connection options are ignored when using SQLite");
+            }
+            if opt.statement_timeout.is_some() {
+                tracing::warn!("statement timeout is not supported with SQLite");
+            }
         }
-        if opt.use_tls {
-            cfg = cfg.tls();
+
+        cfg = cfg
+            .min_connections(opt.min_connections)
+            .max_connections(opt.max_connections)
+            .idle_connection_timeout(opt.idle_connection_timeout)
+            .connection_timeout(opt.connection_timeout)
+            .acquire_timeout(opt.acquire_timeout);
+
+        if opt.read_only {
+            // The schema is managed by the node populating the database.
+            if opt.prune || opt.archive {
+                tracing::warn!("pruning and archiving are ignored on a read replica");
+            }
+            return Ok(cfg.no_migrations());
         }
 
         if opt.prune {
@@ -273,6 +418,14 @@ impl From<PruningOptions> for PrunerCfg {
     }
 }
 
+impl DaProfile for Options {
+    fn da_only(mut self) -> Self {
+        self.store_undecided_state = false;
+        self.archive = false;
+        self
+    }
+}
+
 #[async_trait]
 impl PersistenceOptions for Options {
     type Persistence = Persistence;
@@ -280,6 +433,7 @@ impl PersistenceOptions for Options {
     async fn create(self) -> anyhow::Result<Persistence> {
         let persistence = Persistence {
             store_undecided_state: self.store_undecided_state,
+            compression: self.compression,
             db: SqlStorage::connect(self.try_into()?).await?,
         };
         persistence.migrate_quorum_proposal_leaf_hashes().await?;
@@ -292,10 +446,11 @@ impl PersistenceOptions for Options {
     }
 }
 
-/// Postgres-backed persistence.
+/// SQL-backed persistence.
 pub struct Persistence {
     db: SqlStorage,
     store_undecided_state: bool,
+    compression: CompressionOptions,
 }
 
 impl Persistence {
@@ -343,7 +498,8 @@ impl SequencerPersistence for Persistence {
     async fn load_config(&self) -> anyhow::Result<Option<NetworkConfig>> {
         tracing::info!("loading config from Postgres");
 
-        // Select the most recent config (although there should only be one).
+        // Select the most recent config. The config is saved again when node-local parameters, such
+        // as an adaptive view timeout, change.
         let Some(row) = self
             .db
             .read()
@@ -494,7 +650,7 @@ impl SequencerPersistence for Persistence {
         result
             .map(|row| {
                 let bytes: Vec<u8> = row.get("data");
-                anyhow::Result::<_>::Ok(bincode::deserialize(&bytes)?)
+                anyhow::Result::<_>::Ok(bincode::deserialize(&compression::decode(&bytes)?)?)
             })
             .transpose()
     }
@@ -583,7 +739,9 @@ impl SequencerPersistence for Persistence {
     ) -> anyhow::Result<()> {
         let data = &proposal.data;
         let view = data.view_number().u64();
-        let data_bytes = bincode::serialize(proposal).unwrap();
+        let data_bytes = self
+            .compression
+            .encode(bincode::serialize(proposal).unwrap())?;
 
         let mut tx = self.db.write().await?;
         tx.upsert(
@@ -600,9 +758,15 @@ impl SequencerPersistence for Persistence {
         if !matches!(action, HotShotAction::Propose | HotShotAction::Vote) {
             return Ok(());
         }
+        #[cfg(not(feature = "embedded-db"))]
         let stmt = "
         INSERT INTO highest_voted_view (id, view) VALUES (0, $1)
         ON CONFLICT (id) DO UPDATE SET view = GREATEST(highest_voted_view.view, excluded.view)";
+        // SQLite has no `GREATEST`; its multi-argument `MAX` is the scalar equivalent.
+        #[cfg(feature = "embedded-db")]
+        let stmt = "
+        INSERT INTO highest_voted_view (id, view) VALUES (0, $1)
+        ON CONFLICT (id) DO UPDATE SET view = MAX(highest_voted_view.view, excluded.view)";
 
         let mut tx = self.db.write().await?;
         tx.execute_one_with_retries(stmt, (view.u64() as i64,))
@@ -687,6 +851,574 @@ impl SequencerPersistence for Persistence {
         .await?;
         tx.commit().await
     }
+
+    async fn store_tx_status(
+        &self,
+        hash: Commitment<espresso_types::Transaction>,
+        status: TxStatus,
+    ) -> anyhow::Result<()> {
+        let status_bytes = bincode::serialize(&status).context("serializing tx status")?;
+        let mut tx = self.db.write().await?;
+        tx.upsert(
+            "tx_status",
+            ["hash", "data"],
+            ["hash"],
+            [(hash.to_string(), status_bytes)],
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    async fn load_tx_status(
+        &self,
+        hash: Commitment<espresso_types::Transaction>,
+    ) -> anyhow::Result<Option<TxStatus>> {
+        let result = self
+            .db
+            .read()
+            .await?
+            .fetch_optional(
+                query("SELECT data FROM tx_status WHERE hash = $1").bind(hash.to_string()),
+            )
+            .await?;
+
+        result
+            .map(|row| {
+                let bytes: Vec<u8> = row.get("data");
+                anyhow::Result::<_>::Ok(bincode::deserialize(&bytes)?)
+            })
+            .transpose()
+    }
+
+    async fn load_all_tx_statuses(
+        &self,
+    ) -> anyhow::Result<Vec<(Commitment<espresso_types::Transaction>, TxStatus)>> {
+        let mut tx = self.db.read().await?;
+        query_as::<(String, Vec<u8>)>("SELECT hash, data FROM tx_status")
+            .fetch_all(tx.as_mut())
+            .await?
+            .into_iter()
+            .map(|(hash, bytes)| {
+                let status = bincode::deserialize(&bytes).context("malformed tx status")?;
+                Ok((parse_tx_hash(&hash)?, status))
+            })
+            .collect()
+    }
+
+    async fn append_mempool_txs(&self, txs: &[espresso_types::Transaction]) -> anyhow::Result<()> {
+        if txs.is_empty() {
+            return Ok(());
+        }
+        let values = txs
+            .iter()
+            .map(|tx| {
+                let bytes = bincode::serialize(tx).context("serializing transaction")?;
+                anyhow::Result::<_>::Ok((tx.commit().to_string(), bytes))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut tx = self.db.write().await?;
+        tx.upsert("mempool", ["hash", "data"], ["hash"], values)
+            .await?;
+        tx.commit().await
+    }
+
+    async fn remove_mempool_txs(
+        &self,
+        hashes: &[Commitment<espresso_types::Transaction>],
+    ) -> anyhow::Result<()> {
+        let mut tx = self.db.write().await?;
+        for hash in hashes {
+            tx.execute(query("DELETE FROM mempool WHERE hash = $1").bind(hash.to_string()))
+                .await?;
+        }
+        tx.commit().await
+    }
+
+    async fn load_mempool(&self) -> anyhow::Result<Vec<espresso_types::Transaction>> {
+        let rows = self
+            .db
+            .read()
+            .await?
+            .fetch_all("SELECT data FROM mempool")
+            .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let bytes: Vec<u8> = row.get("data");
+                Ok(bincode::deserialize(&bytes).context("deserializing mempool entry")?)
+            })
+            .collect()
+    }
+
+    async fn store_stake_table(&self, stake_table: &EpochStakeTable) -> anyhow::Result<()> {
+        let bytes = bincode::serialize(stake_table).context("serializing stake table")?;
+        let mut tx = self.db.write().await?;
+        tx.upsert(
+            "epoch_stake_table",
+            ["epoch", "data"],
+            ["epoch"],
+            [(stake_table.epoch.u64() as i64, bytes)],
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    async fn load_stake_table(
+        &self,
+        epoch: EpochNumber,
+    ) -> anyhow::Result<Option<EpochStakeTable>> {
+        let result = self
+            .db
+            .read()
+            .await?
+            .fetch_optional(
+                query("SELECT data FROM epoch_stake_table WHERE epoch = $1")
+                    .bind(epoch.u64() as i64),
+            )
+            .await?;
+
+        result
+            .map(|row| {
+                let bytes: Vec<u8> = row.get("data");
+                anyhow::Result::<_>::Ok(bincode::deserialize(&bytes)?)
+            })
+            .transpose()
+    }
+
+    async fn load_all_stake_tables(&self) -> anyhow::Result<Vec<EpochStakeTable>> {
+        let rows = self
+            .db
+            .read()
+            .await?
+            .fetch_all("SELECT data FROM epoch_stake_table ORDER BY epoch")
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                let bytes: Vec<u8> = row.get("data");
+                bincode::deserialize(&bytes).context("deserializing stake table")
+            })
+            .collect()
+    }
+
+    async fn store_da_pointer(&self, pointer: &DaPointer) -> anyhow::Result<()> {
+        let bytes = bincode::serialize(pointer).context("serializing DA pointer")?;
+        let mut tx = self.db.write().await?;
+        tx.upsert(
+            "da_pointer",
+            ["height", "data"],
+            ["height"],
+            [(pointer.height as i64, bytes)],
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    async fn load_da_pointer(&self, height: u64) -> anyhow::Result<Option<DaPointer>> {
+        let result = self
+            .db
+            .read()
+            .await?
+            .fetch_optional(
+                query("SELECT data FROM da_pointer WHERE height = $1").bind(height as i64),
+            )
+            .await?;
+
+        result
+            .map(|row| {
+                let bytes: Vec<u8> = row.get("data");
+                anyhow::Result::<_>::Ok(bincode::deserialize(&bytes)?)
+            })
+            .transpose()
+    }
+
+    async fn load_all_da_pointers(&self) -> anyhow::Result<Vec<DaPointer>> {
+        let rows = self
+            .db
+            .read()
+            .await?
+            .fetch_all("SELECT data FROM da_pointer ORDER BY height")
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                let bytes: Vec<u8> = row.get("data");
+                bincode::deserialize(&bytes).context("deserializing DA pointer")
+            })
+            .collect()
+    }
+
+    async fn store_fee_deposits(
+        &self,
+        deposits: &[FeeDeposit],
+        l1_block: u64,
+    ) -> anyhow::Result<()> {
+        let values = deposits
+            .iter()
+            .map(|deposit| {
+                let bytes = bincode::serialize(deposit).context("serializing fee deposit")?;
+                anyhow::Result::<_>::Ok((
+                    deposit.l1_block as i64,
+                    deposit.log_index as i64,
+                    deposit.account.0.as_bytes().to_vec(),
+                    bytes,
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut tx = self.db.write().await?;
+        if !values.is_empty() {
+            tx.upsert(
+                "fee_deposit",
+                ["l1_block", "log_index", "account", "data"],
+                ["l1_block", "log_index"],
+                values,
+            )
+            .await?;
+        }
+        tx.upsert(
+            "fee_deposit_scan",
+            ["id", "l1_block"],
+            ["id"],
+            [(0_i32, l1_block as i64)],
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    async fn load_fee_deposits_l1_block(&self) -> anyhow::Result<Option<u64>> {
+        let row = self
+            .db
+            .read()
+            .await?
+            .fetch_optional("SELECT l1_block FROM fee_deposit_scan WHERE id = 0")
+            .await?;
+        Ok(row.map(|row| row.get::<i64, _>("l1_block") as u64))
+    }
+
+    async fn load_fee_deposits(
+        &self,
+        account: FeeAccount,
+        offset: u64,
+        limit: u64,
+    ) -> anyhow::Result<Vec<FeeDeposit>> {
+        let rows = self
+            .db
+            .read()
+            .await?
+            .fetch_all(
+                query(
+                    "SELECT data FROM fee_deposit WHERE account = $1
+                      ORDER BY l1_block, log_index
+                      LIMIT $2 OFFSET $3",
+                )
+                .bind(account.0.as_bytes().to_vec())
+                .bind(limit as i64)
+                .bind(offset as i64),
+            )
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                let bytes: Vec<u8> = row.get("data");
+                bincode::deserialize(&bytes).context("deserializing fee deposit")
+            })
+            .collect()
+    }
+
+    async fn load_all_fee_deposits(&self) -> anyhow::Result<Vec<FeeDeposit>> {
+        let rows = self
+            .db
+            .read()
+            .await?
+            .fetch_all("SELECT data FROM fee_deposit ORDER BY l1_block, log_index")
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                let bytes: Vec<u8> = row.get("data");
+                bincode::deserialize(&bytes).context("deserializing fee deposit")
+            })
+            .collect()
+    }
+
+    async fn store_builder_fees(&self, totals: &[BuilderFeeTotals]) -> anyhow::Result<()> {
+        if totals.is_empty() {
+            return Ok(());
+        }
+        let values = totals
+            .iter()
+            .map(|account| {
+                let bytes = bincode::serialize(account).context("serializing builder fees")?;
+                anyhow::Result::<_>::Ok((account.account.0.as_bytes().to_vec(), bytes))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut tx = self.db.write().await?;
+        tx.upsert("builder_fees", ["account", "data"], ["account"], values)
+            .await?;
+        tx.commit().await
+    }
+
+    async fn load_builder_fees(
+        &self,
+        account: FeeAccount,
+    ) -> anyhow::Result<Option<BuilderFeeTotals>> {
+        let Some(row) = self
+            .db
+            .read()
+            .await?
+            .fetch_optional(
+                query("SELECT data FROM builder_fees WHERE account = $1")
+                    .bind(account.0.as_bytes().to_vec()),
+            )
+            .await?
+        else {
+            return Ok(None);
+        };
+        let bytes: Vec<u8> = row.get("data");
+        Ok(Some(
+            bincode::deserialize(&bytes).context("deserializing builder fees")?,
+        ))
+    }
+
+    async fn load_all_builder_fees(&self) -> anyhow::Result<Vec<BuilderFeeTotals>> {
+        let rows = self
+            .db
+            .read()
+            .await?
+            .fetch_all("SELECT data FROM builder_fees")
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                let bytes: Vec<u8> = row.get("data");
+                bincode::deserialize(&bytes).context("deserializing builder fees")
+            })
+            .collect()
+    }
+
+    async fn store_upgrade(&self, upgrade: &UpgradeRecord) -> anyhow::Result<()> {
+        let bytes = bincode::serialize(upgrade).context("serializing upgrade")?;
+        let mut tx = self.db.write().await?;
+        tx.upsert(
+            "upgrade_status",
+            ["version", "data"],
+            ["version"],
+            [(upgrade.version.to_string(), bytes)],
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    async fn load_upgrades(&self) -> anyhow::Result<Vec<UpgradeRecord>> {
+        let rows = self
+            .db
+            .read()
+            .await?
+            .fetch_all("SELECT data FROM upgrade_status")
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                let bytes: Vec<u8> = row.get("data");
+                Ok(bincode::deserialize(&bytes).context("deserializing upgrade")?)
+            })
+            .collect()
+    }
+
+    async fn store_libp2p_peers(&self, peers: &[String]) -> anyhow::Result<()> {
+        let bytes = bincode::serialize(peers).context("serializing libp2p peers")?;
+        let mut tx = self.db.write().await?;
+        tx.upsert("libp2p_peers", ["id", "data"], ["id"], [(0_i32, bytes)])
+            .await?;
+        tx.commit().await
+    }
+
+    async fn load_libp2p_peers(&self) -> anyhow::Result<Vec<String>> {
+        let Some(row) = self
+            .db
+            .read()
+            .await?
+            .fetch_optional("SELECT data FROM libp2p_peers WHERE id = 0")
+            .await?
+        else {
+            return Ok(vec![]);
+        };
+        let bytes: Vec<u8> = row.get("data");
+        bincode::deserialize(&bytes).context("deserializing libp2p peers")
+    }
+
+    async fn store_banned_peers(&self, peers: &[PubKey]) -> anyhow::Result<()> {
+        let bytes = bincode::serialize(peers).context("serializing banned peers")?;
+        let mut tx = self.db.write().await?;
+        tx.upsert("banned_peers", ["id", "data"], ["id"], [(0_i32, bytes)])
+            .await?;
+        tx.commit().await
+    }
+
+    async fn store_shutdown_checkpoint(
+        &self,
+        checkpoint: &ShutdownCheckpoint,
+    ) -> anyhow::Result<()> {
+        let bytes = bincode::serialize(checkpoint).context("serializing shutdown checkpoint")?;
+        let mut tx = self.db.write().await?;
+        tx.upsert(
+            "shutdown_checkpoint",
+            ["id", "data"],
+            ["id"],
+            [(0_i32, bytes)],
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    async fn take_shutdown_checkpoint(&self) -> anyhow::Result<Option<ShutdownCheckpoint>> {
+        let mut tx = self.db.write().await?;
+        let Some(row) = tx
+            .fetch_optional("DELETE FROM shutdown_checkpoint WHERE id = 0 RETURNING data")
+            .await?
+        else {
+            return Ok(None);
+        };
+        tx.commit().await?;
+        let bytes: Vec<u8> = row.get("data");
+        Ok(Some(
+            bincode::deserialize(&bytes).context("deserializing shutdown checkpoint")?,
+        ))
+    }
+
+    async fn store_view_record(&self, record: &ViewRecord) -> anyhow::Result<()> {
+        let bytes = bincode::serialize(record).context("serializing view record")?;
+        let mut tx = self.db.write().await?;
+        tx.upsert(
+            "view_log",
+            ["view", "data"],
+            ["view"],
+            [(record.view as i64, bytes)],
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    async fn load_view_records(&self, from: u64, until: u64) -> anyhow::Result<Vec<ViewRecord>> {
+        self.db
+            .read()
+            .await?
+            .fetch_all(
+                query("SELECT data FROM view_log WHERE view >= $1 AND view < $2 ORDER BY view")
+                    .bind(from as i64)
+                    .bind(until as i64),
+            )
+            .await?
+            .into_iter()
+            .map(|row| {
+                let bytes: Vec<u8> = row.get("data");
+                bincode::deserialize(&bytes).context("deserializing view record")
+            })
+            .collect()
+    }
+
+    async fn prune_view_records(&self, view: u64) -> anyhow::Result<()> {
+        let mut tx = self.db.write().await?;
+        tx.execute(query("DELETE FROM view_log WHERE view < $1").bind(view as i64))
+            .await?;
+        tx.commit().await
+    }
+
+    async fn store_decide_events(&self, events: &[DecideEvent]) -> anyhow::Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let rows = events
+            .iter()
+            .map(|event| {
+                let bytes = bincode::serialize(event).context("serializing decide event")?;
+                Ok((event.height as i64, bytes))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut tx = self.db.write().await?;
+        tx.upsert("decide_event_log", ["height", "data"], ["height"], rows)
+            .await?;
+        tx.commit().await
+    }
+
+    async fn load_decide_events(
+        &self,
+        from: u64,
+        limit: usize,
+    ) -> anyhow::Result<Vec<DecideEvent>> {
+        self.db
+            .read()
+            .await?
+            .fetch_all(
+                query(
+                    "SELECT data FROM decide_event_log WHERE height >= $1 ORDER BY height LIMIT $2",
+                )
+                .bind(from as i64)
+                .bind(limit as i64),
+            )
+            .await?
+            .into_iter()
+            .map(|row| {
+                let bytes: Vec<u8> = row.get("data");
+                bincode::deserialize(&bytes).context("deserializing decide event")
+            })
+            .collect()
+    }
+
+    async fn prune_decide_events(&self, height: u64) -> anyhow::Result<()> {
+        let mut tx = self.db.write().await?;
+        tx.execute(query("DELETE FROM decide_event_log WHERE height < $1").bind(height as i64))
+            .await?;
+        tx.commit().await
+    }
+
+    async fn store_anchor_state(
+        &self,
+        view: ViewNumber,
+        state: &ValidatedState,
+    ) -> anyhow::Result<()> {
+        let bytes = bincode::serialize(state).context("serializing anchor state")?;
+        let mut tx = self.db.write().await?;
+        // Never replace the state with that of an older anchor leaf.
+        tx.execute(
+            query(
+                "INSERT INTO anchor_state (id, view, data) VALUES (0, $1, $2)
+                 ON CONFLICT (id) DO UPDATE SET view = excluded.view, data = excluded.data
+                 WHERE excluded.view > anchor_state.view",
+            )
+            .bind(view.u64() as i64)
+            .bind(bytes),
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    async fn load_anchor_state(&self) -> anyhow::Result<Option<(ViewNumber, ValidatedState)>> {
+        let Some(row) = self
+            .db
+            .read()
+            .await?
+            .fetch_optional("SELECT view, data FROM anchor_state WHERE id = 0")
+            .await?
+        else {
+            return Ok(None);
+        };
+        let view: i64 = row.get("view");
+        let bytes: Vec<u8> = row.get("data");
+        let state = bincode::deserialize(&bytes).context("deserializing anchor state")?;
+        Ok(Some((ViewNumber::new(view as u64), state)))
+    }
+
+    async fn load_banned_peers(&self) -> anyhow::Result<Vec<PubKey>> {
+        let Some(row) = self
+            .db
+            .read()
+            .await?
+            .fetch_optional("SELECT data FROM banned_peers WHERE id = 0")
+            .await?
+        else {
+            return Ok(vec![]);
+        };
+        let bytes: Vec<u8> = row.get("data");
+        bincode::deserialize(&bytes).context("deserializing banned peers")
+    }
 }
 
 async fn collect_garbage(
@@ -723,8 +1455,9 @@ async fn collect_garbage(
         .map(|row| {
             let view: i64 = row.get("view");
             let data: Vec<u8> = row.get("data");
-            let da_proposal =
-                bincode::deserialize::<Proposal<SeqTypes, DaProposal<SeqTypes>>>(&data)?;
+            let da_proposal = bincode::deserialize::<Proposal<SeqTypes, DaProposal<SeqTypes>>>(
+                &compression::decode(&data)?,
+            )?;
             Ok((view as u64, da_proposal.data))
         })
         .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
@@ -868,16 +1601,20 @@ mod testing {
         }
 
         async fn connect(db: &Self::Storage) -> Self {
-            Options {
+            #[cfg(not(feature = "embedded-db"))]
+            let opt = Options {
                 port: Some(db.port()),
                 host: Some(db.host()),
                 user: Some("postgres".into()),
                 password: Some("password".into()),
                 ..Default::default()
-            }
-            .create()
-            .await
-            .unwrap()
+            };
+            #[cfg(feature = "embedded-db")]
+            let opt = Options {
+                path: Some(db.path()),
+                ..Default::default()
+            };
+            opt.create().await.unwrap()
         }
     }
 }
@@ -901,6 +1638,18 @@ mod test {
     use hotshot_example_types::node_types::TestVersions;
     use hotshot_types::traits::signature_key::SignatureKey;
 
+    #[test]
+    fn test_uri_for_other_backend_rejected() {
+        let opt = |uri: &str| Options {
+            uri: Some(uri.into()),
+            ..Default::default()
+        };
+        #[cfg(not(feature = "embedded-db"))]
+        Config::try_from(opt("sqlite://storage.sqlite")).unwrap_err();
+        #[cfg(feature = "embedded-db")]
+        Config::try_from(opt("postgres://user@localhost/storage")).unwrap_err();
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_quorum_proposals_leaf_hash_migration() {
         // Create some quorum proposals to test with.
@@ -259,6 +259,7 @@ impl<S: TestableSequencerDataSource> TestNode<S> {
                     .iter()
                     .map(|port| format!("http://127.0.0.1:{port}").parse().unwrap())
                     .collect(),
+                ..Default::default()
             });
             modules.state = Some(Default::default());
         }
@@ -532,7 +533,10 @@ impl TestNetwork {
                 base_fee: 1.into(),
                 ..Default::default()
             },
-            stake_table: StakeTableConfig { capacity: 10 },
+            stake_table: StakeTableConfig {
+                capacity: 10,
+                nodes: vec![],
+            },
             l1_finalized: L1Finalized::Number { number: 0 },
             header: Default::default(),
             upgrades: Default::default(),
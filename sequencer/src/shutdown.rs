@@ -0,0 +1,99 @@
+//! Coordinated shutdown of a sequencer node.
+//!
+//! Simply killing the process can interrupt the event handler halfway through persisting a
+//! decide, or in the middle of writing a file when using file system storage. Instead, when the
+//! node receives SIGTERM (or Ctrl-C) it shuts down in stages: it stops accepting transactions,
+//! stops consensus, lets the event handler finish persisting the events it has already received,
+//! and finally records a [`ShutdownCheckpoint`](espresso_types::ShutdownCheckpoint). On the next
+//! startup, a checkpoint matching the stored consensus state means there are no decided leaves
+//! left to recover, so the recovery pass can be skipped.
+
+use std::{sync::Arc, time::Duration};
+
+use clap::Parser;
+use espresso_types::parse_duration;
+use tokio::sync::watch;
+
+#[derive(Clone, Copy, Debug, Parser)]
+pub struct ShutdownConfig {
+    /// Maximum time to wait for in-flight events to be persisted when shutting down.
+    ///
+    /// If this elapses, the node exits anyway, without recording a clean shutdown, and the next
+    /// startup recovers from whatever state was persisted.
+    #[clap(
+        long = "shutdown-timeout",
+        env = "ESPRESSO_SEQUENCER_SHUTDOWN_TIMEOUT",
+        default_value = "30s",
+        value_parser = parse_duration,
+    )]
+    pub timeout: Duration,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+/// A flag which is raised once, and can be awaited by any number of tasks.
+#[derive(Clone, Debug)]
+pub(crate) struct Flag(Arc<watch::Sender<bool>>);
+
+impl Default for Flag {
+    fn default() -> Self {
+        Self(Arc::new(watch::Sender::new(false)))
+    }
+}
+
+impl Flag {
+    /// Raise the flag, waking all tasks waiting on it.
+    pub(crate) fn raise(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub(crate) fn is_raised(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Wait until the flag is raised.
+    pub(crate) async fn wait(&self) {
+        let mut rx = self.0.subscribe();
+        // We hold the sender, so the channel cannot be closed.
+        rx.wait_for(|raised| *raised).await.ok();
+    }
+}
+
+/// Signals used to coordinate a graceful shutdown between a node and its background tasks.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Shutdown {
+    /// Raised when the node stops accepting new transactions.
+    pub(crate) draining: Flag,
+    /// Raised once consensus has stopped, telling the event handler to checkpoint and exit.
+    pub(crate) stopped: Flag,
+    /// Raised by the event handler once it has persisted all its events.
+    pub(crate) done: Flag,
+}
+
+/// Wait for the process to be asked to terminate, with either SIGTERM or Ctrl-C.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = sigterm.recv() => tracing::warn!("received SIGTERM"),
+                    _ = tokio::signal::ctrl_c() => tracing::warn!("received Ctrl-C"),
+                }
+                return;
+            }
+            Err(err) => tracing::error!("failed to listen for SIGTERM: {err:#}"),
+        }
+    }
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        tracing::error!("failed to listen for Ctrl-C: {err:#}");
+        futures::future::pending::<()>().await;
+    }
+    tracing::warn!("received Ctrl-C");
+}
@@ -0,0 +1,296 @@
+//! Portable snapshots of node state.
+//!
+//! Bootstrapping a new node from genesis requires replaying (or fetching) the entire history of the
+//! chain before the node has the state it needs to participate in consensus or answer state
+//! queries. A [`Snapshot`] captures everything a node needs to start from a recent decided block
+//! instead: the decided leaf and its QC, any decided upgrade certificate, and (for storage backends
+//! which persist merklized state) the full fee state and blocks frontier as of that leaf.
+//!
+//! A snapshot can be exported from the storage of a stopped node and imported into empty storage of
+//! any backend, after which the node can be started normally and will resume from the snapshot
+//! leaf. The network config is deliberately not part of the snapshot, since it contains the keys of
+//! the node it was taken from; a node restored from a snapshot should be started with
+//! `--config-peers` so that it fetches the config from the network.
+
+use std::{
+    io::{Read, Write},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use committable::Committable;
+use espresso_types::{
+    v0::traits::{NullEventConsumer, PersistenceOptions, SequencerPersistence},
+    Leaf, ValidatedState,
+};
+use hotshot::types::{Event, EventType};
+use hotshot_query_service::{
+    availability::AvailabilityDataSource, data_source::UpdateDataSource, status::StatusDataSource,
+};
+use hotshot_types::{
+    event::LeafInfo,
+    simple_certificate::{QuorumCertificate, UpgradeCertificate},
+};
+use jf_merkle_tree::MerkleTreeScheme;
+use serde::{Deserialize, Serialize};
+use tokio::time::timeout;
+
+use crate::{
+    api::data_source::{DataSourceOptions, SequencerDataSource},
+    SeqTypes,
+};
+
+/// Magic bytes identifying a snapshot archive.
+const MAGIC: &[u8; 4] = b"ESNP";
+
+/// Version of the snapshot archive format.
+///
+/// This must be incremented whenever the serialization of [`Snapshot`] changes.
+const FORMAT_VERSION: u32 = 1;
+
+/// How long to wait for the snapshot leaf to be loaded from query storage.
+const LEAF_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The state of a node as of a decided leaf.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The decided leaf the snapshot was taken at.
+    pub leaf: Leaf,
+    /// The QC justifying `leaf`.
+    pub qc: QuorumCertificate<SeqTypes>,
+    /// The latest decided upgrade certificate, if any.
+    pub upgrade_certificate: Option<UpgradeCertificate<SeqTypes>>,
+    /// The full merklized state after applying `leaf`.
+    ///
+    /// This is only present if the snapshot was exported from storage which persists merklized
+    /// state.
+    pub state: Option<ValidatedState>,
+}
+
+impl Snapshot {
+    /// The height of the block this snapshot was taken at.
+    pub fn height(&self) -> u64 {
+        self.leaf.height()
+    }
+
+    /// Export a snapshot from the storage described by `opt`.
+    ///
+    /// The snapshot is taken at block `height`, or if no height is given, at the latest block which
+    /// has been decided and added to the query storage. The node using this storage must not be
+    /// running.
+    pub async fn export<O: DataSourceOptions>(opt: O, height: Option<u64>) -> anyhow::Result<Self> {
+        let persistence = opt.clone().create().await?;
+        let data_source =
+            O::DataSource::create(opt.query_options(), Default::default(), false).await?;
+
+        let height = match height {
+            Some(height) => height,
+            None => {
+                let (anchor, _) = persistence
+                    .load_anchor_leaf()
+                    .await?
+                    .context("storage has no decided leaf to snapshot")?;
+                let block_height = data_source.block_height().await? as u64;
+                ensure!(block_height > 0, "query storage has no blocks to snapshot");
+                anchor.height().min(block_height - 1)
+            }
+        };
+        tracing::info!(height, "exporting snapshot");
+
+        let leaf = timeout(LEAF_TIMEOUT, data_source.get_leaf(height as usize).await)
+            .await
+            .map_err(|_| anyhow!("leaf {height} is not available in query storage"))?;
+        let state = data_source
+            .load_state_snapshot(height)
+            .await
+            .context(format!("loading state at height {height}"))?;
+        if state.is_none() {
+            tracing::warn!(
+                height,
+                "storage does not persist merklized state; snapshot will not include state"
+            );
+        }
+
+        let snapshot = Self {
+            leaf: leaf.leaf().clone(),
+            qc: leaf.qc().clone(),
+            upgrade_certificate: persistence.load_upgrade_certificate().await?,
+            state,
+        };
+        snapshot.validate()?;
+        Ok(snapshot)
+    }
+
+    /// Restore this snapshot into the storage described by `opt`.
+    ///
+    /// The consensus storage must be empty. A node started with this storage will resume from the
+    /// snapshot leaf.
+    pub async fn import<O: DataSourceOptions>(&self, opt: O) -> anyhow::Result<()> {
+        self.validate()?;
+        let height = self.height();
+        let view = self.leaf.view_number();
+        tracing::info!(height, ?view, "importing snapshot");
+
+        let persistence = opt.clone().create().await?;
+        if let Some((leaf, _)) = persistence.load_anchor_leaf().await? {
+            bail!(
+                "storage already has decided leaf {}; reset it before importing a snapshot",
+                leaf.height()
+            );
+        }
+
+        // Populate query storage first, so that if anything goes wrong, consensus storage is still
+        // empty and the import can simply be retried.
+        let data_source =
+            O::DataSource::create(opt.query_options(), Default::default(), false).await?;
+        let info = LeafInfo {
+            leaf: self.leaf.clone(),
+            vid_share: None,
+            state: Default::default(),
+            delta: None,
+        };
+        let event = Event {
+            view_number: view,
+            event: EventType::Decide {
+                leaf_chain: Arc::new(vec![info.clone()]),
+                qc: Arc::new(self.qc.clone()),
+                block_size: None,
+            },
+        };
+        data_source
+            .update(&event)
+            .await
+            .map_err(|height| anyhow!("failed to add leaf {height} to query storage"))?;
+        if let Some(state) = &self.state {
+            data_source
+                .import_state_snapshot(height, state)
+                .await
+                .context("importing state")?;
+        }
+
+        persistence
+            .append_decided_leaves(view, [(&info, self.qc.clone())], &NullEventConsumer)
+            .await
+            .context("storing decided leaf")?;
+        persistence
+            .store_upgrade_certificate(self.upgrade_certificate.clone())
+            .await
+            .context("storing upgrade certificate")?;
+
+        tracing::info!(height, ?view, "imported snapshot");
+        Ok(())
+    }
+
+    /// Write this snapshot as a portable archive.
+    pub fn write(&self, mut w: impl Write) -> anyhow::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        bincode::serialize_into(w, self).context("serializing snapshot")
+    }
+
+    /// Read a snapshot from an archive written by [`write`](Self::write).
+    pub fn read(mut r: impl Read) -> anyhow::Result<Self> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)
+            .context("reading snapshot header")?;
+        ensure!(&magic == MAGIC, "not a snapshot archive");
+
+        let mut version = [0; 4];
+        r.read_exact(&mut version)
+            .context("reading snapshot format version")?;
+        let version = u32::from_le_bytes(version);
+        ensure!(
+            version == FORMAT_VERSION,
+            "unsupported snapshot format version {version} (expected {FORMAT_VERSION})"
+        );
+
+        let snapshot: Self = bincode::deserialize_from(r).context("malformed snapshot")?;
+        snapshot.validate()?;
+        Ok(snapshot)
+    }
+
+    /// Check that the contents of this snapshot are consistent with each other.
+    fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.qc.view_number == self.leaf.view_number(),
+            "QC for view {:?} does not match leaf view {:?}",
+            self.qc.view_number,
+            self.leaf.view_number(),
+        );
+        ensure!(
+            self.qc.data.leaf_commit == Committable::commit(&self.leaf),
+            "QC does not certify snapshot leaf"
+        );
+
+        if let Some(state) = &self.state {
+            let header = self.leaf.block_header();
+            ensure!(
+                state.fee_merkle_tree.commitment() == header.fee_merkle_tree_root(),
+                "snapshot fee state does not match leaf {}",
+                self.height(),
+            );
+            ensure!(
+                state.block_merkle_tree.commitment() == header.block_merkle_tree_root(),
+                "snapshot block state does not match leaf {}",
+                self.height(),
+            );
+            ensure!(
+                state.chain_config.commit() == header.chain_config().commit(),
+                "snapshot chain config does not match leaf {}",
+                self.height(),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use espresso_types::{FeeAccount, NodeState};
+    use hotshot_example_types::node_types::TestVersions;
+
+    use super::*;
+
+    async fn genesis_snapshot() -> Snapshot {
+        let state = ValidatedState::default();
+        let instance = NodeState::mock();
+        Snapshot {
+            leaf: Leaf::genesis(&state, &instance).await,
+            qc: QuorumCertificate::genesis::<TestVersions>(&state, &instance).await,
+            upgrade_certificate: None,
+            state: Some(state),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_snapshot_roundtrip() {
+        let snapshot = genesis_snapshot().await;
+
+        let mut bytes = vec![];
+        snapshot.write(&mut bytes).unwrap();
+        let decoded = Snapshot::read(bytes.as_slice()).unwrap();
+        assert_eq!(decoded.leaf, snapshot.leaf);
+        assert_eq!(decoded.qc, snapshot.qc);
+        assert_eq!(decoded.state, snapshot.state);
+
+        // Corrupt the header.
+        bytes[0] = 0;
+        Snapshot::read(bytes.as_slice()).unwrap_err();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_snapshot_inconsistent_state() {
+        let mut snapshot = genesis_snapshot().await;
+        snapshot
+            .state
+            .as_mut()
+            .unwrap()
+            .prefund_account(FeeAccount::default(), 1.into());
+
+        let mut bytes = vec![];
+        snapshot.write(&mut bytes).unwrap();
+        let err = Snapshot::read(bytes.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("fee state"), "{err:#}");
+    }
+}
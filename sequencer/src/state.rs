@@ -66,27 +66,75 @@ async fn store_state_update(
     state: &ValidatedState,
     delta: Delta,
 ) -> anyhow::Result<()> {
-    let ValidatedState {
-        fee_merkle_tree,
-        block_merkle_tree,
-        ..
-    } = state;
     let Delta { fees_delta } = delta;
+    store_fee_accounts(tx, block_number, &state.fee_merkle_tree, fees_delta).await?;
+    store_blocks_frontier(tx, block_number, &state.block_merkle_tree).await?;
 
-    // Insert fee merkle tree nodes
-    for delta in fees_delta {
-        let proof = match fee_merkle_tree.universal_lookup(delta) {
+    tracing::debug!(block_number, "updating state height");
+    UpdateStateData::<SeqTypes, _, { BlockMerkleTree::ARITY }>::set_last_state_height(
+        tx,
+        block_number as usize,
+    )
+    .await
+    .context("setting state height")?;
+    Ok(())
+}
+
+/// Store the full merklized state as of block `block_number`.
+///
+/// This is used to seed state storage from a snapshot rather than from genesis. Once it is stored,
+/// [`update_state_storage_loop`] continues updating the state from `block_number`.
+pub(crate) async fn store_state_snapshot(
+    tx: &mut impl SequencerStateUpdate,
+    block_number: u64,
+    state: &ValidatedState,
+) -> anyhow::Result<()> {
+    let accounts = state
+        .fee_merkle_tree
+        .iter()
+        .map(|(account, _)| *account)
+        .collect::<Vec<_>>();
+    store_fee_accounts(tx, block_number, &state.fee_merkle_tree, accounts).await?;
+    if block_number > 0 {
+        store_blocks_frontier(tx, block_number, &state.block_merkle_tree).await?;
+    }
+
+    // Earlier chain config history is not part of the snapshot, so the best we can do is record
+    // the current config as of the snapshot height.
+    let chain_config = state
+        .chain_config
+        .resolve()
+        .context("snapshot state is missing chain config")?;
+    tx.insert_chain_config(block_number, chain_config).await?;
+
+    UpdateStateData::<SeqTypes, _, { BlockMerkleTree::ARITY }>::set_last_state_height(
+        tx,
+        block_number as usize,
+    )
+    .await
+    .context("setting state height")?;
+    Ok(())
+}
+
+async fn store_fee_accounts(
+    tx: &mut impl SequencerStateUpdate,
+    block_number: u64,
+    fee_merkle_tree: &FeeMerkleTree,
+    accounts: impl IntoIterator<Item = FeeAccount>,
+) -> anyhow::Result<()> {
+    for account in accounts {
+        let proof = match fee_merkle_tree.universal_lookup(account) {
             LookupResult::Ok(_, proof) => proof,
             LookupResult::NotFound(proof) => proof,
-            LookupResult::NotInMemory => bail!("missing merkle path for fee account {delta}"),
+            LookupResult::NotInMemory => bail!("missing merkle path for fee account {account}"),
         };
         let path: Vec<usize> =
             <FeeAccount as ToTraversalPath<{ FeeMerkleTree::ARITY }>>::to_traversal_path(
-                &delta,
+                &account,
                 fee_merkle_tree.height(),
             );
 
-        tracing::debug!(%delta, "inserting fee account");
+        tracing::debug!(%account, "inserting fee account");
         UpdateStateData::<SeqTypes, _, { FeeMerkleTree::ARITY }>::insert_merkle_nodes(
             tx,
             proof,
@@ -96,8 +144,14 @@ async fn store_state_update(
         .await
         .context("failed to store fee merkle nodes")?;
     }
+    Ok(())
+}
 
-    // Insert block merkle tree nodes
+async fn store_blocks_frontier(
+    tx: &mut impl SequencerStateUpdate,
+    block_number: u64,
+    block_merkle_tree: &BlockMerkleTree,
+) -> anyhow::Result<()> {
     let (_, proof) = block_merkle_tree
         .lookup(block_number - 1)
         .expect_ok()
@@ -107,25 +161,15 @@ async fn store_state_update(
         block_merkle_tree.height(),
     );
 
-    {
-        tracing::debug!("inserting blocks frontier");
-        UpdateStateData::<SeqTypes, _, { BlockMerkleTree::ARITY }>::insert_merkle_nodes(
-            tx,
-            proof,
-            path,
-            block_number,
-        )
-        .await
-        .context("failed to store block merkle nodes")?;
-    }
-
-    tracing::debug!(block_number, "updating state height");
-    UpdateStateData::<SeqTypes, _, { BlockMerkleTree::ARITY }>::set_last_state_height(
+    tracing::debug!("inserting blocks frontier");
+    UpdateStateData::<SeqTypes, _, { BlockMerkleTree::ARITY }>::insert_merkle_nodes(
         tx,
-        block_number as usize,
+        proof,
+        path,
+        block_number,
     )
     .await
-    .context("setting state height")?;
+    .context("failed to store block merkle nodes")?;
     Ok(())
 }
 
@@ -174,7 +218,7 @@ where
             .resolve()
             .context("failed to resolve to chain config")?;
 
-        tx.insert_chain_config(cf).await?;
+        tx.insert_chain_config(proposed_leaf.height(), cf).await?;
     }
 
     tx.commit().await?;
@@ -195,26 +239,14 @@ where
     );
 
     // Insert fee merkle tree nodes
-    for (account, _) in state.fee_merkle_tree.iter() {
-        let proof = match state.fee_merkle_tree.universal_lookup(account) {
-            LookupResult::Ok(_, proof) => proof,
-            LookupResult::NotFound(proof) => proof,
-            LookupResult::NotInMemory => bail!("missing merkle path for fee account {account}"),
-        };
-        let path: Vec<usize> =
-            <FeeAccount as ToTraversalPath<{ FeeMerkleTree::ARITY }>>::to_traversal_path(
-                account,
-                state.fee_merkle_tree.height(),
-            );
-
-        UpdateStateData::<SeqTypes, _, { FeeMerkleTree::ARITY }>::insert_merkle_nodes(
-            &mut tx, proof, path, 0,
-        )
-        .await
-        .context("failed to store fee merkle nodes")?;
-    }
-
-    tx.insert_chain_config(chain_config).await?;
+    let accounts = state
+        .fee_merkle_tree
+        .iter()
+        .map(|(account, _)| *account)
+        .collect::<Vec<_>>();
+    store_fee_accounts(&mut tx, 0, &state.fee_merkle_tree, accounts).await?;
+
+    tx.insert_chain_config(0, chain_config).await?;
 
     tx.commit().await?;
     Ok(())
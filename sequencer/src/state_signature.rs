@@ -1,6 +1,9 @@
 //! Utilities for generating and storing the most recent light client state signatures.
 
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
 
 use ark_ff::PrimeField;
 use ark_serialize::CanonicalSerialize;
@@ -11,8 +14,7 @@ use hotshot_stake_table::vec_based::StakeTable;
 use hotshot_types::{
     event::LeafInfo,
     light_client::{
-        CircuitField, LightClientState, StateSignature, StateSignatureRequestBody,
-        StateSignatureScheme, StateVerKey,
+        CircuitField, LightClientState, StateSignature, StateSignatureRequestBody, StateVerKey,
     },
     signature_key::BLSPubKey,
     traits::{
@@ -24,23 +26,30 @@ use hotshot_types::{
 };
 use jf_crhf::CRHF;
 use jf_rescue::{crhf::VariableLengthRescueCRHF, RescueError};
-use jf_signature::SignatureScheme;
 use surf_disco::{Client, Url};
 use tide_disco::error::ServerError;
 use vbs::version::StaticVersionType;
 
-use crate::{SeqTypes, StateKeyPair};
+use self::{
+    aggregator::{StateSignatureAggregator, StateSignatureBundleQueryData},
+    signer::Signer,
+};
+use crate::SeqTypes;
 
+/// Aggregation of state signatures from peers into bundles for the light client contract
+pub mod aggregator;
 /// A relay server that's collecting and serving the light client state signatures
 pub mod relay_server;
+/// Signing light client states in process or with a remote signer
+pub mod signer;
 
 /// Capacity for the in memory signature storage.
 const SIGNATURE_STORAGE_CAPACITY: usize = 100;
 
 #[derive(Debug)]
 pub struct StateSigner<ApiVer: StaticVersionType> {
-    /// Key pair for signing a new light client state
-    key_pair: StateKeyPair,
+    /// Signer for new light client states
+    signer: Box<dyn Signer>,
 
     /// The most recent light client state signatures
     signatures: RwLock<StateSignatureMemStorage>,
@@ -51,15 +60,19 @@ pub struct StateSigner<ApiVer: StaticVersionType> {
 
     /// The state relay server url
     relay_server_client: Option<Client<ServerError, ApiVer>>,
+
+    /// Aggregator collecting signatures from peers on the states signed by this node
+    aggregator: Option<Arc<StateSignatureAggregator<ApiVer>>>,
 }
 
 impl<ApiVer: StaticVersionType> StateSigner<ApiVer> {
-    pub fn new(key_pair: StateKeyPair, stake_table_comm: StakeTableCommitmentType) -> Self {
+    pub fn new(signer: Box<dyn Signer>, stake_table_comm: StakeTableCommitmentType) -> Self {
         Self {
-            key_pair,
+            signer,
             stake_table_comm,
             signatures: Default::default(),
             relay_server_client: Default::default(),
+            aggregator: Default::default(),
         }
     }
 
@@ -69,6 +82,12 @@ impl<ApiVer: StaticVersionType> StateSigner<ApiVer> {
         self
     }
 
+    /// Aggregate signatures from peers on each state signed by this node.
+    pub fn with_aggregator(mut self, aggregator: Arc<StateSignatureAggregator<ApiVer>>) -> Self {
+        self.aggregator = Some(aggregator);
+        self
+    }
+
     pub(super) async fn handle_event(&self, event: &Event<SeqTypes>) {
         let EventType::Decide { leaf_chain, .. } = &event.event else {
             return;
@@ -78,12 +97,22 @@ impl<ApiVer: StaticVersionType> StateSigner<ApiVer> {
         };
         match form_light_client_state(leaf) {
             Ok(state) => {
-                let signature = self.sign_new_state(&state).await;
+                let Some(signature) = self.sign_new_state(&state).await else {
+                    return;
+                };
                 tracing::debug!("New leaves decided. Latest block height: {}", leaf.height(),);
 
+                if let Some(aggregator) = &self.aggregator {
+                    aggregator.queue(StateSignatureRequestBody {
+                        key: self.signer.ver_key(),
+                        state: state.clone(),
+                        signature: signature.clone(),
+                    });
+                }
+
                 if let Some(client) = &self.relay_server_client {
                     let request_body = StateSignatureRequestBody {
-                        key: self.key_pair.ver_key(),
+                        key: self.signer.ver_key(),
                         state,
                         signature,
                     };
@@ -110,21 +139,31 @@ impl<ApiVer: StaticVersionType> StateSigner<ApiVer> {
         pool_guard.get_signature(height)
     }
 
+    /// Return the aggregated signature bundle for the light client state at given height.
+    pub async fn get_state_signature_bundle(
+        &self,
+        height: u64,
+    ) -> Option<StateSignatureBundleQueryData> {
+        self.aggregator.as_ref()?.get_bundle(height).await
+    }
+
     /// Sign the light client state at given height and store it.
-    async fn sign_new_state(&self, state: &LightClientState) -> StateSignature {
-        let msg: [CircuitField; 3] = state.into();
-        let signature = StateSignatureScheme::sign(
-            &(),
-            self.key_pair.sign_key_ref(),
-            msg,
-            &mut rand::thread_rng(),
-        )
-        .unwrap();
+    async fn sign_new_state(&self, state: &LightClientState) -> Option<StateSignature> {
+        let signature = match self.signer.sign(state).await {
+            Ok(signature) => signature,
+            Err(err) => {
+                tracing::error!(
+                    height = state.block_height,
+                    "failed to sign light client state: {err:#}"
+                );
+                return None;
+            }
+        };
         let mut pool_guard = self.signatures.write().await;
         pool_guard.push(
             state.block_height as u64,
             StateSignatureRequestBody {
-                key: self.key_pair.ver_key(),
+                key: self.signer.ver_key(),
                 state: state.clone(),
                 signature: signature.clone(),
             },
@@ -133,7 +172,7 @@ impl<ApiVer: StaticVersionType> StateSigner<ApiVer> {
             "New signature added for block height {}",
             state.block_height
         );
-        signature
+        Some(signature)
     }
 }
 
@@ -0,0 +1,619 @@
+//! Aggregation of light client state signatures from peers.
+//!
+//! Each node signs the light client state of every decided block, but the light client contract
+//! needs signatures from a quorum of the stake table. The [`StateSignatureAggregator`] runs as a
+//! background task alongside the [`StateSigner`](super::StateSigner): whenever this node signs a new
+//! state, it collects signatures for the same state from its peers until the accumulated stake
+//! reaches the quorum threshold, and keeps the resulting bundle available to be served by the API.
+//!
+//! Signatures are collected in two ways. Each node gossips its own signatures to all peers as
+//! [`ExternalMessage`]s, and gossiped signatures are held until this node signs the same state.
+//! Only valid signatures from the stake table, for heights close to that of the latest state this
+//! node signed, are held.
+//! Peers that are not reachable by gossip, or whose signatures were missed, are polled over HTTP
+//! as a fallback, but only while the gossiped signatures fall short of a quorum.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_lock::RwLock;
+use async_trait::async_trait;
+use espresso_types::PubKey;
+use ethers::types::U256;
+use futures::future::join_all;
+use hotshot_contract_adapter::jellyfish::field_to_u256;
+use hotshot_state_prover::service::one_honest_threshold;
+use hotshot_types::{
+    light_client::{
+        CircuitField, LightClientState, StateSignatureRequestBody, StateSignatureScheme,
+        StateSignaturesBundle, StateVerKey,
+    },
+    signature_key::BLSPubKey,
+    traits::signature_key::StakeTableEntryType,
+    PeerConfig,
+};
+use jf_signature::SignatureScheme;
+use serde::{Deserialize, Serialize};
+use surf_disco::{Client, Url};
+use tide_disco::error::ServerError;
+use tokio::{
+    sync::mpsc::{unbounded_channel, Sender, UnboundedReceiver, UnboundedSender},
+    time::{sleep, timeout},
+};
+use vbs::version::StaticVersionType;
+
+use super::{StakeTableCommitmentType, SIGNATURE_STORAGE_CAPACITY};
+use crate::external_event_handler::{encode_external_message, ExternalMessage, OutboundMessage};
+
+/// How many times to poll peers for signatures on a state before giving up.
+const AGGREGATION_ATTEMPTS: usize = 5;
+
+/// How long to wait between rounds of polling peers.
+const AGGREGATION_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How long to wait for a single peer to respond.
+const PEER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How far from the height of the latest state signed by this node gossiped signatures may be.
+///
+/// Peers may sign states slightly before or after we do, but signatures for states far in the past
+/// or future will never be aggregated, and are dropped rather than buffered.
+const GOSSIP_WINDOW: u64 = 10;
+
+/// The stake table commitment a signature bundle is valid for.
+///
+/// This is in the format expected by the light client contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StakeTableCommitment {
+    pub bls_key_comm: U256,
+    pub schnorr_key_comm: U256,
+    pub amount_comm: U256,
+    /// The minimum accumulated stake for a bundle to be accepted.
+    pub threshold: U256,
+}
+
+/// Signatures from a quorum of nodes on a single light client state.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StateSignatureBundleQueryData {
+    pub bundle: StateSignaturesBundle,
+    pub stake_table: StakeTableCommitment,
+}
+
+/// A sink for state signatures gossiped by peers.
+#[async_trait]
+pub(crate) trait StateSignatureSink: Send + Sync {
+    async fn receive_gossip(&self, signature: StateSignatureRequestBody);
+}
+
+/// Where to gossip state signatures made by this node.
+#[derive(Debug)]
+struct Gossip {
+    public_key: PubKey,
+    sender: Sender<OutboundMessage>,
+}
+
+#[derive(Debug)]
+pub struct StateSignatureAggregator<ApiVer: StaticVersionType> {
+    /// Stake of each node, by state key.
+    stake_table: HashMap<StateVerKey, U256>,
+    stake_table_comm: StakeTableCommitment,
+    peers: Vec<Client<ServerError, ApiVer>>,
+    gossip: Option<Gossip>,
+    gossiped: RwLock<GossipStorage>,
+    bundles: RwLock<BundleStorage>,
+    /// The latest state signed by this node.
+    latest: RwLock<Option<LightClientState>>,
+    sender: UnboundedSender<StateSignatureRequestBody>,
+}
+
+impl<ApiVer: StaticVersionType> StateSignatureAggregator<ApiVer> {
+    /// Create an aggregator for the given stake table, which fetches signatures from `peers`.
+    ///
+    /// The returned receiver must be passed to [`run`](Self::run).
+    pub fn new(
+        known_nodes_with_stake: &[PeerConfig<BLSPubKey>],
+        stake_table_comm: StakeTableCommitmentType,
+        peers: Vec<Url>,
+    ) -> (Self, UnboundedReceiver<StateSignatureRequestBody>) {
+        let stake_table = known_nodes_with_stake
+            .iter()
+            .map(|peer| (peer.state_ver_key.clone(), peer.stake_table_entry.stake()))
+            .collect::<HashMap<_, _>>();
+        let total_stake = stake_table
+            .values()
+            .fold(U256::zero(), |total, stake| total + stake);
+        let stake_table_comm = StakeTableCommitment {
+            bls_key_comm: field_to_u256(stake_table_comm.0),
+            schnorr_key_comm: field_to_u256(stake_table_comm.1),
+            amount_comm: field_to_u256(stake_table_comm.2),
+            threshold: one_honest_threshold(total_stake),
+        };
+        let (sender, receiver) = unbounded_channel();
+        let aggregator = Self {
+            stake_table,
+            stake_table_comm,
+            peers: peers.into_iter().map(Client::new).collect(),
+            gossip: None,
+            gossiped: Default::default(),
+            bundles: Default::default(),
+            latest: Default::default(),
+            sender,
+        };
+        (aggregator, receiver)
+    }
+
+    /// Gossip signatures made by this node to peers, via the external message queue.
+    pub(crate) fn with_gossip(
+        mut self,
+        public_key: PubKey,
+        sender: Sender<OutboundMessage>,
+    ) -> Self {
+        self.gossip = Some(Gossip { public_key, sender });
+        self
+    }
+
+    /// Start aggregating signatures for a state this node has just signed.
+    pub(super) fn queue(&self, signature: StateSignatureRequestBody) {
+        if let Some(gossip) = &self.gossip {
+            let message = ExternalMessage::StateSignature(signature.clone());
+            match encode_external_message(&gossip.public_key, &message) {
+                Ok(bytes) => {
+                    if let Err(err) = gossip.sender.try_send(OutboundMessage::Broadcast(bytes)) {
+                        tracing::warn!("unable to gossip state signature: {err}");
+                    }
+                }
+                Err(err) => tracing::warn!("failed to serialize state signature gossip: {err:#}"),
+            }
+        }
+
+        // This can only fail if the aggregation task has exited, in which case there is nothing to
+        // do.
+        self.sender.send(signature).ok();
+    }
+
+    /// Get the aggregated signature bundle for the state at `height`, if a quorum was reached.
+    pub async fn get_bundle(&self, height: u64) -> Option<StateSignatureBundleQueryData> {
+        self.bundles.read().await.get(height)
+    }
+
+    /// Aggregate signatures for each state queued by the signer.
+    ///
+    /// Only the light client state of the latest block is needed to update the light client
+    /// contract, so if new states are signed faster than signatures can be aggregated, older states
+    /// are skipped.
+    pub async fn run(self: Arc<Self>, mut states: UnboundedReceiver<StateSignatureRequestBody>) {
+        while let Some(mut own) = states.recv().await {
+            while let Ok(newer) = states.try_recv() {
+                own = newer;
+            }
+            self.aggregate(own).await;
+        }
+    }
+
+    async fn aggregate(&self, own: StateSignatureRequestBody) {
+        let height = own.state.block_height as u64;
+        *self.latest.write().await = Some(own.state.clone());
+        let mut bundle = StateSignaturesBundle {
+            state: own.state.clone(),
+            signatures: Default::default(),
+            accumulated_weight: U256::zero(),
+        };
+        self.add_signature(&mut bundle, own);
+
+        for attempt in 0..AGGREGATION_ATTEMPTS {
+            if attempt > 0 {
+                sleep(AGGREGATION_RETRY_DELAY).await;
+            }
+
+            // Signatures gossiped by peers are free, so use them before polling anyone.
+            for signature in self.gossiped.write().await.take(height) {
+                self.add_signature(&mut bundle, signature);
+            }
+            if bundle.accumulated_weight >= self.stake_table_comm.threshold {
+                break;
+            }
+            if attempt == 0 && self.gossip.is_some() {
+                // Peers sign the state at about the same time we do, so give their gossip a
+                // chance to arrive before falling back to polling.
+                continue;
+            }
+
+            let responses = join_all(self.peers.iter().map(|peer| async move {
+                timeout(
+                    PEER_TIMEOUT,
+                    peer.get::<StateSignatureRequestBody>(&format!(
+                        "state-signature/block/{height}"
+                    ))
+                    .send(),
+                )
+                .await
+            }))
+            .await;
+            for res in responses {
+                match res {
+                    Ok(Ok(signature)) => {
+                        self.add_signature(&mut bundle, signature);
+                    }
+                    Ok(Err(err)) => {
+                        tracing::debug!(height, "failed to fetch state signature: {err:#}");
+                    }
+                    Err(_) => {
+                        tracing::debug!(height, "timed out fetching state signature");
+                    }
+                }
+            }
+        }
+
+        if bundle.accumulated_weight < self.stake_table_comm.threshold {
+            tracing::warn!(
+                height,
+                weight = %bundle.accumulated_weight,
+                threshold = %self.stake_table_comm.threshold,
+                "failed to aggregate a quorum of state signatures"
+            );
+            return;
+        }
+        tracing::debug!(
+            height,
+            signatures = bundle.signatures.len(),
+            "aggregated state signatures"
+        );
+        self.bundles.write().await.push(
+            height,
+            StateSignatureBundleQueryData {
+                bundle,
+                stake_table: self.stake_table_comm,
+            },
+        );
+    }
+
+    /// Add `signature` to `bundle` if it is a valid signature on the bundled state from a node in
+    /// the stake table which has not already signed.
+    ///
+    /// Returns whether the signature was added.
+    fn add_signature(
+        &self,
+        bundle: &mut StateSignaturesBundle,
+        signature: StateSignatureRequestBody,
+    ) -> bool {
+        let key = &signature.key;
+        if bundle.signatures.contains_key(key) {
+            return false;
+        }
+        let Some(stake) = self.stake_table.get(key) else {
+            tracing::debug!(%key, "ignoring state signature from key not in stake table");
+            return false;
+        };
+        if signature.state != bundle.state {
+            tracing::warn!(
+                %key,
+                state = ?signature.state,
+                expected = ?bundle.state,
+                "peer signed a different light client state"
+            );
+            return false;
+        }
+        if !is_valid_signature(&signature) {
+            tracing::warn!(%key, "ignoring invalid state signature");
+            return false;
+        }
+
+        bundle.signatures.insert(signature.key, signature.signature);
+        bundle.accumulated_weight += *stake;
+        true
+    }
+}
+
+#[async_trait]
+impl<ApiVer: StaticVersionType> StateSignatureSink for StateSignatureAggregator<ApiVer> {
+    async fn receive_gossip(&self, signature: StateSignatureRequestBody) {
+        // Filter out signatures that can never count towards a bundle before buffering them, so
+        // peers cannot fill the buffer with junk.
+        let key = &signature.key;
+        if !self.stake_table.contains_key(key) {
+            tracing::debug!(%key, "ignoring gossiped signature from unknown key");
+            return;
+        }
+        let height = signature.state.block_height as u64;
+        if let Some(latest) = &*self.latest.read().await {
+            let latest_height = latest.block_height as u64;
+            if height.abs_diff(latest_height) > GOSSIP_WINDOW {
+                tracing::debug!(
+                    %key,
+                    height,
+                    latest_height,
+                    "ignoring gossiped signature outside of window"
+                );
+                return;
+            }
+            if height == latest_height && signature.state != *latest {
+                tracing::warn!(
+                    %key,
+                    state = ?signature.state,
+                    expected = ?latest,
+                    "peer gossiped a signature on a different light client state"
+                );
+                return;
+            }
+        }
+        if !is_valid_signature(&signature) {
+            tracing::warn!(%key, height, "ignoring invalid gossiped state signature");
+            return;
+        }
+        if self.bundles.read().await.get(height).is_some() {
+            // We already have a quorum for this state.
+            return;
+        }
+        self.gossiped.write().await.push(height, signature);
+    }
+}
+
+/// Whether `signature` is a valid signature by its key on its light client state.
+pub(crate) fn is_valid_signature(signature: &StateSignatureRequestBody) -> bool {
+    let msg: [CircuitField; 3] = (&signature.state).into();
+    StateSignatureScheme::verify(&(), &signature.key, msg, &signature.signature).is_ok()
+}
+
+/// A rolling in-memory buffer of signatures gossiped by peers, for the most recent heights.
+#[derive(Debug, Default)]
+struct GossipStorage {
+    pool: HashMap<u64, HashMap<StateVerKey, StateSignatureRequestBody>>,
+    deque: VecDeque<u64>,
+}
+
+impl GossipStorage {
+    fn push(&mut self, height: u64, signature: StateSignatureRequestBody) {
+        if !self.pool.contains_key(&height) {
+            self.deque.push_back(height);
+            if self.deque.len() > SIGNATURE_STORAGE_CAPACITY {
+                self.pool.remove(&self.deque.pop_front().unwrap());
+            }
+        }
+        self.pool
+            .entry(height)
+            .or_default()
+            .insert(signature.key.clone(), signature);
+    }
+
+    fn take(&mut self, height: u64) -> Vec<StateSignatureRequestBody> {
+        self.pool
+            .get_mut(&height)
+            .map(|signatures| signatures.drain().map(|(_, signature)| signature).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// A rolling in-memory storage for the most recent aggregated signature bundles.
+#[derive(Debug, Default)]
+struct BundleStorage {
+    pool: HashMap<u64, StateSignatureBundleQueryData>,
+    deque: VecDeque<u64>,
+}
+
+impl BundleStorage {
+    /// Store the bundle for `height`, replacing any bundle already stored for the same height.
+    fn push(&mut self, height: u64, bundle: StateSignatureBundleQueryData) {
+        if self.pool.insert(height, bundle).is_some() {
+            // The height is already in the queue for eviction.
+            return;
+        }
+        self.deque.push_back(height);
+        if self.pool.len() > SIGNATURE_STORAGE_CAPACITY {
+            self.pool.remove(&self.deque.pop_front().unwrap());
+        }
+    }
+
+    fn get(&self, height: u64) -> Option<StateSignatureBundleQueryData> {
+        self.pool.get(&height).cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hotshot_types::{
+        light_client::{LightClientState, StateKeyPair},
+        traits::signature_key::SignatureKey,
+    };
+    use rand::thread_rng;
+    use vbs::version::StaticVersion;
+
+    use super::*;
+
+    fn sign(key_pair: &StateKeyPair, state: &LightClientState) -> StateSignatureRequestBody {
+        let msg: [CircuitField; 3] = state.into();
+        StateSignatureRequestBody {
+            key: key_pair.ver_key(),
+            state: state.clone(),
+            signature: StateSignatureScheme::sign(
+                &(),
+                key_pair.sign_key_ref(),
+                msg,
+                &mut thread_rng(),
+            )
+            .unwrap(),
+        }
+    }
+
+    /// Key pairs for a stake table of 4 nodes, with stakes 1 through 4.
+    fn setup() -> (
+        Vec<StateKeyPair>,
+        StateSignatureAggregator<StaticVersion<0, 1>>,
+    ) {
+        let key_pairs = (0..4)
+            .map(|i| StateKeyPair::generate_from_seed_indexed([0; 32], i))
+            .collect::<Vec<_>>();
+        let stake_table = key_pairs
+            .iter()
+            .enumerate()
+            .map(|(i, key_pair)| {
+                let (pub_key, _) = BLSPubKey::generated_from_seed_indexed([0; 32], i as u64);
+                PeerConfig {
+                    stake_table_entry: pub_key.stake_table_entry(i as u64 + 1),
+                    state_ver_key: key_pair.ver_key(),
+                }
+            })
+            .collect::<Vec<_>>();
+        let (aggregator, _) =
+            StateSignatureAggregator::new(&stake_table, Default::default(), vec![]);
+        (key_pairs, aggregator)
+    }
+
+    #[test]
+    fn test_add_signature() {
+        let (key_pairs, aggregator) = setup();
+        // Total stake is 1 + 2 + 3 + 4 = 10.
+        assert_eq!(aggregator.stake_table_comm.threshold, U256::from(4));
+
+        let state = LightClientState {
+            view_number: 1,
+            block_height: 1,
+            block_comm_root: Default::default(),
+        };
+        let mut bundle = StateSignaturesBundle {
+            state: state.clone(),
+            signatures: Default::default(),
+            accumulated_weight: U256::zero(),
+        };
+
+        // Valid signatures are accumulated by stake.
+        assert!(aggregator.add_signature(&mut bundle, sign(&key_pairs[0], &state)));
+        assert!(aggregator.add_signature(&mut bundle, sign(&key_pairs[2], &state)));
+        assert_eq!(bundle.accumulated_weight, U256::from(4));
+
+        // Duplicate signatures are not counted twice.
+        assert!(!aggregator.add_signature(&mut bundle, sign(&key_pairs[0], &state)));
+
+        // Signatures on other states are rejected.
+        let other_state = LightClientState {
+            block_height: 2,
+            ..state.clone()
+        };
+        assert!(!aggregator.add_signature(&mut bundle, sign(&key_pairs[1], &other_state)));
+
+        // Signatures from keys outside the stake table are rejected.
+        let outsider = StateKeyPair::generate_from_seed_indexed([1; 32], 0);
+        assert!(!aggregator.add_signature(&mut bundle, sign(&outsider, &state)));
+
+        // Forged signatures are rejected.
+        let mut forged = sign(&key_pairs[3], &state);
+        forged.key = key_pairs[1].ver_key();
+        assert!(!aggregator.add_signature(&mut bundle, forged));
+
+        assert_eq!(bundle.signatures.len(), 2);
+        assert_eq!(bundle.accumulated_weight, U256::from(4));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_aggregate_gossip() {
+        let (key_pairs, aggregator) = setup();
+        let state = LightClientState {
+            view_number: 1,
+            block_height: 1,
+            block_comm_root: Default::default(),
+        };
+
+        // Signatures from outside the stake table are not buffered.
+        let outsider = StateKeyPair::generate_from_seed_indexed([1; 32], 0);
+        aggregator.receive_gossip(sign(&outsider, &state)).await;
+        assert!(aggregator.gossiped.read().await.pool.is_empty());
+
+        // Gossiped signatures are aggregated with our own without polling any peers.
+        aggregator.receive_gossip(sign(&key_pairs[1], &state)).await;
+        aggregator.receive_gossip(sign(&key_pairs[2], &state)).await;
+        aggregator.aggregate(sign(&key_pairs[0], &state)).await;
+        let bundle = aggregator.get_bundle(1).await.unwrap();
+        assert_eq!(bundle.bundle.signatures.len(), 3);
+        assert_eq!(bundle.bundle.accumulated_weight, U256::from(6));
+
+        // Once a quorum is reached, further gossip for the same state is dropped.
+        aggregator.receive_gossip(sign(&key_pairs[3], &state)).await;
+        assert!(aggregator.gossiped.read().await.take(1).is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_receive_gossip_filters_signatures() {
+        let (key_pairs, aggregator) = setup();
+        let state = |height: usize| LightClientState {
+            view_number: height,
+            block_height: height,
+            block_comm_root: Default::default(),
+        };
+
+        // Forged signatures are not buffered.
+        let mut forged = sign(&key_pairs[3], &state(1));
+        forged.key = key_pairs[1].ver_key();
+        aggregator.receive_gossip(forged).await;
+        assert!(aggregator.gossiped.read().await.pool.is_empty());
+
+        // Once this node has signed a state, signatures far from its height are not buffered, nor
+        // are signatures on a different state at the same height.
+        *aggregator.latest.write().await = Some(state(100));
+        let window = GOSSIP_WINDOW as usize;
+        aggregator
+            .receive_gossip(sign(&key_pairs[1], &state(100 + window + 1)))
+            .await;
+        aggregator
+            .receive_gossip(sign(&key_pairs[1], &state(100 - window - 1)))
+            .await;
+        let other = LightClientState {
+            view_number: 99,
+            ..state(100)
+        };
+        aggregator.receive_gossip(sign(&key_pairs[1], &other)).await;
+        assert!(aggregator.gossiped.read().await.pool.is_empty());
+
+        // Valid signatures within the window are buffered.
+        aggregator
+            .receive_gossip(sign(&key_pairs[1], &state(100)))
+            .await;
+        aggregator
+            .receive_gossip(sign(&key_pairs[2], &state(100 + window)))
+            .await;
+        let mut gossiped = aggregator.gossiped.write().await;
+        assert_eq!(gossiped.take(100).len(), 1);
+        assert_eq!(gossiped.take(100 + GOSSIP_WINDOW).len(), 1);
+    }
+
+    #[test]
+    fn test_bundle_storage_replaces_height() {
+        let bundle = |weight: u64| StateSignatureBundleQueryData {
+            bundle: StateSignaturesBundle {
+                state: LightClientState {
+                    view_number: 1,
+                    block_height: 1,
+                    block_comm_root: Default::default(),
+                },
+                signatures: Default::default(),
+                accumulated_weight: U256::from(weight),
+            },
+            stake_table: StakeTableCommitment {
+                bls_key_comm: U256::zero(),
+                schnorr_key_comm: U256::zero(),
+                amount_comm: U256::zero(),
+                threshold: U256::zero(),
+            },
+        };
+
+        // A second bundle for the same height replaces the first.
+        let mut storage = BundleStorage::default();
+        storage.push(1, bundle(1));
+        storage.push(1, bundle(2));
+        assert_eq!(
+            storage.get(1).unwrap().bundle.accumulated_weight,
+            U256::from(2)
+        );
+
+        // The replaced bundle does not count towards the capacity, and is evicted only once.
+        for height in 2..=SIGNATURE_STORAGE_CAPACITY as u64 + 2 {
+            storage.push(height, bundle(height));
+        }
+        assert_eq!(storage.pool.len(), SIGNATURE_STORAGE_CAPACITY);
+        assert_eq!(storage.deque.len(), SIGNATURE_STORAGE_CAPACITY);
+        assert!(storage.get(1).is_none());
+        assert!(storage.get(2).is_none());
+        assert!(storage.get(3).is_some());
+    }
+}
@@ -0,0 +1,402 @@
+//! Signing light client states, either in process or with a remote signer.
+//!
+//! By default a node signs light client states with the state key it was started with. Operators
+//! who keep their keys in a dedicated signing service, in the style of web3signer, can instead
+//! configure a [`RemoteSigner`] with `--remote-signer-url`, so that every signature is produced by
+//! the service.
+//!
+//! The remote signer speaks a small JSON protocol. To sign a state, the node sends
+//!
+//! ```text
+//! POST <url>/api/v1/sign/<state verification key>
+//! Authorization: Bearer <token>
+//!
+//! { "state": <light client state> }
+//! ```
+//!
+//! and expects a response of the form `{ "signature": <state signature> }`. Every signature
+//! returned by the service is checked against the node's state verification key before it is used.
+//!
+//! If the remote signer is unreachable, slow or returns a bad signature, the [`FallbackPolicy`]
+//! decides whether the node signs with its local key instead or skips the state. Latency, failures
+//! and fallbacks are reported via metrics in the `remote_signer` group.
+//!
+//! Note that HotShot itself signs votes and proposals with the staking key in process, and requires
+//! a state key pair in the validator configuration, so a local state key must still be configured.
+//! With the `none` fallback policy, it is never used to sign anything.
+
+use std::{fmt::Debug, path::PathBuf, time::Duration};
+
+use anyhow::{bail, ensure, Context};
+use async_trait::async_trait;
+use clap::{Parser, ValueEnum};
+use derivative::Derivative;
+use espresso_types::parse_duration;
+use hotshot_types::{
+    light_client::{
+        CircuitField, LightClientState, StateSignature, StateSignatureScheme, StateVerKey,
+    },
+    traits::metrics::{Counter, Histogram, Metrics},
+};
+use jf_signature::SignatureScheme;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use url::Url;
+
+use crate::StateKeyPair;
+
+/// Something which can sign light client states on behalf of this node.
+#[async_trait]
+pub trait Signer: Debug + Send + Sync {
+    /// The key which verifies signatures produced by this signer.
+    fn ver_key(&self) -> StateVerKey;
+
+    /// Sign a light client state.
+    async fn sign(&self, state: &LightClientState) -> anyhow::Result<StateSignature>;
+}
+
+#[async_trait]
+impl Signer for StateKeyPair {
+    fn ver_key(&self) -> StateVerKey {
+        StateKeyPair::ver_key(self)
+    }
+
+    async fn sign(&self, state: &LightClientState) -> anyhow::Result<StateSignature> {
+        let msg: [CircuitField; 3] = state.into();
+        Ok(StateSignatureScheme::sign(
+            &(),
+            self.sign_key_ref(),
+            msg,
+            &mut rand::thread_rng(),
+        )?)
+    }
+}
+
+/// What to do when the remote signer fails to produce a signature.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum FallbackPolicy {
+    /// Skip signing the state.
+    #[default]
+    None,
+    /// Sign the state with the local state key.
+    Local,
+}
+
+#[derive(Clone, Derivative, Parser)]
+#[derivative(Debug)]
+pub struct RemoteSignerConfig {
+    /// URL of a remote signer holding this node's state key.
+    ///
+    /// If set, light client states are signed by the remote signer instead of in process.
+    #[clap(
+        long = "remote-signer-url",
+        env = "ESPRESSO_SEQUENCER_REMOTE_SIGNER_URL"
+    )]
+    pub url: Option<Url>,
+
+    /// Bearer token authenticating this node to the remote signer.
+    #[clap(
+        long = "remote-signer-token",
+        env = "ESPRESSO_SEQUENCER_REMOTE_SIGNER_TOKEN",
+        hide_env_values = true,
+        conflicts_with = "token_file"
+    )]
+    #[derivative(Debug = "ignore")]
+    pub token: Option<String>,
+
+    /// File containing the bearer token authenticating this node to the remote signer.
+    #[clap(
+        long = "remote-signer-token-file",
+        env = "ESPRESSO_SEQUENCER_REMOTE_SIGNER_TOKEN_FILE"
+    )]
+    pub token_file: Option<PathBuf>,
+
+    /// Timeout for a single request to the remote signer.
+    #[clap(
+        long = "remote-signer-timeout",
+        env = "ESPRESSO_SEQUENCER_REMOTE_SIGNER_TIMEOUT",
+        default_value = "2s",
+        value_parser = parse_duration,
+    )]
+    pub timeout: Duration,
+
+    /// What to do when the remote signer fails to produce a signature.
+    #[clap(
+        long = "remote-signer-fallback",
+        env = "ESPRESSO_SEQUENCER_REMOTE_SIGNER_FALLBACK",
+        default_value = "none"
+    )]
+    pub fallback: FallbackPolicy,
+}
+
+impl Default for RemoteSignerConfig {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+impl RemoteSignerConfig {
+    /// Get the signer for light client states described by this configuration.
+    ///
+    /// This is a [`RemoteSigner`] if a remote signer is configured, and otherwise `key_pair` itself.
+    pub fn signer(
+        &self,
+        key_pair: StateKeyPair,
+        metrics: &dyn Metrics,
+    ) -> anyhow::Result<Box<dyn Signer>> {
+        let Some(url) = &self.url else {
+            return Ok(Box::new(key_pair));
+        };
+        let token = match (&self.token, &self.token_file) {
+            (Some(token), _) => token.clone(),
+            (None, Some(path)) => std::fs::read_to_string(path)
+                .context(format!("reading {}", path.display()))?
+                .trim()
+                .to_string(),
+            (None, None) => {
+                bail!("remote signer requires --remote-signer-token or --remote-signer-token-file")
+            }
+        };
+        ensure!(!token.is_empty(), "remote signer token is empty");
+        Ok(Box::new(RemoteSigner::new(
+            url.clone(),
+            token,
+            self.timeout,
+            key_pair,
+            self.fallback,
+            metrics,
+        )?))
+    }
+}
+
+#[derive(Debug)]
+struct RemoteSignerMetrics {
+    /// Time taken by successful requests to the remote signer, in seconds.
+    latency: Box<dyn Histogram>,
+    /// Number of requests to the remote signer which did not produce a valid signature.
+    failures: Box<dyn Counter>,
+    /// Number of states signed with the local key after the remote signer failed.
+    fallbacks: Box<dyn Counter>,
+}
+
+impl RemoteSignerMetrics {
+    fn new(metrics: &dyn Metrics) -> Self {
+        let metrics = metrics.subgroup("remote_signer".into());
+        Self {
+            latency: metrics.create_histogram("latency".into(), Some("s".into())),
+            failures: metrics.create_counter("failures".into(), None),
+            fallbacks: metrics.create_counter("fallbacks".into(), None),
+        }
+    }
+}
+
+/// The body of a request to the remote signer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignRequest {
+    pub state: LightClientState,
+}
+
+/// The body of a response from the remote signer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignResponse {
+    pub signature: StateSignature,
+}
+
+/// A [`Signer`] which delegates to a remote signing service.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct RemoteSigner {
+    endpoint: Url,
+    #[derivative(Debug = "ignore")]
+    token: String,
+    #[derivative(Debug = "ignore")]
+    client: reqwest::Client,
+    /// The local key, used to identify this node's key to the signer and as a fallback.
+    #[derivative(Debug = "ignore")]
+    key_pair: StateKeyPair,
+    fallback: FallbackPolicy,
+    metrics: RemoteSignerMetrics,
+}
+
+impl RemoteSigner {
+    fn new(
+        url: Url,
+        token: String,
+        timeout: Duration,
+        key_pair: StateKeyPair,
+        fallback: FallbackPolicy,
+        metrics: &dyn Metrics,
+    ) -> anyhow::Result<Self> {
+        let endpoint = url
+            .join(&format!("api/v1/sign/{}", key_pair.ver_key()))
+            .context("invalid remote signer URL")?;
+        let client = reqwest::Client::builder().timeout(timeout).build()?;
+        Ok(Self {
+            endpoint,
+            token,
+            client,
+            key_pair,
+            fallback,
+            metrics: RemoteSignerMetrics::new(metrics),
+        })
+    }
+
+    /// Request a signature on `state` from the remote signer and check it.
+    async fn sign_remote(&self, state: &LightClientState) -> anyhow::Result<StateSignature> {
+        let start = Instant::now();
+        let res = self
+            .client
+            .post(self.endpoint.clone())
+            .bearer_auth(&self.token)
+            .json(&SignRequest {
+                state: state.clone(),
+            })
+            .send()
+            .await?
+            .error_for_status()?;
+        let SignResponse { signature } = res.json().await?;
+
+        let msg: [CircuitField; 3] = state.into();
+        StateSignatureScheme::verify(&(), &self.key_pair.ver_key(), msg, &signature)
+            .context("remote signer returned an invalid signature")?;
+
+        self.metrics
+            .latency
+            .add_point(start.elapsed().as_secs_f64());
+        Ok(signature)
+    }
+}
+
+#[async_trait]
+impl Signer for RemoteSigner {
+    fn ver_key(&self) -> StateVerKey {
+        self.key_pair.ver_key()
+    }
+
+    async fn sign(&self, state: &LightClientState) -> anyhow::Result<StateSignature> {
+        let err = match self.sign_remote(state).await {
+            Ok(signature) => return Ok(signature),
+            Err(err) => err,
+        };
+        self.metrics.failures.add(1);
+        match self.fallback {
+            FallbackPolicy::None => Err(err.context("remote signer failed")),
+            FallbackPolicy::Local => {
+                tracing::warn!(
+                    height = state.block_height,
+                    "remote signer failed, signing with local key: {err:#}"
+                );
+                self.metrics.fallbacks.add(1);
+                Signer::sign(&self.key_pair, state).await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use hotshot_types::traits::metrics::NoMetrics;
+    use portpicker::pick_unused_port;
+    use sequencer_utils::test_utils::setup_test;
+
+    use super::*;
+    use crate::context::TaskList;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_remote_signer() {
+        setup_test();
+
+        // Run a remote signer which holds the key and can be taken offline.
+        #[derive(Clone)]
+        struct Service {
+            key_pair: StateKeyPair,
+            online: Arc<AtomicBool>,
+        }
+        let key_pair = StateKeyPair::generate();
+        let service = Service {
+            key_pair: key_pair.clone(),
+            online: Arc::new(AtomicBool::new(true)),
+        };
+        let mut app = tide::with_state(service.clone());
+        app.at("/api/v1/sign/:key")
+            .post(|mut req: tide::Request<Service>| async move {
+                if !req.state().online.load(Ordering::SeqCst) {
+                    return Ok(tide::Response::new(503));
+                }
+                if req.header("Authorization").map(|h| h.as_str()) != Some("Bearer secret") {
+                    return Ok(tide::Response::new(401));
+                }
+                if req.param("key")? != req.state().key_pair.ver_key().to_string() {
+                    return Ok(tide::Response::new(404));
+                }
+                let SignRequest { state } = req.body_json().await?;
+                let signature = Signer::sign(&req.state().key_pair, &state).await.unwrap();
+                let mut res = tide::Response::new(200);
+                res.set_body(tide::Body::from_json(&SignResponse { signature })?);
+                Ok(res)
+            });
+        let port = pick_unused_port().unwrap();
+        let mut tasks = TaskList::default();
+        tasks.spawn("remote signer", app.listen(format!("127.0.0.1:{port}")));
+
+        let state = LightClientState {
+            view_number: 1,
+            block_height: 1,
+            block_comm_root: Default::default(),
+        };
+        let msg: [CircuitField; 3] = (&state).into();
+        let config = |token: &str, fallback| RemoteSignerConfig {
+            url: Some(format!("http://127.0.0.1:{port}").parse().unwrap()),
+            token: Some(token.into()),
+            fallback,
+            ..Default::default()
+        };
+
+        let signer = config("secret", FallbackPolicy::None)
+            .signer(key_pair.clone(), &NoMetrics)
+            .unwrap();
+        let signature = loop {
+            match signer.sign(&state).await {
+                Ok(signature) => break signature,
+                Err(err) => {
+                    tracing::info!("waiting for remote signer: {err:#}");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        };
+        StateSignatureScheme::verify(&(), &key_pair.ver_key(), msg, &signature).unwrap();
+
+        // Unauthenticated requests are rejected.
+        config("wrong", FallbackPolicy::None)
+            .signer(key_pair.clone(), &NoMetrics)
+            .unwrap()
+            .sign(&state)
+            .await
+            .unwrap_err();
+
+        // When the remote signer is down, we either fail or fall back to the local key.
+        service.online.store(false, Ordering::SeqCst);
+        signer.sign(&state).await.unwrap_err();
+        let signature = config("secret", FallbackPolicy::Local)
+            .signer(key_pair.clone(), &NoMetrics)
+            .unwrap()
+            .sign(&state)
+            .await
+            .unwrap();
+        StateSignatureScheme::verify(&(), &key_pair.ver_key(), msg, &signature).unwrap();
+
+        // A remote signer holding the wrong key is detected.
+        service.online.store(true, Ordering::SeqCst);
+        config("secret", FallbackPolicy::None)
+            .signer(StateKeyPair::generate(), &NoMetrics)
+            .unwrap()
+            .sign(&state)
+            .await
+            .unwrap_err();
+    }
+}
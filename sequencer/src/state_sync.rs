@@ -0,0 +1,746 @@
+//! Node-to-node sync of merklized state over the consensus network.
+//!
+//! A node which is missing part of the fee or block Merkle tree, for example after restarting from
+//! an old snapshot, normally fetches it from the HTTP catchup API of the trusted peers given by
+//! `--state-peers`. This module provides the same over the consensus network itself, so that a
+//! node can recover state from any of its consensus peers without relying on their HTTP APIs.
+//!
+//! Requests and responses are carried as [`ExternalMessage`]s sent directly between nodes. Each
+//! node answers requests from the validated state it holds in consensus memory, so only recent
+//! views are available. Responses are never trusted: the requester checks every response against
+//! Merkle roots from a decided header before using it.
+//!
+//! Any peer can send requests, so serving them is bounded: each peer is rate limited, catchup
+//! requests are answered with proofs for just the requested accounts or frontier, and full Merkle
+//! trees are only sent if they fit in [`MAX_RESPONSE_SIZE`]. Fetching state this way is opt-in on
+//! the client side (`--state-sync`), while every node serves requests from its peers.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use async_lock::RwLock;
+use async_trait::async_trait;
+use committable::{Commitment, Committable};
+use derivative::Derivative;
+use espresso_types::{
+    retain_accounts, v0::traits::StateCatchup, v0_3::ChainConfig, BackoffParams,
+    BlockMerkleCommitment, BlockMerkleTree, FeeAccount, FeeAccountProof, FeeMerkleCommitment,
+    FeeMerkleTree, Header, NodeState, PubKey, ValidatedState,
+};
+use futures::FutureExt;
+use hotshot_types::{
+    data::ViewNumber,
+    traits::{network::ConnectedNetwork, node_implementation::Versions},
+};
+use jf_merkle_tree::{ForgetableMerkleTreeScheme, MerkleCommitment, MerkleTreeScheme};
+use parking_lot::Mutex;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::oneshot, time::timeout};
+
+use crate::{
+    api::BlocksFrontier,
+    context::Consensus,
+    external_event_handler::{encode_external_message, ExternalMessage},
+    SequencerPersistence,
+};
+
+/// How long to wait for a peer to respond to a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum number of peers to try for a single request before giving up.
+const MAX_PEERS_PER_REQUEST: usize = 5;
+
+/// Maximum serialized size of a response we will send to a peer.
+pub const MAX_RESPONSE_SIZE: u64 = 4_000_000;
+
+/// Maximum number of accounts a peer may request proofs for at once.
+const MAX_ACCOUNTS_PER_REQUEST: usize = 1_000;
+
+/// Number of requests a peer may send in a burst.
+///
+/// A request for the full Merkle trees uses up the whole burst.
+const PEER_REQUEST_BURST: f64 = 10.0;
+
+/// Sustained number of requests per second each peer may send.
+const PEER_REQUEST_RATE: f64 = 1.0;
+
+/// Number of peers to track before forgetting idle ones.
+const MAX_TRACKED_PEERS: usize = 1_000;
+
+/// A request for state from a peer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum StateSyncRequest {
+    /// The fee and block Merkle trees as of the given height and view.
+    State { height: u64, view: ViewNumber },
+    /// Proofs for the given accounts as of the given height and view.
+    Accounts {
+        height: u64,
+        view: ViewNumber,
+        accounts: Vec<FeeAccount>,
+    },
+    /// A proof of the last leaf of the block Merkle tree as of the given height and view.
+    Frontier { height: u64, view: ViewNumber },
+    /// The chain config with the given commitment.
+    ChainConfig(Commitment<ChainConfig>),
+}
+
+impl StateSyncRequest {
+    /// The number of rate limit tokens it costs a peer to make this request.
+    fn cost(&self) -> f64 {
+        match self {
+            Self::State { .. } => PEER_REQUEST_BURST,
+            _ => 1.0,
+        }
+    }
+}
+
+/// A peer's response to a [`StateSyncRequest`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum StateSyncResponse {
+    State {
+        fee_merkle_tree: FeeMerkleTree,
+        block_merkle_tree: BlockMerkleTree,
+    },
+    /// A partial snapshot of the fee Merkle tree containing only the requested accounts.
+    Accounts(FeeMerkleTree),
+    Frontier(BlocksFrontier),
+    ChainConfig(ChainConfig),
+    /// The peer does not have the requested state.
+    NotFound,
+    /// The response would exceed [`MAX_RESPONSE_SIZE`], or too many accounts were requested.
+    TooLarge,
+}
+
+/// The state a node can serve to its peers.
+#[async_trait]
+pub(crate) trait StateSource: Send + Sync {
+    /// The validated state at `view`, if it is in memory.
+    async fn state(&self, view: ViewNumber) -> Option<Arc<ValidatedState>>;
+
+    /// The state as of the latest decided view.
+    async fn decided_state(&self) -> Arc<ValidatedState>;
+}
+
+#[async_trait]
+impl<N, P, V> StateSource for Arc<RwLock<Consensus<N, P, V>>>
+where
+    N: ConnectedNetwork<PubKey>,
+    P: SequencerPersistence,
+    V: Versions,
+{
+    async fn state(&self, view: ViewNumber) -> Option<Arc<ValidatedState>> {
+        self.read().await.state(view).await
+    }
+
+    async fn decided_state(&self) -> Arc<ValidatedState> {
+        self.read().await.decided_state().await
+    }
+}
+
+/// Serves state sync requests from peers.
+pub(crate) struct StateSyncServer {
+    source: Arc<dyn StateSource>,
+    limiter: PeerRateLimiter,
+}
+
+impl StateSyncServer {
+    pub(crate) fn new(source: Arc<dyn StateSource>) -> Self {
+        Self {
+            source,
+            limiter: Default::default(),
+        }
+    }
+
+    /// Answer a request from `peer`.
+    ///
+    /// Returns [`None`] if `peer` has exceeded its rate limit, in which case the request should be
+    /// dropped without a response.
+    pub(crate) async fn respond(
+        &self,
+        peer: PubKey,
+        request: StateSyncRequest,
+    ) -> Option<StateSyncResponse> {
+        if !self.limiter.check(peer, request.cost(), Instant::now()) {
+            tracing::debug!(%peer, "peer exceeded state sync rate limit");
+            return None;
+        }
+        Some(respond(&*self.source, request).await)
+    }
+}
+
+/// Answer a state sync request from `source`.
+async fn respond(source: &dyn StateSource, request: StateSyncRequest) -> StateSyncResponse {
+    let response = match request {
+        StateSyncRequest::State { height, view } => match source.state(view).await {
+            Some(state) => StateSyncResponse::State {
+                fee_merkle_tree: state.fee_merkle_tree.clone(),
+                block_merkle_tree: state.block_merkle_tree.clone(),
+            },
+            None => not_in_memory(height, view),
+        },
+        StateSyncRequest::Accounts {
+            height,
+            view,
+            accounts,
+        } => {
+            if accounts.len() > MAX_ACCOUNTS_PER_REQUEST {
+                return StateSyncResponse::TooLarge;
+            }
+            match source.state(view).await {
+                Some(state) => match retain_accounts(&state.fee_merkle_tree, accounts) {
+                    Ok(snapshot) => StateSyncResponse::Accounts(snapshot),
+                    Err(err) => {
+                        tracing::info!(height, ?view, "cannot serve accounts to peer: {err:#}");
+                        StateSyncResponse::NotFound
+                    }
+                },
+                None => not_in_memory(height, view),
+            }
+        }
+        StateSyncRequest::Frontier { height, view } => match source.state(view).await {
+            Some(state) => {
+                let tree = &state.block_merkle_tree;
+                match tree.num_leaves().checked_sub(1) {
+                    Some(last) => match tree.lookup(last).expect_ok() {
+                        Ok((_, frontier)) => StateSyncResponse::Frontier(frontier),
+                        Err(_) => StateSyncResponse::NotFound,
+                    },
+                    None => StateSyncResponse::NotFound,
+                }
+            }
+            None => not_in_memory(height, view),
+        },
+        StateSyncRequest::ChainConfig(commitment) => {
+            let state = source.decided_state().await;
+            match state.chain_config.resolve() {
+                Some(chain_config) if chain_config.commit() == commitment => {
+                    StateSyncResponse::ChainConfig(chain_config)
+                }
+                _ => StateSyncResponse::NotFound,
+            }
+        }
+    };
+    match bincode::serialized_size(&response) {
+        Ok(size) if size <= MAX_RESPONSE_SIZE => response,
+        Ok(size) => {
+            tracing::info!(size, "state sync response is too large to send");
+            StateSyncResponse::TooLarge
+        }
+        Err(err) => {
+            tracing::warn!("failed to compute size of state sync response: {err:#}");
+            StateSyncResponse::NotFound
+        }
+    }
+}
+
+fn not_in_memory(height: u64, view: ViewNumber) -> StateSyncResponse {
+    tracing::info!(height, ?view, "state requested by peer is not in memory");
+    StateSyncResponse::NotFound
+}
+
+/// Per-peer token buckets limiting how often each peer may make requests.
+#[derive(Debug, Default)]
+struct PeerRateLimiter {
+    peers: Mutex<HashMap<PubKey, PeerBucket>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PeerBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl PeerBucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * PEER_REQUEST_RATE).min(PEER_REQUEST_BURST);
+        self.updated = now;
+    }
+}
+
+impl PeerRateLimiter {
+    /// Take `cost` tokens from the bucket for `peer`, if it has enough.
+    fn check(&self, peer: PubKey, cost: f64, now: Instant) -> bool {
+        let mut peers = self.peers.lock();
+        if peers.len() >= MAX_TRACKED_PEERS && !peers.contains_key(&peer) {
+            // Forget peers whose buckets have refilled; they are indistinguishable from new peers.
+            peers.retain(|_, bucket| {
+                bucket.refill(now);
+                bucket.tokens < PEER_REQUEST_BURST
+            });
+        }
+        let bucket = peers.entry(peer).or_insert(PeerBucket {
+            tokens: PEER_REQUEST_BURST,
+            updated: now,
+        });
+        bucket.refill(now);
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Requests which have been sent to peers and are awaiting a response.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PendingRequests {
+    inner: Arc<Mutex<PendingRequestsInner>>,
+}
+
+#[derive(Debug, Default)]
+struct PendingRequestsInner {
+    next_id: u64,
+    requests: HashMap<u64, oneshot::Sender<StateSyncResponse>>,
+}
+
+impl PendingRequests {
+    /// Allocate an ID for a new request, and a channel on which its response will be delivered.
+    fn register(&self) -> (u64, oneshot::Receiver<StateSyncResponse>) {
+        let mut inner = self.inner.lock();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let (sender, receiver) = oneshot::channel();
+        inner.requests.insert(id, sender);
+        (id, receiver)
+    }
+
+    fn cancel(&self, id: u64) {
+        self.inner.lock().requests.remove(&id);
+    }
+
+    /// Deliver the response to request `id`.
+    ///
+    /// Responses to requests which have already been answered, or which have timed out, are
+    /// ignored.
+    pub(crate) fn deliver(&self, id: u64, response: StateSyncResponse) {
+        let Some(sender) = self.inner.lock().requests.remove(&id) else {
+            tracing::debug!(id, "received response to unknown state sync request");
+            return;
+        };
+        // If the receiver has been dropped, the request was abandoned and there is nothing to do.
+        sender.send(response).ok();
+    }
+}
+
+/// A [`StateCatchup`] provider which fetches state from consensus peers over the network.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""))]
+pub struct StateSyncClient<N: ConnectedNetwork<PubKey>> {
+    #[derivative(Debug = "ignore")]
+    network: Arc<N>,
+    public_key: PubKey,
+    peers: Vec<PubKey>,
+    requests: PendingRequests,
+    backoff: BackoffParams,
+}
+
+impl<N: ConnectedNetwork<PubKey>> StateSyncClient<N> {
+    /// Create a client which sends requests from `public_key` to `peers`.
+    ///
+    /// `public_key` is excluded from `peers`, so the full list of consensus nodes can be given.
+    pub fn new(
+        network: Arc<N>,
+        public_key: PubKey,
+        peers: impl IntoIterator<Item = PubKey>,
+        backoff: BackoffParams,
+    ) -> Self {
+        Self {
+            network,
+            public_key,
+            peers: peers
+                .into_iter()
+                .filter(|peer| *peer != public_key)
+                .collect(),
+            requests: Default::default(),
+            backoff,
+        }
+    }
+
+    /// The requests awaiting responses from peers.
+    ///
+    /// Responses are received as consensus events, and must be passed back to the client via
+    /// [`PendingRequests::deliver`].
+    pub(crate) fn requests(&self) -> PendingRequests {
+        self.requests.clone()
+    }
+
+    /// Fetch the fee and block Merkle trees as of `header`, which was decided in `view`.
+    pub async fn fetch_state(
+        &self,
+        header: &Header,
+        view: ViewNumber,
+    ) -> anyhow::Result<(FeeMerkleTree, BlockMerkleTree)> {
+        self.backoff
+            .retry(self, |client| {
+                client
+                    .try_fetch_state(
+                        header.height(),
+                        view,
+                        header.fee_merkle_tree_root(),
+                        header.block_merkle_tree_root(),
+                    )
+                    .boxed()
+            })
+            .await
+    }
+
+    /// Fetch and verify the fee and block Merkle trees with the given roots.
+    async fn try_fetch_state(
+        &self,
+        height: u64,
+        view: ViewNumber,
+        fee_root: FeeMerkleCommitment,
+        block_root: BlockMerkleCommitment,
+    ) -> anyhow::Result<(FeeMerkleTree, BlockMerkleTree)> {
+        self.request_any(StateSyncRequest::State { height, view }, |res| {
+            verify_state(res, fee_root, block_root)
+        })
+        .await
+    }
+
+    /// Send `request` to randomly chosen peers until one of them gives a valid response.
+    async fn request_any<T>(
+        &self,
+        request: StateSyncRequest,
+        verify: impl Fn(StateSyncResponse) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let peers = self
+            .peers
+            .choose_multiple(&mut rand::thread_rng(), MAX_PEERS_PER_REQUEST)
+            .collect::<Vec<_>>();
+        for peer in peers {
+            let start = Instant::now();
+            let res = match self.request(peer, request.clone()).await {
+                Ok(res) => res,
+                Err(err) => {
+                    tracing::info!(%peer, ?request, "state sync request failed: {err:#}");
+                    continue;
+                }
+            };
+            match verify(res) {
+                Ok(res) => {
+                    tracing::debug!(%peer, ?request, elapsed = ?start.elapsed(), "synced state from peer");
+                    return Ok(res);
+                }
+                Err(err) => {
+                    tracing::warn!(%peer, ?request, "invalid state sync response: {err:#}");
+                }
+            }
+        }
+        bail!("could not sync state from any peer");
+    }
+
+    async fn request(
+        &self,
+        peer: &PubKey,
+        request: StateSyncRequest,
+    ) -> anyhow::Result<StateSyncResponse> {
+        let (id, response) = self.requests.register();
+        let res = async {
+            let message = encode_external_message(
+                &self.public_key,
+                &ExternalMessage::StateSyncRequest {
+                    requester: self.public_key,
+                    id,
+                    request,
+                },
+            )?;
+            self.network
+                .direct_message(message, *peer)
+                .await
+                .map_err(|err| anyhow!("failed to send request: {err:?}"))?;
+            timeout(REQUEST_TIMEOUT, response)
+                .await
+                .context("timed out waiting for response")?
+                .context("request was dropped")
+        }
+        .await;
+        self.requests.cancel(id);
+        res
+    }
+}
+
+/// Check that a response contains Merkle trees with the expected roots.
+fn verify_state(
+    res: StateSyncResponse,
+    fee_root: FeeMerkleCommitment,
+    block_root: BlockMerkleCommitment,
+) -> anyhow::Result<(FeeMerkleTree, BlockMerkleTree)> {
+    let StateSyncResponse::State {
+        fee_merkle_tree,
+        block_merkle_tree,
+    } = res
+    else {
+        bail!("peer does not have the requested state");
+    };
+    ensure!(
+        fee_merkle_tree.commitment() == fee_root,
+        "fee Merkle tree root {} does not match expected root {fee_root}",
+        fee_merkle_tree.commitment()
+    );
+    ensure!(
+        block_merkle_tree.commitment() == block_root,
+        "block Merkle tree root {} does not match expected root {block_root}",
+        block_merkle_tree.commitment()
+    );
+    Ok((fee_merkle_tree, block_merkle_tree))
+}
+
+#[async_trait]
+impl<N: ConnectedNetwork<PubKey>> StateCatchup for StateSyncClient<N> {
+    #[tracing::instrument(skip(self, _instance))]
+    async fn try_fetch_accounts(
+        &self,
+        _instance: &NodeState,
+        height: u64,
+        view: ViewNumber,
+        fee_merkle_tree_root: FeeMerkleCommitment,
+        accounts: &[FeeAccount],
+    ) -> anyhow::Result<FeeMerkleTree> {
+        let request = StateSyncRequest::Accounts {
+            height,
+            view,
+            accounts: accounts.to_vec(),
+        };
+        self.request_any(request, |res| {
+            let StateSyncResponse::Accounts(snapshot) = res else {
+                bail!("peer does not have the requested accounts");
+            };
+            for account in accounts {
+                let (proof, _) = FeeAccountProof::prove(&snapshot, (*account).into())
+                    .context(format!("response is missing account {account}"))?;
+                proof
+                    .verify(&fee_merkle_tree_root)
+                    .context(format!("invalid proof for account {account}"))?;
+            }
+            Ok(snapshot)
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip(self, _instance, mt))]
+    async fn try_remember_blocks_merkle_tree(
+        &self,
+        _instance: &NodeState,
+        height: u64,
+        view: ViewNumber,
+        mt: &mut BlockMerkleTree,
+    ) -> anyhow::Result<()> {
+        if mt.num_leaves() == 0 {
+            return Ok(());
+        }
+        let tree = &*mt;
+        let remembered = self
+            .request_any(StateSyncRequest::Frontier { height, view }, |res| {
+                let StateSyncResponse::Frontier(frontier) = res else {
+                    bail!("peer does not have the requested frontier");
+                };
+                let elem = frontier
+                    .elem()
+                    .context("frontier is missing leaf element")?;
+                // Remembering the frontier checks it against the root of the tree.
+                let mut tree = tree.clone();
+                tree.remember(tree.num_leaves() - 1, *elem, &frontier)
+                    .context("invalid frontier")?;
+                Ok(tree)
+            })
+            .await?;
+        *mt = remembered;
+        Ok(())
+    }
+
+    async fn try_fetch_chain_config(
+        &self,
+        commitment: Commitment<ChainConfig>,
+    ) -> anyhow::Result<ChainConfig> {
+        self.request_any(StateSyncRequest::ChainConfig(commitment), |res| {
+            let StateSyncResponse::ChainConfig(chain_config) = res else {
+                bail!("peer does not have the requested chain config");
+            };
+            ensure!(
+                chain_config.commit() == commitment,
+                "chain config {} does not match expected commitment {commitment}",
+                chain_config.commit()
+            );
+            Ok(chain_config)
+        })
+        .await
+    }
+
+    fn backoff(&self) -> &BackoffParams {
+        &self.backoff
+    }
+
+    fn name(&self) -> String {
+        format!("StateSyncClient({} peers)", self.peers.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use espresso_types::FeeAmount;
+    use ethers::types::Address;
+    use hotshot_types::traits::{node_implementation::ConsensusTime, signature_key::SignatureKey};
+    use jf_merkle_tree::UniversalMerkleTreeScheme;
+
+    use super::*;
+
+    struct MockSource {
+        view: ViewNumber,
+        state: Arc<ValidatedState>,
+    }
+
+    #[async_trait]
+    impl StateSource for MockSource {
+        async fn state(&self, view: ViewNumber) -> Option<Arc<ValidatedState>> {
+            (view == self.view).then(|| self.state.clone())
+        }
+
+        async fn decided_state(&self) -> Arc<ValidatedState> {
+            self.state.clone()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_state_sync_respond_and_verify() {
+        let mut state = ValidatedState::default();
+        let account = FeeAccount(Address::repeat_byte(1));
+        state
+            .fee_merkle_tree
+            .update(account, FeeAmount::from(100))
+            .unwrap();
+        let fee_root = state.fee_merkle_tree.commitment();
+        let block_root = state.block_merkle_tree.commitment();
+        let chain_config = state.chain_config.resolve().unwrap();
+        let source = MockSource {
+            view: ViewNumber::new(1),
+            state: Arc::new(state),
+        };
+
+        // State is only served for views in memory.
+        let res = respond(
+            &source,
+            StateSyncRequest::State {
+                height: 0,
+                view: ViewNumber::new(2),
+            },
+        )
+        .await;
+        verify_state(res, fee_root, block_root).unwrap_err();
+
+        let req = StateSyncRequest::State {
+            height: 0,
+            view: ViewNumber::new(1),
+        };
+        let res = respond(&source, req.clone()).await;
+        let (fee_merkle_tree, _) = verify_state(res, fee_root, block_root).unwrap();
+        assert_eq!(
+            fee_merkle_tree.lookup(account).expect_ok().unwrap().0,
+            FeeAmount::from(100)
+        );
+
+        // A response which does not match the expected root is rejected.
+        let mut res = respond(&source, req).await;
+        if let StateSyncResponse::State {
+            fee_merkle_tree, ..
+        } = &mut res
+        {
+            fee_merkle_tree
+                .update(account, FeeAmount::from(1000))
+                .unwrap();
+        }
+        verify_state(res, fee_root, block_root).unwrap_err();
+
+        // Chain configs are served by commitment.
+        let res = respond(
+            &source,
+            StateSyncRequest::ChainConfig(chain_config.commit()),
+        )
+        .await;
+        assert!(matches!(res, StateSyncResponse::ChainConfig(cf) if cf == chain_config));
+        let other = ChainConfig {
+            max_block_size: 1u64.into(),
+            ..chain_config
+        };
+        let res = respond(&source, StateSyncRequest::ChainConfig(other.commit())).await;
+        assert!(matches!(res, StateSyncResponse::NotFound));
+
+        // Account requests are answered with proofs for just the requested accounts.
+        let res = respond(
+            &source,
+            StateSyncRequest::Accounts {
+                height: 0,
+                view: ViewNumber::new(1),
+                accounts: vec![account],
+            },
+        )
+        .await;
+        let StateSyncResponse::Accounts(snapshot) = res else {
+            panic!("expected accounts, got {res:?}");
+        };
+        let (proof, balance) = FeeAccountProof::prove(&snapshot, account.into()).unwrap();
+        proof.verify(&fee_root).unwrap();
+        assert_eq!(balance, FeeAmount::from(100).0);
+
+        let res = respond(
+            &source,
+            StateSyncRequest::Accounts {
+                height: 0,
+                view: ViewNumber::new(1),
+                accounts: vec![account; MAX_ACCOUNTS_PER_REQUEST + 1],
+            },
+        )
+        .await;
+        assert!(matches!(res, StateSyncResponse::TooLarge));
+    }
+
+    #[test]
+    fn test_peer_rate_limiter() {
+        let limiter = PeerRateLimiter::default();
+        let peer = PubKey::generated_from_seed_indexed([0; 32], 0).0;
+        let other = PubKey::generated_from_seed_indexed([0; 32], 1).0;
+        let now = Instant::now();
+
+        // A request for the full state uses up the whole burst.
+        let full = StateSyncRequest::State {
+            height: 0,
+            view: ViewNumber::new(0),
+        }
+        .cost();
+        assert!(limiter.check(peer, full, now));
+        assert!(!limiter.check(peer, 1.0, now));
+
+        // Other peers have their own limits.
+        assert!(limiter.check(other, 1.0, now));
+
+        // The bucket refills over time.
+        let later = now + Duration::from_secs_f64(1.0 / PEER_REQUEST_RATE);
+        assert!(limiter.check(peer, 1.0, later));
+        assert!(!limiter.check(peer, 1.0, later));
+        assert!(!limiter.check(peer, full, later + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_pending_requests() {
+        let requests = PendingRequests::default();
+        let (id1, mut res1) = requests.register();
+        let (id2, mut res2) = requests.register();
+        assert_ne!(id1, id2);
+
+        requests.deliver(id1, StateSyncResponse::NotFound);
+        assert!(matches!(res1.try_recv(), Ok(StateSyncResponse::NotFound)));
+
+        // Responses to cancelled or already answered requests are ignored.
+        requests.cancel(id2);
+        requests.deliver(id2, StateSyncResponse::NotFound);
+        requests.deliver(id1, StateSyncResponse::NotFound);
+        assert!(res2.try_recv().is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! Supervision of long-running background tasks.
+//!
+//! A background task which panics or returns would otherwise simply disappear, leaving the node
+//! running without, say, its pruner until someone notices. Every long-running task spawned on a
+//! [`TaskList`](crate::context::TaskList) is instead run under supervision: panics are caught and
+//! logged, and the [`TaskStatus`] of each task is tracked so it can be served on `status/tasks`.
+//! Tasks spawned with a [`RestartPolicy::Backoff`] policy are restarted when they stop, with
+//! exponentially increasing delays if they keep failing.
+
+use std::{
+    any::Any,
+    cmp::min,
+    fmt::Display,
+    panic::AssertUnwindSafe,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use futures::{Future, FutureExt};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+/// What to do when a supervised task stops.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Record that the task stopped, but do not restart it.
+    Never,
+    /// Restart the task after a delay.
+    ///
+    /// The delay starts at `base` and doubles each time the task stops again soon after being
+    /// restarted, up to `max`. Once the task has run for at least `max`, the delay is reset.
+    Backoff { base: Duration, max: Duration },
+}
+
+impl RestartPolicy {
+    /// Restart with backoff from 1 second up to 1 minute.
+    pub fn restart() -> Self {
+        Self::Backoff {
+            base: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The state of a supervised task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    /// The task stopped and is waiting to be restarted.
+    Restarting,
+    /// The task returned and will not be restarted.
+    Exited,
+    /// The task panicked and will not be restarted.
+    Panicked,
+}
+
+/// The health of a supervised task, as served on `status/tasks`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TaskStatus {
+    pub name: String,
+    pub state: TaskState,
+    /// Whether the task is restarted when it stops.
+    pub restartable: bool,
+    /// The number of times the task has been restarted.
+    pub restarts: u64,
+    /// When the task was last (re)started, in seconds since the Unix epoch.
+    pub started_at: u64,
+    /// Why the task last stopped, if it has.
+    pub last_failure: Option<String>,
+    /// When the task last stopped, in seconds since the Unix epoch.
+    pub last_failure_at: Option<u64>,
+}
+
+/// A shared handle to the status of a supervised task.
+#[derive(Clone, Debug)]
+pub(crate) struct TaskHealth(Arc<Mutex<TaskStatus>>);
+
+impl TaskHealth {
+    pub(crate) fn new(name: impl Display, policy: RestartPolicy) -> Self {
+        Self(Arc::new(Mutex::new(TaskStatus {
+            name: name.to_string(),
+            state: TaskState::Running,
+            restartable: policy != RestartPolicy::Never,
+            restarts: 0,
+            started_at: now(),
+            last_failure: None,
+            last_failure_at: None,
+        })))
+    }
+
+    pub(crate) fn status(&self) -> TaskStatus {
+        self.0.lock().clone()
+    }
+
+    fn stopped(&self, state: TaskState, reason: String) {
+        let mut status = self.0.lock();
+        status.state = state;
+        status.last_failure = Some(reason);
+        status.last_failure_at = Some(now());
+    }
+
+    fn restarted(&self) {
+        let mut status = self.0.lock();
+        status.state = TaskState::Running;
+        status.restarts += 1;
+        status.started_at = now();
+    }
+}
+
+/// Run the task created by `make`, restarting it according to `policy` whenever it stops.
+pub(crate) async fn supervise<F>(
+    health: TaskHealth,
+    policy: RestartPolicy,
+    mut make: impl FnMut() -> F,
+) where
+    F: Future,
+{
+    let mut delay = None;
+    loop {
+        let start = Instant::now();
+        let (state, reason) = match AssertUnwindSafe(make()).catch_unwind().await {
+            Ok(_) => (TaskState::Exited, "task exited".to_string()),
+            Err(panic) => (TaskState::Panicked, panic_message(panic)),
+        };
+
+        let RestartPolicy::Backoff { base, max } = policy else {
+            if state == TaskState::Panicked {
+                tracing::error!(reason, "background task stopped");
+            }
+            health.stopped(state, reason);
+            return;
+        };
+
+        // Back off if the task keeps failing, but start over once it has been running for a while.
+        let next = match delay {
+            Some(delay) if start.elapsed() < max => min(delay * 2, max),
+            _ => base,
+        };
+        delay = Some(next);
+        tracing::error!(reason, ?next, "background task stopped, restarting");
+        health.stopped(TaskState::Restarting, reason);
+        sleep(next).await;
+        health.restarted();
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    let msg = if let Some(msg) = panic.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = panic.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    };
+    format!("task panicked: {msg}")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_restart_after_panic() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let policy = RestartPolicy::Backoff {
+            base: Duration::from_millis(10),
+            max: Duration::from_millis(100),
+        };
+        let health = TaskHealth::new("test", policy);
+
+        let task = {
+            let runs = runs.clone();
+            let health = health.clone();
+            supervise(health, policy, move || {
+                let runs = runs.clone();
+                async move {
+                    if runs.fetch_add(1, Ordering::SeqCst) < 2 {
+                        panic!("boom");
+                    }
+                    futures::future::pending::<()>().await;
+                }
+            })
+        };
+        let task = tokio::spawn(task);
+
+        while runs.load(Ordering::SeqCst) < 3 {
+            sleep(Duration::from_millis(10)).await;
+        }
+        let status = health.status();
+        assert_eq!(status.state, TaskState::Running);
+        assert_eq!(status.restarts, 2);
+        assert_eq!(status.last_failure.as_deref(), Some("task panicked: boom"));
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_no_restart() {
+        let health = TaskHealth::new("test", RestartPolicy::Never);
+        supervise(health.clone(), RestartPolicy::Never, || async {}).await;
+        let status = health.status();
+        assert_eq!(status.state, TaskState::Exited);
+        assert!(!status.restartable);
+        assert_eq!(status.restarts, 0);
+    }
+}
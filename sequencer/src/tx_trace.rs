@@ -0,0 +1,595 @@
+//! Tracing of transactions from submission to decide.
+//!
+//! The latency a client sees between submitting a transaction and seeing it decided is spread over
+//! the submit API, forwarding to consensus, inclusion in a block by the builder, and consensus on
+//! that block. To see where the time goes, a client can submit a transaction with a W3C trace
+//! context in the `traceparent` header of the submit request. [`TxTracer`] then follows the
+//! transaction through each stage and, once it is decided, emits OpenTelemetry spans for it as
+//! children of the client's span:
+//!
+//! * `sequence transaction`, from receiving the request until the transaction is decided, which is
+//!   the parent of one span per stage:
+//! * `submit`, until the transaction has been forwarded to consensus
+//! * `builder inclusion`, until the transaction appears in a DA proposal
+//! * `decide`, until the block containing the transaction is decided
+//!
+//! Only members of the DA committee see DA proposals. On other nodes, the last two stages are
+//! covered by a single `inclusion and decide` span.
+//!
+//! Spans are logged, and pushed to an OTLP/HTTP traces endpoint if one is configured. Transactions
+//! submitted without a sampled trace context are traced under a fresh trace ID at a configurable
+//! sample ratio. The number of transactions traced at once is bounded; when it is reached, the
+//! oldest trace is dropped.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{bail, ensure, Context};
+use clap::Parser;
+use committable::{Commitment, Committable};
+use espresso_types::{NamespaceId, Payload, SeqTypes, Transaction};
+use ethers::utils::hex;
+use hotshot::types::{Event, EventType};
+use hotshot_types::{event::LeafInfo, traits::BlockPayload};
+use parking_lot::Mutex;
+use rand::Rng;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::{channel, error::TrySendError, Receiver, Sender};
+use url::Url;
+
+use crate::{
+    api::otlp::{self, now, OtlpHeader},
+    context::TaskList,
+};
+
+/// Maximum number of decided transactions whose spans are waiting to be pushed.
+const EXPORT_QUEUE_CAPACITY: usize = 1000;
+
+/// Maximum number of decided transactions whose spans are pushed in a single request.
+const MAX_EXPORT_BATCH: usize = 100;
+
+/// `SPAN_KIND_INTERNAL` in the OTLP protocol.
+const SPAN_KIND_INTERNAL: u64 = 1;
+
+/// `SPAN_KIND_SERVER` in the OTLP protocol.
+const SPAN_KIND_SERVER: u64 = 2;
+
+#[derive(Parser, Clone, Debug)]
+pub struct TxTraceConfig {
+    /// URL of an OTLP/HTTP traces endpoint to push the spans of traced transactions to.
+    ///
+    /// This is the full URL of the endpoint, usually ending in `/v1/traces`. If not set, spans are
+    /// only logged.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_OTLP_TRACES_ENDPOINT")]
+    pub otlp_traces_endpoint: Option<Url>,
+
+    /// Headers to add to each OTLP export request, such as for authentication.
+    ///
+    /// A comma-separated list of `name=value` pairs.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_OTLP_TRACES_HEADERS",
+        value_delimiter = ','
+    )]
+    pub otlp_traces_headers: Vec<OtlpHeader>,
+
+    /// Fraction of transactions submitted without a sampled trace context to trace anyway.
+    ///
+    /// Transactions submitted with a sampled W3C `traceparent` header are always traced.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_TX_TRACE_SAMPLE_RATIO",
+        default_value = "0"
+    )]
+    pub tx_trace_sample_ratio: f64,
+
+    /// Maximum number of transactions traced at once.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_TX_TRACE_CAPACITY",
+        default_value = "10000"
+    )]
+    pub tx_trace_capacity: usize,
+}
+
+impl Default for TxTraceConfig {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+/// A W3C trace context, parsed from a `traceparent` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+impl FromStr for TraceParent {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut fields = s.trim().split('-');
+        let (Some(version), Some(trace_id), Some(span_id), Some(flags)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            bail!("traceparent {s:?} does not have four fields");
+        };
+        // Later versions may add fields, which we ignore, but version 00 has exactly four.
+        let version = parse_hex::<1>(version)?[0];
+        ensure!(version != 0xff, "invalid traceparent version {version:02x}");
+        ensure!(
+            version != 0 || fields.next().is_none(),
+            "traceparent {s:?} has too many fields"
+        );
+
+        let trace_id = parse_hex(trace_id)?;
+        let span_id = parse_hex(span_id)?;
+        ensure!(trace_id != [0; 16], "trace ID is all zeros");
+        ensure!(span_id != [0; 8], "span ID is all zeros");
+        let flags = parse_hex::<1>(flags)?[0];
+        Ok(Self {
+            trace_id,
+            span_id,
+            sampled: flags & 1 == 1,
+        })
+    }
+}
+
+impl Display for TraceParent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{}-{}-{:02x}",
+            hex::encode(self.trace_id),
+            hex::encode(self.span_id),
+            self.sampled as u8
+        )
+    }
+}
+
+fn parse_hex<const N: usize>(s: &str) -> anyhow::Result<[u8; N]> {
+    ensure!(
+        s.len() == 2 * N,
+        "expected {N} hex-encoded bytes, got {s:?}"
+    );
+    hex::decode(s)
+        .context(format!("malformed hex {s:?}"))?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("expected {N} bytes"))
+}
+
+/// The progress of a traced transaction. Times are in nanoseconds since the Unix epoch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Trace {
+    trace_id: [u8; 16],
+    /// The client's span, if the transaction was submitted with a trace context.
+    parent_span_id: Option<[u8; 8]>,
+    namespace: NamespaceId,
+    received: u64,
+    forwarded: Option<u64>,
+    included: Option<u64>,
+}
+
+impl Trace {
+    /// Finish the trace of the transaction `hash`, decided at `decided` in block `height`.
+    ///
+    /// Returns the spans of the trace, in the JSON encoding of OTLP.
+    fn finish(self, hash: Commitment<Transaction>, height: u64, decided: u64) -> Vec<Value> {
+        // Stages are clamped to be in order, in case they were observed out of order, such as a
+        // transaction gossiped to the builder being included before we finished forwarding it.
+        let forwarded = self.forwarded.unwrap_or(self.received).max(self.received);
+        let included = self
+            .included
+            .map(|included| included.max(forwarded).min(decided));
+        let elapsed = |from: u64, to: u64| Duration::from_nanos(to.saturating_sub(from));
+        tracing::info!(
+            %hash,
+            trace_id = hex::encode(self.trace_id),
+            height,
+            total = ?elapsed(self.received, decided),
+            submit = ?elapsed(self.received, forwarded),
+            inclusion = ?included.map(|included| elapsed(forwarded, included)),
+            "traced transaction decided",
+        );
+
+        let root = rand::random();
+        let mut spans = vec![span(
+            self.trace_id,
+            root,
+            self.parent_span_id,
+            "sequence transaction",
+            SPAN_KIND_SERVER,
+            (self.received, decided),
+            vec![
+                attribute("tx.hash", json!({ "stringValue": hash.to_string() })),
+                attribute(
+                    "tx.namespace",
+                    json!({ "intValue": self.namespace.to_string() }),
+                ),
+                attribute("block.height", json!({ "intValue": height.to_string() })),
+            ],
+        )];
+        let mut stage = |name, times| {
+            spans.push(span(
+                self.trace_id,
+                rand::random(),
+                Some(root),
+                name,
+                SPAN_KIND_INTERNAL,
+                times,
+                vec![],
+            ))
+        };
+        stage("submit", (self.received, forwarded));
+        match included {
+            Some(included) => {
+                stage("builder inclusion", (forwarded, included));
+                stage("decide", (included, decided));
+            }
+            None => stage("inclusion and decide", (forwarded, decided)),
+        }
+        spans
+    }
+}
+
+/// A span in the JSON encoding of OTLP, running from `start` to `end`.
+fn span(
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    name: &str,
+    kind: u64,
+    (start, end): (u64, u64),
+    attributes: Vec<Value>,
+) -> Value {
+    json!({
+        "traceId": hex::encode(trace_id),
+        "spanId": hex::encode(span_id),
+        "parentSpanId": parent_span_id.map(hex::encode).unwrap_or_default(),
+        "name": name,
+        "kind": kind,
+        "startTimeUnixNano": start.to_string(),
+        "endTimeUnixNano": end.to_string(),
+        "attributes": attributes,
+    })
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+#[derive(Debug, Default)]
+struct Traces {
+    pending: HashMap<Commitment<Transaction>, Trace>,
+    /// Traced transactions in the order they were received, for dropping the oldest trace.
+    ///
+    /// Entries for traces which have since finished are skipped when dropping, and compacted away
+    /// when they outnumber the pending traces.
+    order: VecDeque<(u64, Commitment<Transaction>)>,
+}
+
+impl Traces {
+    fn insert(&mut self, hash: Commitment<Transaction>, trace: Trace, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        while self.pending.len() >= capacity {
+            let Some((received, oldest)) = self.order.pop_front() else {
+                break;
+            };
+            if self.is_current(received, oldest) {
+                tracing::debug!(hash = %oldest, "dropping oldest transaction trace");
+                self.pending.remove(&oldest);
+            }
+        }
+        if self.order.len() >= 2 * capacity {
+            let order = std::mem::take(&mut self.order);
+            self.order = order
+                .into_iter()
+                .filter(|&(received, hash)| self.is_current(received, hash))
+                .collect();
+        }
+
+        self.order.push_back((trace.received, hash));
+        self.pending.insert(hash, trace);
+    }
+
+    /// Whether the trace of `hash` started at `received` is still pending.
+    fn is_current(&self, received: u64, hash: Commitment<Transaction>) -> bool {
+        self.pending
+            .get(&hash)
+            .is_some_and(|trace| trace.received == received)
+    }
+}
+
+/// Follows traced transactions from submission to decide.
+#[derive(Debug)]
+pub struct TxTracer {
+    sample_ratio: f64,
+    capacity: usize,
+    traces: Mutex<Traces>,
+    /// Queue of spans to push, if an OTLP endpoint is configured.
+    exports: Option<Sender<Vec<Value>>>,
+}
+
+impl TxTracer {
+    pub(crate) fn new(config: &TxTraceConfig, tasks: &mut TaskList) -> Self {
+        let exports = config.otlp_traces_endpoint.as_ref().map(|endpoint| {
+            let (send, recv) = channel(EXPORT_QUEUE_CAPACITY);
+            tasks.spawn(
+                "OTLP trace exporter",
+                export(endpoint.clone(), config.otlp_traces_headers.clone(), recv),
+            );
+            send
+        });
+        Self {
+            sample_ratio: config.tx_trace_sample_ratio,
+            capacity: config.tx_trace_capacity,
+            traces: Default::default(),
+            exports,
+        }
+    }
+
+    /// Start tracing a transaction received through the submit API, if it is sampled.
+    ///
+    /// `parent` is the trace context the client submitted the transaction with, if any. A
+    /// transaction which is already being traced keeps its original trace.
+    pub fn start(
+        &self,
+        hash: Commitment<Transaction>,
+        namespace: NamespaceId,
+        parent: Option<TraceParent>,
+    ) {
+        let parent = parent.filter(|parent| parent.sampled);
+        if parent.is_none() && rand::thread_rng().gen::<f64>() >= self.sample_ratio {
+            return;
+        }
+
+        let mut traces = self.traces.lock();
+        if traces.pending.contains_key(&hash) {
+            return;
+        }
+        let trace = Trace {
+            trace_id: parent.map_or_else(rand::random, |parent| parent.trace_id),
+            parent_span_id: parent.map(|parent| parent.span_id),
+            namespace,
+            received: now(),
+            forwarded: None,
+            included: None,
+        };
+        traces.insert(hash, trace, self.capacity);
+    }
+
+    /// Record the outcome of forwarding a transaction to consensus.
+    ///
+    /// The trace of a transaction which could not be forwarded is dropped, since it will not be
+    /// sequenced.
+    pub fn forwarded(&self, hash: Commitment<Transaction>, ok: bool) {
+        let mut traces = self.traces.lock();
+        if !ok {
+            traces.pending.remove(&hash);
+        } else if let Some(trace) = traces.pending.get_mut(&hash) {
+            trace.forwarded.get_or_insert_with(now);
+        }
+    }
+
+    /// Advance the traces of the transactions included in a DA proposal or decided by `event`.
+    pub(crate) fn handle_event(&self, event: &Event<SeqTypes>) {
+        if self.traces.lock().pending.is_empty() {
+            return;
+        }
+        match &event.event {
+            EventType::DaProposal { proposal, .. } => {
+                let payload = Payload::from_bytes(
+                    &proposal.data.encoded_transactions,
+                    &proposal.data.metadata,
+                );
+                let now = now();
+                let mut traces = self.traces.lock();
+                for tx in payload.transactions(payload.ns_table()) {
+                    if let Some(trace) = traces.pending.get_mut(&tx.commit()) {
+                        trace.included.get_or_insert(now);
+                    }
+                }
+            }
+            EventType::Decide { leaf_chain, .. } => {
+                let now = now();
+                let mut finished = vec![];
+                {
+                    let mut traces = self.traces.lock();
+                    for LeafInfo { leaf, .. } in leaf_chain.iter() {
+                        let Some(payload) = leaf.block_payload() else {
+                            continue;
+                        };
+                        for tx in payload.transactions(payload.ns_table()) {
+                            let hash = tx.commit();
+                            if let Some(trace) = traces.pending.remove(&hash) {
+                                finished.push((hash, leaf.height(), trace));
+                            }
+                        }
+                    }
+                }
+                for (hash, height, trace) in finished {
+                    let spans = trace.finish(hash, height, now);
+                    self.export(spans);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn export(&self, spans: Vec<Value>) {
+        let Some(exports) = &self.exports else {
+            return;
+        };
+        if let Err(TrySendError::Full(_)) = exports.try_send(spans) {
+            tracing::warn!("trace export queue is full, dropping spans");
+        }
+    }
+}
+
+/// Push spans from `spans` to an OTLP endpoint, batching those which queue up during a push.
+async fn export(endpoint: Url, headers: Vec<OtlpHeader>, mut spans: Receiver<Vec<Value>>) {
+    let client = reqwest::Client::new();
+    let mut batch = vec![];
+    while spans.recv_many(&mut batch, MAX_EXPORT_BATCH).await > 0 {
+        let request = export_request(batch.drain(..).flatten().collect());
+        if let Err(err) = otlp::post(&client, &endpoint, &headers, &request).await {
+            tracing::warn!(%endpoint, "failed to export transaction traces: {err:#}");
+        }
+    }
+}
+
+/// Build an OTLP export request for `spans`, in the JSON encoding.
+fn export_request(spans: Vec<Value>) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{
+                    "key": "service.name",
+                    "value": { "stringValue": "espresso-sequencer" },
+                }],
+            },
+            "scopeSpans": [{
+                "scope": {
+                    "name": "espresso-sequencer",
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_traceparent() {
+        let s = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let parent = s.parse::<TraceParent>().unwrap();
+        assert_eq!(
+            hex::encode(parent.trace_id),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(hex::encode(parent.span_id), "00f067aa0ba902b7");
+        assert!(parent.sampled);
+        assert_eq!(parent.to_string(), s);
+
+        // Later versions may have more fields.
+        "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra"
+            .parse::<TraceParent>()
+            .unwrap();
+
+        for invalid in [
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e47-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e473x-00f067aa0ba902b7-01",
+        ] {
+            invalid.parse::<TraceParent>().unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_trace_spans() {
+        let tx = Transaction::new(1u32.into(), vec![1]);
+        let parent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+            .parse::<TraceParent>()
+            .unwrap();
+        let trace = Trace {
+            trace_id: parent.trace_id,
+            parent_span_id: Some(parent.span_id),
+            namespace: tx.namespace(),
+            received: 10,
+            forwarded: Some(20),
+            included: Some(30),
+        };
+
+        let spans = trace.clone().finish(tx.commit(), 5, 40);
+        let names = spans.iter().map(|span| &span["name"]).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "sequence transaction",
+                "submit",
+                "builder inclusion",
+                "decide"
+            ]
+        );
+        let root = &spans[0];
+        assert_eq!(root["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(root["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(root["startTimeUnixNano"], "10");
+        assert_eq!(root["endTimeUnixNano"], "40");
+        for stage in &spans[1..] {
+            assert_eq!(stage["traceId"], root["traceId"]);
+            assert_eq!(stage["parentSpanId"], root["spanId"]);
+        }
+        assert_eq!(spans[2]["startTimeUnixNano"], "20");
+        assert_eq!(spans[2]["endTimeUnixNano"], "30");
+
+        // Without a DA proposal, inclusion and decide are a single stage.
+        let spans = Trace {
+            included: None,
+            ..trace
+        }
+        .finish(tx.commit(), 5, 40);
+        assert_eq!(spans.len(), 3);
+        assert_eq!(spans[2]["name"], "inclusion and decide");
+        assert_eq!(spans[2]["startTimeUnixNano"], "20");
+    }
+
+    #[test]
+    fn test_tracer() {
+        let config = TxTraceConfig {
+            tx_trace_capacity: 2,
+            ..Default::default()
+        };
+        let tracer = TxTracer::new(&config, &mut Default::default());
+        let parent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+            .parse::<TraceParent>()
+            .unwrap();
+        let txs = (0..3)
+            .map(|i| Transaction::new(1u32.into(), vec![i]))
+            .collect::<Vec<_>>();
+        let pending = |tx: &Transaction| tracer.traces.lock().pending.get(&tx.commit()).cloned();
+
+        // Transactions without a sampled trace context are not traced at the default ratio.
+        tracer.start(txs[0].commit(), txs[0].namespace(), None);
+        let unsampled = TraceParent {
+            sampled: false,
+            ..parent
+        };
+        tracer.start(txs[0].commit(), txs[0].namespace(), Some(unsampled));
+        assert_eq!(pending(&txs[0]), None);
+
+        tracer.start(txs[0].commit(), txs[0].namespace(), Some(parent));
+        tracer.forwarded(txs[0].commit(), true);
+        let trace = pending(&txs[0]).unwrap();
+        assert_eq!(trace.trace_id, parent.trace_id);
+        assert!(trace.forwarded.is_some());
+
+        // A transaction which could not be forwarded is no longer traced.
+        tracer.start(txs[1].commit(), txs[1].namespace(), Some(parent));
+        tracer.forwarded(txs[1].commit(), false);
+        assert_eq!(pending(&txs[1]), None);
+
+        // When full, the oldest trace is dropped.
+        tracer.start(txs[1].commit(), txs[1].namespace(), Some(parent));
+        tracer.start(txs[2].commit(), txs[2].namespace(), Some(parent));
+        assert_eq!(pending(&txs[0]), None);
+        assert!(pending(&txs[1]).is_some());
+        assert!(pending(&txs[2]).is_some());
+    }
+}
@@ -0,0 +1,163 @@
+//! Tracking of scheduled protocol upgrades.
+//!
+//! Upgrades are scheduled in the genesis file, each with a window of views or times in which nodes
+//! may propose it and the chain config it puts into effect. Consensus takes care of the rest: a node
+//! proposes the upgrade within its window, a quorum votes for it, and once the resulting upgrade
+//! certificate is decided, every node switches to the new version at the certificate's
+//! `new_version_first_view`, without a restart.
+//!
+//! The [`UpgradeManager`] follows this process through consensus events and records how far each
+//! upgrade has progressed. Statuses are persisted in [`SequencerPersistence`], so a node which
+//! restarts still knows which upgrades have already been activated. Together with the schedule,
+//! they are served by the `config/upgrades` endpoint.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use anyhow::Context;
+use espresso_types::{
+    v0::traits::SequencerPersistence, SeqTypes, Upgrade, UpgradeRecord, UpgradeStatus,
+};
+use hotshot::types::{Event, EventType};
+use hotshot_types::{event::LeafInfo, traits::node_implementation::ConsensusTime};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use vbs::version::Version;
+
+/// A protocol upgrade, as reported by `config/upgrades`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpgradeInfo {
+    pub version: Version,
+    /// The schedule for this upgrade, if it is configured on this node.
+    pub upgrade: Option<Upgrade>,
+    pub status: UpgradeStatus,
+}
+
+/// Tracks the progress of protocol upgrades through consensus.
+#[derive(Debug)]
+pub struct UpgradeManager<P> {
+    persistence: Arc<P>,
+    scheduled: BTreeMap<Version, Upgrade>,
+    statuses: Mutex<BTreeMap<Version, UpgradeStatus>>,
+}
+
+impl<P: SequencerPersistence> UpgradeManager<P> {
+    pub fn new(persistence: Arc<P>, scheduled: BTreeMap<Version, Upgrade>) -> Self {
+        Self {
+            persistence,
+            scheduled,
+            statuses: Default::default(),
+        }
+    }
+
+    /// Load the statuses recorded before this node last shut down.
+    pub async fn restore(&self) -> anyhow::Result<()> {
+        let records = self
+            .persistence
+            .load_upgrades()
+            .await
+            .context("loading upgrade statuses")?;
+        let mut statuses = self.statuses.lock();
+        for UpgradeRecord { version, status } in records {
+            statuses.insert(version, status);
+        }
+        Ok(())
+    }
+
+    /// Every upgrade which is scheduled or has been observed, with its current status.
+    pub fn upgrades(&self) -> Vec<UpgradeInfo> {
+        let statuses = self.statuses.lock();
+        let mut upgrades = self
+            .scheduled
+            .iter()
+            .map(|(version, upgrade)| {
+                (
+                    *version,
+                    UpgradeInfo {
+                        version: *version,
+                        upgrade: Some(upgrade.clone()),
+                        status: UpgradeStatus::Scheduled,
+                    },
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+        for (version, status) in statuses.iter() {
+            upgrades
+                .entry(*version)
+                .or_insert_with(|| UpgradeInfo {
+                    version: *version,
+                    upgrade: None,
+                    status: *status,
+                })
+                .status = *status;
+        }
+        upgrades.into_values().collect()
+    }
+
+    /// Update upgrade statuses based on an event from consensus.
+    pub async fn handle_event(&self, event: &Event<SeqTypes>) {
+        let mut updates = vec![];
+        match &event.event {
+            EventType::UpgradeProposal { proposal, .. } => {
+                let upgrade = &proposal.data.upgrade_proposal;
+                updates.push(UpgradeRecord {
+                    version: upgrade.new_version,
+                    status: UpgradeStatus::Proposed {
+                        view: proposal.data.view_number.u64(),
+                        new_version_first_view: upgrade.new_version_first_view.u64(),
+                    },
+                });
+            }
+            EventType::Decide { leaf_chain, .. } => {
+                // Leaves are ordered newest first.
+                for LeafInfo { leaf, .. } in leaf_chain.iter().rev() {
+                    if let Some(cert) = leaf.upgrade_certificate() {
+                        updates.push(UpgradeRecord {
+                            version: cert.data.new_version,
+                            status: UpgradeStatus::Certified {
+                                view: leaf.view_number().u64(),
+                                new_version_first_view: cert.data.new_version_first_view.u64(),
+                            },
+                        });
+                    }
+                    updates.push(UpgradeRecord {
+                        version: leaf.block_header().version(),
+                        status: UpgradeStatus::Activated {
+                            view: leaf.view_number().u64(),
+                            height: leaf.height(),
+                        },
+                    });
+                }
+            }
+            _ => return,
+        }
+
+        let updates = {
+            let mut statuses = self.statuses.lock();
+            updates
+                .into_iter()
+                .filter(|update| {
+                    // Every decided block "activates" its version, but blocks with the base version
+                    // don't activate anything, so only count activations of known upgrades.
+                    let known = !matches!(update.status, UpgradeStatus::Activated { .. })
+                        || self.scheduled.contains_key(&update.version)
+                        || statuses.contains_key(&update.version);
+                    let progress = statuses
+                        .get(&update.version)
+                        .map(UpgradeStatus::progress)
+                        .unwrap_or_default();
+                    if !known || update.status.progress() <= progress {
+                        return false;
+                    }
+                    tracing::info!(version = %update.version, status = ?update.status, "upgrade progressed");
+                    statuses.insert(update.version, update.status);
+                    true
+                })
+                .collect::<Vec<_>>()
+        };
+        for update in updates {
+            if let Err(err) = self.persistence.store_upgrade(&update).await {
+                tracing::warn!(version = %update.version, "failed to persist upgrade status: {err:#}");
+            }
+        }
+    }
+}
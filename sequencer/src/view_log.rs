@@ -0,0 +1,250 @@
+//! A log of what this node observed in each view, for post-mortems of failed views.
+//!
+//! When a view times out, the logs of a single node rarely say why. The view log records, for each
+//! view, the leader, when the proposal and DA proposal arrived, how many votes the resulting quorum
+//! certificate collected, and how the view ended. A view which timed out without a proposal points
+//! at the leader, while one which timed out after the proposal arrived points at voting.
+//!
+//! Records are saved in persistent storage as the view progresses, and served on
+//! `node/view-log/:view` and `node/view-log/:from/:until`. Records older than
+//! [`VIEW_LOG_RETENTION`] views are pruned.
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_lock::RwLock;
+use espresso_types::{v0::traits::SequencerPersistence, PubKey, ViewOutcome, ViewRecord};
+use futures::StreamExt;
+use hotshot::types::{Event, EventType};
+use hotshot_types::{
+    data::ViewNumber,
+    traits::{
+        election::Membership,
+        network::ConnectedNetwork,
+        node_implementation::{ConsensusTime, Versions},
+    },
+};
+
+use crate::{context::Consensus, SeqTypes};
+
+/// Number of views for which records are kept.
+pub const VIEW_LOG_RETENTION: u64 = 100_000;
+
+/// How often, in views, to prune old records.
+const PRUNE_INTERVAL: u64 = 1000;
+
+/// Number of recent views kept in memory, waiting for the votes on their proposals.
+const WINDOW: u64 = 10;
+
+/// Records of recent views, updated from consensus events.
+#[derive(Debug, Default)]
+pub(crate) struct ViewLog {
+    records: BTreeMap<u64, ViewRecord>,
+}
+
+impl ViewLog {
+    /// Update the log from a consensus event.
+    ///
+    /// `leader` gives the leader of a view, and `now` the current time in milliseconds since the
+    /// Unix epoch. Returns the records which changed and should be saved.
+    pub(crate) fn handle_event(
+        &mut self,
+        event: &Event<SeqTypes>,
+        now: u64,
+        leader: impl Fn(ViewNumber) -> Option<PubKey>,
+    ) -> Vec<ViewRecord> {
+        let mut changed = vec![];
+        match &event.event {
+            EventType::ViewFinished { view_number } => {
+                let record = self.record(*view_number, now, &leader);
+                if record.outcome.is_none() {
+                    record.outcome = Some(ViewOutcome::Completed);
+                    record.ended_at = Some(now);
+                    changed.push(record.clone());
+                }
+                changed.push(self.record(*view_number + 1, now, &leader).clone());
+            }
+            EventType::ViewTimeout { view_number } => {
+                let record = self.record(*view_number, now, &leader);
+                record.outcome = Some(if record.proposal_received_at.is_some() {
+                    ViewOutcome::NoQuorum
+                } else {
+                    ViewOutcome::NoProposal
+                });
+                record.ended_at = Some(now);
+                changed.push(record.clone());
+            }
+            EventType::QuorumProposal { proposal, .. } => {
+                let record = self.record(proposal.data.view_number, now, &leader);
+                if record.proposal_received_at.is_none() {
+                    record.proposal_received_at = Some(now);
+                    changed.push(record.clone());
+                }
+
+                // The proposal carries the certificate formed from the votes on its parent.
+                let qc = &proposal.data.justify_qc;
+                let votes = qc
+                    .signatures
+                    .as_ref()
+                    .map(|(_, signers)| signers.count_ones() as u64);
+                if let Some(record) = self.records.get_mut(&qc.view_number.u64()) {
+                    if votes.is_some() && record.votes != votes {
+                        record.votes = votes;
+                        changed.push(record.clone());
+                    }
+                }
+            }
+            EventType::DaProposal { proposal, .. } => {
+                let record = self.record(proposal.data.view_number, now, &leader);
+                if record.da_proposal_received_at.is_none() {
+                    record.da_proposal_received_at = Some(now);
+                    changed.push(record.clone());
+                }
+            }
+            _ => {}
+        }
+
+        // Forget views which are too old to be updated.
+        if let Some(&latest) = self.records.keys().next_back() {
+            self.records = self.records.split_off(&latest.saturating_sub(WINDOW));
+        }
+        changed
+    }
+
+    fn record(
+        &mut self,
+        view: ViewNumber,
+        now: u64,
+        leader: impl Fn(ViewNumber) -> Option<PubKey>,
+    ) -> &mut ViewRecord {
+        self.records
+            .entry(view.u64())
+            .or_insert_with(|| ViewRecord {
+                view: view.u64(),
+                leader: leader(view),
+                started_at: now,
+                proposal_received_at: None,
+                da_proposal_received_at: None,
+                votes: None,
+                ended_at: None,
+                outcome: None,
+            })
+    }
+}
+
+/// Record each view observed by `consensus` in `persistence`.
+#[tracing::instrument(skip_all)]
+pub(crate) async fn record_views<N, P, V>(
+    consensus: Arc<RwLock<Consensus<N, P, V>>>,
+    persistence: Arc<P>,
+) where
+    N: ConnectedNetwork<PubKey>,
+    P: SequencerPersistence,
+    V: Versions,
+{
+    let mut events = consensus.read().await.event_stream();
+    let mut log = ViewLog::default();
+    let mut pruned = 0;
+    while let Some(event) = events.next().await {
+        let records = {
+            let handle = consensus.read().await;
+            let epoch = handle.cur_epoch().await;
+            let membership = &handle.memberships.quorum_membership;
+            log.handle_event(&event, now(), |view| membership.leader(view, epoch).ok())
+        };
+        for record in records {
+            if let Err(err) = persistence.store_view_record(&record).await {
+                tracing::warn!(view = record.view, "failed to save view record: {err:#}");
+            }
+        }
+
+        let view = event.view_number.u64();
+        if view >= pruned + PRUNE_INTERVAL {
+            match persistence
+                .prune_view_records(view.saturating_sub(VIEW_LOG_RETENTION))
+                .await
+            {
+                Ok(()) => pruned = view,
+                Err(err) => tracing::warn!(view, "failed to prune view records: {err:#}"),
+            }
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(view: u64, event: EventType<SeqTypes>) -> Event<SeqTypes> {
+        Event {
+            view_number: ViewNumber::new(view),
+            event,
+        }
+    }
+
+    #[test]
+    fn test_view_outcomes() {
+        let mut log = ViewLog::default();
+        let leader = |_| None;
+
+        // A completed view ends the view and starts the next one.
+        let records = log.handle_event(
+            &event(
+                1,
+                EventType::ViewFinished {
+                    view_number: ViewNumber::new(1),
+                },
+            ),
+            100,
+            leader,
+        );
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].view, 1);
+        assert_eq!(records[0].outcome, Some(ViewOutcome::Completed));
+        assert_eq!(records[0].ended_at, Some(100));
+        assert_eq!(records[1].view, 2);
+        assert_eq!(records[1].started_at, 100);
+        assert_eq!(records[1].outcome, None);
+
+        // A view which times out without a proposal blames the leader.
+        let records = log.handle_event(
+            &event(
+                2,
+                EventType::ViewTimeout {
+                    view_number: ViewNumber::new(2),
+                },
+            ),
+            200,
+            leader,
+        );
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].view, 2);
+        assert_eq!(records[0].started_at, 100);
+        assert_eq!(records[0].outcome, Some(ViewOutcome::NoProposal));
+        assert_eq!(records[0].ended_at, Some(200));
+
+        // Old views are forgotten.
+        log.handle_event(
+            &event(
+                20,
+                EventType::ViewFinished {
+                    view_number: ViewNumber::new(20),
+                },
+            ),
+            300,
+            leader,
+        );
+        assert!(!log.records.contains_key(&1));
+        assert!(log.records.contains_key(&21));
+    }
+}
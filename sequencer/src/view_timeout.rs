@@ -0,0 +1,222 @@
+//! Adaptive view timeouts.
+//!
+//! HotShot waits a fixed `next_view_timeout` for each view before giving up on it. A timeout that
+//! is too short for the network causes a cascade of failed views, while one that is too long slows
+//! down recovery from a faulty leader. When enabled, the [`AdaptiveViewTimeout`] controller
+//! lengthens the timeout after each failed view and shortens it again after a run of decides, always
+//! staying within configured bounds.
+//!
+//! HotShot reads the view timeout from its config when consensus starts, and does not allow it to
+//! be changed while running. The controller therefore records each new timeout in the network
+//! config saved in persistent storage, so it takes effect when the node next starts consensus, and
+//! reports the current value via the `adaptive_view_timeout` metric.
+
+use std::{sync::Arc, time::Duration};
+
+use clap::Parser;
+use espresso_types::{parse_duration, v0::traits::SequencerPersistence, PubKey, SeqTypes};
+use futures::stream::{Stream, StreamExt};
+use hotshot::types::{Event, EventType};
+use hotshot_types::{network::NetworkConfig, traits::metrics::Gauge};
+
+#[derive(Clone, Copy, Debug, Parser)]
+pub struct ViewTimeoutConfig {
+    /// Adapt the view timeout to network conditions.
+    ///
+    /// The timeout is lengthened after each failed view and shortened after a run of successful
+    /// ones, within the bounds given by `--view-timeout-min` and `--view-timeout-max`.
+    #[clap(
+        long = "adaptive-view-timeout",
+        env = "ESPRESSO_SEQUENCER_ADAPTIVE_VIEW_TIMEOUT"
+    )]
+    pub adaptive: bool,
+
+    /// Shortest view timeout the adaptive controller will use.
+    #[clap(
+        long = "view-timeout-min",
+        env = "ESPRESSO_SEQUENCER_VIEW_TIMEOUT_MIN",
+        default_value = "2s",
+        value_parser = parse_duration,
+    )]
+    pub min: Duration,
+
+    /// Longest view timeout the adaptive controller will use.
+    #[clap(
+        long = "view-timeout-max",
+        env = "ESPRESSO_SEQUENCER_VIEW_TIMEOUT_MAX",
+        default_value = "60s",
+        value_parser = parse_duration,
+    )]
+    pub max: Duration,
+
+    /// Factor by which the view timeout is multiplied after a failed view, and divided after a run
+    /// of successful views.
+    #[clap(
+        long = "view-timeout-backoff",
+        env = "ESPRESSO_SEQUENCER_VIEW_TIMEOUT_BACKOFF",
+        default_value = "1.5"
+    )]
+    pub backoff: f64,
+
+    /// Number of consecutive decides after which the view timeout is shortened.
+    #[clap(
+        long = "view-timeout-recovery-views",
+        env = "ESPRESSO_SEQUENCER_VIEW_TIMEOUT_RECOVERY_VIEWS",
+        default_value = "10"
+    )]
+    pub recovery_views: u64,
+}
+
+impl Default for ViewTimeoutConfig {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+impl ViewTimeoutConfig {
+    /// Restrict `timeout` to the configured bounds.
+    pub fn clamp(&self, timeout: Duration) -> Duration {
+        timeout.clamp(self.min, self.max.max(self.min))
+    }
+}
+
+/// Controller which adjusts the view timeout based on consensus progress.
+#[derive(Clone, Debug)]
+pub struct AdaptiveViewTimeout {
+    cfg: ViewTimeoutConfig,
+    timeout: Duration,
+    successes: u64,
+}
+
+impl AdaptiveViewTimeout {
+    /// Start adapting from an initial timeout, which is clamped to the configured bounds.
+    pub fn new(cfg: ViewTimeoutConfig, initial: Duration) -> Self {
+        Self {
+            timeout: cfg.clamp(initial),
+            cfg,
+            successes: 0,
+        }
+    }
+
+    /// The current view timeout.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Record a failed view, lengthening the timeout.
+    ///
+    /// Returns `true` if the timeout changed.
+    pub fn on_view_timeout(&mut self) -> bool {
+        self.successes = 0;
+        self.set(self.timeout.mul_f64(self.cfg.backoff))
+    }
+
+    /// Record a decide, shortening the timeout once enough consecutive decides have been seen.
+    ///
+    /// Returns `true` if the timeout changed.
+    pub fn on_decide(&mut self) -> bool {
+        self.successes += 1;
+        if self.successes < self.cfg.recovery_views {
+            return false;
+        }
+        self.successes = 0;
+        self.set(self.timeout.div_f64(self.cfg.backoff))
+    }
+
+    fn set(&mut self, timeout: Duration) -> bool {
+        let timeout = self.cfg.clamp(timeout);
+        let changed = timeout != self.timeout;
+        self.timeout = timeout;
+        changed
+    }
+}
+
+/// Feed consensus events to `controller`, saving each new timeout to `persistence`.
+#[tracing::instrument(skip_all)]
+pub(crate) async fn adapt_view_timeout<P: SequencerPersistence>(
+    mut events: impl Stream<Item = Event<SeqTypes>> + Unpin,
+    persistence: Arc<P>,
+    mut network_config: NetworkConfig<PubKey>,
+    mut controller: AdaptiveViewTimeout,
+    gauge: Box<dyn Gauge>,
+) {
+    gauge.set(controller.timeout().as_millis() as usize);
+
+    while let Some(event) = events.next().await {
+        let changed = match event.event {
+            EventType::ViewTimeout { .. } => controller.on_view_timeout(),
+            EventType::Decide { .. } => controller.on_decide(),
+            _ => continue,
+        };
+        if !changed {
+            continue;
+        }
+
+        let timeout = controller.timeout();
+        tracing::info!(?timeout, view = ?event.view_number, "adapting view timeout");
+        gauge.set(timeout.as_millis() as usize);
+        network_config.config.next_view_timeout = timeout.as_millis() as u64;
+        if let Err(err) = persistence.save_config(&network_config).await {
+            tracing::warn!(?timeout, "failed to save view timeout: {err:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config() -> ViewTimeoutConfig {
+        ViewTimeoutConfig {
+            adaptive: true,
+            min: Duration::from_secs(2),
+            max: Duration::from_secs(16),
+            backoff: 2.0,
+            recovery_views: 3,
+        }
+    }
+
+    #[test]
+    fn test_adaptive_view_timeout() {
+        // The initial timeout is clamped to the configured bounds.
+        assert_eq!(
+            AdaptiveViewTimeout::new(config(), Duration::from_secs(1)).timeout(),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            AdaptiveViewTimeout::new(config(), Duration::from_secs(60)).timeout(),
+            Duration::from_secs(16)
+        );
+
+        // Failed views lengthen the timeout, up to the maximum.
+        let mut controller = AdaptiveViewTimeout::new(config(), Duration::from_secs(4));
+        assert!(controller.on_view_timeout());
+        assert_eq!(controller.timeout(), Duration::from_secs(8));
+        assert!(controller.on_view_timeout());
+        assert_eq!(controller.timeout(), Duration::from_secs(16));
+        assert!(!controller.on_view_timeout());
+        assert_eq!(controller.timeout(), Duration::from_secs(16));
+
+        // The timeout is only shortened after enough consecutive decides.
+        assert!(!controller.on_decide());
+        assert!(!controller.on_decide());
+        assert!(controller.on_decide());
+        assert_eq!(controller.timeout(), Duration::from_secs(8));
+
+        // A failed view resets the run of decides.
+        assert!(!controller.on_decide());
+        assert!(controller.on_view_timeout());
+        assert_eq!(controller.timeout(), Duration::from_secs(16));
+        for _ in 0..2 {
+            assert!(!controller.on_decide());
+        }
+        assert!(controller.on_decide());
+        assert_eq!(controller.timeout(), Duration::from_secs(8));
+
+        // Steady progress shortens the timeout down to the minimum.
+        for _ in 0..9 {
+            controller.on_decide();
+        }
+        assert_eq!(controller.timeout(), Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,453 @@
+//! A watchdog for the health of the chain, as seen by this node.
+//!
+//! When enabled, the watchdog follows consensus events and the L1 client, and periodically checks
+//! three signs of trouble against configured thresholds:
+//! * the time since this node last saw a decide
+//! * the number of view timeouts within a sliding window
+//! * the age of the latest finalized L1 block known to this node
+//!
+//! When any threshold is breached, the watchdog logs the breach and takes the configured actions:
+//! * `alert` posts the breaches as JSON to an alert webhook, if one is configured
+//! * `restart-network` shuts the node down gracefully and restarts it in the same process, which
+//!   re-establishes its CDN and libp2p connections from scratch
+//! * `exit` shuts the node down gracefully and exits with [`WATCHDOG_EXIT_CODE`], so that an
+//!   orchestrator can tell an unhealthy node from one that crashed, and replace or reschedule it
+//!
+//! Restarting and exiting outlive the node itself, so the watchdog only requests them, and the
+//! binary running the node carries them out (see [`RecoveryRequests`]). After taking action, the
+//! watchdog waits for a cooldown period before acting again, so that a persistent problem does not
+//! flood the alert webhook or restart the node in a loop.
+
+use std::{
+    collections::VecDeque,
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use clap::{Parser, ValueEnum};
+use espresso_types::{parse_duration, L1Client, SeqTypes};
+use futures::stream::{Stream, StreamExt};
+use hotshot::types::{Event, EventType};
+use serde::Serialize;
+use tokio::{
+    sync::watch,
+    time::{interval, MissedTickBehavior},
+};
+use url::Url;
+
+/// The exit code of a node shut down by the watchdog.
+///
+/// This is `EX_TEMPFAIL` from `sysexits.h`: the failure is expected to be temporary, and the node
+/// may be restarted.
+pub const WATCHDOG_EXIT_CODE: i32 = 75;
+
+/// Timeout for delivering an alert to the alert webhook.
+const ALERT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An action the watchdog takes when a health threshold is breached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum WatchdogAction {
+    /// Log the breach and post it to the alert webhook.
+    Alert,
+    /// Restart the node in the same process, reconnecting to the network.
+    RestartNetwork,
+    /// Shut down and exit with [`WATCHDOG_EXIT_CODE`].
+    Exit,
+}
+
+#[derive(Clone, Debug, Parser)]
+pub struct WatchdogConfig {
+    /// Monitor the health of the chain, and act when it degrades.
+    #[clap(long = "watchdog", env = "ESPRESSO_SEQUENCER_WATCHDOG")]
+    pub enabled: bool,
+
+    /// Longest time without a decide before the chain is considered stalled.
+    #[clap(
+        long = "watchdog-max-decide-interval",
+        env = "ESPRESSO_SEQUENCER_WATCHDOG_MAX_DECIDE_INTERVAL",
+        default_value = "5m",
+        value_parser = parse_duration,
+    )]
+    pub max_decide_interval: Duration,
+
+    /// Most view timeouts tolerated within `--watchdog-view-timeout-window`.
+    #[clap(
+        long = "watchdog-max-view-timeouts",
+        env = "ESPRESSO_SEQUENCER_WATCHDOG_MAX_VIEW_TIMEOUTS",
+        default_value = "20"
+    )]
+    pub max_view_timeouts: usize,
+
+    /// Sliding window over which view timeouts are counted.
+    #[clap(
+        long = "watchdog-view-timeout-window",
+        env = "ESPRESSO_SEQUENCER_WATCHDOG_VIEW_TIMEOUT_WINDOW",
+        default_value = "10m",
+        value_parser = parse_duration,
+    )]
+    pub view_timeout_window: Duration,
+
+    /// Greatest age of the latest finalized L1 block before the L1 connection is considered lagging.
+    ///
+    /// L1 blocks take some time to finalize, so this should be well above the finality time of the
+    /// L1.
+    #[clap(
+        long = "watchdog-max-l1-lag",
+        env = "ESPRESSO_SEQUENCER_WATCHDOG_MAX_L1_LAG",
+        default_value = "1h",
+        value_parser = parse_duration,
+    )]
+    pub max_l1_lag: Duration,
+
+    /// Actions to take when a threshold is breached, as a comma-separated list of `alert`,
+    /// `restart-network` and `exit`.
+    ///
+    /// Breaches are always logged. If both `restart-network` and `exit` are given, the node exits.
+    #[clap(
+        long = "watchdog-actions",
+        env = "ESPRESSO_SEQUENCER_WATCHDOG_ACTIONS",
+        value_delimiter = ',',
+        default_value = "alert"
+    )]
+    pub actions: Vec<WatchdogAction>,
+
+    /// URL to post alerts to, for the `alert` action.
+    #[clap(
+        long = "watchdog-alert-url",
+        env = "ESPRESSO_SEQUENCER_WATCHDOG_ALERT_URL"
+    )]
+    pub alert_url: Option<Url>,
+
+    /// How often to check the health of the chain.
+    #[clap(
+        long = "watchdog-interval",
+        env = "ESPRESSO_SEQUENCER_WATCHDOG_INTERVAL",
+        default_value = "15s",
+        value_parser = parse_duration,
+    )]
+    pub interval: Duration,
+
+    /// Shortest time between actions.
+    #[clap(
+        long = "watchdog-cooldown",
+        env = "ESPRESSO_SEQUENCER_WATCHDOG_COOLDOWN",
+        default_value = "10m",
+        value_parser = parse_duration,
+    )]
+    pub cooldown: Duration,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+/// A health threshold which has been breached.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Breach {
+    /// No decide has been seen for `secs` seconds.
+    DecideStalled { secs: u64 },
+    /// There were `count` view timeouts in the last `window_secs` seconds.
+    ViewTimeouts { count: usize, window_secs: u64 },
+    /// The latest finalized L1 block is `secs` seconds old.
+    L1Lag { secs: u64 },
+}
+
+impl Display for Breach {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DecideStalled { secs } => write!(f, "no decide for {secs}s"),
+            Self::ViewTimeouts { count, window_secs } => {
+                write!(f, "{count} view timeouts in the last {window_secs}s")
+            }
+            Self::L1Lag { secs } => write!(f, "latest finalized L1 block is {secs}s old"),
+        }
+    }
+}
+
+/// The body of an alert posted to the alert webhook.
+#[derive(Clone, Debug, Serialize)]
+struct Alert<'a> {
+    node_id: u64,
+    /// When the breaches were detected, in seconds since the Unix epoch.
+    timestamp: u64,
+    breaches: &'a [Breach],
+}
+
+/// Recovery actions requested by the watchdog, to be carried out by whoever runs the node.
+#[derive(Clone, Debug)]
+pub struct RecoveryRequests(Arc<watch::Sender<Option<WatchdogAction>>>);
+
+impl Default for RecoveryRequests {
+    fn default() -> Self {
+        Self(Arc::new(watch::Sender::new(None)))
+    }
+}
+
+impl RecoveryRequests {
+    fn request(&self, action: WatchdogAction) {
+        self.0.send_replace(Some(action));
+    }
+
+    /// Wait until the watchdog requests a recovery action.
+    pub async fn wait(&self) -> WatchdogAction {
+        let mut rx = self.0.subscribe();
+        // We hold the sender, so the channel cannot be closed.
+        loop {
+            if let Some(action) = *rx.borrow_and_update() {
+                return action;
+            }
+            rx.changed().await.ok();
+        }
+    }
+}
+
+/// Tracks decides and view timeouts, and checks them against the configured thresholds.
+#[derive(Debug)]
+struct HealthMonitor {
+    config: WatchdogConfig,
+    last_decide: Instant,
+    timeouts: VecDeque<Instant>,
+}
+
+impl HealthMonitor {
+    /// Start monitoring at `now`, which counts as a decide, giving consensus time to start.
+    fn new(config: WatchdogConfig, now: Instant) -> Self {
+        Self {
+            config,
+            last_decide: now,
+            timeouts: Default::default(),
+        }
+    }
+
+    fn on_event(&mut self, event: &EventType<SeqTypes>, now: Instant) {
+        match event {
+            EventType::Decide { .. } => self.last_decide = now,
+            EventType::ViewTimeout { .. } => self.timeouts.push_back(now),
+            _ => {}
+        }
+    }
+
+    /// Check the health of the chain as of `now`, given the age of the latest finalized L1 block.
+    fn check(&mut self, now: Instant, l1_lag: Option<Duration>) -> Vec<Breach> {
+        while self
+            .timeouts
+            .front()
+            .is_some_and(|timeout| now.duration_since(*timeout) > self.config.view_timeout_window)
+        {
+            self.timeouts.pop_front();
+        }
+
+        let mut breaches = vec![];
+        let since_decide = now.duration_since(self.last_decide);
+        if since_decide > self.config.max_decide_interval {
+            breaches.push(Breach::DecideStalled {
+                secs: since_decide.as_secs(),
+            });
+        }
+        if self.timeouts.len() > self.config.max_view_timeouts {
+            breaches.push(Breach::ViewTimeouts {
+                count: self.timeouts.len(),
+                window_secs: self.config.view_timeout_window.as_secs(),
+            });
+        }
+        if let Some(lag) = l1_lag.filter(|lag| *lag > self.config.max_l1_lag) {
+            breaches.push(Breach::L1Lag {
+                secs: lag.as_secs(),
+            });
+        }
+        breaches
+    }
+}
+
+/// Watch the health of the chain, taking the configured actions when it degrades.
+///
+/// Runs until `events` ends, which happens when consensus shuts down.
+#[tracing::instrument(skip_all)]
+pub(crate) async fn watch_health(
+    mut events: impl Stream<Item = Event<SeqTypes>> + Unpin,
+    l1_client: L1Client,
+    config: WatchdogConfig,
+    node_id: u64,
+    recovery: RecoveryRequests,
+) {
+    let client = reqwest::Client::new();
+    let mut ticker = interval(config.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut monitor = HealthMonitor::new(config.clone(), Instant::now());
+    let mut last_action: Option<Instant> = None;
+
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                let Some(event) = event else {
+                    break;
+                };
+                monitor.on_event(&event.event, Instant::now());
+            }
+            _ = ticker.tick() => {
+                let l1_lag = l1_lag(&l1_client).await;
+                let breaches = monitor.check(Instant::now(), l1_lag);
+                if breaches.is_empty() {
+                    continue;
+                }
+                for breach in &breaches {
+                    tracing::error!(%breach, "chain health threshold breached");
+                }
+                if last_action.is_some_and(|at| at.elapsed() < config.cooldown) {
+                    continue;
+                }
+                last_action = Some(Instant::now());
+                act(&client, &config, node_id, &breaches, &recovery).await;
+            }
+        }
+    }
+}
+
+/// Take the configured actions in response to `breaches`.
+async fn act(
+    client: &reqwest::Client,
+    config: &WatchdogConfig,
+    node_id: u64,
+    breaches: &[Breach],
+    recovery: &RecoveryRequests,
+) {
+    if config.actions.contains(&WatchdogAction::Alert) {
+        if let Some(url) = &config.alert_url {
+            let alert = Alert {
+                node_id,
+                timestamp: unix_timestamp(),
+                breaches,
+            };
+            if let Err(err) = post_alert(client, url, &alert).await {
+                tracing::warn!(%url, "failed to deliver watchdog alert: {err:#}");
+            }
+        }
+    }
+
+    // Exiting supersedes restarting.
+    let action = [WatchdogAction::Exit, WatchdogAction::RestartNetwork]
+        .into_iter()
+        .find(|action| config.actions.contains(action));
+    if let Some(action) = action {
+        tracing::error!(?action, "watchdog requesting recovery");
+        recovery.request(action);
+    }
+}
+
+async fn post_alert(client: &reqwest::Client, url: &Url, alert: &Alert<'_>) -> anyhow::Result<()> {
+    client
+        .post(url.clone())
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_vec(alert)?)
+        .timeout(ALERT_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// The age of the latest finalized L1 block known to the L1 client, if any.
+async fn l1_lag(l1_client: &L1Client) -> Option<Duration> {
+    let finalized = l1_client.snapshot().await.finalized?;
+    let timestamp = finalized.timestamp.as_u64();
+    Some(Duration::from_secs(
+        unix_timestamp().saturating_sub(timestamp),
+    ))
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use hotshot_types::{data::ViewNumber, traits::node_implementation::ConsensusTime};
+
+    use super::*;
+
+    #[test]
+    fn test_health_monitor() {
+        let config = WatchdogConfig {
+            max_decide_interval: Duration::from_secs(60),
+            max_view_timeouts: 2,
+            view_timeout_window: Duration::from_secs(100),
+            max_l1_lag: Duration::from_secs(600),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let timeout = EventType::ViewTimeout {
+            view_number: ViewNumber::new(1),
+        };
+        let mut monitor = HealthMonitor::new(config, start);
+        assert_eq!(monitor.check(at(30), None), vec![]);
+
+        // Consensus gets some time to start before the first decide.
+        assert_eq!(
+            monitor.check(at(61), None),
+            vec![Breach::DecideStalled { secs: 61 }]
+        );
+
+        // View timeouts are counted within the window.
+        for secs in [10, 20, 30] {
+            monitor.on_event(&timeout, at(secs));
+        }
+        assert_eq!(
+            monitor.check(at(50), None),
+            vec![Breach::ViewTimeouts {
+                count: 3,
+                window_secs: 100
+            }]
+        );
+
+        // Timeouts which fall out of the window are forgotten.
+        monitor.last_decide = at(100);
+        assert_eq!(monitor.check(at(115), None), vec![]);
+        assert_eq!(monitor.timeouts.len(), 2);
+
+        // A lagging L1 is a breach on its own.
+        assert_eq!(
+            monitor.check(at(115), Some(Duration::from_secs(601))),
+            vec![Breach::L1Lag { secs: 601 }]
+        );
+    }
+
+    #[test]
+    fn test_parse_actions() {
+        let config = WatchdogConfig::parse_from([
+            "watchdog",
+            "--watchdog",
+            "--watchdog-actions",
+            "alert,restart-network,exit",
+        ]);
+        assert!(config.enabled);
+        assert_eq!(
+            config.actions,
+            [
+                WatchdogAction::Alert,
+                WatchdogAction::RestartNetwork,
+                WatchdogAction::Exit
+            ]
+        );
+        assert_eq!(WatchdogConfig::default().actions, [WatchdogAction::Alert]);
+    }
+
+    #[tokio::test]
+    async fn test_recovery_requests() {
+        let recovery = RecoveryRequests::default();
+        let waiter = recovery.clone();
+        let task = tokio::spawn(async move { waiter.wait().await });
+        recovery.request(WatchdogAction::RestartNetwork);
+        assert_eq!(task.await.unwrap(), WatchdogAction::RestartNetwork);
+
+        // A request made before waiting is not missed.
+        assert_eq!(recovery.wait().await, WatchdogAction::RestartNetwork);
+    }
+}
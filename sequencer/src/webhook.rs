@@ -0,0 +1,497 @@
+//! Webhook notifications of consensus events.
+//!
+//! Downstream systems which need to react to consensus, such as indexers or alerting, can register
+//! webhooks instead of polling the API. Each [`Webhook`] is a URL along with the kinds of events it
+//! is interested in. When consensus emits a matching event, the event is `POST`ed to the URL as a
+//! JSON [`WebhookEvent`].
+//!
+//! Each webhook has its own bounded queue and delivery task, so a slow or unreachable endpoint
+//! delays neither consensus nor the other webhooks. Failed deliveries are retried with exponential
+//! backoff, up to a maximum number of attempts. If a webhook falls so far behind that its queue is
+//! full, new events for it are dropped. Deliveries, retries, failures and drops are reported via
+//! metrics in the `webhook` group.
+
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::{bail, Context};
+use clap::Parser;
+use committable::{Commitment, Committable};
+use espresso_types::{parse_duration, Header, SeqTypes};
+use hotshot::types::{Event, EventType};
+use hotshot_types::{
+    event::LeafInfo,
+    traits::{
+        metrics::{Counter, Metrics},
+        node_implementation::ConsensusTime,
+    },
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::mpsc::{channel, error::TrySendError, Receiver, Sender},
+    time::sleep,
+};
+use url::Url;
+use vbs::version::Version;
+
+use crate::context::TaskList;
+
+#[derive(Clone, Debug, Parser)]
+pub struct WebhookConfig {
+    /// Webhooks to notify of consensus events.
+    ///
+    /// Each webhook has the form `url=<url>[,events=<kind>+<kind>...]`, where the kinds of events
+    /// are `decide`, `view-timeout` and `upgrade`. If `events` is omitted, all kinds of events are
+    /// delivered. Multiple webhooks can be given by repeating the option, or separated by `;`.
+    #[clap(
+        long = "webhook",
+        env = "ESPRESSO_SEQUENCER_WEBHOOKS",
+        value_delimiter = ';'
+    )]
+    pub webhooks: Vec<Webhook>,
+
+    /// Maximum number of events waiting to be delivered to each webhook.
+    ///
+    /// Events for a webhook whose queue is full are dropped.
+    #[clap(
+        long = "webhook-queue-capacity",
+        env = "ESPRESSO_SEQUENCER_WEBHOOK_QUEUE_CAPACITY",
+        default_value = "1000"
+    )]
+    pub queue_capacity: usize,
+
+    /// Maximum number of attempts to deliver each event before giving up.
+    #[clap(
+        long = "webhook-max-attempts",
+        env = "ESPRESSO_SEQUENCER_WEBHOOK_MAX_ATTEMPTS",
+        default_value = "5"
+    )]
+    pub max_attempts: usize,
+
+    /// Delay before retrying a failed delivery. The delay doubles with each failed attempt.
+    #[clap(
+        long = "webhook-retry-delay",
+        env = "ESPRESSO_SEQUENCER_WEBHOOK_RETRY_DELAY",
+        default_value = "1s",
+        value_parser = parse_duration,
+    )]
+    pub retry_delay: Duration,
+
+    /// Timeout for a single delivery attempt.
+    #[clap(
+        long = "webhook-timeout",
+        env = "ESPRESSO_SEQUENCER_WEBHOOK_TIMEOUT",
+        default_value = "10s",
+        value_parser = parse_duration,
+    )]
+    pub timeout: Duration,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+/// A kind of consensus event which can be delivered to webhooks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WebhookEventKind {
+    Decide,
+    ViewTimeout,
+    Upgrade,
+}
+
+impl WebhookEventKind {
+    const ALL: [Self; 3] = [Self::Decide, Self::ViewTimeout, Self::Upgrade];
+}
+
+impl FromStr for WebhookEventKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim() {
+            "decide" => Ok(Self::Decide),
+            "view-timeout" => Ok(Self::ViewTimeout),
+            "upgrade" => Ok(Self::Upgrade),
+            s => {
+                bail!("unknown webhook event kind {s:?}; expected decide, view-timeout or upgrade")
+            }
+        }
+    }
+}
+
+/// A URL to notify of consensus events, and the kinds of events to notify it of.
+///
+/// Parsed from strings of the form `url=<url>[,events=<kind>+<kind>...]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Webhook {
+    pub url: Url,
+    pub events: Vec<WebhookEventKind>,
+}
+
+impl FromStr for Webhook {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut url = None;
+        let mut events = None;
+        for field in s.split(',') {
+            let (key, value) = field.split_once('=').context(format!(
+                "malformed webhook field {field:?}; expected key=value"
+            ))?;
+            match key.trim() {
+                "url" => {
+                    url = Some(
+                        value
+                            .trim()
+                            .parse()
+                            .context(format!("invalid webhook URL {value:?}"))?,
+                    );
+                }
+                "events" => {
+                    events = Some(
+                        value
+                            .split('+')
+                            .map(WebhookEventKind::from_str)
+                            .collect::<anyhow::Result<_>>()?,
+                    );
+                }
+                key => bail!("unknown webhook field {key:?}"),
+            }
+        }
+        Ok(Self {
+            url: url.context("webhook is missing URL (url=<url>)")?,
+            events: events.unwrap_or_else(|| WebhookEventKind::ALL.to_vec()),
+        })
+    }
+}
+
+/// A consensus event, as delivered to webhooks.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum WebhookEvent {
+    /// New blocks were decided.
+    Decide {
+        /// The newly decided blocks, in increasing order of height.
+        blocks: Vec<DecidedBlock>,
+    },
+    /// A view timed out without a block being proposed and voted on.
+    ViewTimeout { view: u64 },
+    /// An upgrade to a new protocol version was proposed.
+    Upgrade {
+        /// The view in which the upgrade was proposed.
+        view: u64,
+        old_version: Version,
+        new_version: Version,
+        /// The first view in which the new version will be in effect, if the upgrade is certified.
+        new_version_first_view: u64,
+    },
+}
+
+impl WebhookEvent {
+    fn kind(&self) -> WebhookEventKind {
+        match self {
+            Self::Decide { .. } => WebhookEventKind::Decide,
+            Self::ViewTimeout { .. } => WebhookEventKind::ViewTimeout,
+            Self::Upgrade { .. } => WebhookEventKind::Upgrade,
+        }
+    }
+
+    /// Convert a consensus event to a webhook event, if it is of a kind webhooks can receive.
+    pub fn from_consensus(event: &Event<SeqTypes>) -> Option<Self> {
+        match &event.event {
+            EventType::Decide { leaf_chain, .. } => Some(Self::Decide {
+                blocks: leaf_chain
+                    .iter()
+                    .rev()
+                    .map(|LeafInfo { leaf, .. }| {
+                        let header = leaf.block_header();
+                        DecidedBlock {
+                            height: header.height(),
+                            view: leaf.view_number().u64(),
+                            hash: header.commit(),
+                            timestamp: header.timestamp(),
+                        }
+                    })
+                    .collect(),
+            }),
+            EventType::ViewTimeout { view_number } => Some(Self::ViewTimeout {
+                view: view_number.u64(),
+            }),
+            EventType::UpgradeProposal { proposal, .. } => {
+                let upgrade = &proposal.data.upgrade_proposal;
+                Some(Self::Upgrade {
+                    view: proposal.data.view_number.u64(),
+                    old_version: upgrade.old_version,
+                    new_version: upgrade.new_version,
+                    new_version_first_view: upgrade.new_version_first_view.u64(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A newly decided block, as delivered to webhooks.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecidedBlock {
+    pub height: u64,
+    pub view: u64,
+    pub hash: Commitment<Header>,
+    pub timestamp: u64,
+}
+
+#[derive(Debug)]
+struct WebhookMetrics {
+    /// Number of events delivered successfully.
+    delivered: Box<dyn Counter>,
+    /// Number of failed delivery attempts which were retried.
+    retried: Box<dyn Counter>,
+    /// Number of events which could not be delivered after the maximum number of attempts.
+    failed: Box<dyn Counter>,
+    /// Number of events dropped because a webhook's queue was full.
+    dropped: Box<dyn Counter>,
+}
+
+impl WebhookMetrics {
+    fn new(metrics: &dyn Metrics) -> Self {
+        let metrics = metrics.subgroup("webhook".into());
+        Self {
+            delivered: metrics.create_counter("delivered".into(), None),
+            retried: metrics.create_counter("retried".into(), None),
+            failed: metrics.create_counter("failed".into(), None),
+            dropped: metrics.create_counter("dropped".into(), None),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Target {
+    url: Url,
+    events: Vec<WebhookEventKind>,
+    queue: Sender<Arc<WebhookEvent>>,
+}
+
+/// Delivers consensus events to the configured webhooks.
+#[derive(Debug)]
+pub(crate) struct WebhookDispatcher {
+    targets: Vec<Target>,
+    metrics: Arc<WebhookMetrics>,
+}
+
+impl WebhookDispatcher {
+    /// Create a dispatcher, spawning a delivery task in `tasks` for each configured webhook.
+    pub(crate) fn new(config: &WebhookConfig, metrics: &dyn Metrics, tasks: &mut TaskList) -> Self {
+        let metrics = Arc::new(WebhookMetrics::new(metrics));
+        let client = reqwest::Client::new();
+        let targets = config
+            .webhooks
+            .iter()
+            .map(|webhook| {
+                let (queue, events) = channel(config.queue_capacity);
+                tasks.spawn(
+                    format!("webhook {}", webhook.url),
+                    deliver(
+                        client.clone(),
+                        webhook.url.clone(),
+                        events,
+                        config.clone(),
+                        metrics.clone(),
+                    ),
+                );
+                Target {
+                    url: webhook.url.clone(),
+                    events: webhook.events.clone(),
+                    queue,
+                }
+            })
+            .collect();
+        Self { targets, metrics }
+    }
+
+    /// Queue `event` for delivery to each webhook interested in it.
+    ///
+    /// This never blocks: if a webhook's queue is full, the event is dropped for that webhook.
+    pub(crate) fn handle_event(&self, event: &Event<SeqTypes>) {
+        if self.targets.is_empty() {
+            return;
+        }
+        let Some(event) = WebhookEvent::from_consensus(event) else {
+            return;
+        };
+        let kind = event.kind();
+        let event = Arc::new(event);
+        for target in &self.targets {
+            if !target.events.contains(&kind) {
+                continue;
+            }
+            match target.queue.try_send(event.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    tracing::warn!(url = %target.url, ?kind, "webhook queue full, dropping event");
+                    self.metrics.dropped.add(1);
+                }
+                Err(TrySendError::Closed(_)) => {
+                    tracing::error!(url = %target.url, "webhook delivery task has exited");
+                }
+            }
+        }
+    }
+}
+
+/// Deliver queued events to the webhook at `url`, in order.
+async fn deliver(
+    client: reqwest::Client,
+    url: Url,
+    mut events: Receiver<Arc<WebhookEvent>>,
+    config: WebhookConfig,
+    metrics: Arc<WebhookMetrics>,
+) {
+    while let Some(event) = events.recv().await {
+        let body = match serde_json::to_vec(&*event) {
+            Ok(body) => body,
+            Err(err) => {
+                tracing::error!(%url, "failed to serialize webhook event: {err:#}");
+                metrics.failed.add(1);
+                continue;
+            }
+        };
+
+        let mut delay = config.retry_delay;
+        for attempt in 1..=config.max_attempts.max(1) {
+            match post(&client, &url, body.clone(), config.timeout).await {
+                Ok(()) => {
+                    metrics.delivered.add(1);
+                    break;
+                }
+                Err(err) if attempt < config.max_attempts => {
+                    tracing::info!(%url, attempt, "webhook delivery failed, will retry after {delay:?}: {err:#}");
+                    metrics.retried.add(1);
+                    sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(err) => {
+                    tracing::warn!(%url, attempt, "giving up on webhook delivery: {err:#}");
+                    metrics.failed.add(1);
+                }
+            }
+        }
+    }
+}
+
+async fn post(
+    client: &reqwest::Client,
+    url: &Url,
+    body: Vec<u8>,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    client
+        .post(url.clone())
+        .header("Content-Type", "application/json")
+        .body(body)
+        .timeout(timeout)
+        .send()
+        .await
+        .context("sending request")?
+        .error_for_status()
+        .context("webhook rejected event")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use hotshot_types::{data::ViewNumber, traits::metrics::NoMetrics};
+    use parking_lot::Mutex;
+    use portpicker::pick_unused_port;
+    use sequencer_utils::test_utils::setup_test;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_webhook() {
+        let url: Url = "http://localhost:8080/hook".parse().unwrap();
+        assert_eq!(
+            "url=http://localhost:8080/hook".parse::<Webhook>().unwrap(),
+            Webhook {
+                url: url.clone(),
+                events: WebhookEventKind::ALL.to_vec(),
+            }
+        );
+        assert_eq!(
+            "events=decide+upgrade, url=http://localhost:8080/hook"
+                .parse::<Webhook>()
+                .unwrap(),
+            Webhook {
+                url,
+                events: vec![WebhookEventKind::Decide, WebhookEventKind::Upgrade],
+            }
+        );
+        "events=decide".parse::<Webhook>().unwrap_err();
+        "url=not a url".parse::<Webhook>().unwrap_err();
+        "url=http://localhost,events=finality"
+            .parse::<Webhook>()
+            .unwrap_err();
+        "url=http://localhost,secret=1"
+            .parse::<Webhook>()
+            .unwrap_err();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_webhook_delivery() {
+        setup_test();
+
+        // Run a webhook receiver which fails the first request and records the rest.
+        #[derive(Clone, Default)]
+        struct Collector {
+            requests: Arc<AtomicUsize>,
+            received: Arc<Mutex<Vec<WebhookEvent>>>,
+        }
+        let receiver = Collector::default();
+        let mut app = tide::with_state(receiver.clone());
+        app.at("/hook")
+            .post(|mut req: tide::Request<Collector>| async move {
+                if req.state().requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return Ok(tide::Response::new(500));
+                }
+                let event: WebhookEvent = req.body_json().await?;
+                req.state().received.lock().push(event);
+                Ok(tide::Response::new(200))
+            });
+        let port = pick_unused_port().unwrap();
+        let mut tasks = TaskList::default();
+        tasks.spawn("webhook receiver", app.listen(format!("127.0.0.1:{port}")));
+
+        let config = WebhookConfig {
+            webhooks: vec![
+                format!("url=http://127.0.0.1:{port}/hook,events=view-timeout")
+                    .parse()
+                    .unwrap(),
+            ],
+            retry_delay: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let dispatcher = WebhookDispatcher::new(&config, &NoMetrics, &mut tasks);
+
+        // Events the webhook is not interested in are filtered out.
+        let view_number = ViewNumber::new(3);
+        dispatcher.handle_event(&Event {
+            view_number,
+            event: EventType::ViewFinished { view_number },
+        });
+        dispatcher.handle_event(&Event {
+            view_number,
+            event: EventType::ViewTimeout { view_number },
+        });
+
+        // The event is delivered after the first attempt fails.
+        while receiver.received.lock().is_empty() {
+            sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(
+            *receiver.received.lock(),
+            [WebhookEvent::ViewTimeout { view: 3 }]
+        );
+        assert_eq!(receiver.requests.load(Ordering::SeqCst), 2);
+    }
+}
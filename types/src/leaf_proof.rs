@@ -0,0 +1,155 @@
+//! Offline verification of decided leaves.
+//!
+//! A bridge or other external verifier which does not want to trust the node serving it data can
+//! check for itself that a leaf was decided by consensus. The `availability/leaf/:height/qc`
+//! endpoint serves a leaf along with the quorum certificate (QC) justifying it and the stake table
+//! in effect at that height. The function in this module checks that the QC certifies exactly that
+//! leaf, and that it carries signatures from enough stake in the stake table to form a quorum.
+//!
+//! The verifier is responsible for deciding which stake tables to trust, for example by following
+//! the stake table contract on L1, or by checking the stake table hash against a trusted source.
+
+use committable::Committable;
+use ethers::types::U256;
+use hotshot_types::{
+    message::UpgradeLock,
+    simple_certificate::QuorumCertificate,
+    simple_vote::VersionedVoteData,
+    traits::{
+        node_implementation::Versions,
+        signature_key::{SignatureKey, StakeTableEntryType},
+    },
+};
+use thiserror::Error;
+
+use crate::{EpochStakeTable, Leaf, PubKey, SeqTypes};
+
+/// Reasons a leaf certificate can fail to verify.
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum LeafCertificateError {
+    #[error("QC is for view {qc_view}, but leaf is from view {leaf_view}")]
+    ViewMismatch { leaf_view: u64, qc_view: u64 },
+    #[error("QC does not certify the given leaf")]
+    LeafMismatch,
+    #[error("QC vote commitment does not match the certified data")]
+    VoteCommitmentMismatch,
+    #[error("QC has no signatures")]
+    MissingSignatures,
+    #[error("stake table is empty")]
+    EmptyStakeTable,
+    #[error("QC signatures do not represent a quorum of the stake table")]
+    InvalidSignatures,
+}
+
+/// The amount of stake which must sign a QC for it to be valid: more than two thirds of the total.
+pub fn quorum_threshold(stake_table: &EpochStakeTable) -> U256 {
+    let total = stake_table.nodes.iter().fold(U256::zero(), |total, node| {
+        total + node.stake_table_entry.stake()
+    });
+    total * 2 / 3 + 1
+}
+
+/// Verify that `qc` certifies `leaf` with signatures from a quorum of `stake_table`.
+///
+/// `upgrade_lock` determines the protocol version, and hence the format of the signed vote data,
+/// in the view of `leaf`; for a network which has not upgraded, `UpgradeLock::new()` suffices.
+///
+/// The genesis leaf is not certified by a signed QC, and always fails verification with
+/// [`LeafCertificateError::MissingSignatures`].
+pub async fn verify_leaf_certificate<V: Versions>(
+    leaf: &Leaf,
+    qc: &QuorumCertificate<SeqTypes>,
+    stake_table: &EpochStakeTable,
+    upgrade_lock: &UpgradeLock<SeqTypes, V>,
+) -> Result<(), LeafCertificateError> {
+    if qc.view_number != leaf.view_number() {
+        return Err(LeafCertificateError::ViewMismatch {
+            leaf_view: *leaf.view_number(),
+            qc_view: *qc.view_number,
+        });
+    }
+    if qc.data.leaf_commit != leaf.commit() {
+        return Err(LeafCertificateError::LeafMismatch);
+    }
+
+    // The signatures are over a commitment to the certified data, bound to the view and protocol
+    // version. Recompute it rather than trusting the one in the QC.
+    let vote_commitment =
+        VersionedVoteData::new_infallible(qc.data.clone(), qc.view_number, upgrade_lock)
+            .await
+            .commit();
+    if vote_commitment != qc.vote_commitment {
+        return Err(LeafCertificateError::VoteCommitmentMismatch);
+    }
+
+    let signatures = qc
+        .signatures
+        .as_ref()
+        .ok_or(LeafCertificateError::MissingSignatures)?;
+    if stake_table.nodes.is_empty() {
+        return Err(LeafCertificateError::EmptyStakeTable);
+    }
+    let entries = stake_table
+        .nodes
+        .iter()
+        .map(|node| node.stake_table_entry.clone())
+        .collect();
+    let params = PubKey::public_parameter(entries, quorum_threshold(stake_table));
+    if !PubKey::check(&params, vote_commitment.as_ref(), signatures) {
+        return Err(LeafCertificateError::InvalidSignatures);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use committable::Commitment;
+    use hotshot_types::{
+        data::{EpochNumber, ViewNumber},
+        traits::node_implementation::ConsensusTime,
+    };
+
+    use super::*;
+    use crate::{MockSequencerVersions, NodeState, ValidatedState};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reject_mismatched_certificate() {
+        let instance = NodeState::mock();
+        let leaf = Leaf::genesis(&ValidatedState::default(), &instance).await;
+        let qc = QuorumCertificate::genesis::<MockSequencerVersions>(
+            &ValidatedState::default(),
+            &instance,
+        )
+        .await;
+        let stake_table = EpochStakeTable {
+            epoch: EpochNumber::genesis(),
+            nodes: vec![],
+        };
+        let lock = UpgradeLock::<SeqTypes, MockSequencerVersions>::new();
+
+        // The genesis QC certifies the genesis leaf, but carries no signatures.
+        assert_eq!(
+            verify_leaf_certificate(&leaf, &qc, &stake_table, &lock).await,
+            Err(LeafCertificateError::MissingSignatures)
+        );
+
+        // A QC from another view does not certify the leaf.
+        let mut other = qc.clone();
+        other.view_number = ViewNumber::new(1);
+        assert_eq!(
+            verify_leaf_certificate(&leaf, &other, &stake_table, &lock).await,
+            Err(LeafCertificateError::ViewMismatch {
+                leaf_view: 0,
+                qc_view: 1
+            })
+        );
+
+        // Nor does a QC for a different leaf in the same view.
+        let mut other = qc.clone();
+        other.data.leaf_commit = Commitment::from_raw([1; 32]);
+        assert_eq!(
+            verify_leaf_certificate(&leaf, &other, &stake_table, &lock).await,
+            Err(LeafCertificateError::LeafMismatch)
+        );
+    }
+}
@@ -4,4 +4,7 @@ pub mod v0;
 pub use v0::*;
 
 pub mod eth_signature_key;
+pub mod leaf_proof;
+pub mod namespace;
 mod reference_tests;
+pub mod transaction_proof;
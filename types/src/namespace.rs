@@ -0,0 +1,181 @@
+//! Offline verification of namespace proofs.
+//!
+//! Rollups consume only their own namespace of each Espresso block. A sequencer node serving a
+//! namespace returns the transactions in that namespace along with an [`NsProof`], which can be
+//! checked against the [`Header`] of the block without trusting the node that served it. The
+//! functions in this module perform that check without depending on the sequencer API server, so
+//! that downstream Rust clients can verify namespace data by linking against this crate alone.
+
+use hotshot_types::vid::VidCommon;
+use thiserror::Error;
+
+use crate::{Header, NamespaceId, NsProof, Transaction};
+
+/// Reasons a namespace proof can fail to verify.
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum NamespaceProofError {
+    #[error("namespace {0} is not in the block, but a proof was given")]
+    UnexpectedProof(NamespaceId),
+    #[error("namespace {ns_id} is not in the block, but {count} transactions were given")]
+    UnexpectedTransactions { ns_id: NamespaceId, count: usize },
+    #[error("namespace {0} is in the block, but no proof was given")]
+    MissingProof(NamespaceId),
+    #[error("proof for namespace {0} is invalid for this block")]
+    InvalidProof(NamespaceId),
+    #[error("proof is for namespace {actual}, expected namespace {expected}")]
+    WrongNamespace {
+        expected: NamespaceId,
+        actual: NamespaceId,
+    },
+    #[error("transactions do not match the proven payload of namespace {0}")]
+    PayloadMismatch(NamespaceId),
+}
+
+/// Verify that `payload` is exactly the contents of namespace `ns_id` in the block with `header`.
+///
+/// `proof` and `common` are as served by the `availability/block/:height/namespace/:ns_id` endpoint
+/// and the `availability/vid/common/:height` endpoint, respectively. The VID common data is needed
+/// to check `proof` against the payload commitment in `header`, and is itself checked for
+/// consistency with that commitment.
+///
+/// If `ns_id` does not appear in the namespace table of `header`, the namespace is empty: `payload`
+/// must be empty and no proof may be given. Otherwise, `proof` must prove the namespace and
+/// `payload` must contain exactly the proven transactions, in order.
+pub fn verify_namespace_proof(
+    header: &Header,
+    ns_id: NamespaceId,
+    payload: &[Transaction],
+    proof: Option<&NsProof>,
+    common: &VidCommon,
+) -> Result<(), NamespaceProofError> {
+    let ns_table = header.ns_table();
+    if ns_table.find_ns_id(&ns_id).is_none() {
+        if proof.is_some() {
+            return Err(NamespaceProofError::UnexpectedProof(ns_id));
+        }
+        if !payload.is_empty() {
+            return Err(NamespaceProofError::UnexpectedTransactions {
+                ns_id,
+                count: payload.len(),
+            });
+        }
+        return Ok(());
+    }
+
+    let proof = proof.ok_or(NamespaceProofError::MissingProof(ns_id))?;
+    let (transactions, proven_ns_id) = proof
+        .verify(ns_table, &header.payload_commitment(), common)
+        .ok_or(NamespaceProofError::InvalidProof(ns_id))?;
+    if proven_ns_id != ns_id {
+        return Err(NamespaceProofError::WrongNamespace {
+            expected: ns_id,
+            actual: proven_ns_id,
+        });
+    }
+    if transactions != payload {
+        return Err(NamespaceProofError::PayloadMismatch(ns_id));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use hotshot::traits::BlockPayload;
+    use hotshot_types::{
+        traits::{block_contents::BlockHeader, EncodeBytes},
+        vid::vid_scheme,
+    };
+    use jf_vid::VidScheme;
+
+    use super::*;
+    use crate::{NodeState, Payload};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_verify_namespace_proof() {
+        let ns_a = NamespaceId::from(1u32);
+        let ns_b = NamespaceId::from(2u32);
+        let ns_missing = NamespaceId::from(3u32);
+        let txs = vec![
+            Transaction::new(ns_a, vec![1, 2, 3]),
+            Transaction::new(ns_b, vec![4, 5]),
+            Transaction::new(ns_a, vec![6]),
+        ];
+
+        let instance = NodeState::mock();
+        let (payload, ns_table) =
+            Payload::from_transactions(txs.clone(), &Default::default(), &instance)
+                .await
+                .unwrap();
+        let vid = vid_scheme(10).disperse(payload.encode()).unwrap();
+        let header = Header::genesis(
+            &instance,
+            vid.commit,
+            payload.builder_commitment(&ns_table),
+            ns_table,
+        );
+        let proof = |ns_id| {
+            let index = payload.ns_table().find_ns_id(&ns_id).unwrap();
+            NsProof::new(&payload, &index, &vid.common).unwrap()
+        };
+        let ns_a_txs = vec![txs[0].clone(), txs[2].clone()];
+        let ns_b_txs = vec![txs[1].clone()];
+
+        // Valid proofs.
+        verify_namespace_proof(&header, ns_a, &ns_a_txs, Some(&proof(ns_a)), &vid.common).unwrap();
+        verify_namespace_proof(&header, ns_b, &ns_b_txs, Some(&proof(ns_b)), &vid.common).unwrap();
+        verify_namespace_proof(&header, ns_missing, &[], None, &vid.common).unwrap();
+
+        // Incomplete or incorrect payloads.
+        assert_eq!(
+            verify_namespace_proof(
+                &header,
+                ns_a,
+                &ns_a_txs[..1],
+                Some(&proof(ns_a)),
+                &vid.common
+            ),
+            Err(NamespaceProofError::PayloadMismatch(ns_a))
+        );
+        assert_eq!(
+            verify_namespace_proof(&header, ns_a, &ns_b_txs, Some(&proof(ns_a)), &vid.common),
+            Err(NamespaceProofError::PayloadMismatch(ns_a))
+        );
+
+        // Wrong or missing proofs.
+        assert_eq!(
+            verify_namespace_proof(&header, ns_a, &ns_b_txs, Some(&proof(ns_b)), &vid.common),
+            Err(NamespaceProofError::WrongNamespace {
+                expected: ns_a,
+                actual: ns_b
+            })
+        );
+        assert_eq!(
+            verify_namespace_proof(&header, ns_a, &ns_a_txs, None, &vid.common),
+            Err(NamespaceProofError::MissingProof(ns_a))
+        );
+        assert_eq!(
+            verify_namespace_proof(&header, ns_missing, &[], Some(&proof(ns_a)), &vid.common),
+            Err(NamespaceProofError::UnexpectedProof(ns_missing))
+        );
+        assert_eq!(
+            verify_namespace_proof(&header, ns_missing, &ns_a_txs, None, &vid.common),
+            Err(NamespaceProofError::UnexpectedTransactions {
+                ns_id: ns_missing,
+                count: 2
+            })
+        );
+
+        // Proof against a different block.
+        let other_vid = vid_scheme(10).disperse(vec![0; 100]).unwrap();
+        assert_eq!(
+            verify_namespace_proof(
+                &header,
+                ns_a,
+                &ns_a_txs,
+                Some(&proof(ns_a)),
+                &other_vid.common
+            ),
+            Err(NamespaceProofError::InvalidProof(ns_a))
+        );
+    }
+}
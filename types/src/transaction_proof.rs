@@ -0,0 +1,232 @@
+//! Offline verification of transaction inclusion proofs.
+//!
+//! A client which needs to prove that a transaction was sequenced, such as an exchange crediting a
+//! deposit, typically trusts only a single block commitment, for example one which has been
+//! certified by the light client contract. The `availability/transaction/:hash/proof` endpoint
+//! serves everything needed to link a transaction to such a block: the header of the block
+//! containing the transaction, a [`TxProof`] of the transaction against that header's payload
+//! commitment, and a Merkle proof placing that header in the history committed to by the trusted
+//! _anchor_ block. The functions in this module check all of these without trusting the node that
+//! served them.
+
+use committable::Committable;
+use hotshot_types::vid::VidCommon;
+use jf_merkle_tree::{MerkleCommitment, MerkleTreeScheme};
+use thiserror::Error;
+
+use crate::{BlockMerkleTree, Header, Transaction, TxProof};
+
+/// Proof that a header is at a given position in the block Merkle tree of a later header.
+pub type BlockProof = <BlockMerkleTree as MerkleTreeScheme>::MembershipProof;
+
+/// Reasons a transaction proof can fail to verify.
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum TransactionProofError {
+    #[error("anchor block {anchor} is older than block {height} containing the transaction")]
+    AnchorTooOld { height: u64, anchor: u64 },
+    #[error("block {height} is the anchor block, but its header does not match the anchor")]
+    HeaderMismatch { height: u64 },
+    #[error("block {height} is the anchor block, but a block proof was given")]
+    UnexpectedBlockProof { height: u64 },
+    #[error("no proof was given linking block {height} to anchor block {anchor}")]
+    MissingBlockProof { height: u64, anchor: u64 },
+    #[error("proof of block {height} relative to anchor block {anchor} is invalid")]
+    InvalidBlockProof { height: u64, anchor: u64 },
+    #[error("transaction is not proven to be in block {height}")]
+    InvalidTransactionProof { height: u64 },
+}
+
+/// Verify that `transaction` is included in the chain committed to by `anchor`.
+///
+/// `header` is the header of the block containing the transaction, `proof` proves the transaction
+/// against its payload commitment, and `common` is the VID common data of that block. If `header`
+/// is not `anchor` itself, `block_proof` must prove that `header` is in the block Merkle tree of
+/// `anchor`.
+///
+/// The caller is responsible for checking that `anchor` is trusted, for example by comparing its
+/// commitment to one certified by the light client contract.
+pub fn verify_transaction_proof(
+    anchor: &Header,
+    header: &Header,
+    block_proof: Option<&BlockProof>,
+    transaction: &Transaction,
+    proof: &TxProof,
+    common: &VidCommon,
+) -> Result<(), TransactionProofError> {
+    let height = header.height();
+    let anchor_height = anchor.height();
+    if anchor_height < height {
+        return Err(TransactionProofError::AnchorTooOld {
+            height,
+            anchor: anchor_height,
+        });
+    }
+
+    if anchor_height == height {
+        if block_proof.is_some() {
+            return Err(TransactionProofError::UnexpectedBlockProof { height });
+        }
+        if header.commit() != anchor.commit() {
+            return Err(TransactionProofError::HeaderMismatch { height });
+        }
+    } else {
+        let block_proof = block_proof.ok_or(TransactionProofError::MissingBlockProof {
+            height,
+            anchor: anchor_height,
+        })?;
+        let invalid = TransactionProofError::InvalidBlockProof {
+            height,
+            anchor: anchor_height,
+        };
+        match BlockMerkleTree::verify(
+            anchor.block_merkle_tree_root().digest(),
+            height,
+            block_proof,
+        ) {
+            Ok(Ok(())) => {}
+            _ => return Err(invalid),
+        }
+        if block_proof.elem() != Some(&header.commit()) {
+            return Err(invalid);
+        }
+    }
+
+    match proof.verify(
+        header.ns_table(),
+        transaction,
+        &header.payload_commitment(),
+        common,
+    ) {
+        Some(true) => Ok(()),
+        _ => Err(TransactionProofError::InvalidTransactionProof { height }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hotshot::traits::BlockPayload;
+    use hotshot_query_service::availability::QueryablePayload;
+    use hotshot_types::{
+        traits::{block_contents::BlockHeader, EncodeBytes},
+        vid::vid_scheme,
+    };
+    use jf_merkle_tree::{AppendableMerkleTreeScheme, LookupResult};
+    use jf_vid::VidScheme;
+
+    use super::*;
+    use crate::{NamespaceId, NodeState, Payload, BLOCK_MERKLE_TREE_HEIGHT};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_verify_transaction_proof() {
+        let txs = vec![
+            Transaction::new(NamespaceId::from(1u32), vec![1, 2, 3]),
+            Transaction::new(NamespaceId::from(2u32), vec![4, 5]),
+        ];
+
+        let instance = NodeState::mock();
+        let (payload, ns_table) =
+            Payload::from_transactions(txs.clone(), &Default::default(), &instance)
+                .await
+                .unwrap();
+        let vid = vid_scheme(10).disperse(payload.encode()).unwrap();
+        let header = Header::genesis(
+            &instance,
+            vid.commit,
+            payload.builder_commitment(&ns_table),
+            ns_table.clone(),
+        );
+        let (index, tx) = payload.enumerate(&ns_table).nth(1).unwrap();
+        assert_eq!(tx, txs[1]);
+        let (_, proof) = TxProof::new(&index, &payload, &vid.common).unwrap();
+
+        // A later anchor block whose block Merkle tree includes `header`.
+        let mut tree = BlockMerkleTree::new(BLOCK_MERKLE_TREE_HEIGHT);
+        tree.push(header.commit()).unwrap();
+        let mut intermediate = header.clone();
+        *intermediate.height_mut() = 1;
+        tree.push(intermediate.commit()).unwrap();
+        let mut anchor = header.clone();
+        *anchor.height_mut() = 2;
+        *anchor.block_merkle_tree_root_mut() = tree.commitment();
+        let LookupResult::Ok(_, block_proof) = tree.lookup(0) else {
+            panic!("missing block proof");
+        };
+        let LookupResult::Ok(_, wrong_block_proof) = tree.lookup(1) else {
+            panic!("missing block proof");
+        };
+
+        // Valid proofs, relative to the block itself and to a later block.
+        verify_transaction_proof(&header, &header, None, &tx, &proof, &vid.common).unwrap();
+        verify_transaction_proof(
+            &anchor,
+            &header,
+            Some(&block_proof),
+            &tx,
+            &proof,
+            &vid.common,
+        )
+        .unwrap();
+
+        // Wrong transaction.
+        assert_eq!(
+            verify_transaction_proof(&header, &header, None, &txs[0], &proof, &vid.common),
+            Err(TransactionProofError::InvalidTransactionProof { height: 0 })
+        );
+
+        // Missing, unexpected or incorrect block proofs.
+        assert_eq!(
+            verify_transaction_proof(&anchor, &header, None, &tx, &proof, &vid.common),
+            Err(TransactionProofError::MissingBlockProof {
+                height: 0,
+                anchor: 2
+            })
+        );
+        assert_eq!(
+            verify_transaction_proof(
+                &header,
+                &header,
+                Some(&block_proof),
+                &tx,
+                &proof,
+                &vid.common
+            ),
+            Err(TransactionProofError::UnexpectedBlockProof { height: 0 })
+        );
+        assert_eq!(
+            verify_transaction_proof(
+                &anchor,
+                &header,
+                Some(&wrong_block_proof),
+                &tx,
+                &proof,
+                &vid.common
+            ),
+            Err(TransactionProofError::InvalidBlockProof {
+                height: 0,
+                anchor: 2
+            })
+        );
+
+        // Anchors which do not commit to the block.
+        assert_eq!(
+            verify_transaction_proof(&intermediate, &header, None, &tx, &proof, &vid.common),
+            Err(TransactionProofError::MissingBlockProof {
+                height: 0,
+                anchor: 1
+            })
+        );
+        assert_eq!(
+            verify_transaction_proof(&header, &intermediate, None, &tx, &proof, &vid.common),
+            Err(TransactionProofError::AnchorTooOld {
+                height: 1,
+                anchor: 0
+            })
+        );
+        let mut other = header.clone();
+        *other.timestamp_mut() += 1;
+        assert_eq!(
+            verify_transaction_proof(&other, &header, None, &tx, &proof, &vid.common),
+            Err(TransactionProofError::HeaderMismatch { height: 0 })
+        );
+    }
+}
@@ -15,7 +15,10 @@ use hotshot_types::{
         signature_key::BuilderSignatureKey,
     },
 };
-use std::str::FromStr;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+};
 use thiserror::Error;
 use tide_disco::error::ServerError;
 use url::Url;
@@ -172,6 +175,20 @@ pub enum ExecutionError {
     BidRecipientNotFound,
 }
 
+#[derive(Error, Debug, Eq, PartialEq)]
+/// Ways in which auction results can be inconsistent.
+pub enum AuctionResultsError {
+    #[error("Winning bid for view {bid_view} in auction results for view {results_view}")]
+    /// A winning bid is for a different view than the auction results.
+    BidViewMismatch { bid_view: u64, results_view: u64 },
+    #[error("Invalid signature on winning bid")]
+    /// A winning bid is not signed by its account.
+    InvalidBidSignature,
+    #[error("Namespace {0} won by more than one bid")]
+    /// Sequencing rights for a namespace were sold to more than one bidder.
+    DuplicateNamespace(NamespaceId),
+}
+
 impl From<FeeError> for ExecutionError {
     fn from(e: FeeError) -> Self {
         Self::FeeError(e)
@@ -218,7 +235,7 @@ impl BidTx {
         Ok(())
     }
     /// Cryptographic signature verification
-    fn verify(&self) -> Result<(), ExecutionError> {
+    pub fn verify(&self) -> Result<(), ExecutionError> {
         self.body
             .account
             .validate_builder_signature(&self.signature, self.body.commit().as_ref())
@@ -249,6 +266,10 @@ impl BidTx {
     pub fn url(&self) -> Url {
         self.body.url()
     }
+    /// Get the namespaces this bid is for
+    pub fn namespaces(&self) -> &[NamespaceId] {
+        &self.body.namespaces
+    }
 }
 
 impl Committable for SolverAuctionResults {
@@ -312,6 +333,41 @@ impl SolverAuctionResults {
     pub fn reserve_bids(&self) -> &[(NamespaceId, Url)] {
         &self.reserve_bids
     }
+    /// The URL of the builder holding the sequencing rights for each namespace.
+    ///
+    /// Namespaces won at auction are sequenced by the winning bidder, and reserved namespaces
+    /// which were not won are sequenced by their reserve builder.
+    pub fn reservations(&self) -> BTreeMap<NamespaceId, Url> {
+        let mut reservations: BTreeMap<_, _> = self.reserve_bids.iter().cloned().collect();
+        for bid in &self.winning_bids {
+            for ns in bid.namespaces() {
+                reservations.insert(*ns, bid.url());
+            }
+        }
+        reservations
+    }
+
+    /// Check that these results are consistent: every winning bid is validly signed and for the
+    /// view of the results, and no namespace is won by more than one bid.
+    pub fn validate(&self) -> Result<(), AuctionResultsError> {
+        let mut won = BTreeSet::new();
+        for bid in &self.winning_bids {
+            if bid.view() != self.view_number {
+                return Err(AuctionResultsError::BidViewMismatch {
+                    bid_view: bid.view().u64(),
+                    results_view: self.view_number.u64(),
+                });
+            }
+            bid.verify()
+                .map_err(|_| AuctionResultsError::InvalidBidSignature)?;
+            for ns in bid.namespaces() {
+                if !won.insert(*ns) {
+                    return Err(AuctionResultsError::DuplicateNamespace(*ns));
+                }
+            }
+        }
+        Ok(())
+    }
     /// Empty results for the genesis view.
     pub fn genesis() -> Self {
         Self {
@@ -349,6 +405,22 @@ pub struct SolverAuctionResultsProvider {
     pub results_path: String,
 }
 
+impl SolverAuctionResultsProvider {
+    /// Submit a bid to the solver.
+    pub async fn submit_bid(&self, bid: &BidTx) -> anyhow::Result<()> {
+        SurfClient::new(
+            self.url
+                .join(&self.marketplace_path)
+                .context("Malformed solver URL")?,
+        )
+        .post::<()>("submit_bid")
+        .body_json(bid)?
+        .send()
+        .await?;
+        Ok(())
+    }
+}
+
 impl Default for SolverAuctionResultsProvider {
     fn default() -> Self {
         Self {
@@ -403,6 +475,53 @@ mod test {
         bidtx.charge(&mut state).unwrap();
     }
 
+    #[test]
+    fn test_auction_results_reservations() {
+        let key = FeeAccount::test_key_pair();
+        let ns = |id: u64| NamespaceId::from(id);
+        let reserve = Url::from_str("https://reserve:3131").unwrap();
+        let winner = Url::from_str("https://winner:3131").unwrap();
+        let bid = BidTxBody::new(
+            key.fee_account(),
+            FeeAmount::from(1),
+            ViewNumber::new(5),
+            vec![ns(1)],
+            winner.clone(),
+            FeeAmount::default(),
+        )
+        .signed(&key)
+        .unwrap();
+
+        // Winning bids take precedence over reserve builders.
+        let results = SolverAuctionResults::new(
+            ViewNumber::new(5),
+            vec![bid.clone()],
+            vec![(ns(1), reserve.clone()), (ns(2), reserve.clone())],
+        );
+        results.validate().unwrap();
+        assert_eq!(
+            results.reservations(),
+            BTreeMap::from([(ns(1), winner), (ns(2), reserve)])
+        );
+
+        // A namespace cannot be won twice.
+        let results = SolverAuctionResults::new(ViewNumber::new(5), vec![bid.clone(), bid], vec![]);
+        assert_eq!(
+            results.validate(),
+            Err(AuctionResultsError::DuplicateNamespace(ns(1)))
+        );
+
+        // Bids must be for the view of the results.
+        let results = SolverAuctionResults::new(ViewNumber::new(6), vec![BidTx::mock(key)], vec![]);
+        assert_eq!(
+            results.validate(),
+            Err(AuctionResultsError::BidViewMismatch {
+                bid_view: 0,
+                results_view: 6
+            })
+        );
+    }
+
     #[test]
     fn test_bid_tx_construct() {
         let key_pair = EthKeyPair::random();
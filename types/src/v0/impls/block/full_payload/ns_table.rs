@@ -16,9 +16,13 @@ use crate::{
         bytes_serde_impl, u32_from_bytes, u32_to_bytes, usize_from_bytes, usize_to_bytes,
     },
     NamespaceId, NsIndex, NsIter, NsPayloadRange, NsTable, NsTableBuilder, NsTableValidationError,
-    NumNss, PayloadByteLen, NS_ID_BYTE_LEN, NS_OFFSET_BYTE_LEN, NUM_NSS_BYTE_LEN,
+    NumNss, PayloadByteLen, ValidationParallelism, NS_ID_BYTE_LEN, NS_OFFSET_BYTE_LEN,
+    NUM_NSS_BYTE_LEN,
 };
 
+/// The fewest namespace table entries worth reading on a separate validation worker.
+const MIN_ENTRIES_BATCH: usize = 1024;
+
 // Boilerplate: `#[serde(remote = "Self")]` allows invariant checking on
 // deserialization via re-implementation of `Deserialize` in terms of default
 // derivation. See
@@ -121,11 +125,20 @@ impl NsTable {
     pub fn validate(
         &self,
         payload_byte_len: &PayloadByteLen,
+    ) -> Result<(), NsTableValidationError> {
+        self.validate_parallel(payload_byte_len, &ValidationParallelism::sequential())
+    }
+
+    /// Like [`Self::validate`], but reads the entries of large tables on `validation` workers.
+    pub fn validate_parallel(
+        &self,
+        payload_byte_len: &PayloadByteLen,
+        validation: &ValidationParallelism,
     ) -> Result<(), NsTableValidationError> {
         use NsTableValidationError::*;
 
         // conditions 1-3
-        self.validate_deserialization_invariants()?;
+        self.validate_entries(validation)?;
 
         // condition 4
         let len = self.len().0;
@@ -188,6 +201,13 @@ impl NsTable {
     /// Checks conditions 1-3 of [`NsTable::validate`]. Those conditions can be
     /// checked by looking only at the contents of the [`NsTable`].
     fn validate_deserialization_invariants(&self) -> Result<(), NsTableValidationError> {
+        self.validate_entries(&ValidationParallelism::sequential())
+    }
+
+    fn validate_entries(
+        &self,
+        validation: &ValidationParallelism,
+    ) -> Result<(), NsTableValidationError> {
         use NsTableValidationError::*;
 
         // Byte length for a table with `x` entries must be exactly `x *
@@ -219,14 +239,16 @@ impl NsTable {
         // Offsets must increase monotonically. Offsets must
         // be nonzero. Namespace IDs must be unique
         {
-            let mut prev_offset = 0;
-            let mut repeat_ns_ids = HashSet::<NamespaceId>::new();
-            for (ns_id, offset) in self.iter().map(|i| {
+            let indices = self.iter().collect::<Vec<_>>();
+            let entries = validation.map(&indices, MIN_ENTRIES_BATCH, |i| {
                 (
-                    self.read_ns_id_unchecked(&i),
-                    self.read_ns_offset_unchecked(&i),
+                    self.read_ns_id_unchecked(i),
+                    self.read_ns_offset_unchecked(i),
                 )
-            }) {
+            });
+            let mut prev_offset = 0;
+            let mut repeat_ns_ids = HashSet::<NamespaceId>::new();
+            for (ns_id, offset) in entries {
                 if !repeat_ns_ids.insert(ns_id) {
                     return Err(DuplicateNamespaceId);
                 }
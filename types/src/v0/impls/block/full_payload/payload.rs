@@ -50,6 +50,24 @@ impl Payload {
         ns_payload.export_tx(&ns_id, index.tx())
     }
 
+    /// The number of payload bytes occupied by each namespace in this block.
+    pub fn ns_byte_lens(&self) -> impl Iterator<Item = (NamespaceId, usize)> + '_ {
+        let byte_len = self.byte_len();
+        self.ns_table.iter().filter_map(move |index| {
+            let ns_id = self.ns_table.read_ns_id(&index)?;
+            let range = self.ns_table.ns_range(&index, &byte_len);
+            Some((ns_id, range.as_block_range().len()))
+        })
+    }
+
+    /// The number of transactions in each namespace in this block.
+    pub fn ns_num_txs(&self) -> impl Iterator<Item = (NamespaceId, usize)> + '_ {
+        self.ns_table.iter().filter_map(move |index| {
+            let ns_id = self.ns_table.read_ns_id(&index)?;
+            Some((ns_id, self.ns_payload(&index).iter().count()))
+        })
+    }
+
     // CRATE-VISIBLE HELPERS START HERE
 
     pub(crate) fn read_ns_payload(&self, range: &NsPayloadRange) -> &NsPayload {
@@ -9,7 +9,8 @@ use ark_serialize::{
 use committable::{Commitment, Committable, RawCommitmentBuilder};
 use contract_bindings::fee_contract::DepositFilter;
 use ethers::{
-    prelude::{Address, U256},
+    contract::LogMeta,
+    prelude::{Address, H256, U256},
     utils::{parse_units, ParseUnits},
 };
 use hotshot_query_service::explorer::MonetaryValue;
@@ -24,6 +25,7 @@ use num_traits::CheckedSub;
 use sequencer_utils::{
     impl_serde_from_string_or_integer, impl_to_fixed_bytes, ser::FromStringOrInteger,
 };
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::{
@@ -44,6 +46,79 @@ pub enum FeeError {
     MerkleTreeError(MerkleTreeError),
 }
 
+/// A deposit into the fee contract on the L1.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FeeDeposit {
+    pub account: FeeAccount,
+    pub amount: FeeAmount,
+    /// The L1 block containing the deposit.
+    pub l1_block: u64,
+    /// The hash of the L1 transaction which made the deposit.
+    pub tx_hash: H256,
+    /// The index of the deposit event among the logs of its L1 block.
+    pub log_index: u64,
+}
+
+/// The fees charged to a builder's fee account, totalled over the decided blocks it built.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BuilderFeeTotals {
+    pub account: FeeAccount,
+    /// The number of decided blocks which charged a fee to the account.
+    pub blocks: u64,
+    /// The total fees charged to the account by those blocks.
+    pub amount: FeeAmount,
+    /// The height of the first block which charged a fee to the account.
+    pub first_height: u64,
+    /// The height of the latest block which charged a fee to the account.
+    pub last_height: u64,
+}
+
+impl BuilderFeeTotals {
+    pub fn new(account: FeeAccount) -> Self {
+        Self {
+            account,
+            blocks: 0,
+            amount: 0.into(),
+            first_height: 0,
+            last_height: 0,
+        }
+    }
+
+    /// Add the fee charged to the account by the block at `height`.
+    ///
+    /// Returns `false`, leaving the totals unchanged, if a block at or above `height` has already
+    /// been added, so that blocks which are decided again after a restart are not counted twice.
+    pub fn add(&mut self, height: u64, amount: FeeAmount) -> bool {
+        if self.blocks > 0 && height <= self.last_height {
+            return false;
+        }
+        if self.blocks == 0 {
+            self.first_height = height;
+        }
+        self.blocks += 1;
+        self.amount = FeeAmount(self.amount.0.saturating_add(amount.0));
+        self.last_height = height;
+        true
+    }
+}
+
+impl FeeDeposit {
+    pub fn from_event(event: DepositFilter, meta: &LogMeta) -> Self {
+        Self {
+            account: event.user.into(),
+            amount: event.amount.into(),
+            l1_block: meta.block_number.as_u64(),
+            tx_hash: meta.transaction_hash,
+            log_index: meta.log_index.as_u64(),
+        }
+    }
+
+    /// The credit to the fee state made by this deposit.
+    pub fn info(&self) -> FeeInfo {
+        FeeInfo::new(self.account, self.amount)
+    }
+}
+
 impl FeeInfo {
     pub fn new(account: impl Into<FeeAccount>, amount: impl Into<FeeAmount>) -> Self {
         Self {
@@ -454,7 +529,7 @@ mod test {
 
     use crate::{FeeAccount, FeeAmount, FeeInfo};
 
-    use super::IterableFeeInfo;
+    use super::{BuilderFeeTotals, IterableFeeInfo};
 
     #[test]
     fn test_iterable_fee_info() {
@@ -469,4 +544,20 @@ mod test {
         let accounts = fees.accounts();
         assert_eq!(vec![FeeAccount::from(Address::zero())], accounts);
     }
+
+    #[test]
+    fn test_builder_fee_totals() {
+        let mut totals = BuilderFeeTotals::new(FeeAccount::from(Address::zero()));
+        assert!(totals.add(5, FeeAmount::from(2)));
+        assert!(totals.add(7, FeeAmount::from(3)));
+        assert_eq!(totals.blocks, 2);
+        assert_eq!(totals.amount, FeeAmount::from(5));
+        assert_eq!((totals.first_height, totals.last_height), (5, 7));
+
+        // Blocks which were already counted are ignored.
+        assert!(!totals.add(7, FeeAmount::from(3)));
+        assert!(!totals.add(6, FeeAmount::from(1)));
+        assert_eq!(totals.blocks, 2);
+        assert_eq!(totals.amount, FeeAmount::from(5));
+    }
 }
@@ -1,6 +1,6 @@
 use crate::v0::{
     retain_accounts, traits::StateCatchup, v0_3::ChainConfig, FeeMerkleTree, GenesisHeader,
-    L1BlockInfo, L1Client, PubKey, Timestamp, Upgrade, UpgradeMode,
+    L1BlockInfo, L1Client, PubKey, Timestamp, Upgrade, UpgradeMode, ValidationParallelism,
 };
 use hotshot_types::traits::states::InstanceState;
 use hotshot_types::HotShotConfig;
@@ -39,6 +39,8 @@ pub struct NodeState {
     /// to use in functions such as genesis.
     /// (example: genesis returns V2 Header if version is 0.2)
     pub current_version: Version,
+    /// How the validation of proposed headers is spread over threads.
+    pub validation: ValidationParallelism,
 }
 
 impl NodeState {
@@ -62,6 +64,7 @@ impl NodeState {
             l1_genesis: None,
             upgrades: Default::default(),
             current_version,
+            validation: Default::default(),
         }
     }
 
@@ -128,6 +131,11 @@ impl NodeState {
         self.current_version = ver;
         self
     }
+
+    pub fn with_validation_parallelism(mut self, validation: ValidationParallelism) -> Self {
+        self.validation = validation;
+        self
+    }
 }
 
 // This allows us to turn on `Default` on InstanceState trait
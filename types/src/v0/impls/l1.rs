@@ -2,17 +2,21 @@ use std::{
     cmp::{min, Ordering},
     fmt::Debug,
     num::NonZeroUsize,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        Arc,
+    },
     time::Duration,
 };
 
-use anyhow::{bail, Context};
+use anyhow::{bail, ensure, Context};
 use async_trait::async_trait;
 use clap::Parser;
 use committable::{Commitment, Committable, RawCommitmentBuilder};
 use contract_bindings::fee_contract::FeeContract;
 use ethers::{
-    prelude::{Address, BlockNumber, Middleware, Provider, H256, U256},
+    contract::LogMeta,
+    prelude::{Address, BlockNumber, Middleware, Provider, H256, U256, U64},
     providers::{Http, JsonRpcClient, ProviderError, PubsubClient, Ws, WsClientError},
 };
 use futures::{
@@ -25,13 +29,13 @@ use serde::{de::DeserializeOwned, Serialize};
 use tokio::{
     spawn,
     sync::{Mutex, MutexGuard, RwLock},
-    time::sleep,
+    time::{sleep, timeout},
 };
 use tracing::Instrument;
 use url::Url;
 
-use super::{L1BlockInfo, L1ClientMetrics, L1State, L1UpdateTask, RpcClient};
-use crate::{FeeInfo, L1Client, L1ClientOptions, L1Event, L1ReconnectTask, L1Snapshot};
+use super::{FailoverState, L1BlockInfo, L1ClientMetrics, L1State, L1UpdateTask, RpcClient};
+use crate::{FeeDeposit, FeeInfo, L1Client, L1ClientOptions, L1Event, L1ReconnectTask, L1Snapshot};
 
 impl PartialOrd for L1BlockInfo {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
@@ -101,9 +105,42 @@ impl RpcClient {
         })
     }
 
+    async fn connect(
+        url: Url,
+        metrics: Arc<L1ClientMetrics>,
+        retry_delay: Duration,
+    ) -> anyhow::Result<Self> {
+        match url.scheme() {
+            "http" | "https" => Ok(Self::http(url, metrics)),
+            "ws" | "wss" => Self::ws(url, metrics, retry_delay).await,
+            scheme => bail!("unsupported JSON-RPC protocol {scheme}"),
+        }
+    }
+
+    fn failover(clients: Vec<Self>, threshold: usize, metrics: Arc<L1ClientMetrics>) -> Self {
+        metrics.active_provider.set(0);
+        Self::Failover {
+            clients: Arc::new(clients),
+            failover: Arc::new(FailoverState {
+                active: AtomicUsize::new(0),
+                failures: AtomicUsize::new(0),
+                threshold: threshold.max(1),
+            }),
+            metrics,
+        }
+    }
+
     async fn shut_down(&self) {
-        if let Self::Ws { reconnect, .. } = self {
-            *reconnect.lock().await = L1ReconnectTask::Cancelled;
+        match self {
+            Self::Http { .. } => {}
+            Self::Ws { reconnect, .. } => {
+                *reconnect.lock().await = L1ReconnectTask::Cancelled;
+            }
+            Self::Failover { clients, .. } => {
+                for client in clients.iter() {
+                    Box::pin(client.shut_down()).await;
+                }
+            }
         }
     }
 
@@ -111,6 +148,69 @@ impl RpcClient {
         match self {
             Self::Http { metrics, .. } => metrics,
             Self::Ws { metrics, .. } => metrics,
+            Self::Failover { metrics, .. } => metrics,
+        }
+    }
+
+    /// Whether this client supports subscriptions, or must poll for new blocks.
+    ///
+    /// For a failover client, this depends on which client is currently active.
+    fn supports_subscriptions(&self) -> bool {
+        match self {
+            Self::Http { .. } => false,
+            Self::Ws { .. } => true,
+            Self::Failover {
+                clients, failover, ..
+            } => clients[failover.active()].supports_subscriptions(),
+        }
+    }
+
+    /// The client requests should currently be sent to.
+    fn active(&self) -> &Self {
+        match self {
+            Self::Failover {
+                clients, failover, ..
+            } => clients[failover.active()].active(),
+            client => client,
+        }
+    }
+}
+
+impl FailoverState {
+    fn active(&self) -> usize {
+        self.active.load(AtomicOrdering::Relaxed)
+    }
+
+    fn record_success(&self) {
+        self.failures.store(0, AtomicOrdering::Relaxed);
+    }
+
+    /// Record a failed request to client `index`, failing over to the next client if the threshold
+    /// of consecutive failures has been reached.
+    fn record_failure(&self, index: usize, num_clients: usize, metrics: &L1ClientMetrics) {
+        let failures = self.failures.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+        if failures >= self.threshold {
+            self.switch(index, (index + 1) % num_clients, metrics);
+        }
+    }
+
+    /// Switch from client `from` to client `to`.
+    ///
+    /// This has no effect if `from` is no longer the active client, for example because a
+    /// concurrent request already failed over.
+    fn switch(&self, from: usize, to: usize, metrics: &L1ClientMetrics) {
+        if from == to {
+            return;
+        }
+        if self
+            .active
+            .compare_exchange(from, to, AtomicOrdering::Relaxed, AtomicOrdering::Relaxed)
+            .is_ok()
+        {
+            tracing::warn!(from, to, "switching L1 provider");
+            self.failures.store(0, AtomicOrdering::Relaxed);
+            metrics.active_provider.set(to);
+            metrics.failovers.add(1);
         }
     }
 }
@@ -201,6 +301,23 @@ impl JsonRpcClient for RpcClient {
                     }
                 }
             }
+            Self::Failover {
+                clients,
+                failover,
+                metrics,
+            } => {
+                let index = failover.active();
+                match clients[index].request(method, params).await {
+                    Ok(res) => {
+                        failover.record_success();
+                        res
+                    }
+                    Err(err) => {
+                        failover.record_failure(index, clients.len(), metrics);
+                        Err(err)?
+                    }
+                }
+            }
         };
         Ok(res)
     }
@@ -213,10 +330,7 @@ impl PubsubClient for RpcClient {
     where
         T: Into<U256>,
     {
-        match self {
-            Self::Http { .. } => Err(ProviderError::CustomError(
-                "subscriptions not supported with HTTP client".into(),
-            )),
+        match self.active() {
             Self::Ws { conn, .. } => Ok(conn
                 .try_read()
                 // We only lock the connection exclusively when we are resetting it, so if it is
@@ -226,6 +340,9 @@ impl PubsubClient for RpcClient {
                     ProviderError::CustomError("connection closed; reset in progress".into())
                 })?
                 .subscribe(id)?),
+            _ => Err(ProviderError::CustomError(
+                "subscriptions not supported with HTTP client".into(),
+            )),
         }
     }
 
@@ -233,10 +350,7 @@ impl PubsubClient for RpcClient {
     where
         T: Into<U256>,
     {
-        match self {
-            Self::Http { .. } => Err(ProviderError::CustomError(
-                "subscriptions not supported with HTTP client".into(),
-            )),
+        match self.active() {
             Self::Ws { conn, .. } => Ok(conn
                 .try_read()
                 // We only lock the connection exclusively when we are resetting it, so if it is
@@ -246,6 +360,9 @@ impl PubsubClient for RpcClient {
                     ProviderError::CustomError("connection closed; reset in progress".into())
                 })?
                 .unsubscribe(id)?),
+            _ => Err(ProviderError::CustomError(
+                "subscriptions not supported with HTTP client".into(),
+            )),
         }
     }
 }
@@ -292,11 +409,43 @@ impl L1ClientOptions {
         }
     }
 
+    /// Instantiate an `L1Client` backed by multiple redundant providers.
+    ///
+    /// `urls` are given in priority order. Requests are sent to one provider at a time, starting
+    /// with the first. If the active provider fails [`l1_failover_threshold`](Self) requests in a
+    /// row, the client fails over to the next provider. The health of each provider is also
+    /// checked periodically, and the client switches back to the highest priority healthy provider.
+    ///
+    /// The type of each JSON-RPC client is inferred from the scheme of its URL, as in
+    /// [`connect`](Self::connect).
+    pub async fn connect_failover(
+        self,
+        urls: impl IntoIterator<Item = Url>,
+    ) -> anyhow::Result<L1Client> {
+        let mut urls = urls.into_iter().collect::<Vec<_>>();
+        ensure!(!urls.is_empty(), "at least one L1 provider is required");
+        if urls.len() == 1 {
+            return self.connect(urls.remove(0)).await;
+        }
+
+        let metrics = self.create_metrics(urls.len());
+        let mut clients = vec![];
+        for url in urls {
+            clients.push(
+                RpcClient::connect(url.clone(), metrics.clone(), self.l1_retry_delay)
+                    .await
+                    .context(format!("connecting to L1 provider {url}"))?,
+            );
+        }
+        let client = RpcClient::failover(clients, self.l1_failover_threshold, metrics);
+        Ok(L1Client::with_provider(self, Provider::new(client)))
+    }
+
     /// Synchronous, infallible version of `connect` for HTTP clients.
     ///
     /// `url` must have a scheme `http` or `https`.
     pub fn http(self, url: Url) -> L1Client {
-        let metrics = self.create_metrics();
+        let metrics = self.create_metrics(1);
         L1Client::with_provider(self, Provider::new(RpcClient::http(url, metrics)))
     }
 
@@ -304,7 +453,7 @@ impl L1ClientOptions {
     ///
     /// `url` must have a scheme `ws` or `wss`.
     pub async fn ws(self, url: Url) -> anyhow::Result<L1Client> {
-        let metrics = self.create_metrics();
+        let metrics = self.create_metrics(1);
         let retry_delay = self.l1_retry_delay;
         Ok(L1Client::with_provider(
             self,
@@ -312,18 +461,24 @@ impl L1ClientOptions {
         ))
     }
 
-    fn create_metrics(&self) -> Arc<L1ClientMetrics> {
-        Arc::new(L1ClientMetrics::new(&**self.metrics))
+    fn create_metrics(&self, num_providers: usize) -> Arc<L1ClientMetrics> {
+        Arc::new(L1ClientMetrics::new(&**self.metrics, num_providers))
     }
 }
 
 impl L1ClientMetrics {
-    fn new(metrics: &(impl Metrics + ?Sized)) -> Self {
+    fn new(metrics: &(impl Metrics + ?Sized), num_providers: usize) -> Self {
         Self {
             head: metrics.create_gauge("head".into(), None),
             finalized: metrics.create_gauge("finalized".into(), None),
             ws_reconnects: metrics.create_counter("ws_reconnects".into(), None),
             stream_reconnects: metrics.create_counter("stream_reconnects".into(), None),
+            reorgs: metrics.create_counter("reorgs".into(), None),
+            active_provider: metrics.create_gauge("active_provider".into(), None),
+            failovers: metrics.create_counter("failovers".into(), None),
+            provider_healthy: (0..num_providers)
+                .map(|i| metrics.create_gauge(format!("provider_{i}_healthy"), None))
+                .collect(),
         }
     }
 }
@@ -339,10 +494,13 @@ impl L1Client {
             retry_delay: opt.l1_retry_delay,
             provider: Arc::new(provider),
             events_max_block_range: opt.l1_events_max_block_range,
+            finalized_confirmations: opt.l1_finalized_confirmations,
             state: Arc::new(Mutex::new(L1State::new(opt.l1_blocks_cache_size))),
             sender,
             receiver: receiver.deactivate(),
             update_task: Default::default(),
+            health_check_task: Default::default(),
+            health_check_interval: opt.l1_health_check_interval,
         }
     }
 
@@ -371,6 +529,13 @@ impl L1Client {
         if update_task.is_none() {
             *update_task = Some(spawn(self.update_loop()));
         }
+
+        if matches!((*self.provider).as_ref(), RpcClient::Failover { .. }) {
+            let mut health_check_task = self.health_check_task.0.lock().await;
+            if health_check_task.is_none() {
+                *health_check_task = Some(spawn(self.health_check_loop()));
+            }
+        }
     }
 
     /// Shut down background tasks associated with this L1 client.
@@ -381,6 +546,9 @@ impl L1Client {
         if let Some(update_task) = self.update_task.0.lock().await.take() {
             update_task.abort();
         }
+        if let Some(health_check_task) = self.health_check_task.0.lock().await.take() {
+            health_check_task.abort();
+        }
         (*self.provider).as_ref().shut_down().await;
     }
 
@@ -391,6 +559,7 @@ impl L1Client {
     fn update_loop(&self) -> impl Future<Output = ()> {
         let rpc = self.provider.clone();
         let retry_delay = self.retry_delay;
+        let confirmations = self.finalized_confirmations;
         let state = self.state.clone();
         let sender = self.sender.clone();
         let metrics = (*rpc).as_ref().metrics().clone();
@@ -400,9 +569,9 @@ impl L1Client {
             loop {
                 // Subscribe to new blocks. This task cannot fail; retry until we succeed.
                 let mut block_stream = loop {
-                    let res = match (*rpc).as_ref() {
-                        RpcClient::Ws { .. } => rpc.subscribe_blocks().await.map(StreamExt::boxed),
-                        RpcClient::Http { .. } => rpc
+                    let res = match (*rpc).as_ref().supports_subscriptions() {
+                        true => rpc.subscribe_blocks().await.map(StreamExt::boxed),
+                        false => rpc
                             .watch_blocks()
                             .await
                             .map(|stream| {
@@ -462,7 +631,7 @@ impl L1Client {
                             // A new block has been produced. This happens fairly rarely, so it is now ok to
                             // poll to see if a new block has been finalized.
                             let finalized = loop {
-                                match get_finalized_block(&rpc).await {
+                                match get_finalized_block(&rpc, head, confirmations).await {
                                     Ok(finalized) => break finalized,
                                     Err(err) => {
                                         tracing::warn!("error getting finalized block: {err:#}");
@@ -495,6 +664,9 @@ impl L1Client {
                                 }
                                 state.snapshot.finalized = finalized;
                                 if let Some(finalized) = finalized {
+                                    if state.put_finalized(finalized) {
+                                        metrics.reorgs.add(1);
+                                    }
                                     sender
                                         .broadcast_direct(L1Event::NewFinalized { finalized })
                                         .await
@@ -521,6 +693,46 @@ impl L1Client {
         }.instrument(span)
     }
 
+    fn health_check_loop(&self) -> impl Future<Output = ()> {
+        let rpc = self.provider.clone();
+        let interval = self.health_check_interval;
+
+        let span = tracing::warn_span!("L1 client health check");
+        async move {
+            let RpcClient::Failover {
+                clients,
+                failover,
+                metrics,
+            } = (*rpc).as_ref()
+            else {
+                return;
+            };
+
+            loop {
+                sleep(interval).await;
+
+                let mut healthy = vec![];
+                for (i, client) in clients.iter().enumerate() {
+                    let res =
+                        timeout(interval, client.request::<_, U64>("eth_blockNumber", ())).await;
+                    let ok = matches!(res, Ok(Ok(_)));
+                    if !ok {
+                        tracing::warn!(provider = i, "L1 provider failed health check");
+                    }
+                    metrics.provider_healthy[i].set(ok as usize);
+                    healthy.push(ok);
+                }
+
+                // Prefer the healthy provider with the highest priority. If no provider is healthy,
+                // stay where we are.
+                if let Some(best) = healthy.iter().position(|ok| *ok) {
+                    failover.switch(failover.active(), best, metrics);
+                }
+            }
+        }
+        .instrument(span)
+    }
+
     /// Get a snapshot from the l1.
     pub async fn snapshot(&self) -> L1Snapshot {
         self.state.lock().await.snapshot
@@ -713,7 +925,9 @@ impl L1Client {
 
         // After fetching, add the block to the cache.
         let mut state = self.state.lock().await;
-        state.put_finalized(block);
+        if state.put_finalized(block) {
+            (*self.provider).as_ref().metrics().reorgs.add(1);
+        }
         (state, block)
     }
 
@@ -725,6 +939,26 @@ impl L1Client {
         prev_finalized: Option<u64>,
         new_finalized: u64,
     ) -> Vec<FeeInfo> {
+        self.get_finalized_deposit_events(fee_contract_address, prev_finalized, new_finalized)
+            .await
+            .iter()
+            .map(FeeDeposit::info)
+            .collect()
+    }
+
+    /// Get each `Deposit` occurring between `prev` and `new`, along with the L1 transaction which
+    /// made it.
+    ///
+    /// Each event is checked against the current canonical chain before it is returned. If an event
+    /// comes from a block which has since been reorged out, for example because a provider served
+    /// logs from a stale fork, all events from that range are discarded and the range is scanned
+    /// again.
+    pub async fn get_finalized_deposit_events(
+        &self,
+        fee_contract_address: Address,
+        prev_finalized: Option<u64>,
+        new_finalized: u64,
+    ) -> Vec<FeeDeposit> {
         // No new blocks have been finalized, therefore there are no
         // new deposits.
         if prev_finalized >= Some(new_finalized) {
@@ -760,24 +994,75 @@ impl L1Client {
 
                 // query for deposit events, loop until successful.
                 loop {
-                    match fee_contract
+                    let events = match fee_contract
                         .deposit_filter()
                         .address(fee_contract.address().into())
                         .from_block(from)
                         .to_block(to)
-                        .query()
+                        .query_with_meta()
                         .await
                     {
-                        Ok(events) => break stream::iter(events),
+                        Ok(events) => events,
                         Err(err) => {
                             tracing::warn!(from, to, %err, "Fee L1Event Error");
                             sleep(retry_delay).await;
+                            continue;
+                        }
+                    };
+                    match self.is_canonical(events.iter().map(|(_, meta)| meta)).await {
+                        Ok(true) => {
+                            break stream::iter(
+                                events
+                                    .into_iter()
+                                    .map(|(event, meta)| FeeDeposit::from_event(event, &meta)),
+                            )
+                        }
+                        Ok(false) => {
+                            tracing::warn!(from, to, "L1 reorg detected, rescanning for deposits");
+                            (*self.provider).as_ref().metrics().reorgs.add(1);
+                        }
+                        Err(err) => {
+                            tracing::warn!(from, to, "failed to check deposit blocks: {err:#}");
                         }
                     }
+                    sleep(retry_delay).await;
                 }
             }
         });
-        events.flatten().map(FeeInfo::from).collect().await
+        events.flatten().collect().await
+    }
+
+    /// Check that the blocks which emitted some events are still part of the canonical L1 chain.
+    async fn is_canonical(
+        &self,
+        events: impl IntoIterator<Item = &LogMeta>,
+    ) -> anyhow::Result<bool> {
+        let mut checked = None;
+        for meta in events {
+            let number = meta.block_number.as_u64();
+            // Events are sorted by block, so we only need to check each block once.
+            if checked == Some(number) {
+                continue;
+            }
+            let block = self
+                .provider
+                .get_block(number)
+                .await?
+                .context(format!("L1 block {number} is not available"))?;
+            if block.hash != Some(meta.block_hash) {
+                tracing::warn!(
+                    number,
+                    event_block = ?meta.block_hash,
+                    canonical_block = ?block.hash,
+                    "event was emitted by a block which is no longer canonical",
+                );
+                // Any information we have cached about this block is also suspect.
+                self.state.lock().await.finalized.pop(&number);
+                return Ok(false);
+            }
+            checked = Some(number);
+        }
+        Ok(true)
     }
 }
 
@@ -789,7 +1074,12 @@ impl L1State {
         }
     }
 
-    fn put_finalized(&mut self, info: L1BlockInfo) {
+    /// Add a finalized block to the cache.
+    ///
+    /// Returns `true` if this replaces a different block at the same height, meaning the L1 has
+    /// reorged. In this case, cached blocks after `info`, which belong to the abandoned fork, are
+    /// discarded.
+    fn put_finalized(&mut self, info: L1BlockInfo) -> bool {
         assert!(
             self.snapshot.finalized.is_some()
                 && info.number <= self.snapshot.finalized.unwrap().number,
@@ -797,19 +1087,39 @@ impl L1State {
             self.snapshot,
         );
 
-        if let Some((old_number, old_info)) = self.finalized.push(info.number, info) {
-            if old_number == info.number {
+        let reorg = match self.finalized.peek(&info.number) {
+            Some(old_info) if *old_info != info => {
                 tracing::error!(
                     ?old_info,
                     ?info,
-                    "got different info for the same finalized height; something has gone very wrong with the L1",
+                    "got different info for the same finalized height; the L1 has reorged",
                 );
+                true
+            }
+            _ => false,
+        };
+        if reorg {
+            let stale = self
+                .finalized
+                .iter()
+                .map(|(number, _)| *number)
+                .filter(|number| *number > info.number)
+                .collect::<Vec<_>>();
+            for number in stale {
+                self.finalized.pop(&number);
             }
         }
+        self.finalized.put(info.number, info);
+        reorg
     }
 }
 
-async fn get_finalized_block(rpc: &Provider<RpcClient>) -> anyhow::Result<Option<L1BlockInfo>> {
+/// Get the latest finalized block, which is at least `confirmations` blocks behind `head`.
+async fn get_finalized_block(
+    rpc: &Provider<RpcClient>,
+    head: u64,
+    confirmations: u64,
+) -> anyhow::Result<Option<L1BlockInfo>> {
     let Some(block) = rpc.get_block(BlockNumber::Finalized).await? else {
         // This can happen in rare cases where the L1 chain is very young and has not finalized a
         // block yet. This is more common in testing and demo environments. In any case, we proceed
@@ -824,9 +1134,31 @@ async fn get_finalized_block(rpc: &Provider<RpcClient>) -> anyhow::Result<Option
     // finalized prefix of the L1 chain.
     let number = block.number.context("finalized block has no number")?;
     let hash = block.hash.context("finalized block has no hash")?;
+    let finalized = L1BlockInfo {
+        number: number.as_u64(),
+        timestamp: block.timestamp,
+        hash,
+    };
+    if confirmations == 0 {
+        return Ok(Some(finalized));
+    }
 
+    // If the provider considers blocks final before they are sufficiently deep, fall back to the
+    // latest block which is.
+    let Some(confirmed) = head.checked_sub(confirmations) else {
+        tracing::warn!(head, confirmations, "no sufficiently confirmed block yet");
+        return Ok(None);
+    };
+    if finalized.number <= confirmed {
+        return Ok(Some(finalized));
+    }
+    let block = rpc
+        .get_block(confirmed)
+        .await?
+        .context(format!("confirmed block {confirmed} is not available"))?;
+    let hash = block.hash.context("confirmed block has no hash")?;
     Ok(Some(L1BlockInfo {
-        number: number.as_u64(),
+        number: confirmed,
         timestamp: block.timestamp,
         hash,
     }))
@@ -1145,7 +1477,7 @@ mod test {
         let provider = Provider::new(
             RpcClient::ws(
                 anvil.ws_endpoint().parse().unwrap(),
-                Arc::new(L1ClientMetrics::new(&NoMetrics)),
+                Arc::new(L1ClientMetrics::new(&NoMetrics, 1)),
                 Duration::from_secs(1),
             )
             .await
@@ -1246,4 +1578,100 @@ mod test {
         };
         tracing::info!(?final_state, "state updated");
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_l1_failover() {
+        setup_test();
+
+        // Start with the primary provider down and the secondary up.
+        let primary_port = pick_unused_port().unwrap();
+        let secondary = Anvil::new().block_time(1u32).spawn();
+        let client = L1ClientOptions {
+            l1_failover_threshold: 2,
+            l1_health_check_interval: Duration::from_secs(1),
+            ..Default::default()
+        }
+        .connect_failover([
+            format!("http://localhost:{primary_port}").parse().unwrap(),
+            secondary.endpoint().parse().unwrap(),
+        ])
+        .await
+        .unwrap();
+        let RpcClient::Failover { failover, .. } = (*client.provider).as_ref() else {
+            panic!("expected failover client");
+        };
+        assert_eq!(failover.active(), 0);
+
+        // Requests fail until we reach the failover threshold, then go to the secondary.
+        client.provider().get_chainid().await.unwrap_err();
+        client.provider().get_chainid().await.unwrap_err();
+        assert_eq!(failover.active(), 1);
+        assert_eq!(client.provider().get_chainid().await.unwrap(), 31337.into());
+
+        // Once the primary comes back up, the health check switches back to it.
+        client.spawn_tasks().await;
+        let _primary = Anvil::new().block_time(1u32).port(primary_port).spawn();
+        for retry in 0..10 {
+            if failover.active() == 0 {
+                break;
+            }
+            tracing::info!(retry, "waiting for health check to switch providers");
+            sleep(Duration::from_secs(1)).await;
+        }
+        assert_eq!(failover.active(), 0);
+        assert_eq!(client.provider().get_chainid().await.unwrap(), 31337.into());
+    }
+
+    #[test]
+    fn test_l1_state_reorg() {
+        let block = |number, hash: u64| L1BlockInfo {
+            number,
+            timestamp: number.into(),
+            hash: H256::from_low_u64_be(hash),
+        };
+
+        let mut state = L1State::new(NonZeroUsize::new(10).unwrap());
+        state.snapshot.finalized = Some(block(3, 0));
+        for number in 1..=3 {
+            assert!(!state.put_finalized(block(number, 0)));
+        }
+        // Seeing the same block again is not a reorg.
+        assert!(!state.put_finalized(block(2, 0)));
+
+        // Replacing block 2 drops the cached block 3, which descended from the old block 2.
+        assert!(state.put_finalized(block(2, 1)));
+        assert_eq!(state.finalized.peek(&1), Some(&block(1, 0)));
+        assert_eq!(state.finalized.peek(&2), Some(&block(2, 1)));
+        assert_eq!(state.finalized.peek(&3), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_finalized_confirmations() {
+        setup_test();
+
+        let anvil = Anvil::new().block_time(1u32).spawn();
+        let url: Url = anvil.endpoint().parse().unwrap();
+        let l1_client = L1ClientOptions {
+            l1_polling_interval: Duration::from_secs(1),
+            l1_finalized_confirmations: 3,
+            ..Default::default()
+        }
+        .connect(url)
+        .await
+        .unwrap();
+        l1_client.spawn_tasks().await;
+
+        // Anvil finalizes every block immediately, but we should only treat blocks as finalized
+        // once they are 3 blocks deep.
+        let block = l1_client.wait_for_finalized_block(2).await;
+        let head = l1_client
+            .provider
+            .get_block_number()
+            .await
+            .unwrap()
+            .as_u64();
+        assert!(head >= block.number + 3, "head {head}, finalized {block:?}");
+        let snapshot = l1_client.snapshot().await;
+        assert!(snapshot.finalized.unwrap().number + 3 <= snapshot.head);
+    }
 }
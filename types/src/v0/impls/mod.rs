@@ -11,8 +11,8 @@ mod solver;
 mod state;
 mod transaction;
 
-pub use auction::SolverAuctionResultsProvider;
-pub use fee_info::{retain_accounts, FeeError};
+pub use auction::{AuctionResultsError, SolverAuctionResultsProvider};
+pub use fee_info::{retain_accounts, BuilderFeeTotals, FeeDeposit, FeeError};
 pub use instance_state::NodeState;
 pub use state::ProposalValidationError;
 pub use state::{get_l1_deposits, BuilderValidationError, StateValidationError, ValidatedState};
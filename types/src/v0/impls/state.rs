@@ -20,7 +20,7 @@ use jf_merkle_tree::{
 use jf_vid::VidScheme;
 use num_traits::CheckedSub;
 use serde::{Deserialize, Serialize};
-use std::ops::Add;
+use std::{collections::BTreeMap, ops::Add};
 use thiserror::Error;
 use time::OffsetDateTime;
 use vbs::version::Version;
@@ -32,9 +32,9 @@ use super::{
 use crate::{
     traits::StateCatchup,
     v0_3::{ChainConfig, FullNetworkTx, IterableFeeInfo, ResolvableChainConfig},
-    BlockMerkleTree, Delta, FeeAccount, FeeAmount, FeeInfo, FeeMerkleTree, Header, Leaf,
-    NsTableValidationError, PayloadByteLen, SeqTypes, UpgradeType, BLOCK_MERKLE_TREE_HEIGHT,
-    FEE_MERKLE_TREE_HEIGHT,
+    BlockMerkleTree, BuilderSignature, Delta, FeeAccount, FeeAmount, FeeInfo, FeeMerkleTree,
+    Header, Leaf, NsTableValidationError, PayloadByteLen, SeqTypes, UpgradeType,
+    ValidationParallelism, BLOCK_MERKLE_TREE_HEIGHT, FEE_MERKLE_TREE_HEIGHT,
 };
 
 /// This enum is not used in code but functions as an index of
@@ -394,6 +394,7 @@ pub(crate) struct ValidatedTransition<'a> {
     parent: &'a Header,
     proposal: Proposal<'a>,
     view_number: u64,
+    validation: ValidationParallelism,
 }
 
 impl<'a> ValidatedTransition<'a> {
@@ -402,6 +403,7 @@ impl<'a> ValidatedTransition<'a> {
         parent: &'a Header,
         proposal: Proposal<'a>,
         view_number: u64,
+        validation: ValidationParallelism,
     ) -> Self {
         let expected_chain_config = state
             .chain_config
@@ -413,6 +415,7 @@ impl<'a> ValidatedTransition<'a> {
             parent,
             proposal,
             view_number,
+            validation,
         }
     }
 
@@ -511,7 +514,9 @@ impl<'a> ValidatedTransition<'a> {
     /// verifying signatures. Signatures are identified by index of fee `Vec`.
     fn validate_builder_fee(&self) -> Result<(), ProposalValidationError> {
         // TODO move logic from stand alone fn to here.
-        if let Err(err) = validate_builder_fee(self.proposal.header, self.view_number) {
+        if let Err(err) =
+            validate_builder_fee(self.proposal.header, self.view_number, &self.validation)
+        {
             return Err(ProposalValidationError::BuilderValidationError(err));
         }
         Ok(())
@@ -605,7 +610,10 @@ impl<'a> ValidatedTransition<'a> {
             .header
             .ns_table()
             // Should be safe since `u32` will always fit in a `usize`.
-            .validate(&PayloadByteLen(self.proposal.block_size as usize))
+            .validate_parallel(
+                &PayloadByteLen(self.proposal.block_size as usize),
+                &self.validation,
+            )
             .map_err(ProposalValidationError::from)
     }
 }
@@ -647,51 +655,57 @@ impl From<MerkleTreeError> for FeeError {
 fn validate_builder_fee(
     proposed_header: &Header,
     view_number: u64,
+    validation: &ValidationParallelism,
 ) -> Result<(), BuilderValidationError> {
-    let version = proposed_header.version();
-
-    // TODO since we are iterating, should we include account/amount in errors?
-    for (fee_info, signature) in proposed_header
+    let fees = proposed_header
         .fee_info()
-        .iter()
+        .into_iter()
         .zip(proposed_header.builder_signature())
-    {
-        // check that `amount` fits in a u64
-        fee_info
-            .amount()
-            .as_u64()
-            .ok_or(BuilderValidationError::FeeAmountOutOfRange(fee_info.amount))?;
-
-        // Verify signatures.
-
-        // TODO Marketplace signatures are placeholders for now. In
-        // finished Marketplace signatures will cover the full
-        // transaction.
-        if version.minor >= 3 {
-            fee_info
-                .account()
-                .validate_sequencing_fee_signature_marketplace(
-                    &signature,
-                    fee_info.amount().as_u64().unwrap(),
-                    view_number,
-                )
-                .then_some(())
-                .ok_or(BuilderValidationError::InvalidBuilderSignature)?;
-        } else {
-            fee_info
-                .account()
-                .validate_fee_signature(
-                    &signature,
-                    fee_info.amount().as_u64().unwrap(),
-                    proposed_header.metadata(),
-                    &proposed_header.payload_commitment(),
-                )
-                .then_some(())
-                .ok_or(BuilderValidationError::InvalidBuilderSignature)?;
-        }
-    }
+        .collect::<Vec<_>>();
+
+    // Signatures are expensive to check, so each one may be checked on its own worker. Results are
+    // reported in the order of the fees, so the first invalid fee is always the one reported.
+    validation
+        .map(&fees, 1, |(fee_info, signature)| {
+            validate_fee_signature(proposed_header, view_number, fee_info, signature)
+        })
+        .into_iter()
+        .collect()
+}
 
-    Ok(())
+/// Validate the amount and signature of a single builder fee.
+fn validate_fee_signature(
+    proposed_header: &Header,
+    view_number: u64,
+    fee_info: &FeeInfo,
+    signature: &BuilderSignature,
+) -> Result<(), BuilderValidationError> {
+    // check that `amount` fits in a u64
+    let amount = fee_info
+        .amount()
+        .as_u64()
+        .ok_or(BuilderValidationError::FeeAmountOutOfRange(fee_info.amount))?;
+
+    // Verify signatures.
+
+    // TODO Marketplace signatures are placeholders for now. In
+    // finished Marketplace signatures will cover the full
+    // transaction.
+    let valid = if proposed_header.version().minor >= 3 {
+        fee_info
+            .account()
+            .validate_sequencing_fee_signature_marketplace(signature, amount, view_number)
+    } else {
+        fee_info.account().validate_fee_signature(
+            signature,
+            amount,
+            proposed_header.metadata(),
+            &proposed_header.payload_commitment(),
+        )
+    };
+    valid
+        .then_some(())
+        .ok_or(BuilderValidationError::InvalidBuilderSignature)
 }
 
 impl ValidatedState {
@@ -789,7 +803,11 @@ impl ValidatedState {
         }
 
         let mut delta = Delta::default();
-        validated_state.apply_proposal(&mut delta, parent_leaf, l1_deposits);
+        validated_state.apply_proposal(
+            &mut delta,
+            parent_leaf,
+            deposit_deltas(&instance.validation, &l1_deposits),
+        );
 
         validated_state.charge_fees(
             &mut delta,
@@ -857,6 +875,37 @@ fn _apply_full_transactions(
         .try_for_each(|tx| tx.execute(validated_state))
 }
 
+/// The minimum number of L1 deposits summed on a single validation worker.
+const MIN_DEPOSITS_BATCH: usize = 256;
+
+/// Sum `deposits` into a single fee delta per account.
+///
+/// Batches of deposits are summed on the workers of `validation`, and the batch totals are merged
+/// in order. The result is ordered by account, regardless of the number of workers.
+fn deposit_deltas(validation: &ValidationParallelism, deposits: &[FeeInfo]) -> Vec<FeeInfo> {
+    let batches = deposits.chunks(MIN_DEPOSITS_BATCH).collect::<Vec<_>>();
+    let mut deltas = BTreeMap::<FeeAccount, FeeAmount>::new();
+    for batch in validation.map(&batches, 1, |batch| sum_deposits(batch)) {
+        for (account, amount) in batch {
+            let delta = deltas.entry(account).or_default();
+            *delta = *delta + amount;
+        }
+    }
+    deltas
+        .into_iter()
+        .map(|(account, amount)| FeeInfo { account, amount })
+        .collect()
+}
+
+fn sum_deposits(deposits: &[FeeInfo]) -> BTreeMap<FeeAccount, FeeAmount> {
+    let mut sums = BTreeMap::<FeeAccount, FeeAmount>::new();
+    for FeeInfo { account, amount } in deposits {
+        let sum = sums.entry(*account).or_default();
+        *sum = *sum + *amount;
+    }
+    sums
+}
+
 pub async fn get_l1_deposits(
     instance: &NodeState,
     header: &Header,
@@ -930,6 +979,7 @@ impl HotShotState<SeqTypes> for ValidatedState {
                 VidSchemeType::get_payload_byte_len(&vid_common),
             ),
             view_number,
+            instance.validation,
         )
         .validate()?
         .wait_for_l1(&instance.l1_client)
@@ -1176,6 +1226,7 @@ mod test {
                 parent,
                 proposal,
                 view_number: 1,
+                validation: instance.validation,
             }
         }
     }
@@ -1618,6 +1669,62 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_deposit_deltas_parallel() {
+        let accounts = (0..5)
+            .map(|i| FeeAccount::generated_from_seed_indexed([0; 32], i).0)
+            .collect::<Vec<_>>();
+        let deposits = (0..10 * MIN_DEPOSITS_BATCH as u64)
+            .map(|i| FeeInfo::new(accounts[i as usize % accounts.len()], i))
+            .collect::<Vec<_>>();
+
+        let sequential = deposit_deltas(&ValidationParallelism::sequential(), &deposits);
+        assert_eq!(sequential.len(), accounts.len());
+        let total: u64 = (0..deposits.len() as u64).sum();
+        assert_eq!(
+            sequential
+                .iter()
+                .fold(FeeAmount::from(0), |sum, delta| sum + delta.amount),
+            total.into()
+        );
+
+        for workers in [2, 4, 16] {
+            let parallel = deposit_deltas(&ValidationParallelism { workers }, &deposits);
+            assert_eq!(parallel, sequential, "workers = {workers}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_validation_parallelism_small_batches() {
+        let validation = ValidationParallelism { workers: 4 };
+        let caller = std::thread::current().id();
+        let thread = |_: &u64| std::thread::current().id();
+
+        // Work too small to split is done on the calling thread, without involving the pool, so
+        // small headers are validated exactly as they would be with a single worker.
+        for (len, min_batch) in [(0, 1), (1, 1), (100, 64)] {
+            let items = vec![0; len];
+            assert!(
+                validation
+                    .map(&items, min_batch, thread)
+                    .into_iter()
+                    .all(|id| id == caller),
+                "len = {len}, min_batch = {min_batch}"
+            );
+        }
+
+        // Larger work is spread over the pool, and results keep the order of the items.
+        let items = (0..1000).collect::<Vec<u64>>();
+        assert!(validation
+            .map(&items, 1, thread)
+            .into_iter()
+            .all(|id| id != caller));
+        assert_eq!(
+            validation.map(&items, 1, |i| i * 2),
+            items.iter().map(|i| i * 2).collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn test_charge_fee() {
         initialize_logging();
@@ -1772,7 +1879,12 @@ mod test {
             }),
         };
 
-        validate_builder_fee(&header, *parent.view_number() + 1).unwrap();
+        validate_builder_fee(
+            &header,
+            *parent.view_number() + 1,
+            &ValidationParallelism::sequential(),
+        )
+        .unwrap();
     }
 
     #[tokio::test(flavor = "multi_thread")]
@@ -1834,6 +1946,11 @@ mod test {
             .then_some(())
             .unwrap();
 
-        validate_builder_fee(&header, *parent.view_number() + 1).unwrap();
+        validate_builder_fee(
+            &header,
+            *parent.view_number() + 1,
+            &ValidationParallelism::sequential(),
+        )
+        .unwrap();
     }
 }
@@ -13,13 +13,15 @@ use serde::{Deserialize, Serialize};
 
 mod header;
 mod impls;
+mod records;
 pub mod traits;
 mod utils;
 pub use header::Header;
 pub use impls::{
-    get_l1_deposits, retain_accounts, BuilderValidationError, FeeError, ProposalValidationError,
-    StateValidationError,
+    get_l1_deposits, retain_accounts, AuctionResultsError, BuilderFeeTotals,
+    BuilderValidationError, FeeDeposit, FeeError, ProposalValidationError, StateValidationError,
 };
+pub use records::*;
 pub use utils::*;
 use vbs::version::{StaticVersion, StaticVersionType};
 
@@ -124,7 +126,7 @@ reexport_unchanged_types!(
     BlockSize,
 );
 pub(crate) use v0_3::{
-    L1ClientMetrics, L1Event, L1ReconnectTask, L1State, L1UpdateTask, RpcClient,
+    FailoverState, L1ClientMetrics, L1Event, L1ReconnectTask, L1State, L1UpdateTask, RpcClient,
 };
 
 #[derive(
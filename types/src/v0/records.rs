@@ -0,0 +1,171 @@
+//! Records a node keeps about its own operation, such as transaction statuses, the views it
+//! observed and the progress of upgrades, as stored by
+//! [`SequencerPersistence`](super::traits::SequencerPersistence) and served by the API.
+
+use hotshot_types::{
+    data::{EpochNumber, ViewNumber},
+    light_client::StateVerKey,
+    signature_key::BLSPubKey,
+    stake_table::StakeTableEntry,
+};
+use serde::{Deserialize, Serialize};
+use vbs::version::Version;
+
+use super::Leaf;
+
+/// The status of a transaction submitted through this node.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum TxStatus {
+    /// The transaction was accepted for sequencing but has not yet been included in a block.
+    Pending,
+    /// The transaction was included in a decided block.
+    Sequenced {
+        /// Height of the block containing the transaction.
+        block: u64,
+        /// Position of the transaction within the block.
+        index: u64,
+    },
+    /// The transaction was not accepted for sequencing.
+    Rejected { reason: String },
+}
+
+/// A node's entry in the stake table for some epoch.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct StakeTableNode {
+    /// The node's consensus key and stake.
+    pub stake_table_entry: StakeTableEntry<BLSPubKey>,
+    /// The key used to verify the node's light client state signatures.
+    pub state_ver_key: StateVerKey,
+    /// Whether the node is a member of the DA committee.
+    pub da: bool,
+}
+
+/// The stake table in effect during an epoch.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct EpochStakeTable {
+    pub epoch: EpochNumber,
+    pub nodes: Vec<StakeTableNode>,
+}
+
+/// Where the payload of a block was mirrored on an external data availability layer.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DaPointer {
+    /// The height of the mirrored block.
+    pub height: u64,
+    /// The external DA layer, e.g. `celestia`.
+    pub layer: String,
+    /// The commitment by which the DA layer identifies the payload, hex-encoded.
+    pub commitment: String,
+    /// The height of the block which includes the payload on the DA layer, if it has one.
+    pub da_height: Option<u64>,
+}
+
+/// A record that a node shut down gracefully, written as the last step of the shutdown.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ShutdownCheckpoint {
+    /// The view of the anchor leaf when the node shut down.
+    ///
+    /// All decided leaves up to and including this view had been processed.
+    pub anchor_view: ViewNumber,
+    /// The view of the last consensus event the node handled, if any.
+    pub last_event_view: Option<ViewNumber>,
+}
+
+/// What this node observed during a single view, recorded for post-mortems of failed views.
+///
+/// Times are in milliseconds since the Unix epoch, according to this node's clock.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ViewRecord {
+    pub view: u64,
+    /// The leader of the view, according to this node's stake table.
+    pub leader: Option<BLSPubKey>,
+    /// When this node entered the view.
+    pub started_at: u64,
+    /// When this node received the quorum proposal for the view, if it did.
+    pub proposal_received_at: Option<u64>,
+    /// When this node received the DA proposal for the view, if it did.
+    ///
+    /// Only members of the DA committee receive DA proposals.
+    pub da_proposal_received_at: Option<u64>,
+    /// The number of votes in the quorum certificate formed for this view, once this node has seen
+    /// it in a later proposal.
+    pub votes: Option<u64>,
+    /// When and how the view ended, if it has.
+    pub ended_at: Option<u64>,
+    pub outcome: Option<ViewOutcome>,
+}
+
+/// How a view ended.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ViewOutcome {
+    /// This node moved on to the next view before the view timed out.
+    Completed,
+    /// The view timed out without receiving a proposal from the leader.
+    NoProposal,
+    /// The view timed out after this node received the proposal, but before a quorum certificate
+    /// for it was formed.
+    NoQuorum,
+}
+
+/// A decided block, as recorded in the replayable log of decide events.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DecideEvent {
+    pub height: u64,
+    /// The view in which the block was decided.
+    pub view: u64,
+    /// The decided leaf, including the block payload if this node had it when the block was
+    /// decided.
+    pub leaf: Leaf,
+}
+
+/// The progress of a protocol upgrade through consensus, as observed by this node.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpgradeStatus {
+    /// The upgrade is scheduled, but has not been proposed yet.
+    Scheduled,
+    /// The upgrade has been proposed, and nodes are voting on it.
+    Proposed {
+        /// The view in which the upgrade was proposed.
+        view: u64,
+        /// The first view in which the new version will be used, if the upgrade is certified.
+        new_version_first_view: u64,
+    },
+    /// A certificate for the upgrade has been decided, so the new version will take effect.
+    Certified {
+        /// The view of the decided leaf carrying the upgrade certificate.
+        view: u64,
+        /// The first view in which the new version will be used.
+        new_version_first_view: u64,
+    },
+    /// A block with the new version has been decided.
+    Activated {
+        /// The view of the first decided leaf with the new version.
+        view: u64,
+        /// The height of the first block with the new version.
+        height: u64,
+    },
+}
+
+impl UpgradeStatus {
+    /// How far along the upgrade process this status is.
+    ///
+    /// An upgrade only ever moves forward, so a status is only replaced by one which is further
+    /// along.
+    pub fn progress(&self) -> u8 {
+        match self {
+            Self::Scheduled => 0,
+            Self::Proposed { .. } => 1,
+            Self::Certified { .. } => 2,
+            Self::Activated { .. } => 3,
+        }
+    }
+}
+
+/// The latest status of an upgrade to `version`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct UpgradeRecord {
+    pub version: Version,
+    pub status: UpgradeStatus,
+}
@@ -4,17 +4,18 @@ use std::{cmp::max, collections::BTreeMap, fmt::Debug, ops::Range, sync::Arc};
 
 use anyhow::{bail, ensure, Context};
 use async_trait::async_trait;
-use committable::Commitment;
+use committable::{Commitment, Committable};
 use dyn_clone::DynClone;
 use futures::{FutureExt, TryFutureExt};
 use hotshot::{types::EventType, HotShotInitializer};
 use hotshot_types::{
     consensus::CommitmentMap,
-    data::{DaProposal, QuorumProposal, VidDisperseShare, ViewNumber},
+    data::{DaProposal, EpochNumber, QuorumProposal, VidDisperseShare, ViewNumber},
     event::{HotShotAction, LeafInfo},
     message::Proposal,
     simple_certificate::{QuorumCertificate, UpgradeCertificate},
     traits::{
+        block_contents::BlockPayload,
         node_implementation::{ConsensusTime, Versions},
         storage::Storage,
         ValidatedState as HotShotState,
@@ -23,12 +24,15 @@ use hotshot_types::{
     vid::VidSchemeType,
 };
 use itertools::Itertools;
+use jf_merkle_tree::MerkleTreeScheme;
 use jf_vid::VidScheme;
 use serde::{de::DeserializeOwned, Serialize};
 
 use crate::{
-    v0::impls::ValidatedState, v0_3::ChainConfig, BackoffParams, BlockMerkleTree, Event,
-    FeeAccount, FeeAccountProof, FeeMerkleCommitment, FeeMerkleTree, Leaf, NetworkConfig, SeqTypes,
+    v0::impls::ValidatedState, v0_3::ChainConfig, BackoffParams, BlockMerkleTree, BuilderFeeTotals,
+    DaPointer, DecideEvent, EpochStakeTable, Event, FeeAccount, FeeAccountProof, FeeDeposit,
+    FeeMerkleCommitment, FeeMerkleTree, Leaf, NetworkConfig, PubKey, SeqTypes, ShutdownCheckpoint,
+    Transaction, TxStatus, UpgradeRecord, ViewRecord,
 };
 
 use super::impls::NodeState;
@@ -488,9 +492,9 @@ pub trait SequencerPersistence: Sized + Send + Sync + 'static {
             // If we are starting from genesis, we can provide the full state.
             Some(Arc::new(genesis_validated_state))
         } else {
-            // Otherwise, we will have to construct a sparse state and fetch missing data during
-            // catchup.
-            None
+            // Otherwise, resume from the state we saved for the anchor leaf, if we have it. If not,
+            // we will have to construct a sparse state and fetch missing data during catchup.
+            self.load_anchor_state_for(&leaf).await.map(Arc::new)
         };
 
         // If we are not starting from genesis, we start from the view following the maximum view
@@ -544,10 +548,39 @@ pub trait SequencerPersistence: Sized + Send + Sync + 'static {
         ))
     }
 
+    /// Load the state saved for the anchor leaf `leaf`, if it is present and consistent.
+    async fn load_anchor_state_for(&self, leaf: &Leaf) -> Option<ValidatedState> {
+        let (view, state) = match self.load_anchor_state().await {
+            Ok(Some(state)) => state,
+            Ok(None) => {
+                tracing::info!("no saved anchor state, will fetch state from peers");
+                return None;
+            }
+            Err(err) => {
+                tracing::warn!("error loading anchor state, will fetch state from peers: {err:#}");
+                return None;
+            }
+        };
+        let header = leaf.block_header();
+        if view != leaf.view_number()
+            || state.block_merkle_tree.commitment() != header.block_merkle_tree_root()
+            || state.fee_merkle_tree.commitment() != header.fee_merkle_tree_root()
+        {
+            tracing::warn!(
+                ?view,
+                anchor_view = ?leaf.view_number(),
+                "saved anchor state does not match anchor leaf, will fetch state from peers"
+            );
+            return None;
+        }
+        tracing::info!(?view, "starting from saved anchor state");
+        Some(state)
+    }
+
     /// Update storage based on an event from consensus.
     async fn handle_event(&self, event: &Event, consumer: &(impl EventConsumer + 'static)) {
         if let EventType::Decide { leaf_chain, qc, .. } = &event.event {
-            let Some(LeafInfo { leaf, .. }) = leaf_chain.first() else {
+            let Some(LeafInfo { leaf, state, .. }) = leaf_chain.first() else {
                 // No new leaves.
                 return;
             };
@@ -570,9 +603,53 @@ pub trait SequencerPersistence: Sized + Send + Sync + 'static {
                 );
                 return;
             }
+
+            // Save the state after the new anchor leaf, so that if we restart, we can resume from
+            // it without fetching state from our peers.
+            if let Err(err) = self.store_anchor_state(leaf.view_number(), state).await {
+                tracing::warn!("failed to save anchor state: {err:#}");
+            }
+
+            if let Err(err) = self.update_sequenced_tx_statuses(leaf_chain).await {
+                tracing::warn!("failed to update transaction statuses: {err:#}");
+            }
         }
     }
 
+    /// Mark transactions submitted through this node as sequenced, if they appear in `leaf_chain`.
+    ///
+    /// Only transactions which already have a status recorded are updated, so that we do not
+    /// persist a status for every transaction in the chain. Leaves whose payloads are not available
+    /// are skipped.
+    async fn update_sequenced_tx_statuses(
+        &self,
+        leaf_chain: &[LeafInfo<SeqTypes>],
+    ) -> anyhow::Result<()> {
+        for LeafInfo { leaf, .. } in leaf_chain {
+            let Some(payload) = leaf.block_payload() else {
+                continue;
+            };
+            let height = leaf.block_header().height();
+            for (index, tx) in payload.transactions(payload.ns_table()).enumerate() {
+                let hash = tx.commit();
+                match self.load_tx_status(hash).await? {
+                    None | Some(TxStatus::Sequenced { .. }) => continue,
+                    Some(_) => {
+                        self.store_tx_status(
+                            hash,
+                            TxStatus::Sequenced {
+                                block: height,
+                                index: index as u64,
+                            },
+                        )
+                        .await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Append decided leaves to persistent storage and emit a corresponding event.
     ///
     /// `consumer` will be sent a `Decide` event containing all decided leaves in persistent storage
@@ -631,6 +708,245 @@ pub trait SequencerPersistence: Sized + Send + Sync + 'static {
         decided_upgrade_certificate: Option<UpgradeCertificate<SeqTypes>>,
     ) -> anyhow::Result<()>;
 
+    /// Record the status of a transaction submitted through this node.
+    async fn store_tx_status(
+        &self,
+        _hash: Commitment<Transaction>,
+        _status: TxStatus,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Load the status of a transaction submitted through this node.
+    ///
+    /// Returns `None` if this node has no record of the transaction.
+    async fn load_tx_status(
+        &self,
+        _hash: Commitment<Transaction>,
+    ) -> anyhow::Result<Option<TxStatus>> {
+        Ok(None)
+    }
+
+    /// Load the status of every recorded transaction.
+    async fn load_all_tx_statuses(
+        &self,
+    ) -> anyhow::Result<Vec<(Commitment<Transaction>, TxStatus)>> {
+        Ok(vec![])
+    }
+
+    /// Add transactions to this node's persistent mempool.
+    ///
+    /// Transactions which are already in the mempool are left as they are.
+    async fn append_mempool_txs(&self, _txs: &[Transaction]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Remove transactions from this node's persistent mempool.
+    ///
+    /// Hashes of transactions which are not in the mempool are ignored.
+    async fn remove_mempool_txs(&self, _hashes: &[Commitment<Transaction>]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Load all transactions in this node's persistent mempool.
+    async fn load_mempool(&self) -> anyhow::Result<Vec<Transaction>> {
+        Ok(vec![])
+    }
+
+    /// Record the stake table in effect during an epoch.
+    ///
+    /// Stake tables are kept indefinitely, so that the history of the stake table can be queried.
+    async fn store_stake_table(&self, _stake_table: &EpochStakeTable) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Load the stake table recorded for `epoch`, if any.
+    async fn load_stake_table(
+        &self,
+        _epoch: EpochNumber,
+    ) -> anyhow::Result<Option<EpochStakeTable>> {
+        Ok(None)
+    }
+
+    /// Load every recorded stake table.
+    async fn load_all_stake_tables(&self) -> anyhow::Result<Vec<EpochStakeTable>> {
+        Ok(vec![])
+    }
+
+    /// Record where the payload of a block was mirrored on an external DA layer.
+    ///
+    /// This replaces any pointer previously recorded for the same block.
+    async fn store_da_pointer(&self, _pointer: &DaPointer) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Load the external DA pointer recorded for the block at `height`, if any.
+    async fn load_da_pointer(&self, _height: u64) -> anyhow::Result<Option<DaPointer>> {
+        Ok(None)
+    }
+
+    /// Load every recorded external DA pointer.
+    async fn load_all_da_pointers(&self) -> anyhow::Result<Vec<DaPointer>> {
+        Ok(vec![])
+    }
+
+    /// Record fee deposits found by scanning the L1 up to and including `l1_block`.
+    ///
+    /// Deposits which were already recorded are left as they are.
+    async fn store_fee_deposits(
+        &self,
+        _deposits: &[FeeDeposit],
+        _l1_block: u64,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// The last L1 block scanned for fee deposits, if any.
+    async fn load_fee_deposits_l1_block(&self) -> anyhow::Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Load up to `limit` recorded deposits to `account`, skipping the first `offset`.
+    ///
+    /// Deposits are returned in the order they were made on the L1.
+    async fn load_fee_deposits(
+        &self,
+        _account: FeeAccount,
+        _offset: u64,
+        _limit: u64,
+    ) -> anyhow::Result<Vec<FeeDeposit>> {
+        Ok(vec![])
+    }
+
+    /// Load every recorded deposit, to any account, in the order they were made on the L1.
+    async fn load_all_fee_deposits(&self) -> anyhow::Result<Vec<FeeDeposit>> {
+        Ok(vec![])
+    }
+
+    /// Record the fee totals of builder accounts.
+    ///
+    /// This replaces any totals previously recorded for the same accounts.
+    async fn store_builder_fees(&self, _totals: &[BuilderFeeTotals]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Load the fee totals recorded for `account`, if any.
+    async fn load_builder_fees(
+        &self,
+        _account: FeeAccount,
+    ) -> anyhow::Result<Option<BuilderFeeTotals>> {
+        Ok(None)
+    }
+
+    /// Load the fee totals recorded for every builder account.
+    async fn load_all_builder_fees(&self) -> anyhow::Result<Vec<BuilderFeeTotals>> {
+        Ok(vec![])
+    }
+
+    /// Record the latest status of a protocol upgrade.
+    ///
+    /// This replaces any status previously recorded for the same version.
+    async fn store_upgrade(&self, _upgrade: &UpgradeRecord) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Load the statuses of all protocol upgrades recorded with
+    /// [`store_upgrade`](Self::store_upgrade).
+    async fn load_upgrades(&self) -> anyhow::Result<Vec<UpgradeRecord>> {
+        Ok(vec![])
+    }
+
+    /// Record the libp2p peers this node knows about, replacing any previously recorded.
+    ///
+    /// Each peer is given as a multiaddress ending with the peer's ID, e.g.
+    /// `/ip4/10.0.0.1/udp/1769/quic-v1/p2p/12D3KooW...`.
+    async fn store_libp2p_peers(&self, _peers: &[String]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Load the libp2p peers recorded with [`store_libp2p_peers`](Self::store_libp2p_peers).
+    async fn load_libp2p_peers(&self) -> anyhow::Result<Vec<String>> {
+        Ok(vec![])
+    }
+
+    /// Record the peers banned for misbehavior, replacing any previously recorded.
+    async fn store_banned_peers(&self, _peers: &[PubKey]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Load the peers recorded with [`store_banned_peers`](Self::store_banned_peers).
+    async fn load_banned_peers(&self) -> anyhow::Result<Vec<PubKey>> {
+        Ok(vec![])
+    }
+
+    /// Record that the node shut down gracefully.
+    async fn store_shutdown_checkpoint(
+        &self,
+        _checkpoint: &ShutdownCheckpoint,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Load and remove the checkpoint recorded by the last graceful shutdown, if any.
+    ///
+    /// The checkpoint is removed so that it only ever applies to the run which recorded it; if the
+    /// node then crashes, the next startup does not find a stale checkpoint.
+    async fn take_shutdown_checkpoint(&self) -> anyhow::Result<Option<ShutdownCheckpoint>> {
+        Ok(None)
+    }
+
+    /// Record what this node observed during a view, replacing any earlier record for the view.
+    async fn store_view_record(&self, _record: &ViewRecord) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Load the recorded views in the range `from..until`, in order of view number.
+    async fn load_view_records(&self, _from: u64, _until: u64) -> anyhow::Result<Vec<ViewRecord>> {
+        Ok(vec![])
+    }
+
+    /// Delete the records of all views before `view`.
+    async fn prune_view_records(&self, _view: u64) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Append decided blocks to the replayable log of decide events.
+    ///
+    /// An event which is already in the log, at the same height, is replaced.
+    async fn store_decide_events(&self, _events: &[DecideEvent]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Load at most `limit` logged decide events, starting from height `from`, in order of height.
+    async fn load_decide_events(
+        &self,
+        _from: u64,
+        _limit: usize,
+    ) -> anyhow::Result<Vec<DecideEvent>> {
+        Ok(vec![])
+    }
+
+    /// Delete the logged decide events for all blocks before `height`.
+    async fn prune_decide_events(&self, _height: u64) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Save the validated state after the anchor leaf at `view`.
+    ///
+    /// Only the state for the most recent anchor leaf needs to be kept.
+    async fn store_anchor_state(
+        &self,
+        _view: ViewNumber,
+        _state: &ValidatedState,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Load the state saved with [`store_anchor_state`](Self::store_anchor_state), with its view.
+    async fn load_anchor_state(&self) -> anyhow::Result<Option<(ViewNumber, ValidatedState)>> {
+        Ok(None)
+    }
+
     async fn load_anchor_view(&self) -> anyhow::Result<ViewNumber> {
         match self.load_anchor_leaf().await? {
             Some((leaf, _)) => Ok(leaf.view_number()),
@@ -642,6 +958,19 @@ pub trait SequencerPersistence: Sized + Send + Sync + 'static {
 #[async_trait]
 pub trait EventConsumer: Debug + DynClone + Send + Sync {
     async fn handle_event(&self, event: &Event) -> anyhow::Result<()>;
+
+    /// Bring the consumer in line with consensus storage when the node starts up.
+    ///
+    /// `anchor` is the latest decided leaf in consensus storage, and `qc` the certificate for it. A
+    /// consumer which keeps its own copy of the chain can use this to repair any divergence left by
+    /// a crash, before it is sent new events.
+    async fn reconcile(
+        &self,
+        _anchor: &Leaf,
+        _qc: &QuorumCertificate<SeqTypes>,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 
 dyn_clone::clone_trait_object!(EventConsumer);
@@ -655,6 +984,14 @@ where
     async fn handle_event(&self, event: &Event) -> anyhow::Result<()> {
         (**self).handle_event(event).await
     }
+
+    async fn reconcile(
+        &self,
+        anchor: &Leaf,
+        qc: &QuorumCertificate<SeqTypes>,
+    ) -> anyhow::Result<()> {
+        (**self).reconcile(anchor, qc).await
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
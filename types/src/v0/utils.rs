@@ -4,20 +4,27 @@ use clap::Parser;
 use derive_more::{From, Into};
 use futures::future::BoxFuture;
 use rand::Rng;
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
 use sequencer_utils::{impl_serde_from_string_or_integer, ser::FromStringOrInteger};
 use serde::{Deserialize, Serialize};
 use std::{
     cmp::{min, Ordering},
+    collections::HashMap,
     fmt::{self, Debug, Display, Formatter},
     num::ParseIntError,
     str::FromStr,
+    sync::{Arc, Mutex, OnceLock},
     time::Duration,
 };
 use thiserror::Error;
 use time::{
     format_description::well_known::Rfc3339 as TimestampFormat, macros::time, Date, OffsetDateTime,
 };
-use tokio::time::sleep;
+use tokio::{
+    runtime::{Handle, RuntimeFlavor},
+    task::block_in_place,
+    time::sleep,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub enum Update<T> {
@@ -181,6 +188,82 @@ pub const BACKOFF_FACTOR: u32 = 2;
 // Exponential backoff jitter as a fraction of the backoff delay, (numerator, denominator).
 pub const BACKOFF_JITTER: (u64, u64) = (1, 10);
 
+/// How the work of validating and applying a proposed header is spread over threads.
+///
+/// Builder fee signatures, fee deltas from L1 deposits and the entries of large namespace tables
+/// are processed in batches on a shared thread pool, and the results are merged in their original
+/// order, so the outcome of validation does not depend on the number of workers. The namespace
+/// table checks on the merged entries, and everything else, run on the consensus task.
+#[derive(Clone, Copy, Debug, Parser, PartialEq, Eq)]
+pub struct ValidationParallelism {
+    /// Number of threads used to validate and apply a proposed header.
+    ///
+    /// With 1 worker, all validation runs on the consensus task. Otherwise, a pool of this many
+    /// threads is started when the first large enough header is validated, and reused after that.
+    #[clap(
+        long = "validation-workers",
+        env = "ESPRESSO_SEQUENCER_VALIDATION_WORKERS",
+        default_value = "1"
+    )]
+    pub workers: usize,
+}
+
+impl Default for ValidationParallelism {
+    fn default() -> Self {
+        Self::parse_from(std::iter::empty::<String>())
+    }
+}
+
+impl ValidationParallelism {
+    /// Validate everything on the calling thread.
+    pub fn sequential() -> Self {
+        Self { workers: 1 }
+    }
+
+    /// Apply `f` to every item of `items`, returning the results in the order of `items`.
+    ///
+    /// Items are split into at most `workers` batches of at least `min_batch` items each, which
+    /// are processed on the validation thread pool. If there is only one batch, it is processed on
+    /// the calling thread, so small headers never pay for handing work to the pool.
+    ///
+    /// The calling thread blocks until every batch is done. If it is a worker of a multi-threaded
+    /// Tokio runtime, the runtime is told so, and moves its other tasks to other workers meanwhile.
+    pub fn map<T, R>(&self, items: &[T], min_batch: usize, f: impl Fn(&T) -> R + Sync) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+    {
+        let batches = min(self.workers, items.len() / min_batch.max(1)).max(1);
+        if batches == 1 {
+            return items.iter().map(f).collect();
+        }
+        let batch_size = items.len().div_ceil(batches);
+        let pool = self.pool();
+        let run = || pool.install(|| items.par_iter().with_min_len(batch_size).map(&f).collect());
+        match Handle::try_current() {
+            Ok(rt) if rt.runtime_flavor() == RuntimeFlavor::MultiThread => block_in_place(run),
+            _ => run(),
+        }
+    }
+
+    /// The thread pool shared by everything validated with this number of workers.
+    fn pool(&self) -> Arc<ThreadPool> {
+        static POOLS: OnceLock<Mutex<HashMap<usize, Arc<ThreadPool>>>> = OnceLock::new();
+        let mut pools = POOLS.get_or_init(Default::default).lock().unwrap();
+        pools
+            .entry(self.workers)
+            .or_insert_with(|| {
+                let pool = ThreadPoolBuilder::new()
+                    .num_threads(self.workers)
+                    .thread_name(|i| format!("validation-{i}"))
+                    .build()
+                    .expect("failed to start validation thread pool");
+                Arc::new(pool)
+            })
+            .clone()
+    }
+}
+
 #[derive(Clone, Copy, Debug, Parser, PartialEq, Eq, PartialOrd, Ord)]
 pub struct BackoffParams {
     /// Exponential backoff exponent.
@@ -8,7 +8,11 @@ use ethers::{
 use hotshot_types::traits::metrics::{Counter, Gauge, Metrics, NoMetrics};
 use lru::LruCache;
 use serde::{Deserialize, Serialize};
-use std::{num::NonZeroUsize, sync::Arc, time::Duration};
+use std::{
+    num::NonZeroUsize,
+    sync::{atomic::AtomicUsize, Arc},
+    time::Duration,
+};
 use tokio::{
     sync::{Mutex, RwLock},
     task::JoinHandle,
@@ -88,6 +92,42 @@ pub struct L1ClientOptions {
     )]
     pub l1_events_max_block_range: u64,
 
+    /// Number of L1 blocks a block must be buried under before it is treated as finalized.
+    ///
+    /// Deposits are only credited to the fee state once the block containing them is finalized. By
+    /// default, the finality reported by the L1 provider is trusted. On chains where that is not
+    /// reliable, such as some testnets, setting this requires blocks to additionally be at least
+    /// this many blocks behind the L1 head.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_L1_FINALIZED_CONFIRMATIONS",
+        default_value = "0"
+    )]
+    pub l1_finalized_confirmations: u64,
+
+    /// Number of consecutive failed requests after which to fail over to the next L1 provider.
+    ///
+    /// Only relevant when multiple L1 providers are configured.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_L1_FAILOVER_THRESHOLD",
+        default_value = "3"
+    )]
+    pub l1_failover_threshold: usize,
+
+    /// Interval at which to check the health of each L1 provider.
+    ///
+    /// If the active provider is found to be unhealthy, or a healthy provider with a higher
+    /// priority is found, the client switches to the healthy provider with the highest priority.
+    /// Only relevant when multiple L1 providers are configured.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_L1_HEALTH_CHECK_INTERVAL",
+        default_value = "30s",
+        value_parser = parse_duration,
+    )]
+    pub l1_health_check_interval: Duration,
+
     #[clap(skip = Arc::<Box<dyn Metrics>>::new(Box::new(NoMetrics)))]
     pub metrics: Arc<Box<dyn Metrics>>,
 }
@@ -106,6 +146,8 @@ pub struct L1Client {
     pub(crate) provider: Arc<Provider<RpcClient>>,
     /// Maximum number of L1 blocks that can be scanned for events in a single query.
     pub(crate) events_max_block_range: u64,
+    /// Minimum depth below the L1 head for a block to be treated as finalized.
+    pub(crate) finalized_confirmations: u64,
     /// Shared state updated by an asynchronous task which polls the L1.
     pub(crate) state: Arc<Mutex<L1State>>,
     /// Channel used by the async update task to send events to clients.
@@ -114,6 +156,10 @@ pub struct L1Client {
     pub(crate) receiver: InactiveReceiver<L1Event>,
     /// Async task which updates the shared state.
     pub(crate) update_task: Arc<L1UpdateTask>,
+    /// Async task which checks the health of redundant providers.
+    pub(crate) health_check_task: Arc<L1UpdateTask>,
+    /// Interval at which to check the health of redundant providers.
+    pub(crate) health_check_interval: Duration,
 }
 
 /// An Ethereum RPC client over HTTP or WebSockets.
@@ -130,6 +176,23 @@ pub(crate) enum RpcClient {
         retry_delay: Duration,
         metrics: Arc<L1ClientMetrics>,
     },
+    /// Multiple redundant clients, in priority order, only one of which is used at a time.
+    Failover {
+        clients: Arc<Vec<RpcClient>>,
+        failover: Arc<FailoverState>,
+        metrics: Arc<L1ClientMetrics>,
+    },
+}
+
+/// Tracks which of several redundant L1 clients is in use.
+#[derive(Debug)]
+pub(crate) struct FailoverState {
+    /// Index of the client currently in use.
+    pub(crate) active: AtomicUsize,
+    /// Number of consecutive failed requests to the active client.
+    pub(crate) failures: AtomicUsize,
+    /// Number of consecutive failures after which to switch to the next client.
+    pub(crate) threshold: usize,
 }
 
 /// In-memory view of the L1 state, updated asynchronously.
@@ -162,4 +225,12 @@ pub(crate) struct L1ClientMetrics {
     pub(crate) finalized: Box<dyn Gauge>,
     pub(crate) ws_reconnects: Box<dyn Counter>,
     pub(crate) stream_reconnects: Box<dyn Counter>,
+    /// Number of times blocks the client had already seen were replaced by an L1 reorg.
+    pub(crate) reorgs: Box<dyn Counter>,
+    /// Index of the L1 provider currently in use.
+    pub(crate) active_provider: Box<dyn Gauge>,
+    /// Number of times the client has switched L1 providers.
+    pub(crate) failovers: Box<dyn Counter>,
+    /// Whether each L1 provider passed its last health check.
+    pub(crate) provider_healthy: Vec<Box<dyn Gauge>>,
 }